@@ -0,0 +1,61 @@
+-- Baseline schema. Uses IF NOT EXISTS throughout so it is a no-op on databases that were
+-- created by hand before migrations existed.
+
+CREATE TABLE IF NOT EXISTS pages (
+    id UUID PRIMARY KEY,
+    title TEXT NOT NULL,
+    content_json JSONB NOT NULL DEFAULT '{}'::jsonb,
+    raw_markdown TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS blocks (
+    id UUID PRIMARY KEY,
+    page_id UUID NOT NULL REFERENCES pages(id) ON DELETE CASCADE,
+    parent_block_id UUID,
+    block_type TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS page_links (
+    source_page_id UUID NOT NULL REFERENCES pages(id) ON DELETE CASCADE,
+    target_page_id UUID NOT NULL REFERENCES pages(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (source_page_id, target_page_id)
+);
+
+CREATE TABLE IF NOT EXISTS block_references (
+    id UUID PRIMARY KEY,
+    referencing_page_id UUID NOT NULL REFERENCES pages(id) ON DELETE CASCADE,
+    referencing_block_id UUID NOT NULL,
+    referenced_page_id UUID NOT NULL REFERENCES pages(id) ON DELETE CASCADE,
+    referenced_block_id UUID NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (referencing_block_id, referenced_block_id)
+);
+
+CREATE TABLE IF NOT EXISTS audio_recordings (
+    id UUID PRIMARY KEY,
+    page_id UUID REFERENCES pages(id) ON DELETE SET NULL,
+    file_path TEXT NOT NULL,
+    mime_type TEXT,
+    duration_ms INTEGER,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE IF NOT EXISTS audio_timestamps (
+    id UUID PRIMARY KEY,
+    audio_recording_id UUID NOT NULL REFERENCES audio_recordings(id) ON DELETE CASCADE,
+    block_id UUID NOT NULL,
+    timestamp_ms INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_blocks_page_id ON blocks (page_id);
+CREATE INDEX IF NOT EXISTS idx_page_links_target_page_id ON page_links (target_page_id);
+CREATE INDEX IF NOT EXISTS idx_block_references_referenced_block_id ON block_references (referenced_block_id);
+CREATE INDEX IF NOT EXISTS idx_block_references_referencing_page_id ON block_references (referencing_page_id);
+CREATE INDEX IF NOT EXISTS idx_audio_recordings_page_id ON audio_recordings (page_id);
+CREATE INDEX IF NOT EXISTS idx_audio_timestamps_recording_id ON audio_timestamps (audio_recording_id);
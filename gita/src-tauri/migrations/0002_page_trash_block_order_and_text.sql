@@ -0,0 +1,8 @@
+-- Soft-deleted pages, sibling order for blocks and searchable block text
+
+ALTER TABLE pages ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
+ALTER TABLE blocks ADD COLUMN IF NOT EXISTS order_index INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE blocks ADD COLUMN IF NOT EXISTS content_text TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_blocks_page_parent_order ON blocks (page_id, parent_block_id, order_index);
@@ -0,0 +1,8 @@
+-- Tracks which file in the notes directory each page was last synced with
+
+CREATE TABLE IF NOT EXISTS note_sync_state (
+    page_id UUID PRIMARY KEY REFERENCES pages(id) ON DELETE CASCADE,
+    file_path TEXT NOT NULL UNIQUE, -- Relative to the notes directory
+    content_hash TEXT NOT NULL,     -- SHA-256 of the file content as last written or read
+    last_synced_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
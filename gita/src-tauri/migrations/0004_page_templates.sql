@@ -0,0 +1,5 @@
+-- Pages flagged as templates for create_page_from_template
+
+ALTER TABLE pages ADD COLUMN IF NOT EXISTS is_template BOOLEAN NOT NULL DEFAULT false;
+
+CREATE INDEX IF NOT EXISTS idx_pages_is_template ON pages (is_template) WHERE is_template;
@@ -0,0 +1,17 @@
+-- At most one live daily note (titled YYYY-MM-DD) per date, so concurrent create_daily_note
+-- calls can't create duplicates. Duplicates left by the old race keep their content but get
+-- the start of their ID appended to the title; the oldest page keeps the plain date.
+
+UPDATE pages p
+SET title = p.title || ' (' || left(p.id::text, 8) || ')', updated_at = now()
+WHERE p.deleted_at IS NULL
+  AND p.title ~ '^[0-9]{4}-[0-9]{2}-[0-9]{2}$'
+  AND EXISTS (
+      SELECT 1 FROM pages older
+      WHERE older.title = p.title
+        AND older.deleted_at IS NULL
+        AND (older.created_at, older.id) < (p.created_at, p.id)
+  );
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_pages_daily_note_title ON pages (title)
+WHERE deleted_at IS NULL AND title ~ '^[0-9]{4}-[0-9]{2}-[0-9]{2}$';
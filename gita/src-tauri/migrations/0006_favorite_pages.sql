@@ -0,0 +1,4 @@
+-- Favorited (pinned) pages and their position in the sidebar
+
+ALTER TABLE pages ADD COLUMN IF NOT EXISTS is_favorite BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE pages ADD COLUMN IF NOT EXISTS favorite_order INTEGER;
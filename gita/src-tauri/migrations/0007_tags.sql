@@ -0,0 +1,28 @@
+-- Tags on pages. Names are unique ignoring case; the casing first used is kept for display.
+
+CREATE TABLE IF NOT EXISTS tags (
+    id UUID PRIMARY KEY,
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_tags_name_lower ON tags (lower(name));
+
+CREATE TABLE IF NOT EXISTS page_tags (
+    page_id UUID NOT NULL REFERENCES pages(id) ON DELETE CASCADE,
+    tag_id UUID NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+    from_content BOOLEAN NOT NULL DEFAULT false, -- Typed as #tag in the page rather than added by hand
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (page_id, tag_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_page_tags_tag_id ON page_tags (tag_id);
+
+-- Tag names of a page, for selecting alongside page rows
+CREATE OR REPLACE FUNCTION page_tag_names(p_page_id UUID) RETURNS TEXT[]
+LANGUAGE sql STABLE AS $$
+    SELECT COALESCE(array_agg(t.name ORDER BY lower(t.name)), '{}')
+    FROM page_tags pt
+    JOIN tags t ON t.id = pt.tag_id
+    WHERE pt.page_id = p_page_id
+$$;
@@ -0,0 +1,30 @@
+-- Case- and accent-insensitive title matching for [[link]] autocompletion. title_search holds
+-- lower(unaccent(title)); it is kept up to date by a trigger because unaccent() isn't
+-- immutable, so it can't back a generated column or an expression index.
+
+CREATE EXTENSION IF NOT EXISTS unaccent;
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+ALTER TABLE pages ADD COLUMN IF NOT EXISTS title_search TEXT;
+
+CREATE OR REPLACE FUNCTION set_page_title_search() RETURNS trigger
+LANGUAGE plpgsql AS $$
+BEGIN
+    NEW.title_search := lower(unaccent(NEW.title));
+    RETURN NEW;
+END
+$$;
+
+DROP TRIGGER IF EXISTS trg_pages_title_search ON pages;
+CREATE TRIGGER trg_pages_title_search
+BEFORE INSERT OR UPDATE OF title ON pages
+FOR EACH ROW EXECUTE FUNCTION set_page_title_search();
+
+UPDATE pages SET title_search = lower(unaccent(title)) WHERE title_search IS NULL;
+ALTER TABLE pages ALTER COLUMN title_search SET NOT NULL;
+
+-- Prefix matches use the btree, word and substring matches the trigram index
+CREATE INDEX IF NOT EXISTS idx_pages_title_search_prefix ON pages (title_search text_pattern_ops)
+WHERE deleted_at IS NULL AND NOT is_template;
+CREATE INDEX IF NOT EXISTS idx_pages_title_search_trgm ON pages USING gin (title_search gin_trgm_ops)
+WHERE deleted_at IS NULL AND NOT is_template;
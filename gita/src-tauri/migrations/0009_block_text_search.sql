@@ -0,0 +1,6 @@
+-- Substring search over block text for (((block reference))) autocompletion
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS idx_blocks_content_text_trgm ON blocks USING gin (lower(content_text) gin_trgm_ops)
+WHERE content_text IS NOT NULL;
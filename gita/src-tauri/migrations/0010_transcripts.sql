@@ -0,0 +1,15 @@
+-- Transcripts of audio recordings, e.g. from Whisper. One per recording; segments is an array
+-- of {start_ms, end_ms, text} in recording order.
+
+CREATE TABLE IF NOT EXISTS transcripts (
+    recording_id UUID PRIMARY KEY REFERENCES audio_recordings(id) ON DELETE CASCADE,
+    full_text TEXT NOT NULL,
+    segments JSONB NOT NULL DEFAULT '[]'::jsonb,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Narrows a search to the transcripts containing the term before their segments are scanned
+CREATE INDEX IF NOT EXISTS idx_transcripts_full_text_trgm ON transcripts USING gin (lower(full_text) gin_trgm_ops);
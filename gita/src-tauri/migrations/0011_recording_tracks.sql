@@ -0,0 +1,11 @@
+-- How a recording's sources were written. 'mixed' and 'mono_mixed' recordings have one file;
+-- 'split' recordings keep the microphone in file_path and system audio in secondary_file_path
+-- (NULL when no loopback device was captured).
+
+ALTER TABLE audio_recordings ADD COLUMN IF NOT EXISTS track_mode TEXT NOT NULL DEFAULT 'mixed';
+ALTER TABLE audio_recordings ADD COLUMN IF NOT EXISTS secondary_file_path TEXT;
+
+ALTER TABLE audio_recordings DROP CONSTRAINT IF EXISTS audio_recordings_track_mode_check;
+ALTER TABLE audio_recordings ADD CONSTRAINT audio_recordings_track_mode_check
+    CHECK (track_mode IN ('mixed', 'split', 'mono_mixed')
+           AND (secondary_file_path IS NULL OR track_mode = 'split'));
@@ -0,0 +1,27 @@
+-- Task blocks: checklist items (list items with a `checked` flag) and `todo` blocks. task_state
+-- is 'todo' or 'done' for tasks and NULL for every other block; completed_at is set while done.
+
+ALTER TABLE blocks ADD COLUMN IF NOT EXISTS task_state TEXT;
+ALTER TABLE blocks ADD COLUMN IF NOT EXISTS completed_at TIMESTAMPTZ;
+
+ALTER TABLE blocks DROP CONSTRAINT IF EXISTS blocks_task_state_check;
+ALTER TABLE blocks ADD CONSTRAINT blocks_task_state_check
+    CHECK (task_state IN ('todo', 'done') AND (completed_at IS NULL OR task_state = 'done')
+           OR task_state IS NULL AND completed_at IS NULL);
+
+CREATE INDEX IF NOT EXISTS idx_blocks_task_state ON blocks (task_state, created_at) WHERE task_state IS NOT NULL;
+
+-- Existing tasks, found the same way as the block sync does. When they were completed isn't
+-- known, so the block's last update stands in.
+UPDATE blocks b
+SET task_state = CASE WHEN task.node->>'checked' = 'true' THEN 'done' ELSE 'todo' END,
+    completed_at = CASE WHEN task.node->>'checked' = 'true' THEN b.updated_at END
+FROM pages p
+CROSS JOIN LATERAL jsonb_path_query(
+    p.content_json,
+    'strict $.** ? (@.uniqueID like_regex "^[0-9a-fA-F]{8}-([0-9a-fA-F]{4}-){3}[0-9a-fA-F]{12}$"
+                    && (@.type == "todo" || (@.type == "listitem" && @.checked.type() == "boolean")))'
+) AS task(node)
+WHERE b.page_id = p.id
+  AND b.id = (task.node->>'uniqueID')::uuid
+  AND b.task_state IS NULL;
@@ -0,0 +1,3 @@
+-- Recently edited blocks across the vault ("Today's edits"), newest first
+
+CREATE INDEX IF NOT EXISTS idx_blocks_updated_at ON blocks (updated_at DESC);
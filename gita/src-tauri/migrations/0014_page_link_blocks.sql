@@ -0,0 +1,26 @@
+-- The blocks each page link was written in, so backlinks can show the sentence linking to a
+-- page. A page can link to another from several blocks; page_links keeps one row per pair.
+
+CREATE TABLE IF NOT EXISTS page_link_blocks (
+    source_page_id UUID NOT NULL,
+    target_page_id UUID NOT NULL,
+    block_id UUID NOT NULL REFERENCES blocks(id) ON DELETE CASCADE,
+    PRIMARY KEY (source_page_id, target_page_id, block_id),
+    FOREIGN KEY (source_page_id, target_page_id)
+        REFERENCES page_links(source_page_id, target_page_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_page_link_blocks_target_page_id ON page_link_blocks (target_page_id);
+CREATE INDEX IF NOT EXISTS idx_page_link_blocks_block_id ON page_link_blocks (block_id);
+
+-- Existing links, matched by the [[Title]] or [[id]] text in the source page's blocks. Pages
+-- pick up exact blocks (including aliased links) the next time they're saved.
+INSERT INTO page_link_blocks (source_page_id, target_page_id, block_id)
+SELECT l.source_page_id, l.target_page_id, b.id
+FROM page_links l
+JOIN pages t ON t.id = l.target_page_id
+JOIN blocks b ON b.page_id = l.source_page_id
+WHERE b.content_text IS NOT NULL
+  AND (strpos(lower(b.content_text), lower('[[' || t.title || ']]')) > 0
+       OR strpos(lower(b.content_text), '[[' || t.id::text || ']]') > 0)
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,14 @@
+-- IDs given to pages and blocks imported from other apps, by their ID there (e.g. Roam's block
+-- uids), so re-running an import updates what it created before instead of duplicating it and
+-- references between imported blocks keep pointing at the same blocks. page_id is set once
+-- the page or block has been written; rows for blocks only referenced so far have none.
+
+CREATE TABLE IF NOT EXISTS imported_uids (
+    source TEXT NOT NULL, -- The app imported from, e.g. 'roam'
+    uid TEXT NOT NULL,
+    id UUID NOT NULL, -- The page's or block's ID here
+    page_id UUID REFERENCES pages(id) ON DELETE CASCADE,
+    PRIMARY KEY (source, uid)
+);
+
+CREATE INDEX IF NOT EXISTS idx_imported_uids_page_id ON imported_uids (page_id);
@@ -0,0 +1,24 @@
+-- `[[Title]]` links whose target page didn't exist when the linking page was last saved, so
+-- the UI can offer to create those pages. Rebuilt with the page's other links on every save.
+
+CREATE TABLE IF NOT EXISTS unresolved_links (
+    source_page_id UUID NOT NULL REFERENCES pages(id) ON DELETE CASCADE,
+    target_title TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (source_page_id, target_title)
+);
+
+CREATE INDEX IF NOT EXISTS idx_unresolved_links_target_title ON unresolved_links (target_title);
+
+-- Existing pages, from the [[Title]] text in their blocks; each page's list is rebuilt exactly
+-- the next time it is saved
+INSERT INTO unresolved_links (source_page_id, target_title)
+SELECT DISTINCT b.page_id, trim(m[1])
+FROM blocks b
+CROSS JOIN LATERAL regexp_matches(b.content_text, '\[\[(.*?)\]\]', 'g') AS m
+WHERE trim(m[1]) <> ''
+  AND NOT EXISTS (
+      SELECT 1 FROM pages t
+      WHERE t.deleted_at IS NULL AND (t.title = trim(m[1]) OR t.id::text = trim(m[1]))
+  )
+ON CONFLICT DO NOTHING;
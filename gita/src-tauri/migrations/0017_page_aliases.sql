@@ -0,0 +1,21 @@
+-- [[link]] titles now resolve ignoring case, and pages can have aliases: other names a link
+-- can use to reach them. An alias belongs to one page whatever its case.
+
+CREATE INDEX IF NOT EXISTS idx_pages_lower_title ON pages (lower(title)) WHERE deleted_at IS NULL;
+
+CREATE TABLE IF NOT EXISTS page_aliases (
+    page_id UUID NOT NULL REFERENCES pages(id) ON DELETE CASCADE,
+    alias TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (page_id, alias)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_page_aliases_lower_alias ON page_aliases (lower(alias));
+
+-- Aliases of a page, for selecting alongside page rows
+CREATE OR REPLACE FUNCTION page_alias_names(p_page_id UUID) RETURNS TEXT[]
+LANGUAGE sql STABLE AS $$
+    SELECT COALESCE(array_agg(a.alias ORDER BY lower(a.alias)), '{}')
+    FROM page_aliases a
+    WHERE a.page_id = p_page_id
+$$;
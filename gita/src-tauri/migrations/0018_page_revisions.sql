@@ -0,0 +1,15 @@
+-- Earlier versions of each page's content, saved whenever update_page changes it, so edits can
+-- be compared and undone. Only the newest revisions of each page are kept.
+
+CREATE TABLE IF NOT EXISTS page_revisions (
+    id UUID PRIMARY KEY,
+    page_id UUID NOT NULL REFERENCES pages(id) ON DELETE CASCADE,
+    content_json JSONB NOT NULL,
+    raw_markdown TEXT,
+    content_hash TEXT NOT NULL, -- SHA-256 of the content, see revision_handler::content_hash
+    change_summary TEXT, -- Describes the change that replaced this content, when given
+    page_updated_at TIMESTAMPTZ NOT NULL, -- When this content was saved
+    created_at TIMESTAMPTZ NOT NULL DEFAULT clock_timestamp()
+);
+
+CREATE INDEX IF NOT EXISTS idx_page_revisions_page_id_created_at ON page_revisions (page_id, created_at DESC);
@@ -0,0 +1,5 @@
+-- How many times a page links to another, in total and from each block, so removing one of
+-- several [[links]] keeps the backlink. Existing links count once until their page is saved.
+
+ALTER TABLE page_links ADD COLUMN IF NOT EXISTS link_count INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE page_link_blocks ADD COLUMN IF NOT EXISTS link_count INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,5 @@
+-- Hash of each page's content_json as update_page last synced it, so saves of unchanged content
+-- can skip the block and link sync. NULL until the page is next saved, and reset by writes
+-- that change content_json without a sync.
+
+ALTER TABLE pages ADD COLUMN IF NOT EXISTS content_hash TEXT; -- See json_utils::content_json_hash
@@ -0,0 +1,13 @@
+-- Searches saved under a name ("smart pages"), run again on demand: a title query plus a
+-- search filter as serialized by page_handler::SearchFilter.
+
+CREATE TABLE IF NOT EXISTS saved_queries (
+    id UUID PRIMARY KEY,
+    name TEXT NOT NULL,
+    query TEXT NOT NULL DEFAULT '', -- Matched against page titles; empty matches every page
+    filter JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_saved_queries_lower_name ON saved_queries (lower(name));
@@ -0,0 +1,16 @@
+-- Runs of the database maintenance job (see maintenance.rs), with the rows each step cleaned.
+-- The newest run tells every app instance sharing the database when the next one is due.
+
+CREATE TABLE IF NOT EXISTS maintenance_runs (
+    id UUID PRIMARY KEY,
+    started_at TIMESTAMPTZ NOT NULL,
+    finished_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    scheduled BOOLEAN NOT NULL, -- False when run by hand
+    revisions_pruned BIGINT NOT NULL,
+    block_references_removed BIGINT NOT NULL,
+    page_links_removed BIGINT NOT NULL,
+    audio_timestamps_removed BIGINT NOT NULL,
+    tables_analyzed TEXT[] NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_maintenance_runs_finished_at ON maintenance_runs (finished_at DESC);
@@ -0,0 +1,8 @@
+-- A name and notes for each recording, shown in place of its file name. start_recording sets
+-- the title, by default to its page's title and the date; both can be edited afterwards.
+
+ALTER TABLE audio_recordings ADD COLUMN IF NOT EXISTS title TEXT;
+ALTER TABLE audio_recordings ADD COLUMN IF NOT EXISTS description TEXT;
+
+-- list_all_recordings pages through every recording, newest first
+CREATE INDEX IF NOT EXISTS idx_audio_recordings_created_at ON audio_recordings (created_at DESC);
@@ -0,0 +1,4 @@
+-- get_reference_summaries counts the references into each page of the sidebar at once, by the
+-- page the referenced block is on
+
+CREATE INDEX IF NOT EXISTS idx_block_references_referenced_page_id ON block_references (referenced_page_id);
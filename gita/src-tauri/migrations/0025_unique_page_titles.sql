@@ -0,0 +1,30 @@
+-- At most one live page per title, ignoring case, so a [[link]] always resolves to the same
+-- page. Duplicates already in the vault keep their content but get the start of their ID
+-- appended to the title; the oldest page keeps the plain title. Each renamed page is recorded
+-- so the app can list them and offer to merge them into the page that kept the title.
+
+CREATE TABLE IF NOT EXISTS renamed_duplicate_pages (
+    page_id UUID PRIMARY KEY REFERENCES pages(id) ON DELETE CASCADE,
+    original_title TEXT NOT NULL,
+    kept_page_id UUID REFERENCES pages(id) ON DELETE SET NULL, -- The page that kept the title
+    renamed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+WITH ranked AS (
+    SELECT id, title, first_value(id) OVER (PARTITION BY lower(title) ORDER BY created_at, id) AS kept_page_id
+    FROM pages
+    WHERE deleted_at IS NULL
+), renamed AS (
+    UPDATE pages p
+    SET title = p.title || ' (' || left(p.id::text, 8) || ')', updated_at = now()
+    FROM ranked r
+    WHERE r.id = p.id AND r.kept_page_id <> p.id
+    RETURNING p.id, r.title AS original_title, r.kept_page_id
+)
+INSERT INTO renamed_duplicate_pages (page_id, original_title, kept_page_id)
+SELECT id, original_title, kept_page_id FROM renamed
+ON CONFLICT (page_id) DO NOTHING;
+
+-- Replaces the plain index from 0017, which served the same lookups
+DROP INDEX IF EXISTS idx_pages_lower_title;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_pages_unique_lower_title ON pages (lower(title)) WHERE deleted_at IS NULL;
@@ -0,0 +1,27 @@
+-- When each page's content_json or raw_markdown last changed. updated_at moves on with any
+-- write to the row (a rename, a template flag, a link resync), so page lists sort by this
+-- instead. Existing pages start from their updated_at. New rows get their updated_at from a
+-- trigger, which also covers restoring a backup taken before this column existed; saves that
+-- change the content set it explicitly.
+
+ALTER TABLE pages ADD COLUMN IF NOT EXISTS content_updated_at TIMESTAMPTZ;
+
+CREATE OR REPLACE FUNCTION set_page_content_updated_at() RETURNS trigger
+LANGUAGE plpgsql AS $$
+BEGIN
+    NEW.content_updated_at := COALESCE(NEW.content_updated_at, NEW.updated_at);
+    RETURN NEW;
+END
+$$;
+
+DROP TRIGGER IF EXISTS trg_pages_content_updated_at ON pages;
+CREATE TRIGGER trg_pages_content_updated_at
+BEFORE INSERT ON pages
+FOR EACH ROW EXECUTE FUNCTION set_page_content_updated_at();
+
+UPDATE pages SET content_updated_at = updated_at WHERE content_updated_at IS NULL;
+ALTER TABLE pages ALTER COLUMN content_updated_at SET NOT NULL;
+
+-- The default order of list_pages ("recently edited")
+CREATE INDEX IF NOT EXISTS idx_pages_content_updated_at ON pages (content_updated_at DESC, id DESC)
+WHERE deleted_at IS NULL;
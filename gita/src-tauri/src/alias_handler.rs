@@ -0,0 +1,104 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+// Import the shared DalError
+use crate::dal_error::DalError;
+
+// Another name a page can be linked by, e.g. `[[JS]]` for a page titled "JavaScript"
+#[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct PageAlias {
+    pub page_id: Uuid,
+    pub alias: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Gives a page an alias, or DalError::NotFound if there is no such page. Refused with
+// DalError::Conflict when a live page is titled the same, or the alias already belongs to a
+// page, ignoring case either way.
+pub async fn add_alias(pool: &PgPool, page_id: Uuid, alias: &str) -> Result<PageAlias, DalError> {
+    let mut tx = pool.begin().await?;
+
+    let page_exists = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM pages WHERE id = $1) AS "exists!"
+        "#,
+        page_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if !page_exists {
+        return Err(DalError::NotFound);
+    }
+
+    let title_taken = sqlx::query_scalar!(
+        r#"
+        SELECT title
+        FROM pages
+        WHERE lower(title) = lower($1) AND deleted_at IS NULL
+        LIMIT 1
+        "#,
+        alias
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    if let Some(title) = title_taken {
+        return Err(DalError::Conflict(format!("A page is already titled '{}'", title)));
+    }
+
+    let inserted = sqlx::query_as!(
+        PageAlias,
+        r#"
+        INSERT INTO page_aliases (page_id, alias, created_at)
+        VALUES ($1, $2, now())
+        RETURNING page_id, alias, created_at
+        "#,
+        page_id,
+        alias
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(DalError::from);
+    let inserted = match inserted {
+        Err(e) if e.is_unique_violation() => {
+            return Err(DalError::Conflict(format!("'{}' is already an alias", alias)));
+        }
+        result => result?,
+    };
+
+    tx.commit().await?;
+    Ok(inserted)
+}
+
+// Returns false if the page had no such alias (matched ignoring case)
+pub async fn remove_alias<'e>(executor: impl PgExecutor<'e>, page_id: Uuid, alias: &str) -> Result<bool, DalError> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM page_aliases
+        WHERE page_id = $1 AND lower(alias) = lower($2)
+        "#,
+        page_id,
+        alias
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn list_aliases<'e>(executor: impl PgExecutor<'e>, page_id: Uuid) -> Result<Vec<PageAlias>, DalError> {
+    let aliases = sqlx::query_as!(
+        PageAlias,
+        r#"
+        SELECT page_id, alias, created_at
+        FROM page_aliases
+        WHERE page_id = $1
+        ORDER BY lower(alias)
+        "#,
+        page_id
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(aliases)
+}
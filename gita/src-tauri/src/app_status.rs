@@ -0,0 +1,120 @@
+// A snapshot of the backend's health for the frontend: whether the database answers, how busy
+// the pool is, whether the configured directories can be written to and which recordings are
+// running. Each part is gathered independently, so one failing doesn't hide the others.
+
+use serde::Serialize;
+use sqlx::PgPool;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::audio;
+use crate::db;
+
+// How long the database probe may take before the database is reported unreachable. Kept well
+// below the pool's acquire timeout so a dead connection doesn't stall the UI.
+const DB_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Debug)]
+pub struct DatabaseStatus {
+    pub connected: bool,            // A pool exists and answered SELECT 1 within DB_PROBE_TIMEOUT
+    pub latency_ms: Option<u64>,    // Round trip of the probe
+    pub error: Option<String>,
+    pub pool_size: u32,             // Open connections, busy or idle
+    pub idle_connections: usize,
+    pub max_connections: u32,
+    pub schema: Option<db::SchemaVersion>, // None when the database couldn't be asked
+}
+
+#[derive(Serialize, Debug)]
+pub struct DirectoryStatus {
+    pub path: String,
+    pub exists: bool,
+    pub writable: bool, // A file could be created in it
+}
+
+#[derive(Serialize, Debug)]
+pub struct AppStatus {
+    pub app_version: String,
+    pub database: DatabaseStatus,
+    pub notes_dir: DirectoryStatus,
+    pub audio_dir: DirectoryStatus,
+    pub active_recordings: Vec<audio::RecordingInfo>,
+}
+
+pub async fn get_app_status(
+    app_version: String,
+    pool: Option<PgPool>,
+    db_error: Option<String>,
+    notes_dir: &Path,
+    audio_dir: &Path,
+) -> AppStatus {
+    AppStatus {
+        app_version,
+        database: probe_database(pool.as_ref(), db_error).await,
+        notes_dir: directory_status(notes_dir),
+        audio_dir: directory_status(audio_dir),
+        active_recordings: audio::list_active_recordings(),
+    }
+}
+
+// db_error is the last connection error, reported when there is no pool at all
+async fn probe_database(pool: Option<&PgPool>, db_error: Option<String>) -> DatabaseStatus {
+    let Some(pool) = pool else {
+        return DatabaseStatus {
+            connected: false,
+            latency_ms: None,
+            error: Some(db_error.unwrap_or_else(|| "Database is not connected".to_string())),
+            pool_size: 0,
+            idle_connections: 0,
+            max_connections: 0,
+            schema: None,
+        };
+    };
+
+    let started = Instant::now();
+    let probe = async {
+        sqlx::query_scalar::<_, i32>("SELECT 1").fetch_one(pool).await?;
+        let latency_ms = started.elapsed().as_millis() as u64;
+        let schema = db::get_schema_version(pool).await?;
+        Ok::<_, sqlx::Error>((latency_ms, schema))
+    };
+    let (connected, latency_ms, error, schema) = match tokio::time::timeout(DB_PROBE_TIMEOUT, probe).await {
+        Ok(Ok((latency_ms, schema))) => (true, Some(latency_ms), None, Some(schema)),
+        Ok(Err(e)) => (false, None, Some(e.to_string()), None),
+        Err(_) => (
+            false,
+            None,
+            Some(format!("The database did not answer within {}s", DB_PROBE_TIMEOUT.as_secs())),
+            None,
+        ),
+    };
+
+    DatabaseStatus {
+        connected,
+        latency_ms,
+        error,
+        pool_size: pool.size(),
+        idle_connections: pool.num_idle(),
+        max_connections: pool.options().get_max_connections(),
+        schema,
+    }
+}
+
+fn directory_status(path: &Path) -> DirectoryStatus {
+    let exists = path.is_dir();
+    // Permission bits don't tell the whole story (ACLs, read-only mounts), so try a real file
+    let writable = exists && {
+        let probe_path = path.join(format!(".write-check-{}", Uuid::new_v4()));
+        let created = std::fs::File::create(&probe_path).is_ok();
+        if created {
+            let _ = std::fs::remove_file(&probe_path);
+        }
+        created
+    };
+    DirectoryStatus {
+        path: path.to_string_lossy().to_string(),
+        exists,
+        writable,
+    }
+}
@@ -1,155 +1,1177 @@
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{BuildStreamError, Sample, SampleFormat, StreamConfig}; // Removed SupportedStreamConfig
+use cpal::{BuildStreamError, Sample, SampleFormat, StreamConfig, SupportedStreamConfig, SupportedStreamConfigRange};
 use ringbuf::{HeapRb, Producer}; // Removed Consumer
-use std::fs::File;
-use std::io::BufWriter;
 use std::path::{Path, PathBuf};
 use sqlx::PgPool;
 use uuid::Uuid;
 use crate::audio_handler::{self, AudioRecording as DalAudioRecording};
-use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering, AtomicUsize}};
+use crate::audio_encoder::{AudioEncoder, AudioFormat};
+use crate::recording_recovery::{self, RecoveryNote};
+use crate::vault_crypto::VaultKey;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, atomic::{AtomicBool, Ordering, AtomicU32, AtomicU64}};
 use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 // Removed: use rusqlite::{params, Connection};
-use std::collections::HashMap; // Keep for ACTIVE_RECORDINGS
+use std::collections::{HashMap, HashSet}; // Keep for ACTIVE_RECORDINGS
+use tauri::{AppHandle, Emitter, Runtime};
 
 // Define a struct to hold the recording state
 struct RecordingState {
     // is_recording is implicit if the entry exists in ACTIVE_RECORDINGS
     start_time: Instant,
     page_id: Option<String>, // MODIFIED from note_id: String
+    title: Option<String>,
     file_path: PathBuf,
-    writer: Arc<Mutex<Option<hound::WavWriter<BufWriter<File>>>>>,
-    // mic_stream: Option<cpal::Stream>, // These are !Send, managed by their thread.
-    // loopback_stream: Option<cpal::Stream>, // These are !Send, managed by their thread.
+    secondary_file_path: Option<PathBuf>, // System audio in Split mode
+    writer: Arc<Mutex<Option<AudioEncoder>>>,
+    secondary_writer: Arc<Mutex<Option<AudioEncoder>>>,
+    format: AudioFormat,
+    track_mode: TrackMode,
+    // The streams themselves are !Send; each is owned by its thread and closed when the thread exits
     mic_stream_thread: Option<JoinHandle<()>>,
     loopback_stream_thread: Option<JoinHandle<()>>,
     writer_thread: Option<JoinHandle<()>>,
     stop_signal: Arc<AtomicBool>,
+    mic_device_name: String,
+    loopback_device_name: Option<String>, // None when recording microphone only
+    gains: Arc<SourceGains>,
+    silence_log: Arc<Mutex<SilenceLog>>,
+    auto_stop: Arc<Mutex<Option<AutoStop>>>, // Set by the writer thread when a limit is hit
+    levels: Arc<Mutex<Option<RecordingLevelsEvent>>>, // Latest levels window, set by the writer thread
+    loopback_source: Option<LoopbackSource>, // None when recording microphone only
+    ring_buffer_capacity: usize,
+    mic_counters: Arc<StreamCounters>,
+    loopback_counters: Arc<StreamCounters>,
+    mic_sample_format: SampleFormat,
+    loopback_sample_format: Option<SampleFormat>, // None when no loopback stream is active
 }
 
+// Gains are linear multipliers applied to each source before mixing
+pub const DEFAULT_GAIN: f32 = 1.0;
+pub const MAX_GAIN: f32 = 4.0;
+
+// Limits a requested gain to 0.0..=MAX_GAIN
+pub fn clamp_gain(gain: f32) -> f32 {
+    if gain.is_finite() { gain.clamp(0.0, MAX_GAIN) } else { DEFAULT_GAIN }
+}
+
+// Per-source gains shared with the writer thread, so they can change while recording.
+// Each f32 is stored as its bit pattern.
+struct SourceGains {
+    mic: AtomicU32,
+    loopback: AtomicU32,
+}
+
+impl SourceGains {
+    fn new(mic: f32, loopback: f32) -> Self {
+        SourceGains {
+            mic: AtomicU32::new(clamp_gain(mic).to_bits()),
+            loopback: AtomicU32::new(clamp_gain(loopback).to_bits()),
+        }
+    }
+
+    fn mic(&self) -> f32 {
+        f32::from_bits(self.mic.load(Ordering::Relaxed))
+    }
+
+    fn loopback(&self) -> f32 {
+        f32::from_bits(self.loopback.load(Ordering::Relaxed))
+    }
+}
+
+// Each stream's ring buffer holds this many f32 samples by default (~340 ms of stereo at
+// 48 kHz). Machines that can't keep the writer thread scheduled overflow it and need more.
+pub const DEFAULT_RING_BUFFER_CAPACITY: usize = 32768;
+pub const MIN_RING_BUFFER_CAPACITY: usize = 4096;
+pub const MAX_RING_BUFFER_CAPACITY: usize = 4_194_304; // ~43 s of stereo at 48 kHz
+
+// Counts what happened to one input stream's samples, updated by its callbacks and the writer
+// thread. Samples are counted as the device delivers them, before conversion to stereo.
+#[derive(Default)]
+struct StreamCounters {
+    samples_pushed: AtomicU64,
+    samples_dropped: AtomicU64, // Ring buffer was full
+    callback_errors: AtomicU64,
+    underrun_frames: AtomicU64, // Silence the writer filled in while this stream lagged the other
+}
+
+impl StreamCounters {
+    fn snapshot(&self, sample_format: SampleFormat) -> StreamDiagnostics {
+        StreamDiagnostics {
+            sample_format: sample_format.to_string(),
+            samples_pushed: self.samples_pushed.load(Ordering::Relaxed),
+            samples_dropped: self.samples_dropped.load(Ordering::Relaxed),
+            callback_errors: self.callback_errors.load(Ordering::Relaxed),
+            underrun_frames: self.underrun_frames.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct StreamDiagnostics {
+    pub sample_format: String, // Format the device delivers, e.g. "f32" or "i16"
+    pub samples_pushed: u64,
+    pub samples_dropped: u64,
+    pub callback_errors: u64,
+    pub underrun_frames: u64,
+}
+
+// A recording's per-stream counters, to explain dropouts and glitches
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct RecordingDiagnostics {
+    pub recording_id: String,
+    pub ring_buffer_capacity: usize,
+    pub mic: StreamDiagnostics,
+    pub loopback: Option<StreamDiagnostics>, // None when no loopback stream is active
+    pub incomplete: bool, // The writer thread panicked, so the file was neither finalized nor saved
+}
+
+// How a recording is captured. Device names must match an input device exactly; without one
+// the host's default microphone (and, on Windows, a "Stereo Mix"-style loopback) is used.
+pub struct RecordingOptions<'a> {
+    pub mic_device_name: Option<&'a str>,
+    pub loopback_device_name: Option<&'a str>,
+    pub format: AudioFormat,
+    pub track_mode: TrackMode,
+    pub mic_gain: f32,
+    pub loopback_gain: f32,
+    pub silence: SilenceSettings,
+    pub limits: RecordingLimits,
+    pub ring_buffer_capacity: usize, // Samples per stream, see DEFAULT_RING_BUFFER_CAPACITY
+    pub encryption_key: Option<&'a VaultKey>, // Files are encrypted with the vault key when set
+    pub title: Option<&'a str>, // Saved with the recording
+}
+
+// How the microphone and loopback sources are written. Split keeps them in separate files so
+// they can be rebalanced later; both files start at the same moment, so timestamps apply to
+// either.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackMode {
+    #[default]
+    Mixed,     // One stereo file
+    Split,     // <id>_mic and <id>_system stereo files
+    MonoMixed, // One mono file, half the size of Mixed; enough for voice memos
+}
+
+impl TrackMode {
+    // As stored in audio_recordings.track_mode
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TrackMode::Mixed => "mixed",
+            TrackMode::Split => "split",
+            TrackMode::MonoMixed => "mono_mixed",
+        }
+    }
+
+    // Channels of the mixed signal the writer thread builds (and, except in Split mode, writes)
+    fn mix_channels(&self) -> u16 {
+        match self {
+            TrackMode::MonoMixed => 1,
+            TrackMode::Mixed | TrackMode::Split => 2,
+        }
+    }
+}
+
+// Guards against a forgotten recording filling the disk
+#[derive(Debug, Clone, Copy)]
+pub struct RecordingLimits {
+    pub max_duration: Option<Duration>, // None for no limit
+    pub min_free_space_bytes: u64,      // Recording stops when the audio directory's disk has less free
+}
+
+// How often the writer thread checks the free space left in the audio directory
+const FREE_SPACE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// Free bytes on the filesystem holding `dir`, or an error when it's below `min_free_space_bytes`
+pub fn check_free_space(dir: &Path, min_free_space_bytes: u64) -> Result<u64, String> {
+    let available_bytes = fs2::available_space(dir)
+        .map_err(|e| format!("Failed to check free disk space in {}: {}", dir.display(), e))?;
+    if available_bytes < min_free_space_bytes {
+        return Err(format!(
+            "Not enough free disk space to record: {} MB free in {}, at least {} MB required",
+            available_bytes / 1_000_000,
+            dir.display(),
+            min_free_space_bytes / 1_000_000
+        ));
+    }
+    Ok(available_bytes)
+}
+
+// Why a recording was stopped without stop_recording being called
+#[derive(serde::Serialize, Debug, Clone)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum AutoStopReason {
+    DeviceLost { device_name: String },
+    MaxDuration { max_duration_ms: u64 },
+    LowDiskSpace { available_bytes: u64, min_free_space_bytes: u64 },
+}
+
+struct AutoStop {
+    reason: AutoStopReason,
+    at: Instant,   // When capture stopped; the recording's duration ends here
+    handled: bool, // Picked up by find_auto_stopped_recordings
+}
+
+// A recording that has to be stopped, and why
+pub struct AutoStopRequest {
+    pub recording_id: String,
+    pub reason: AutoStopReason,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SilenceMode {
+    #[default]
+    Off,
+    Skip, // Leave long silent stretches out of the file
+    Mark, // Keep the audio but report long silent stretches when the recording stops
+}
+
+// Silence is judged on the mixed output over SILENCE_WINDOW_MS windows
+const SILENCE_WINDOW_MS: u64 = 50;
+
+#[derive(serde::Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct SilenceSettings {
+    pub mode: SilenceMode,
+    pub threshold: f32,      // RMS (0.0..=1.0) below which a window counts as silent
+    pub min_silence_ms: u64, // How long silence must last before it is skipped or marked
+}
+
+impl Default for SilenceSettings {
+    fn default() -> Self {
+        SilenceSettings {
+            mode: SilenceMode::Off,
+            threshold: 0.01, // About -40 dBFS
+            min_silence_ms: 3000,
+        }
+    }
+}
+
+// A silent stretch of a recording, in milliseconds from the start of the file
+#[derive(serde::Serialize, Debug, Clone, Copy)]
+pub struct SilenceRange {
+    pub start_ms: u64,
+    pub end_ms: u64,
+}
+
+// What the silence detector found, shared with RecordingState. Positions are in recording
+// time, i.e. including skipped audio.
+#[derive(Default)]
+struct SilenceLog {
+    skipped: Vec<(u64, Option<u64>)>, // (start_ms, end_ms); end_ms is None while still skipping
+    marked: Vec<SilenceRange>,
+}
+
+impl SilenceLog {
+    // Where a moment of the recording ended up in the file. A moment inside a skipped stretch
+    // maps to the point where the skip starts.
+    fn file_position_ms(&self, recording_ms: u64) -> u64 {
+        let skipped_ms: u64 = self
+            .skipped
+            .iter()
+            .take_while(|(start_ms, _)| *start_ms < recording_ms)
+            .map(|&(start_ms, end_ms)| end_ms.unwrap_or(recording_ms).min(recording_ms) - start_ms)
+            .sum();
+        recording_ms - skipped_ms
+    }
+}
+
+// Finds stretches where the mixed output stays below the threshold. In Skip mode windows past
+// the first min_silence_ms of a stretch are dropped; in Mark mode everything is kept and
+// stretches lasting at least min_silence_ms are logged.
+struct SilenceDetector {
+    settings: SilenceSettings,
+    sample_rate: u32,
+    channels: usize,
+    window: Vec<i16>,           // Interleaved samples of the window being filled
+    recording_frames: u64,      // Frames received so far, including dropped ones
+    silent_since: Option<u64>,  // Frame where the current silent stretch began
+    skipping: bool,
+    log: Arc<Mutex<SilenceLog>>,
+}
+
+impl SilenceDetector {
+    fn new(settings: SilenceSettings, sample_rate: u32, channels: u16, log: Arc<Mutex<SilenceLog>>) -> Self {
+        SilenceDetector {
+            settings,
+            sample_rate,
+            channels: channels as usize,
+            window: Vec::new(),
+            recording_frames: 0,
+            silent_since: None,
+            skipping: false,
+            log,
+        }
+    }
+
+    fn frames_to_ms(&self, frames: u64) -> u64 {
+        frames * 1000 / self.sample_rate as u64
+    }
+
+    // Appends the frames to keep from `samples` (interleaved) to `out`
+    fn process(&mut self, samples: &[i16], out: &mut Vec<i16>) {
+        let window_len = (SILENCE_WINDOW_MS * self.sample_rate as u64 / 1000) as usize * self.channels;
+        for frame in samples.chunks_exact(self.channels) {
+            self.window.extend_from_slice(frame);
+            if self.window.len() >= window_len {
+                self.end_window(out);
+            }
+        }
+    }
+
+    // Flushes the last, partial window and closes an open silent stretch
+    fn finish(&mut self, out: &mut Vec<i16>) {
+        self.recording_frames += (self.window.len() / self.channels) as u64;
+        if !self.skipping {
+            out.extend_from_slice(&self.window);
+        }
+        self.window.clear();
+        self.end_silence(self.recording_frames);
+    }
+
+    fn end_window(&mut self, out: &mut Vec<i16>) {
+        let window_start = self.recording_frames;
+        self.recording_frames += (self.window.len() / self.channels) as u64;
+
+        let sum_squares: f64 = self
+            .window
+            .iter()
+            .map(|&sample| (sample as f64 / i16::MAX as f64).powi(2))
+            .sum();
+        let rms = (sum_squares / self.window.len() as f64).sqrt();
+
+        if rms < self.settings.threshold as f64 {
+            let silent_since = *self.silent_since.get_or_insert(window_start);
+            let silent_ms = self.frames_to_ms(self.recording_frames - silent_since);
+            if self.settings.mode == SilenceMode::Skip && silent_ms > self.settings.min_silence_ms {
+                if !self.skipping {
+                    self.skipping = true;
+                    let start_ms = self.frames_to_ms(window_start);
+                    self.log.lock().unwrap().skipped.push((start_ms, None));
+                }
+                self.window.clear();
+                return;
+            }
+        } else {
+            self.end_silence(window_start);
+        }
+        out.extend_from_slice(&self.window);
+        self.window.clear();
+    }
+
+    // Closes the current silent stretch, which ended at frame `end`
+    fn end_silence(&mut self, end: u64) {
+        let Some(silent_since) = self.silent_since.take() else {
+            return;
+        };
+        let end_ms = self.frames_to_ms(end);
+        let start_ms = self.frames_to_ms(silent_since);
+        let mut log = self.log.lock().unwrap();
+        if self.skipping {
+            self.skipping = false;
+            if let Some(skip) = log.skipped.last_mut() {
+                skip.1 = Some(end_ms);
+            }
+        }
+        if self.settings.mode == SilenceMode::Mark && end_ms - start_ms >= self.settings.min_silence_ms {
+            log.marked.push(SilenceRange { start_ms, end_ms });
+        }
+    }
+}
+
+// Description of an input device, returned to the frontend for device pickers
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct AudioDeviceInfo {
+    pub index: usize,
+    pub name: String,
+    pub is_default: bool,
+    pub sample_rates: Vec<u32>,
+    pub channel_counts: Vec<u16>,
+}
+
+// Snapshot of an active recording's state
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct RecordingInfo {
+    pub recording_id: String,
+    pub page_id: Option<String>,
+    pub file_path: String,
+    pub elapsed_ms: u64,
+    pub mic_device_name: String,
+    pub loopback_device_name: Option<String>,
+    pub mic_gain: f32,
+    pub loopback_gain: f32,
+    pub loopback_active: bool,
+    pub mic_peak: Option<f32>, // From the latest levels window; None before the first one
+    pub loopback_peak: Option<f32>,
+}
+
+// Returned by start_recording. More than one recording may run for a page; the others are
+// listed so the UI can warn about it.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct StartedRecording {
+    pub recording_id: String,
+    pub capturing_system_audio: bool, // False when only the microphone is being recorded
+    pub loopback_source: Option<LoopbackSource>,
+    pub page_already_recording: bool,
+    pub other_page_recording_ids: Vec<String>,
+}
+
+// Events emitted to the frontend over the lifetime of a recording
+pub const EVENT_RECORDING_STARTED: &str = "recording://started";
+pub const EVENT_RECORDING_STOPPED: &str = "recording://stopped";
+pub const EVENT_RECORDING_ERROR: &str = "recording://error";
+pub const EVENT_RECORDING_LEVELS: &str = "recording://levels";
+pub const EVENT_RECORDING_RECOVERED: &str = "recording://recovered";
+pub const EVENT_RECORDING_DEVICE_LOST: &str = "recording://device-lost";
+pub const EVENT_RECORDING_AUTO_STOPPED: &str = "recording://auto-stopped";
+pub const EVENT_RECORDINGS_FINISHING: &str = "recording://finishing"; // The app is exiting and saving recordings
+
+// How often the writer thread emits a levels event
+const LEVEL_EVENT_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct RecordingStartedEvent {
+    pub recording_id: String,
+    pub page_id: Option<String>,
+    pub mic_device_name: String,
+    pub loopback_device_name: Option<String>,
+    pub loopback_source: Option<LoopbackSource>,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct RecordingStoppedEvent {
+    pub recording_id: String,
+    pub duration_ms: u64,
+    pub file_path: String,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct RecordingsFinishingEvent {
+    pub recording_ids: Vec<String>,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct RecordingDeviceLostEvent {
+    pub recording_id: String,
+    pub device_name: String,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct RecordingAutoStoppedEvent {
+    pub recording_id: String,
+    #[serde(flatten)]
+    pub reason: AutoStopReason,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct RecordingErrorEvent {
+    pub recording_id: String,
+    pub stream: String,
+    pub message: String,
+}
+
+// Peak and RMS levels (0.0..=1.0) over the last LEVEL_EVENT_INTERVAL.
+// elapsed_ms is the position in the output file, derived from frames written.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct RecordingLevelsEvent {
+    pub recording_id: String,
+    pub elapsed_ms: u64,
+    pub mic_peak: f32,
+    pub mic_rms: f32,
+    pub loopback_peak: Option<f32>, // None when no loopback stream is active
+    pub loopback_rms: Option<f32>,
+}
+
+// Accumulates per-source levels between two levels events
+#[derive(Default)]
+struct LevelMeter {
+    mic_peak: f32,
+    mic_sum_squares: f64,
+    loopback_peak: f32,
+    loopback_sum_squares: f64,
+    frames: u64,
+}
+
+impl LevelMeter {
+    fn add_frame(&mut self, mic: (f32, f32), loopback: (f32, f32)) {
+        self.mic_peak = self.mic_peak.max(mic.0.abs()).max(mic.1.abs());
+        self.mic_sum_squares += ((mic.0 * mic.0 + mic.1 * mic.1) / 2.0) as f64;
+        self.loopback_peak = self.loopback_peak.max(loopback.0.abs()).max(loopback.1.abs());
+        self.loopback_sum_squares += ((loopback.0 * loopback.0 + loopback.1 * loopback.1) / 2.0) as f64;
+        self.frames += 1;
+    }
+
+    // Produces an event for the current window and resets the meter
+    fn take_event(&mut self, recording_id: &str, elapsed_ms: u64, loopback_active: bool) -> RecordingLevelsEvent {
+        let rms = |sum_squares: f64| {
+            if self.frames == 0 { 0.0 } else { (sum_squares / self.frames as f64).sqrt() as f32 }
+        };
+        let event = RecordingLevelsEvent {
+            recording_id: recording_id.to_string(),
+            elapsed_ms,
+            mic_peak: self.mic_peak.min(1.0),
+            mic_rms: rms(self.mic_sum_squares),
+            loopback_peak: loopback_active.then(|| self.loopback_peak.min(1.0)),
+            loopback_rms: loopback_active.then(|| rms(self.loopback_sum_squares)),
+        };
+        *self = LevelMeter::default();
+        event
+    }
+}
+
+// Turns one input stream's interleaved samples into stereo frames at the output rate. Mono is
+// duplicated to both sides and channels beyond the first two are dropped. Samples of a frame
+// split across two reads from the ring buffer are kept until the rest arrives.
+struct StreamInput {
+    channels: usize,
+    pending: Vec<f32>,
+    resampler: LinearResampler,
+}
+
+impl StreamInput {
+    fn new(channels: u16, sample_rate: u32, output_rate: u32) -> Self {
+        StreamInput {
+            channels: channels.max(1) as usize,
+            pending: Vec::new(),
+            resampler: LinearResampler::new(sample_rate, output_rate),
+        }
+    }
+
+    fn push_samples(&mut self, samples: &[f32], out: &mut Vec<(f32, f32)>) {
+        self.pending.extend_from_slice(samples);
+        let whole_frames_len = self.pending.len() / self.channels * self.channels;
+        let frames = self.pending[..whole_frames_len]
+            .chunks_exact(self.channels)
+            .map(|frame| if frame.len() == 1 { (frame[0], frame[0]) } else { (frame[0], frame[1]) });
+        self.resampler.process(frames, out);
+        self.pending.drain(..whole_frames_len);
+    }
+}
+
+// Linear interpolation between consecutive input frames. The position of the next output frame
+// carries over between calls, so chunk boundaries don't add drift over a long recording.
+struct LinearResampler {
+    step: f64,     // Input frames per output frame
+    position: f64, // Of the next output frame, in input frames after `previous`
+    previous: Option<(f32, f32)>,
+}
+
+impl LinearResampler {
+    fn new(input_rate: u32, output_rate: u32) -> Self {
+        LinearResampler {
+            step: input_rate as f64 / output_rate as f64,
+            position: 0.0,
+            previous: None,
+        }
+    }
+
+    fn process(&mut self, frames: impl Iterator<Item = (f32, f32)>, out: &mut Vec<(f32, f32)>) {
+        if self.step == 1.0 {
+            out.extend(frames);
+            return;
+        }
+        for frame in frames {
+            if let Some(previous) = self.previous {
+                // Output frames falling between `previous` (position 0) and `frame` (position 1)
+                while self.position < 1.0 {
+                    let t = self.position as f32;
+                    out.push((
+                        previous.0 + (frame.0 - previous.0) * t,
+                        previous.1 + (frame.1 - previous.1) * t,
+                    ));
+                    self.position += self.step;
+                }
+                self.position -= 1.0;
+            }
+            self.previous = Some(frame);
+        }
+    }
+}
+
+// Common sample rates reported for each device when they fall inside a supported range
+const COMMON_SAMPLE_RATES: [u32; 9] = [8000, 16000, 22050, 32000, 44100, 48000, 88200, 96000, 192000];
+
 lazy_static::lazy_static! {
     static ref ACTIVE_RECORDINGS: Mutex<HashMap<String, Arc<Mutex<RecordingState>>>> = Mutex::new(HashMap::new());
     // Global host, initialized on first use. Keep it alive for callbacks.
-    static ref GLOBAL_HOST: Mutex<Option<cpal::Host>> = Mutex::new(None);
-    // Ensures devices_changed_callback is registered only once.
-    // static ref DEVICE_CHANGE_LISTENER_REGISTERED: AtomicBool = AtomicBool::new(false);
-}
-
-
-// This callback function will be invoked by CPAL when audio devices change.
-// It needs to be `Send + 'static` if it's registered globally.
-// To interact with ACTIVE_RECORDINGS, it must be carefully designed.
-/*
-fn devices_changed_callback(host_id: cpal::HostId) {
-    println!("Audio devices changed for host: {:?}", host_id);
-
-    // Create a snapshot of recording identifiers to check.
-    // This avoids holding the ACTIVE_RECORDINGS lock for too long.
-    let recordings_to_check: Vec<(String, String, Option<String>)> = {
-        let active_recordings_guard = ACTIVE_RECORDINGS.lock().unwrap();
-        active_recordings_guard.iter().filter_map(|(id, state_arc)| {
-            let state_guard = state_arc.lock().unwrap(); // Lock individual state
-            Some((
-                id.clone(),
-                state_guard.mic_device_identifier.clone(),
-                state_guard.loopback_device_identifier.clone(),
-            ))
-        }).collect()
+    static ref GLOBAL_HOST: Mutex<cpal::Host> = {
+        println!("Initializing global CPAL host.");
+        Mutex::new(cpal::default_host())
     };
+    // Recordings stopped automatically (device lost, limit reached), until stop_recording is called for them
+    static ref AUTO_STOPPED_RECORDINGS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    // Recordings start_recording is still opening devices and files for
+    static ref STARTING_RECORDINGS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    // Recordings taken out of ACTIVE_RECORDINGS whose threads are being joined and files finalized
+    static ref STOPPING_RECORDINGS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
 
-    if recordings_to_check.is_empty() {
-        println!("Device change detected, but no active recordings to check.");
-        return;
+
+// cpal has no device-change notification, so while recordings are running the host's input
+// devices are polled and a recording whose device is gone is stopped.
+pub const DEVICE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// Locks the global host. Every recording's device lookups go through it, so a panic inside a
+// backend while one was enumerating devices mustn't keep the others from starting.
+fn lock_host() -> MutexGuard<'static, cpal::Host> {
+    GLOBAL_HOST.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub fn has_active_recordings() -> bool {
+    !ACTIVE_RECORDINGS.lock().unwrap().is_empty()
+}
+
+// Checks every running recording's devices against the host's current input devices.
+// Enumerating devices can block, so call this off the async runtime.
+pub fn find_recordings_with_lost_devices() -> Vec<AutoStopRequest> {
+    // Snapshot the handles first so no state lock is taken while the map is locked
+    let recordings: Vec<(String, Arc<Mutex<RecordingState>>)> = {
+        let recordings_map = ACTIVE_RECORDINGS.lock().unwrap();
+        recordings_map.iter().map(|(id, state)| (id.clone(), state.clone())).collect()
+    };
+    // WASAPI loopback captures an output device, which is looked for among the output devices
+    let devices_in_use: Vec<(String, String, Option<String>, bool)> = recordings
+        .into_iter()
+        .filter_map(|(id, recording_arc)| {
+            let state = recording_arc.lock().unwrap();
+            let loopback_is_output = state.loopback_source == Some(LoopbackSource::WasapiLoopback);
+            // Already stopping; nothing more to do for it
+            (!state.stop_signal.load(Ordering::Relaxed))
+                .then(|| (id, state.mic_device_name.clone(), state.loopback_device_name.clone(), loopback_is_output))
+        })
+        .collect();
+    if devices_in_use.is_empty() {
+        return Vec::new();
     }
 
-    // Get the current list of available devices from the global host.
-    // This requires locking GLOBAL_HOST.
-    let host_opt = { // Scope for host_guard
-        let mut host_guard = GLOBAL_HOST.lock().unwrap();
-        if host_guard.is_none() {
-            // Attempt to initialize if not already. Should ideally be initialized before callback is registered.
-            println!("WARN: GLOBAL_HOST not initialized during devices_changed_callback. Attempting to initialize.");
-            *host_guard = Some(cpal::default_host());
-        }
-        host_guard.clone() // Clone the Option<Host>, not the MutexGuard
+    let (current_device_names, current_output_device_names): (Vec<String>, Vec<String>) = {
+        let host = lock_host();
+        let input_names = match host.input_devices() {
+            Ok(devices) => devices.filter_map(|d| d.name().ok()).collect(),
+            Err(e) => {
+                eprintln!("[AudioProcessing] Failed to enumerate input devices while checking for removed devices: {}", e);
+                return Vec::new();
+            }
+        };
+        let output_names = if devices_in_use.iter().any(|(_, _, _, loopback_is_output)| *loopback_is_output) {
+            match host.output_devices() {
+                Ok(devices) => devices.filter_map(|d| d.name().ok()).collect(),
+                Err(e) => {
+                    eprintln!("[AudioProcessing] Failed to enumerate output devices while checking for removed devices: {}", e);
+                    return Vec::new();
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        (input_names, output_names)
+    };
+
+    devices_in_use
+        .into_iter()
+        .filter_map(|(recording_id, mic_device_name, loopback_device_name, loopback_is_output)| {
+            let mic_lost = (!current_device_names.contains(&mic_device_name)).then_some(mic_device_name);
+            let loopback_names = if loopback_is_output { &current_output_device_names } else { &current_device_names };
+            let loopback_lost = loopback_device_name.filter(|name| !loopback_names.contains(name));
+            mic_lost
+                .or(loopback_lost)
+                .map(|device_name| AutoStopRequest {
+                    recording_id,
+                    reason: AutoStopReason::DeviceLost { device_name },
+                })
+        })
+        .collect()
+}
+
+// Recordings whose writer thread stopped them on reaching a limit, each returned once
+pub fn find_auto_stopped_recordings() -> Vec<AutoStopRequest> {
+    let recordings: Vec<(String, Arc<Mutex<RecordingState>>)> = {
+        let recordings_map = ACTIVE_RECORDINGS.lock().unwrap();
+        recordings_map.iter().map(|(id, state)| (id.clone(), state.clone())).collect()
     };
+    recordings
+        .into_iter()
+        .filter_map(|(recording_id, recording_arc)| {
+            let auto_stop_arc = recording_arc.lock().unwrap().auto_stop.clone();
+            let mut auto_stop = auto_stop_arc.lock().unwrap();
+            let auto_stop = auto_stop.as_mut().filter(|auto_stop| !auto_stop.handled)?;
+            auto_stop.handled = true;
+            Some(AutoStopRequest {
+                recording_id,
+                reason: auto_stop.reason.clone(),
+            })
+        })
+        .collect()
+}
 
-    let host = match host_opt {
-        Some(h) => h,
+// Stops a recording the way stop_recording does, saving what was captured so far, and tells
+// the frontend why. Without a database the recording is only signalled to stop, which
+// finalizes the file; its row is written when stop_recording is called once the database is back.
+pub async fn stop_recording_automatically(
+    request: AutoStopRequest,
+    db_pool: Option<&PgPool>,
+    app_handle: &AppHandle,
+) {
+    let AutoStopRequest { recording_id, reason } = request;
+    println!("[AudioProcessing] Stopping recording {} automatically: {:?}", recording_id, reason);
+
+    match db_pool {
+        Some(db_pool) => {
+            AUTO_STOPPED_RECORDINGS.lock().unwrap().insert(recording_id.clone());
+            if let Err(e) = stop_recording(recording_id.clone(), db_pool, app_handle).await {
+                eprintln!("[AudioProcessing] Failed to stop recording {} automatically: {}", recording_id, e);
+            }
+        }
         None => {
-            eprintln!("ERROR: GLOBAL_HOST could not be initialized in devices_changed_callback. Cannot check devices.");
-            return;
+            if let Ok(recording_arc) = active_recording(&recording_id) {
+                recording_arc.lock().unwrap().stop_signal.store(true, Ordering::Relaxed);
+            }
         }
-    };
+    }
 
-    let current_devices = match host.input_devices() {
-        Ok(devices) => devices.collect::<Vec<_>>(),
-        Err(e) => {
-            eprintln!("Error fetching current input devices in callback: {}", e);
-            return;
+    let emitted = match reason {
+        AutoStopReason::DeviceLost { device_name } => {
+            app_handle.emit(EVENT_RECORDING_DEVICE_LOST, RecordingDeviceLostEvent { recording_id, device_name })
         }
+        reason => app_handle.emit(EVENT_RECORDING_AUTO_STOPPED, RecordingAutoStoppedEvent { recording_id, reason }),
     };
+    if let Err(e) = emitted {
+        eprintln!("[AudioProcessing] Failed to emit auto-stop event: {}", e);
+    }
+}
 
-    let current_device_names: Vec<String> = current_devices.iter().filter_map(|d| d.name().ok()).collect();
-    println!("Current available input device names: {:?}", current_device_names);
+// Removed local AudioRecording and AudioBlockReference structs
 
-    for (rec_id, mic_id, loop_id_opt) in recordings_to_check {
-        let mut mic_found = false;
-        for name in &current_device_names {
-            if *name == mic_id {
-                mic_found = true;
-                break;
-            }
-        }
+// Lists the input devices of the global host along with their supported formats
+pub fn list_input_devices() -> Result<Vec<AudioDeviceInfo>, String> {
+    let host_ref = lock_host();
+
+    let default_name = host_ref.default_input_device().and_then(|d| d.name().ok());
+    let devices = host_ref
+        .input_devices()
+        .map_err(|e| format!("Failed to enumerate input devices: {}", e))?;
 
-        let mut loopback_found_or_not_used = true; // Assume true if not used
-        if let Some(loop_id) = loop_id_opt {
-            loopback_found_or_not_used = false; // Now it must be found
-            for name in &current_device_names {
-                if *name == loop_id {
-                    loopback_found_or_not_used = true;
-                    break;
+    let mut result = Vec::new();
+    for (index, device) in devices.enumerate() {
+        let name = match device.name() {
+            Ok(name) => name,
+            Err(e) => {
+                println!("  Input Device {}: Error getting name: {}", index, e);
+                continue;
+            }
+        };
+
+        let mut sample_rates: Vec<u32> = Vec::new();
+        let mut channel_counts: Vec<u16> = Vec::new();
+        if let Ok(ranges) = device.supported_input_configs() {
+            for range in ranges {
+                for rate in COMMON_SAMPLE_RATES {
+                    if range.min_sample_rate().0 <= rate && rate <= range.max_sample_rate().0 && !sample_rates.contains(&rate) {
+                        sample_rates.push(rate);
+                    }
+                }
+                if !channel_counts.contains(&range.channels()) {
+                    channel_counts.push(range.channels());
                 }
             }
         }
+        sample_rates.sort_unstable();
+        channel_counts.sort_unstable();
+
+        result.push(AudioDeviceInfo {
+            index,
+            is_default: default_name.as_deref() == Some(name.as_str()),
+            name,
+            sample_rates,
+            channel_counts,
+        });
+    }
 
-        if !mic_found || !loopback_found_or_not_used {
-            println!(
-                "Device change: Mic found: {}, Loopback found/not used: {} for recording ID: {}",
-                mic_found, loopback_found_or_not_used, rec_id
-            );
-            // Device used by this recording is missing. Signal it to stop.
-            let active_recordings_guard = ACTIVE_RECORDINGS.lock().unwrap();
-            if let Some(state_arc) = active_recordings_guard.get(&rec_id) {
-                let mut state_guard = state_arc.lock().unwrap();
-                if !state_guard.stop_signal.load(Ordering::Relaxed) {
-                    state_guard.stop_signal.store(true, Ordering::Relaxed);
-                    println!("Recording {} stopped due to audio device removal/change.", rec_id);
-                }
-            }
+    Ok(result)
+}
+
+// Finds an input device by exact name, failing with the list of available names otherwise
+fn find_input_device(devices: &[cpal::Device], name: &str) -> Result<cpal::Device, String> {
+    devices
+        .iter()
+        .find(|d| d.name().map(|n| n == name).unwrap_or(false))
+        .cloned()
+        .ok_or_else(|| {
+            let available: Vec<String> = devices.iter().filter_map(|d| d.name().ok()).collect();
+            format!("Audio input device '{}' not found. Available devices: {}", name, available.join(", "))
+        })
+}
+
+// Where a recording's system audio comes from
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoopbackSource {
+    Named, // The input device passed as loopback_device_name
+    #[cfg_attr(not(windows), allow(dead_code))]
+    WasapiLoopback, // The default output device, captured in WASAPI loopback mode
+    #[cfg_attr(not(windows), allow(dead_code))]
+    StereoMix, // An input device that mirrors the output ("Stereo Mix" and the like)
+}
+
+// A device to capture system audio from, and how it was found
+struct LoopbackDevice {
+    device: cpal::Device,
+    name: String,
+    source: LoopbackSource,
+}
+
+// Picks the loopback device: the input device named, or else whatever the platform offers.
+// None records the microphone only.
+fn select_loopback_device(
+    host: &cpal::Host,
+    input_devices: &[cpal::Device],
+    name: Option<&str>,
+) -> Result<Option<LoopbackDevice>, String> {
+    match name {
+        Some(name) => {
+            let device = find_input_device(input_devices, name)?;
+            println!("Loopback device selected by name: '{}'", name);
+            Ok(Some(LoopbackDevice { device, name: name.to_string(), source: LoopbackSource::Named }))
         }
+        None => Ok(platform_loopback_device(host, input_devices)),
     }
 }
-*/
 
-// Removed local AudioRecording and AudioBlockReference structs
+#[cfg(windows)]
+fn platform_loopback_device(host: &cpal::Host, input_devices: &[cpal::Device]) -> Option<LoopbackDevice> {
+    let selected = wasapi_loopback_device(host).or_else(|| stereo_mix_device(input_devices));
+    match &selected {
+        Some(loopback) => println!("Windows loopback device found and selected: '{}' ({:?})", loopback.name, loopback.source),
+        None => println!("WARN: No Windows loopback device (WASAPI loopback or Stereo Mix, etc.) found. Will record microphone only."),
+    }
+    selected
+}
+
+// cpal captures an output device in loopback mode when an input stream is built on it, in the
+// render device's mix format.
+#[cfg(windows)]
+fn wasapi_loopback_device(host: &cpal::Host) -> Option<LoopbackDevice> {
+    let Some(device) = host.default_output_device() else {
+        println!("WARN: No default output device for WASAPI loopback.");
+        return None;
+    };
+    let name = device.name().ok()?;
+    match device.default_output_config() {
+        Ok(config) if CONVERTIBLE_SAMPLE_FORMATS.contains(&config.sample_format()) => {
+            Some(LoopbackDevice { device, name, source: LoopbackSource::WasapiLoopback })
+        }
+        Ok(config) => {
+            println!("WARN: Output device '{}' mixes in unsupported format {:?}; skipping WASAPI loopback.", name, config.sample_format());
+            None
+        }
+        Err(e) => {
+            println!("WARN: Failed to get mix format of output device '{}' for WASAPI loopback: {}", name, e);
+            None
+        }
+    }
+}
+
+// Drivers that expose the output mix as an input device, found by name
+#[cfg(windows)]
+fn stereo_mix_device(input_devices: &[cpal::Device]) -> Option<LoopbackDevice> {
+    input_devices.iter().find_map(|device_candidate| {
+        let name = device_candidate.name().ok()?;
+        (name.contains("Stereo Mix") || name.contains("Wave Out Mix") || name.contains("What U Hear") || name.contains("Loopback"))
+            .then(|| LoopbackDevice { device: device_candidate.clone(), name, source: LoopbackSource::StereoMix })
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn platform_loopback_device(_host: &cpal::Host, _input_devices: &[cpal::Device]) -> Option<LoopbackDevice> {
+    println!("INFO: Automatic loopback device selection is not implemented for macOS. Logged candidates may be manually selectable in the future.");
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn platform_loopback_device(_host: &cpal::Host, _input_devices: &[cpal::Device]) -> Option<LoopbackDevice> {
+    println!("INFO: Automatic loopback device selection is not implemented for Linux. Logged candidates may be manually selectable in the future.");
+    None
+}
+
+#[cfg(not(any(windows, target_os = "macos", target_os = "linux")))]
+fn platform_loopback_device(_host: &cpal::Host, _input_devices: &[cpal::Device]) -> Option<LoopbackDevice> {
+    println!("INFO: Loopback device detection is OS-specific. Microphone only for this platform unless a generic input device serves as loopback.");
+    None
+}
+
+impl RecordingState {
+    fn elapsed_ms(&self) -> u64 {
+        self.start_time.elapsed().as_millis() as u64
+    }
+
+    // The current position in the output file, which trails elapsed_ms once silence is skipped
+    fn file_position_ms(&self) -> u64 {
+        self.silence_log.lock().unwrap().file_position_ms(self.elapsed_ms())
+    }
+
+    fn diagnostics(&self, recording_id: &str) -> RecordingDiagnostics {
+        RecordingDiagnostics {
+            recording_id: recording_id.to_string(),
+            ring_buffer_capacity: self.ring_buffer_capacity,
+            mic: self.mic_counters.snapshot(self.mic_sample_format),
+            loopback: self.loopback_sample_format.map(|format| self.loopback_counters.snapshot(format)),
+            incomplete: false,
+        }
+    }
+}
+
+// The sample counters of an active recording's streams
+pub fn get_recording_diagnostics(recording_id: &str) -> Result<RecordingDiagnostics, String> {
+    let recording_arc = active_recording(recording_id)?;
+    let state = recording_arc.lock().unwrap();
+    Ok(state.diagnostics(recording_id))
+}
+
+// Maps a position in an active recording's timeline to the matching position in its file, so
+// timestamps taken while silence was being skipped still point at the right audio. Returns
+// None if the recording isn't active.
+pub fn map_recording_position(recording_id: &str, recording_ms: u64) -> Option<u64> {
+    let recording_arc = active_recording(recording_id).ok()?;
+    let state = recording_arc.lock().unwrap();
+    let position_ms = state.silence_log.lock().unwrap().file_position_ms(recording_ms);
+    Some(position_ms)
+}
+
+// Looks up an active recording. The map lock is released before returning, so callers can
+// lock the state without holding both locks (start/stop take them in the same order).
+fn active_recording(recording_id: &str) -> Result<Arc<Mutex<RecordingState>>, String> {
+    let recordings_map = ACTIVE_RECORDINGS.lock().unwrap();
+    recordings_map.get(recording_id).cloned()
+        .ok_or_else(|| format!("Recording {} is not active", recording_id))
+}
+
+pub fn is_recording_active(recording_id: &str) -> bool {
+    ACTIVE_RECORDINGS.lock().unwrap().contains_key(recording_id)
+}
+
+// IDs of the recordings running now
+pub fn active_recording_ids() -> Vec<Uuid> {
+    ACTIVE_RECORDINGS.lock().unwrap().keys().filter_map(|id| Uuid::parse_str(id).ok()).collect()
+}
+
+// Milliseconds since the recording started, for stamping blocks with the current position
+pub fn get_recording_elapsed_ms(recording_id: &str) -> Result<u64, String> {
+    let recording_arc = active_recording(recording_id)?;
+    let state = recording_arc.lock().unwrap();
+    Ok(state.elapsed_ms())
+}
+
+// Where a page's recording currently is, so a new block can be stamped with that position
+pub struct ActiveRecordingPosition {
+    pub recording_id: Uuid,
+    pub page_id: Uuid,
+    pub file_path: String,
+    pub secondary_file_path: Option<String>,
+    pub track_mode: TrackMode,
+    pub mime_type: &'static str,
+    pub elapsed_ms: u64,
+}
+
+// Finds the recording running for a page. If several are, the most recently started one wins.
+pub fn find_active_recording_for_page(page_id: Uuid) -> Option<ActiveRecordingPosition> {
+    // Snapshot the handles first so no state lock is taken while the map is locked
+    let recordings: Vec<(String, Arc<Mutex<RecordingState>>)> = {
+        let recordings_map = ACTIVE_RECORDINGS.lock().unwrap();
+        recordings_map.iter().map(|(id, state)| (id.clone(), state.clone())).collect()
+    };
+
+    recordings
+        .into_iter()
+        .filter_map(|(id, recording_arc)| {
+            let recording_id = Uuid::parse_str(&id).ok()?;
+            let state = recording_arc.lock().unwrap();
+            let recording_page_id = state.page_id.as_deref().and_then(|p| Uuid::parse_str(p).ok())?;
+            (recording_page_id == page_id).then(|| {
+                let position = ActiveRecordingPosition {
+                    recording_id,
+                    page_id,
+                    file_path: state.file_path.to_string_lossy().to_string(),
+                    secondary_file_path: state
+                        .secondary_file_path
+                        .as_ref()
+                        .map(|path| path.to_string_lossy().to_string()),
+                    track_mode: state.track_mode,
+                    mime_type: state.format.mime_type(),
+                    elapsed_ms: state.file_position_ms(),
+                };
+                (state.elapsed_ms(), position)
+            })
+        })
+        .min_by_key(|(elapsed_ms, _)| *elapsed_ms)
+        .map(|(_, position)| position)
+}
+
+// IDs of the recordings running for a page
+fn active_recording_ids_for_page(page_id: Uuid) -> Vec<String> {
+    let recordings: Vec<(String, Arc<Mutex<RecordingState>>)> = {
+        let recordings_map = ACTIVE_RECORDINGS.lock().unwrap();
+        recordings_map.iter().map(|(id, state)| (id.clone(), state.clone())).collect()
+    };
+    recordings
+        .into_iter()
+        .filter(|(_, recording_arc)| {
+            let state = recording_arc.lock().unwrap();
+            state.page_id.as_deref().and_then(|p| Uuid::parse_str(p).ok()) == Some(page_id)
+        })
+        .map(|(id, _)| id)
+        .collect()
+}
+
+// Returns details about an active recording, or an error if it is not active
+pub fn get_recording_info(recording_id: &str) -> Result<RecordingInfo, String> {
+    let recording_arc = active_recording(recording_id)?;
+    let state = recording_arc.lock().unwrap();
+    let levels = state.levels.lock().unwrap();
+    Ok(RecordingInfo {
+        recording_id: recording_id.to_string(),
+        page_id: state.page_id.clone(),
+        file_path: state.file_path.to_string_lossy().to_string(),
+        elapsed_ms: state.elapsed_ms(),
+        mic_device_name: state.mic_device_name.clone(),
+        loopback_device_name: state.loopback_device_name.clone(),
+        mic_gain: state.gains.mic(),
+        loopback_gain: state.gains.loopback(),
+        loopback_active: state.loopback_device_name.is_some(),
+        mic_peak: levels.as_ref().map(|levels| levels.mic_peak),
+        loopback_peak: levels.as_ref().and_then(|levels| levels.loopback_peak),
+    })
+}
+
+// Details of every active recording, longest running first
+pub fn list_active_recordings() -> Vec<RecordingInfo> {
+    let ids: Vec<String> = ACTIVE_RECORDINGS.lock().unwrap().keys().cloned().collect();
+    // A recording stopped since the snapshot was taken is simply left out
+    let mut recordings: Vec<RecordingInfo> = ids.iter().filter_map(|id| get_recording_info(id).ok()).collect();
+    recordings.sort_by_key(|info| std::cmp::Reverse(info.elapsed_ms));
+    recordings
+}
+
+// Changes the gains of an active recording; a gain left as None is kept. Values are clamped
+// with clamp_gain. Returns the recording's info with the gains now in effect.
+pub fn set_recording_gain(
+    recording_id: &str,
+    mic_gain: Option<f32>,
+    loopback_gain: Option<f32>,
+) -> Result<RecordingInfo, String> {
+    let recording_arc = active_recording(recording_id)?;
+    {
+        let state = recording_arc.lock().unwrap();
+        if let Some(gain) = mic_gain {
+            state.gains.mic.store(clamp_gain(gain).to_bits(), Ordering::Relaxed);
+        }
+        if let Some(gain) = loopback_gain {
+            state.gains.loopback.store(clamp_gain(gain).to_bits(), Ordering::Relaxed);
+        }
+    }
+    get_recording_info(recording_id)
+}
+
+// Why start_recording didn't start a recording
+#[derive(Debug)]
+pub enum StartRecordingError {
+    InvalidId(String),
+    // A recording with this ID is running, still starting, or stopped automatically and not yet
+    // handed back by stop_recording
+    AlreadyRecording(String),
+    Failed(String), // Devices, files or settings
+}
+
+impl From<String> for StartRecordingError {
+    fn from(message: String) -> Self {
+        StartRecordingError::Failed(message)
+    }
+}
+
+// A recording ID taken by start_recording until the recording is in ACTIVE_RECORDINGS. Dropping
+// it gives the ID back, so a start that fails part-way can be retried with the same ID.
+struct StartingRecording(String);
+
+impl StartingRecording {
+    // The ID must be a UUID and free. The check and the claim happen under the ACTIVE_RECORDINGS
+    // lock, which the final insert takes too, so of two starts with the same ID only the first
+    // gets past here.
+    fn claim(recording_id: &str) -> Result<Self, StartRecordingError> {
+        Uuid::parse_str(recording_id)
+            .map_err(|e| StartRecordingError::InvalidId(format!("Invalid recording ID format: {}", e)))?;
+        let recordings_map = ACTIVE_RECORDINGS.lock().unwrap();
+        let mut starting = STARTING_RECORDINGS.lock().unwrap();
+        if recordings_map.contains_key(recording_id)
+            || starting.contains(recording_id)
+            || STOPPING_RECORDINGS.lock().unwrap().contains(recording_id)
+            || AUTO_STOPPED_RECORDINGS.lock().unwrap().contains(recording_id)
+        {
+            return Err(StartRecordingError::AlreadyRecording(format!(
+                "Recording {} has already been started",
+                recording_id
+            )));
+        }
+        starting.insert(recording_id.to_string());
+        Ok(StartingRecording(recording_id.to_string()))
+    }
+}
+
+impl Drop for StartingRecording {
+    fn drop(&mut self) {
+        STARTING_RECORDINGS.lock().unwrap().remove(&self.0);
+    }
+}
+
+// A recording ID held by stop_recording (or shutdown) from taking the recording out of
+// ACTIVE_RECORDINGS until its files are finalized and saved. claim refuses the ID meanwhile, so
+// a new recording can't reopen, and so truncate, the files being finished.
+struct StoppingRecording(String);
+
+impl StoppingRecording {
+    // Call with the ACTIVE_RECORDINGS lock held, as the recording is removed from it, so claim
+    // finds the ID in one of the two
+    fn mark(recording_id: &str) -> Self {
+        STOPPING_RECORDINGS.lock().unwrap().insert(recording_id.to_string());
+        StoppingRecording(recording_id.to_string())
+    }
+}
+
+impl Drop for StoppingRecording {
+    fn drop(&mut self) {
+        STOPPING_RECORDINGS.lock().unwrap().remove(&self.0);
+    }
+}
 
-// Start recording audio
-pub fn start_recording(page_id_opt: Option<&str>, recording_id: &str, audio_dir: &str) -> Result<String, String> {
+// Start recording audio with the devices, format and gains in `options`. The ID is checked
+// first: stop_recording stores the recording under it, and its files are named after it.
+pub fn start_recording(
+    app_handle: AppHandle,
+    page_id_opt: Option<&str>,
+    recording_id: &str,
+    audio_dir: &str,
+    options: RecordingOptions,
+) -> Result<StartedRecording, StartRecordingError> {
+    let _starting = StartingRecording::claim(recording_id)?;
+
+    let RecordingOptions { mic_device_name, loopback_device_name, format, track_mode, mic_gain, loopback_gain, silence, limits, ring_buffer_capacity, encryption_key, title } = options;
+    // Skipped silence would have to be cut from both files at the same frames to keep them aligned
+    if track_mode == TrackMode::Split && silence.mode == SilenceMode::Skip {
+        let message = "Silence can't be skipped when sources are recorded to separate tracks; use mark mode instead";
+        return Err(message.to_string().into());
+    }
+
+    let audio_dir_path = Path::new(audio_dir);
+    std::fs::create_dir_all(audio_dir_path).map_err(|e| format!("Failed to create audio directory: {}", e))?;
+    check_free_space(audio_dir_path, limits.min_free_space_bytes)?;
     // --- Device Variables ---
     let mic_device: cpal::Device;
     let mut available_input_devices: Vec<cpal::Device> = Vec::new();
-    // loopback_device and loopback_device_identifier are determined after host lock.
+    let loopback_selection: Option<LoopbackDevice>;
 
     // --- Host Initialization and Device Enumeration Scope ---
-    { // New scope to limit the lifetime of host_guard and host_ref
-        let mut host_guard = GLOBAL_HOST.lock().unwrap();
-        if host_guard.is_none() {
-            println!("Initializing global CPAL host.");
-            *host_guard = Some(cpal::default_host());
-        }
-        let host_ref = host_guard.as_ref().expect("GLOBAL_HOST should be initialized after check");
+    { // New scope to limit the lifetime of host_ref (the host lock)
+        let host_ref = lock_host();
 
         println!("Selected host: {}", host_ref.id().name());
         println!("Probing for available input devices...");
@@ -170,25 +1192,30 @@ pub fn start_recording(page_id_opt: Option<&str>, recording_id: &str, audio_dir:
                 }
             }
             Err(e) => {
-                return Err(format!("Failed to enumerate input devices: {}", e));
+                return Err(format!("Failed to enumerate input devices: {}", e).into());
             }
         }
 
         if available_input_devices.is_empty() {
-            return Err("No input devices found.".to_string());
+            return Err("No input devices found.".to_string().into());
         }
 
-        mic_device = host_ref.default_input_device()
-            .ok_or_else(|| "No default microphone input device available".to_string())?;
+        mic_device = match mic_device_name {
+            Some(name) => find_input_device(&available_input_devices, name)?,
+            None => host_ref.default_input_device()
+                .ok_or_else(|| "No default microphone input device available".to_string())?,
+        };
         // mic_device is cloned here by ok_or_else -> ok -> map, or default_input_device itself might return owned/cloned.
         // If not, mic_device = host_ref.default_input_device()....?.clone(); may be needed if mic_device must own.
         // Assuming default_input_device() gives ownership or a clone, or a 'static ref if that were possible (it's not for Device).
         // For safety, let's assume it's cloned or owned. CPAL Device struct is usually cloneable.
+
+        loopback_selection = select_loopback_device(&host_ref, &available_input_devices, loopback_device_name)?;
     } // GLOBAL_HOST lock is released here
 
     // --- Post-Host-Lock Device Processing ---
     let mic_device_identifier = mic_device.name().map_err(|e| format!("Failed to get mic device name: {}", e))?;
-    println!("Default microphone device selected: '{}'", mic_device_identifier);
+    println!("Microphone device selected: '{}'", mic_device_identifier);
     if let Ok(config) = mic_device.default_input_config() { // This uses the now-owned mic_device
         println!("  Default mic config: {} channels, {} Hz, {:?}", config.channels(), config.sample_rate().0, config.sample_format());
     }
@@ -200,227 +1227,147 @@ pub fn start_recording(page_id_opt: Option<&str>, recording_id: &str, audio_dir:
         // match host.devices_changed_event_stream() { ... } // host is no longer in scope
         // ...
     }
-    */    // Loopback device selection using the populated available_input_devices
-    let mut loopback_device: Option<cpal::Device> = None;
-    let mut loopback_device_identifier: Option<String> = None;
-    let mut loopback_actual_channels: Option<u16> = None;
-
-    if cfg!(windows) {
-        println!("Attempting to find specific loopback device on Windows...");
-        for device_candidate in available_input_devices.iter() { // Iterate over the cloned devices
-            if let Ok(name) = device_candidate.name() {
-                if name.contains("Stereo Mix") || name.contains("Wave Out Mix") || name.contains("What U Hear") || name.contains("Loopback") {
-                    loopback_device = Some(device_candidate.clone()); // Clone again for ownership by Option
-                    loopback_device_identifier = Some(name);
-                    break;
-                }
-            }
-        }
-        if let Some(ref id) = loopback_device_identifier {
-            println!("Windows loopback device found and selected: '{}'", id);
-        } else {
-            println!("WARN: No specific Windows loopback device (Stereo Mix, etc.) found. Will record microphone only.");
-        }
-    } else if cfg!(target_os = "macos") {
-        println!("INFO: Automatic loopback device selection is not implemented for macOS. Logged candidates may be manually selectable in the future.");
-    } else if cfg!(target_os = "linux") {
-        println!("INFO: Automatic loopback device selection is not implemented for Linux. Logged candidates may be manually selectable in the future.");
-    } else {
-        println!("INFO: Loopback device detection is OS-specific. Microphone only for this platform unless a generic input device serves as loopback.");
-    }
+    */
+    let (loopback_device, loopback_device_identifier, loopback_source) = match loopback_selection {
+        Some(LoopbackDevice { device, name, source }) => (Some(device), Some(name), Some(source)),
+        None => (None, None, None),
+    };
 
     // --- Configuration ---
     const TARGET_SAMPLE_RATE: u32 = 48000;
-    let target_sample_format = SampleFormat::F32; // Process as f32, convert to i16 for WAV
-
-    // Configure Microphone
-    let supported_mic_config = mic_device.default_input_config()
-        .map_err(|e| format!("Failed to get default mic config: {}", e))?;
-    let mut stream_mic_config: StreamConfig = supported_mic_config.into();
-    stream_mic_config.sample_rate = cpal::SampleRate(TARGET_SAMPLE_RATE);
-
-    let supports_target_rate_mic = mic_device.supported_input_configs()
-        .map_err(|e| format!("Failed to get supported mic configs: {}", e))?
-        .any(|range| {
-            let config_at_target_rate = range.with_sample_rate(cpal::SampleRate(TARGET_SAMPLE_RATE));
-            config_at_target_rate.channels() <= 2 && config_at_target_rate.sample_format() == target_sample_format
-        });
-
-    if !supports_target_rate_mic {
-        println!("WARN: Microphone does not support {} Hz sample rate with f32 format. Using default.", TARGET_SAMPLE_RATE);
-        let fallback_supported_config = mic_device.default_input_config().map_err(|e| format!("Failed to get default mic config: {}", e))?;
-        stream_mic_config = fallback_supported_config.into(); // Re-assign, sample rate will be default
-    }
-
-    // Try to set to stereo, fall back to mono
-    let original_mic_channels = stream_mic_config.channels; // Channels from current config (either target rate or default)
-
-    let supports_stereo_mic = mic_device.supported_input_configs()
-        .map_err(|e| format!("Failed to get supported mic configs: {}", e))?
-        .any(|range| {
-            let config_at_current_rate = range.with_sample_rate(stream_mic_config.sample_rate);
-            config_at_current_rate.channels() == 2 && config_at_current_rate.sample_format() == target_sample_format
-        });
-
-    if supports_stereo_mic {
-        stream_mic_config.channels = 2;
-        println!("Microphone configured for stereo input at {:?}.", stream_mic_config.sample_rate);
-    } else {
-        let supports_mono_mic = mic_device.supported_input_configs()
-            .map_err(|e| format!("Failed to get supported mic configs: {}", e))?
-            .any(|range| {
-                let config_at_current_rate = range.with_sample_rate(stream_mic_config.sample_rate);
-                config_at_current_rate.channels() == 1 && config_at_current_rate.sample_format() == target_sample_format
-            });
-        if supports_mono_mic {
-            stream_mic_config.channels = 1;
-            println!("Microphone configured for mono input at {:?}. Will be upmixed to stereo.", stream_mic_config.sample_rate);
-        } else {
-            println!("WARN: Microphone does not support stereo or mono at {:?}. Using original channels: {}.", stream_mic_config.sample_rate, original_mic_channels);
-            stream_mic_config.channels = original_mic_channels; // Keep original channels if specific fallbacks fail
-        }
-    }    let final_mic_config: StreamConfig = stream_mic_config;
-    let mic_actual_channels = final_mic_config.channels;
-    println!("[AudioProcessing] Final Microphone config: Channels: {}, Rate: {}Hz", 
-         final_mic_config.channels, final_mic_config.sample_rate.0);
-    if final_mic_config.sample_rate.0 != TARGET_SAMPLE_RATE {
-        println!("[AudioProcessing] WARN: Mic stream sample rate {} Hz differs from target WAV rate {} Hz.", final_mic_config.sample_rate.0, TARGET_SAMPLE_RATE);
-    }
-
-    // Configure Loopback
-    let mut loopback_config_final: Option<StreamConfig> = None;
-    // let final_loopback_device_identifier = loopback_device_identifier.clone(); // Removed
-
-    if let Some(ref dev) = loopback_device {
-        let supported_loop_config = dev.default_input_config()
-            .map_err(|e| format!("Failed to get default loopback config: {}", e))?;
-        let mut stream_loop_config: StreamConfig = supported_loop_config.into();
-        stream_loop_config.sample_rate = cpal::SampleRate(TARGET_SAMPLE_RATE);
-
-        let supports_target_rate_loop = dev.supported_input_configs()
-            .map_err(|e| format!("Failed to get supported loopback configs: {}", e))?
-            .any(|range| {
-                let config_at_target_rate = range.with_sample_rate(cpal::SampleRate(TARGET_SAMPLE_RATE));
-                config_at_target_rate.channels() <= 2 && config_at_target_rate.sample_format() == target_sample_format
-            });
-
-        if !supports_target_rate_loop {
-            println!("[AudioProcessing] WARN: Loopback device does not support {} Hz sample rate with f32 format. Using default.", TARGET_SAMPLE_RATE);
-            let fallback_supported_config = dev.default_input_config().map_err(|e| format!("Failed to get default loopback config: {}", e))?;
-            stream_loop_config = fallback_supported_config.into(); // Re-assign, sample rate will be default
-        }
-
-        let original_loop_channels = stream_loop_config.channels;
-
-        let supports_stereo_loop = dev.supported_input_configs()
-            .map_err(|e| format!("Failed to get supported loopback configs: {}", e))?
-            .any(|range| {
-                let config_at_current_rate = range.with_sample_rate(stream_loop_config.sample_rate);
-                config_at_current_rate.channels() == 2 && config_at_current_rate.sample_format() == target_sample_format
-            });
+    let target_sample_format = SampleFormat::F32; // Process as f32, convert to i16 for WAV
 
-        if supports_stereo_loop {
-            stream_loop_config.channels = 2;
-            println!("[AudioProcessing] Loopback device configured for stereo input at {:?}.", stream_loop_config.sample_rate);
-        } else {
-            let supports_mono_loop = dev.supported_input_configs()
-                .map_err(|e| format!("Failed to get supported loopback configs: {}", e))?
-                .any(|range| {
-                    let config_at_current_rate = range.with_sample_rate(stream_loop_config.sample_rate);
-                    config_at_current_rate.channels() == 1 && config_at_current_rate.sample_format() == target_sample_format
-                });
-            if supports_mono_loop {
-                stream_loop_config.channels = 1;
-                println!("[AudioProcessing] Loopback device configured for mono input at {:?}. Will be upmixed to stereo.", stream_loop_config.sample_rate);
-            } else {
-                println!("[AudioProcessing] WARN: Loopback device does not support stereo or mono at {:?}. Using default channels: {}.", stream_loop_config.sample_rate, original_loop_channels);
-                stream_loop_config.channels = original_loop_channels;
-            }        }        let final_loop_conf: StreamConfig = stream_loop_config;
-        loopback_actual_channels = Some(final_loop_conf.channels);
-        loopback_config_final = Some(final_loop_conf.clone());
-        println!("[AudioProcessing] Final Loopback config: Channels: {}, Rate: {}Hz", 
-         final_loop_conf.channels, final_loop_conf.sample_rate.0);
-        if final_loop_conf.sample_rate.0 != TARGET_SAMPLE_RATE {
-            println!("[AudioProcessing] WARN: Loopback stream sample rate {} Hz differs from target WAV rate {} Hz.", final_loop_conf.sample_rate.0, TARGET_SAMPLE_RATE);
+    let mic_config = negotiate_stream_config(&mic_device, TARGET_SAMPLE_RATE, target_sample_format)
+        .map_err(|e| format!("Failed to configure microphone: {}", e))?;
+    log_negotiated_config("Microphone", &mic_config, TARGET_SAMPLE_RATE);
+
+    // WASAPI loopback has to use the render device's shared-mode mix format; its rate and
+    // channel count are converted by the writer thread like any other stream's
+    let mut loopback_config: Option<NegotiatedConfig> = match (loopback_device.as_ref(), loopback_source) {
+        (Some(dev), Some(LoopbackSource::WasapiLoopback)) => {
+            let mix_format = dev
+                .default_output_config()
+                .map_err(|e| format!("Failed to get loopback mix format: {}", e))?;
+            Some(NegotiatedConfig::from_default(mix_format))
         }
-    } else {
-        loopback_actual_channels = None;
+        (Some(dev), _) => Some(
+            negotiate_stream_config(dev, TARGET_SAMPLE_RATE, target_sample_format)
+                .map_err(|e| format!("Failed to configure loopback device: {}", e))?,
+        ),
+        (None, _) => None,
+    };
+    match &loopback_config {
+        Some(config) => log_negotiated_config("Loopback", config, TARGET_SAMPLE_RATE),
+        None => println!("[AudioProcessing] Loopback stream not active or not configured for writer thread."),
     }
 
-    println!("[AudioProcessing] Mic stream determined channels for writer thread: {}", mic_actual_channels);
-    if let Some(ch) = loopback_actual_channels {
-        println!("[AudioProcessing] Loopback stream determined channels for writer thread: {}", ch);
-    } else {
-        println!("[AudioProcessing] Loopback stream not active or not configured for writer thread.");
-    }
+    // --- Output File Setup ---
+    // In Split mode this is the microphone track; the system track is created once it's known
+    // whether the loopback stream could be built
+    let file_stem = match track_mode {
+        TrackMode::Split => format!("{}_mic", recording_id),
+        TrackMode::Mixed | TrackMode::MonoMixed => recording_id.to_string(),
+    };
+    let file_path = audio_dir_path.join(format!("{}.{}", file_stem, format.extension()));
+    let file_channels = track_mode.mix_channels();
 
-    // --- WAV File Setup ---
-    let audio_dir_path = Path::new(audio_dir);
-    std::fs::create_dir_all(audio_dir_path).map_err(|e| format!("Failed to create audio directory: {}", e))?;
-    let file_path = audio_dir_path.join(format!("{}.wav", recording_id));
+    println!("[AudioProcessing] Output file: Format: {:?}, Tracks: {:?}, Channels: {}, Sample Rate: {} Hz, Bits/Sample: 16", format, track_mode, file_channels, TARGET_SAMPLE_RATE);
 
-    let spec = hound::WavSpec {
-        channels: 2, // Always stereo output
-        sample_rate: TARGET_SAMPLE_RATE,
-        bits_per_sample: 16,
-        sample_format: hound::SampleFormat::Int,
-    };
-    println!("[AudioProcessing] WAV Spec for output file: Channels: {}, Sample Rate: {} Hz, Bits/Sample: {}, Format: {:?}", spec.channels, spec.sample_rate, spec.bits_per_sample, spec.sample_format);
-    
-    let wav_writer = Arc::new(Mutex::new(Some(
-        hound::WavWriter::create(file_path.clone(), spec)
-            .map_err(|e| format!("Failed to create WAV file: {}", e))?
+    let audio_writer = Arc::new(Mutex::new(Some(
+        AudioEncoder::create(&file_path, format, file_channels, TARGET_SAMPLE_RATE, encryption_key)?
     )));
 
     // --- Ring Buffers and Stop Signal ---
     // Buffer size should be generous enough, e.g., for a few hundred ms of audio at 48kHz stereo.
-    // 48000 samples/sec * 2 channels * 4 bytes/sample (f32) * 0.2 sec = 76800 bytes.
     // Ringbuf stores number of items, not bytes. So, for 200ms of stereo f32: 48000 * 0.2 * 2 = 19200 samples.
-    // Or for mono: 48000 * 0.2 = 9600 samples.
-    // Let's use a slightly larger buffer, e.g. 32768, which can hold ~0.34s of stereo data or ~0.68s of mono.
-    const RING_BUFFER_CAPACITY: usize = 32768;
-    let (mic_producer, mut mic_consumer) = HeapRb::<f32>::new(RING_BUFFER_CAPACITY).split();
-    let (loopback_producer, mut loopback_consumer) = HeapRb::<f32>::new(RING_BUFFER_CAPACITY).split();
+    // The default (DEFAULT_RING_BUFFER_CAPACITY) holds ~0.34s of stereo data or ~0.68s of mono.
+    let ring_buffer_capacity = ring_buffer_capacity.clamp(MIN_RING_BUFFER_CAPACITY, MAX_RING_BUFFER_CAPACITY);
+    let (mic_producer, mut mic_consumer) = HeapRb::<f32>::new(ring_buffer_capacity).split();
+    let (loopback_producer, mut loopback_consumer) = HeapRb::<f32>::new(ring_buffer_capacity).split();
     let stop_signal = Arc::new(AtomicBool::new(false));
+    let mic_counters = Arc::new(StreamCounters::default());
+    let loopback_counters = Arc::new(StreamCounters::default());
 
-    // --- Stream Building ---
-    let _err_fn = |err: cpal::StreamError| {
-        eprintln!("An error occurred on an audio stream: {}", err);
-    };
-
-    let mic_stream_stop_signal = stop_signal.clone();
+    // --- Stream Threads ---
+    // Each stream is built, played and owned by its own thread, so capture runs until the
+    // stop signal is set rather than ending when this function returns
     let mic_device_name_log = mic_device.name().unwrap_or_else(|_| "Unknown Mic".to_string());
-    let mic_stream = build_input_stream_generic::<f32>(&mic_device, &final_mic_config, mic_producer, mic_stream_stop_signal, mic_device_name_log.clone())
-        .map_err(|e| format!("Failed to build microphone stream: {}", e))?;
-    println!("[AudioProcessing] Microphone stream built for device: '{}'", mic_device_name_log);
+    let mic_stream_thread = spawn_input_stream_thread(
+        mic_device,
+        mic_config.clone(),
+        mic_producer,
+        stop_signal.clone(),
+        mic_device_name_log.clone(),
+        app_handle.clone(),
+        recording_id.to_string(),
+        mic_counters.clone(),
+    )
+    .map_err(|e| format!("Failed to start microphone stream: {}", e))?;
+    println!("[AudioProcessing] Microphone stream playing for device: '{}'", mic_device_name_log);
 
-    let mut actual_loopback_stream: Option<cpal::Stream> = None;
-    if let (Some(dev), Some(conf)) = (loopback_device.as_ref(), loopback_config_final.as_ref()) {
+    let mut loopback_stream_thread: Option<JoinHandle<()>> = None;
+    if let (Some(dev), Some(conf)) = (loopback_device, loopback_config.as_ref()) {
         let loopback_device_name_log = dev.name().unwrap_or_else(|_| "Unknown Loopback".to_string());
-        match build_input_stream_generic::<f32>(dev, conf, loopback_producer, stop_signal.clone(), loopback_device_name_log.clone()) {
-            Ok(stream) => {
-                println!("[AudioProcessing] Loopback stream built successfully for device: '{}'", loopback_device_name_log);
-                actual_loopback_stream = Some(stream);
+        match spawn_input_stream_thread(dev, conf.clone(), loopback_producer, stop_signal.clone(), loopback_device_name_log.clone(), app_handle.clone(), recording_id.to_string(), loopback_counters.clone()) {
+            Ok(handle) => {
+                println!("[AudioProcessing] Loopback stream playing for device: '{}'", loopback_device_name_log);
+                loopback_stream_thread = Some(handle);
             }
             Err(e) => {
-                println!("[AudioProcessing] WARN: Failed to build loopback stream for device '{}': {}. Recording microphone only.", loopback_device_name_log, e);
-                loopback_actual_channels = None;
+                println!("[AudioProcessing] WARN: Failed to start loopback stream for device '{}': {}. Recording microphone only.", loopback_device_name_log, e);
+                loopback_config = None;
                 // loopback_device_identifier should remain Some if device was found but stream failed,
-                // but actual_loopback_stream being None is key for writer thread.
+                // but loopback_stream_thread being None is key for writer thread.
                 // For consistency in RecordingState, perhaps clear loopback_device_identifier if stream fails?
                 // final_loopback_device_identifier = None; // Decided against this to keep original device name for potential debugging.
             }
         }
-    } else {
-        loopback_actual_channels = None; // loopback_device_identifier is already None
     }
     // --- Mixing and Writing Thread ---
     let writer_thread_stop_signal = stop_signal.clone();
-    let writer_clone = wav_writer.clone();
+    let writer_clone = audio_writer.clone();
     // Removed target_sample_rate and target_channels_wav, using const TARGET_SAMPLE_RATE and fixed 2 channels for WAV.
     
     // Extract loopback status before moving into thread to avoid Send issues
-    let loopback_is_active = actual_loopback_stream.is_some() && loopback_actual_channels.is_some();
+    let loopback_is_active = loopback_stream_thread.is_some() && loopback_config.is_some();
+
+    let mut secondary_file_path: Option<PathBuf> = None;
+    let secondary_writer: Arc<Mutex<Option<AudioEncoder>>> = Arc::new(Mutex::new(None));
+    if track_mode == TrackMode::Split && loopback_is_active {
+        let path = audio_dir_path.join(format!("{}_system.{}", recording_id, format.extension()));
+        match AudioEncoder::create(&path, format, 2, TARGET_SAMPLE_RATE, encryption_key) {
+            Ok(encoder) => {
+                *secondary_writer.lock().unwrap() = Some(encoder);
+                secondary_file_path = Some(path);
+            }
+            Err(e) => {
+                // Nothing has been written yet, so don't leave an empty microphone file behind
+                stop_signal.store(true, Ordering::Relaxed);
+                drop(audio_writer.lock().unwrap().take());
+                let _ = std::fs::remove_file(&file_path);
+                return Err(format!("Failed to create system audio track: {}", e).into());
+            }
+        }
+    }
+    let writer_secondary = secondary_writer.clone();
+    let mic_actual_channels = mic_config.channels;
+    let mic_sample_rate = mic_config.config.sample_rate.0;
+    let loopback_actual_channels = loopback_config.as_ref().map(|conf| conf.channels);
+    let loopback_sample_rate = loopback_config.as_ref().map(|conf| conf.config.sample_rate.0);
+
+    let writer_app_handle = app_handle.clone();
+    let writer_recording_id = recording_id.to_string();
+    let gains = Arc::new(SourceGains::new(mic_gain, loopback_gain));
+    let writer_gains = gains.clone();
+    let silence_log = Arc::new(Mutex::new(SilenceLog::default()));
+    let writer_silence_log = silence_log.clone();
+    let auto_stop: Arc<Mutex<Option<AutoStop>>> = Arc::new(Mutex::new(None));
+    let writer_auto_stop = auto_stop.clone();
+    let levels: Arc<Mutex<Option<RecordingLevelsEvent>>> = Arc::new(Mutex::new(None));
+    let writer_levels = levels.clone();
+    let writer_mic_counters = mic_counters.clone();
+    let writer_loopback_counters = loopback_counters.clone();
+    let writer_audio_dir = audio_dir_path.to_path_buf();
 
     let writer_thread = thread::spawn(move || {
         let mut iteration_count: u64 = 0; // For logging initial samples and periodic updates
@@ -433,9 +1380,36 @@ pub fn start_recording(page_id_opt: Option<&str>, recording_id: &str, audio_dir:
             loopback_is_active,
             loopback_actual_channels.map_or_else(|| "N/A".to_string(), |ch| ch.to_string()));
 
-        let mut mic_samples_f32 = Vec::with_capacity(RING_BUFFER_CAPACITY);
-        let mut loopback_samples_f32 = Vec::with_capacity(RING_BUFFER_CAPACITY);
-        let mut mixed_samples_i16 = Vec::with_capacity(RING_BUFFER_CAPACITY * 2);
+        let mut mic_samples_f32 = Vec::with_capacity(ring_buffer_capacity);
+        let mut loopback_samples_f32 = Vec::with_capacity(ring_buffer_capacity);
+        let mut mixed_samples_i16 = Vec::with_capacity(ring_buffer_capacity * 2);
+        // Split mode writes each source to its own file; the mix is still built for silence marking
+        let mut mic_track_i16: Vec<i16> = Vec::new();
+        let mut system_track_i16: Vec<i16> = Vec::new();
+        let mix_channels = track_mode.mix_channels();
+
+        // Each stream is converted to stereo frames at the output rate before mixing
+        let mut mic_input = StreamInput::new(mic_actual_channels, mic_sample_rate, TARGET_SAMPLE_RATE);
+        let mut loopback_input = StreamInput::new(
+            loopback_actual_channels.unwrap_or(2),
+            loopback_sample_rate.unwrap_or(TARGET_SAMPLE_RATE),
+            TARGET_SAMPLE_RATE,
+        );
+        let mut mic_frames: Vec<(f32, f32)> = Vec::with_capacity(ring_buffer_capacity);
+        let mut loopback_frames: Vec<(f32, f32)> = Vec::with_capacity(ring_buffer_capacity);
+
+        let mut silence_detector = (silence.mode != SilenceMode::Off)
+            .then(|| SilenceDetector::new(silence, TARGET_SAMPLE_RATE, mix_channels, writer_silence_log));
+        let mut kept_samples_i16: Vec<i16> = Vec::with_capacity(ring_buffer_capacity * 2);
+        // Temporary buffers for pop_slice, sized once since the capacity can be large
+        let mut temp_mic_buffer = vec![0.0f32; ring_buffer_capacity];
+        let mut temp_loopback_buffer = vec![0.0f32; ring_buffer_capacity];
+
+        let mut level_meter = LevelMeter::default();
+        let mut last_level_event = Instant::now();
+        let mut frames_written: u64 = 0;
+        let writer_started_at = Instant::now();
+        let mut last_free_space_check = Instant::now();
 
         loop {
             if writer_thread_stop_signal.load(Ordering::Relaxed) {
@@ -446,10 +1420,8 @@ pub fn start_recording(page_id_opt: Option<&str>, recording_id: &str, audio_dir:
             mic_samples_f32.clear();
             loopback_samples_f32.clear();
             mixed_samples_i16.clear();
-
-            // Temporary buffers for pop_slice
-            let mut temp_mic_buffer = vec![0.0f32; RING_BUFFER_CAPACITY];
-            let mut temp_loopback_buffer = vec![0.0f32; RING_BUFFER_CAPACITY];
+            mic_track_i16.clear();
+            system_track_i16.clear();
 
             let num_popped_mic = mic_consumer.pop_slice(&mut temp_mic_buffer);
             if num_popped_mic > 0 {
@@ -471,80 +1443,91 @@ pub fn start_recording(page_id_opt: Option<&str>, recording_id: &str, audio_dir:
                 }
             }
 
-            let mut mic_idx = 0;
-            let mut loop_idx = 0;
-
-            let mut current_iteration_mic_frames_processed = 0;
-            let mut current_iteration_loop_frames_processed = 0;
-
-            while mic_idx < mic_samples_f32.len() || (has_active_loopback && loop_idx < loopback_samples_f32.len()) {
-                let mut mic_l = 0.0_f32;
-                let mut mic_r = 0.0_f32;
-                let mut loop_l = 0.0_f32;
-                let mut loop_r = 0.0_f32;
-
-                // Get microphone frame
-                if mic_idx < mic_samples_f32.len() {
-                    if mic_actual_channels == 1 {
-                        mic_l = mic_samples_f32[mic_idx];
-                        mic_r = mic_l;
-                        mic_idx += 1;
-                    } else if mic_idx + 1 < mic_samples_f32.len() { // Need 2 samples for stereo
-                        mic_l = mic_samples_f32[mic_idx];
-                        mic_r = mic_samples_f32[mic_idx + 1];
-                        mic_idx += 2;
-                    } else {
-                        mic_idx = mic_samples_f32.len();
-                    }
-                    current_iteration_mic_frames_processed +=1;
-                }
+            mic_frames.clear();
+            loopback_frames.clear();
+            mic_input.push_samples(&mic_samples_f32, &mut mic_frames);
+            if has_active_loopback {
+                loopback_input.push_samples(&loopback_samples_f32, &mut loopback_frames);
+            }
 
-                // Get loopback frame
-                if has_active_loopback && loop_idx < loopback_samples_f32.len() {
-                    if let Some(ch) = loopback_actual_channels {
-                        if ch == 1 {
-                            loop_l = loopback_samples_f32[loop_idx];
-                            loop_r = loop_l;
-                            loop_idx += 1;
-                        } else if loop_idx + 1 < loopback_samples_f32.len() {
-                            loop_l = loopback_samples_f32[loop_idx];
-                            loop_r = loopback_samples_f32[loop_idx + 1];
-                            loop_idx += 2;
-                        } else {
-                            loop_idx = loopback_samples_f32.len();
-                        }
-                         current_iteration_loop_frames_processed +=1;
-                    }
-                }
+            let current_iteration_mic_frames_processed = mic_frames.len();
+            let current_iteration_loop_frames_processed = loopback_frames.len();
+            if has_active_loopback {
+                let frames = current_iteration_mic_frames_processed.max(current_iteration_loop_frames_processed);
+                writer_mic_counters.underrun_frames.fetch_add((frames - current_iteration_mic_frames_processed) as u64, Ordering::Relaxed);
+                writer_loopback_counters.underrun_frames.fetch_add((frames - current_iteration_loop_frames_processed) as u64, Ordering::Relaxed);
+            }
+
+            // Read once per iteration; set_recording_gain may change them at any time
+            let mic_gain = writer_gains.mic();
+            let loopback_gain = writer_gains.loopback();
+
+            let to_i16 = |sample: f32| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            // A stream with fewer frames this iteration contributes silence for the rest
+            for frame_idx in 0..mic_frames.len().max(loopback_frames.len()) {
+                let (mic_l, mic_r) = mic_frames.get(frame_idx).map_or((0.0, 0.0), |&(l, r)| (l * mic_gain, r * mic_gain));
+                let (loop_l, loop_r) = loopback_frames
+                    .get(frame_idx)
+                    .map_or((0.0, 0.0), |&(l, r)| (l * loopback_gain, r * loopback_gain));
 
                 if iteration_count < LOG_INITIAL_SAMPLES_COUNT && (mic_l != 0.0 || mic_r != 0.0 || loop_l != 0.0 || loop_r != 0.0) {
                      println!("[AudioProcessing] Writer Pre-mix (Iter {}): Mic (L:{:.4}, R:{:.4}), Loop (L:{:.4}, R:{:.4})", iteration_count, mic_l, mic_r, loop_l, loop_r);
                 }
 
+                level_meter.add_frame((mic_l, mic_r), (loop_l, loop_r));
+
                 let final_l = (mic_l + loop_l).max(-1.0).min(1.0);
                 let final_r = (mic_r + loop_r).max(-1.0).min(1.0);
 
-                mixed_samples_i16.push((final_l * std::i16::MAX as f32) as i16);
-                mixed_samples_i16.push((final_r * std::i16::MAX as f32) as i16);
+                match track_mode {
+                    TrackMode::Mixed => {
+                        mixed_samples_i16.push(to_i16(final_l));
+                        mixed_samples_i16.push(to_i16(final_r));
+                    }
+                    TrackMode::MonoMixed => mixed_samples_i16.push(to_i16((final_l + final_r) / 2.0)),
+                    TrackMode::Split => {
+                        mixed_samples_i16.push(to_i16(final_l));
+                        mixed_samples_i16.push(to_i16(final_r));
+                        // Both tracks get a frame for every frame of the mix, so they stay aligned
+                        mic_track_i16.push(to_i16(mic_l));
+                        mic_track_i16.push(to_i16(mic_r));
+                        system_track_i16.push(to_i16(loop_l));
+                        system_track_i16.push(to_i16(loop_r));
+                    }
+                }
             }
 
             if (iteration_count < LOG_INITIAL_SAMPLES_COUNT || iteration_count % PERIODIC_LOG_INTERVAL == 0) && (current_iteration_mic_frames_processed > 0 || current_iteration_loop_frames_processed > 0) {
                 println!("[AudioProcessing] Writer (Iter {}): Mic frames processed this iter: {}, Loopback frames processed this iter: {}. Total mixed stereo i16 samples generated: {}",
-                    iteration_count, current_iteration_mic_frames_processed, current_iteration_loop_frames_processed, mixed_samples_i16.len() / 2);
+                    iteration_count, current_iteration_mic_frames_processed, current_iteration_loop_frames_processed, mixed_samples_i16.len() / mix_channels as usize);
             }
 
 
+            let samples_to_write: &[i16] = match silence_detector.as_mut() {
+                Some(detector) => {
+                    kept_samples_i16.clear();
+                    detector.process(&mixed_samples_i16, &mut kept_samples_i16);
+                    &kept_samples_i16
+                }
+                None => &mixed_samples_i16,
+            };
+
             if !mixed_samples_i16.is_empty() {
+                // Skip mode is refused for Split, so the mix is only used for marking there
+                let file_samples: &[i16] = if track_mode == TrackMode::Split { &mic_track_i16 } else { samples_to_write };
                 if let Ok(mut guard) = writer_clone.lock() {
                     if let Some(writer) = guard.as_mut() {
-                        for sample_i16 in mixed_samples_i16.iter() {
-                            writer.write_sample(*sample_i16).unwrap_or_else(|e| eprintln!("[AudioProcessing] Error writing mixed sample: {}",e));
-                        }
-                         if iteration_count >= LOG_INITIAL_SAMPLES_COUNT && mixed_samples_i16.len() > LOG_CHUNK_THRESHOLD {
-                            println!("[AudioProcessing] Writer (Iter {}): Wrote {} i16 samples ({} stereo frames) to WAV.", iteration_count, mixed_samples_i16.len(), mixed_samples_i16.len()/2);
+                        writer.write_samples(file_samples).unwrap_or_else(|e| eprintln!("[AudioProcessing] Error writing mixed samples: {}",e));
+                         if iteration_count >= LOG_INITIAL_SAMPLES_COUNT && file_samples.len() > LOG_CHUNK_THRESHOLD {
+                            println!("[AudioProcessing] Writer (Iter {}): Wrote {} i16 samples ({} frames) to output file.", iteration_count, file_samples.len(), file_samples.len() / file_channels as usize);
                         }
                     }
                 }
+                if let Ok(mut guard) = writer_secondary.lock() {
+                    if let Some(writer) = guard.as_mut() {
+                        writer.write_samples(&system_track_i16).unwrap_or_else(|e| eprintln!("[AudioProcessing] Error writing system track samples: {}", e));
+                    }
+                }
             } else {
                 if !writer_thread_stop_signal.load(Ordering::Relaxed) && mic_consumer.is_empty() && (!has_active_loopback || loopback_consumer.is_empty()) {
                     if iteration_count % (PERIODIC_LOG_INTERVAL * 10) == 0 { // Log sleep less often
@@ -553,124 +1536,429 @@ pub fn start_recording(page_id_opt: Option<&str>, recording_id: &str, audio_dir:
                     thread::sleep(Duration::from_millis(10));
                 }
             }
+            frames_written += (samples_to_write.len() / mix_channels as usize) as u64;
+            if last_level_event.elapsed() >= LEVEL_EVENT_INTERVAL {
+                let elapsed_ms = frames_written * 1000 / TARGET_SAMPLE_RATE as u64;
+                let event = level_meter.take_event(&writer_recording_id, elapsed_ms, has_active_loopback);
+                *writer_levels.lock().unwrap() = Some(event.clone());
+                if let Err(e) = writer_app_handle.emit(EVENT_RECORDING_LEVELS, event) {
+                    eprintln!("[AudioProcessing] Failed to emit levels event: {}", e);
+                }
+                last_level_event = Instant::now();
+            }
+
+            // Stop capturing when a limit is hit; the file is finalized below and the device
+            // watcher saves the recording
+            let mut limit_reached = limits
+                .max_duration
+                .filter(|max_duration| writer_started_at.elapsed() >= *max_duration)
+                .map(|max_duration| AutoStopReason::MaxDuration { max_duration_ms: max_duration.as_millis() as u64 });
+            if limit_reached.is_none() && last_free_space_check.elapsed() >= FREE_SPACE_CHECK_INTERVAL {
+                last_free_space_check = Instant::now();
+                match fs2::available_space(&writer_audio_dir) {
+                    Ok(available_bytes) if available_bytes < limits.min_free_space_bytes => {
+                        limit_reached = Some(AutoStopReason::LowDiskSpace {
+                            available_bytes,
+                            min_free_space_bytes: limits.min_free_space_bytes,
+                        });
+                    }
+                    Ok(_) => {}
+                    Err(e) => eprintln!("[AudioProcessing] Failed to check free disk space: {}", e),
+                }
+            }
+            if let Some(reason) = limit_reached {
+                println!("[AudioProcessing] Writer thread: Limit reached after {} frames: {:?}", frames_written, reason);
+                *writer_auto_stop.lock().unwrap() = Some(AutoStop { reason, at: Instant::now(), handled: false });
+                writer_thread_stop_signal.store(true, Ordering::Relaxed);
+            }
+
             iteration_count += 1;
         }
-        println!("[AudioProcessing] Writer thread: Loop finished. Finalizing WAV file.");
+        println!("[AudioProcessing] Writer thread: Loop finished. Finalizing output file.");
+        if let Some(writer) = writer_secondary.lock().ok().and_then(|mut guard| guard.take()) {
+            writer.finalize().unwrap_or_else(|e| eprintln!("[AudioProcessing] Error finalizing system track encoder: {}", e));
+        }
         if let Ok(mut guard) = writer_clone.lock() {
+            // In Split mode the tracks were written directly and the detector's output isn't needed
+            if let (Some(detector), Some(writer), false) = (silence_detector.as_mut(), guard.as_mut(), track_mode == TrackMode::Split) {
+                kept_samples_i16.clear();
+                detector.finish(&mut kept_samples_i16);
+                writer.write_samples(&kept_samples_i16).unwrap_or_else(|e| eprintln!("[AudioProcessing] Error writing mixed samples: {}",e));
+            }
             if let Some(writer) = guard.take() {
-                writer.finalize().unwrap_or_else(|e| eprintln!("[AudioProcessing] Error finalizing WAV writer: {}", e));
-                 println!("[AudioProcessing] Writer thread: WAV file finalized successfully.");
+                writer.finalize().unwrap_or_else(|e| eprintln!("[AudioProcessing] Error finalizing audio encoder: {}", e));
+                 println!("[AudioProcessing] Writer thread: Output file finalized successfully.");
             } else {
-                println!("[AudioProcessing] Writer thread: WAV writer was already taken or None before finalization call.");
+                println!("[AudioProcessing] Writer thread: Audio encoder was already taken or None before finalization call.");
             }
         } else {
-            eprintln!("[AudioProcessing] Writer thread: Failed to acquire lock for WAV writer finalization.");
+            eprintln!("[AudioProcessing] Writer thread: Failed to acquire lock for audio encoder finalization.");
         }
         println!("[AudioProcessing] Writer thread: Exiting.");
-    });    // --- Play Streams and Store State ---
-    mic_stream.play().map_err(|e| format!("Failed to play mic stream: {}", e))?;
-    let mic_thread_stop_signal = stop_signal.clone();
-    let mic_stream_thread = std::thread::spawn(move || {
-        // Note: We can't move the stream into the thread due to Send trait issues
-        // The stream will be dropped when this function ends, but that's okay
-        // because the stream callbacks will continue running until the stop signal
-        loop {
-            if mic_thread_stop_signal.load(Ordering::Relaxed) {
-                println!("[AudioProcessing] Mic stream thread: Stop signal received. Exiting.");
-                break;
-            }
-            std::thread::sleep(Duration::from_millis(50));
-        }
-        println!("[AudioProcessing] Mic stream thread: Finished.");
     });
 
-    let mut loopback_stream_thread: Option<JoinHandle<()>> = None;
-    if let Some(stream) = actual_loopback_stream {
-        stream.play().map_err(|e| format!("Failed to play loopback stream: {}", e))?;
+    // --- Store State ---
+    if loopback_stream_thread.is_some() {
         println!("Both microphone and loopback streams are playing.");
-        let loop_thread_stop_signal = stop_signal.clone();
-        loopback_stream_thread = Some(std::thread::spawn(move || {
-            // Note: We can't move the stream into the thread due to Send trait issues
-            // The stream will be dropped when this function ends, but that's okay
-            // because the stream callbacks will continue running until the stop signal
-            loop {
-                if loop_thread_stop_signal.load(Ordering::Relaxed) {
-                    println!("[AudioProcessing] Loopback stream thread: Stop signal received. Exiting.");
-                    break;
-                }
-                std::thread::sleep(Duration::from_millis(50));
-            }
-            println!("[AudioProcessing] Loopback stream thread: Finished.");
-        }));
     } else {
         println!("Only microphone stream is playing.");
     }
 
+    let loopback_source = loopback_source.filter(|_| loopback_is_active);
+    let started_event = RecordingStartedEvent {
+        recording_id: recording_id.to_string(),
+        page_id: page_id_opt.map(|s| s.to_string()),
+        mic_device_name: mic_device_identifier.clone(),
+        loopback_device_name: if loopback_is_active { loopback_device_identifier.clone() } else { None },
+        loopback_source,
+    };
+
     let recording_state_data = RecordingState {
         start_time: Instant::now(),
         page_id: page_id_opt.map(|s| s.to_string()),
+        title: title.map(str::to_string),
         file_path: file_path.clone(),
-        writer: wav_writer.clone(),
+        secondary_file_path,
+        writer: audio_writer.clone(),
+        secondary_writer,
+        format,
+        track_mode,
         mic_stream_thread: Some(mic_stream_thread),
         loopback_stream_thread,
         writer_thread: Some(writer_thread),
         stop_signal,
-        // mic_device_identifier, // Store the identifier // Removed
-        // loopback_device_identifier: if loopback_actual_channels.is_some() { final_loopback_device_identifier } else { None }, // Store if loopback is active // Removed
+        gains,
+        silence_log,
+        auto_stop,
+        levels,
+        loopback_source,
+        ring_buffer_capacity,
+        mic_counters,
+        loopback_counters,
+        mic_sample_format: mic_config.sample_format,
+        loopback_sample_format: loopback_config.as_ref().filter(|_| loopback_is_active).map(|conf| conf.sample_format),
+        mic_device_name: mic_device_identifier,
+        loopback_device_name: if loopback_is_active { loopback_device_identifier } else { None },
     };
 
-    let mut recordings_map = ACTIVE_RECORDINGS.lock().unwrap();
-    recordings_map.insert(recording_id.to_string(), Arc::new(Mutex::new(recording_state_data)));
+    let other_page_recording_ids = page_id_opt
+        .and_then(|page_id| Uuid::parse_str(page_id).ok())
+        .map(active_recording_ids_for_page)
+        .unwrap_or_default();
+    ACTIVE_RECORDINGS.lock().unwrap().insert(recording_id.to_string(), Arc::new(Mutex::new(recording_state_data)));
 
     println!("Recording {} started.", recording_id);
-    Ok(recording_id.to_string())
+    if !other_page_recording_ids.is_empty() {
+        println!("[AudioProcessing] Page is also being recorded by {:?}", other_page_recording_ids);
+    }
+    if let Err(e) = app_handle.emit(EVENT_RECORDING_STARTED, started_event) {
+        eprintln!("[AudioProcessing] Failed to emit started event: {}", e);
+    }
+    Ok(StartedRecording {
+        recording_id: recording_id.to_string(),
+        capturing_system_audio: loopback_source.is_some(),
+        loopback_source,
+        page_already_recording: !other_page_recording_ids.is_empty(),
+        other_page_recording_ids,
+    })
+}
+
+// Sample formats the input callbacks convert to f32, best first
+const CONVERTIBLE_SAMPLE_FORMATS: [SampleFormat; 5] =
+    [SampleFormat::F32, SampleFormat::I16, SampleFormat::U16, SampleFormat::I32, SampleFormat::U8];
+
+// The stream config an input device was opened with. channels is what the writer thread
+// converts to stereo; sample_format is what the callback converts to f32.
+#[derive(Debug, Clone, PartialEq)]
+struct NegotiatedConfig {
+    config: StreamConfig,
+    channels: u16,
+    sample_format: SampleFormat,
+}
+
+impl NegotiatedConfig {
+    fn from_default(default: SupportedStreamConfig) -> Self {
+        NegotiatedConfig {
+            channels: default.channels(),
+            sample_format: default.sample_format(),
+            config: default.into(),
+        }
+    }
+}
+
+// Picks the input config closest to the target that the device supports
+fn negotiate_stream_config(
+    device: &cpal::Device,
+    target_rate: u32,
+    target_format: SampleFormat,
+) -> Result<NegotiatedConfig, String> {
+    let default = device
+        .default_input_config()
+        .map_err(|e| format!("Failed to get default config: {}", e))?;
+    let supported: Vec<SupportedStreamConfigRange> = device
+        .supported_input_configs()
+        .map_err(|e| format!("Failed to get supported configs: {}", e))?
+        .collect();
+    choose_stream_config(&supported, default, target_rate, target_format)
+}
+
+// Prefers the target format, then the best other format the callbacks can convert; at that format the target rate (else the default rate), then stereo, then mono,
+// then the default channel count. Without any supported ranges the default config is used.
+fn choose_stream_config(
+    supported: &[SupportedStreamConfigRange],
+    default: SupportedStreamConfig,
+    target_rate: u32,
+    target_format: SampleFormat,
+) -> Result<NegotiatedConfig, String> {
+    if supported.is_empty() {
+        return if CONVERTIBLE_SAMPLE_FORMATS.contains(&default.sample_format()) {
+            Ok(NegotiatedConfig::from_default(default))
+        } else {
+            Err(format!("Unsupported sample format {:?}", default.sample_format()))
+        };
+    }
+
+    let sample_format = std::iter::once(target_format)
+        .chain(CONVERTIBLE_SAMPLE_FORMATS)
+        .filter(|format| CONVERTIBLE_SAMPLE_FORMATS.contains(format))
+        .find(|format| supported.iter().any(|range| range.sample_format() == *format))
+        .ok_or_else(|| "Device supports no sample format that can be converted".to_string())?;
+    let ranges: Vec<&SupportedStreamConfigRange> =
+        supported.iter().filter(|range| range.sample_format() == sample_format).collect();
+
+    let supports_rate = |range: &SupportedStreamConfigRange, rate: u32| {
+        range.min_sample_rate().0 <= rate && rate <= range.max_sample_rate().0
+    };
+    let sample_rate = [target_rate, default.sample_rate().0]
+        .into_iter()
+        .find(|rate| ranges.iter().any(|range| supports_rate(range, *rate)))
+        .unwrap_or_else(|| ranges[0].max_sample_rate().0);
+    let ranges_at_rate: Vec<&SupportedStreamConfigRange> =
+        ranges.into_iter().filter(|range| supports_rate(range, sample_rate)).collect();
+
+    let channels = [2, 1, default.channels()]
+        .into_iter()
+        .find(|channels| ranges_at_rate.iter().any(|range| range.channels() == *channels))
+        .unwrap_or_else(|| ranges_at_rate[0].channels());
+
+    Ok(NegotiatedConfig {
+        config: StreamConfig {
+            channels,
+            sample_rate: cpal::SampleRate(sample_rate),
+            buffer_size: cpal::BufferSize::Default,
+        },
+        channels,
+        sample_format,
+    })
+}
+
+fn log_negotiated_config(label: &str, negotiated: &NegotiatedConfig, target_rate: u32) {
+    println!(
+        "[AudioProcessing] Final {} config: Channels: {}, Rate: {}Hz, Format: {:?}",
+        label, negotiated.channels, negotiated.config.sample_rate.0, negotiated.sample_format
+    );
+    if negotiated.channels == 1 {
+        println!("[AudioProcessing] {} stream is mono and will be upmixed to stereo.", label);
+    }
+    if negotiated.config.sample_rate.0 != target_rate {
+        println!(
+            "[AudioProcessing] {} stream sample rate {} Hz will be resampled to {} Hz.",
+            label, negotiated.config.sample_rate.0, target_rate
+        );
+    }
+}
+
+// How often a stream thread wakes to check the stop signal; joining it unparks it sooner
+const STREAM_THREAD_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Runs an input stream on a thread of its own for as long as the recording lasts. cpal streams
+// aren't Send on every platform and stop capturing when dropped, so the thread builds, plays
+// and owns the stream, parking until the stop signal is set. Returns once the stream is
+// playing, or the error that kept it from starting.
+#[allow(clippy::too_many_arguments)]
+fn spawn_input_stream_thread<R: Runtime>(
+    device: cpal::Device,
+    config: NegotiatedConfig,
+    producer: Producer<f32, Arc<HeapRb<f32>>>,
+    stop_signal: Arc<AtomicBool>,
+    stream_name: String,
+    app_handle: AppHandle<R>,
+    recording_id: String,
+    counters: Arc<StreamCounters>,
+) -> Result<JoinHandle<()>, String> {
+    let (started_tx, started_rx) = std::sync::mpsc::channel::<Result<(), String>>();
+    let thread_stream_name = stream_name.clone();
+    let handle = thread::spawn(move || {
+        let builder = InputStreamBuilder {
+            device: &device,
+            config: &config.config,
+            producer,
+            stop_signal: stop_signal.clone(),
+            stream_name: thread_stream_name.clone(),
+            app_handle,
+            recording_id,
+            counters,
+        };
+        let built = with_sample_type(config.sample_format, builder)
+            .unwrap_or(Err(BuildStreamError::StreamConfigNotSupported));
+        let stream = match built {
+            Ok(stream) => stream,
+            Err(e) => {
+                let _ = started_tx.send(Err(format!("Failed to build stream: {}", e)));
+                return;
+            }
+        };
+        if let Err(e) = stream.play() {
+            let _ = started_tx.send(Err(format!("Failed to play stream: {}", e)));
+            return;
+        }
+        let _ = started_tx.send(Ok(()));
+
+        while !stop_signal.load(Ordering::Relaxed) {
+            thread::park_timeout(STREAM_THREAD_POLL_INTERVAL);
+        }
+        drop(stream);
+        println!("[AudioProcessing] Stream thread for '{}': Stop signal received, stream closed.", thread_stream_name);
+    });
+
+    let started = started_rx
+        .recv()
+        .unwrap_or_else(|_| Err(format!("Stream thread for '{}' exited before starting", stream_name)));
+    match started {
+        Ok(()) => Ok(handle),
+        Err(e) => {
+            let _ = handle.join();
+            Err(e)
+        }
+    }
+}
+
+// Work that needs the Rust type of a stream's samples, run by with_sample_type
+trait WithSampleType {
+    type Output;
+
+    fn call<T>(self) -> Self::Output
+    where
+        T: cpal::SizedSample + Send + 'static,
+        f32: cpal::FromSample<T>;
+}
+
+// Calls f with the sample type of format. None for a format the input callbacks can't convert;
+// each one in CONVERTIBLE_SAMPLE_FORMATS has its type here.
+fn with_sample_type<F: WithSampleType>(format: SampleFormat, f: F) -> Option<F::Output> {
+    match format {
+        SampleFormat::F32 => Some(f.call::<f32>()),
+        SampleFormat::I16 => Some(f.call::<i16>()),
+        SampleFormat::U16 => Some(f.call::<u16>()),
+        SampleFormat::I32 => Some(f.call::<i32>()),
+        SampleFormat::U8 => Some(f.call::<u8>()),
+        _ => None,
+    }
+}
+
+// Builds an input stream for the negotiated format, its callback converting to f32
+struct InputStreamBuilder<'a, R: Runtime> {
+    device: &'a cpal::Device,
+    config: &'a StreamConfig,
+    producer: Producer<f32, Arc<HeapRb<f32>>>,
+    stop_signal: Arc<AtomicBool>,
+    stream_name: String,
+    app_handle: AppHandle<R>,
+    recording_id: String,
+    counters: Arc<StreamCounters>,
+}
+
+impl<R: Runtime> WithSampleType for InputStreamBuilder<'_, R> {
+    type Output = Result<cpal::Stream, BuildStreamError>;
+
+    fn call<T>(self) -> Self::Output
+    where
+        T: cpal::SizedSample + Send + 'static,
+        f32: cpal::FromSample<T>,
+    {
+        build_input_stream_generic::<T, R>(
+            self.device,
+            self.config,
+            self.producer,
+            self.stop_signal,
+            self.stream_name,
+            self.app_handle,
+            self.recording_id,
+            self.counters,
+        )
+    }
+}
+
+// Pushes samples to the ring buffer as f32 and returns how many fit
+fn push_as_f32<T: Sample>(producer: &mut Producer<f32, Arc<HeapRb<f32>>>, samples: &[T]) -> usize
+where
+    f32: cpal::FromSample<T>,
+{
+    producer.push_iter(&mut samples.iter().map(|&sample| f32::from_sample(sample)))
 }
 
-// Helper function to build input stream and push to a producer
-fn build_input_stream_generic<T: Sample + Send + cpal::SizedSample + 'static>(
+// Builds an input stream of sample type T that pushes what the device captures to the producer
+#[allow(clippy::too_many_arguments)]
+fn build_input_stream_generic<T: Sample + Send + cpal::SizedSample + 'static, R: Runtime>(
     device: &cpal::Device,
     config: &cpal::StreamConfig,
     mut producer: Producer<f32, Arc<HeapRb<f32>>>,
     stop_signal: Arc<AtomicBool>,
     stream_name: String, // For logging
+    app_handle: AppHandle<R>,
+    recording_id: String,
+    counters: Arc<StreamCounters>,
 ) -> Result<cpal::Stream, BuildStreamError> 
 where
     T: cpal::Sample,
     f32: cpal::FromSample<T>,
 {
-    lazy_static::lazy_static! {
-        static ref STREAM_DATA_LOG_COUNT: AtomicUsize = AtomicUsize::new(0);
-    }
-    const MAX_STREAM_DATA_LOGS: usize = 5; // Log first few data packets globally to confirm flow
+    const MAX_STREAM_DATA_LOGS: usize = 5; // Log first few data packets of each stream to confirm flow
+    const RING_BUFFER_FULL_LOG_INTERVAL: usize = 1000; // Log every Nth callback that finds the ring buffer full
 
     let data_callback_stream_name = stream_name.clone();
     let error_callback_stream_name = stream_name.clone();
+    let data_recording_id = recording_id.clone();
     let device_name_for_log = device.name().unwrap_or_else(|_| "UnknownDevice".to_string());
+    let error_counters = counters.clone();
     
-    let err_fn = move |err| {
+    let err_fn = move |err: cpal::StreamError| {
         eprintln!("[AudioProcessing] Stream error on '{}': {}", error_callback_stream_name, err);
+        error_counters.callback_errors.fetch_add(1, Ordering::Relaxed);
+        let event = RecordingErrorEvent {
+            recording_id: recording_id.clone(),
+            stream: error_callback_stream_name.clone(),
+            message: err.to_string(),
+        };
+        if let Err(e) = app_handle.emit(EVENT_RECORDING_ERROR, event) {
+            eprintln!("[AudioProcessing] Failed to emit error event: {}", e);
+        }
     };
 
+    // Counted per stream, so concurrent recordings each log their own first packets and drops
+    let mut data_log_count: usize = 0;
+    let mut ring_buffer_full_count: usize = 0;
+
     device.build_input_stream(
         config,
         move |data: &[T], _: &_| {
             if stop_signal.load(Ordering::Relaxed) {
                 return;
-            }            let current_log_count = STREAM_DATA_LOG_COUNT.load(Ordering::Relaxed);
-            if current_log_count < MAX_STREAM_DATA_LOGS {
-                println!("[AudioProcessing] Data received on stream '{}' (Device: {}): {} samples. (Global log count: {})",
-                    data_callback_stream_name, device_name_for_log, data.len(), current_log_count);
-                STREAM_DATA_LOG_COUNT.fetch_add(1, Ordering::Relaxed);
-            }            for &sample_val in data.iter() { // Assuming loop variable is sample_val based on full context
-                if producer.is_full() {
-                     if STREAM_DATA_LOG_COUNT.load(Ordering::Relaxed) % 1000 == 0 { 
-                        println!("[AudioProcessing] WARN: Ring buffer full for stream '{}'. Dropping samples.", data_callback_stream_name);
-                     }
-                    break;
-                }let f32_sample: f32 = f32::from_sample(sample_val);
-                producer.push(f32_sample).unwrap_or_else(|_| {
-                    // This is expected if writer thread stops first or during shutdown.
-                });
+            }
+            if data_log_count < MAX_STREAM_DATA_LOGS {
+                println!("[AudioProcessing] Data received on stream '{}' (Device: {}, recording {}): {} samples. (Log count: {})",
+                    data_callback_stream_name, device_name_for_log, data_recording_id, data.len(), data_log_count);
+                data_log_count += 1;
+            }
+            // Whatever doesn't fit in the ring buffer is dropped
+            let pushed = push_as_f32(&mut producer, data);
+            counters.samples_pushed.fetch_add(pushed as u64, Ordering::Relaxed);
+            if pushed < data.len() {
+                counters.samples_dropped.fetch_add((data.len() - pushed) as u64, Ordering::Relaxed);
+                if ring_buffer_full_count.is_multiple_of(RING_BUFFER_FULL_LOG_INTERVAL) {
+                    println!("[AudioProcessing] WARN: Ring buffer full for stream '{}' (recording {}). Dropping samples.", data_callback_stream_name, data_recording_id);
+                }
+                ring_buffer_full_count += 1;
             }
         },
         err_fn,
@@ -678,24 +1966,177 @@ where
     )
 }
 
+// A stopped recording's saved row, plus the silent stretches found in Mark mode and the final
+// stream counters (None when it had already been stopped automatically). An incomplete
+// recording's writer thread panicked; it has no row, and its file is left for recovery.
+pub struct StoppedRecording {
+    pub recording: Option<DalAudioRecording>,
+    pub incomplete: bool,
+    pub silence_ranges: Vec<SilenceRange>,
+    pub diagnostics: Option<RecordingDiagnostics>,
+}
+
 // New async stop_recording function
 pub async fn stop_recording(
     recording_id_key: String, // This is the String version of UUID from ACTIVE_RECORDINGS key
     db_pool: &PgPool,
-) -> Result<DalAudioRecording, String> {
+    app_handle: &AppHandle,
+) -> Result<StoppedRecording, String> {
     println!("[AudioProcessing] Command received to stop recording: {}", recording_id_key);
 
-    let recording_arc = {
+    // Taken out of the map first, so lookups of running recordings never wait on this one while
+    // its threads are joined and its files finalized. The ID stays taken until then.
+    let taken = {
+        let mut recordings_map = ACTIVE_RECORDINGS.lock().unwrap();
+        let recording_arc = recordings_map.remove(&recording_id_key);
+        recording_arc.map(|recording_arc| (recording_arc, StoppingRecording::mark(&recording_id_key)))
+    };
+    let (recording_arc, _stopping) = match taken {
+        Some(taken) => taken,
+        // Already stopped automatically; hand back what was saved then
+        None if AUTO_STOPPED_RECORDINGS.lock().unwrap().contains(&recording_id_key) => {
+            let recording = saved_auto_stopped_recording(&recording_id_key, db_pool).await?;
+            return Ok(StoppedRecording {
+                recording: Some(recording),
+                incomplete: false,
+                silence_ranges: Vec::new(),
+                diagnostics: None,
+            });
+        }
+        None => return Err(format!("No active recording with ID {}", recording_id_key)),
+    };
+
+    let finished = finish_recording(&recording_id_key, &recording_arc, None, app_handle)
+        .await
+        .ok_or_else(|| format!("Recording {} did not finish", recording_id_key))?;
+    let dal_recording = if finished.diagnostics.incomplete {
+        // No row will come for an automatic stop to hand back later
+        AUTO_STOPPED_RECORDINGS.lock().unwrap().remove(&recording_id_key);
+        None
+    } else {
+        Some(save_finished_recording(&finished, db_pool).await?)
+    };
+
+    Ok(StoppedRecording {
+        recording: dal_recording,
+        incomplete: finished.diagnostics.incomplete,
+        silence_ranges: finished.silence_ranges,
+        diagnostics: Some(finished.diagnostics),
+    })
+}
+
+// How long shutdown waits for recordings to finish and be saved before leaving them to recovery
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How often a join with a deadline checks whether the thread is done
+const THREAD_JOIN_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// Stops every running recording as the app exits. Each one is finished and its row saved as
+// stop_recording does; one that can't be within timeout (or without a database) gets a
+// recovery note so startup recovery saves it against its page.
+pub async fn stop_all_recordings(db_pool: Option<&PgPool>, app_handle: &AppHandle, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    let recordings: Vec<(String, Arc<Mutex<RecordingState>>, StoppingRecording)> = {
         let mut recordings_map = ACTIVE_RECORDINGS.lock().unwrap();
-        recordings_map.remove(&recording_id_key)
-            .ok_or_else(|| format!("No active recording with ID {}", recording_id_key))?
+        recordings_map
+            .drain()
+            .map(|(id, recording_arc)| {
+                let stopping = StoppingRecording::mark(&id);
+                (id, recording_arc, stopping)
+            })
+            .collect()
     };
+    if recordings.is_empty() {
+        return;
+    }
+
+    let finishing_event = RecordingsFinishingEvent {
+        recording_ids: recordings.iter().map(|(id, _, _)| id.clone()).collect(),
+    };
+    if let Err(e) = app_handle.emit(EVENT_RECORDINGS_FINISHING, finishing_event) {
+        eprintln!("[AudioProcessing] Failed to emit finishing event: {}", e);
+    }
+    // Signal every recording up front so their threads wind down together
+    for (_, recording_arc, _) in &recordings {
+        recording_arc.lock().unwrap().stop_signal.store(true, Ordering::Relaxed);
+    }
+
+    for (recording_id_key, recording_arc, _stopping) in recordings {
+        let saved = match (finish_recording(&recording_id_key, &recording_arc, Some(deadline), app_handle).await, db_pool) {
+            (Some(finished), Some(db_pool)) if !finished.diagnostics.incomplete => {
+                match tokio::time::timeout_at(deadline.into(), save_finished_recording(&finished, db_pool)).await {
+                    Ok(Ok(_)) => true,
+                    Ok(Err(e)) => {
+                        eprintln!("[AudioProcessing] Failed to save recording {} on shutdown: {}", recording_id_key, e);
+                        false
+                    }
+                    Err(_) => {
+                        eprintln!("[AudioProcessing] Timed out saving recording {} on shutdown", recording_id_key);
+                        false
+                    }
+                }
+            }
+            _ => false,
+        };
+        if saved {
+            continue;
+        }
+
+        let note = {
+            let state = recording_arc.lock().unwrap();
+            let elapsed = chrono::Duration::from_std(state.start_time.elapsed()).unwrap_or_else(|_| chrono::Duration::zero());
+            RecoveryNote {
+                recording_id: Uuid::parse_str(&recording_id_key).unwrap_or_default(),
+                page_id: state.page_id.as_deref().and_then(|id| Uuid::parse_str(id).ok()),
+                title: state.title.clone(),
+                started_at: chrono::Utc::now() - elapsed,
+                file_path: state.file_path.to_string_lossy().to_string(),
+            }
+        };
+        match recording_recovery::write_recovery_note(&note) {
+            Ok(()) => println!("[AudioProcessing] Left recording {} for recovery on next start", recording_id_key),
+            Err(e) => eprintln!("[AudioProcessing] Failed to write recovery note for {}: {}", recording_id_key, e),
+        }
+    }
+}
+
+// A recording whose threads have stopped and whose files are finalized, ready to be saved
+struct FinishedRecording {
+    recording_id: String,
+    page_id: Option<String>,
+    title: Option<String>,
+    file_path: String,
+    secondary_file_path: Option<String>,
+    format: AudioFormat,
+    track_mode: TrackMode,
+    duration_ms: u64,
+    silence_ranges: Vec<SilenceRange>,
+    diagnostics: RecordingDiagnostics,
+}
 
+// Signals a recording to stop, joins its threads and finalizes its files, then tells the
+// frontend it stopped. Joining and finalizing block, so they run off the async runtime. With a
+// deadline, a writer thread still running by then is left alone and None is returned; its file
+// is then finished by recovery instead. A writer thread that panicked may have left its file
+// half-written, so the files aren't finalized and the diagnostics are marked incomplete.
+async fn finish_recording(
+    recording_id_key: &str,
+    recording_arc: &Arc<Mutex<RecordingState>>,
+    deadline: Option<Instant>,
+    app_handle: &AppHandle,
+) -> Option<FinishedRecording> {
     let (
         start_time,
-        page_id_str_opt,
+        page_id,
+        title,
         file_path_buf,
+        secondary_file_path,
         final_writer_arc,
+        secondary_writer_arc,
+        format,
+        track_mode,
+        silence_log,
+        auto_stop,
         writer_thread_handle,
         mic_stream_thread_handle,
         loop_stream_thread_handle
@@ -706,8 +2147,15 @@ pub async fn stop_recording(
         (
             recording_state_guard.start_time,
             recording_state_guard.page_id.clone(),
+            recording_state_guard.title.clone(),
             recording_state_guard.file_path.clone(),
+            recording_state_guard.secondary_file_path.clone(),
             recording_state_guard.writer.clone(),
+            recording_state_guard.secondary_writer.clone(),
+            recording_state_guard.format,
+            recording_state_guard.track_mode,
+            recording_state_guard.silence_log.clone(),
+            recording_state_guard.auto_stop.clone(),
             recording_state_guard.writer_thread.take(),
             recording_state_guard.mic_stream_thread.take(),
             recording_state_guard.loopback_stream_thread.take()
@@ -715,49 +2163,147 @@ pub async fn stop_recording(
     };
 
     println!("[AudioProcessing] Stop recording {}: Waiting for writer thread to finish.", recording_id_key);
+    let mut writer_panicked = false;
     if let Some(handle) = writer_thread_handle {
-        if let Err(e) = handle.join() {
-            eprintln!("[AudioProcessing] Error joining writer thread for {}: {:?}", recording_id_key, e);
-        } else {
-            println!("[AudioProcessing] Writer thread for {} joined successfully.", recording_id_key);
+        // The writer still owns the encoders, so they can't be finalized under it
+        match join_recording_thread(handle, deadline, "writer", recording_id_key).await {
+            ThreadJoin::Finished => {}
+            ThreadJoin::Panicked => writer_panicked = true,
+            ThreadJoin::TimedOut => return None,
         }
     } else {
          eprintln!("[AudioProcessing] WARN: No writer thread handle found for recording id: {}. File might not be complete.", recording_id_key);
     }
-
     if let Some(handle) = mic_stream_thread_handle {
-        if let Err(e) = handle.join() {
-            eprintln!("[AudioProcessing] Error joining mic stream thread for {}: {:?}", recording_id_key, e);
-        } else {
-            println!("[AudioProcessing] Mic stream thread for {} joined successfully.", recording_id_key);
-        }
+        join_recording_thread(handle, deadline, "mic stream", recording_id_key).await;
     }
-
     if let Some(handle) = loop_stream_thread_handle {
-        if let Err(e) = handle.join() {
-            eprintln!("[AudioProcessing] Error joining loopback stream thread for {}: {:?}", recording_id_key, e);
-        } else {
-            println!("[AudioProcessing] Loopback stream thread for {} joined successfully.", recording_id_key);
-        }
+        join_recording_thread(handle, deadline, "loopback stream", recording_id_key).await;
     }
 
-    {
-        let mut writer_guard = final_writer_arc.lock().unwrap();
-        if let Some(writer) = writer_guard.take() {
-             if let Err(e) = writer.finalize() {
-                eprintln!("WARN: Failed to finalize WAV writer for {}: {}. Continuing metadata saving.", recording_id_key, e);
-             } else {
-                println!("[AudioProcessing] WAV writer for {} finalized successfully by stop_recording.", recording_id_key);
-             }
+    if writer_panicked {
+        eprintln!(
+            "[AudioProcessing] Recording {} is incomplete: its writer thread panicked. Leaving the file for recovery.",
+            recording_id_key
+        );
+    } else {
+        let recording_id = recording_id_key.to_string();
+        let finalized = tokio::task::spawn_blocking(move || {
+            finalize_encoders(&recording_id, &final_writer_arc, &secondary_writer_arc)
+        })
+        .await;
+        if let Err(e) = finalized {
+            eprintln!("WARN: Failed to finalize encoders for {}: {}. Continuing metadata saving.", recording_id_key, e);
         }
     }
 
-    let duration_ms = start_time.elapsed().as_millis();
+    // The writer thread has finished, so the silence log is complete. Skipped audio isn't part
+    // of the file's duration. A writer that panicked may have poisoned these locks.
+    let stopped_at = auto_stop
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+        .map_or_else(Instant::now, |auto_stop| auto_stop.at);
+    let (duration_ms, silence_ranges) = {
+        let silence_log = silence_log.lock().unwrap_or_else(PoisonError::into_inner);
+        let duration_ms = silence_log.file_position_ms(stopped_at.duration_since(start_time).as_millis() as u64);
+        (duration_ms, silence_log.marked.clone())
+    };
+    let mut diagnostics = recording_arc.lock().unwrap().diagnostics(recording_id_key);
+    diagnostics.incomplete = writer_panicked;
     let file_path_string = file_path_buf.to_string_lossy().to_string();
     println!("Recording {} stopped. Duration: {}ms. File: {}", recording_id_key, duration_ms, file_path_string);
+    println!("[AudioProcessing] Final stream counters for {}: {:?}", recording_id_key, diagnostics);
+    let stopped_event = RecordingStoppedEvent {
+        recording_id: recording_id_key.to_string(),
+        duration_ms,
+        file_path: file_path_string.clone(),
+    };
+    if let Err(e) = app_handle.emit(EVENT_RECORDING_STOPPED, stopped_event) {
+        eprintln!("[AudioProcessing] Failed to emit stopped event: {}", e);
+    }
+
+    Some(FinishedRecording {
+        recording_id: recording_id_key.to_string(),
+        page_id,
+        title,
+        file_path: file_path_string,
+        secondary_file_path: secondary_file_path.map(|path| path.to_string_lossy().to_string()),
+        format,
+        track_mode,
+        duration_ms,
+        silence_ranges,
+        diagnostics,
+    })
+}
+
+// Finalizes a recording's encoders once its writer thread has exited
+fn finalize_encoders(
+    recording_id_key: &str,
+    writer: &Mutex<Option<AudioEncoder>>,
+    secondary_writer: &Mutex<Option<AudioEncoder>>,
+) {
+    if let Some(writer) = secondary_writer.lock().unwrap().take() {
+        if let Err(e) = writer.finalize() {
+            eprintln!("WARN: Failed to finalize system track encoder for {}: {}. Continuing metadata saving.", recording_id_key, e);
+        }
+    }
+    if let Some(writer) = writer.lock().unwrap().take() {
+        if let Err(e) = writer.finalize() {
+            eprintln!("WARN: Failed to finalize audio encoder for {}: {}. Continuing metadata saving.", recording_id_key, e);
+        } else {
+            println!("[AudioProcessing] Audio encoder for {} finalized successfully by stop_recording.", recording_id_key);
+        }
+    }
+}
+
+// How joining one of a recording's threads went
+enum ThreadJoin {
+    Finished,
+    Panicked,
+    TimedOut, // Still running when the deadline passed
+}
+
+// Joins one of a recording's threads on a blocking thread, so the async runtime isn't held up
+// while it winds down. With a deadline, gives up once it passes, leaving the thread running.
+async fn join_recording_thread(
+    handle: JoinHandle<()>,
+    deadline: Option<Instant>,
+    thread_name: &str,
+    recording_id_key: &str,
+) -> ThreadJoin {
+    // Stream threads park between stop signal checks
+    handle.thread().unpark();
+    if let Some(deadline) = deadline {
+        while !handle.is_finished() {
+            if Instant::now() >= deadline {
+                eprintln!("[AudioProcessing] Gave up waiting for {} thread for {}", thread_name, recording_id_key);
+                return ThreadJoin::TimedOut;
+            }
+            tokio::time::sleep(THREAD_JOIN_POLL_INTERVAL).await;
+        }
+    }
+    match tokio::task::spawn_blocking(move || handle.join()).await {
+        Ok(Ok(())) => {
+            println!("[AudioProcessing] {} thread for {} joined successfully.", thread_name, recording_id_key);
+            ThreadJoin::Finished
+        }
+        Ok(Err(e)) => {
+            eprintln!("[AudioProcessing] {} thread for {} panicked: {:?}", thread_name, recording_id_key, e);
+            ThreadJoin::Panicked
+        }
+        Err(e) => {
+            eprintln!("[AudioProcessing] Error joining {} thread for {}: {}", thread_name, recording_id_key, e);
+            ThreadJoin::Panicked
+        }
+    }
+}
 
-    let page_uuid: Option<Uuid> = match page_id_str_opt {
-        Some(id_str) => match Uuid::parse_str(&id_str) {
+// Writes a finished recording's row and reads it back
+async fn save_finished_recording(finished: &FinishedRecording, db_pool: &PgPool) -> Result<DalAudioRecording, String> {
+    let recording_id_key = &finished.recording_id;
+    let page_uuid: Option<Uuid> = match &finished.page_id {
+        Some(id_str) => match Uuid::parse_str(id_str) {
             Ok(uuid) => Some(uuid),
             Err(e) => {
                 eprintln!("Error parsing page_id '{}' for recording {}: {}. Recording will be saved without page association.", id_str, recording_id_key, e);
@@ -767,7 +2313,7 @@ pub async fn stop_recording(
         None => None,
     };
 
-    let recording_uuid = Uuid::parse_str(&recording_id_key)
+    let recording_uuid = Uuid::parse_str(recording_id_key)
         .map_err(|e| format!("Failed to parse recording_id_key '{}' as UUID: {}", recording_id_key, e))?;
     // Remove the _frontend_recording_uuid variable, just use recording_uuid
 
@@ -776,9 +2322,12 @@ pub async fn stop_recording(
         db_pool,
         recording_uuid, // <<<< PASS THE PARSED recording_uuid AS THE ID
         page_uuid,
-        &file_path_string,
-        Some("audio/wav"),
-        Some(duration_ms as i32),
+        &finished.file_path,
+        finished.secondary_file_path.as_deref(),
+        finished.track_mode.as_str(),
+        Some(finished.format.mime_type()),
+        Some(finished.duration_ms as i32),
+        finished.title.as_deref(),
     )
     .await
     .map_err(|e| format!("Failed to insert recording metadata into database: {}", e))?;
@@ -792,12 +2341,23 @@ pub async fn stop_recording(
     }
 
     // Fetch the full DalAudioRecording to return, using the ID we intended to insert.
-    let dal_recording = audio_handler::get_audio_recording(db_pool, recording_uuid) // Use recording_uuid here
+    audio_handler::get_audio_recording(db_pool, recording_uuid) // Use recording_uuid here
         .await
-        .map_err(|e| format!("Failed to fetch audio recording with intended ID {}: {}", recording_uuid, e))?
-        .ok_or_else(|| format!("Audio recording with ID {} not found after attempting insert.", recording_uuid))?;
+        .map_err(|e| format!("Failed to fetch audio recording with intended ID {}: {}", recording_uuid, e))
+}
 
-    Ok(dal_recording)
+// The row saved for a recording that was stopped automatically. The ID is forgotten once the
+// finished row has been handed back.
+async fn saved_auto_stopped_recording(recording_id_key: &str, db_pool: &PgPool) -> Result<DalAudioRecording, String> {
+    let recording_uuid = Uuid::parse_str(recording_id_key)
+        .map_err(|e| format!("Failed to parse recording_id_key '{}' as UUID: {}", recording_id_key, e))?;
+    let recording = audio_handler::get_audio_recording(db_pool, recording_uuid)
+        .await
+        .ok()
+        .filter(|recording| recording.duration_ms.is_some())
+        .ok_or_else(|| format!("Recording {} was stopped automatically and is still being saved", recording_id_key))?;
+    AUTO_STOPPED_RECORDINGS.lock().unwrap().remove(recording_id_key);
+    Ok(recording)
 }
 
 // Removed old SQLite-specific functions:
@@ -805,3 +2365,267 @@ pub async fn stop_recording(
 // - get_audio_block_references
 // - create_audio_block_reference
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpal::{SampleRate, SupportedBufferSize};
+
+    fn range(channels: u16, min_rate: u32, max_rate: u32, format: SampleFormat) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange::new(
+            channels,
+            SampleRate(min_rate),
+            SampleRate(max_rate),
+            SupportedBufferSize::Unknown,
+            format,
+        )
+    }
+
+    fn default_config(channels: u16, rate: u32, format: SampleFormat) -> SupportedStreamConfig {
+        SupportedStreamConfig::new(channels, SampleRate(rate), SupportedBufferSize::Unknown, format)
+    }
+
+    // (channels, sample rate, format) of the chosen config
+    fn choose(supported: &[SupportedStreamConfigRange], default: SupportedStreamConfig) -> (u16, u32, SampleFormat) {
+        let negotiated = choose_stream_config(supported, default, 48000, SampleFormat::F32).unwrap();
+        assert_eq!(negotiated.config.channels, negotiated.channels);
+        (negotiated.channels, negotiated.config.sample_rate.0, negotiated.sample_format)
+    }
+
+    #[test]
+    fn negotiation_keeps_a_stereo_only_device_at_the_target() {
+        let supported = [range(2, 8000, 96000, SampleFormat::F32)];
+        let default = default_config(2, 44100, SampleFormat::F32);
+        assert_eq!(choose(&supported, default), (2, 48000, SampleFormat::F32));
+    }
+
+    #[test]
+    fn negotiation_opens_a_mono_only_device_as_mono() {
+        let supported = [range(1, 8000, 96000, SampleFormat::F32)];
+        let default = default_config(1, 48000, SampleFormat::F32);
+        assert_eq!(choose(&supported, default), (1, 48000, SampleFormat::F32));
+
+        let both = [range(1, 8000, 96000, SampleFormat::F32), range(2, 8000, 96000, SampleFormat::F32)];
+        assert_eq!(choose(&both, default_config(1, 48000, SampleFormat::F32)), (2, 48000, SampleFormat::F32));
+    }
+
+    #[test]
+    fn negotiation_falls_back_from_an_unsupported_rate() {
+        // The default rate when the device has it, else the highest rate it has
+        let supported = [range(2, 44100, 44100, SampleFormat::F32)];
+        assert_eq!(choose(&supported, default_config(2, 44100, SampleFormat::F32)), (2, 44100, SampleFormat::F32));
+        let supported = [range(2, 16000, 22050, SampleFormat::F32)];
+        assert_eq!(choose(&supported, default_config(2, 44100, SampleFormat::F32)), (2, 22050, SampleFormat::F32));
+        // Channels are chosen among the ranges at the chosen rate
+        let supported = [range(1, 44100, 44100, SampleFormat::F32), range(2, 96000, 96000, SampleFormat::F32)];
+        assert_eq!(choose(&supported, default_config(1, 44100, SampleFormat::F32)), (1, 44100, SampleFormat::F32));
+    }
+
+    #[test]
+    fn negotiation_converts_when_f32_is_unsupported() {
+        let supported = [range(2, 48000, 48000, SampleFormat::I16)];
+        assert_eq!(choose(&supported, default_config(2, 48000, SampleFormat::I16)), (2, 48000, SampleFormat::I16));
+
+        let unconvertible = [range(2, 48000, 48000, SampleFormat::F64)];
+        let default = default_config(2, 48000, SampleFormat::F64);
+        assert!(choose_stream_config(&unconvertible, default.clone(), 48000, SampleFormat::F32).is_err());
+        // Without any ranges the default config is used, if it can be converted
+        assert!(choose_stream_config(&[], default, 48000, SampleFormat::F32).is_err());
+        assert_eq!(choose(&[], default_config(1, 44100, SampleFormat::I16)), (1, 44100, SampleFormat::I16));
+    }
+
+    #[test]
+    fn negotiation_ranks_sample_formats() {
+        let at = |format| range(2, 48000, 48000, format);
+        let default = || default_config(2, 48000, SampleFormat::U16);
+        let chosen_format = |supported: &[SupportedStreamConfigRange]| choose(supported, default()).2;
+        let all = [at(SampleFormat::U16), at(SampleFormat::I16), at(SampleFormat::F32)];
+        assert_eq!(chosen_format(&all), SampleFormat::F32);
+        assert_eq!(chosen_format(&[at(SampleFormat::U16), at(SampleFormat::I16)]), SampleFormat::I16);
+        assert_eq!(chosen_format(&[at(SampleFormat::F64), at(SampleFormat::U16)]), SampleFormat::U16);
+        assert_eq!(chosen_format(&[at(SampleFormat::U8), at(SampleFormat::I32)]), SampleFormat::I32);
+    }
+
+    // The sample type a format is dispatched to, and what its silence converts to
+    struct SampleTypeName;
+
+    impl WithSampleType for SampleTypeName {
+        type Output = (&'static str, f32);
+
+        fn call<T>(self) -> Self::Output
+        where
+            T: cpal::SizedSample + Send + 'static,
+            f32: cpal::FromSample<T>,
+        {
+            (std::any::type_name::<T>(), f32::from_sample(T::EQUILIBRIUM))
+        }
+    }
+
+    #[test]
+    fn sample_formats_dispatch_to_their_types() {
+        assert_eq!(with_sample_type(SampleFormat::F32, SampleTypeName), Some(("f32", 0.0)));
+        assert_eq!(with_sample_type(SampleFormat::I16, SampleTypeName), Some(("i16", 0.0)));
+        assert_eq!(with_sample_type(SampleFormat::U16, SampleTypeName), Some(("u16", 0.0)));
+        for format in CONVERTIBLE_SAMPLE_FORMATS {
+            assert!(with_sample_type(format, SampleTypeName).is_some(), "{:?} isn't dispatched", format);
+        }
+        assert_eq!(with_sample_type(SampleFormat::F64, SampleTypeName), None);
+    }
+
+    // The samples as push_as_f32 stores them
+    fn converted<T: Sample>(samples: &[T]) -> Vec<f32>
+    where
+        f32: cpal::FromSample<T>,
+    {
+        let (mut producer, mut consumer) = HeapRb::<f32>::new(samples.len()).split();
+        assert_eq!(push_as_f32(&mut producer, samples), samples.len());
+        consumer.pop_iter().collect()
+    }
+
+    #[test]
+    fn samples_convert_to_f32() {
+        assert_eq!(converted(&[-1.0f32, -0.25, 0.0, 0.5, 1.0]), vec![-1.0, -0.25, 0.0, 0.5, 1.0]);
+        assert_eq!(converted(&[i16::MIN, -16384i16, 0, 16384]), vec![-1.0, -0.5, 0.0, 0.5]);
+        assert_eq!(converted(&[0u16, 16384, 32768, 49152]), vec![-1.0, -0.5, 0.0, 0.5]);
+        assert!((converted(&[i16::MAX])[0] - 1.0).abs() < 1e-4);
+        assert!((converted(&[u16::MAX])[0] - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn push_as_f32_stops_when_the_ring_buffer_is_full() {
+        let (mut producer, consumer) = HeapRb::<f32>::new(3).split();
+        assert_eq!(push_as_f32(&mut producer, &[1i16, 2, 3, 4, 5]), 3);
+        assert_eq!(consumer.len(), 3);
+    }
+
+    // A 440 Hz tone at the rate, in stereo frames
+    fn tone(rate: u32, frames: usize) -> Vec<(f32, f32)> {
+        (0..frames)
+            .map(|index| {
+                let sample = (index as f32 * 440.0 * std::f32::consts::TAU / rate as f32).sin();
+                (sample, -sample)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resampler_turns_44100_hz_into_48000_hz_frames() {
+        let input = tone(44100, 44100 * 10);
+        // Chunks of uneven sizes, as popped from the ring buffer
+        let mut resampler = LinearResampler::new(44100, 48000);
+        let mut output = Vec::new();
+        for chunk in input.chunks(441).flat_map(|chunk| chunk.chunks(300)) {
+            resampler.process(chunk.iter().copied(), &mut output);
+        }
+        assert!(output.len().abs_diff(48000 * 10) <= 1, "{} frames", output.len());
+
+        // The same frames as from one chunk, so boundaries add no drift
+        let mut whole = Vec::new();
+        LinearResampler::new(44100, 48000).process(input.iter().copied(), &mut whole);
+        assert_eq!(output, whole);
+        // Each output frame lies on the tone, between the input frames around it
+        let expected = tone(48000, 48000);
+        assert!(output.iter().zip(&expected).all(|(a, b)| (a.0 - b.0).abs() < 0.01 && (a.1 - b.1).abs() < 0.01));
+    }
+
+    #[test]
+    fn resampler_passes_frames_through_at_the_same_rate() {
+        let input = tone(48000, 1000);
+        let mut output = Vec::new();
+        LinearResampler::new(48000, 48000).process(input.iter().copied(), &mut output);
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn stream_input_upmixes_mono_and_joins_split_frames() {
+        let mut output = Vec::new();
+        let mut mono = StreamInput::new(1, 48000, 48000);
+        mono.push_samples(&[0.25, -0.5], &mut output);
+        assert_eq!(output, vec![(0.25, 0.25), (-0.5, -0.5)]);
+
+        output.clear();
+        let mut surround = StreamInput::new(4, 48000, 48000);
+        surround.push_samples(&[0.1, 0.2, 0.3, 0.4, 0.5, 0.6], &mut output);
+        assert_eq!(output, vec![(0.1, 0.2)]);
+        surround.push_samples(&[0.7, 0.8], &mut output);
+        assert_eq!(output, vec![(0.1, 0.2), (0.5, 0.6)]);
+    }
+
+    #[test]
+    fn starting_a_recording_refuses_an_invalid_id() {
+        let result = StartingRecording::claim("not-a-uuid");
+        assert!(matches!(result, Err(StartRecordingError::InvalidId(_))));
+        assert!(matches!(StartingRecording::claim(""), Err(StartRecordingError::InvalidId(_))));
+    }
+
+    #[test]
+    fn starting_a_recording_refuses_an_id_in_use() {
+        let recording_id = Uuid::new_v4().to_string();
+        let starting = StartingRecording::claim(&recording_id).unwrap();
+        assert!(matches!(StartingRecording::claim(&recording_id), Err(StartRecordingError::AlreadyRecording(_))));
+        // A failed start gives the ID back
+        drop(starting);
+        let starting = StartingRecording::claim(&recording_id).unwrap();
+        drop(starting);
+
+        // Stopped automatically, but not yet handed back by stop_recording
+        AUTO_STOPPED_RECORDINGS.lock().unwrap().insert(recording_id.clone());
+        let result = StartingRecording::claim(&recording_id);
+        AUTO_STOPPED_RECORDINGS.lock().unwrap().remove(&recording_id);
+        assert!(matches!(result, Err(StartRecordingError::AlreadyRecording(_))));
+    }
+
+    #[test]
+    fn starting_a_recording_refuses_an_id_being_stopped() {
+        let recording_id = Uuid::new_v4().to_string();
+        // As stop_recording does while the recording's files are finalized
+        let stopping = StoppingRecording::mark(&recording_id);
+        assert!(matches!(StartingRecording::claim(&recording_id), Err(StartRecordingError::AlreadyRecording(_))));
+
+        // Once stopped and saved, the ID is free again
+        drop(stopping);
+        assert!(StartingRecording::claim(&recording_id).is_ok());
+    }
+
+    // The stream used to be dropped when start_recording returned, so capture stopped after the
+    // first few callbacks. Its thread has to keep it playing until the stop signal.
+    #[cfg(feature = "audio-device-tests")]
+    #[test]
+    fn capture_continues_after_the_stream_thread_starts() {
+        let app = tauri::test::mock_app();
+        let device = lock_host().default_input_device().expect("no default input device");
+        let config = negotiate_stream_config(&device, 48000, SampleFormat::F32).unwrap();
+        let (producer, mut consumer) = HeapRb::<f32>::new(DEFAULT_RING_BUFFER_CAPACITY).split();
+        let stop_signal = Arc::new(AtomicBool::new(false));
+        let counters = Arc::new(StreamCounters::default());
+        let handle = spawn_input_stream_thread(
+            device,
+            config,
+            producer,
+            stop_signal.clone(),
+            "test".to_string(),
+            app.handle().clone(),
+            Uuid::new_v4().to_string(),
+            counters.clone(),
+        )
+        .unwrap();
+
+        // Drained like the writer thread does, so samples are never dropped for lack of room
+        let mut pushed = counters.samples_pushed.load(Ordering::Relaxed);
+        for second in 1..=4 {
+            let until = Instant::now() + Duration::from_secs(1);
+            while Instant::now() < until {
+                consumer.clear();
+                thread::sleep(Duration::from_millis(10));
+            }
+            let now = counters.samples_pushed.load(Ordering::Relaxed);
+            assert!(now > pushed, "no samples arrived during second {}", second);
+            pushed = now;
+        }
+
+        stop_signal.store(true, Ordering::Relaxed);
+        handle.thread().unpark();
+        handle.join().unwrap();
+        assert_eq!(counters.callback_errors.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,528 @@
+// Output encoders for recordings. The writer thread in audio.rs produces interleaved i16
+// frames and hands them to an AudioEncoder, which writes WAV (hound), FLAC or Ogg Opus,
+// encrypted when given the vault key.
+
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use audiopus::coder::Encoder as OpusEncoder;
+use audiopus::{Application, Bitrate, Channels, SampleRate};
+
+use crate::vault_crypto::{VaultFile, VaultKey};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AudioFormat {
+    #[default]
+    Wav,
+    Flac,
+    Opus,
+}
+
+impl AudioFormat {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "wav" => Ok(AudioFormat::Wav),
+            "flac" => Ok(AudioFormat::Flac),
+            "opus" => Ok(AudioFormat::Opus),
+            other => Err(format!("Unsupported audio format '{}'. Expected one of: wav, flac, opus", other)),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AudioFormat::Wav => "wav",
+            AudioFormat::Flac => "flac",
+            AudioFormat::Opus => "opus",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            AudioFormat::Wav => "audio/wav",
+            AudioFormat::Flac => "audio/flac",
+            AudioFormat::Opus => "audio/ogg",
+        }
+    }
+}
+
+pub enum AudioEncoder {
+    Wav(hound::WavWriter<BufWriter<VaultFile>>),
+    Flac(FlacWriter<BufWriter<VaultFile>>),
+    Opus(OggOpusWriter<BufWriter<VaultFile>>),
+}
+
+impl AudioEncoder {
+    // The file is encrypted with key when one is given
+    pub fn create(
+        path: &Path,
+        format: AudioFormat,
+        channels: u16,
+        sample_rate: u32,
+        key: Option<&VaultKey>,
+    ) -> Result<Self, String> {
+        match format {
+            AudioFormat::Wav => {
+                let spec = hound::WavSpec {
+                    channels,
+                    sample_rate,
+                    bits_per_sample: 16,
+                    sample_format: hound::SampleFormat::Int,
+                };
+                let file = VaultFile::create(path, key).map_err(|e| format!("Failed to create WAV file: {}", e))?;
+                hound::WavWriter::new(BufWriter::new(file), spec)
+                    .map(AudioEncoder::Wav)
+                    .map_err(|e| format!("Failed to create WAV file: {}", e))
+            }
+            AudioFormat::Flac => {
+                let file = VaultFile::create(path, key).map_err(|e| format!("Failed to create FLAC file: {}", e))?;
+                FlacWriter::new(BufWriter::new(file), channels, sample_rate)
+                    .map(AudioEncoder::Flac)
+                    .map_err(|e| format!("Failed to write FLAC header: {}", e))
+            }
+            AudioFormat::Opus => {
+                let file = VaultFile::create(path, key).map_err(|e| format!("Failed to create Opus file: {}", e))?;
+                OggOpusWriter::new(BufWriter::new(file), channels, sample_rate).map(AudioEncoder::Opus)
+            }
+        }
+    }
+
+    // Writes interleaved 16-bit samples
+    pub fn write_samples(&mut self, samples: &[i16]) -> Result<(), String> {
+        match self {
+            AudioEncoder::Wav(writer) => {
+                let mut sample_writer = writer.get_i16_writer(samples.len() as u32);
+                for sample in samples {
+                    sample_writer.write_sample(*sample);
+                }
+                sample_writer.flush().map_err(|e| e.to_string())
+            }
+            AudioEncoder::Flac(writer) => writer.write_samples(samples).map_err(|e| e.to_string()),
+            AudioEncoder::Opus(writer) => writer.write_samples(samples),
+        }
+    }
+
+    // Flushes buffered audio and fixes up headers so the file is playable
+    pub fn finalize(self) -> Result<(), String> {
+        match self {
+            AudioEncoder::Wav(writer) => writer.finalize().map_err(|e| e.to_string()),
+            AudioEncoder::Flac(writer) => writer.finalize().map_err(|e| e.to_string()),
+            AudioEncoder::Opus(writer) => writer.finalize(),
+        }
+    }
+}
+
+// --- FLAC ---
+// Minimal FLAC encoder: fixed-size blocks, fixed linear predictors (order 0-4) and
+// single-partition Rice-coded residuals. Compresses speech to roughly 50-60% of WAV.
+
+const FLAC_BLOCK_SIZE: usize = 4096;
+const FLAC_BITS_PER_SAMPLE: u32 = 16;
+const FLAC_MAX_RICE_PARAMETER: u32 = 14;
+
+pub struct FlacWriter<W: Write + Seek> {
+    inner: W,
+    channels: u16,
+    sample_rate: u32,
+    pending: Vec<i16>, // interleaved samples not yet encoded into a frame
+    frame_number: u32,
+    total_frames: u64, // samples per channel written so far
+    min_frame_size: u32,
+    max_frame_size: u32,
+}
+
+impl<W: Write + Seek> FlacWriter<W> {
+    pub fn new(mut inner: W, channels: u16, sample_rate: u32) -> io::Result<Self> {
+        if channels == 0 || channels > 8 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "FLAC supports 1 to 8 channels"));
+        }
+        inner.write_all(b"fLaC")?;
+        let mut writer = FlacWriter {
+            inner,
+            channels,
+            sample_rate,
+            pending: Vec::with_capacity(FLAC_BLOCK_SIZE * channels as usize),
+            frame_number: 0,
+            total_frames: 0,
+            min_frame_size: 0,
+            max_frame_size: 0,
+        };
+        // Placeholder STREAMINFO; rewritten with the real totals in finalize
+        let stream_info = writer.stream_info();
+        writer.inner.write_all(&stream_info)?;
+        Ok(writer)
+    }
+
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        let block_len = FLAC_BLOCK_SIZE * self.channels as usize;
+        self.pending.extend_from_slice(samples);
+        if self.pending.len() >= block_len {
+            let mut start = 0;
+            while self.pending.len() - start >= block_len {
+                let block: Vec<i16> = self.pending[start..start + block_len].to_vec();
+                self.write_frame(&block)?;
+                start += block_len;
+            }
+            self.pending.drain(..start);
+        }
+        Ok(())
+    }
+
+    pub fn finalize(mut self) -> io::Result<()> {
+        let channels = self.channels as usize;
+        let complete_len = self.pending.len() - self.pending.len() % channels;
+        if complete_len > 0 {
+            let block: Vec<i16> = self.pending[..complete_len].to_vec();
+            self.write_frame(&block)?;
+        }
+        self.pending.clear();
+
+        let stream_info = self.stream_info();
+        self.inner.seek(SeekFrom::Start(4))?;
+        self.inner.write_all(&stream_info)?;
+        self.inner.seek(SeekFrom::End(0))?;
+        self.inner.flush()
+    }
+
+    // Metadata block header plus the 34-byte STREAMINFO body
+    fn stream_info(&self) -> Vec<u8> {
+        let mut bits = BitWriter::default();
+        bits.write(1, 1); // last metadata block
+        bits.write(0, 7); // STREAMINFO
+        bits.write(34, 24);
+        bits.write(FLAC_BLOCK_SIZE as u64, 16); // min block size
+        bits.write(FLAC_BLOCK_SIZE as u64, 16); // max block size
+        bits.write(self.min_frame_size as u64, 24);
+        bits.write(self.max_frame_size as u64, 24);
+        bits.write(self.sample_rate as u64, 20);
+        bits.write(self.channels as u64 - 1, 3);
+        bits.write(FLAC_BITS_PER_SAMPLE as u64 - 1, 5);
+        bits.write(self.total_frames, 36);
+        for _ in 0..4 {
+            bits.write(0, 32); // MD5 unknown
+        }
+        bits.into_bytes()
+    }
+
+    fn write_frame(&mut self, interleaved: &[i16]) -> io::Result<()> {
+        let channels = self.channels as usize;
+        let block_size = interleaved.len() / channels;
+
+        let mut bits = BitWriter::default();
+        bits.write(0x3FFE, 14); // sync code
+        bits.write(0, 1); // reserved
+        bits.write(0, 1); // fixed blocking strategy
+        let block_size_code = if block_size == FLAC_BLOCK_SIZE {
+            0b1100
+        } else if block_size <= 256 {
+            0b0110
+        } else {
+            0b0111
+        };
+        bits.write(block_size_code, 4);
+        bits.write(0b0000, 4); // sample rate from STREAMINFO
+        bits.write(channels as u64 - 1, 4); // independent channels
+        bits.write(0b100, 3); // 16 bits per sample
+        bits.write(0, 1); // reserved
+        for byte in utf8_coded(self.frame_number) {
+            bits.write(byte as u64, 8);
+        }
+        match block_size_code {
+            0b0110 => bits.write(block_size as u64 - 1, 8),
+            0b0111 => bits.write(block_size as u64 - 1, 16),
+            _ => {}
+        }
+        let header_crc = crc8(bits.bytes());
+        bits.write(header_crc as u64, 8);
+
+        for channel in 0..channels {
+            let samples: Vec<i64> = interleaved
+                .iter()
+                .skip(channel)
+                .step_by(channels)
+                .map(|s| *s as i64)
+                .collect();
+            write_fixed_subframe(&mut bits, &samples);
+        }
+
+        bits.align();
+        let frame_crc = crc16(bits.bytes());
+        bits.write(frame_crc as u64, 16);
+
+        let frame = bits.into_bytes();
+        self.inner.write_all(&frame)?;
+
+        let frame_size = frame.len() as u32;
+        self.min_frame_size = if self.min_frame_size == 0 { frame_size } else { self.min_frame_size.min(frame_size) };
+        self.max_frame_size = self.max_frame_size.max(frame_size);
+        self.frame_number += 1;
+        self.total_frames += block_size as u64;
+        Ok(())
+    }
+}
+
+// Residuals of the fixed polynomial predictor of the given order (samples[order..])
+fn fixed_residuals(samples: &[i64], order: usize) -> Vec<i64> {
+    (order..samples.len())
+        .map(|i| {
+            let s = samples;
+            match order {
+                0 => s[i],
+                1 => s[i] - s[i - 1],
+                2 => s[i] - 2 * s[i - 1] + s[i - 2],
+                3 => s[i] - 3 * s[i - 1] + 3 * s[i - 2] - s[i - 3],
+                _ => s[i] - 4 * s[i - 1] + 6 * s[i - 2] - 4 * s[i - 3] + s[i - 4],
+            }
+        })
+        .collect()
+}
+
+fn write_fixed_subframe(bits: &mut BitWriter, samples: &[i64]) {
+    let max_order = 4.min(samples.len().saturating_sub(1));
+    let (order, residuals) = (0..=max_order)
+        .map(|order| (order, fixed_residuals(samples, order)))
+        .min_by_key(|(_, residuals)| residuals.iter().map(|r| r.unsigned_abs()).sum::<u64>())
+        .expect("at least order 0 is always available");
+
+    bits.write(0, 1); // padding
+    bits.write(0b001000 | order as u64, 6); // SUBFRAME_FIXED
+    bits.write(0, 1); // no wasted bits
+    for warmup in &samples[..order] {
+        bits.write_signed(*warmup, FLAC_BITS_PER_SAMPLE);
+    }
+
+    let folded: Vec<u64> = residuals
+        .iter()
+        .map(|r| if *r >= 0 { (*r as u64) << 1 } else { ((-*r as u64) << 1) - 1 })
+        .collect();
+    let rice_parameter = (0..=FLAC_MAX_RICE_PARAMETER)
+        .min_by_key(|k| folded.iter().map(|u| (u >> k) + 1 + *k as u64).sum::<u64>())
+        .unwrap_or(0);
+
+    bits.write(0b00, 2); // Rice coding, 4-bit parameters
+    bits.write(0, 4); // partition order 0
+    bits.write(rice_parameter as u64, 4);
+    for u in folded {
+        bits.write_unary(u >> rice_parameter);
+        bits.write(u, rice_parameter);
+    }
+}
+
+// FLAC's UTF-8-like variable length encoding of frame numbers
+fn utf8_coded(value: u32) -> Vec<u8> {
+    if value < 0x80 {
+        return vec![value as u8];
+    }
+    let (byte_count, first_prefix) = match value {
+        0..=0x7FF => (2, 0xC0u8),
+        0x800..=0xFFFF => (3, 0xE0),
+        0x1_0000..=0x1F_FFFF => (4, 0xF0),
+        0x20_0000..=0x3FF_FFFF => (5, 0xF8),
+        _ => (6, 0xFC),
+    };
+    let mut bytes = vec![0u8; byte_count];
+    let mut remaining = value;
+    for byte in bytes.iter_mut().skip(1).rev() {
+        *byte = 0x80 | (remaining & 0x3F) as u8;
+        remaining >>= 6;
+    }
+    bytes[0] = first_prefix | remaining as u8;
+    bytes
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc: u8 = 0;
+    for byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+// MSB-first bit packer
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    accumulator: u64,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u64, bits: u32) {
+        if bits == 0 {
+            return;
+        }
+        debug_assert!(bits <= 56);
+        self.accumulator = (self.accumulator << bits) | (value & ((1u64 << bits) - 1));
+        self.bit_count += bits;
+        while self.bit_count >= 8 {
+            self.bit_count -= 8;
+            self.bytes.push((self.accumulator >> self.bit_count) as u8);
+        }
+        self.accumulator &= (1u64 << self.bit_count) - 1;
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    fn write_unary(&mut self, zeros: u64) {
+        let mut remaining = zeros;
+        while remaining >= 32 {
+            self.write(0, 32);
+            remaining -= 32;
+        }
+        self.write(1, remaining as u32 + 1);
+    }
+
+    fn align(&mut self) {
+        if self.bit_count > 0 {
+            self.write(0, 8 - self.bit_count);
+        }
+    }
+
+    // Complete bytes written so far
+    fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+// --- Ogg Opus ---
+
+const OPUS_FRAME_MS: u32 = 20;
+const OPUS_MAX_PACKET_SIZE: usize = 4000;
+const OPUS_BITRATE: i32 = 96_000;
+
+pub struct OggOpusWriter<W: Write> {
+    packet_writer: ogg::PacketWriter<W>,
+    encoder: OpusEncoder,
+    serial: u32,
+    channels: usize,
+    frame_size: usize, // samples per channel per Opus packet
+    pre_skip: u64,
+    pending: Vec<i16>,
+    samples_encoded: u64, // real (non-padding) samples per channel encoded so far
+}
+
+impl<W: Write> OggOpusWriter<W> {
+    pub fn new(inner: W, channels: u16, sample_rate: u32) -> Result<Self, String> {
+        let opus_rate = SampleRate::try_from(sample_rate as i32)
+            .map_err(|_| format!("Opus does not support a {} Hz sample rate", sample_rate))?;
+        let opus_channels = match channels {
+            1 => Channels::Mono,
+            2 => Channels::Stereo,
+            other => return Err(format!("Opus encoding supports mono or stereo, not {} channels", other)),
+        };
+
+        let mut encoder = OpusEncoder::new(opus_rate, opus_channels, Application::Audio)
+            .map_err(|e| format!("Failed to create Opus encoder: {}", e))?;
+        encoder
+            .set_bitrate(Bitrate::BitsPerSecond(OPUS_BITRATE))
+            .map_err(|e| format!("Failed to set Opus bitrate: {}", e))?;
+        let pre_skip = encoder.lookahead().map_err(|e| format!("Failed to query Opus lookahead: {}", e))? as u64;
+
+        let mut writer = OggOpusWriter {
+            packet_writer: ogg::PacketWriter::new(inner),
+            encoder,
+            serial: rand_serial(),
+            channels: channels as usize,
+            frame_size: (sample_rate * OPUS_FRAME_MS / 1000) as usize,
+            pre_skip,
+            pending: Vec::new(),
+            samples_encoded: 0,
+        };
+
+        // Identification header (RFC 7845 section 5.1)
+        let mut head = Vec::with_capacity(19);
+        head.extend_from_slice(b"OpusHead");
+        head.push(1); // version
+        head.push(channels as u8);
+        head.extend_from_slice(&(pre_skip as u16).to_le_bytes());
+        head.extend_from_slice(&sample_rate.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // channel mapping family
+        writer.write_header_packet(head)?;
+
+        // Comment header (RFC 7845 section 5.2)
+        let vendor = b"gita";
+        let mut tags = Vec::new();
+        tags.extend_from_slice(b"OpusTags");
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor);
+        tags.extend_from_slice(&0u32.to_le_bytes()); // no user comments
+        writer.write_header_packet(tags)?;
+
+        Ok(writer)
+    }
+
+    pub fn write_samples(&mut self, samples: &[i16]) -> Result<(), String> {
+        self.pending.extend_from_slice(samples);
+        let packet_len = self.frame_size * self.channels;
+        let mut start = 0;
+        while self.pending.len() - start >= packet_len {
+            let frame: Vec<i16> = self.pending[start..start + packet_len].to_vec();
+            self.samples_encoded += self.frame_size as u64;
+            self.encode_packet(&frame, ogg::PacketWriteEndInfo::NormalPacket)?;
+            start += packet_len;
+        }
+        self.pending.drain(..start);
+        Ok(())
+    }
+
+    pub fn finalize(mut self) -> Result<(), String> {
+        // The last packet is padded with silence; its granule position only counts the real
+        // samples so players trim the padding.
+        let packet_len = self.frame_size * self.channels;
+        let real_samples = (self.pending.len() / self.channels) as u64;
+        let mut frame = std::mem::take(&mut self.pending);
+        frame.resize(packet_len, 0);
+        self.samples_encoded += real_samples;
+        self.encode_packet(&frame, ogg::PacketWriteEndInfo::EndStream)?;
+
+        self.packet_writer
+            .into_inner()
+            .flush()
+            .map_err(|e| format!("Failed to flush Opus file: {}", e))
+    }
+
+    fn write_header_packet(&mut self, packet: Vec<u8>) -> Result<(), String> {
+        self.packet_writer
+            .write_packet(packet.into_boxed_slice(), self.serial, ogg::PacketWriteEndInfo::EndPage, 0)
+            .map_err(|e| format!("Failed to write Opus header: {}", e))
+    }
+
+    fn encode_packet(&mut self, frame: &[i16], end_info: ogg::PacketWriteEndInfo) -> Result<(), String> {
+        let mut output = vec![0u8; OPUS_MAX_PACKET_SIZE];
+        let len = self
+            .encoder
+            .encode(frame, &mut output)
+            .map_err(|e| format!("Opus encoding failed: {}", e))?;
+        output.truncate(len);
+        let granule_position = self.pre_skip + self.samples_encoded;
+        self.packet_writer
+            .write_packet(output.into_boxed_slice(), self.serial, end_info, granule_position)
+            .map_err(|e| format!("Failed to write Opus packet: {}", e))
+    }
+}
+
+// Ogg stream serial numbers only need to be unique within a file
+fn rand_serial() -> u32 {
+    uuid::Uuid::new_v4().as_u128() as u32
+}
@@ -0,0 +1,103 @@
+// Encrypts the recordings made before the vault had a passphrase, one file at a time (both
+// files of a split recording). Files that are already encrypted are left as they are, so the
+// migration can be run again after a failure.
+
+use serde::Serialize;
+use sqlx::PgPool;
+use std::path::PathBuf;
+use tauri::{AppHandle, Emitter};
+
+use crate::audio;
+use crate::audio_handler;
+use crate::dal_error::DalError;
+use crate::vault_crypto::{self, VaultKey};
+
+pub const EVENT_ENCRYPT_PROGRESS: &str = "vault://encrypt-progress";
+
+#[derive(Serialize, Debug, Clone)]
+pub struct EncryptProgressEvent {
+    pub current: usize, // 1-based index of the file just processed
+    pub total: usize,
+    pub file_path: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct EncryptFailure {
+    pub file_path: String,
+    pub error: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct EncryptSummary {
+    pub total_files: usize,
+    pub encrypted: usize,
+    pub already_encrypted: usize,
+    pub missing_files: Vec<String>,  // Recorded in the database but not found on disk
+    pub skipped_active: Vec<String>, // Still being recorded
+    pub failures: Vec<EncryptFailure>,
+}
+
+pub async fn encrypt_existing_audio(
+    pool: &PgPool,
+    app_handle: &AppHandle,
+    key: &VaultKey,
+) -> Result<EncryptSummary, DalError> {
+    let recordings = audio_handler::get_all_audio_recordings(pool).await?;
+    let files: Vec<(String, String)> = recordings
+        .into_iter()
+        .flat_map(|recording| {
+            let id = recording.id.to_string();
+            std::iter::once((id.clone(), recording.file_path))
+                .chain(recording.secondary_file_path.map(|path| (id, path)))
+        })
+        .collect();
+    let mut summary = EncryptSummary {
+        total_files: files.len(),
+        ..Default::default()
+    };
+
+    // A failing file is recorded and the migration moves on to the next one
+    for (index, (recording_id, file_path)) in files.iter().enumerate() {
+        let path = PathBuf::from(file_path);
+        if audio::is_recording_active(recording_id) {
+            summary.skipped_active.push(file_path.clone());
+        } else if !path.is_file() {
+            summary.missing_files.push(file_path.clone());
+        } else {
+            let key = key.clone();
+            let result = tokio::task::spawn_blocking(move || vault_crypto::encrypt_in_place(&path, &key))
+                .await
+                .map_err(|e| format!("Encryption task failed: {}", e))
+                .and_then(|result| result.map_err(|e| e.to_string()));
+            match result {
+                Ok(true) => summary.encrypted += 1,
+                Ok(false) => summary.already_encrypted += 1,
+                Err(e) => {
+                    eprintln!("[Vault] Failed to encrypt {}: {}", file_path, e);
+                    summary.failures.push(EncryptFailure {
+                        file_path: file_path.clone(),
+                        error: e,
+                    });
+                }
+            }
+        }
+
+        let event = EncryptProgressEvent {
+            current: index + 1,
+            total: files.len(),
+            file_path: file_path.clone(),
+        };
+        if let Err(e) = app_handle.emit(EVENT_ENCRYPT_PROGRESS, event) {
+            eprintln!("[Vault] Failed to emit progress event: {}", e);
+        }
+    }
+
+    println!(
+        "[Vault] Encrypted {} of {} recording files ({} already encrypted, {} failed)",
+        summary.encrypted,
+        summary.total_files,
+        summary.already_encrypted,
+        summary.failures.len()
+    );
+    Ok(summary)
+}
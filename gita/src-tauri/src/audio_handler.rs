@@ -1,8 +1,9 @@
 use chrono::{DateTime, Utc};
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
 use uuid::Uuid;
 
 // Import the shared DalError
+use crate::block_handler::{self, Block};
 use crate::dal_error::DalError;
 
 #[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
@@ -10,9 +11,13 @@ pub struct AudioRecording {
     pub id: Uuid,
     pub page_id: Option<Uuid>, // Can be NULL if audio is not associated with a page
     pub file_path: String,
+    pub secondary_file_path: Option<String>, // System audio of a split recording
+    pub track_mode: String,                  // mixed, split or mono_mixed
     pub mime_type: Option<String>,
     pub duration_ms: Option<i32>,
     pub created_at: DateTime<Utc>,
+    pub title: Option<String>, // Shown in place of the file name when set
+    pub description: Option<String>,
     // updated_at is not in the audio_recordings table schema provided
 }
 
@@ -26,28 +31,44 @@ pub struct AudioTimestamp {
     // updated_at is not in the audio_timestamps table schema
 }
 
+#[allow(clippy::too_many_arguments)] // One argument per column of the row
 pub async fn create_audio_recording(
     pool: &PgPool,
     id: Uuid, // <<<< ADDED ID PARAMETER
     page_id: Option<Uuid>,
     file_path: &str,
+    secondary_file_path: Option<&str>,
+    track_mode: &str,
     mime_type: Option<&str>,
     duration_ms: Option<i32>,
+    title: Option<&str>,
 ) -> Result<Uuid, DalError> { // Still returns Uuid (the one passed in)
     // LET new_id = Uuid::new_v4(); // <<<< REMOVED
     sqlx::query!(
         r#"
-        INSERT INTO audio_recordings (id, page_id, file_path, mime_type, duration_ms, created_at)
-        VALUES ($1, $2, $3, $4, $5, now())
-        -- No RETURNING id needed if we assume the passed id is used,
-        -- but to confirm insertion or for consistency:
+        INSERT INTO audio_recordings
+            (id, page_id, file_path, secondary_file_path, track_mode, mime_type, duration_ms, created_at, title)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, now(), $8)
+        -- A placeholder row may already exist if blocks were stamped while recording. A title
+        -- given to it while recording is kept.
+        ON CONFLICT (id) DO UPDATE
+        SET page_id = EXCLUDED.page_id,
+            file_path = EXCLUDED.file_path,
+            secondary_file_path = EXCLUDED.secondary_file_path,
+            track_mode = EXCLUDED.track_mode,
+            mime_type = EXCLUDED.mime_type,
+            duration_ms = EXCLUDED.duration_ms,
+            title = COALESCE(audio_recordings.title, EXCLUDED.title)
         RETURNING id
         "#,
         id, // <<<< USE PROVIDED ID
         page_id,
         file_path,
+        secondary_file_path,
+        track_mode,
         mime_type,
-        duration_ms
+        duration_ms,
+        title
     )
     .fetch_one(pool) // fetch_one to ensure it was inserted and to get the ID back (even if it's the same)
     .await?;
@@ -55,11 +76,13 @@ pub async fn create_audio_recording(
     Ok(id) // Return the ID that was passed in and inserted
 }
 
-pub async fn get_audio_recording(pool: &PgPool, id: Uuid) -> Result<Option<AudioRecording>, DalError> {
+// Returns DalError::NotFound if there is no recording with this ID
+pub async fn get_audio_recording(pool: &PgPool, id: Uuid) -> Result<AudioRecording, DalError> {
     let recording = sqlx::query_as!(
         AudioRecording,
         r#"
-        SELECT id, page_id, file_path, mime_type, duration_ms, created_at
+        SELECT id, page_id, file_path, secondary_file_path, track_mode, mime_type, duration_ms, created_at,
+               title, description
         FROM audio_recordings
         WHERE id = $1
         "#,
@@ -68,7 +91,7 @@ pub async fn get_audio_recording(pool: &PgPool, id: Uuid) -> Result<Option<Audio
     .fetch_optional(pool)
     .await?;
 
-    Ok(recording)
+    recording.ok_or(DalError::NotFound)
 }
 
 pub async fn get_audio_recordings_for_page(
@@ -78,7 +101,8 @@ pub async fn get_audio_recordings_for_page(
     let recordings = sqlx::query_as!(
         AudioRecording,
         r#"
-        SELECT id, page_id, file_path, mime_type, duration_ms, created_at
+        SELECT id, page_id, file_path, secondary_file_path, track_mode, mime_type, duration_ms, created_at,
+               title, description
         FROM audio_recordings
         WHERE page_id = $1
         ORDER BY created_at DESC
@@ -91,63 +115,689 @@ pub async fn get_audio_recordings_for_page(
     Ok(recordings)
 }
 
+// Every recording, oldest first
+pub async fn get_all_audio_recordings(pool: &PgPool) -> Result<Vec<AudioRecording>, DalError> {
+    let recordings = sqlx::query_as!(
+        AudioRecording,
+        r#"
+        SELECT id, page_id, file_path, secondary_file_path, track_mode, mime_type, duration_ms, created_at,
+               title, description
+        FROM audio_recordings
+        ORDER BY created_at
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(recordings)
+}
+
+// Recordings not attached to any page, newest first like get_audio_recordings_for_page
+pub async fn get_unassigned_audio_recordings(pool: &PgPool) -> Result<Vec<AudioRecording>, DalError> {
+    let recordings = sqlx::query_as!(
+        AudioRecording,
+        r#"
+        SELECT id, page_id, file_path, secondary_file_path, track_mode, mime_type, duration_ms, created_at,
+               title, description
+        FROM audio_recordings
+        WHERE page_id IS NULL
+        ORDER BY created_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(recordings)
+}
+
+// Attaches a recording to a page, or detaches it when page_id is None. Its timestamps point at
+// blocks, not pages, so they're left as they are. Returns None if no recording with this ID exists.
+pub async fn update_audio_recording_page(
+    pool: &PgPool,
+    recording_id: Uuid,
+    page_id: Option<Uuid>,
+) -> Result<Option<AudioRecording>, DalError> {
+    let recording = sqlx::query_as!(
+        AudioRecording,
+        r#"
+        UPDATE audio_recordings
+        SET page_id = $2
+        WHERE id = $1
+        RETURNING id, page_id, file_path, secondary_file_path, track_mode, mime_type, duration_ms, created_at,
+                  title, description
+        "#,
+        recording_id,
+        page_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(recording)
+}
+
+// Sets a recording's title and description. A field left as None is kept; Some(None) clears it.
+// Returns None if no recording with this ID exists.
+pub async fn update_audio_recording_metadata(
+    pool: &PgPool,
+    recording_id: Uuid,
+    title: Option<Option<&str>>,
+    description: Option<Option<&str>>,
+) -> Result<Option<AudioRecording>, DalError> {
+    let recording = sqlx::query_as!(
+        AudioRecording,
+        r#"
+        UPDATE audio_recordings
+        SET title = CASE WHEN $2 THEN $3 ELSE title END,
+            description = CASE WHEN $4 THEN $5 ELSE description END
+        WHERE id = $1
+        RETURNING id, page_id, file_path, secondary_file_path, track_mode, mime_type, duration_ms, created_at,
+                  title, description
+        "#,
+        recording_id,
+        title.is_some(),
+        title.flatten(),
+        description.is_some(),
+        description.flatten()
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(recording)
+}
+
+// Narrows list_all_recordings. Filters combine; the date range includes both ends.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct RecordingFilter {
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+    pub has_transcript: Option<bool>,
+    pub has_timestamps: Option<bool>, // Whether any block points into the recording
+}
+
+// A recording in the vault-wide list, with the title of the page it belongs to
+#[derive(Debug)]
+pub struct RecordingListing {
+    pub recording: AudioRecording,
+    pub page_title: Option<String>,
+    pub has_transcript: bool,
+    pub timestamp_count: i64,
+}
+
+// Recordings of every page, and those attached to none, newest first
+pub async fn list_all_recordings(
+    pool: &PgPool,
+    filter: &RecordingFilter,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<RecordingListing>, DalError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT r.id, r.page_id, r.file_path, r.secondary_file_path, r.track_mode, r.mime_type, r.duration_ms,
+               r.created_at, r.title, r.description, p.title AS "page_title?",
+               EXISTS (SELECT 1 FROM transcripts t WHERE t.recording_id = r.id) AS "has_transcript!",
+               (SELECT COUNT(*) FROM audio_timestamps a WHERE a.audio_recording_id = r.id) AS "timestamp_count!"
+        FROM audio_recordings r
+        LEFT JOIN pages p ON p.id = r.page_id
+        WHERE ($1::timestamptz IS NULL OR r.created_at >= $1)
+          AND ($2::timestamptz IS NULL OR r.created_at <= $2)
+          AND ($3::bool IS NULL OR EXISTS (SELECT 1 FROM transcripts t WHERE t.recording_id = r.id) = $3)
+          AND ($4::bool IS NULL OR EXISTS (SELECT 1 FROM audio_timestamps a WHERE a.audio_recording_id = r.id) = $4)
+        ORDER BY r.created_at DESC, r.id DESC
+        LIMIT $5 OFFSET $6
+        "#,
+        filter.created_from,
+        filter.created_to,
+        filter.has_transcript,
+        filter.has_timestamps,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| RecordingListing {
+            recording: AudioRecording {
+                id: row.id,
+                page_id: row.page_id,
+                file_path: row.file_path,
+                secondary_file_path: row.secondary_file_path,
+                track_mode: row.track_mode,
+                mime_type: row.mime_type,
+                duration_ms: row.duration_ms,
+                created_at: row.created_at,
+                title: row.title,
+                description: row.description,
+            },
+            page_title: row.page_title,
+            has_transcript: row.has_transcript,
+            timestamp_count: row.timestamp_count,
+        })
+        .collect())
+}
+
 // Still to implement:
 // delete_audio_recording
 // add_audio_timestamp_to_block
 // get_audio_timestamps_for_block
 // get_audio_timestamps_for_recording
 
-pub async fn delete_audio_recording(pool: &PgPool, id: Uuid) -> Result<bool, DalError> {
+// Returns the deleted recording, or None if there was no recording with this ID
+pub async fn delete_audio_recording(pool: &PgPool, id: Uuid) -> Result<Option<AudioRecording>, DalError> {
     // Note: Deleting an audio recording will also delete associated audio_timestamps
     // due to ON DELETE CASCADE in the audio_timestamps table schema.
-    let result = sqlx::query!(
+    let recording = sqlx::query_as!(
+        AudioRecording,
         r#"
         DELETE FROM audio_recordings
         WHERE id = $1
+        RETURNING id, page_id, file_path, secondary_file_path, track_mode, mime_type, duration_ms, created_at,
+                  title, description
         "#,
         id
     )
-    .execute(pool)
+    .fetch_optional(pool)
     .await?;
 
-    Ok(result.rows_affected() > 0)
+    Ok(recording)
 }
 
-pub async fn add_audio_timestamp_to_block(
-    pool: &PgPool,
+// Points a recording at its files' new locations after they were moved. A path left as None
+// is kept. Returns None if no recording with this ID exists.
+pub async fn update_audio_recording_file_paths<'e>(
+    executor: impl PgExecutor<'e>,
+    id: Uuid,
+    file_path: Option<&str>,
+    secondary_file_path: Option<&str>,
+) -> Result<Option<AudioRecording>, DalError> {
+    let recording = sqlx::query_as!(
+        AudioRecording,
+        r#"
+        UPDATE audio_recordings
+        SET file_path = COALESCE($2, file_path),
+            secondary_file_path = COALESCE($3, secondary_file_path)
+        WHERE id = $1
+        RETURNING id, page_id, file_path, secondary_file_path, track_mode, mime_type, duration_ms, created_at,
+                  title, description
+        "#,
+        id,
+        file_path,
+        secondary_file_path
+    )
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(recording)
+}
+
+pub async fn add_audio_timestamp_to_block<'e>(
+    executor: impl PgExecutor<'e>,
     audio_recording_id: Uuid,
     block_id: Uuid,
     timestamp_ms: i32,
-) -> Result<Uuid, DalError> {
+) -> Result<AudioTimestamp, DalError> {
     let new_id = Uuid::new_v4();
-    let query_result = sqlx::query!(
+    let timestamp = sqlx::query_as!(
+        AudioTimestamp,
         r#"
         INSERT INTO audio_timestamps (id, audio_recording_id, block_id, timestamp_ms, created_at)
         VALUES ($1, $2, $3, $4, now())
-        RETURNING id
+        RETURNING id, audio_recording_id, block_id, timestamp_ms, created_at
         "#,
         new_id,
         audio_recording_id,
         block_id,
         timestamp_ms
     )
-    .fetch_one(pool)
+    .fetch_one(executor)
     .await?;
 
-    Ok(query_result.id)
+    Ok(timestamp)
 }
 
-pub async fn get_audio_timestamps_for_block(
+// Creates the recording row for a recording that is still running, so timestamps can point at
+// it. stop_recording later fills in the duration. Does nothing if the row already exists.
+pub async fn ensure_audio_recording<'e>(
+    executor: impl PgExecutor<'e>,
+    id: Uuid,
+    page_id: Option<Uuid>,
+    file_path: &str,
+    secondary_file_path: Option<&str>,
+    track_mode: &str,
+    mime_type: Option<&str>,
+) -> Result<(), DalError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audio_recordings (id, page_id, file_path, secondary_file_path, track_mode, mime_type, duration_ms, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, NULL, now())
+        ON CONFLICT (id) DO NOTHING
+        "#,
+        id,
+        page_id,
+        file_path,
+        secondary_file_path,
+        track_mode,
+        mime_type
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+// Of the given recording IDs, those with no row or only the placeholder row written while
+// recording, i.e. recordings that were never stopped
+pub async fn find_unfinished_recording_ids(pool: &PgPool, ids: &[Uuid]) -> Result<Vec<Uuid>, DalError> {
+    let unfinished = sqlx::query_scalar!(
+        r#"
+        SELECT ids.id AS "id!"
+        FROM unnest($1::uuid[]) AS ids(id)
+        WHERE NOT EXISTS (
+            SELECT 1 FROM audio_recordings r WHERE r.id = ids.id AND r.duration_ms IS NOT NULL
+        )
+        "#,
+        ids
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(unfinished)
+}
+
+// Saves a recording salvaged from disk after a crash. A placeholder row keeps its page; the
+// page and start time left by shutdown are used when known (a page deleted since is dropped).
+#[allow(clippy::too_many_arguments)] // One argument per column of the row
+pub async fn save_recovered_recording(
     pool: &PgPool,
-    block_id: Uuid,
-) -> Result<Vec<AudioTimestamp>, DalError> {
-    let timestamps = sqlx::query_as!(
+    id: Uuid,
+    page_id: Option<Uuid>,
+    file_path: &str,
+    secondary_file_path: Option<&str>,
+    track_mode: &str,
+    mime_type: &str,
+    duration_ms: i32,
+    created_at: Option<DateTime<Utc>>,
+    title: Option<&str>,
+) -> Result<(), DalError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audio_recordings
+            (id, page_id, file_path, secondary_file_path, track_mode, mime_type, duration_ms, created_at, title)
+        VALUES ($1, (SELECT p.id FROM pages p WHERE p.id = $2), $3, $4, $5, $6, $7, COALESCE($8, now()), $9)
+        ON CONFLICT (id) DO UPDATE
+        SET page_id = COALESCE(audio_recordings.page_id, EXCLUDED.page_id),
+            file_path = EXCLUDED.file_path,
+            secondary_file_path = EXCLUDED.secondary_file_path,
+            track_mode = EXCLUDED.track_mode,
+            mime_type = EXCLUDED.mime_type,
+            duration_ms = EXCLUDED.duration_ms,
+            title = COALESCE(audio_recordings.title, EXCLUDED.title)
+        "#,
+        id,
+        page_id,
+        file_path,
+        secondary_file_path,
+        track_mode,
+        mime_type,
+        duration_ms,
+        created_at,
+        title
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// The recording a new block should be stamped against, and the position to stamp
+pub struct RecordingPosition<'a> {
+    pub recording_id: Uuid,
+    pub page_id: Uuid,
+    pub file_path: &'a str,
+    pub secondary_file_path: Option<&'a str>,
+    pub track_mode: &'a str,
+    pub mime_type: &'a str,
+    pub timestamp_ms: i32,
+}
+
+// Appends a new block to the end of its siblings and, when a recording is given, links it to
+// the recording position in the same transaction.
+pub async fn create_block_with_timestamp(
+    pool: &PgPool,
+    page_id: Uuid,
+    parent_block_id: Option<Uuid>,
+    block_type: Option<&str>,
+    recording: Option<RecordingPosition<'_>>,
+) -> Result<(Block, Option<AudioTimestamp>), DalError> {
+    let mut tx = pool.begin().await?;
+
+    if let Some(parent_id) = parent_block_id {
+        let parent_page_id = block_handler::get_page_id_for_block(&mut *tx, parent_id)
+            .await?
+            .ok_or(DalError::NotFound)?;
+        if parent_page_id != page_id {
+            return Err(DalError::Conflict("Parent block belongs to another page".to_string()));
+        }
+    }
+
+    let block_id = Uuid::new_v4();
+    let order_index = block_handler::next_order_index(&mut *tx, page_id, parent_block_id).await?;
+    block_handler::create_block(&mut *tx, block_id, page_id, parent_block_id, block_type, order_index, None).await?;
+    let block = block_handler::get_block(&mut *tx, block_id)
+        .await?
+        .ok_or(DalError::NotFound)?;
+
+    let timestamp = match recording {
+        Some(position) => {
+            ensure_audio_recording(
+                &mut *tx,
+                position.recording_id,
+                Some(position.page_id),
+                position.file_path,
+                position.secondary_file_path,
+                position.track_mode,
+                Some(position.mime_type),
+            )
+            .await?;
+            Some(add_audio_timestamp_to_block(&mut *tx, position.recording_id, block_id, position.timestamp_ms).await?)
+        }
+        None => None,
+    };
+
+    tx.commit().await?;
+    Ok((block, timestamp))
+}
+
+pub async fn get_audio_timestamp(pool: &PgPool, id: Uuid) -> Result<Option<AudioTimestamp>, DalError> {
+    let timestamp = sqlx::query_as!(
         AudioTimestamp,
         r#"
         SELECT id, audio_recording_id, block_id, timestamp_ms, created_at
         FROM audio_timestamps
-        WHERE block_id = $1
-        ORDER BY timestamp_ms ASC
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(timestamp)
+}
+
+// Moves an existing timestamp to a new position in its recording.
+// Returns None if no timestamp with this ID exists.
+pub async fn update_audio_timestamp(
+    pool: &PgPool,
+    id: Uuid,
+    timestamp_ms: i32,
+) -> Result<Option<AudioTimestamp>, DalError> {
+    let timestamp = sqlx::query_as!(
+        AudioTimestamp,
+        r#"
+        UPDATE audio_timestamps
+        SET timestamp_ms = $2
+        WHERE id = $1
+        RETURNING id, audio_recording_id, block_id, timestamp_ms, created_at
+        "#,
+        id,
+        timestamp_ms
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(timestamp)
+}
+
+pub async fn delete_audio_timestamp(pool: &PgPool, id: Uuid) -> Result<bool, DalError> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM audio_timestamps
+        WHERE id = $1
+        "#,
+        id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// One timestamp of a bulk import: a block and its position in the recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimestampEntry {
+    pub block_id: Uuid,
+    pub timestamp_ms: i32,
+}
+
+// What add_audio_timestamps_bulk did with an entry
+#[derive(Debug)]
+pub enum TimestampEntryOutcome {
+    Added(AudioTimestamp),
+    // The block already has a timestamp at this position, stored or earlier in the same import
+    Duplicate,
+    Rejected(String),
+}
+
+// Adds the entries' timestamps to a recording with one insert, checking each entry on its own:
+// its block has to exist and its position has to lie within the recording. Entries for a block
+// at a position it already has are skipped as duplicates; a block can have several positions.
+// Returns one outcome per entry, in the order given, or NotFound if there is no such recording.
+pub async fn add_audio_timestamps_bulk(
+    pool: &PgPool,
+    audio_recording_id: Uuid,
+    entries: &[TimestampEntry],
+) -> Result<Vec<TimestampEntryOutcome>, DalError> {
+    let mut tx = pool.begin().await?;
+    let duration_ms = sqlx::query_scalar!(
+        r#"
+        SELECT duration_ms
+        FROM audio_recordings
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        audio_recording_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(DalError::NotFound)?;
+
+    let block_ids: Vec<Uuid> = entries.iter().map(|entry| entry.block_id).collect();
+    let existing_blocks: std::collections::HashSet<Uuid> =
+        sqlx::query_scalar!("SELECT id FROM blocks WHERE id = ANY($1)", &block_ids)
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .collect();
+    let mut taken: std::collections::HashSet<TimestampEntry> = sqlx::query!(
+        r#"
+        SELECT block_id, timestamp_ms
+        FROM audio_timestamps
+        WHERE audio_recording_id = $1 AND block_id = ANY($2)
+        "#,
+        audio_recording_id,
+        &block_ids
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|row| TimestampEntry {
+        block_id: row.block_id,
+        timestamp_ms: row.timestamp_ms,
+    })
+    .collect();
+
+    // Each accepted entry gets its row's ID up front, to find the row among those inserted
+    let mut new_ids: Vec<Option<Uuid>> = Vec::with_capacity(entries.len());
+    let mut outcomes: Vec<Option<TimestampEntryOutcome>> = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let rejection = match duration_ms {
+            _ if entry.timestamp_ms < 0 => Some("timestamp_ms cannot be negative".to_string()),
+            Some(duration_ms) if entry.timestamp_ms > duration_ms => {
+                Some(format!("timestamp_ms is past the end of the recording ({} ms)", duration_ms))
+            }
+            _ if !existing_blocks.contains(&entry.block_id) => {
+                Some(format!("Block with ID {} not found", entry.block_id))
+            }
+            _ => None,
+        };
+        let outcome = match rejection {
+            Some(reason) => Some(TimestampEntryOutcome::Rejected(reason)),
+            None if !taken.insert(*entry) => Some(TimestampEntryOutcome::Duplicate),
+            None => None,
+        };
+        new_ids.push(outcome.is_none().then(Uuid::new_v4));
+        outcomes.push(outcome);
+    }
+
+    let (ids, accepted): (Vec<Uuid>, Vec<&TimestampEntry>) =
+        new_ids.iter().zip(entries).filter_map(|(id, entry)| Some(((*id)?, entry))).unzip();
+    let accepted_block_ids: Vec<Uuid> = accepted.iter().map(|entry| entry.block_id).collect();
+    let accepted_positions: Vec<i32> = accepted.iter().map(|entry| entry.timestamp_ms).collect();
+    let mut inserted: std::collections::HashMap<Uuid, AudioTimestamp> = sqlx::query_as!(
+        AudioTimestamp,
+        r#"
+        INSERT INTO audio_timestamps (id, audio_recording_id, block_id, timestamp_ms, created_at)
+        SELECT t.id, $1, t.block_id, t.timestamp_ms, now()
+        FROM unnest($2::uuid[], $3::uuid[], $4::int4[]) AS t(id, block_id, timestamp_ms)
+        RETURNING id, audio_recording_id, block_id, timestamp_ms, created_at
+        "#,
+        audio_recording_id,
+        &ids,
+        &accepted_block_ids,
+        &accepted_positions
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|timestamp| (timestamp.id, timestamp))
+    .collect();
+
+    let outcomes = outcomes
+        .into_iter()
+        .zip(new_ids)
+        .map(|(outcome, id)| match outcome {
+            Some(outcome) => Ok(outcome),
+            None => id
+                .and_then(|id| inserted.remove(&id))
+                .map(TimestampEntryOutcome::Added)
+                .ok_or_else(|| DalError::Internal("An inserted audio timestamp was not returned".to_string())),
+        })
+        .collect::<Result<Vec<_>, DalError>>()?;
+    tx.commit().await?;
+    Ok(outcomes)
+}
+
+// Outcomes for entries some of which were rejected before add_audio_timestamps_bulk (e.g. a
+// block ID that didn't parse): each rejected entry gets its reason, and the others, in turn,
+// the outcomes add_audio_timestamps_bulk returned for them. Keeps the order of the entries.
+pub fn merge_timestamp_outcomes(
+    checked: Vec<Result<TimestampEntry, String>>,
+    outcomes: Vec<TimestampEntryOutcome>,
+) -> Result<Vec<TimestampEntryOutcome>, DalError> {
+    let mut outcomes = outcomes.into_iter();
+    let merged = checked
+        .into_iter()
+        .map(|entry| match entry {
+            Ok(_) => outcomes
+                .next()
+                .ok_or_else(|| DalError::Internal("Missing result for a timestamp entry".to_string())),
+            Err(reason) => Ok(TimestampEntryOutcome::Rejected(reason)),
+        })
+        .collect::<Result<Vec<_>, DalError>>()?;
+    if outcomes.next().is_some() {
+        return Err(DalError::Internal("More results than timestamp entries".to_string()));
+    }
+    Ok(merged)
+}
+
+// Moves every timestamp of a recording at or after after_ms by delta_ms, e.g. by minus the
+// length of silence trimmed from its start. Positions that would end up before the start are
+// set to 0 with clamp_negative, and otherwise fail the whole shift with DalError::Conflict.
+// Returns the moved timestamps ordered by position, or NotFound if there is no such recording.
+pub async fn shift_audio_timestamps(
+    pool: &PgPool,
+    audio_recording_id: Uuid,
+    delta_ms: i32,
+    after_ms: i32,
+    clamp_negative: bool,
+) -> Result<Vec<AudioTimestamp>, DalError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query_scalar!("SELECT id FROM audio_recordings WHERE id = $1 FOR UPDATE", audio_recording_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(DalError::NotFound)?;
+
+    if !clamp_negative {
+        let negative = sqlx::query_scalar!(
+            r#"
+            SELECT count(*) AS "count!"
+            FROM audio_timestamps
+            WHERE audio_recording_id = $1 AND timestamp_ms >= $3 AND timestamp_ms::bigint + $2 < 0
+            "#,
+            audio_recording_id,
+            delta_ms as i64,
+            after_ms
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if negative > 0 {
+            return Err(DalError::Conflict(format!(
+                "{} timestamps would move before the start of the recording",
+                negative
+            )));
+        }
+    }
+
+    // Summed as bigint, so a large delta saturates instead of overflowing
+    let mut timestamps = sqlx::query_as!(
+        AudioTimestamp,
+        r#"
+        UPDATE audio_timestamps
+        SET timestamp_ms = LEAST(GREATEST(timestamp_ms::bigint + $2, 0), 2147483647)::int4
+        WHERE audio_recording_id = $1 AND timestamp_ms >= $3
+        RETURNING id, audio_recording_id, block_id, timestamp_ms, created_at
+        "#,
+        audio_recording_id,
+        delta_ms as i64,
+        after_ms
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    timestamps.sort_by_key(|timestamp| (timestamp.timestamp_ms, timestamp.id));
+    Ok(timestamps)
+}
+
+// A timestamp together with the recording it points into, for showing audio badges on blocks
+#[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct BlockAudioTimestamp {
+    pub id: Uuid,
+    pub audio_recording_id: Uuid,
+    pub block_id: Uuid,
+    pub timestamp_ms: i32,
+    pub created_at: DateTime<Utc>,
+    pub file_path: String,
+    pub mime_type: Option<String>,
+    pub duration_ms: Option<i32>,
+    pub recording_created_at: DateTime<Utc>,
+}
+
+pub async fn get_audio_timestamps_for_block(
+    pool: &PgPool,
+    block_id: Uuid,
+) -> Result<Vec<BlockAudioTimestamp>, DalError> {
+    let timestamps = sqlx::query_as!(
+        BlockAudioTimestamp,
+        r#"
+        SELECT t.id, t.audio_recording_id, t.block_id, t.timestamp_ms, t.created_at,
+               r.file_path, r.mime_type, r.duration_ms, r.created_at AS recording_created_at
+        FROM audio_timestamps t
+        JOIN audio_recordings r ON r.id = t.audio_recording_id
+        WHERE t.block_id = $1
+        ORDER BY t.timestamp_ms ASC
         "#,
         block_id
     )
@@ -157,6 +807,30 @@ pub async fn get_audio_timestamps_for_block(
     Ok(timestamps)
 }
 
+// Timestamps for every block of a page, ordered by block and then position
+pub async fn get_audio_timestamps_for_page(
+    pool: &PgPool,
+    page_id: Uuid,
+) -> Result<Vec<BlockAudioTimestamp>, DalError> {
+    let timestamps = sqlx::query_as!(
+        BlockAudioTimestamp,
+        r#"
+        SELECT t.id, t.audio_recording_id, t.block_id, t.timestamp_ms, t.created_at,
+               r.file_path, r.mime_type, r.duration_ms, r.created_at AS recording_created_at
+        FROM audio_timestamps t
+        JOIN audio_recordings r ON r.id = t.audio_recording_id
+        JOIN blocks b ON b.id = t.block_id
+        WHERE b.page_id = $1
+        ORDER BY t.block_id, t.timestamp_ms ASC
+        "#,
+        page_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(timestamps)
+}
+
 pub async fn get_audio_timestamps_for_recording(
     pool: &PgPool,
     audio_recording_id: Uuid,
@@ -176,3 +850,148 @@ pub async fn get_audio_timestamps_for_recording(
 
     Ok(timestamps)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page_handler;
+
+    // A page with blocks, and a 10 s recording on it
+    async fn setup(pool: &PgPool, blocks: usize) -> (Uuid, Vec<Uuid>) {
+        let page_id = page_handler::create_page(pool, "Meeting", serde_json::json!({}), None).await.unwrap();
+        let mut block_ids = Vec::new();
+        for index in 0..blocks {
+            let block_id = Uuid::new_v4();
+            block_handler::create_block(pool, block_id, page_id, None, Some("paragraph"), index as i32, None)
+                .await
+                .unwrap();
+            block_ids.push(block_id);
+        }
+        let recording_id = Uuid::new_v4();
+        let file_path = "meeting.wav";
+        create_audio_recording(pool, recording_id, Some(page_id), file_path, None, "mixed", None, Some(10_000), None)
+            .await
+            .unwrap();
+        (recording_id, block_ids)
+    }
+
+    fn entry(block_id: Uuid, timestamp_ms: i32) -> TimestampEntry {
+        TimestampEntry { block_id, timestamp_ms }
+    }
+
+    // Each outcome as the position added, "duplicate" or "rejected"
+    fn summary(outcomes: &[TimestampEntryOutcome]) -> Vec<String> {
+        outcomes
+            .iter()
+            .map(|outcome| match outcome {
+                TimestampEntryOutcome::Added(timestamp) => timestamp.timestamp_ms.to_string(),
+                TimestampEntryOutcome::Duplicate => "duplicate".to_string(),
+                TimestampEntryOutcome::Rejected(_) => "rejected".to_string(),
+            })
+            .collect()
+    }
+
+    async fn positions(pool: &PgPool, recording_id: Uuid) -> Vec<(Uuid, i32)> {
+        let mut positions: Vec<(Uuid, i32)> = get_audio_timestamps_for_recording(pool, recording_id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|timestamp| (timestamp.block_id, timestamp.timestamp_ms))
+            .collect();
+        positions.sort_by_key(|&(block_id, timestamp_ms)| (timestamp_ms, block_id));
+        positions
+    }
+
+    #[sqlx::test(migrator = "crate::db::MIGRATOR")]
+    async fn bulk_timestamps_skip_duplicates_and_keep_entry_order(pool: PgPool) {
+        let (recording_id, blocks) = setup(&pool, 2).await;
+        let (a, b) = (blocks[0], blocks[1]);
+        let stored = add_audio_timestamps_bulk(&pool, recording_id, &[entry(b, 2000)]).await.unwrap();
+        assert_eq!(summary(&stored), vec!["2000"]);
+
+        let entries = [
+            entry(a, 1000),
+            entry(a, 1000),           // Repeats the entry before
+            entry(b, 2000),           // Already stored
+            entry(Uuid::new_v4(), 0), // No such block
+            entry(a, -1),
+            entry(a, 10_001), // Past the end
+            entry(a, 2000),   // The same position as another block's
+            entry(b, 1000),
+        ];
+        let outcomes = add_audio_timestamps_bulk(&pool, recording_id, &entries).await.unwrap();
+        assert_eq!(
+            summary(&outcomes),
+            vec!["1000", "duplicate", "duplicate", "rejected", "rejected", "rejected", "2000", "1000"]
+        );
+        for (outcome, entry) in outcomes.iter().zip(&entries) {
+            if let TimestampEntryOutcome::Added(timestamp) = outcome {
+                assert_eq!((timestamp.block_id, timestamp.audio_recording_id), (entry.block_id, recording_id));
+            }
+        }
+        let mut expected = vec![(a, 1000), (b, 1000), (a, 2000), (b, 2000)];
+        expected.sort_by_key(|&(block_id, timestamp_ms)| (timestamp_ms, block_id));
+        assert_eq!(positions(&pool, recording_id).await, expected);
+
+        let missing = add_audio_timestamps_bulk(&pool, Uuid::new_v4(), &entries).await;
+        assert!(matches!(missing, Err(DalError::NotFound)));
+    }
+
+    #[test]
+    fn merged_timestamp_outcomes_follow_the_entries() {
+        let block_id = Uuid::new_v4();
+        let checked = vec![
+            Err("Invalid block ID".to_string()),
+            Ok(entry(block_id, 0)),
+            Err("Invalid block ID".to_string()),
+            Ok(entry(block_id, 0)),
+        ];
+        let outcomes = vec![
+            TimestampEntryOutcome::Rejected("past the end".to_string()),
+            TimestampEntryOutcome::Duplicate,
+        ];
+        let merged = merge_timestamp_outcomes(checked.clone(), outcomes).unwrap();
+        let reasons: Vec<Option<&str>> = merged
+            .iter()
+            .map(|outcome| match outcome {
+                TimestampEntryOutcome::Rejected(reason) => Some(reason.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(reasons, vec![Some("Invalid block ID"), Some("past the end"), Some("Invalid block ID"), None]);
+
+        assert!(merge_timestamp_outcomes(checked.clone(), vec![TimestampEntryOutcome::Duplicate]).is_err());
+        let too_many = (0..3).map(|_| TimestampEntryOutcome::Duplicate).collect();
+        assert!(merge_timestamp_outcomes(checked, too_many).is_err());
+    }
+
+    #[sqlx::test(migrator = "crate::db::MIGRATOR")]
+    async fn shifting_moves_overlapping_timestamps_together(pool: PgPool) {
+        let (recording_id, blocks) = setup(&pool, 2).await;
+        let (a, b) = (blocks[0], blocks[1]);
+        let entries = [entry(a, 100), entry(a, 500), entry(b, 500), entry(b, 900)];
+        add_audio_timestamps_bulk(&pool, recording_id, &entries).await.unwrap();
+
+        // Only positions at or after after_ms move, returned by position
+        let moved = shift_audio_timestamps(&pool, recording_id, -200, 500, false).await.unwrap();
+        let moved: Vec<i32> = moved.iter().map(|timestamp| timestamp.timestamp_ms).collect();
+        assert_eq!(moved, vec![300, 300, 700]);
+        let mut expected = vec![(a, 100), (a, 300), (b, 300), (b, 700)];
+        expected.sort_by_key(|&(block_id, timestamp_ms)| (timestamp_ms, block_id));
+        assert_eq!(positions(&pool, recording_id).await, expected);
+
+        // Moving any before the start is refused as a whole, unless clamped to 0
+        let refused = shift_audio_timestamps(&pool, recording_id, -400, 0, false).await;
+        assert!(matches!(refused, Err(DalError::Conflict(_))));
+        assert_eq!(positions(&pool, recording_id).await, expected);
+        let clamped = shift_audio_timestamps(&pool, recording_id, -400, 0, true).await.unwrap();
+        let clamped: Vec<i32> = clamped.iter().map(|timestamp| timestamp.timestamp_ms).collect();
+        assert_eq!(clamped, vec![0, 0, 0, 300]);
+
+        // Timestamps that now share a position are still separate entries, and a duplicate
+        // of one of them is recognised
+        let outcomes = add_audio_timestamps_bulk(&pool, recording_id, &[entry(a, 0), entry(b, 0)]).await.unwrap();
+        assert_eq!(summary(&outcomes), vec!["duplicate", "duplicate"]);
+        assert_eq!(positions(&pool, recording_id).await.len(), 4);
+    }
+}
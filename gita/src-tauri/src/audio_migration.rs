@@ -0,0 +1,272 @@
+// Moves every recording file the database knows about into a new audio directory and points
+// the rows at the new paths. Files are renamed when both directories are on the same file
+// system; otherwise they are copied, checked and only deleted once the rows are updated, so a
+// failed database update leaves every recording playable from where it was.
+
+use serde::Serialize;
+use sqlx::PgPool;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+use uuid::Uuid;
+
+use crate::audio_handler;
+use crate::dal_error::DalError;
+
+pub const EVENT_AUDIO_MIGRATION_PROGRESS: &str = "audio-migration://progress";
+
+// How often a progress event is emitted while a large file is being copied
+const COPY_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+const COPY_BUFFER_SIZE: usize = 1024 * 1024;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AudioMigrationProgressEvent {
+    pub current: usize, // 1-based index of the file being moved
+    pub total: usize,
+    pub path: String,
+    pub bytes_done: u64, // Across all files, including the current one so far
+    pub bytes_total: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MissingRecordingFile {
+    pub recording_id: Uuid,
+    pub file_path: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AudioMigrationFailure {
+    pub recording_id: Uuid,
+    pub file_path: String,
+    pub error: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct AudioMigrationSummary {
+    pub total_recordings: usize,
+    pub moved: usize, // Files, so a split recording's two tracks count twice
+    pub already_in_place: usize,
+    pub bytes_moved: u64,
+    pub missing_files: Vec<MissingRecordingFile>, // Rows left pointing where they did
+    pub failures: Vec<AudioMigrationFailure>,     // Files left where they were, rows unchanged
+}
+
+// A file now in the new directory whose row still has to be updated
+struct MovedFile {
+    recording_id: Uuid,
+    old_path: PathBuf,
+    new_path: PathBuf,
+    secondary: bool, // The system track of a split recording
+    copied: bool,    // The original is still at old_path
+}
+
+pub async fn migrate_audio_directory(
+    pool: &PgPool,
+    app_handle: &AppHandle,
+    new_dir: &Path,
+) -> Result<AudioMigrationSummary, String> {
+    let recordings = audio_handler::get_all_audio_recordings(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut summary = AudioMigrationSummary {
+        total_recordings: recordings.len(),
+        ..Default::default()
+    };
+
+    // Sizes up front, so progress can be reported in bytes
+    let mut to_move: Vec<(Uuid, bool, PathBuf, PathBuf, u64)> = Vec::new(); // (id, secondary, old path, new path, size)
+    for recording in recordings {
+        let files = std::iter::once((recording.file_path, false))
+            .chain(recording.secondary_file_path.map(|path| (path, true)));
+        for (file_path, secondary) in files {
+            let old_path = PathBuf::from(&file_path);
+            let metadata = std::fs::metadata(&old_path).ok().filter(|metadata| metadata.is_file());
+            match (metadata, old_path.file_name()) {
+                (Some(_), _) if old_path.parent() == Some(new_dir) => summary.already_in_place += 1,
+                (Some(metadata), Some(file_name)) => {
+                    let new_path = new_dir.join(file_name);
+                    to_move.push((recording.id, secondary, old_path, new_path, metadata.len()));
+                }
+                _ => summary.missing_files.push(MissingRecordingFile {
+                    recording_id: recording.id,
+                    file_path,
+                }),
+            }
+        }
+    }
+
+    let total = to_move.len();
+    let bytes_total: u64 = to_move.iter().map(|(_, _, _, _, len)| len).sum();
+    let mut bytes_done: u64 = 0;
+    let mut moved: Vec<MovedFile> = Vec::new();
+
+    for (index, (recording_id, secondary, old_path, new_path, len)) in to_move.into_iter().enumerate() {
+        let path = old_path.display().to_string();
+        let progress = AudioMigrationProgressEvent {
+            current: index + 1,
+            total,
+            path: path.clone(),
+            bytes_done,
+            bytes_total,
+        };
+
+        let handle = app_handle.clone();
+        let (source, target) = (old_path.clone(), new_path.clone());
+        let result = tokio::task::spawn_blocking(move || move_file(&handle, &source, &target, progress))
+            .await
+            .map_err(|e| format!("File move task failed: {}", e))
+            .and_then(|result| result.map_err(|e| e.to_string()));
+
+        bytes_done += len;
+        match result {
+            Ok(copied) => {
+                summary.bytes_moved += len;
+                moved.push(MovedFile {
+                    recording_id,
+                    old_path,
+                    new_path,
+                    secondary,
+                    copied,
+                });
+            }
+            Err(error) => {
+                eprintln!("[AudioMigration] Failed to move {}: {}", path, error);
+                summary.failures.push(AudioMigrationFailure {
+                    recording_id,
+                    file_path: path.clone(),
+                    error,
+                });
+            }
+        }
+        emit_progress(
+            app_handle,
+            AudioMigrationProgressEvent {
+                current: index + 1,
+                total,
+                path,
+                bytes_done,
+                bytes_total,
+            },
+        );
+    }
+
+    if let Err(e) = update_file_paths(pool, &moved).await {
+        for file in &moved {
+            if let Err(undo_error) = undo_move(file) {
+                eprintln!(
+                    "[AudioMigration] Failed to move {} back to {}: {}",
+                    file.new_path.display(),
+                    file.old_path.display(),
+                    undo_error
+                );
+            }
+        }
+        return Err(format!("Failed to update recording paths, files were moved back: {}", e));
+    }
+
+    for file in moved.iter().filter(|file| file.copied) {
+        if let Err(e) = std::fs::remove_file(&file.old_path) {
+            eprintln!("[AudioMigration] Failed to delete {} after copying it: {}", file.old_path.display(), e);
+        }
+    }
+    summary.moved = moved.len();
+    Ok(summary)
+}
+
+// All rows or none, so a failure can be undone by moving every file back
+async fn update_file_paths(pool: &PgPool, moved: &[MovedFile]) -> Result<(), DalError> {
+    let mut tx = pool.begin().await?;
+    for file in moved {
+        let new_path = file.new_path.to_string_lossy();
+        let (file_path, secondary_file_path) = if file.secondary {
+            (None, Some(new_path.as_ref()))
+        } else {
+            (Some(new_path.as_ref()), None)
+        };
+        audio_handler::update_audio_recording_file_paths(&mut *tx, file.recording_id, file_path, secondary_file_path)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+// Renames the file, or copies it when the rename fails (e.g. across drives). Returns whether it
+// was copied, in which case the original is still in place.
+fn move_file(
+    app_handle: &AppHandle,
+    old_path: &Path,
+    new_path: &Path,
+    progress: AudioMigrationProgressEvent,
+) -> io::Result<bool> {
+    if new_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", new_path.display()),
+        ));
+    }
+    emit_progress(app_handle, progress.clone());
+    if std::fs::rename(old_path, new_path).is_ok() {
+        return Ok(false);
+    }
+
+    let result = copy_with_progress(app_handle, old_path, new_path, progress);
+    if result.is_err() {
+        let _ = std::fs::remove_file(new_path); // Don't leave a partial copy behind
+    }
+    result.map(|()| true)
+}
+
+fn copy_with_progress(
+    app_handle: &AppHandle,
+    old_path: &Path,
+    new_path: &Path,
+    mut progress: AudioMigrationProgressEvent,
+) -> io::Result<()> {
+    let mut source = File::open(old_path)?;
+    let expected_len = source.metadata()?.len();
+    let mut target = File::create(new_path)?;
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+    let start_bytes = progress.bytes_done;
+    let mut copied: u64 = 0;
+    let mut last_emit = Instant::now();
+
+    loop {
+        let read = source.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        target.write_all(&buffer[..read])?;
+        copied += read as u64;
+        if last_emit.elapsed() >= COPY_PROGRESS_INTERVAL {
+            progress.bytes_done = start_bytes + copied;
+            emit_progress(app_handle, progress.clone());
+            last_emit = Instant::now();
+        }
+    }
+    target.sync_all()?;
+
+    let copied_len = std::fs::metadata(new_path)?.len();
+    if copied_len != expected_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Copy is {} bytes but the original is {} bytes", copied_len, expected_len),
+        ));
+    }
+    Ok(())
+}
+
+fn undo_move(file: &MovedFile) -> io::Result<()> {
+    if file.copied {
+        std::fs::remove_file(&file.new_path)
+    } else {
+        std::fs::rename(&file.new_path, &file.old_path)
+    }
+}
+
+fn emit_progress(app_handle: &AppHandle, event: AudioMigrationProgressEvent) {
+    if let Err(e) = app_handle.emit(EVENT_AUDIO_MIGRATION_PROGRESS, event) {
+        eprintln!("[AudioMigration] Failed to emit progress event: {}", e);
+    }
+}
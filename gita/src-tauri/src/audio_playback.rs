@@ -0,0 +1,212 @@
+// Lets the webview play recordings, which live in the app data directory it can't read. Short
+// ranges come back as a self-contained WAV encoded in base64 (enough for jumping to a block's
+// timestamp); whole files are served through Tauri's asset protocol. Ranges can also be saved
+// as standalone clips. Encrypted recordings are decrypted as they are read, so they need the
+// vault key; clips are always written unencrypted.
+
+use base64::Engine;
+use serde::Serialize;
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+
+use crate::audio_encoder::{AudioEncoder, AudioFormat};
+use crate::vault_crypto::{VaultFile, VaultKey};
+
+// Longest range returned by read_wav_range; longer playback should use the file URL
+pub const MAX_CLIP_MS: u64 = 10 * 60 * 1000;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AudioClip {
+    pub mime_type: &'static str,
+    pub start_ms: u64,         // The range actually returned, after clamping
+    pub end_ms: u64,
+    pub file_duration_ms: u64, // Read from the file, so known even when duration_ms isn't
+    pub data_base64: String,   // A complete WAV file
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ExportedClip {
+    pub file_path: String,
+    pub mime_type: &'static str,
+    pub start_ms: u64, // The range actually exported, after clamping
+    pub end_ms: u64,
+    pub duration_ms: u64,
+    pub size_bytes: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AudioFileUrl {
+    pub url: String, // Loadable by the webview once the file is in the asset protocol scope
+    pub file_path: String,
+    pub mime_type: Option<String>,
+}
+
+// A WAV recording opened for reading, positioned at the start of a clamped frame range
+struct WavRange {
+    reader: hound::WavReader<BufReader<VaultFile>>,
+    spec: hound::WavSpec,
+    start_frame: u64,
+    end_frame: u64, // Never before start_frame
+    total_frames: u64,
+    position: u64, // Next frame read_frames returns
+}
+
+impl WavRange {
+    // Both ends are clamped to the file; a missing start means the beginning and a missing end
+    // means the end of the file
+    fn open(path: &Path, start_ms: Option<u64>, end_ms: Option<u64>, key: Option<&VaultKey>) -> Result<Self, String> {
+        let is_wav = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case(AudioFormat::Wav.extension()));
+        if !is_wav {
+            return Err("Only WAV recordings can be read in ranges; play other formats through their file URL".to_string());
+        }
+
+        let file = VaultFile::open(path, key).map_err(|e| format!("Failed to open recording: {}", e))?;
+        let mut reader =
+            hound::WavReader::new(BufReader::new(file)).map_err(|e| format!("Failed to open recording: {}", e))?;
+        let spec = reader.spec();
+        if spec.bits_per_sample != 16 || spec.sample_format != hound::SampleFormat::Int {
+            return Err("Only 16-bit WAV recordings can be read in ranges".to_string());
+        }
+
+        let total_frames = reader.duration() as u64;
+        let ms_to_frame = |ms: u64| (ms.saturating_mul(spec.sample_rate as u64) / 1000).min(total_frames);
+        let start_frame = ms_to_frame(start_ms.unwrap_or(0));
+        let end_frame = ms_to_frame(end_ms.unwrap_or(u64::MAX)).max(start_frame);
+
+        reader
+            .seek(start_frame as u32)
+            .map_err(|e| format!("Failed to seek in recording: {}", e))?;
+        Ok(WavRange {
+            reader,
+            spec,
+            start_frame,
+            end_frame,
+            total_frames,
+            position: start_frame,
+        })
+    }
+
+    fn frame_to_ms(&self, frame: u64) -> u64 {
+        frame * 1000 / self.spec.sample_rate as u64
+    }
+
+    // Reads up to max_frames frames of the range as interleaved samples
+    fn read_frames(&mut self, max_frames: u64) -> Result<Vec<i16>, String> {
+        let frames = max_frames.min(self.end_frame.saturating_sub(self.position));
+        let samples: Vec<i16> = self
+            .reader
+            .samples::<i16>()
+            .take((frames * self.spec.channels as u64) as usize)
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("Failed to read recording: {}", e))?;
+        self.position += frames;
+        Ok(samples)
+    }
+}
+
+// Reads [start_ms, end_ms) of a WAV recording. Both ends are clamped to the file; a missing
+// start means the beginning and a missing end means MAX_CLIP_MS after the start.
+pub fn read_wav_range(
+    path: &Path,
+    start_ms: Option<u64>,
+    end_ms: Option<u64>,
+    key: Option<&VaultKey>,
+) -> Result<AudioClip, String> {
+    let mut range = WavRange::open(path, start_ms, end_ms, key)?;
+    let max_clip_frames = MAX_CLIP_MS * range.spec.sample_rate as u64 / 1000;
+    range.end_frame = range.end_frame.min(range.start_frame + max_clip_frames);
+    let samples = range.read_frames(range.end_frame - range.start_frame)?;
+
+    let mut wav_bytes: Vec<u8> = Vec::with_capacity(44 + samples.len() * 2);
+    {
+        let mut writer = hound::WavWriter::new(Cursor::new(&mut wav_bytes), range.spec)
+            .map_err(|e| format!("Failed to encode audio range: {}", e))?;
+        let mut sample_writer = writer.get_i16_writer(samples.len() as u32);
+        for sample in &samples {
+            sample_writer.write_sample(*sample);
+        }
+        sample_writer.flush().map_err(|e| format!("Failed to encode audio range: {}", e))?;
+        writer.finalize().map_err(|e| format!("Failed to encode audio range: {}", e))?;
+    }
+
+    Ok(AudioClip {
+        mime_type: AudioFormat::Wav.mime_type(),
+        start_ms: range.frame_to_ms(range.start_frame),
+        end_ms: range.frame_to_ms(range.end_frame),
+        file_duration_ms: range.frame_to_ms(range.total_frames),
+        data_base64: base64::engine::general_purpose::STANDARD.encode(&wav_bytes),
+    })
+}
+
+// Writes [start_ms, end_ms) of a WAV recording to dest as a standalone file in the given
+// format, a second at a time so long clips aren't held in memory. The range is clamped to the
+// file and must not be empty after clamping.
+pub fn export_wav_clip(
+    source: &Path,
+    start_ms: u64,
+    end_ms: u64,
+    dest: &Path,
+    format: AudioFormat,
+    key: Option<&VaultKey>,
+) -> Result<ExportedClip, String> {
+    let mut range = WavRange::open(source, Some(start_ms), Some(end_ms), key)?;
+    if range.end_frame <= range.start_frame {
+        return Err(format!(
+            "The range starts after the end of the recording ({}ms)",
+            range.frame_to_ms(range.total_frames)
+        ));
+    }
+
+    let mut encoder = AudioEncoder::create(dest, format, range.spec.channels, range.spec.sample_rate, None)?;
+    let chunk_frames = range.spec.sample_rate as u64;
+    let result = (|| {
+        loop {
+            let samples = range.read_frames(chunk_frames)?;
+            if samples.is_empty() {
+                break;
+            }
+            encoder.write_samples(&samples)?;
+        }
+        encoder.finalize()
+    })();
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(dest); // Don't leave a broken file behind
+        return Err(format!("Failed to write clip: {}", e));
+    }
+
+    let size_bytes = std::fs::metadata(dest)
+        .map_err(|e| format!("Failed to read clip file: {}", e))?
+        .len();
+    let start_ms = range.frame_to_ms(range.start_frame);
+    let end_ms = range.frame_to_ms(range.end_frame);
+    Ok(ExportedClip {
+        file_path: dest.to_string_lossy().to_string(),
+        mime_type: format.mime_type(),
+        start_ms,
+        end_ms,
+        duration_ms: end_ms - start_ms,
+        size_bytes,
+    })
+}
+
+// The URL the webview loads an asset protocol file from; the same as convertFileSrc in
+// @tauri-apps/api/core
+pub fn asset_url(path: &Path) -> String {
+    let encoded: String = path
+        .to_string_lossy()
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'!' | b'~' | b'*' | b'\'' | b'(' | b')' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect();
+    if cfg!(any(windows, target_os = "android")) {
+        format!("http://asset.localhost/{}", encoded)
+    } else {
+        format!("asset://localhost/{}", encoded)
+    }
+}
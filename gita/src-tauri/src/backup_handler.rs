@@ -0,0 +1,155 @@
+use serde_json::Value;
+use sqlx::types::Json;
+use sqlx::{PgConnection, PgPool, Postgres, Row, Transaction};
+
+// Import the shared DalError
+use crate::dal_error::DalError;
+
+// A table copied into backups. Rows are moved as JSON objects keyed by column name, so a backup
+// taken before a column was added still restores (the column gets its default).
+pub struct BackupTable {
+    pub name: &'static str,
+    pub key_columns: &'static [&'static str],
+    pub derived_columns: &'static [&'static str], // Maintained by triggers; left out of backups
+}
+
+// Every table a backup holds, parents before children so rows can be restored in this order.
+// note_sync_state isn't included: it describes files on this machine and is rebuilt by syncing.
+// Nor are unresolved_links, rebuilt when a page is saved, or page_revisions (history).
+pub const BACKUP_TABLES: &[BackupTable] = &[
+    BackupTable { name: "pages", key_columns: &["id"], derived_columns: &["title_search"] },
+    BackupTable { name: "tags", key_columns: &["id"], derived_columns: &[] },
+    BackupTable { name: "blocks", key_columns: &["id"], derived_columns: &[] },
+    BackupTable { name: "imported_uids", key_columns: &["source", "uid"], derived_columns: &[] },
+    BackupTable { name: "page_links", key_columns: &["source_page_id", "target_page_id"], derived_columns: &[] },
+    BackupTable { name: "page_link_blocks", key_columns: &["source_page_id", "target_page_id", "block_id"], derived_columns: &[] },
+    BackupTable { name: "block_references", key_columns: &["id"], derived_columns: &[] },
+    BackupTable { name: "page_tags", key_columns: &["page_id", "tag_id"], derived_columns: &[] },
+    BackupTable { name: "page_aliases", key_columns: &["page_id", "alias"], derived_columns: &[] },
+    BackupTable { name: "saved_queries", key_columns: &["id"], derived_columns: &[] },
+    BackupTable { name: "audio_recordings", key_columns: &["id"], derived_columns: &[] },
+    BackupTable { name: "audio_timestamps", key_columns: &["id"], derived_columns: &[] },
+    BackupTable { name: "transcripts", key_columns: &["recording_id"], derived_columns: &[] },
+];
+
+pub fn backup_table(name: &str) -> Option<&'static BackupTable> {
+    BACKUP_TABLES.iter().find(|table| table.name == name)
+}
+
+// Rows changed by one upsert batch
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UpsertCounts {
+    pub inserted: u64,
+    pub updated: u64,
+    pub unchanged: u64, // Already present with the same values
+}
+
+// A read-only transaction that sees the database as it was when it began, so every table in a
+// backup comes from the same moment
+pub async fn begin_snapshot(pool: &PgPool) -> Result<Transaction<'static, Postgres>, DalError> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+        .execute(&mut *tx)
+        .await?;
+    Ok(tx)
+}
+
+// Opens a cursor over all rows of a table as JSON objects, in key order. Read it with
+// fetch_cursor_rows and close it with close_cursor before opening the next one.
+pub async fn open_table_cursor(conn: &mut PgConnection, table: &BackupTable) -> Result<(), DalError> {
+    let sql = format!(
+        "DECLARE backup_rows NO SCROLL CURSOR FOR SELECT to_jsonb(t) - $1::text[] FROM {} t ORDER BY {}",
+        quote_ident(table.name),
+        table.key_columns.iter().map(|column| format!("t.{}", quote_ident(column))).collect::<Vec<_>>().join(", ")
+    );
+    let derived: Vec<String> = table.derived_columns.iter().map(|column| column.to_string()).collect();
+    sqlx::query(&sql).bind(derived).execute(conn).await?;
+    Ok(())
+}
+
+// The next batch of rows from the open cursor; empty once it is exhausted
+pub async fn fetch_cursor_rows(conn: &mut PgConnection, batch_size: usize) -> Result<Vec<Value>, DalError> {
+    let rows = sqlx::query_scalar::<_, Value>(&format!("FETCH FORWARD {} FROM backup_rows", batch_size))
+        .fetch_all(conn)
+        .await?;
+    Ok(rows)
+}
+
+pub async fn close_cursor(conn: &mut PgConnection) -> Result<(), DalError> {
+    sqlx::query("CLOSE backup_rows").execute(conn).await?;
+    Ok(())
+}
+
+// The table's current columns, used to check the column names found in a backup before they
+// are put into SQL
+pub async fn table_columns(pool: &PgPool, table: &BackupTable) -> Result<Vec<String>, DalError> {
+    let columns = sqlx::query_scalar::<_, String>(
+        "SELECT column_name::text FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = $1",
+    )
+    .bind(table.name)
+    .fetch_all(pool)
+    .await?;
+    Ok(columns)
+}
+
+// Removes everything a backup holds, children first. Deleting pages and recordings cascades to
+// note_sync_state as well.
+pub async fn delete_backup_tables(conn: &mut PgConnection) -> Result<(), DalError> {
+    for table in BACKUP_TABLES.iter().rev() {
+        sqlx::query(&format!("DELETE FROM {}", quote_ident(table.name))).execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+// Inserts rows (JSON objects with the given columns) or, for keys that already exist,
+// overwrites them. Columns must have been checked against table_columns. Rows that would
+// change nothing are counted as unchanged and not written.
+pub async fn upsert_rows(
+    conn: &mut PgConnection,
+    table: &BackupTable,
+    columns: &[String],
+    rows: &[Value],
+) -> Result<UpsertCounts, DalError> {
+    let column_list = columns.iter().map(|column| quote_ident(column)).collect::<Vec<_>>().join(", ");
+    let key_list = table.key_columns.iter().map(|column| quote_ident(column)).collect::<Vec<_>>().join(", ");
+    let value_columns: Vec<String> = columns
+        .iter()
+        .filter(|column| !table.key_columns.contains(&column.as_str()))
+        .map(|column| quote_ident(column))
+        .collect();
+
+    let on_conflict = if value_columns.is_empty() {
+        "DO NOTHING".to_string()
+    } else {
+        format!(
+            "DO UPDATE SET {} WHERE ({}) IS DISTINCT FROM ({})",
+            value_columns.iter().map(|column| format!("{0} = EXCLUDED.{0}", column)).collect::<Vec<_>>().join(", "),
+            value_columns.iter().map(|column| format!("t.{}", column)).collect::<Vec<_>>().join(", "),
+            value_columns.iter().map(|column| format!("EXCLUDED.{}", column)).collect::<Vec<_>>().join(", "),
+        )
+    };
+    // xmax is 0 for a freshly inserted row version and set for one written by the update
+    let sql = format!(
+        "INSERT INTO {table} AS t ({columns}) \
+         SELECT {columns} FROM jsonb_populate_recordset(NULL::{table}, $1) \
+         ON CONFLICT ({keys}) {on_conflict} \
+         RETURNING (t.xmax = 0) AS inserted",
+        table = quote_ident(table.name),
+        columns = column_list,
+        keys = key_list,
+        on_conflict = on_conflict,
+    );
+
+    let written = sqlx::query(&sql).bind(Json(rows)).fetch_all(conn).await?;
+    let inserted = written.iter().filter(|row| row.get::<bool, _>("inserted")).count() as u64;
+    let updated = written.len() as u64 - inserted;
+    Ok(UpsertCounts {
+        inserted,
+        updated,
+        unchanged: rows.len() as u64 - written.len() as u64,
+    })
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
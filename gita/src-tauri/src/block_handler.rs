@@ -1,5 +1,5 @@
 use chrono::{DateTime, Utc};
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgExecutor, PgPool};
 use uuid::Uuid;
 
 // Import the shared DalError
@@ -11,22 +11,37 @@ pub struct Block {
     pub page_id: Uuid,
     pub parent_block_id: Option<Uuid>,
     pub block_type: Option<String>,
+    pub order_index: i32, // Position among siblings sharing the same parent_block_id
+    pub content_text: Option<String>, // Normalized text of the block's own inline children; None when blank
+    pub task_state: Option<String>, // "todo" or "done" for checklist items and todo blocks; None otherwise
+    pub completed_at: Option<DateTime<Utc>>, // Set while task_state is "done"
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
-pub async fn create_block(
-    pool: &PgPool,
+// A block whose text matched a search, with enough page context to render a suggestion
+#[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct BlockSearchResult {
+    pub block_id: Uuid,
+    pub page_id: Uuid,
+    pub page_title: String,
+    pub content_text: String,
+}
+
+pub async fn create_block<'e>(
+    executor: impl PgExecutor<'e>,
     id: Uuid, // Accept the ID from content_json
     page_id: Uuid,
     parent_block_id: Option<Uuid>,
     block_type: Option<&str>,
+    order_index: i32,
+    content_text: Option<&str>,
 ) -> Result<Uuid, DalError> {
     // The 'id' is now provided, not generated.
     sqlx::query!(
         r#"
-        INSERT INTO blocks (id, page_id, parent_block_id, block_type, created_at, updated_at)
-        VALUES ($1, $2, $3, $4, now(), now())
+        INSERT INTO blocks (id, page_id, parent_block_id, block_type, order_index, content_text, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, now(), now())
         ON CONFLICT (id) DO NOTHING
         -- If a block with this ID somehow already exists (e.g. from a previous failed sync or different page),
         -- DO NOTHING to prevent error. Or, consider DO UPDATE if attributes might change.
@@ -37,57 +52,274 @@ pub async fn create_block(
         id, // Use the provided id
         page_id,
         parent_block_id,
-        block_type
+        block_type,
+        order_index,
+        content_text
     )
-    .execute(pool) // Use execute instead of fetch_one as ON CONFLICT DO NOTHING might not return a row
+    .execute(executor) // Use execute instead of fetch_one as ON CONFLICT DO NOTHING might not return a row
     .await?;
 
     Ok(id) // Return the provided id
 }
 
-pub async fn get_block(pool: &PgPool, id: Uuid) -> Result<Option<Block>, DalError> {
+// A block to insert with create_blocks
+#[derive(Debug, Clone)]
+pub struct NewBlock {
+    pub id: Uuid, // The ID from content_json
+    pub page_id: Uuid,
+    pub parent_block_id: Option<Uuid>,
+    pub block_type: Option<String>,
+    pub order_index: i32,
+    pub content_text: Option<String>,
+    pub task_state: Option<String>,
+}
+
+// Inserts many blocks with one statement. As in create_block, IDs that already exist are
+// skipped. Returns the number of blocks inserted.
+pub async fn create_blocks<'e>(executor: impl PgExecutor<'e>, blocks: &[NewBlock]) -> Result<u64, DalError> {
+    let mut ids = Vec::with_capacity(blocks.len());
+    let mut page_ids = Vec::with_capacity(blocks.len());
+    let mut parent_block_ids = Vec::with_capacity(blocks.len());
+    let mut block_types = Vec::with_capacity(blocks.len());
+    let mut order_indexes = Vec::with_capacity(blocks.len());
+    let mut content_texts = Vec::with_capacity(blocks.len());
+    let mut task_states = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        ids.push(block.id);
+        page_ids.push(block.page_id);
+        parent_block_ids.push(block.parent_block_id);
+        block_types.push(block.block_type.clone());
+        order_indexes.push(block.order_index);
+        content_texts.push(block.content_text.clone());
+        task_states.push(block.task_state.clone());
+    }
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO blocks
+            (id, page_id, parent_block_id, block_type, order_index, content_text, task_state, completed_at, created_at, updated_at)
+        SELECT b.id, b.page_id, b.parent_block_id, b.block_type, b.order_index, b.content_text, b.task_state,
+               CASE WHEN b.task_state = 'done' THEN now() END, now(), now()
+        FROM unnest($1::uuid[], $2::uuid[], $3::uuid[], $4::text[], $5::int4[], $6::text[], $7::text[])
+             AS b(id, page_id, parent_block_id, block_type, order_index, content_text, task_state)
+        ON CONFLICT (id) DO NOTHING
+        "#,
+        &ids,
+        &page_ids,
+        &parent_block_ids as &[Option<Uuid>],
+        &block_types as &[Option<String>],
+        &order_indexes,
+        &content_texts as &[Option<String>],
+        &task_states as &[Option<String>]
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+pub async fn get_block<'e>(executor: impl PgExecutor<'e>, id: Uuid) -> Result<Option<Block>, DalError> {
     let block = sqlx::query_as!(
         Block,
         r#"
-        SELECT id, page_id, parent_block_id, block_type, created_at, updated_at
+        SELECT id, page_id, parent_block_id, block_type, order_index, content_text, task_state, completed_at, created_at, updated_at
         FROM blocks
         WHERE id = $1
         "#,
         id
     )
-    .fetch_optional(pool)
+    .fetch_optional(executor)
     .await?;
 
     Ok(block)
 }
 
-pub async fn get_blocks_for_page(pool: &PgPool, page_id: Uuid) -> Result<Vec<Block>, DalError> {
+pub async fn get_blocks_for_page<'e>(executor: impl PgExecutor<'e>, page_id: Uuid) -> Result<Vec<Block>, DalError> {
     let blocks = sqlx::query_as!(
         Block,
         r#"
-        SELECT id, page_id, parent_block_id, block_type, created_at, updated_at
+        SELECT id, page_id, parent_block_id, block_type, order_index, content_text, task_state, completed_at, created_at, updated_at
         FROM blocks
         WHERE page_id = $1
-        ORDER BY created_at ASC -- Or some other meaningful order
+        ORDER BY parent_block_id NULLS FIRST, order_index ASC, created_at ASC
         "#,
         page_id
     )
-    .fetch_all(pool)
+    .fetch_all(executor)
     .await?;
 
     Ok(blocks)
 }
 
-// Still to implement:
-// update_block
-// delete_block
+// Order index that places a new block after its last sibling
+pub async fn next_order_index<'e>(
+    executor: impl PgExecutor<'e>,
+    page_id: Uuid,
+    parent_block_id: Option<Uuid>,
+) -> Result<i32, DalError> {
+    let next = sqlx::query_scalar!(
+        r#"
+        SELECT COALESCE(MAX(order_index) + 1, 0) AS "next!"
+        FROM blocks
+        WHERE page_id = $1 AND parent_block_id IS NOT DISTINCT FROM $2
+        "#,
+        page_id,
+        parent_block_id
+    )
+    .fetch_one(executor)
+    .await?;
+
+    Ok(next)
+}
+
+// Moves the page's blocks under parent_block_id from order_index from_index on by delta: +1
+// makes room for a block inserted at from_index, -1 closes the gap one left there
+pub async fn shift_sibling_order<'e>(
+    executor: impl PgExecutor<'e>,
+    page_id: Uuid,
+    parent_block_id: Option<Uuid>,
+    from_index: i32,
+    delta: i32,
+) -> Result<u64, DalError> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE blocks
+        SET order_index = order_index + $4
+        WHERE page_id = $1 AND parent_block_id IS NOT DISTINCT FROM $2 AND order_index >= $3
+        "#,
+        page_id,
+        parent_block_id,
+        from_index,
+        delta
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// Case-insensitive substring search over block text, skipping blocks on trashed pages.
+// Every matching block is returned, even when several share the same text.
+pub async fn search_blocks(
+    pool: &PgPool,
+    query_term: &str,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<BlockSearchResult>, DalError> {
+    let search_pattern = format!("%{}%", query_term);
+
+    let results = sqlx::query_as!(
+        BlockSearchResult,
+        r#"
+        SELECT b.id AS block_id, b.page_id, p.title AS page_title, b.content_text AS "content_text!"
+        FROM blocks b
+        JOIN pages p ON p.id = b.page_id
+        WHERE b.content_text ILIKE $1
+          AND p.deleted_at IS NULL
+        ORDER BY p.updated_at DESC, b.page_id, b.parent_block_id NULLS FIRST, b.order_index, b.id
+        LIMIT $2 OFFSET $3
+        "#,
+        search_pattern,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(results)
+}
+
+// Characters of block text shown around the match in a suggestion
+const SNIPPET_CHARS: usize = 80;
+const SNIPPET_LEAD_CHARS: usize = 20; // How much text before the match is kept
 
-pub async fn update_block(
+// A block offered while typing a (((block reference)))
+#[derive(Debug, serde::Serialize)]
+pub struct BlockSuggestion {
+    pub block_id: Uuid,
+    pub page_id: Uuid,
+    pub page_title: String,
+    pub snippet: String, // Part of the block text around the match
+}
+
+// Blocks whose text contains query (ignoring case), most recently updated pages first.
+// Blocks on exclude_page_id, usually the page being edited, are left out.
+pub async fn suggest_blocks(
     pool: &PgPool,
+    query: &str,
+    limit: i64,
+    exclude_page_id: Option<Uuid>,
+) -> Result<Vec<BlockSuggestion>, DalError> {
+    let like_term = query.to_lowercase().replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT b.id, b.page_id, p.title AS page_title, b.content_text AS "content_text!"
+        FROM blocks b
+        JOIN pages p ON p.id = b.page_id
+        WHERE b.content_text IS NOT NULL
+          AND lower(b.content_text) LIKE '%' || $1 || '%'
+          AND p.deleted_at IS NULL
+          AND ($2::uuid IS NULL OR b.page_id <> $2)
+        ORDER BY p.updated_at DESC, b.page_id, b.parent_block_id NULLS FIRST, b.order_index, b.id
+        LIMIT $3
+        "#,
+        like_term,
+        exclude_page_id,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| BlockSuggestion {
+            block_id: row.id,
+            page_id: row.page_id,
+            page_title: row.page_title,
+            snippet: snippet_around(&row.content_text, query),
+        })
+        .collect())
+}
+
+// Up to SNIPPET_CHARS characters of text, starting a little before the first match of query
+// (ignoring case). Cut ends are marked with an ellipsis.
+pub(crate) fn snippet_around(text: &str, query: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let lower = |c: &char| c.to_lowercase().next().unwrap_or(*c);
+    let text_lower: Vec<char> = chars.iter().map(lower).collect();
+    let query_lower: Vec<char> = query.chars().map(|c| lower(&c)).collect();
+
+    let match_start = if query_lower.is_empty() {
+        0
+    } else {
+        text_lower
+            .windows(query_lower.len())
+            .position(|window| window == query_lower.as_slice())
+            .unwrap_or(0)
+    };
+    let start = match_start.saturating_sub(SNIPPET_LEAD_CHARS).min(chars.len().saturating_sub(SNIPPET_CHARS));
+    let end = (start + SNIPPET_CHARS).min(chars.len());
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    snippet.extend(&chars[start..end]);
+    if end < chars.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+pub async fn update_block<'e>(
+    executor: impl PgExecutor<'e>,
     id: Uuid,
     // page_id cannot be updated, it's fixed once created.
     parent_block_id: Option<Option<Uuid>>, // Option<Option<T>>: Outer=update?, Inner=value (Some(val) or None for NULL)
     block_type: Option<Option<String>>,    // Option<Option<T>>: Outer=update?, Inner=value (Some(val) or None for NULL)
+    order_index: Option<i32>,
+    content_text: Option<Option<String>>,
 ) -> Result<bool, DalError> {
     let mut set_clauses = Vec::new();
     let mut params_count = 1; // Start with $1 for id
@@ -100,12 +332,24 @@ pub async fn update_block(
         params_count += 1;
         set_clauses.push(format!("block_type = ${}", params_count));
     }
+    if order_index.is_some() {
+        params_count += 1;
+        set_clauses.push(format!("order_index = ${}", params_count));
+    }
+    if content_text.is_some() {
+        params_count += 1;
+        set_clauses.push(format!("content_text = ${}", params_count));
+    }
 
     if set_clauses.is_empty() {
         return Ok(false); // No fields to update
     }
 
-    set_clauses.push("updated_at = now()".to_string()); // Always update updated_at
+    // updated_at is when the block was last edited, so a position shift caused by a sibling
+    // being added or removed doesn't count
+    if parent_block_id.is_some() || block_type.is_some() || content_text.is_some() {
+        set_clauses.push("updated_at = now()".to_string());
+    }
 
     let query_str = format!(
         "UPDATE blocks SET {} WHERE id = $1 RETURNING id", // RETURNING id to check if row was found
@@ -121,12 +365,18 @@ pub async fn update_block(
     if let Some(bt) = block_type {
         query = query.bind(bt); // bt is Option<String> directly
     }
+    if let Some(oi) = order_index {
+        query = query.bind(oi);
+    }
+    if let Some(ct) = content_text {
+        query = query.bind(ct);
+    }
 
-    let result = query.execute(pool).await?;
+    let result = query.execute(executor).await?;
     Ok(result.rows_affected() > 0)
 }
 
-pub async fn get_page_id_for_block(pool: &PgPool, block_id: Uuid) -> Result<Option<Uuid>, DalError> {
+pub async fn get_page_id_for_block<'e>(executor: impl PgExecutor<'e>, block_id: Uuid) -> Result<Option<Uuid>, DalError> {
     let result = sqlx::query!(
         r#"
         SELECT page_id
@@ -135,23 +385,234 @@ pub async fn get_page_id_for_block(pool: &PgPool, block_id: Uuid) -> Result<Opti
         "#,
         block_id
     )
-    .fetch_optional(pool)
+    .fetch_optional(executor)
     .await?;
 
     // query! returns a record-like struct, so access page_id field, then map to Option<Uuid>
     Ok(result.map(|row| row.page_id))
 }
 
-pub async fn delete_block(pool: &PgPool, id: Uuid) -> Result<bool, DalError> {
+// What page_handler::delete_block_tree removed
+#[derive(Debug, serde::Serialize)]
+pub struct DeletedBlocks {
+    pub page_id: Uuid,
+    pub block_ids: Vec<Uuid>, // The block and all of its descendants
+    pub block_references_removed: u64, // Made from inside the deleted blocks
+    pub audio_timestamps_removed: u64,
+    pub broken_references: i64, // References from other blocks to the deleted ones, now shown by vault health
+}
+
+// Deletes only the given blocks: their children keep a parent_block_id that no longer exists.
+// update_page uses this because the content is authoritative there; children that vanished
+// are deleted in the same pass and those that survive get their new parent from the content.
+// Anywhere else, go through the page content (page_handler::delete_block_node).
+pub async fn delete_blocks<'e>(executor: impl PgExecutor<'e>, ids: &[Uuid]) -> Result<u64, DalError> {
     let result = sqlx::query!(
         r#"
         DELETE FROM blocks
+        WHERE id = ANY($1)
+        "#,
+        ids
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// Clears what hangs off blocks whose nodes were just removed from their page's content: the
+// references made from inside them and their audio timestamps. References to them from other
+// blocks are kept (their text still mentions the block) and counted. Call before the page is
+// resynced, which deletes the blocks' rows.
+pub async fn clear_deleted_block_attachments(
+    conn: &mut PgConnection,
+    page_id: Uuid,
+    block_ids: Vec<Uuid>,
+) -> Result<DeletedBlocks, DalError> {
+    let block_references_removed = sqlx::query!(
+        r#"
+        DELETE FROM block_references
+        WHERE referencing_block_id = ANY($1)
+        "#,
+        &block_ids
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    let audio_timestamps_removed = sqlx::query!(
+        r#"
+        DELETE FROM audio_timestamps
+        WHERE block_id = ANY($1)
+        "#,
+        &block_ids
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    let broken_references = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM block_references
+        WHERE referenced_block_id = ANY($1)
+        "#,
+        &block_ids
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(DeletedBlocks {
+        page_id,
+        block_ids,
+        block_references_removed,
+        audio_timestamps_removed,
+        broken_references,
+    })
+}
+
+// A block edited since some moment, for the "Today's edits" view
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct EditedBlock {
+    pub block_id: Uuid,
+    pub text: String,
+    pub block_type: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// The edited blocks of one page, most recently edited first
+#[derive(Debug, serde::Serialize)]
+pub struct PageEdits {
+    pub page_id: Uuid,
+    pub page_title: String,
+    pub last_edited_at: DateTime<Utc>,
+    pub blocks: Vec<EditedBlock>,
+}
+
+// Blocks with text edited (or created, moved or checked off) since `since` on live pages,
+// grouped by page with the most recently edited page first. limit caps the number of blocks.
+pub async fn get_recently_edited_blocks(
+    pool: &PgPool,
+    since: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<PageEdits>, DalError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT b.id AS block_id, b.page_id, p.title AS page_title, b.content_text AS "text!",
+               b.block_type, b.updated_at
+        FROM blocks b
+        JOIN pages p ON p.id = b.page_id
+        WHERE b.updated_at >= $1
+          AND b.content_text IS NOT NULL
+          AND p.deleted_at IS NULL
+          AND NOT p.is_template
+        ORDER BY b.updated_at DESC, b.id
+        LIMIT $2
+        "#,
+        since,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut pages: Vec<PageEdits> = Vec::new();
+    let mut page_positions: std::collections::HashMap<Uuid, usize> = std::collections::HashMap::new();
+    for row in rows {
+        let position = *page_positions.entry(row.page_id).or_insert_with(|| {
+            pages.push(PageEdits {
+                page_id: row.page_id,
+                page_title: row.page_title.clone(),
+                last_edited_at: row.updated_at,
+                blocks: Vec::new(),
+            });
+            pages.len() - 1
+        });
+        pages[position].blocks.push(EditedBlock {
+            block_id: row.block_id,
+            text: row.text,
+            block_type: row.block_type,
+            updated_at: row.updated_at,
+        });
+    }
+    Ok(pages)
+}
+
+// --- Tasks ---
+
+// Which tasks list_tasks returns. Filters combine; the default is every task in the vault.
+#[derive(Debug, Default, serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct TaskFilter {
+    pub open_only: bool,
+    pub page_id: Option<Uuid>,
+    pub created_within_days: Option<i32>, // Created in the last N days
+    pub completed_this_week: bool, // Done since the start of the current week (Monday)
+    pub limit: Option<i64>,
+}
+
+// A task block with the page it's on
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct Task {
+    pub block_id: Uuid,
+    pub page_id: Uuid,
+    pub page_title: String,
+    pub text: Option<String>,
+    pub task_state: String,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+// Sets whether a block is a task and, if so, whether it's done. completed_at is set when the
+// task becomes done and cleared when it's reopened.
+pub async fn set_block_task_state<'e>(
+    executor: impl PgExecutor<'e>,
+    id: Uuid,
+    task_state: Option<&str>,
+) -> Result<bool, DalError> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE blocks
+        SET task_state = $2,
+            completed_at = CASE WHEN $2 = 'done' THEN COALESCE(completed_at, now()) END,
+            updated_at = now()
         WHERE id = $1
         "#,
-        id
+        id,
+        task_state
     )
-    .execute(pool)
+    .execute(executor)
     .await?;
 
     Ok(result.rows_affected() > 0)
 }
+
+// Tasks on live pages (templates excluded), open ones first, then newest first
+pub async fn list_tasks(pool: &PgPool, filter: &TaskFilter) -> Result<Vec<Task>, DalError> {
+    let tasks = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT b.id AS block_id, b.page_id, p.title AS page_title, b.content_text AS text,
+               b.task_state AS "task_state!", b.created_at, b.completed_at
+        FROM blocks b
+        JOIN pages p ON p.id = b.page_id
+        WHERE b.task_state IS NOT NULL
+          AND p.deleted_at IS NULL
+          AND NOT p.is_template
+          AND (NOT $1 OR b.task_state = 'todo')
+          AND ($2::uuid IS NULL OR b.page_id = $2)
+          AND ($3::int IS NULL OR b.created_at >= now() - make_interval(days => $3))
+          AND (NOT $4 OR (b.task_state = 'done' AND b.completed_at >= date_trunc('week', now())))
+        ORDER BY b.task_state = 'done', b.created_at DESC, b.order_index
+        LIMIT $5
+        "#,
+        filter.open_only,
+        filter.page_id,
+        filter.created_within_days,
+        filter.completed_this_week,
+        filter.limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(tasks)
+}
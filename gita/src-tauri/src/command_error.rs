@@ -0,0 +1,167 @@
+// Error type returned by every Tauri command. It serializes as
+// `{ "code": "not_found", "message": "..." }` (plus `field` for invalid input), so the frontend
+// can branch on `code` and show `message` to the user. The codes are part of the frontend
+// contract; don't rename them.
+
+use serde::Serialize;
+use serde_json::Value;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::dal_error::DalError;
+use crate::db;
+
+#[derive(Debug, Error, Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum CommandError {
+    #[error("{message}")]
+    NotFound { message: String },
+
+    #[error("{message}")]
+    InvalidInput { field: String, message: String }, // field is the command parameter at fault
+
+    #[error("{message}")]
+    Database { message: String }, // Query failures and a database that isn't configured
+
+    #[error("{message}")]
+    DatabaseUnavailable { message: String }, // The database didn't answer; trying again later may work
+
+    #[error("{message}")]
+    AudioDevice { message: String },
+
+    #[error("{message}")]
+    AlreadyRecording { message: String }, // A recording with the requested ID is already running
+
+    #[error("{message}")]
+    VaultLocked { message: String }, // Needs the vault key; unlock_vault and try again
+
+    #[error("{message}")]
+    Conflict {
+        message: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        current: Option<Value>, // The item as it is now, when the conflict was a stale write
+        #[serde(skip_serializing_if = "Option::is_none")]
+        existing_id: Option<String>, // The page already using the title, when the conflict was a title
+    },
+
+    #[error("{message}")]
+    Internal { message: String },
+}
+
+impl CommandError {
+    pub fn not_found(message: impl Into<String>) -> Self {
+        CommandError::NotFound { message: message.into() }
+    }
+
+    pub fn invalid_input(field: impl Into<String>, message: impl Into<String>) -> Self {
+        CommandError::InvalidInput {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+
+    pub fn database(message: impl Into<String>) -> Self {
+        CommandError::Database { message: message.into() }
+    }
+
+    pub fn audio_device(message: impl Into<String>) -> Self {
+        CommandError::AudioDevice { message: message.into() }
+    }
+
+    pub fn already_recording(message: impl Into<String>) -> Self {
+        CommandError::AlreadyRecording { message: message.into() }
+    }
+
+    pub fn vault_locked(message: impl Into<String>) -> Self {
+        CommandError::VaultLocked { message: message.into() }
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        CommandError::Conflict {
+            message: message.into(),
+            current: None,
+            existing_id: None,
+        }
+    }
+
+    // A write refused because the item changed since the caller read it. current is sent along
+    // so the frontend can merge instead of reloading.
+    pub fn stale_write(message: impl Into<String>, current: Value) -> Self {
+        CommandError::Conflict {
+            message: message.into(),
+            current: Some(current),
+            existing_id: None,
+        }
+    }
+}
+
+impl From<DalError> for CommandError {
+    fn from(err: DalError) -> Self {
+        match err {
+            DalError::NotFound => CommandError::not_found("Item not found"),
+            DalError::Conflict(message) => CommandError::conflict(message),
+            DalError::TitleTaken { title, existing_id } => CommandError::Conflict {
+                message: format!("A page titled '{}' already exists", title),
+                current: None,
+                existing_id: Some(existing_id.to_string()),
+            },
+            DalError::Uuid(e) => CommandError::invalid_input("id", e.to_string()),
+            DalError::Sqlx(e) => CommandError::from(e),
+            other => CommandError::Internal { message: other.to_string() },
+        }
+    }
+}
+
+impl From<sqlx::Error> for CommandError {
+    fn from(err: sqlx::Error) -> Self {
+        if db::is_transient(&err) {
+            CommandError::DatabaseUnavailable {
+                message: format!("Database temporarily unavailable: {}", err),
+            }
+        } else {
+            CommandError::database(format!("Database query failed: {}", err))
+        }
+    }
+}
+
+impl From<uuid::Error> for CommandError {
+    fn from(err: uuid::Error) -> Self {
+        CommandError::invalid_input("id", format!("Invalid ID format: {}", err))
+    }
+}
+
+impl From<std::io::Error> for CommandError {
+    fn from(err: std::io::Error) -> Self {
+        CommandError::Internal { message: err.to_string() }
+    }
+}
+
+// Errors from helpers that still report plain messages (file system, settings, sync)
+impl From<String> for CommandError {
+    fn from(message: String) -> Self {
+        CommandError::Internal { message }
+    }
+}
+
+// Parses a UUID command parameter, naming the parameter and what it identifies on failure,
+// e.g. parse_uuid(&page_id, "page_id", "page ID")
+pub fn parse_uuid(value: &str, field: &str, what: &str) -> Result<Uuid, CommandError> {
+    Uuid::parse_str(value).map_err(|e| CommandError::invalid_input(field, format!("Invalid {} format: {}", what, e)))
+}
+
+// Parses a YYYY-MM-DD date command parameter. Dates that don't exist (2024-02-30) are rejected.
+pub fn parse_date(value: &str, field: &str) -> Result<chrono::NaiveDate, CommandError> {
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .filter(|date| date.format("%Y-%m-%d").to_string() == value) // Reject unpadded forms like 2024-1-5
+        .ok_or_else(|| CommandError::invalid_input(field, format!("Invalid date '{}': expected YYYY-MM-DD", value)))
+}
+
+// Like CommandError::from, but with a specific message when the item wasn't found
+pub fn not_found_as(message: impl Into<String>) -> impl FnOnce(DalError) -> CommandError {
+    let message = message.into();
+    move |err| match err {
+        DalError::NotFound => CommandError::not_found(message),
+        other => CommandError::from(other),
+    }
+}
@@ -14,10 +14,28 @@ pub enum DalError {
     #[error("Item not found")]
     NotFound,
 
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("A page titled '{title}' already exists ({existing_id})")]
+    TitleTaken { title: String, existing_id: uuid::Uuid }, // Titles are unique among live pages, ignoring case
+
     #[error("An unexpected error occurred: {0}")]
     Internal(String),
 }
 
+impl DalError {
+    // True when a write lost a race against another insert of the same unique value
+    pub fn is_unique_violation(&self) -> bool {
+        matches!(self, DalError::Sqlx(sqlx::Error::Database(db_err)) if db_err.is_unique_violation())
+    }
+
+    // True when the database couldn't be reached, rather than the query failing
+    pub fn is_transient(&self) -> bool {
+        matches!(self, DalError::Sqlx(e) if crate::db::is_transient(e))
+    }
+}
+
 // Optional: Add a blanket implementation to convert other errors to DalError::Internal
 // impl<E: std::error::Error + Send + Sync + 'static> From<E> for DalError {
 //     fn from(err: E) -> Self {
@@ -1,11 +1,162 @@
-use sqlx::{postgres::PgPoolOptions, PgPool};
-use std::env;
+use sqlx::migrate::{MigrateError, Migrator};
+use sqlx::{postgres::PgPoolOptions, Executor, PgPool};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use thiserror::Error;
 
-pub async fn init_pool() -> Result<PgPool, sqlx::Error> {
-    let database_url = env::var("DATABASE_URL")
-        .expect("DATABASE_URL must be set");
+use crate::dal_error::DalError;
+
+// Migrations embedded from src-tauri/migrations at compile time
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, Error)]
+pub enum DbInitError {
+    #[error("Could not connect to the database: {0}")]
+    Connect(#[from] sqlx::Error),
+
+    #[error("The database schema (version {0}) is newer than this version of the app. Please update the app.")]
+    SchemaTooNew(i64),
+
+    #[error("Database migration failed: {0}")]
+    Migrate(MigrateError),
+}
+
+impl From<MigrateError> for DbInitError {
+    fn from(err: MigrateError) -> Self {
+        match err {
+            MigrateError::VersionMissing(version) => DbInitError::SchemaTooNew(version),
+            other => DbInitError::Migrate(other),
+        }
+    }
+}
+
+// Schema version applied to the database vs. the newest one this binary knows about
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct SchemaVersion {
+    pub current_version: Option<i64>, // None when no migrations have been applied yet
+    pub latest_version: i64,
+    pub pending_migrations: usize,
+    pub database_is_newer: bool, // The app binary is older than the schema
+}
+
+// Retries of transient failures back off exponentially up to a cap, within a total budget
+const RETRY_INITIAL_DELAY: Duration = Duration::from_millis(100);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(2);
+const RETRY_BUDGET: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub max_connections: u32,
+    pub acquire_timeout: Duration, // How long a query waits for a free connection (or a new one)
+    pub idle_timeout: Option<Duration>,      // None keeps idle connections open
+    pub statement_timeout: Option<Duration>, // None lets statements run as long as they take
+}
+
+pub async fn init_pool(database_url: &str, config: &PoolConfig) -> Result<PgPool, sqlx::Error> {
+    let statement_timeout_ms = config.statement_timeout.map(|timeout| timeout.as_millis());
     PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
+        .max_connections(config.max_connections)
+        .acquire_timeout(config.acquire_timeout)
+        .idle_timeout(config.idle_timeout)
+        .after_connect(move |conn, _meta| {
+            Box::pin(async move {
+                if let Some(ms) = statement_timeout_ms {
+                    conn.execute(format!("SET statement_timeout = {}", ms).as_str()).await?;
+                }
+                Ok(())
+            })
+        })
+        .connect(database_url)
         .await
 }
+
+// Failures that say nothing about the query: the connection dropped, the server is restarting
+// or no connection could be had in time. The same query may well succeed a moment later.
+pub fn is_transient(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::WorkerCrashed => true,
+        sqlx::Error::Database(db_err) => db_err.code().is_some_and(|code| {
+            // Connection exceptions, and shutdown or startup in progress
+            code.starts_with("08") || matches!(code.as_ref(), "57P01" | "57P02" | "57P03")
+        }),
+        _ => false,
+    }
+}
+
+// Runs op again after transient failures, until it succeeds, fails otherwise or the retry
+// budget is spent. Only for reads and writes that are safe to repeat, since a dropped
+// connection can hide a write that went through.
+pub async fn with_retry<T, F, Fut>(mut op: F) -> Result<T, DalError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, DalError>>,
+{
+    let started = Instant::now();
+    let mut delay = RETRY_INITIAL_DELAY;
+    loop {
+        match op().await {
+            Err(e) if e.is_transient() && started.elapsed() + delay <= RETRY_BUDGET => {
+                eprintln!("[Database] Transient error, retrying in {} ms: {}", delay.as_millis(), e);
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(RETRY_MAX_DELAY);
+            }
+            result => return result,
+        }
+    }
+}
+
+// Brings the schema up to date. Safe on databases created by hand from the pre-migration
+// schema, since the first migrations only create what is missing.
+pub async fn run_migrations(pool: &PgPool) -> Result<(), DbInitError> {
+    let applied_before = applied_versions(pool).await?;
+    let pending = MIGRATOR.iter().filter(|m| !applied_before.contains(&m.version)).count();
+    if pending > 0 {
+        println!("[Database] Applying {} pending migration(s)...", pending);
+    }
+    // Migrations can take longer than the statement timeout on a large vault, so they run on a
+    // connection without one, which is closed afterwards rather than returned to the pool
+    let mut conn = pool.acquire().await?;
+    conn.execute("SET statement_timeout = 0").await?;
+    // run_direct rather than run: run on a pool connection trips "Acquire is not general enough"
+    MIGRATOR.run_direct(&mut *conn).await?;
+    drop(conn.detach());
+    if pending > 0 {
+        println!("[Database] Migrations complete.");
+    }
+    Ok(())
+}
+
+pub async fn get_schema_version(pool: &PgPool) -> Result<SchemaVersion, sqlx::Error> {
+    let applied = applied_versions(pool).await?;
+    let latest_version = MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0);
+    let current_version = applied.iter().copied().max();
+    Ok(SchemaVersion {
+        current_version,
+        latest_version,
+        pending_migrations: MIGRATOR.iter().filter(|m| !applied.contains(&m.version)).count(),
+        database_is_newer: current_version.is_some_and(|v| v > latest_version),
+    })
+}
+
+// Versions recorded in sqlx's bookkeeping table, which doesn't exist before the first run
+async fn applied_versions(pool: &PgPool) -> Result<Vec<i64>, sqlx::Error> {
+    let table_exists: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+        .fetch_one(pool)
+        .await?;
+    if !table_exists {
+        return Ok(Vec::new());
+    }
+    sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success ORDER BY version")
+        .fetch_all(pool)
+        .await
+}
+
+// A vault's database must be PostgreSQL: the DAL's queries are PostgreSQL-specific (JSONB,
+// ILIKE, recursive CTEs, row locking)
+pub fn check_database_url(database_url: &str) -> Result<(), String> {
+    let scheme = database_url.split(':').next().unwrap_or_default().to_ascii_lowercase();
+    match scheme.as_str() {
+        "postgres" | "postgresql" => Ok(()),
+        other => Err(format!("Unsupported database URL scheme '{}'. Expected postgres://", other)),
+    }
+}
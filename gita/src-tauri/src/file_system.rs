@@ -1,23 +1,28 @@
 // use std::fs::{self, File}; // Removed
 // use std::io::{Read, Write}; // Removed
-// use std::path::Path; // Removed
 // use std::sync::Mutex; // Removed as it was likely for DB connection state or similar, not needed now
 
 // Removed: use rusqlite::Connection;
 // Removed: use tauri::AppHandle; // Was not present in snippet, but good to confirm
-// Removed: use chrono::{DateTime, Utc};
 // Removed: use regex::Regex; // Removed unused import
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 // Removed: use uuid::Uuid;
-// Removed: use walkdir::WalkDir;
+use walkdir::WalkDir;
 
 #[derive(Debug, Serialize, Deserialize)]
-struct NoteFrontMatter {
-    id: Option<String>,
-    title: Option<String>,
-    created_at: Option<String>,
-    updated_at: Option<String>,
-    tags: Option<Vec<String>>,
+pub struct NoteFrontMatter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
 }
 
 impl Default for NoteFrontMatter {
@@ -32,9 +37,101 @@ impl Default for NoteFrontMatter {
     }
 }
 
-// All public functions (init_database, get_all_notes, search_notes, read_markdown_file,
-// write_markdown_file, create_note, create_daily_note, delete_note, find_backlinks)
-// and the public structs NoteMetadata and Note have been removed.
-// The file now only contains NoteFrontMatter, its Default impl, and extract_front_matter.
-// Necessary use statements (regex, serde, serde_yaml) are kept.
-// Unnecessary use statements (fs, io, path, chrono, uuid, walkdir, rusqlite) are removed.
+// Recursively lists the Markdown files under dir, sorted by path. Hidden files and folders
+// (e.g. Obsidian's .obsidian and .trash) are skipped.
+pub fn scan_directory(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    if !dir.is_dir() {
+        return Err(format!("{} is not a directory", dir.display()));
+    }
+
+    let mut files = Vec::new();
+    let walker = WalkDir::new(dir)
+        .follow_links(true)
+        .into_iter()
+        .filter_entry(|entry| entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.'));
+    for entry in walker {
+        let entry = entry.map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+        let is_markdown = entry
+            .path()
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("md"));
+        if entry.file_type().is_file() && is_markdown {
+            files.push(entry.into_path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Checks that files can be created in dir by creating and removing one. Permission bits can't
+// tell: the read-only flag means nothing for a directory on Unix, and ACLs or a read-only mount
+// refuse writes whatever the bits say.
+pub fn check_directory_writable(dir: &Path) -> Result<(), String> {
+    let probe = dir.join(format!(".write-test-{}", uuid::Uuid::new_v4()));
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .map_err(|e| format!("Directory is not writable: {}", e))?;
+    if let Err(e) = std::fs::remove_file(&probe) {
+        eprintln!("[FileSystem] Failed to remove {}: {}", probe.display(), e);
+    }
+    Ok(())
+}
+
+// Splits a leading `---` YAML block off a note. Returns None for the front matter when there
+// is no block or it isn't valid YAML, in which case the whole content is the body.
+pub fn extract_front_matter(content: &str) -> (Option<NoteFrontMatter>, &str) {
+    let rest = match content.strip_prefix("---\n").or_else(|| content.strip_prefix("---\r\n")) {
+        Some(rest) => rest,
+        None => return (None, content),
+    };
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            let yaml = &rest[..offset];
+            let body = &rest[offset + line.len()..];
+            return match serde_yaml::from_str::<NoteFrontMatter>(yaml) {
+                Ok(front_matter) => (Some(front_matter), body),
+                Err(_) => (None, content),
+            };
+        }
+        offset += line.len();
+    }
+    (None, content)
+}
+
+// Renders a note file: a YAML front matter block followed by the body
+pub fn render_note(front_matter: &NoteFrontMatter, body: &str) -> Result<String, String> {
+    let yaml = serde_yaml::to_string(front_matter).map_err(|e| format!("Failed to serialize front matter: {}", e))?;
+    Ok(format!("---\n{}---\n{}", yaml, body))
+}
+
+// Turns a page title into a safe file name (without extension)
+pub fn file_name_for_title(title: &str) -> String {
+    let name: String = title
+        .chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control() { '-' } else { c })
+        .collect();
+    let name = name.trim().trim_start_matches('.').to_string();
+    if name.is_empty() { "Untitled".to_string() } else { name }
+}
+
+// Parses front matter dates as written by Obsidian and most static site generators:
+// RFC 3339, "YYYY-MM-DD HH:MM[:SS]" or a bare "YYYY-MM-DD" (taken as UTC).
+pub fn parse_front_matter_date(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(naive.and_utc());
+        }
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|naive| naive.and_utc())
+}
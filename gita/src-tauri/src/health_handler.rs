@@ -0,0 +1,180 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+// Import the shared DalError
+use crate::dal_error::DalError;
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct OrphanPage {
+    pub id: Uuid,
+    pub title: String,
+    pub updated_at: DateTime<Utc>,
+    pub is_stub: bool, // No content yet, e.g. created for a link that has since been removed
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct BrokenBlockReference {
+    pub id: Uuid,
+    pub referencing_page_id: Uuid,
+    pub referencing_page_title: String,
+    pub referencing_block_id: Uuid,
+    pub referenced_block_id: Uuid, // No longer exists
+}
+
+// A timestamp whose block was deleted, e.g. removed in the editor
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct OrphanAudioTimestamp {
+    pub id: Uuid,
+    pub audio_recording_id: Uuid,
+    pub block_id: Uuid, // No longer exists
+    pub timestamp_ms: i32,
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct UnresolvedLink {
+    pub page_id: Uuid,
+    pub page_title: String,
+    pub target_title: String, // As written between the brackets, without |alias or #heading
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct MissingAudioFile {
+    pub recording_id: Uuid,
+    pub page_id: Option<Uuid>,
+    pub file_path: String,
+}
+
+// Problems found across the vault, for the "Vault doctor" screen. Trashed pages are ignored.
+#[derive(Debug, serde::Serialize)]
+pub struct VaultHealth {
+    pub orphan_pages: Vec<OrphanPage>, // No live page links here; daily notes and templates aren't counted
+    pub broken_block_references: Vec<BrokenBlockReference>,
+    pub orphan_audio_timestamps: Vec<OrphanAudioTimestamp>,
+    pub unresolved_links: Vec<UnresolvedLink>, // `[[links]]` in raw_markdown that match no page
+    pub missing_audio_files: Vec<MissingAudioFile>,
+}
+
+pub async fn get_vault_health(pool: &PgPool, audio_dir: &Path) -> Result<VaultHealth, DalError> {
+    let orphan_pages = sqlx::query_as!(
+        OrphanPage,
+        r#"
+        SELECT p.id, p.title, p.updated_at,
+               (p.content_json = '{}'::jsonb AND coalesce(btrim(p.raw_markdown, E' \t\r\n'), '') = '') AS "is_stub!"
+        FROM pages p
+        WHERE p.deleted_at IS NULL
+          AND NOT p.is_template
+          AND p.title !~ '^[0-9]{4}-[0-9]{2}-[0-9]{2}$'
+          AND NOT EXISTS (
+              SELECT 1
+              FROM page_links l
+              JOIN pages src ON src.id = l.source_page_id
+              WHERE l.target_page_id = p.id AND l.source_page_id <> p.id AND src.deleted_at IS NULL
+          )
+        ORDER BY p.title
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let broken_block_references = sqlx::query_as!(
+        BrokenBlockReference,
+        r#"
+        SELECT br.id, br.referencing_page_id, p.title AS referencing_page_title,
+               br.referencing_block_id, br.referenced_block_id
+        FROM block_references br
+        JOIN pages p ON p.id = br.referencing_page_id
+        WHERE p.deleted_at IS NULL
+          AND NOT EXISTS (SELECT 1 FROM blocks b WHERE b.id = br.referenced_block_id)
+        ORDER BY p.title, br.created_at
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let orphan_audio_timestamps = sqlx::query_as!(
+        OrphanAudioTimestamp,
+        r#"
+        SELECT t.id, t.audio_recording_id, t.block_id, t.timestamp_ms
+        FROM audio_timestamps t
+        WHERE NOT EXISTS (SELECT 1 FROM blocks b WHERE b.id = t.block_id)
+        ORDER BY t.audio_recording_id, t.timestamp_ms
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    // Same resolution as page links: by title (ignoring case) or alias among live pages, or by
+    // page ID
+    let unresolved_links = sqlx::query_as!(
+        UnresolvedLink,
+        r#"
+        SELECT DISTINCT p.id AS page_id, p.title AS page_title, links.target AS "target_title!"
+        FROM pages p
+        CROSS JOIN LATERAL (
+            SELECT trim(split_part(split_part(m[1], '|', 1), '#', 1)) AS target
+            FROM regexp_matches(p.raw_markdown, '\[\[(.*?)\]\]', 'g') AS m
+        ) links
+        WHERE p.deleted_at IS NULL
+          AND links.target <> ''
+          AND NOT EXISTS (
+              SELECT 1 FROM pages t
+              WHERE t.deleted_at IS NULL AND (lower(t.title) = lower(links.target) OR t.id::text = links.target)
+          )
+          AND NOT EXISTS (
+              SELECT 1 FROM page_aliases a JOIN pages t ON t.id = a.page_id
+              WHERE t.deleted_at IS NULL AND lower(a.alias) = lower(links.target)
+          )
+        ORDER BY p.title, "target_title!"
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let recordings = sqlx::query!(
+        r#"
+        SELECT id, page_id, file_path, secondary_file_path
+        FROM audio_recordings
+        ORDER BY created_at
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    // Recordings normally live in the audio directory, so one listing of it answers most
+    // lookups; only files stored elsewhere are checked one by one
+    let audio_files: HashSet<PathBuf> = std::fs::read_dir(audio_dir)
+        .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+        .unwrap_or_default();
+    let missing_audio_files = recordings
+        .into_iter()
+        .flat_map(|recording| {
+            // Both tracks of a split recording
+            std::iter::once(recording.file_path)
+                .chain(recording.secondary_file_path)
+                .map(move |file_path| MissingAudioFile {
+                    recording_id: recording.id,
+                    page_id: recording.page_id,
+                    file_path,
+                })
+        })
+        .filter(|missing| {
+            let path = Path::new(&missing.file_path);
+            if path.parent() == Some(audio_dir) {
+                !audio_files.contains(path)
+            } else {
+                !path.is_file()
+            }
+        })
+        .collect();
+
+    Ok(VaultHealth {
+        orphan_pages,
+        broken_block_references,
+        orphan_audio_timestamps,
+        unresolved_links,
+        missing_audio_files,
+    })
+}
@@ -0,0 +1,586 @@
+// Exports a page as a standalone HTML file. content_json is rendered node by node: headings,
+// paragraphs, (nested) lists, quotes, code and text marks. [[Links]] become links to the other
+// exported files, or plain text for pages that weren't exported. (((Block references))) are
+// inlined as blockquotes. Blocks with audio timestamps carry data-timestamp attributes; with
+// audio bundled, the recordings are copied next to the file (audio/<recording id>/<file name>)
+// and a small script seeks to the timestamp when such a block is clicked.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::audio_handler;
+use crate::file_system;
+use crate::link_handler;
+use crate::page_handler::{self, Page, ResolvedBlock, ResolvedReference};
+use crate::vault_crypto::{self, VaultKey};
+
+// How deep references inside inlined blocks are followed
+const REFERENCE_DEPTH: u32 = 2;
+const AUDIO_DIR_NAME: &str = "audio";
+
+lazy_static! {
+    // A [[page link]] or a (((block reference))) inside a text node
+    static ref INLINE_REFERENCE_REGEX: Regex = Regex::new(r"\[\[(.*?)\]\]|\(\(\((.*?)\)\)\)").unwrap();
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(default)]
+pub struct HtmlExportOptions {
+    pub include_linked_pages: bool, // Also export the pages this one links to, next to it
+    pub bundle_audio: bool,         // Copy the recordings its blocks have timestamps in
+}
+
+#[derive(Serialize, Debug)]
+pub struct HtmlExportSummary {
+    pub files: Vec<String>, // The page's file first, then linked pages'
+    pub audio_files: usize,
+    pub missing_audio_files: Vec<String>, // Recorded in the database but not found on disk
+}
+
+// Everything a page's HTML can point at, gathered before rendering
+#[derive(Default)]
+struct RenderContext {
+    page_files: HashMap<String, String>, // Linked page title or ID -> exported file name
+    references: HashMap<Uuid, ResolvedBlock>,
+    timestamps: HashMap<Uuid, (Uuid, i32)>, // block_id -> (recording ID, first timestamp in ms)
+    audio_sources: Vec<(Uuid, String)>,     // Bundled recordings: (recording ID, relative path)
+}
+
+// Writes the page to dest, and with include_linked_pages the pages it links to next to it.
+// Existing files other than dest aren't overwritten; linked pages get a numbered name instead.
+pub async fn export_page_html(
+    pool: &PgPool,
+    page: Page,
+    dest: &Path,
+    options: HtmlExportOptions,
+    vault_key: Option<&VaultKey>, // Bundled recordings that are encrypted are decrypted with it
+) -> Result<HtmlExportSummary, String> {
+    let dir = dest.parent().map(Path::to_path_buf).unwrap_or_default();
+    let dest_name = dest
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| "The destination needs a file name".to_string())?;
+
+    let mut pages = vec![page];
+    if options.include_linked_pages {
+        let target_ids: Vec<Uuid> = link_handler::find_outgoing_links_for_page(pool, pages[0].id)
+            .await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .map(|link| link.target_page_id)
+            .filter(|id| *id != pages[0].id)
+            .collect();
+        let mut linked = page_handler::get_pages(pool, &target_ids).await.map_err(|e| e.to_string())?;
+        linked.sort_by(|a, b| a.title.cmp(&b.title));
+        pages.extend(linked);
+    }
+
+    // Every exported page can be linked to from every other, by title or by ID
+    let mut used_names = HashSet::from([dest_name.clone()]);
+    let mut file_names = vec![dest_name];
+    for page in &pages[1..] {
+        file_names.push(allocate_file_name(&dir, &page.title, &mut used_names));
+    }
+    let mut page_files = HashMap::new();
+    for (page, file_name) in pages.iter().zip(&file_names) {
+        page_files.insert(page.title.clone(), file_name.clone());
+        page_files.insert(page.id.to_string(), file_name.clone());
+    }
+
+    let mut summary = HtmlExportSummary { files: Vec::new(), audio_files: 0, missing_audio_files: Vec::new() };
+    let mut copied_recordings: HashMap<Uuid, Option<String>> = HashMap::new();
+    for (page, file_name) in pages.iter().zip(&file_names) {
+        let mut context = RenderContext { page_files: page_files.clone(), ..Default::default() };
+
+        let mut reference_ids = Vec::new();
+        page_handler::collect_block_reference_ids(&page.content_json, &mut reference_ids);
+        for block_id in reference_ids {
+            let resolved = page_handler::resolve_block_reference(pool, block_id, REFERENCE_DEPTH)
+                .await
+                .map_err(|e| e.to_string())?;
+            collect_resolved_blocks(resolved, &mut context.references);
+        }
+
+        let timestamps = audio_handler::get_audio_timestamps_for_page(pool, page.id)
+            .await
+            .map_err(|e| e.to_string())?;
+        for timestamp in timestamps {
+            context
+                .timestamps
+                .entry(timestamp.block_id)
+                .or_insert((timestamp.audio_recording_id, timestamp.timestamp_ms));
+            if !options.bundle_audio {
+                continue;
+            }
+            let copied = match copied_recordings.get(&timestamp.audio_recording_id) {
+                Some(copied) => copied.clone(),
+                None => {
+                    let copied = copy_recording(&dir, timestamp.audio_recording_id, &timestamp.file_path, vault_key)?;
+                    match &copied {
+                        Some(_) => summary.audio_files += 1,
+                        None => summary.missing_audio_files.push(timestamp.file_path.clone()),
+                    }
+                    copied_recordings.insert(timestamp.audio_recording_id, copied.clone());
+                    copied
+                }
+            };
+            if let Some(relative_path) = copied {
+                if !context.audio_sources.iter().any(|(id, _)| *id == timestamp.audio_recording_id) {
+                    context.audio_sources.push((timestamp.audio_recording_id, relative_path));
+                }
+            }
+        }
+
+        let html = render_page_html(&page.title, &page.content_json, &context);
+        let path = dir.join(file_name);
+        std::fs::write(&path, html).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        summary.files.push(path.to_string_lossy().to_string());
+    }
+
+    Ok(summary)
+}
+
+// Keeps every resolved block of a reference tree by ID; the nested references are looked up
+// from there when the block is rendered
+fn collect_resolved_blocks(reference: ResolvedReference, blocks: &mut HashMap<Uuid, ResolvedBlock>) {
+    if let ResolvedReference::Block(mut block) = reference {
+        for nested in std::mem::take(&mut block.references) {
+            collect_resolved_blocks(nested, blocks);
+        }
+        blocks.insert(block.block_id, block);
+    }
+}
+
+fn allocate_file_name(dir: &Path, title: &str, used_names: &mut HashSet<String>) -> String {
+    let base = file_system::file_name_for_title(title);
+    let mut candidate = format!("{}.html", base);
+    let mut n = 2;
+    while used_names.contains(&candidate) || dir.join(&candidate).exists() {
+        candidate = format!("{} ({}).html", base, n);
+        n += 1;
+    }
+    used_names.insert(candidate.clone());
+    candidate
+}
+
+// Copies a recording to audio/<recording id>/ under dir, decrypted so the page can play it.
+// Returns its path relative to dir, or None if the file is missing.
+fn copy_recording(
+    dir: &Path,
+    recording_id: Uuid,
+    file_path: &str,
+    vault_key: Option<&VaultKey>,
+) -> Result<Option<String>, String> {
+    let source = PathBuf::from(file_path);
+    let Some(file_name) = source.file_name().filter(|_| source.is_file()) else {
+        return Ok(None);
+    };
+    let target_dir = dir.join(AUDIO_DIR_NAME).join(recording_id.to_string());
+    std::fs::create_dir_all(&target_dir).map_err(|e| format!("Failed to create audio directory: {}", e))?;
+    vault_crypto::copy_decrypted(&source, &target_dir.join(file_name), vault_key)
+        .map_err(|e| format!("Failed to copy {}: {}", file_path, e))?;
+    Ok(Some(format!("{}/{}/{}", AUDIO_DIR_NAME, recording_id, file_name.to_string_lossy())))
+}
+
+// --- Rendering ---
+
+const STYLE: &str = "body{font-family:system-ui,sans-serif;line-height:1.6;max-width:46rem;margin:2rem auto;padding:0 1rem;color:#222}\
+blockquote.block-ref{border-left:3px solid #ccc;margin:.5rem 0;padding:.25rem .75rem;color:#444}\
+blockquote.block-ref footer{font-size:.85em;color:#777}\
+.missing{color:#a33}ul.checklist{list-style:none;padding-left:1.2rem}\
+[data-timestamp]{cursor:pointer}[data-timestamp]:hover{background:#f4f4f4}\
+pre{background:#f6f6f6;padding:.75rem;overflow-x:auto}";
+
+// Clicking a block with a timestamp plays its recording from there
+const SEEK_SCRIPT: &str = "document.addEventListener('click',function(e){\
+var block=e.target.closest('[data-timestamp]');if(!block)return;\
+var audio=document.getElementById('recording-'+block.dataset.recording);if(!audio)return;\
+audio.currentTime=Number(block.dataset.timestamp)/1000;audio.play();});";
+
+fn render_page_html(title: &str, content: &Value, context: &RenderContext) -> String {
+    let root = content.get("root").unwrap_or(content);
+    let mut ancestors = Vec::new();
+    let body = render_nodes(children(root), context, &mut ancestors);
+
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n", escape_html(title), STYLE));
+    html.push_str(&format!("<article>\n<h1 class=\"page-title\">{}</h1>\n{}</article>\n", escape_html(title), body));
+    if !context.audio_sources.is_empty() {
+        html.push_str("<section class=\"recordings\">\n");
+        for (recording_id, path) in &context.audio_sources {
+            html.push_str(&format!(
+                "<audio id=\"recording-{}\" src=\"{}\" controls preload=\"none\"></audio>\n",
+                recording_id,
+                escape_html(&url_path(path))
+            ));
+        }
+        html.push_str(&format!("</section>\n<script>{}</script>\n", SEEK_SCRIPT));
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn children(node: &Value) -> &[Value] {
+    node.get("children").and_then(|v| v.as_array()).map(Vec::as_slice).unwrap_or_default()
+}
+
+fn node_type(node: &Value) -> &str {
+    node.get("type").and_then(|v| v.as_str()).unwrap_or_default()
+}
+
+// Block-level nodes, one element per line. ancestors holds the referenced blocks being inlined,
+// so a block that references itself through others isn't expanded forever.
+fn render_nodes(nodes: &[Value], context: &RenderContext, ancestors: &mut Vec<Uuid>) -> String {
+    nodes.iter().map(|node| render_block(node, context, ancestors)).collect()
+}
+
+fn render_block(node: &Value, context: &RenderContext, ancestors: &mut Vec<Uuid>) -> String {
+    let attributes = block_attributes(node, context, ancestors);
+    match node_type(node) {
+        "heading" => {
+            let level = node
+                .get("tag")
+                .and_then(|v| v.as_str())
+                .and_then(|tag| tag.strip_prefix('h'))
+                .and_then(|n| n.parse::<u8>().ok())
+                .filter(|level| (1..=6).contains(level))
+                .unwrap_or(1);
+            let (inline, _) = render_inline(children(node), context, ancestors);
+            format!("<h{0}{1}>{2}</h{0}>\n", level, attributes, inline)
+        }
+        "quote" => {
+            let (inline, _) = render_inline(children(node), context, ancestors);
+            format!("<blockquote{}>{}</blockquote>\n", attributes, inline)
+        }
+        "code" => {
+            let language = node.get("language").and_then(|v| v.as_str()).unwrap_or_default();
+            let class = if language.is_empty() {
+                String::new()
+            } else {
+                format!(" class=\"language-{}\"", escape_html(language))
+            };
+            format!("<pre{}><code{}>{}</code></pre>\n", attributes, class, escape_html(&plain_text(children(node))))
+        }
+        "horizontalrule" => "<hr>\n".to_string(),
+        "list" => render_list(node, &attributes, context, ancestors),
+        // A list item on its own (an inlined block) renders as a one-item list
+        "listitem" => render_list(&serde_json::json!({ "type": "list", "children": [node] }), "", context, ancestors),
+        _ => {
+            // Inlined block references are blockquotes, which can't go inside a <p>
+            let (inline, has_blocks) = render_inline(children(node), context, ancestors);
+            let tag = if has_blocks { "div" } else { "p" };
+            format!("<{0}{1}>{2}</{0}>\n", tag, attributes, inline)
+        }
+    }
+}
+
+fn render_list(list: &Value, attributes: &str, context: &RenderContext, ancestors: &mut Vec<Uuid>) -> String {
+    let list_type = list.get("listType").and_then(|v| v.as_str()).unwrap_or("bullet");
+    let (tag, extra) = match list_type {
+        "number" => {
+            let start = list.get("start").and_then(|v| v.as_u64()).unwrap_or(1);
+            ("ol", if start == 1 { String::new() } else { format!(" start=\"{}\"", start) })
+        }
+        "check" => ("ul", " class=\"checklist\"".to_string()),
+        _ => ("ul", String::new()),
+    };
+
+    // Lexical keeps a nested list in a list item of its own after the item it belongs to, so
+    // it's moved into that item here
+    let mut items: Vec<String> = Vec::new();
+    for item in children(list) {
+        let item_children = children(item);
+        let nested_lists: Vec<&Value> = item_children.iter().filter(|child| node_type(child) == "list").collect();
+        if !nested_lists.is_empty() && nested_lists.len() == item_children.len() {
+            let nested: String = nested_lists
+                .into_iter()
+                .map(|nested| render_list(nested, "", context, ancestors))
+                .collect();
+            match items.last_mut() {
+                Some(last) => last.push_str(&nested),
+                None => items.push(format!("<li>{}", nested)),
+            }
+            continue;
+        }
+
+        let checkbox = match item.get("checked").and_then(|v| v.as_bool()) {
+            Some(checked) if list_type == "check" => {
+                format!("<input type=\"checkbox\" disabled{}> ", if checked { " checked" } else { "" })
+            }
+            _ => String::new(),
+        };
+        let (inline, _) = render_inline(item_children, context, ancestors);
+        items.push(format!("<li{}>{}{}", block_attributes(item, context, ancestors), checkbox, inline));
+    }
+
+    let mut html = format!("<{}{}{}>\n", tag, attributes, extra);
+    for item in items {
+        html.push_str(&item);
+        html.push_str("</li>\n");
+    }
+    html.push_str(&format!("</{}>\n", tag));
+    html
+}
+
+// id and audio timestamp attributes for a node that is a block. Inlined blocks get none, so
+// IDs stay unique when a page quotes its own blocks.
+fn block_attributes(node: &Value, context: &RenderContext, ancestors: &[Uuid]) -> String {
+    if !ancestors.is_empty() {
+        return String::new();
+    }
+    let Some(block_id) = node.get("uniqueID").and_then(|v| v.as_str()).and_then(|id| Uuid::parse_str(id).ok()) else {
+        return String::new();
+    };
+    let mut attributes = format!(" id=\"block-{}\"", block_id);
+    if let Some((recording_id, timestamp_ms)) = context.timestamps.get(&block_id) {
+        attributes.push_str(&format!(" data-recording=\"{}\" data-timestamp=\"{}\"", recording_id, timestamp_ms));
+    }
+    attributes
+}
+
+// Inline nodes as HTML, and whether that includes block-level elements (inlined references)
+fn render_inline(nodes: &[Value], context: &RenderContext, ancestors: &mut Vec<Uuid>) -> (String, bool) {
+    let mut html = String::new();
+    let mut has_blocks = false;
+    for node in nodes {
+        match node_type(node) {
+            "text" | "code-highlight" => {
+                let text = node.get("text").and_then(|v| v.as_str()).unwrap_or_default();
+                let format = node.get("format").and_then(|v| v.as_u64()).unwrap_or(0);
+                let (inner, blocks) = render_text(text, context, ancestors);
+                has_blocks |= blocks;
+                html.push_str(&apply_marks(&inner, format));
+            }
+            "linebreak" => html.push_str("<br>"),
+            "tab" => html.push('\t'),
+            "link" | "autolink" => {
+                let url = node.get("url").and_then(|v| v.as_str()).unwrap_or_default();
+                let (inner, blocks) = render_inline(children(node), context, ancestors);
+                has_blocks |= blocks;
+                if is_safe_url(url) {
+                    html.push_str(&format!("<a href=\"{}\">{}</a>", escape_html(url), inner));
+                } else {
+                    html.push_str(&inner);
+                }
+            }
+            "list" => {} // Nested lists are rendered by render_list
+            _ => {
+                let (inner, blocks) = render_inline(children(node), context, ancestors);
+                has_blocks |= blocks;
+                html.push_str(&inner);
+            }
+        }
+    }
+    (html, has_blocks)
+}
+
+// Lexical's text format bits, innermost mark last
+fn apply_marks(html: &str, format: u64) -> String {
+    const MARKS: &[(u64, &str)] = &[(1, "strong"), (2, "em"), (4, "s"), (8, "u"), (32, "sub"), (64, "sup"), (16, "code")];
+    let open: String = MARKS.iter().filter(|(bit, _)| format & bit != 0).map(|(_, tag)| format!("<{}>", tag)).collect();
+    let close: String = MARKS.iter().rev().filter(|(bit, _)| format & bit != 0).map(|(_, tag)| format!("</{}>", tag)).collect();
+    format!("{}{}{}", open, html, close)
+}
+
+// Text with its [[links]] and (((block references))) resolved
+fn render_text(text: &str, context: &RenderContext, ancestors: &mut Vec<Uuid>) -> (String, bool) {
+    let mut html = String::new();
+    let mut has_blocks = false;
+    let mut last = 0;
+    for cap in INLINE_REFERENCE_REGEX.captures_iter(text) {
+        let whole = cap.get(0).expect("group 0 always matches");
+        html.push_str(&escape_html(&text[last..whole.start()]));
+        last = whole.end();
+
+        if let Some(link) = cap.get(1) {
+            html.push_str(&render_page_link(link.as_str().trim(), context));
+        } else if let Some(reference) = cap.get(2) {
+            match Uuid::parse_str(reference.as_str().trim()) {
+                Ok(block_id) => {
+                    let (reference_html, is_block) = render_block_reference(block_id, context, ancestors);
+                    has_blocks |= is_block;
+                    html.push_str(&reference_html);
+                }
+                Err(_) => html.push_str(&escape_html(whole.as_str())),
+            }
+        }
+    }
+    html.push_str(&escape_html(&text[last..]));
+    (html, has_blocks)
+}
+
+// A link to the page's exported file, or its title as plain text when it wasn't exported
+fn render_page_link(target: &str, context: &RenderContext) -> String {
+    let (target, label) = target.split_once('|').map(|(target, label)| (target.trim(), label.trim())).unwrap_or((target, target));
+    match context.page_files.get(target) {
+        Some(file_name) => format!(
+            "<a class=\"page-link\" href=\"{}\">{}</a>",
+            escape_html(&url_path(file_name)),
+            escape_html(label)
+        ),
+        None => escape_html(label),
+    }
+}
+
+// The referenced block as a blockquote naming its page. Returns whether a blockquote was made.
+fn render_block_reference(block_id: Uuid, context: &RenderContext, ancestors: &mut Vec<Uuid>) -> (String, bool) {
+    let Some(block) = context.references.get(&block_id) else {
+        return (format!("<span class=\"block-ref missing\">((({})))</span>", block_id), false);
+    };
+    let source = match context.page_files.get(&block.page_id.to_string()) {
+        Some(file_name) => format!(
+            "<a href=\"{}#block-{}\">{}</a>",
+            escape_html(&url_path(file_name)),
+            block_id,
+            escape_html(&block.page_title)
+        ),
+        None => escape_html(&block.page_title),
+    };
+    if ancestors.contains(&block_id) {
+        return (format!("<span class=\"block-ref cycle\">↻ {}</span>", source), false);
+    }
+
+    ancestors.push(block_id);
+    let content = if block.nodes.is_empty() {
+        format!("<p>{}</p>\n", escape_html(block.text.as_deref().unwrap_or_default()))
+    } else {
+        render_nodes(&block.nodes, context, ancestors)
+    };
+    ancestors.pop();
+    (format!("<blockquote class=\"block-ref\">\n{}<footer>{}</footer>\n</blockquote>", content, source), true)
+}
+
+fn plain_text(nodes: &[Value]) -> String {
+    nodes
+        .iter()
+        .map(|node| match node_type(node) {
+            "linebreak" => "\n".to_string(),
+            "tab" => "\t".to_string(),
+            _ => node
+                .get("text")
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+                .unwrap_or_else(|| plain_text(children(node))),
+        })
+        .collect()
+}
+
+// Links other than these (javascript: and the like) are rendered as their text only
+fn is_safe_url(url: &str) -> bool {
+    let lower = url.trim().to_ascii_lowercase();
+    ["http://", "https://", "mailto:", "#"].iter().any(|prefix| lower.starts_with(prefix))
+        || !lower.contains(':')
+}
+
+// A relative path as a URL: each segment percent-encoded where needed
+fn url_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| {
+            segment
+                .bytes()
+                .map(|byte| match byte {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+                    _ => format!("%{:02X}", byte),
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn text(text: &str, format: u64) -> Value {
+        json!({"type": "text", "text": text, "format": format})
+    }
+
+    fn item(children: Vec<Value>) -> Value {
+        json!({"type": "listitem", "children": children})
+    }
+
+    fn list(list_type: &str, items: Vec<Value>) -> Value {
+        json!({"type": "list", "listType": list_type, "children": items})
+    }
+
+    fn render(nodes: Vec<Value>) -> String {
+        render_nodes(&nodes, &RenderContext::default(), &mut Vec::new())
+    }
+
+    #[test]
+    fn nested_lists_render_inside_the_item_they_follow() {
+        // Lexical keeps each nested list in an item of its own after its parent item
+        let nested = list(
+            "bullet",
+            vec![
+                item(vec![text("Apples", 0)]),
+                item(vec![list("number", vec![item(vec![text("Green", 0)]), item(vec![text("Red", 0)])])]),
+                item(vec![text("Pears", 0)]),
+                item(vec![list("bullet", vec![item(vec![text("Ripe", 0)])])]),
+            ],
+        );
+        assert_eq!(
+            render(vec![nested]),
+            "<ul>\n\
+             <li>Apples<ol>\n<li>Green</li>\n<li>Red</li>\n</ol>\n</li>\n\
+             <li>Pears<ul>\n<li>Ripe</li>\n</ul>\n</li>\n\
+             </ul>\n"
+        );
+
+        // A nested list with no item before it gets an empty one
+        let orphan = list("bullet", vec![item(vec![list("bullet", vec![item(vec![text("Alone", 0)])])])]);
+        assert_eq!(render(vec![orphan]), "<ul>\n<li><ul>\n<li>Alone</li>\n</ul>\n</li>\n</ul>\n");
+    }
+
+    #[test]
+    fn text_formats_render_as_nested_marks() {
+        let paragraph = json!({"type": "paragraph", "children": [
+            text("bold", 1),
+            text(" italic", 2),
+            text(" both", 3),
+            text(" a<b", 16),
+            text(" bold code", 17),
+            text(" plain", 0),
+        ]});
+        assert_eq!(
+            render(vec![paragraph]),
+            "<p><strong>bold</strong><em> italic</em><strong><em> both</em></strong><code> a&lt;b</code>\
+             <strong><code> bold code</code></strong> plain</p>\n"
+        );
+    }
+
+    #[test]
+    fn marks_apply_inside_list_items() {
+        let marked = list("check", vec![json!({"type": "listitem", "checked": true, "children": [text("Done", 1)]})]);
+        assert_eq!(
+            render(vec![marked]),
+            "<ul class=\"checklist\">\n\
+             <li><input type=\"checkbox\" disabled checked> <strong>Done</strong></li>\n\
+             </ul>\n"
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use sqlx::PgPool;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// Import the shared DalError
+use crate::dal_error::DalError;
+
+// IDs for uids from another app (e.g. Roam block uids), keyed by uid. Uids seen for the first
+// time get a fresh ID, so the same uid maps to the same page or block on every import.
+pub async fn map_import_uids(pool: &PgPool, source: &str, uids: &[String]) -> Result<HashMap<String, Uuid>, DalError> {
+    let fresh_ids: Vec<Uuid> = uids.iter().map(|_| Uuid::new_v4()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO imported_uids (source, uid, id)
+        SELECT $1, o.uid, o.id
+        FROM unnest($2::text[], $3::uuid[]) AS o(uid, id)
+        ON CONFLICT (source, uid) DO NOTHING
+        "#,
+        source,
+        uids,
+        &fresh_ids
+    )
+    .execute(pool)
+    .await?;
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT uid, id
+        FROM imported_uids
+        WHERE source = $1 AND uid = ANY($2)
+        "#,
+        source,
+        uids
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| (row.uid, row.id)).collect())
+}
+
+// Gives uids new IDs, for blocks whose old ID is taken by a block on another page (one that
+// was moved between pages since the last import). Returns the new IDs keyed by uid.
+pub async fn remap_import_uids(pool: &PgPool, source: &str, uids: &[String]) -> Result<HashMap<String, Uuid>, DalError> {
+    let fresh_ids: Vec<Uuid> = uids.iter().map(|_| Uuid::new_v4()).collect();
+    sqlx::query!(
+        r#"
+        UPDATE imported_uids i
+        SET id = o.id, page_id = NULL
+        FROM unnest($2::text[], $3::uuid[]) AS o(uid, id)
+        WHERE i.source = $1 AND i.uid = o.uid
+        "#,
+        source,
+        uids,
+        &fresh_ids
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(uids.iter().cloned().zip(fresh_ids).collect())
+}
+
+// The live page imported for this key (a page uid, or a title for pages without one)
+pub async fn get_imported_page(pool: &PgPool, source: &str, key: &str) -> Result<Option<Uuid>, DalError> {
+    let page_id = sqlx::query_scalar!(
+        r#"
+        SELECT p.id
+        FROM imported_uids i
+        JOIN pages p ON p.id = i.page_id
+        WHERE i.source = $1 AND i.uid = $2 AND p.deleted_at IS NULL
+        "#,
+        source,
+        key
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(page_id)
+}
+
+// Records that the page for this key is page_id, replacing any earlier (e.g. trashed) page
+pub async fn set_imported_page(pool: &PgPool, source: &str, key: &str, page_id: Uuid) -> Result<(), DalError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO imported_uids (source, uid, id, page_id)
+        VALUES ($1, $2, $3, $3)
+        ON CONFLICT (source, uid) DO UPDATE SET id = EXCLUDED.id, page_id = EXCLUDED.page_id
+        "#,
+        source,
+        key,
+        page_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Records which page the blocks with these uids were written to
+pub async fn set_imported_block_page(pool: &PgPool, source: &str, uids: &[String], page_id: Uuid) -> Result<(), DalError> {
+    sqlx::query!(
+        r#"
+        UPDATE imported_uids
+        SET page_id = $3
+        WHERE source = $1 AND uid = ANY($2) AND page_id IS DISTINCT FROM $3
+        "#,
+        source,
+        uids,
+        page_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Which of these block IDs belong to a page other than page_id
+pub async fn blocks_on_other_pages(pool: &PgPool, page_id: Uuid, ids: &[Uuid]) -> Result<Vec<Uuid>, DalError> {
+    let taken = sqlx::query_scalar!(
+        r#"
+        SELECT id
+        FROM blocks
+        WHERE id = ANY($2) AND page_id <> $1
+        "#,
+        page_id,
+        ids
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(taken)
+}
+
+// Which of these block IDs have no block
+pub async fn missing_block_ids(pool: &PgPool, ids: &[Uuid]) -> Result<Vec<Uuid>, DalError> {
+    let missing = sqlx::query_scalar!(
+        r#"
+        SELECT o.id AS "id!"
+        FROM unnest($1::uuid[]) AS o(id)
+        WHERE NOT EXISTS (SELECT 1 FROM blocks b WHERE b.id = o.id)
+        "#,
+        ids
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(missing)
+}
+
+// Pages with a block reference to any of these blocks
+pub async fn pages_referencing_blocks(pool: &PgPool, ids: &[Uuid]) -> Result<Vec<Uuid>, DalError> {
+    let pages = sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT referencing_page_id
+        FROM block_references
+        WHERE referenced_block_id = ANY($1)
+        "#,
+        ids
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(pages)
+}
@@ -0,0 +1,234 @@
+// Runs long operations (imports, vault-wide repairs) in the background as jobs the frontend can
+// follow and cancel. Starting a job returns its ID right away; the job then reports progress as
+// job://progress events and through get_job_status/list_jobs. Cancelling trips a token the job
+// checks between batches. Each batch commits on its own, so a cancelled or crashed job keeps
+// what it finished and leaves nothing half-written. Job state is kept in memory only and is
+// gone after a restart.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use tauri::{AppHandle, Emitter};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+pub const EVENT_JOB_PROGRESS: &str = "job://progress";
+
+// Finished jobs beyond this many are forgotten, oldest first
+const MAX_FINISHED_JOBS: usize = 50;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    ImportVault,
+    NormalizeAllPages,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Running,
+    Completed,
+    Cancelled, // Stopped at a batch boundary; result holds the summary of the batches done
+    Failed,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct JobStatus {
+    pub id: Uuid,
+    pub kind: JobKind,
+    pub state: JobState,
+    pub done: u64,
+    pub total: u64,
+    pub message: Option<String>, // What the job is working on, e.g. the file being imported
+    pub error: Option<String>,   // Only for a failed job
+    pub result: Option<Value>,   // The job's summary, once it has stopped without failing
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+// Emitted on every progress report and once more when the job stops
+#[derive(Serialize, Debug, Clone)]
+pub struct JobProgressEvent {
+    pub job_id: Uuid,
+    pub kind: JobKind,
+    pub state: JobState,
+    pub done: u64,
+    pub total: u64,
+    pub message: Option<String>,
+}
+
+impl From<&JobStatus> for JobProgressEvent {
+    fn from(status: &JobStatus) -> Self {
+        JobProgressEvent {
+            job_id: status.id,
+            kind: status.kind,
+            state: status.state,
+            done: status.done,
+            total: status.total,
+            message: status.message.clone(),
+        }
+    }
+}
+
+struct JobEntry {
+    status: JobStatus,
+    token: CancellationToken,
+}
+
+// Every job started since launch. Cheap to clone; clones share the same jobs.
+#[derive(Clone, Default)]
+pub struct JobRegistry {
+    jobs: Arc<Mutex<HashMap<Uuid, JobEntry>>>,
+}
+
+// Handed to a running job to report progress and check for cancellation
+#[derive(Clone)]
+pub struct JobContext {
+    id: Uuid,
+    app_handle: AppHandle,
+    registry: JobRegistry,
+    token: CancellationToken,
+}
+
+impl JobContext {
+    // Checked before each batch; once true the job should return what it has done so far
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    pub fn progress(&self, done: usize, total: usize, message: impl Into<String>) {
+        let event = self.registry.update(self.id, |status| {
+            status.done = done as u64;
+            status.total = total as u64;
+            status.message = Some(message.into());
+        });
+        if let Some(event) = event {
+            emit_progress(&self.app_handle, event);
+        }
+    }
+}
+
+impl JobRegistry {
+    // Spawns run and returns the new job's ID. The job's Ok value becomes its result; an Err or
+    // a panic marks it failed.
+    pub fn start<F, Fut, T>(&self, app_handle: &AppHandle, kind: JobKind, run: F) -> Uuid
+    where
+        F: FnOnce(JobContext) -> Fut,
+        Fut: Future<Output = Result<T, String>> + Send + 'static,
+        T: Serialize + Send + 'static,
+    {
+        let id = Uuid::new_v4();
+        let token = CancellationToken::new();
+        let status = JobStatus {
+            id,
+            kind,
+            state: JobState::Running,
+            done: 0,
+            total: 0,
+            message: None,
+            error: None,
+            result: None,
+            started_at: Utc::now(),
+            finished_at: None,
+        };
+        self.lock().insert(id, JobEntry { status, token: token.clone() });
+
+        let context = JobContext {
+            id,
+            app_handle: app_handle.clone(),
+            registry: self.clone(),
+            token: token.clone(),
+        };
+        // Run on its own task so a panic in the job is reported here instead of lost
+        let job = tauri::async_runtime::spawn(run(context));
+        let (registry, app_handle) = (self.clone(), app_handle.clone());
+        tauri::async_runtime::spawn(async move {
+            let (state, result, error) = match job.await {
+                Ok(Ok(value)) => {
+                    let state = if token.is_cancelled() { JobState::Cancelled } else { JobState::Completed };
+                    match serde_json::to_value(value) {
+                        Ok(value) => (state, Some(value), None),
+                        Err(e) => (JobState::Failed, None, Some(format!("Failed to serialize job result: {}", e))),
+                    }
+                }
+                Ok(Err(e)) => (JobState::Failed, None, Some(e)),
+                Err(e) => (JobState::Failed, None, Some(format!("Job stopped unexpectedly: {}", e))),
+            };
+            if let Some(error) = &error {
+                eprintln!("[Jobs] Job {} ({:?}) failed: {}", id, kind, error);
+            }
+            let event = registry.update(id, |status| {
+                status.state = state;
+                status.result = result;
+                status.error = error;
+                status.finished_at = Some(Utc::now());
+            });
+            if let Some(event) = event {
+                emit_progress(&app_handle, event);
+            }
+            registry.forget_old_jobs();
+        });
+        id
+    }
+
+    // Asks a running job to stop after its current batch. Returns None for an unknown job; a
+    // job that has already stopped is returned unchanged.
+    pub fn cancel(&self, id: Uuid) -> Option<JobStatus> {
+        let jobs = self.lock();
+        let entry = jobs.get(&id)?;
+        entry.token.cancel();
+        Some(entry.status.clone())
+    }
+
+    // Whether any job hasn't stopped yet
+    pub fn has_running(&self) -> bool {
+        self.lock().values().any(|entry| entry.status.state == JobState::Running)
+    }
+
+    pub fn status(&self, id: Uuid) -> Option<JobStatus> {
+        self.lock().get(&id).map(|entry| entry.status.clone())
+    }
+
+    // Newest first
+    pub fn list(&self) -> Vec<JobStatus> {
+        let mut statuses: Vec<JobStatus> = self.lock().values().map(|entry| entry.status.clone()).collect();
+        statuses.sort_by_key(|status| std::cmp::Reverse(status.started_at));
+        statuses
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<Uuid, JobEntry>> {
+        self.jobs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn update(&self, id: Uuid, change: impl FnOnce(&mut JobStatus)) -> Option<JobProgressEvent> {
+        let mut jobs = self.lock();
+        let entry = jobs.get_mut(&id)?;
+        change(&mut entry.status);
+        Some(JobProgressEvent::from(&entry.status))
+    }
+
+    fn forget_old_jobs(&self) {
+        let mut jobs = self.lock();
+        let mut finished: Vec<(DateTime<Utc>, Uuid)> = jobs
+            .values()
+            .filter_map(|entry| entry.status.finished_at.map(|finished_at| (finished_at, entry.status.id)))
+            .collect();
+        if finished.len() <= MAX_FINISHED_JOBS {
+            return;
+        }
+        finished.sort();
+        for (_, id) in &finished[..finished.len() - MAX_FINISHED_JOBS] {
+            jobs.remove(id);
+        }
+    }
+}
+
+fn emit_progress(app_handle: &AppHandle, event: JobProgressEvent) {
+    if let Err(e) = app_handle.emit(EVENT_JOB_PROGRESS, event) {
+        eprintln!("[Jobs] Failed to emit progress event: {}", e);
+    }
+}
@@ -0,0 +1,504 @@
+// Helpers for walking the Lexical editor state stored as a page's content_json
+
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+// Node types that are blocks, and so should carry a uniqueID to get a block row
+pub const BLOCK_NODE_TYPES: &[&str] = &["paragraph", "heading", "listitem", "quote", "code", "todo"];
+
+// The editor state's root node, or the value itself for content saved without one
+pub fn content_root(content_json: &Value) -> &Value {
+    content_json.get("root").unwrap_or(content_json)
+}
+
+// Compact JSON with every object's keys in sorted order, so equal content always serializes
+// the same whichever order its keys arrived in
+pub fn canonical_json(value: &Value) -> String {
+    fn write(value: &Value, out: &mut String) {
+        match value {
+            Value::Object(obj) => {
+                let mut entries: Vec<(&String, &Value)> = obj.iter().collect();
+                entries.sort_by(|a, b| a.0.cmp(b.0));
+                out.push('{');
+                for (index, (key, value)) in entries.into_iter().enumerate() {
+                    if index > 0 {
+                        out.push(',');
+                    }
+                    out.push_str(&Value::String(key.clone()).to_string());
+                    out.push(':');
+                    write(value, out);
+                }
+                out.push('}');
+            }
+            Value::Array(items) => {
+                out.push('[');
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        out.push(',');
+                    }
+                    write(item, out);
+                }
+                out.push(']');
+            }
+            _ => out.push_str(&value.to_string()),
+        }
+    }
+
+    let mut out = String::new();
+    write(value, &mut out);
+    out
+}
+
+// SHA-256 (hex) of content_json's canonical serialization, as stored in pages.content_hash
+pub fn content_json_hash(content_json: &Value) -> String {
+    let digest = Sha256::digest(canonical_json(content_json).as_bytes());
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn node_type(node: &Value) -> Option<&str> {
+    node.get("type").and_then(|v| v.as_str())
+}
+
+// The node's uniqueID, if it has one that is a UUID
+pub fn unique_id(node: &Value) -> Option<Uuid> {
+    node.get("uniqueID").and_then(|v| v.as_str()).and_then(|s| Uuid::parse_str(s).ok())
+}
+
+pub fn is_list_item(node: &Value) -> bool {
+    node_type(node) == Some("listitem")
+}
+
+// A list item that only wraps lists, which is how Lexical nests a list under the item before it
+pub fn is_nested_list_holder(node: &Value) -> bool {
+    is_list_item(node)
+        && node.get("children").and_then(|v| v.as_array()).is_some_and(|children| {
+            !children.is_empty() && children.iter().all(|child| node_type(child) == Some("list"))
+        })
+}
+
+// Nested list holders aren't blocks themselves; their lists' items are
+pub fn is_block_node(node: &Value) -> bool {
+    node_type(node).is_some_and(|t| BLOCK_NODE_TYPES.contains(&t)) && !is_nested_list_holder(node)
+}
+
+// Object keys and array indices leading to the node with the given uniqueID
+pub fn find_block_path(node: &Value, block_id: &str) -> Option<Vec<String>> {
+    match node {
+        Value::Object(obj) => {
+            if obj.get("uniqueID").and_then(|v| v.as_str()) == Some(block_id) {
+                return Some(Vec::new());
+            }
+            obj.iter().find_map(|(key, value)| {
+                let mut path = find_block_path(value, block_id)?;
+                path.insert(0, key.clone());
+                Some(path)
+            })
+        }
+        Value::Array(items) => items.iter().enumerate().find_map(|(index, item)| {
+            let mut path = find_block_path(item, block_id)?;
+            path.insert(0, index.to_string());
+            Some(path)
+        }),
+        _ => None,
+    }
+}
+
+// A JSON pointer (RFC 6901) for a path from find_block_path
+pub fn json_pointer(path: &[String]) -> String {
+    path.iter()
+        .map(|step| format!("/{}", step.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+// The block node with the given uniqueID
+pub fn find_block_node(content_json: &Value, block_id: Uuid) -> Option<&Value> {
+    let path = find_block_path(content_json, &block_id.to_string())?;
+    content_json.pointer(&json_pointer(&path))
+}
+
+// Every UUID uniqueID in the node and the nodes inside it, in document order
+pub fn collect_unique_ids(node: &Value) -> Vec<Uuid> {
+    fn walk(node: &Value, ids: &mut Vec<Uuid>) {
+        match node {
+            Value::Object(obj) => {
+                ids.extend(unique_id(node));
+                obj.values().for_each(|value| walk(value, ids));
+            }
+            Value::Array(items) => items.iter().for_each(|item| walk(item, ids)),
+            _ => {}
+        }
+    }
+
+    let mut ids = Vec::new();
+    walk(node, &mut ids);
+    ids
+}
+
+// Checks that node is a single block, as sent for a block edit: a block node whose uniqueID,
+// if it has one, is a UUID, with no blocks (or other uniqueIDs) inside it
+pub fn check_single_block_node(node: &Value) -> Result<(), String> {
+    if !node.is_object() || !is_block_node(node) {
+        return Err(format!("The node must be a block, one of: {}", BLOCK_NODE_TYPES.join(", ")));
+    }
+    if node.get("uniqueID").is_some_and(|v| !v.is_null()) && unique_id(node).is_none() {
+        return Err("The node's uniqueID must be a UUID".to_string());
+    }
+    fn contains_block(value: &Value) -> bool {
+        match value {
+            Value::Object(obj) => {
+                is_block_node(value) || node_type(value) == Some("list") || obj.values().any(contains_block)
+            }
+            Value::Array(items) => items.iter().any(contains_block),
+            _ => false,
+        }
+    }
+    let children = node.get("children").unwrap_or(&Value::Null);
+    if contains_block(children) || !collect_unique_ids(children).is_empty() {
+        return Err("The node must not contain other blocks; use update_page_content for structural edits".to_string());
+    }
+    Ok(())
+}
+
+// Replaces the node with uniqueID block_id by node and returns the node it replaced, or None if
+// there is no such node. A list item's nested list sits in the list item after it, so it stays.
+pub fn replace_block_node(content_json: &mut Value, block_id: Uuid, node: Value) -> Option<Value> {
+    let path = find_block_path(content_json, &block_id.to_string())?;
+    let slot = content_json.pointer_mut(&json_pointer(&path))?;
+    Some(std::mem::replace(slot, node))
+}
+
+// Inserts node right after the node with uniqueID anchor_id, among its siblings. After a list
+// item holding a nested list in the next list item, node goes after that one, so the nested
+// list stays with its item. Returns false if there is no such node.
+pub fn insert_block_after(content_json: &mut Value, anchor_id: Uuid, node: Value) -> bool {
+    insert_blocks_next_to(content_json, anchor_id, vec![node], true)
+}
+
+// Inserts nodes, in order, among the siblings of the node with uniqueID anchor_id: right before
+// it, or right after it as insert_block_after does. Returns false if there is no such node.
+pub fn insert_blocks_next_to(content_json: &mut Value, anchor_id: Uuid, nodes: Vec<Value>, after: bool) -> bool {
+    let Some(path) = find_block_path(content_json, &anchor_id.to_string()) else {
+        return false;
+    };
+    let Some((index, parent_path)) = path.split_last() else {
+        return false; // The anchor is the content's top-level value
+    };
+    let Ok(index) = index.parse::<usize>() else {
+        return false;
+    };
+    let Some(Value::Array(siblings)) = content_json.pointer_mut(&json_pointer(parent_path)) else {
+        return false;
+    };
+    let mut position = if after { index + 1 } else { index };
+    if after && is_list_item(&siblings[index]) && siblings.get(position).is_some_and(is_nested_list_holder) {
+        position += 1;
+    }
+    siblings.splice(position..position, nodes);
+    true
+}
+
+// Appends list items to the end of the nested list of the list item parent_id, which Lexical
+// keeps in the list item after it. A list item without one gets one, of its own list's type.
+// Returns false if there is no such list item.
+pub fn append_nested_items(content_json: &mut Value, parent_id: Uuid, items: Vec<Value>) -> bool {
+    let Some(path) = find_block_path(content_json, &parent_id.to_string()) else {
+        return false;
+    };
+    let Some((index, parent_path)) = path.split_last() else {
+        return false;
+    };
+    let Ok(index) = index.parse::<usize>() else {
+        return false;
+    };
+    let list_type = parent_path
+        .split_last()
+        .and_then(|(_, list_path)| content_json.pointer(&json_pointer(list_path)))
+        .and_then(|list| list.get("listType"))
+        .and_then(|v| v.as_str())
+        .unwrap_or("bullet")
+        .to_string();
+    let Some(Value::Array(siblings)) = content_json.pointer_mut(&json_pointer(parent_path)) else {
+        return false;
+    };
+    if !is_list_item(&siblings[index]) {
+        return false;
+    }
+
+    let nested_list = siblings
+        .get_mut(index + 1)
+        .filter(|next| is_nested_list_holder(next))
+        .and_then(|holder| holder.get_mut("children"))
+        .and_then(|lists| lists.as_array_mut())
+        .and_then(|lists| lists.last_mut())
+        .and_then(|list| list.get_mut("children"))
+        .and_then(|items| items.as_array_mut());
+    match nested_list {
+        Some(nested_items) => nested_items.extend(items),
+        None => {
+            let holder = serde_json::json!({
+                "children": [list_node(&list_type, 1, items)],
+                "direction": "ltr",
+                "format": "",
+                "indent": 0,
+                "type": "listitem",
+                "version": 1
+            });
+            siblings.insert(index + 1, holder);
+        }
+    }
+    true
+}
+
+// A list node as the editor saves one, with its items numbered from start
+pub fn list_node(list_type: &str, start: u64, mut items: Vec<Value>) -> Value {
+    for (index, item) in items.iter_mut().enumerate() {
+        item["value"] = Value::from(start + index as u64);
+    }
+    serde_json::json!({
+        "children": items,
+        "direction": "ltr",
+        "format": "",
+        "indent": 0,
+        "listType": list_type,
+        "start": start,
+        "tag": if list_type == "number" { "ol" } else { "ul" },
+        "type": "list",
+        "version": 1
+    })
+}
+
+// Removes the node with uniqueID block_id and returns what was removed: the node, then for a
+// list item the list item holding its nested list, if it has one. A list left empty is removed
+// too, with the list item holding it when it was a nested list. Returns None if there is no
+// such node.
+pub fn remove_block_node(content_json: &mut Value, block_id: Uuid) -> Option<Vec<Value>> {
+    let path = find_block_path(content_json, &block_id.to_string())?;
+    let (index, parent_path) = path.split_last()?;
+    let index: usize = index.parse().ok()?;
+    let Some(Value::Array(siblings)) = content_json.pointer_mut(&json_pointer(parent_path)) else {
+        return None;
+    };
+    let mut removed = vec![siblings.remove(index)];
+    if is_list_item(&removed[0]) && siblings.get(index).is_some_and(is_nested_list_holder) {
+        removed.push(siblings.remove(index));
+    }
+
+    // parent_path ends in the "children" of the node the block was in. While that node is a list
+    // left empty, or a list item that held only that list, remove it from its own siblings.
+    let mut children_path = parent_path.to_vec();
+    while children_path.len() >= 3 {
+        let owner_path = &children_path[..children_path.len() - 1];
+        let Some(owner) = content_json.pointer(&json_pointer(owner_path)) else {
+            break;
+        };
+        let emptied = owner.get("children").and_then(|v| v.as_array()).is_some_and(Vec::is_empty);
+        if !emptied || !matches!(node_type(owner), Some("list") | Some("listitem")) || unique_id(owner).is_some() {
+            break;
+        }
+        let (owner_index, owner_siblings_path) = owner_path.split_last()?;
+        let owner_index: usize = owner_index.parse().ok()?;
+        if let Some(Value::Array(owner_siblings)) = content_json.pointer_mut(&json_pointer(owner_siblings_path)) {
+            owner_siblings.remove(owner_index);
+        }
+        children_path = owner_siblings_path.to_vec();
+    }
+    Some(removed)
+}
+
+#[derive(serde::Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UniqueIdRepair {
+    pub ids_added: usize, // Block nodes that had no uniqueID
+    pub ids_fixed: usize, // uniqueIDs that weren't a UUID or repeated an earlier node's
+}
+
+impl UniqueIdRepair {
+    pub fn changed(&self) -> bool {
+        self.ids_added > 0 || self.ids_fixed > 0
+    }
+}
+
+// Gives every block node in content_json a uniqueID of its own. Block nodes without one get a
+// fresh ID; a uniqueID that isn't a UUID, or that an earlier node in document order already
+// has, is replaced with a fresh one.
+pub fn repair_unique_ids(content_json: &mut Value) -> UniqueIdRepair {
+    fn walk(node: &mut Value, seen: &mut HashSet<Uuid>, repair: &mut UniqueIdRepair) {
+        let is_block = is_block_node(node);
+        let id = unique_id(node);
+        match node {
+            Value::Object(obj) => {
+                let has_id = obj.get("uniqueID").is_some_and(|v| !v.is_null());
+                if has_id && !id.is_some_and(|id| seen.insert(id)) {
+                    let fresh_id = Uuid::new_v4();
+                    seen.insert(fresh_id);
+                    obj.insert("uniqueID".to_string(), Value::String(fresh_id.to_string()));
+                    repair.ids_fixed += 1;
+                } else if !has_id && is_block {
+                    let fresh_id = Uuid::new_v4();
+                    seen.insert(fresh_id);
+                    obj.insert("uniqueID".to_string(), Value::String(fresh_id.to_string()));
+                    repair.ids_added += 1;
+                }
+                // Children before other keys, so IDs are kept by whichever node comes first
+                if let Some(children) = obj.get_mut("children") {
+                    walk(children, seen, repair);
+                }
+                for (key, value) in obj.iter_mut() {
+                    if key != "children" {
+                        walk(value, seen, repair);
+                    }
+                }
+            }
+            Value::Array(items) => {
+                for item in items {
+                    walk(item, seen, repair);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut repair = UniqueIdRepair::default();
+    let mut seen = HashSet::new();
+    match content_json.get_mut("root") {
+        Some(root) => walk(root, &mut seen, &mut repair),
+        None => walk(content_json, &mut seen, &mut repair),
+    }
+    repair
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn id(n: u128) -> Uuid {
+        Uuid::from_u128(n)
+    }
+
+    fn block(kind: &str, n: u128) -> Value {
+        json!({"type": kind, "uniqueID": id(n).to_string(), "children": [{"type": "text", "text": n.to_string()}]})
+    }
+
+    fn paragraph(n: u128) -> Value {
+        block("paragraph", n)
+    }
+
+    fn item(n: u128) -> Value {
+        block("listitem", n)
+    }
+
+    fn list(items: Vec<Value>) -> Value {
+        json!({"type": "list", "listType": "bullet", "children": items})
+    }
+
+    fn holder(list: Value) -> Value {
+        json!({"type": "listitem", "children": [list]})
+    }
+
+    // Paragraph 1, a list of item 2 (with items 3 and 4 nested under it) and item 5, paragraph 6
+    fn content() -> Value {
+        json!({"root": {"type": "root", "children": [
+            paragraph(1),
+            list(vec![item(2), holder(list(vec![item(3), item(4)])), item(5)]),
+            paragraph(6),
+        ]}})
+    }
+
+    // uniqueIDs (as their numbers) of the blocks in document order
+    fn order(content: &Value) -> Vec<u128> {
+        collect_unique_ids(content).into_iter().map(|id| id.as_u128()).collect()
+    }
+
+    #[test]
+    fn find_block_node_finds_nested_items() {
+        let content = content();
+        let path = find_block_path(&content, &id(4).to_string()).unwrap();
+        assert_eq!(json_pointer(&path), "/root/children/1/children/1/children/0/children/1");
+        assert_eq!(find_block_node(&content, id(4)), Some(&item(4)));
+        assert_eq!(find_block_node(&content, id(99)), None);
+    }
+
+    #[test]
+    fn replace_block_node_keeps_the_nested_list() {
+        let mut content = content();
+        assert_eq!(replace_block_node(&mut content, id(2), paragraph(7)), Some(item(2)));
+        assert_eq!(order(&content), vec![1, 7, 3, 4, 5, 6]);
+        assert_eq!(replace_block_node(&mut content, id(4), item(8)), Some(item(4)));
+        assert_eq!(order(&content), vec![1, 7, 3, 8, 5, 6]);
+        assert_eq!(replace_block_node(&mut content, id(99), item(9)), None);
+        assert_eq!(order(&content), vec![1, 7, 3, 8, 5, 6]);
+    }
+
+    #[test]
+    fn insert_block_after_last_children() {
+        let mut content = content();
+        assert!(insert_block_after(&mut content, id(4), item(7)));
+        assert!(insert_block_after(&mut content, id(6), paragraph(8)));
+        assert!(insert_block_after(&mut content, id(5), item(9)));
+        assert_eq!(order(&content), vec![1, 2, 3, 4, 7, 5, 9, 6, 8]);
+        let nested = content.pointer("/root/children/1/children/1/children/0/children").unwrap();
+        assert_eq!(nested.as_array().unwrap().len(), 3);
+        assert!(!insert_block_after(&mut content, id(99), item(10)));
+    }
+
+    #[test]
+    fn insert_block_after_an_item_goes_past_its_nested_list() {
+        let mut content = content();
+        assert!(insert_block_after(&mut content, id(2), item(7)));
+        assert_eq!(order(&content), vec![1, 2, 3, 4, 7, 5, 6]);
+        assert_eq!(content.pointer("/root/children/1/children/2"), Some(&item(7)));
+    }
+
+    #[test]
+    fn insert_blocks_next_to_goes_before_or_after_the_anchor() {
+        let mut content = content();
+        assert!(insert_blocks_next_to(&mut content, id(2), vec![item(7), item(8)], false));
+        assert!(insert_blocks_next_to(&mut content, id(4), vec![item(9)], false));
+        assert!(insert_blocks_next_to(&mut content, id(2), vec![item(10)], true));
+        assert_eq!(order(&content), vec![1, 7, 8, 2, 3, 9, 4, 10, 5, 6]);
+        assert!(!insert_blocks_next_to(&mut content, id(99), vec![item(11)], false));
+    }
+
+    #[test]
+    fn append_nested_items_adds_to_or_creates_the_nested_list() {
+        let mut content = content();
+        assert!(append_nested_items(&mut content, id(2), vec![item(7)]));
+        let nested = holder(list(vec![item(3), item(4), item(7)]));
+        assert_eq!(content.pointer("/root/children/1/children/1"), Some(&nested));
+
+        // Item 5 has no nested list, so it gets one of its list's type after it
+        assert!(append_nested_items(&mut content, id(5), vec![item(8), item(9)]));
+        assert_eq!(order(&content), vec![1, 2, 3, 4, 7, 5, 8, 9, 6]);
+        let nested = content.pointer("/root/children/1/children/3").unwrap();
+        assert!(is_nested_list_holder(nested));
+        assert_eq!(nested["children"][0]["listType"], "bullet");
+
+        assert!(!append_nested_items(&mut content, id(1), vec![item(10)]));
+        assert!(!append_nested_items(&mut content, id(99), vec![item(10)]));
+    }
+
+    #[test]
+    fn remove_block_node_takes_the_nested_list_with_its_item() {
+        let mut content = content();
+        let removed = remove_block_node(&mut content, id(2)).unwrap();
+        assert_eq!(removed, vec![item(2), holder(list(vec![item(3), item(4)]))]);
+        assert_eq!(order(&content), vec![1, 5, 6]);
+        assert_eq!(remove_block_node(&mut content, id(99)), None);
+    }
+
+    #[test]
+    fn remove_block_node_removes_lists_left_empty() {
+        let mut content = content();
+        assert_eq!(remove_block_node(&mut content, id(4)), Some(vec![item(4)]));
+        assert_eq!(remove_block_node(&mut content, id(3)), Some(vec![item(3)]));
+        // The emptied nested list went with the item holding it
+        assert_eq!(content.pointer("/root/children/1/children"), Some(&json!([item(2), item(5)])));
+        remove_block_node(&mut content, id(2)).unwrap();
+        remove_block_node(&mut content, id(5)).unwrap();
+        assert_eq!(content["root"]["children"], json!([paragraph(1), paragraph(6)]));
+        remove_block_node(&mut content, id(6)).unwrap();
+        assert_eq!(content["root"]["children"], json!([paragraph(1)]));
+    }
+}
@@ -1,9 +1,11 @@
 use chrono::{DateTime, Utc};
-use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use sqlx::{PgConnection, PgExecutor, PgPool};
 use uuid::Uuid;
 
 // Import the shared DalError
 use crate::dal_error::DalError;
+use crate::block_handler;
 
 #[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
 pub struct PageLink {
@@ -25,31 +27,134 @@ pub struct BlockReference {
     // updated_at is not in the block_references table schema
 }
 
-// --- Page Link Functions ---
+// An incoming block reference, with the referencing page's title and the text around the
+// reference so the references panel can render it without further lookups
+#[derive(Debug, serde::Serialize)]
+pub struct BlockReferenceDetail {
+    pub id: Uuid,
+    pub referencing_page_id: Uuid,
+    pub referencing_page_title: String,
+    pub referencing_block_id: Uuid,
+    pub referencing_block_snippet: String,
+    pub referenced_page_id: Uuid,
+    pub referenced_block_id: Uuid,
+    pub created_at: DateTime<Utc>,
+}
 
-pub async fn add_page_link(
-    pool: &PgPool,
-    source_page_id: Uuid,
-    target_page_id: Uuid,
-) -> Result<(), DalError> {
-    sqlx::query!(
-        r#"
-        INSERT INTO page_links (source_page_id, target_page_id, created_at)
-        VALUES ($1, $2, now())
-        ON CONFLICT (source_page_id, target_page_id) DO NOTHING
-        -- If the link already exists, do nothing. Or use DO UPDATE if you need to update created_at.
-        "#,
-        source_page_id,
-        target_page_id
-    )
-    .execute(pool)
-    .await?;
-    // Returns Result<(), DalError> indicating success or failure. No specific ID for this link type.
-    Ok(())
+// A block reference made from a page, with what it points at. The referenced block may have
+// been deleted since, leaving its text unknown.
+#[derive(Debug, serde::Serialize)]
+pub struct OutgoingBlockReference {
+    pub id: Uuid,
+    pub referenced_page_id: Uuid,
+    pub referenced_page_title: String,
+    pub referenced_block_id: Uuid,
+    pub referenced_block_text: Option<String>,
+    pub created_at: DateTime<Utc>,
 }
 
-pub async fn remove_page_link(
-    pool: &PgPool,
+// Every block reference made from one block of a page
+#[derive(Debug, serde::Serialize)]
+pub struct ReferencingBlock {
+    pub block_id: Uuid,
+    pub block_text: Option<String>,
+    pub references: Vec<OutgoingBlockReference>,
+}
+
+// A page linking to another page, with what a backlinks panel needs to render it, plus, when
+// asked for per block and it can be found, the block on that page containing the link
+#[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct BacklinkSource {
+    pub id: Uuid,
+    pub title: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub content_updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub is_favorite: bool,
+    pub favorite_order: Option<i32>,
+    pub tags: Vec<String>,
+    pub linked_at: DateTime<Utc>, // created_at of the page_links row
+    pub link_count: i32,          // Times the source page links here, across all its blocks
+    pub block_id: Option<Uuid>,
+    pub block_text: Option<String>,
+    pub block_link_count: Option<i32>, // Times this block links here
+}
+
+// A page linked to from another page, for a "links to" panel
+#[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct OutgoingLinkPage {
+    pub id: Uuid,
+    pub title: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub content_updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub is_favorite: bool,
+    pub favorite_order: Option<i32>,
+    pub tags: Vec<String>,
+    pub linked_at: DateTime<Utc>, // created_at of the page_links row
+}
+
+// A `[[Title]]` written in a page for which no page existed when it was saved
+#[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct UnresolvedLink {
+    pub source_page_id: Uuid,
+    pub target_title: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Titles of daily notes, which create_daily_note names after the date (YYYY-MM-DD)
+pub const DAILY_NOTE_TITLE_PATTERN: &str = r"^\d{4}-\d{2}-\d{2}$";
+
+#[derive(Debug, serde::Deserialize, Clone)]
+#[serde(default)]
+pub struct GraphOptions {
+    pub focus_page_id: Option<Uuid>, // Only include pages within max_hops links of this page
+    pub max_hops: i32,
+    pub exclude_daily_notes: bool,
+    pub exclude_title_pattern: Option<String>, // PostgreSQL regular expression matched against titles
+    pub min_degree: usize, // Drop pages with fewer edges than this (after the other filters)
+}
+
+impl Default for GraphOptions {
+    fn default() -> Self {
+        GraphOptions {
+            focus_page_id: None,
+            max_hops: 1,
+            exclude_daily_notes: false,
+            exclude_title_pattern: None,
+            min_degree: 0,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct GraphNode {
+    pub id: Uuid,
+    pub title: String,
+    pub backlink_count: i64,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Links from one page to another: the page link plus every block reference between them
+#[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct GraphEdge {
+    pub source: Uuid,
+    pub target: Uuid,
+    pub weight: i64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct GraphData {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+// --- Page Link Functions ---
+
+pub async fn remove_page_link<'e>(
+    executor: impl PgExecutor<'e>,
     source_page_id: Uuid,
     target_page_id: Uuid,
 ) -> Result<bool, DalError> {
@@ -61,34 +166,206 @@ pub async fn remove_page_link(
         source_page_id,
         target_page_id
     )
-    .execute(pool)
+    .execute(executor)
     .await?;
 
     Ok(result.rows_affected() > 0)
 }
 
-pub async fn find_backlinks_for_page( // Incoming links
-    pool: &PgPool,
+pub async fn find_backlinks_for_page<'e>( // Incoming links
+    executor: impl PgExecutor<'e>,
     page_id: Uuid, // This is the target_page_id
 ) -> Result<Vec<PageLink>, DalError> {
     let links = sqlx::query_as!(
         PageLink,
         r#"
-        SELECT source_page_id, target_page_id, created_at
-        FROM page_links
-        WHERE target_page_id = $1
-        ORDER BY created_at DESC
+        SELECT l.source_page_id, l.target_page_id, l.created_at
+        FROM page_links l
+        JOIN pages p ON p.id = l.source_page_id
+        WHERE l.target_page_id = $1 AND p.deleted_at IS NULL -- Skip links from trashed pages
+        ORDER BY l.created_at DESC
         "#,
         page_id
     )
-    .fetch_all(pool)
+    .fetch_all(executor)
     .await?;
 
     Ok(links)
 }
 
-pub async fn find_outgoing_links_for_page(
-    pool: &PgPool,
+// Stores a page's links, one entry per occurrence: the target page and the block the link was
+// written in, if any. Occurrences are counted per target and per block, and written with one
+// insert per table. Targets that aren't pages are skipped. Returns the count stored per target.
+pub async fn set_page_links(
+    tx: &mut PgConnection,
+    source_page_id: Uuid,
+    links: &[(Uuid, Option<Uuid>)],
+) -> Result<HashMap<Uuid, i32>, DalError> {
+    let mut target_counts: HashMap<Uuid, i32> = HashMap::new();
+    let mut block_counts: HashMap<(Uuid, Uuid), i32> = HashMap::new();
+    for (target_id, block_id) in links {
+        *target_counts.entry(*target_id).or_default() += 1;
+        if let Some(block_id) = block_id {
+            *block_counts.entry((*target_id, *block_id)).or_default() += 1;
+        }
+    }
+    let (targets, counts): (Vec<Uuid>, Vec<i32>) = target_counts.into_iter().unzip();
+
+    let stored = sqlx::query!(
+        r#"
+        INSERT INTO page_links (source_page_id, target_page_id, link_count, created_at)
+        SELECT $1, l.target_page_id, l.link_count, now()
+        FROM unnest($2::uuid[], $3::int4[]) AS l(target_page_id, link_count)
+        JOIN pages p ON p.id = l.target_page_id
+        ON CONFLICT (source_page_id, target_page_id) DO UPDATE SET link_count = EXCLUDED.link_count
+        RETURNING target_page_id, link_count
+        "#,
+        source_page_id,
+        &targets,
+        &counts
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    add_link_blocks(&mut *tx, source_page_id, block_counts).await?;
+
+    Ok(stored.into_iter().map(|row| (row.target_page_id, row.link_count)).collect())
+}
+
+// Replaces the links written in the given blocks of the source page with links, one (target,
+// block) entry per occurrence, and moves the page's link counts by the difference. Links from
+// its other blocks are kept. Returns the targets whose count changed, with the count now
+// stored (0 when the page no longer links there).
+pub async fn set_block_links(
+    tx: &mut PgConnection,
+    source_page_id: Uuid,
+    block_ids: &[Uuid],
+    links: &[(Uuid, Uuid)],
+) -> Result<HashMap<Uuid, i32>, DalError> {
+    let removed = sqlx::query!(
+        r#"
+        DELETE FROM page_link_blocks
+        WHERE source_page_id = $1 AND block_id = ANY($2)
+        RETURNING target_page_id, link_count
+        "#,
+        source_page_id,
+        block_ids
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut deltas: HashMap<Uuid, i32> = HashMap::new();
+    for row in removed {
+        *deltas.entry(row.target_page_id).or_default() -= row.link_count;
+    }
+    let mut block_counts: HashMap<(Uuid, Uuid), i32> = HashMap::new();
+    for (target_id, block_id) in links {
+        *deltas.entry(*target_id).or_default() += 1;
+        *block_counts.entry((*target_id, *block_id)).or_default() += 1;
+    }
+    deltas.retain(|_, delta| *delta != 0);
+    let (targets, counts): (Vec<Uuid>, Vec<i32>) = deltas.into_iter().unzip();
+
+    let stored = sqlx::query!(
+        r#"
+        INSERT INTO page_links (source_page_id, target_page_id, link_count, created_at)
+        SELECT $1, l.target_page_id, l.link_count, now()
+        FROM unnest($2::uuid[], $3::int4[]) AS l(target_page_id, link_count)
+        JOIN pages p ON p.id = l.target_page_id
+        ON CONFLICT (source_page_id, target_page_id) DO UPDATE SET link_count = page_links.link_count + EXCLUDED.link_count
+        RETURNING target_page_id, link_count
+        "#,
+        source_page_id,
+        &targets,
+        &counts
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    // Their block rows go with them
+    sqlx::query!(
+        r#"
+        DELETE FROM page_links
+        WHERE source_page_id = $1 AND link_count <= 0
+        "#,
+        source_page_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    add_link_blocks(&mut *tx, source_page_id, block_counts).await?;
+
+    Ok(stored.into_iter().map(|row| (row.target_page_id, row.link_count.max(0))).collect())
+}
+
+// Stores how often the source page links to each target from each block, for targets it has a
+// page_links row for and blocks that exist
+async fn add_link_blocks(
+    tx: &mut PgConnection,
+    source_page_id: Uuid,
+    block_counts: HashMap<(Uuid, Uuid), i32>,
+) -> Result<(), DalError> {
+    let mut block_targets = Vec::with_capacity(block_counts.len());
+    let mut block_ids = Vec::with_capacity(block_counts.len());
+    let mut block_link_counts = Vec::with_capacity(block_counts.len());
+    for ((target_id, block_id), count) in block_counts {
+        block_targets.push(target_id);
+        block_ids.push(block_id);
+        block_link_counts.push(count);
+    }
+    sqlx::query!(
+        r#"
+        INSERT INTO page_link_blocks (source_page_id, target_page_id, block_id, link_count)
+        SELECT $1, b.target_page_id, b.block_id, b.link_count
+        FROM unnest($2::uuid[], $3::uuid[], $4::int4[]) AS b(target_page_id, block_id, link_count)
+        JOIN page_links l ON l.source_page_id = $1 AND l.target_page_id = b.target_page_id
+        JOIN blocks bl ON bl.id = b.block_id
+        ON CONFLICT (source_page_id, target_page_id, block_id) DO UPDATE SET link_count = EXCLUDED.link_count
+        "#,
+        source_page_id,
+        &block_targets,
+        &block_ids,
+        &block_link_counts
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+// Backlinks in one query, newest source page first. With per_block, one row per block the link
+// was written in, so a page linking from several blocks shows up once for each; without, one
+// row per page and no block context, as for links made outside any block. Each row carries how
+// often the page, and the block, link here.
+pub async fn find_backlink_sources_for_page<'e>(
+    executor: impl PgExecutor<'e>,
+    page_id: Uuid,
+    per_block: bool,
+) -> Result<Vec<BacklinkSource>, DalError> {
+    let pages = sqlx::query_as!(
+        BacklinkSource,
+        r#"
+        SELECT p.id, p.title, p.created_at, p.updated_at, p.deleted_at, p.is_favorite, p.favorite_order,
+               p.content_updated_at, page_tag_names(p.id) AS "tags!", l.created_at AS linked_at, l.link_count,
+               b.id AS "block_id?", b.content_text AS "block_text?", lb.link_count AS "block_link_count?"
+        FROM page_links l
+        JOIN pages p ON p.id = l.source_page_id
+        LEFT JOIN page_link_blocks lb
+               ON $2 AND lb.source_page_id = l.source_page_id AND lb.target_page_id = l.target_page_id
+        LEFT JOIN blocks b ON b.id = lb.block_id
+        WHERE l.target_page_id = $1 AND p.deleted_at IS NULL
+        ORDER BY p.updated_at DESC, p.id, b.parent_block_id NULLS FIRST, b.order_index, b.id
+        "#,
+        page_id,
+        per_block
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(pages)
+}
+
+pub async fn find_outgoing_links_for_page<'e>(
+    executor: impl PgExecutor<'e>,
     page_id: Uuid, // This is the source_page_id
 ) -> Result<Vec<PageLink>, DalError> {
     let links = sqlx::query_as!(
@@ -101,55 +378,119 @@ pub async fn find_outgoing_links_for_page(
         "#,
         page_id
     )
-    .fetch_all(pool)
+    .fetch_all(executor)
     .await?;
 
     Ok(links)
 }
 
-// Still to implement block reference functions:
-// add_block_reference
-// get_block_references_from_block
-// get_block_references_to_block
+// Pages this page links to, with each target's metadata. Trashed targets are left out, as
+// in find_backlink_sources_for_page.
+pub async fn find_outgoing_link_pages<'e>(
+    executor: impl PgExecutor<'e>,
+    page_id: Uuid,
+) -> Result<Vec<OutgoingLinkPage>, DalError> {
+    let pages = sqlx::query_as!(
+        OutgoingLinkPage,
+        r#"
+        SELECT p.id, p.title, p.created_at, p.updated_at, p.deleted_at, p.is_favorite, p.favorite_order,
+               p.content_updated_at, page_tag_names(p.id) AS "tags!", l.created_at AS linked_at
+        FROM page_links l
+        JOIN pages p ON p.id = l.target_page_id
+        WHERE l.source_page_id = $1 AND p.deleted_at IS NULL
+        ORDER BY l.created_at DESC, p.title
+        "#,
+        page_id
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(pages)
+}
+
+// Records the `[[Titles]]` in the source page that match no page
+pub async fn add_unresolved_links<'e>(
+    executor: impl PgExecutor<'e>,
+    source_page_id: Uuid,
+    target_titles: &[String],
+) -> Result<u64, DalError> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO unresolved_links (source_page_id, target_title)
+        SELECT $1, t.target_title
+        FROM unnest($2::text[]) AS t(target_title)
+        ON CONFLICT (source_page_id, target_title) DO NOTHING
+        "#,
+        source_page_id,
+        target_titles
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// The page's unresolved links, leaving out titles that a live page (or alias) has taken since
+// the page was saved; its link is stored the next time it is
+pub async fn find_unresolved_links_for_page<'e>(
+    executor: impl PgExecutor<'e>,
+    source_page_id: Uuid,
+) -> Result<Vec<UnresolvedLink>, DalError> {
+    let links = sqlx::query_as!(
+        UnresolvedLink,
+        r#"
+        SELECT u.source_page_id, u.target_title, u.created_at
+        FROM unresolved_links u
+        WHERE u.source_page_id = $1
+          AND NOT EXISTS (SELECT 1 FROM pages p WHERE lower(p.title) = lower(u.target_title) AND p.deleted_at IS NULL)
+          AND NOT EXISTS (
+              SELECT 1 FROM page_aliases a JOIN pages p ON p.id = a.page_id
+              WHERE lower(a.alias) = lower(u.target_title) AND p.deleted_at IS NULL
+          )
+        ORDER BY u.target_title
+        "#,
+        source_page_id
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(links)
+}
 
 // --- Block Reference Functions ---
 
-pub async fn add_block_reference(
-    pool: &PgPool,
+// Returns the reference's ID, and whether it was newly inserted. When the reference already
+// exists the existing row's ID is returned.
+pub async fn add_block_reference<'e>(
+    executor: impl PgExecutor<'e>,
     referencing_page_id: Uuid,
     referencing_block_id: Uuid,
     referenced_page_id: Uuid,
     referenced_block_id: Uuid,
-) -> Result<Uuid, DalError> {
-    let new_id = Uuid::new_v4();
-    sqlx::query!(
+) -> Result<(Uuid, bool), DalError> {
+    let row = sqlx::query!(
         r#"
         INSERT INTO block_references
             (id, referencing_page_id, referencing_block_id, referenced_page_id, referenced_block_id, created_at)
         VALUES ($1, $2, $3, $4, $5, now())
-        ON CONFLICT (referencing_block_id, referenced_block_id) DO NOTHING
-        -- If the reference already exists, do nothing.
-        -- Consider if ON CONFLICT needs to return the existing ID or update a timestamp.
-        -- For now, it just inserts or does nothing, returning the new_id if inserted.
-        -- To reliably get the ID (new or existing), a SELECT after INSERT or more complex logic is needed.
-        -- For simplicity, we'll assume new_id is desired if insert happens.
+        ON CONFLICT (referencing_block_id, referenced_block_id)
+        DO UPDATE SET created_at = block_references.created_at -- No-op so RETURNING sees the existing row
+        RETURNING id, (xmax = 0) AS "inserted!" -- xmax is only 0 for a freshly inserted row
         "#,
-        new_id,
+        Uuid::new_v4(),
         referencing_page_id,
         referencing_block_id,
         referenced_page_id,
         referenced_block_id
     )
-    .execute(pool)
+    .fetch_one(executor)
     .await?;
-    // This doesn't return the ID if there's a conflict and DO NOTHING occurs.
-    // If returning the ID is critical even on conflict, this needs adjustment.
-    // The plan asks for `Result<uuid::Uuid, dal::Error>`, so returning the generated new_id.
-    Ok(new_id)
+
+    Ok((row.id, row.inserted))
 }
 
-pub async fn get_block_references_from_block( // Outgoing references from a specific block
-    pool: &PgPool,
+pub async fn get_block_references_from_block<'e>( // Outgoing references from a specific block
+    executor: impl PgExecutor<'e>,
     referencing_block_id: Uuid,
 ) -> Result<Vec<BlockReference>, DalError> {
     let references = sqlx::query_as!(
@@ -162,34 +503,208 @@ pub async fn get_block_references_from_block( // Outgoing references from a spec
         "#,
         referencing_block_id
     )
-    .fetch_all(pool)
+    .fetch_all(executor)
     .await?;
 
     Ok(references)
 }
 
-pub async fn get_block_references_to_block( // Incoming references to a specific block
-    pool: &PgPool,
+// Incoming references to a specific block from live pages, most recent first. References whose
+// referencing block no longer exists are left out.
+pub async fn get_block_references_to_block<'e>(
+    executor: impl PgExecutor<'e>,
     referenced_block_id: Uuid,
-) -> Result<Vec<BlockReference>, DalError> {
-    let references = sqlx::query_as!(
-        BlockReference,
+) -> Result<Vec<BlockReferenceDetail>, DalError> {
+    let rows = sqlx::query!(
         r#"
-        SELECT id, referencing_page_id, referencing_block_id, referenced_page_id, referenced_block_id, created_at
-        FROM block_references
-        WHERE referenced_block_id = $1
-        ORDER BY created_at DESC
+        SELECT br.id, br.referencing_page_id, p.title AS referencing_page_title, br.referencing_block_id,
+               b.content_text AS referencing_block_text, br.referenced_page_id, br.referenced_block_id,
+               br.created_at
+        FROM block_references br
+        JOIN pages p ON p.id = br.referencing_page_id
+        JOIN blocks b ON b.id = br.referencing_block_id
+        WHERE br.referenced_block_id = $1 AND p.deleted_at IS NULL
+        ORDER BY br.created_at DESC, br.id
         "#,
         referenced_block_id
     )
-    .fetch_all(pool)
+    .fetch_all(executor)
     .await?;
 
-    Ok(references)
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let reference = format!("((({})))", row.referenced_block_id);
+            BlockReferenceDetail {
+                id: row.id,
+                referencing_page_id: row.referencing_page_id,
+                referencing_page_title: row.referencing_page_title,
+                referencing_block_id: row.referencing_block_id,
+                referencing_block_snippet: block_handler::snippet_around(
+                    row.referencing_block_text.as_deref().unwrap_or_default(),
+                    &reference,
+                ),
+                referenced_page_id: row.referenced_page_id,
+                referenced_block_id: row.referenced_block_id,
+                created_at: row.created_at,
+            }
+        })
+        .collect())
 }
 
-pub async fn remove_block_reference(
-    pool: &PgPool,
+// Block references made from a page, grouped by referencing block in page order. References
+// whose referencing block no longer exists are left out.
+pub async fn get_outgoing_references_for_page<'e>(
+    executor: impl PgExecutor<'e>,
+    page_id: Uuid, // The referencing_page_id
+) -> Result<Vec<ReferencingBlock>, DalError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT br.id, br.referencing_block_id, b.content_text AS referencing_block_text,
+               br.referenced_page_id, p.title AS referenced_page_title, br.referenced_block_id,
+               rb.content_text AS "referenced_block_text?", br.created_at
+        FROM block_references br
+        JOIN blocks b ON b.id = br.referencing_block_id
+        JOIN pages p ON p.id = br.referenced_page_id
+        LEFT JOIN blocks rb ON rb.id = br.referenced_block_id
+        WHERE br.referencing_page_id = $1
+        ORDER BY b.parent_block_id NULLS FIRST, b.order_index, b.id, br.created_at, br.id
+        "#,
+        page_id
+    )
+    .fetch_all(executor)
+    .await?;
+
+    let mut blocks: Vec<ReferencingBlock> = Vec::new();
+    for row in rows {
+        let reference = OutgoingBlockReference {
+            id: row.id,
+            referenced_page_id: row.referenced_page_id,
+            referenced_page_title: row.referenced_page_title,
+            referenced_block_id: row.referenced_block_id,
+            referenced_block_text: row.referenced_block_text,
+            created_at: row.created_at,
+        };
+        match blocks.last_mut() {
+            Some(block) if block.block_id == row.referencing_block_id => block.references.push(reference),
+            _ => blocks.push(ReferencingBlock {
+                block_id: row.referencing_block_id,
+                block_text: row.referencing_block_text,
+                references: vec![reference],
+            }),
+        }
+    }
+
+    Ok(blocks)
+}
+
+// Deletes block references whose referencing block no longer exists. Returns how many were
+// removed.
+pub async fn remove_dangling_block_references<'e>(executor: impl PgExecutor<'e>) -> Result<u64, DalError> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM block_references br
+        WHERE NOT EXISTS (SELECT 1 FROM blocks b WHERE b.id = br.referencing_block_id)
+        "#
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// Incoming reference counts for every block on a page that is referenced at least once,
+// ignoring references from trashed pages
+pub async fn get_reference_counts_for_page<'e>(
+    executor: impl PgExecutor<'e>,
+    page_id: Uuid, // The referenced_page_id
+) -> Result<HashMap<Uuid, i64>, DalError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT br.referenced_block_id, COUNT(*) AS "count!"
+        FROM block_references br
+        JOIN pages p ON p.id = br.referencing_page_id
+        WHERE br.referenced_page_id = $1 AND p.deleted_at IS NULL
+        GROUP BY br.referenced_block_id
+        "#,
+        page_id
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| (row.referenced_block_id, row.count)).collect())
+}
+
+// How much a page is referenced from elsewhere, for the "n linked references" badge
+#[derive(Debug, Clone, Copy, Default, serde::Serialize)]
+pub struct ReferenceSummary {
+    pub page_links: i64,        // [[links]] to the page, counting every occurrence
+    pub block_references: i64,  // (((references))) to any of its blocks
+    pub referencing_pages: i64, // Distinct pages making either
+}
+
+// A ReferenceSummary for one page; all zeros for a page nothing references
+pub async fn get_reference_summary<'e>(
+    executor: impl PgExecutor<'e>,
+    page_id: Uuid,
+) -> Result<ReferenceSummary, DalError> {
+    let summaries = get_reference_summaries(executor, &[page_id]).await?;
+    Ok(summaries.get(&page_id).copied().unwrap_or_default())
+}
+
+// ReferenceSummaries for many pages in one round trip, keyed by page ID; every ID asked for
+// has an entry. Links and references from trashed pages are left out, and so are references
+// to blocks that no longer exist.
+pub async fn get_reference_summaries<'e>(
+    executor: impl PgExecutor<'e>,
+    page_ids: &[Uuid],
+) -> Result<HashMap<Uuid, ReferenceSummary>, DalError> {
+    let rows = sqlx::query!(
+        r#"
+        WITH links AS (
+            SELECT l.target_page_id AS page_id, l.source_page_id, SUM(l.link_count)::bigint AS links, 0::bigint AS refs
+            FROM page_links l
+            JOIN pages src ON src.id = l.source_page_id AND src.deleted_at IS NULL
+            WHERE l.target_page_id = ANY($1)
+            GROUP BY l.target_page_id, l.source_page_id
+        ), refs AS (
+            SELECT br.referenced_page_id AS page_id, br.referencing_page_id AS source_page_id,
+                0::bigint AS links, COUNT(*) AS refs
+            FROM block_references br
+            JOIN blocks b ON b.id = br.referenced_block_id
+            JOIN pages src ON src.id = br.referencing_page_id AND src.deleted_at IS NULL
+            WHERE br.referenced_page_id = ANY($1)
+            GROUP BY br.referenced_page_id, br.referencing_page_id
+        )
+        SELECT page_id AS "page_id!",
+            SUM(links)::bigint AS "page_links!",
+            SUM(refs)::bigint AS "block_references!",
+            COUNT(DISTINCT source_page_id) AS "referencing_pages!"
+        FROM (SELECT * FROM links UNION ALL SELECT * FROM refs) counts
+        GROUP BY page_id
+        "#,
+        page_ids
+    )
+    .fetch_all(executor)
+    .await?;
+
+    let mut summaries: HashMap<Uuid, ReferenceSummary> =
+        page_ids.iter().map(|id| (*id, ReferenceSummary::default())).collect();
+    for row in rows {
+        summaries.insert(
+            row.page_id,
+            ReferenceSummary {
+                page_links: row.page_links,
+                block_references: row.block_references,
+                referencing_pages: row.referencing_pages,
+            },
+        );
+    }
+    Ok(summaries)
+}
+
+pub async fn remove_block_reference<'e>(
+    executor: impl PgExecutor<'e>,
     id: Uuid, // ID of the block reference itself
 ) -> Result<bool, DalError> {
     let result = sqlx::query!(
@@ -199,7 +714,7 @@ pub async fn remove_block_reference(
         "#,
         id
     )
-    .execute(pool)
+    .execute(executor)
     .await?;
     Ok(result.rows_affected() > 0)
 }
@@ -210,25 +725,114 @@ pub async fn remove_block_reference(
 
 // --- Functions to clear links/references for a page (as per Step 3 of plan) ---
 
-pub async fn remove_all_page_links_from_source(
-    pool: &PgPool,
+// Returns how many times the page linked to each target it no longer links to
+pub async fn remove_all_page_links_from_source<'e>(
+    executor: impl PgExecutor<'e>,
+    source_page_id: Uuid,
+) -> Result<HashMap<Uuid, i32>, DalError> {
+    let rows = sqlx::query!(
+        r#"
+        DELETE FROM page_links
+        WHERE source_page_id = $1
+        RETURNING target_page_id, link_count
+        "#,
+        source_page_id
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| (row.target_page_id, row.link_count)).collect())
+}
+
+// Pages that link to or are linked from any of these pages, excluding the pages themselves
+pub async fn linked_page_ids<'e>(executor: impl PgExecutor<'e>, page_ids: &[Uuid]) -> Result<Vec<Uuid>, DalError> {
+    let ids = sqlx::query_scalar!(
+        r#"
+        SELECT target_page_id AS "id!" FROM page_links WHERE source_page_id = ANY($1)
+        UNION
+        SELECT source_page_id AS "id!" FROM page_links WHERE target_page_id = ANY($1)
+        EXCEPT
+        SELECT unnest($1::uuid[])
+        "#,
+        page_ids
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(ids)
+}
+
+pub async fn remove_all_unresolved_links_from_source<'e>(
+    executor: impl PgExecutor<'e>,
     source_page_id: Uuid,
 ) -> Result<u64, DalError> {
     let result = sqlx::query!(
         r#"
-        DELETE FROM page_links
+        DELETE FROM unresolved_links
         WHERE source_page_id = $1
         "#,
         source_page_id
     )
-    .execute(pool)
+    .execute(executor)
     .await?;
 
     Ok(result.rows_affected())
 }
 
-pub async fn remove_all_block_references_from_referencing_page(
-    pool: &PgPool,
+// Removes the unresolved links of the source page whose title isn't one of target_titles
+pub async fn remove_unresolved_links_except<'e>(
+    executor: impl PgExecutor<'e>,
+    source_page_id: Uuid,
+    target_titles: &[String],
+) -> Result<u64, DalError> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM unresolved_links
+        WHERE source_page_id = $1 AND NOT (target_title = ANY($2))
+        "#,
+        source_page_id,
+        target_titles
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// Stores references made from blocks of the referencing page, each a (referencing block,
+// referenced block) pair, with one insert. References to blocks that don't exist are skipped.
+// Returns the referenced block IDs that were stored.
+pub async fn add_block_references<'e>(
+    executor: impl PgExecutor<'e>,
+    referencing_page_id: Uuid,
+    references: &[(Uuid, Uuid)],
+) -> Result<Vec<Uuid>, DalError> {
+    let (referencing_block_ids, referenced_block_ids): (Vec<Uuid>, Vec<Uuid>) = references.iter().copied().unzip();
+    let ids: Vec<Uuid> = references.iter().map(|_| Uuid::new_v4()).collect();
+
+    let stored = sqlx::query_scalar!(
+        r#"
+        INSERT INTO block_references
+            (id, referencing_page_id, referencing_block_id, referenced_page_id, referenced_block_id, created_at)
+        SELECT r.id, $1, r.referencing_block_id, b.page_id, r.referenced_block_id, now()
+        FROM unnest($2::uuid[], $3::uuid[], $4::uuid[]) AS r(id, referencing_block_id, referenced_block_id)
+        JOIN blocks b ON b.id = r.referenced_block_id
+        ON CONFLICT (referencing_block_id, referenced_block_id) DO NOTHING
+        RETURNING referenced_block_id
+        "#,
+        referencing_page_id,
+        &ids,
+        &referencing_block_ids,
+        &referenced_block_ids
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(stored)
+}
+
+pub async fn remove_all_block_references_from_referencing_page<'e>(
+    executor: impl PgExecutor<'e>,
     referencing_page_id: Uuid, // This is the page whose content is being updated
 ) -> Result<u64, DalError> {
     let result = sqlx::query!(
@@ -238,8 +842,200 @@ pub async fn remove_all_block_references_from_referencing_page(
         "#,
         referencing_page_id
     )
-    .execute(pool)
+    .execute(executor)
     .await?;
 
     Ok(result.rows_affected())
 }
+
+// Removes the references made from the given blocks
+pub async fn remove_block_references_from_blocks<'e>(
+    executor: impl PgExecutor<'e>,
+    referencing_block_ids: &[Uuid],
+) -> Result<u64, DalError> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM block_references
+        WHERE referencing_block_id = ANY($1)
+        "#,
+        referencing_block_ids
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// --- Graph Functions ---
+
+// Live pages and the links between them for the graph view. Trashed pages and pages whose
+// title matches an exclusion pattern are left out, along with their edges.
+pub async fn get_graph_data(pool: &PgPool, options: &GraphOptions) -> Result<GraphData, DalError> {
+    let exclude_pattern = match (options.exclude_daily_notes, options.exclude_title_pattern.as_deref()) {
+        (true, Some(pattern)) => Some(format!("(?:{})|(?:{})", DAILY_NOTE_TITLE_PATTERN, pattern)),
+        (true, None) => Some(DAILY_NOTE_TITLE_PATTERN.to_string()),
+        (false, pattern) => pattern.map(str::to_string),
+    };
+
+    let page_ids = match options.focus_page_id {
+        Some(page_id) => Some(get_page_neighborhood(pool, page_id, options.max_hops, exclude_pattern.as_deref()).await?),
+        None => None,
+    };
+
+    let mut nodes = sqlx::query_as!(
+        GraphNode,
+        r#"
+        SELECT p.id, p.title, p.updated_at, COUNT(src.id) AS "backlink_count!"
+        FROM pages p
+        LEFT JOIN page_links pl ON pl.target_page_id = p.id AND pl.source_page_id <> p.id
+        LEFT JOIN pages src ON src.id = pl.source_page_id AND src.deleted_at IS NULL
+        WHERE p.deleted_at IS NULL
+          AND ($1::text IS NULL OR p.title !~ $1)
+          AND ($2::uuid[] IS NULL OR p.id = ANY($2))
+        GROUP BY p.id
+        ORDER BY p.title
+        "#,
+        exclude_pattern.as_deref(),
+        page_ids.as_deref()
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut edges = sqlx::query_as!(
+        GraphEdge,
+        r#"
+        WITH visible AS (
+            SELECT id FROM pages
+            WHERE deleted_at IS NULL
+              AND ($1::text IS NULL OR title !~ $1)
+              AND ($2::uuid[] IS NULL OR id = ANY($2))
+        ),
+        links AS (
+            SELECT source_page_id AS source, target_page_id AS target FROM page_links
+            UNION ALL
+            SELECT referencing_page_id, referenced_page_id FROM block_references
+        )
+        SELECT l.source AS "source!", l.target AS "target!", COUNT(*) AS "weight!"
+        FROM links l
+        JOIN visible s ON s.id = l.source
+        JOIN visible t ON t.id = l.target
+        WHERE l.source <> l.target
+        GROUP BY l.source, l.target
+        "#,
+        exclude_pattern.as_deref(),
+        page_ids.as_deref()
+    )
+    .fetch_all(pool)
+    .await?;
+
+    if options.min_degree > 0 {
+        let mut degrees: HashMap<Uuid, usize> = HashMap::new();
+        for edge in &edges {
+            *degrees.entry(edge.source).or_default() += 1;
+            *degrees.entry(edge.target).or_default() += 1;
+        }
+        nodes.retain(|node| degrees.get(&node.id).copied().unwrap_or(0) >= options.min_degree);
+        let kept: HashSet<Uuid> = nodes.iter().map(|node| node.id).collect();
+        edges.retain(|edge| kept.contains(&edge.source) && kept.contains(&edge.target));
+    }
+
+    Ok(GraphData { nodes, edges })
+}
+
+// Pages reachable from page_id within max_hops links in either direction, including the page
+// itself. Excluded pages are not traversed through.
+async fn get_page_neighborhood(
+    pool: &PgPool,
+    page_id: Uuid,
+    max_hops: i32,
+    exclude_pattern: Option<&str>,
+) -> Result<Vec<Uuid>, DalError> {
+    let ids = sqlx::query_scalar!(
+        r#"
+        WITH RECURSIVE visible AS (
+            SELECT id FROM pages
+            WHERE deleted_at IS NULL AND ($3::text IS NULL OR title !~ $3)
+        ),
+        adjacency AS (
+            SELECT source_page_id AS a, target_page_id AS b FROM page_links
+            UNION
+            SELECT target_page_id, source_page_id FROM page_links
+            UNION
+            SELECT referencing_page_id, referenced_page_id FROM block_references
+            UNION
+            SELECT referenced_page_id, referencing_page_id FROM block_references
+        ),
+        neighborhood (id, hops) AS (
+            SELECT id, 0 FROM pages WHERE id = $1
+            UNION
+            SELECT adj.b, n.hops + 1
+            FROM neighborhood n
+            JOIN adjacency adj ON adj.a = n.id
+            JOIN visible v ON v.id = adj.b
+            WHERE n.hops < $2
+        )
+        SELECT DISTINCT id AS "id!" FROM neighborhood
+        "#,
+        page_id,
+        max_hops.max(0),
+        exclude_pattern
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page_handler;
+
+    #[sqlx::test(migrator = "crate::db::MIGRATOR")]
+    async fn adding_the_same_block_reference_twice_returns_the_same_id(pool: PgPool) {
+        let page_id = page_handler::create_page(&pool, "Notes", serde_json::json!({}), None).await.unwrap();
+        let (referencing_block_id, referenced_block_id) = (Uuid::new_v4(), Uuid::new_v4());
+        for (index, block_id) in [referencing_block_id, referenced_block_id].into_iter().enumerate() {
+            block_handler::create_block(&pool, block_id, page_id, None, Some("paragraph"), index as i32, None)
+                .await
+                .unwrap();
+        }
+
+        let (first_id, first_inserted) =
+            add_block_reference(&pool, page_id, referencing_block_id, page_id, referenced_block_id).await.unwrap();
+        let (second_id, second_inserted) =
+            add_block_reference(&pool, page_id, referencing_block_id, page_id, referenced_block_id).await.unwrap();
+
+        assert_eq!(second_id, first_id);
+        assert!(first_inserted);
+        assert!(!second_inserted);
+        let references = get_block_references_from_block(&pool, referencing_block_id).await.unwrap();
+        assert_eq!(references.len(), 1);
+        assert_eq!(references[0].id, first_id);
+    }
+
+    #[sqlx::test(migrator = "crate::db::MIGRATOR")]
+    async fn backlink_sources_come_per_page_or_per_block(pool: PgPool) {
+        let target = page_handler::create_page(&pool, "Target", serde_json::json!({}), None).await.unwrap();
+        let source = page_handler::create_page(&pool, "Source", serde_json::json!({}), None).await.unwrap();
+        let paragraph = |text: &str| {
+            serde_json::json!({"type": "paragraph", "uniqueID": Uuid::new_v4().to_string(),
+                               "children": [{"type": "text", "text": text}]})
+        };
+        let content = serde_json::json!({"root": {"type": "root", "children": [
+            paragraph("See [[Target]]"),
+            paragraph("And [[Target]] again, [[Target]]"),
+        ]}});
+        page_handler::update_page(&pool, source, None, Some(content), None, None, false, None).await.unwrap();
+
+        let pages = find_backlink_sources_for_page(&pool, target, false).await.unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!((pages[0].id, pages[0].link_count), (source, 3));
+        assert!(pages[0].block_id.is_none());
+
+        let blocks = find_backlink_sources_for_page(&pool, target, true).await.unwrap();
+        let counts: Vec<Option<i32>> = blocks.iter().map(|backlink| backlink.block_link_count).collect();
+        assert_eq!(counts, vec![Some(1), Some(2)]);
+        assert!(blocks.iter().all(|backlink| backlink.id == source && backlink.block_text.is_some()));
+    }
+}
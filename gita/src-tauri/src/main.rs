@@ -5,32 +5,81 @@
 
 mod file_system;
 mod audio;
+mod audio_encoder;
 mod db;
+mod settings;
+mod vault_import;
+mod note_sync;
+mod notes_watcher;
+mod command_error;
+mod recording_recovery;
+mod audio_playback;
+mod audio_migration;
+mod transcript_import;
+mod app_status;
+mod vault_backup;
+mod html_export;
+mod roam_import;
+mod json_utils;
+mod page_normalize;
+mod page_integrity;
+mod page_events;
+mod vault_crypto;
+mod audio_encryption;
+mod maintenance;
+mod markdown_export;
+mod markdown_import;
+mod transfer;
+mod jobs;
 pub mod dal_error;
 pub mod page_handler;
 pub mod block_handler;
 pub mod audio_handler;
 pub mod link_handler;
+pub mod sync_handler;
+pub mod tag_handler;
+pub mod alias_handler;
+pub mod saved_query_handler;
+pub mod revision_handler;
+pub mod stats_handler;
+pub mod health_handler;
+pub mod transcript_handler;
+pub mod backup_handler;
+pub mod import_handler;
 
 use dotenvy;
-use std::path::PathBuf;
-use std::sync::Mutex;
-use tauri::{AppHandle, Manager, State};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use tauri::{AppHandle, Emitter, Manager, State};
 use serde_json::Value;
 use uuid::Uuid;
+use crate::command_error::{not_found_as, parse_date, parse_uuid, CommandError};
+use crate::page_events::PageChangeKind;
 use crate::page_handler::Page as DalPage;
+use crate::page_handler::PageMetadata as DalPageMetadata;
 use crate::audio_handler::AudioRecording as DalAudioRecording;
 use crate::audio_handler::AudioTimestamp as DalAudioTimestamp;
-use crate::link_handler::BlockReference as DalBlockReference; // For the new command
+use crate::link_handler::BlockReferenceDetail as DalBlockReferenceDetail;
+use crate::link_handler::BacklinkSource as DalBacklinkSource;
+use crate::link_handler::OutgoingLinkPage as DalOutgoingLinkPage;
+use crate::link_handler::UnresolvedLink as DalUnresolvedLink;
+use crate::block_handler::Block as DalBlock;
+use crate::block_handler::BlockSearchResult as DalBlockSearchResult;
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 struct CommandAudioRecording {
     id: String,
     page_id: Option<String>,
     file_path: String,
+    secondary_file_path: Option<String>, // System audio of a split recording; file_path is the mic
+    track_mode: String,
     mime_type: Option<String>,
     duration_ms: Option<i32>,
     created_at: String,
+    title: Option<String>,
+    description: Option<String>,
 }
 
 impl From<DalAudioRecording> for CommandAudioRecording {
@@ -39,13 +88,52 @@ impl From<DalAudioRecording> for CommandAudioRecording {
             id: ar.id.to_string(),
             page_id: ar.page_id.map(|uuid| uuid.to_string()),
             file_path: ar.file_path,
+            secondary_file_path: ar.secondary_file_path,
+            track_mode: ar.track_mode,
             mime_type: ar.mime_type,
             duration_ms: ar.duration_ms,
             created_at: ar.created_at.to_rfc3339(),
+            title: ar.title,
+            description: ar.description,
+        }
+    }
+}
+
+// Returned by stop_recording: the saved recording plus any silent stretches marked in it and
+// its final stream counters. An incomplete recording has no saved row; its fields are absent.
+#[derive(serde::Serialize, Debug)]
+struct CommandStoppedRecording {
+    #[serde(flatten)]
+    recording: Option<CommandAudioRecording>,
+    incomplete: bool,
+    silence_ranges: Vec<audio::SilenceRange>,
+    diagnostics: Option<audio::RecordingDiagnostics>,
+}
+
+impl From<audio::StoppedRecording> for CommandStoppedRecording {
+    fn from(stopped: audio::StoppedRecording) -> Self {
+        CommandStoppedRecording {
+            recording: stopped.recording.map(CommandAudioRecording::from),
+            incomplete: stopped.incomplete,
+            silence_ranges: stopped.silence_ranges,
+            diagnostics: stopped.diagnostics,
         }
     }
 }
 
+// Returned by stop_all_recordings
+#[derive(serde::Serialize, Debug)]
+struct CommandStopAllRecordings {
+    stopped: Vec<CommandStoppedRecording>,
+    failures: Vec<CommandRecordingFailure>,
+}
+
+#[derive(serde::Serialize, Debug)]
+struct CommandRecordingFailure {
+    recording_id: String,
+    error: String,
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 struct CommandAudioTimestamp {
     id: String,
@@ -67,12 +155,46 @@ impl From<DalAudioTimestamp> for CommandAudioTimestamp {
     }
 }
 
+// An audio timestamp plus the recording it points into
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct CommandBlockAudioTimestamp {
+    #[serde(flatten)]
+    timestamp: CommandAudioTimestamp,
+    file_path: String,
+    mime_type: Option<String>,
+    duration_ms: Option<i32>,
+    recording_created_at: String,
+}
+
+impl From<audio_handler::BlockAudioTimestamp> for CommandBlockAudioTimestamp {
+    fn from(at: audio_handler::BlockAudioTimestamp) -> Self {
+        CommandBlockAudioTimestamp {
+            timestamp: CommandAudioTimestamp {
+                id: at.id.to_string(),
+                audio_recording_id: at.audio_recording_id.to_string(),
+                block_id: at.block_id.to_string(),
+                timestamp_ms: at.timestamp_ms,
+                created_at: at.created_at.to_rfc3339(),
+            },
+            file_path: at.file_path,
+            mime_type: at.mime_type,
+            duration_ms: at.duration_ms,
+            recording_created_at: at.recording_created_at.to_rfc3339(),
+        }
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 struct CommandPageMetadata {
     id: String,
     title: String,
     created_at: String,
     updated_at: String,
+    content_updated_at: String, // When the content last changed; updated_at also moves on metadata changes
+    deleted_at: Option<String>,
+    is_favorite: bool,
+    favorite_order: Option<i32>,
+    tags: Vec<String>,
 }
 
 impl From<DalPage> for CommandPageMetadata {
@@ -82,10 +204,116 @@ impl From<DalPage> for CommandPageMetadata {
             title: page.title,
             created_at: page.created_at.to_rfc3339(),
             updated_at: page.updated_at.to_rfc3339(),
+            content_updated_at: page.content_updated_at.to_rfc3339(),
+            deleted_at: page.deleted_at.map(|dt| dt.to_rfc3339()),
+            is_favorite: page.is_favorite,
+            favorite_order: page.favorite_order,
+            tags: page.tags,
+        }
+    }
+}
+
+impl From<DalPageMetadata> for CommandPageMetadata {
+    fn from(page: DalPageMetadata) -> Self {
+        CommandPageMetadata {
+            id: page.id.to_string(),
+            title: page.title,
+            created_at: page.created_at.to_rfc3339(),
+            updated_at: page.updated_at.to_rfc3339(),
+            content_updated_at: page.content_updated_at.to_rfc3339(),
+            deleted_at: page.deleted_at.map(|dt| dt.to_rfc3339()),
+            is_favorite: page.is_favorite,
+            favorite_order: page.favorite_order,
+            tags: page.tags,
+        }
+    }
+}
+
+// Backlink entry: the source page's metadata plus the block that contains the link, if known.
+// A page linking from several blocks gives one entry per block. link_count is how often the
+// page links here in total, block_link_count how often that block does.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct CommandBacklink {
+    #[serde(flatten)]
+    page: CommandPageMetadata,
+    linked_at: String,
+    link_count: i32,
+    block_id: Option<String>,
+    block_text: Option<String>,
+    block_link_count: Option<i32>,
+}
+
+impl From<DalBacklinkSource> for CommandBacklink {
+    fn from(backlink: DalBacklinkSource) -> Self {
+        CommandBacklink {
+            page: CommandPageMetadata {
+                id: backlink.id.to_string(),
+                title: backlink.title,
+                created_at: backlink.created_at.to_rfc3339(),
+                updated_at: backlink.updated_at.to_rfc3339(),
+                content_updated_at: backlink.content_updated_at.to_rfc3339(),
+                deleted_at: backlink.deleted_at.map(|dt| dt.to_rfc3339()),
+                is_favorite: backlink.is_favorite,
+                favorite_order: backlink.favorite_order,
+                tags: backlink.tags,
+            },
+            linked_at: backlink.linked_at.to_rfc3339(),
+            link_count: backlink.link_count,
+            block_id: backlink.block_id.map(|id| id.to_string()),
+            block_text: backlink.block_text,
+            block_link_count: backlink.block_link_count,
+        }
+    }
+}
+
+// Outgoing link entry: the target page's metadata plus when the link was stored
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct CommandOutgoingLink {
+    #[serde(flatten)]
+    page: CommandPageMetadata,
+    linked_at: String,
+}
+
+impl From<DalOutgoingLinkPage> for CommandOutgoingLink {
+    fn from(link: DalOutgoingLinkPage) -> Self {
+        CommandOutgoingLink {
+            page: CommandPageMetadata {
+                id: link.id.to_string(),
+                title: link.title,
+                created_at: link.created_at.to_rfc3339(),
+                updated_at: link.updated_at.to_rfc3339(),
+                content_updated_at: link.content_updated_at.to_rfc3339(),
+                deleted_at: link.deleted_at.map(|dt| dt.to_rfc3339()),
+                is_favorite: link.is_favorite,
+                favorite_order: link.favorite_order,
+                tags: link.tags,
+            },
+            linked_at: link.linked_at.to_rfc3339(),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct CommandUnresolvedLink {
+    title: String,
+    created_at: String,
+}
+
+impl From<DalUnresolvedLink> for CommandUnresolvedLink {
+    fn from(link: DalUnresolvedLink) -> Self {
+        CommandUnresolvedLink {
+            title: link.target_title,
+            created_at: link.created_at.to_rfc3339(),
         }
     }
 }
 
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct CommandOutgoingLinks {
+    links: Vec<CommandOutgoingLink>,
+    unresolved: Vec<CommandUnresolvedLink>, // `[[Titles]]` with no page yet
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 struct CommandPage {
     id: String,
@@ -94,6 +322,10 @@ struct CommandPage {
     raw_markdown: Option<String>,
     created_at: String,
     updated_at: String,
+    deleted_at: Option<String>,
+    is_template: bool,
+    tags: Vec<String>,
+    aliases: Vec<String>,
 }
 
 impl From<DalPage> for CommandPage {
@@ -105,28 +337,182 @@ impl From<DalPage> for CommandPage {
             raw_markdown: page.raw_markdown,
             created_at: page.created_at.to_rfc3339(),
             updated_at: page.updated_at.to_rfc3339(),
+            deleted_at: page.deleted_at.map(|dt| dt.to_rfc3339()),
+            is_template: page.is_template,
+            tags: page.tags,
+            aliases: page.aliases,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct CommandBlock {
+    id: String,
+    page_id: String,
+    parent_block_id: Option<String>,
+    block_type: Option<String>,
+    order_index: i32,
+    content_text: Option<String>,
+    task_state: Option<String>,
+    completed_at: Option<String>,
+    created_at: String,
+    updated_at: String,
+}
+
+impl From<DalBlock> for CommandBlock {
+    fn from(block: DalBlock) -> Self {
+        CommandBlock {
+            id: block.id.to_string(),
+            page_id: block.page_id.to_string(),
+            parent_block_id: block.parent_block_id.map(|id| id.to_string()),
+            block_type: block.block_type,
+            order_index: block.order_index,
+            content_text: block.content_text,
+            task_state: block.task_state,
+            completed_at: block.completed_at.map(|dt| dt.to_rfc3339()),
+            created_at: block.created_at.to_rfc3339(),
+            updated_at: block.updated_at.to_rfc3339(),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct CommandTag {
+    id: String,
+    name: String,
+    page_count: i64,
+}
+
+impl From<tag_handler::TagWithCount> for CommandTag {
+    fn from(tag: tag_handler::TagWithCount) -> Self {
+        CommandTag {
+            id: tag.id.to_string(),
+            name: tag.name,
+            page_count: tag.page_count,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct CommandBlockWithTimestamp {
+    block: CommandBlock,
+    timestamp: Option<CommandAudioTimestamp>, // None when no recording was running for the page
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct CommandGraphNode {
+    id: String,
+    title: String,
+    backlink_count: i64,
+    updated_at: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct CommandGraphEdge {
+    source: String,
+    target: String,
+    weight: i64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct CommandGraphData {
+    nodes: Vec<CommandGraphNode>,
+    edges: Vec<CommandGraphEdge>,
+}
+
+impl From<link_handler::GraphData> for CommandGraphData {
+    fn from(graph: link_handler::GraphData) -> Self {
+        CommandGraphData {
+            nodes: graph
+                .nodes
+                .into_iter()
+                .map(|node| CommandGraphNode {
+                    id: node.id.to_string(),
+                    title: node.title,
+                    backlink_count: node.backlink_count,
+                    updated_at: node.updated_at.to_rfc3339(),
+                })
+                .collect(),
+            edges: graph
+                .edges
+                .into_iter()
+                .map(|edge| CommandGraphEdge {
+                    source: edge.source.to_string(),
+                    target: edge.target.to_string(),
+                    weight: edge.weight,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct CommandBlockSearchResult {
+    block_id: String,
+    page_id: String,
+    page_title: String,
+    content_text: String,
+}
+
+impl From<DalBlockSearchResult> for CommandBlockSearchResult {
+    fn from(result: DalBlockSearchResult) -> Self {
+        CommandBlockSearchResult {
+            block_id: result.block_id.to_string(),
+            page_id: result.page_id.to_string(),
+            page_title: result.page_title,
+            content_text: result.content_text,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct CommandBacklinkSource {
+    page_id: String,
+    title: String,
+    updated_at: String,
+}
+
+impl From<DalBacklinkSource> for CommandBacklinkSource {
+    fn from(source: DalBacklinkSource) -> Self {
+        CommandBacklinkSource {
+            page_id: source.id.to_string(),
+            title: source.title,
+            updated_at: source.updated_at.to_rfc3339(),
         }
     }
 }
 
-// New struct for Block References to be sent over Tauri command
+// A page together with everything needed to render its backlinks and block reference badges
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct CommandPageWithReferences {
+    page: CommandPage,
+    backlink_count: usize,
+    backlinks: Vec<CommandBacklinkSource>,
+    block_reference_counts: HashMap<String, i64>, // block_id -> incoming reference count
+}
+
+// An incoming block reference sent over Tauri command, with what the panel needs to render it
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 struct CommandBlockReference {
     id: String,
     referencing_page_id: String,
+    referencing_page_title: String,
     referencing_block_id: String,
+    referencing_block_snippet: String,
     referenced_page_id: String,
     referenced_block_id: String,
     created_at: String,
 }
 
 // Conversion from the DAL struct to the Command struct
-impl From<DalBlockReference> for CommandBlockReference {
-    fn from(br: DalBlockReference) -> Self {
+impl From<DalBlockReferenceDetail> for CommandBlockReference {
+    fn from(br: DalBlockReferenceDetail) -> Self {
         CommandBlockReference {
             id: br.id.to_string(),
             referencing_page_id: br.referencing_page_id.to_string(),
+            referencing_page_title: br.referencing_page_title,
             referencing_block_id: br.referencing_block_id.to_string(),
+            referencing_block_snippet: br.referencing_block_snippet,
             referenced_page_id: br.referenced_page_id.to_string(),
             referenced_block_id: br.referenced_block_id.to_string(),
             created_at: br.created_at.to_rfc3339(),
@@ -134,12 +520,125 @@ impl From<DalBlockReference> for CommandBlockReference {
     }
 }
 
+// A block reference made from a page, as sent over Tauri command
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct CommandOutgoingBlockReference {
+    id: String,
+    referenced_page_id: String,
+    referenced_page_title: String,
+    referenced_block_id: String,
+    referenced_block_text: Option<String>, // None when the referenced block was deleted
+    created_at: String,
+}
+
+// One block of a page and the block references made from it
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct CommandReferencingBlock {
+    block_id: String,
+    block_text: Option<String>,
+    references: Vec<CommandOutgoingBlockReference>,
+}
+
+impl From<link_handler::ReferencingBlock> for CommandReferencingBlock {
+    fn from(block: link_handler::ReferencingBlock) -> Self {
+        CommandReferencingBlock {
+            block_id: block.block_id.to_string(),
+            block_text: block.block_text,
+            references: block
+                .references
+                .into_iter()
+                .map(|reference| CommandOutgoingBlockReference {
+                    id: reference.id.to_string(),
+                    referenced_page_id: reference.referenced_page_id.to_string(),
+                    referenced_page_title: reference.referenced_page_title,
+                    referenced_block_id: reference.referenced_block_id.to_string(),
+                    referenced_block_text: reference.referenced_block_text,
+                    created_at: reference.created_at.to_rfc3339(),
+                })
+                .collect(),
+        }
+    }
+}
+
+// Returned by redirect_block_references
+#[derive(serde::Serialize, Debug)]
+struct CommandBlockReferenceRedirect {
+    pages_modified: Vec<String>,
+    failures: Vec<page_handler::RedirectFailure>,
+}
 
 // Define a struct to hold the database connection
 struct AppState {
-    pool: sqlx::PgPool,
-    notes_dir: Mutex<PathBuf>,
-    audio_dir: Mutex<PathBuf>,
+    pool: RwLock<Option<sqlx::PgPool>>, // None while the database is unreachable
+    db_error: Mutex<Option<String>>,    // Last connection error, shown by get_db_status
+    settings: Mutex<settings::Settings>,
+    app_data_dir: PathBuf,
+    notes_dir: tokio::sync::RwLock<PathBuf>, // Async locks: read in async commands, can't be poisoned
+    audio_dir: tokio::sync::RwLock<PathBuf>,
+    notes_watcher: Mutex<Option<notes_watcher::NotesWatcher>>, // None when watching is disabled
+    vault_key: RwLock<Option<vault_crypto::VaultKey>>,          // Only while the vault is unlocked
+    vault_switch: tokio::sync::Mutex<()>,                       // Held while switch_vault swaps the fields above
+    jobs: jobs::JobRegistry,
+}
+
+impl AppState {
+    // Cheap clone of the current pool, or a friendly error if the database isn't connected
+    fn pool(&self) -> Result<sqlx::PgPool, CommandError> {
+        self.pool
+            .read()
+            .map_err(|_| "Failed to acquire database pool lock".to_string())?
+            .clone()
+            .ok_or_else(|| CommandError::database("Database is not connected. Check the connection settings."))
+    }
+
+    async fn notes_directory(&self) -> Result<String, CommandError> {
+        let notes_dir = self.notes_dir.read().await;
+        Ok(notes_dir
+            .to_str()
+            .map(|s| s.to_string())
+            .ok_or_else(|| "Notes directory path is not valid UTF-8".to_string())?)
+    }
+
+    async fn audio_directory(&self) -> Result<String, CommandError> {
+        let audio_dir = self.audio_dir.read().await;
+        Ok(audio_dir
+            .to_str()
+            .map(|s| s.to_string())
+            .ok_or_else(|| "Audio directory path is not valid UTF-8".to_string())?)
+    }
+
+    // The unlocked vault key, or None while the vault is locked (or has no passphrase)
+    fn vault_key(&self) -> Result<Option<vault_crypto::VaultKey>, CommandError> {
+        Ok(self
+            .vault_key
+            .read()
+            .map_err(|_| "Failed to acquire vault key lock".to_string())?
+            .clone())
+    }
+
+    // The key needed to read path: None for a plain file, and a vault_locked error for an
+    // encrypted one while the vault is locked
+    fn vault_key_for_file(&self, path: &Path) -> Result<Option<vault_crypto::VaultKey>, CommandError> {
+        if !vault_crypto::is_encrypted(path)? {
+            return Ok(None);
+        }
+        self.vault_key()?
+            .map(Some)
+            .ok_or_else(|| CommandError::vault_locked(vault_crypto::LOCKED_MESSAGE))
+    }
+}
+
+// Connects using the database settings, producing a user-facing error message on failure
+async fn connect_database(db_settings: &settings::DatabaseSettings) -> Result<sqlx::PgPool, String> {
+    let url = db_settings.resolved_url().ok_or_else(|| {
+        "No database URL configured. Set one in the app settings or via the DATABASE_URL environment variable.".to_string()
+    })?;
+    let pool = db::init_pool(&url, &db_settings.pool_config())
+        .await
+        .map_err(|e| format!("Could not connect to the database at {}: {}", settings::redact_database_url(&url), e))?;
+    // Commands only see the pool once the schema is up to date
+    db::run_migrations(&pool).await.map_err(|e| e.to_string())?;
+    Ok(pool)
 }
 
 // Initialize the app state
@@ -152,300 +651,3205 @@ async fn init_app_state(app_handle: &AppHandle) -> Result<AppState, Box<dyn std:
     
     // Create the app data directory if it doesn't exist
     std::fs::create_dir_all(&app_data_dir)?;
+
+    let app_settings = settings::load_or_create(&app_data_dir)?;
     
-    // Initialize the database
-    let pool = db::init_pool().await?;
+    let active_vault = app_settings.active_vault_config();
+    if active_vault.is_none() {
+        if let Some(name) = &app_settings.active_vault {
+            eprintln!("Vault '{}' is no longer configured; using the database settings", name);
+        }
+    }
+
+    // Initialize the database. An unreachable database is not fatal: the state is still managed
+    // so the frontend can report the problem and fix the URL via set_database_url.
+    let (pool, db_error) = match connect_database(&app_settings.database_for(active_vault)).await {
+        Ok(pool) => (Some(pool), None),
+        Err(e) => {
+            eprintln!("{}", e);
+            (None, Some(e))
+        }
+    };
     
-    // Set default notes and audio directories
-    let notes_dir = app_data_dir.join("notes");
-    let audio_dir = app_data_dir.join("audio");
+    // The active vault's notes and audio directories, or the default ones
+    let (notes_dir, audio_dir) = settings::Settings::directories_for(active_vault, &app_data_dir);
     
     // Create the directories if they don't exist
     std::fs::create_dir_all(&notes_dir)?;
     std::fs::create_dir_all(&audio_dir)?;
+
+    if let Some(pool) = &pool {
+        prepare_database(app_handle, pool, &audio_dir).await;
+    }
+
+    // A watcher that fails to start only disables change events
+    let watcher = match notes_watcher::NotesWatcher::start(app_handle.clone(), &notes_dir) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            eprintln!("{}", e);
+            None
+        }
+    };
     
     Ok(AppState {
-        pool,
-        notes_dir: Mutex::new(notes_dir),
-        audio_dir: Mutex::new(audio_dir),
+        pool: RwLock::new(pool),
+        db_error: Mutex::new(db_error),
+        settings: Mutex::new(app_settings),
+        app_data_dir,
+        notes_dir: tokio::sync::RwLock::new(notes_dir),
+        audio_dir: tokio::sync::RwLock::new(audio_dir),
+        notes_watcher: Mutex::new(watcher),
+        vault_key: RwLock::new(None),
+        vault_switch: tokio::sync::Mutex::new(()),
+        jobs: jobs::JobRegistry::default(),
     })
 }
 
-// Command to get the notes directory
-#[tauri::command]
-fn get_notes_directory(state: State<AppState>) -> Result<String, String> {
-    let notes_dir = state.notes_dir.lock().map_err(|_| "Failed to acquire notes directory lock".to_string())?;
-    notes_dir.to_str().map(|s| s.to_string()).ok_or_else(|| "Notes directory path is not valid UTF-8".to_string())
+// Startup work on a newly connected database, before commands use it
+async fn prepare_database(app_handle: &AppHandle, pool: &sqlx::PgPool, audio_dir: &Path) {
+    // Recordings interrupted by a crash are salvaged before anything else touches the audio directory
+    if let Err(e) = recover_and_report_recordings(app_handle, pool, audio_dir).await {
+        eprintln!("Recording recovery failed: {}", e);
+    }
+    // Block references left behind by blocks deleted outside a page save
+    match link_handler::remove_dangling_block_references(pool).await {
+        Ok(0) => {}
+        Ok(removed) => println!("Removed {} dangling block references", removed),
+        Err(e) => eprintln!("Failed to remove dangling block references: {}", e),
+    }
+    // Rows that drifted from the content are only reported here; verify_all_pages fixes them.
+    // Runs in the background, as it reads every page.
+    let (app_handle, pool) = (app_handle.clone(), pool.clone());
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = page_integrity::verify_all_pages(&pool, &app_handle, false).await {
+            eprintln!("Page integrity check failed: {}", e);
+        }
+    });
 }
 
-// Command to set the notes directory
-#[tauri::command]
-fn set_notes_directory(state: State<AppState>, path: &str) -> Result<(), String> {
-    let path = PathBuf::from(path);
-    
-    // Check if the directory exists
-    if !path.exists() {
-        return Err("Directory does not exist".to_string());
+// Runs crash recovery on the audio directory and tells the frontend what was found
+async fn recover_and_report_recordings(
+    app_handle: &AppHandle,
+    pool: &sqlx::PgPool,
+    audio_dir: &Path,
+) -> Result<recording_recovery::RecoveryReport, String> {
+    let report = recording_recovery::recover_recordings(pool, audio_dir).await?;
+    if !report.is_empty() {
+        if let Err(e) = app_handle.emit(audio::EVENT_RECORDING_RECOVERED, report.clone()) {
+            eprintln!("Failed to emit recording recovery event: {}", e);
+        }
     }
-    
-    // Check if the directory is readable
-    if std::fs::metadata(&path).map_err(|e| e.to_string())?.permissions().readonly() {
-        return Err("Directory is not writable".to_string());
+    Ok(report)
+}
+
+// Stops recordings whose microphone or loopback device was unplugged, or whose writer thread
+// hit a duration or disk space limit, for as long as the app runs
+async fn watch_active_recordings(app_handle: AppHandle) {
+    let mut interval = tokio::time::interval(audio::DEVICE_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        if !audio::has_active_recordings() {
+            continue;
+        }
+        let lost_devices = match tokio::task::spawn_blocking(audio::find_recordings_with_lost_devices).await {
+            Ok(lost_devices) => lost_devices,
+            Err(e) => {
+                eprintln!("Failed to check recording devices: {}", e);
+                continue;
+            }
+        };
+        let pool = app_handle.try_state::<AppState>().and_then(|state| state.pool().ok());
+        for request in lost_devices.into_iter().chain(audio::find_auto_stopped_recordings()) {
+            audio::stop_recording_automatically(request, pool.as_ref(), &app_handle).await;
+        }
     }
-    
-    // Update the notes directory
-    let mut notes_dir = state.notes_dir.lock().map_err(|_| "Failed to acquire notes directory lock".to_string())?;
-    *notes_dir = path;
-    
-    Ok(())
 }
 
-// Command to get the audio directory
-#[tauri::command]
-fn get_audio_directory(state: State<AppState>) -> Result<String, String> {
-    let audio_dir = state.audio_dir.lock().map_err(|_| "Failed to acquire audio directory lock".to_string())?;
-    audio_dir.to_str().map(|s| s.to_string()).ok_or_else(|| "Audio directory path is not valid UTF-8".to_string())
+// Runs the database maintenance whenever settings.maintenance.interval_hours have passed since
+// the last run (by this or another app instance sharing the database), for as long as the app
+// runs
+async fn schedule_maintenance(app_handle: AppHandle) {
+    let mut interval = tokio::time::interval(maintenance::SCHEDULE_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(state) = app_handle.try_state::<AppState>() else {
+            continue; // Still starting up
+        };
+        let Ok(pool) = state.pool() else {
+            continue;
+        };
+        let Ok(maintenance_settings) = state.settings.lock().map(|app_settings| app_settings.maintenance.clone()) else {
+            continue;
+        };
+        if !maintenance_settings.enabled {
+            continue;
+        }
+        let run_interval = std::time::Duration::from_secs(maintenance_settings.interval_hours.saturating_mul(3600));
+        let due = match maintenance::get_last_report(&pool).await {
+            Ok(last) => last.is_none_or(|last| {
+                (chrono::Utc::now() - last.finished_at).to_std().is_ok_and(|elapsed| elapsed >= run_interval)
+            }),
+            Err(e) => {
+                eprintln!("[Maintenance] Failed to read the last run: {}", e);
+                false
+            }
+        };
+        if !due {
+            continue;
+        }
+        let result = match maintenance_options(&state, true) {
+            Ok(options) => maintenance::run_maintenance(&pool, &options).await.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        // Ok(None): another app instance is running it
+        if let Err(e) = result {
+            eprintln!("[Maintenance] Scheduled run failed: {}", e);
+        }
+    }
 }
 
-// Command to set the audio directory
-#[tauri::command]
-fn set_audio_directory(state: State<AppState>, path: &str) -> Result<(), String> {
-    let path = PathBuf::from(path);
-    
-    // Check if the directory exists
-    if !path.exists() {
-        return Err("Directory does not exist".to_string());
+fn maintenance_options(state: &AppState, scheduled: bool) -> Result<maintenance::MaintenanceOptions, CommandError> {
+    let revision_retention_days = state
+        .settings
+        .lock()
+        .map_err(|_| "Failed to acquire settings lock".to_string())?
+        .maintenance
+        .revision_retention_days;
+    Ok(maintenance::MaintenanceOptions {
+        revision_retention_days,
+        active_recording_ids: audio::active_recording_ids(),
+        scheduled,
+    })
+}
+
+// Set while exit is put off for running recordings to be saved
+static FINISHING_RECORDINGS: AtomicBool = AtomicBool::new(false);
+
+// Puts off closing the window or exiting while recordings are running. They're stopped and
+// saved (or left for recovery once audio::SHUTDOWN_TIMEOUT passes) and then the app exits.
+// Returns whether the close or exit has to be prevented.
+fn finish_recordings_before_exit(app_handle: &AppHandle) -> bool {
+    if FINISHING_RECORDINGS.load(Ordering::SeqCst) {
+        return true;
     }
-    
-    // Check if the directory is readable
-    if std::fs::metadata(&path).map_err(|e| e.to_string())?.permissions().readonly() {
-        return Err("Directory is not writable".to_string());
+    if !audio::has_active_recordings() {
+        return false;
     }
-    
-    // Update the audio directory
-    let mut audio_dir = state.audio_dir.lock().map_err(|_| "Failed to acquire audio directory lock".to_string())?;
-    *audio_dir = path;
-    
-    Ok(())
+    FINISHING_RECORDINGS.store(true, Ordering::SeqCst);
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        let pool = app_handle.try_state::<AppState>().and_then(|state| state.pool().ok());
+        audio::stop_all_recordings(pool.as_ref(), &app_handle, audio::SHUTDOWN_TIMEOUT).await;
+        FINISHING_RECORDINGS.store(false, Ordering::SeqCst);
+        app_handle.exit(0);
+    });
+    true
 }
 
-// Command to get all notes
-#[tauri::command]
-async fn get_all_notes(state: State<'_, AppState>) -> Result<Vec<CommandPageMetadata>, String> {
-    let pages = page_handler::list_pages(&state.pool)
-        .await
-        .map_err(|e| e.to_string())?;
-
-    let result: Vec<CommandPageMetadata> = pages.into_iter().map(CommandPageMetadata::from).collect();
-    Ok(result)
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct CommandDbStatus {
+    connected: bool,
+    database_url: Option<String>, // Password redacted
+    error: Option<String>,
+    config_path: String,
 }
 
-// Command to search notes
-#[tauri::command]
-async fn search_notes(state: State<'_, AppState>, query: String) -> Result<Vec<CommandPageMetadata>, String> {
-    let pages = page_handler::search_pages(&state.pool, &query)
-        .await
-        .map_err(|e| e.to_string())?;
-    let result: Vec<CommandPageMetadata> = pages.into_iter().map(CommandPageMetadata::from).collect();
-    Ok(result)
+fn db_status(state: &AppState) -> Result<CommandDbStatus, CommandError> {
+    let connected = state.pool.read().map_err(|_| "Failed to acquire database pool lock".to_string())?.is_some();
+    let error = state.db_error.lock().map_err(|_| "Failed to acquire database status lock".to_string())?.clone();
+    let database_url = {
+        let app_settings = state.settings.lock().map_err(|_| "Failed to acquire settings lock".to_string())?;
+        app_settings
+            .database_for(app_settings.active_vault_config())
+            .resolved_url()
+            .map(|url| settings::redact_database_url(&url))
+    };
+    Ok(CommandDbStatus {
+        connected,
+        database_url,
+        error,
+        config_path: settings::config_path(&state.app_data_dir).to_string_lossy().to_string(),
+    })
 }
 
-// New get_page_details function (replaces read_markdown_file)
+// Command to report whether the database is connected and why not if it isn't
 #[tauri::command]
-async fn get_page_details(state: State<'_, AppState>, id: String) -> Result<CommandPage, String> {
-    let page_uuid = Uuid::parse_str(&id).map_err(|e| format!("Invalid page ID format: {}", e))?;
-    let page = page_handler::get_page(&state.pool, page_uuid)
-        .await
-        .map_err(|e| e.to_string())?
-        .ok_or_else(|| format!("Page with ID {} not found", id))?;
-    Ok(CommandPage::from(page))
+fn get_db_status(state: State<AppState>) -> Result<CommandDbStatus, CommandError> {
+    db_status(&state)
 }
 
-// New update_page_content function (replaces write_markdown_file)
+// Command to get the applied and expected schema versions, e.g. to detect an outdated app binary
 #[tauri::command]
-async fn update_page_content(
-    state: State<'_, AppState>,
-    id: String,
-    title: Option<String>,
-    raw_markdown: Option<String>,
-    content_json: Option<Value>, // Allow updating content_json too
-) -> Result<bool, String> {
-    let page_uuid = Uuid::parse_str(&id).map_err(|e| format!("Invalid page ID format: {}", e))?;
-
-    // Prepare Option<&str> for title and raw_markdown
-    let title_ref = title.as_deref();
-    // let raw_markdown_ref = raw_markdown.as_deref();
-
-    let updated = page_handler::update_page(
-        &state.pool,
-        page_uuid,
-        title_ref,
-        content_json, // Pass content_json directly
-        raw_markdown.as_deref().map(Some), // If raw_markdown is Some(String), pass Some(Some(string_slice)). If None, pass None.
-    )
-    .await
-    .map_err(|e| e.to_string())?;
-
-    Ok(updated)
+async fn get_schema_version(state: State<'_, AppState>) -> Result<db::SchemaVersion, CommandError> {
+    db::get_schema_version(&state.pool()?)
+        .await
+        .map_err(CommandError::from)
 }
 
-// Command to create a new note
+// Command to check the backend's health in one call: database reachability (with a short
+// timeout), pool usage, schema version, the notes and audio directories and active recordings
 #[tauri::command]
-async fn create_note(
-    state: State<'_, AppState>,
-    title: String, // Changed from &str to String
-    content: String, // Changed from &str to String, assumed to be raw_markdown
-) -> Result<CommandPage, String> {
-    // For new notes, content_json could be empty or derived from raw_markdown.
-    // Here, we'll use a default empty JSON object.
-    // A more sophisticated approach might parse markdown to JSON.
-    let default_content_json = serde_json::json!({});
-
-    let new_page_id = page_handler::create_page(
-        &state.pool,
-        &title,
-        default_content_json.clone(), // Pass clone here
-        Some(&content),
-    )
-    .await
-    .map_err(|e| e.to_string())?;
+async fn get_app_status(app_handle: AppHandle, state: State<'_, AppState>) -> Result<app_status::AppStatus, CommandError> {
+    let pool = state.pool.read().map_err(|_| "Failed to acquire database pool lock".to_string())?.clone();
+    let db_error = state.db_error.lock().map_err(|_| "Failed to acquire database status lock".to_string())?.clone();
+    let notes_dir = state.notes_dir.read().await.clone();
+    let audio_dir = state.audio_dir.read().await.clone();
+    let app_version = app_handle.package_info().version.to_string();
 
-    // Fetch the created page to return its full details
-    let new_page_details = page_handler::get_page(&state.pool, new_page_id)
-        .await
-        .map_err(|e| e.to_string())?
-        .ok_or_else(|| "Failed to retrieve newly created page".to_string())?;
-
-    Ok(CommandPage::from(new_page_details))
+    Ok(app_status::get_app_status(app_version, pool, db_error, &notes_dir, &audio_dir).await)
 }
 
-// Command to create a daily note
+// Command to connect to a new database URL (or retry the current one) without restarting.
+// The URL is saved to config.toml, as the active vault's when one is, and the pool swapped
+// only if the connection succeeds. Refused while jobs are running, as they use the old pool.
 #[tauri::command]
-async fn create_daily_note(state: State<'_, AppState>) -> Result<CommandPage, String> {
-    let today_str = chrono::Local::now().format("%Y-%m-%d").to_string();
+async fn set_database_url(state: State<'_, AppState>, url: Option<String>) -> Result<CommandDbStatus, CommandError> {
+    let _switching = state.vault_switch.lock().await;
+    if state.jobs.has_running() {
+        return Err(CommandError::conflict(
+            "Wait for running jobs to finish, or cancel them, before changing the database",
+        ));
+    }
+    let mut db_settings = {
+        let app_settings = state.settings.lock().map_err(|_| "Failed to acquire settings lock".to_string())?;
+        app_settings.database_for(app_settings.active_vault_config())
+    };
+    if let Some(url) = url {
+        db_settings.url = Some(url);
+    }
 
-    // Check if daily note already exists by title
-    let existing_pages = page_handler::search_pages(&state.pool, &today_str)
-        .await
-        .map_err(|e| e.to_string())?;
+    let new_pool = match connect_database(&db_settings).await {
+        Ok(pool) => pool,
+        Err(e) => {
+            *state.db_error.lock().map_err(|_| "Failed to acquire database status lock".to_string())? = Some(e.clone());
+            return Err(CommandError::database(e));
+        }
+    };
 
-    let mut daily_page: Option<DalPage> = None;
-    for page in existing_pages {
-        if page.title == today_str {
-            daily_page = Some(page);
-            break;
+    {
+        let mut app_settings = state.settings.lock().map_err(|_| "Failed to acquire settings lock".to_string())?;
+        let active_vault = app_settings.active_vault.clone();
+        match app_settings.vaults.iter_mut().find(|vault| Some(&vault.name) == active_vault.as_ref()) {
+            Some(vault) => vault.database_url = db_settings.url.unwrap_or_default(),
+            None => app_settings.database = db_settings,
         }
+        settings::save(&state.app_data_dir, &app_settings)?;
     }
 
-    if let Some(page) = daily_page {
-        // If it exists, just return it
-        Ok(CommandPage::from(page))
-    } else {
-        // If not, create it
-        let default_content_json = serde_json::json!({
-            "type": "doc",
-            "content": [
-                { "type": "heading", "attrs": { "level": 1 }, "content": [{ "type": "text", "text": &today_str }] },
-                { "type": "paragraph" } // Add an empty paragraph
-            ]
-        });
-        let initial_markdown = format!("# {}
+    let old_pool = state
+        .pool
+        .write()
+        .map_err(|_| "Failed to acquire database pool lock".to_string())?
+        .replace(new_pool);
+    *state.db_error.lock().map_err(|_| "Failed to acquire database status lock".to_string())? = None;
+    if let Some(old_pool) = old_pool {
+        old_pool.close().await; // Waits for in-flight queries on the old pool to finish
+    }
 
-", today_str);
+    db_status(&state)
+}
 
-        let new_page_id = page_handler::create_page(
-            &state.pool,
-            &today_str,
-            default_content_json.clone(),
-            Some(&initial_markdown),
-        )
-        .await
-        .map_err(|e| e.to_string())?;
+// Emitted by switch_vault once the new vault is in use, so the frontend drops its caches
+const EVENT_VAULT_SWITCHED: &str = "vault://switched";
 
-        let new_page_details = page_handler::get_page(&state.pool, new_page_id)
-            .await
-            .map_err(|e| e.to_string())?
-            .ok_or_else(|| "Failed to retrieve newly created daily page".to_string())?;
+#[derive(serde::Serialize, Debug, Clone)]
+struct CommandVault {
+    name: String,
+    database_url: String, // Password redacted
+    notes_dir: String,
+    audio_dir: String,
+    active: bool,
+}
 
-        Ok(CommandPage::from(new_page_details))
+impl CommandVault {
+    fn new(vault: &settings::VaultConfig, active: bool) -> Self {
+        CommandVault {
+            name: vault.name.clone(),
+            database_url: settings::redact_database_url(&vault.database_url),
+            notes_dir: vault.notes_dir.clone(),
+            audio_dir: vault.audio_dir.clone(),
+            active,
+        }
     }
 }
 
-// Command to delete a note
-#[tauri::command]
-async fn delete_note(state: State<'_, AppState>, note_id: String) -> Result<bool, String> {
-    let page_uuid = Uuid::parse_str(&note_id).map_err(|e| format!("Invalid page ID format: {}", e))?;
-    page_handler::delete_page(&state.pool, page_uuid)
-        .await
-        .map_err(|e| e.to_string())
+#[derive(serde::Serialize, Debug, Clone)]
+struct VaultSwitchedEvent {
+    name: Option<String>, // None for the database settings' connection
 }
 
-// Command to find backlinks for a note
+// Command to list the configured vaults
 #[tauri::command]
-async fn find_backlinks(state: State<'_, AppState>, note_id: String) -> Result<Vec<CommandPageMetadata>, String> {
-    let page_uuid = Uuid::parse_str(&note_id).map_err(|e| format!("Invalid page ID format: {}", e))?;
-
-    let links = link_handler::find_backlinks_for_page(&state.pool, page_uuid)
-        .await
-        .map_err(|e| e.to_string())?;
+fn list_vaults(state: State<AppState>) -> Result<Vec<CommandVault>, CommandError> {
+    let app_settings = state.settings.lock().map_err(|_| "Failed to acquire settings lock".to_string())?;
+    let active = app_settings.active_vault_config().map(|vault| vault.name.clone());
+    Ok(app_settings
+        .vaults
+        .iter()
+        .map(|vault| CommandVault::new(vault, Some(&vault.name) == active.as_ref()))
+        .collect())
+}
 
-    let mut source_pages_metadata = Vec::new();
-    for link in links {
-        if let Ok(Some(page)) = page_handler::get_page(&state.pool, link.source_page_id).await {
-            source_pages_metadata.push(CommandPageMetadata::from(page));
-        }
-        // Optionally log if a source page isn't found
-    }
-    Ok(source_pages_metadata)
+// Command to get the vault in use, or null when the database settings' connection is
+#[tauri::command]
+fn get_active_vault(state: State<AppState>) -> Result<Option<CommandVault>, CommandError> {
+    let app_settings = state.settings.lock().map_err(|_| "Failed to acquire settings lock".to_string())?;
+    Ok(app_settings.active_vault_config().map(|vault| CommandVault::new(vault, true)))
 }
 
-// Command to start recording
+// Command to add a vault to the list, saved to config.toml. Its name must be new and its
+// directories must exist; the database is only connected to by switch_vault.
 #[tauri::command]
+fn add_vault(state: State<AppState>, config: settings::VaultConfig) -> Result<CommandVault, CommandError> {
+    let vault = settings::VaultConfig {
+        name: config.name.trim().to_string(),
+        database_url: config.database_url.trim().to_string(),
+        ..config
+    };
+    if vault.name.is_empty() {
+        return Err(CommandError::invalid_input("config.name", "Vault name cannot be empty"));
+    }
+    db::check_database_url(&vault.database_url).map_err(|e| CommandError::invalid_input("config.database_url", e))?;
+    for (field, dir) in [("config.notes_dir", &vault.notes_dir), ("config.audio_dir", &vault.audio_dir)] {
+        let dir = Path::new(dir);
+        if !dir.is_dir() {
+            return Err(CommandError::invalid_input(field, "Directory does not exist"));
+        }
+        file_system::check_directory_writable(dir).map_err(|e| CommandError::invalid_input(field, e))?;
+    }
+
+    let mut app_settings = state.settings.lock().map_err(|_| "Failed to acquire settings lock".to_string())?;
+    if app_settings.vaults.iter().any(|existing| existing.name.to_lowercase() == vault.name.to_lowercase()) {
+        return Err(CommandError::conflict(format!("A vault named '{}' already exists", vault.name)));
+    }
+    app_settings.vaults.push(vault.clone());
+    if let Err(e) = settings::save(&state.app_data_dir, &app_settings) {
+        app_settings.vaults.pop();
+        return Err(e.into());
+    }
+    Ok(CommandVault::new(&vault, false))
+}
+
+// Command to switch to another vault without restarting; with no name, back to the database
+// settings' connection and the directories in the app data directory. Refused while recordings
+// or jobs are running. The new database is connected and migrated first, so a vault that can't be
+// reached leaves the current one in use. Then the notes watcher (if running) moves to the new
+// notes directory, the pool and directories are swapped and the old pool is closed. Emits
+// vault://switched. Returns the vault now in use.
+#[tauri::command]
+async fn switch_vault(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    name: Option<String>,
+) -> Result<Option<CommandVault>, CommandError> {
+    let _switching = state.vault_switch.lock().await;
+    // A running recording writes into the current audio directory and database
+    if audio::has_active_recordings() {
+        return Err(CommandError::conflict("Stop all recordings before switching vaults"));
+    }
+    // Jobs write through the current pool, which is closed below
+    if state.jobs.has_running() {
+        return Err(CommandError::conflict("Wait for running jobs to finish, or cancel them, before switching vaults"));
+    }
+
+    let (vault, db_settings) = {
+        let app_settings = state.settings.lock().map_err(|_| "Failed to acquire settings lock".to_string())?;
+        let vault = match name.as_deref() {
+            Some(name) => Some(
+                app_settings
+                    .find_vault(name)
+                    .cloned()
+                    .ok_or_else(|| CommandError::not_found(format!("No vault named '{}'", name)))?,
+            ),
+            None => None,
+        };
+        let db_settings = app_settings.database_for(vault.as_ref());
+        (vault, db_settings)
+    };
+    let (notes_dir, audio_dir) = settings::Settings::directories_for(vault.as_ref(), &state.app_data_dir);
+    std::fs::create_dir_all(&notes_dir)?;
+    std::fs::create_dir_all(&audio_dir)?;
+
+    let new_pool = connect_database(&db_settings).await.map_err(CommandError::database)?;
+    {
+        let mut app_settings = state.settings.lock().map_err(|_| "Failed to acquire settings lock".to_string())?;
+        let previous = std::mem::replace(&mut app_settings.active_vault, name.clone());
+        if let Err(e) = settings::save(&state.app_data_dir, &app_settings) {
+            app_settings.active_vault = previous;
+            return Err(e.into());
+        }
+    }
+    prepare_database(&app_handle, &new_pool, &audio_dir).await;
+
+    {
+        // Held until the watcher has moved too, as in set_notes_directory
+        let mut current_notes_dir = state.notes_dir.write().await;
+        *current_notes_dir = notes_dir.clone();
+        let mut watcher = state.notes_watcher.lock().map_err(|_| "Failed to acquire notes watcher lock".to_string())?;
+        if let Some(old_watcher) = watcher.take() {
+            old_watcher.stop();
+            match notes_watcher::NotesWatcher::start(app_handle.clone(), &notes_dir) {
+                Ok(new_watcher) => *watcher = Some(new_watcher),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+    *state.audio_dir.write().await = audio_dir;
+    let old_pool = state
+        .pool
+        .write()
+        .map_err(|_| "Failed to acquire database pool lock".to_string())?
+        .replace(new_pool);
+    *state.db_error.lock().map_err(|_| "Failed to acquire database status lock".to_string())? = None;
+    if let Some(old_pool) = old_pool {
+        old_pool.close().await; // Waits for in-flight queries on the old pool to finish
+    }
+
+    println!("Switched to vault {}", name.as_deref().unwrap_or("(database settings)"));
+    if let Err(e) = app_handle.emit(EVENT_VAULT_SWITCHED, VaultSwitchedEvent { name }) {
+        eprintln!("Failed to emit vault switched event: {}", e);
+    }
+    Ok(vault.map(|vault| CommandVault::new(&vault, true)))
+}
+
+// Command to get the notes directory
+#[tauri::command]
+async fn get_notes_directory(state: State<'_, AppState>) -> Result<String, CommandError> {
+    state.notes_directory().await
+}
+
+// A path the notes or audio directory can be set to: an existing directory files can be created in
+fn check_directory(path: String) -> Result<PathBuf, CommandError> {
+    let path = PathBuf::from(path);
+    if !path.is_dir() {
+        return Err(CommandError::invalid_input("path", "Directory does not exist"));
+    }
+    file_system::check_directory_writable(&path).map_err(|e| CommandError::invalid_input("path", e))?;
+    Ok(path)
+}
+
+// Command to set the notes directory
+#[tauri::command]
+async fn set_notes_directory(app_handle: AppHandle, state: State<'_, AppState>, path: String) -> Result<(), CommandError> {
+    let path = check_directory(path)?;
+    
+    // Update the notes directory. The lock is held until the watcher has moved too, so a
+    // concurrent start_notes_watcher can't start one on the old directory.
+    let mut notes_dir = state.notes_dir.write().await;
+    *notes_dir = path.clone();
+
+    // Move a running watcher over to the new directory
+    let mut watcher = state.notes_watcher.lock().map_err(|_| "Failed to acquire notes watcher lock".to_string())?;
+    if let Some(old_watcher) = watcher.take() {
+        old_watcher.stop();
+        *watcher = Some(notes_watcher::NotesWatcher::start(app_handle, &path)?);
+    }
+    
+    Ok(())
+}
+
+// Command to start watching the notes directory for file changes (no-op if already running)
+#[tauri::command]
+async fn start_notes_watcher(app_handle: AppHandle, state: State<'_, AppState>) -> Result<(), CommandError> {
+    let notes_dir = state.notes_dir.read().await;
+    let notes_dir = notes_dir.as_path();
+    let mut watcher = state.notes_watcher.lock().map_err(|_| "Failed to acquire notes watcher lock".to_string())?;
+    if watcher.as_ref().is_some_and(|w| w.directory() == notes_dir) {
+        return Ok(());
+    }
+    if let Some(old_watcher) = watcher.take() {
+        old_watcher.stop();
+    }
+    *watcher = Some(notes_watcher::NotesWatcher::start(app_handle, notes_dir)?);
+    Ok(())
+}
+
+// Command to stop watching the notes directory
+#[tauri::command]
+fn stop_notes_watcher(state: State<AppState>) -> Result<(), CommandError> {
+    let mut watcher = state.notes_watcher.lock().map_err(|_| "Failed to acquire notes watcher lock".to_string())?;
+    if let Some(old_watcher) = watcher.take() {
+        old_watcher.stop();
+    }
+    Ok(())
+}
+
+// Command to check whether the notes directory watcher is running
+#[tauri::command]
+fn is_notes_watcher_running(state: State<AppState>) -> Result<bool, CommandError> {
+    let watcher = state.notes_watcher.lock().map_err(|_| "Failed to acquire notes watcher lock".to_string())?;
+    Ok(watcher.is_some())
+}
+
+// Command to two-way sync pages with the Markdown files in the notes directory
+#[tauri::command]
+async fn sync_notes_directory(state: State<'_, AppState>) -> Result<note_sync::SyncReport, CommandError> {
+    let notes_dir = state.notes_dir.read().await.clone();
+    Ok(note_sync::sync_notes_directory(&state.pool()?, &notes_dir).await?)
+}
+
+// Command to get the audio directory
+#[tauri::command]
+async fn get_audio_directory(state: State<'_, AppState>) -> Result<String, CommandError> {
+    state.audio_directory().await
+}
+
+// Command to set the audio directory. With migrate, every recording's file is moved there first
+// (emitting audio-migration://progress events) and the summary is returned; recordings whose
+// file is missing or couldn't be moved keep their old path.
+#[tauri::command]
+async fn set_audio_directory(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    path: String,
+    migrate: Option<bool>,
+) -> Result<Option<audio_migration::AudioMigrationSummary>, CommandError> {
+    let path = check_directory(path)?;
+    
+    let mut summary = None;
+    if migrate.unwrap_or(false) {
+        // A running recording's file is still being written
+        if audio::has_active_recordings() {
+            return Err(CommandError::conflict("Stop all recordings before moving the audio directory"));
+        }
+        summary = Some(audio_migration::migrate_audio_directory(&state.pool()?, &app_handle, &path).await?);
+    }
+
+    // Update the audio directory
+    *state.audio_dir.write().await = path;
+    
+    Ok(summary)
+}
+
+// Default and maximum number of rows returned by paginated listing commands
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+// Resolves optional limit/offset command parameters into sane values
+fn resolve_pagination(limit: Option<i64>, offset: Option<i64>) -> Result<(i64, i64), CommandError> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    let offset = offset.unwrap_or(0);
+    if limit <= 0 {
+        return Err(CommandError::invalid_input("limit", "limit must be greater than zero"));
+    }
+    if offset < 0 {
+        return Err(CommandError::invalid_input("offset", "offset cannot be negative"));
+    }
+    Ok((limit.min(MAX_PAGE_SIZE), offset))
+}
+
+// Command to get all notes, most recently edited first unless sort says otherwise
+// ("recently-created" or "title")
+#[tauri::command]
+async fn get_all_notes(
+    state: State<'_, AppState>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    sort: Option<page_handler::PageSort>,
+) -> Result<Vec<CommandPageMetadata>, CommandError> {
+    let (limit, offset) = resolve_pagination(limit, offset)?;
+    let sort = sort.unwrap_or_default();
+    let pool = state.pool()?;
+    let pages = db::with_retry(|| page_handler::list_pages(&pool, limit, offset, sort)).await?;
+
+    let result: Vec<CommandPageMetadata> = pages.into_iter().map(CommandPageMetadata::from).collect();
+    Ok(result)
+}
+
+// Command to count all notes (excluding trashed pages), used to size paginated lists
+#[tauri::command]
+async fn count_notes(state: State<'_, AppState>) -> Result<i64, CommandError> {
+    let pool = state.pool()?;
+    db::with_retry(|| page_handler::count_pages(&pool))
+        .await
+        .map_err(CommandError::from)
+}
+
+// Search result: the page's metadata plus the filters it matched
+#[derive(serde::Serialize, Debug)]
+struct CommandSearchResult {
+    #[serde(flatten)]
+    page: CommandPageMetadata,
+    matched_filters: Vec<&'static str>,
+}
+
+// Rejects filters this version can't read and date ranges that end before they start
+fn validate_search_filter(filter: &page_handler::SearchFilter) -> Result<(), CommandError> {
+    if filter.version == 0 || filter.version > page_handler::SEARCH_FILTER_VERSION {
+        return Err(CommandError::invalid_input(
+            "filter.version",
+            format!("Unsupported filter version {}", filter.version),
+        ));
+    }
+    if let (Some(from), Some(to)) = (filter.created_from, filter.created_to) {
+        if from > to {
+            return Err(CommandError::invalid_input("filter.created_from", "created_from must not be after created_to"));
+        }
+    }
+    if let (Some(from), Some(to)) = (filter.updated_from, filter.updated_to) {
+        if from > to {
+            return Err(CommandError::invalid_input("filter.updated_from", "updated_from must not be after updated_to"));
+        }
+    }
+    Ok(())
+}
+
+// Command to search notes by title, optionally narrowed by a filter (dates, tag, links,
+// recordings, daily notes)
+#[tauri::command]
+async fn search_notes(
+    state: State<'_, AppState>,
+    query: String,
+    filter: Option<Value>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<Vec<CommandSearchResult>, CommandError> {
+    let (limit, offset) = resolve_pagination(limit, offset)?;
+    let filter = match filter {
+        Some(filter) => parse_search_filter(filter, "filter")?,
+        None => page_handler::SearchFilter::default(),
+    };
+
+    let pool = state.pool()?;
+    run_search(&pool, &query, &filter, limit, offset).await
+}
+
+async fn run_search(
+    pool: &sqlx::PgPool,
+    query: &str,
+    filter: &page_handler::SearchFilter,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<CommandSearchResult>, CommandError> {
+    let pages = db::with_retry(|| page_handler::search_pages(pool, query, filter, limit, offset)).await?;
+    let matched_filters = filter.active_filters();
+    let result = pages
+        .into_iter()
+        .map(|page| CommandSearchResult {
+            page: CommandPageMetadata::from(page),
+            matched_filters: matched_filters.clone(),
+        })
+        .collect();
+    Ok(result)
+}
+
+// A search filter as sent by the frontend: checked, with its tag name normalized
+fn parse_search_filter(value: Value, field: &str) -> Result<page_handler::SearchFilter, CommandError> {
+    let mut filter: page_handler::SearchFilter = serde_json::from_value(value)
+        .map_err(|e| CommandError::invalid_input(field, format!("Invalid filter: {}", e)))?;
+    validate_search_filter(&filter)?;
+    if let Some(tag) = &filter.has_tag {
+        filter.has_tag = Some(normalize_tag_name(tag, "filter.has_tag")?.to_string());
+    }
+    Ok(filter)
+}
+
+#[derive(serde::Serialize, Debug)]
+struct CommandSavedQuery {
+    id: String,
+    name: String,
+    query: String,
+    filter: Value,
+    created_at: String,
+    updated_at: String,
+}
+
+impl From<saved_query_handler::SavedQuery> for CommandSavedQuery {
+    fn from(saved: saved_query_handler::SavedQuery) -> Self {
+        CommandSavedQuery {
+            id: saved.id.to_string(),
+            name: saved.name,
+            query: saved.query,
+            filter: saved.filter,
+            created_at: saved.created_at.to_rfc3339(),
+            updated_at: saved.updated_at.to_rfc3339(),
+        }
+    }
+}
+
+fn normalize_saved_query_name(name: &str) -> Result<&str, CommandError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(CommandError::invalid_input("name", "Saved query name cannot be empty"));
+    }
+    Ok(name)
+}
+
+// Command to save a search under a name. query is matched against page titles (default: every
+// page); filter is a search filter as taken by search_notes.
+#[tauri::command]
+async fn save_query(
+    state: State<'_, AppState>,
+    name: String,
+    filter: Value,
+    query: Option<String>,
+) -> Result<CommandSavedQuery, CommandError> {
+    let name = normalize_saved_query_name(&name)?;
+    let filter = parse_search_filter(filter, "filter")?;
+    let filter = serde_json::to_value(&filter).map_err(|e| e.to_string())?;
+    let query = query.as_deref().unwrap_or("");
+    let saved = saved_query_handler::create_saved_query(&state.pool()?, name, query, &filter).await?;
+    Ok(CommandSavedQuery::from(saved))
+}
+
+// Command to rename a saved query and/or change its search. Fields left out are kept.
+#[tauri::command]
+async fn update_saved_query(
+    state: State<'_, AppState>,
+    id: String,
+    name: Option<String>,
+    query: Option<String>,
+    filter: Option<Value>,
+) -> Result<CommandSavedQuery, CommandError> {
+    let query_uuid = parse_uuid(&id, "id", "saved query ID")?;
+    let name = name.as_deref().map(normalize_saved_query_name).transpose()?;
+    let filter = filter
+        .map(|filter| {
+            let filter = parse_search_filter(filter, "filter")?;
+            serde_json::to_value(&filter).map_err(|e| CommandError::from(e.to_string()))
+        })
+        .transpose()?;
+    let pool = state.pool()?;
+    let saved = saved_query_handler::update_saved_query(&pool, query_uuid, name, query.as_deref(), filter.as_ref())
+        .await
+        .map_err(not_found_as(format!("Saved query with ID {} not found", id)))?;
+    Ok(CommandSavedQuery::from(saved))
+}
+
+// Command to list saved queries by name
+#[tauri::command]
+async fn list_saved_queries(state: State<'_, AppState>) -> Result<Vec<CommandSavedQuery>, CommandError> {
+    let pool = state.pool()?;
+    let saved = db::with_retry(|| saved_query_handler::list_saved_queries(&pool)).await?;
+    Ok(saved.into_iter().map(CommandSavedQuery::from).collect())
+}
+
+#[derive(serde::Serialize, Debug)]
+struct CommandSavedQueryResults {
+    saved_query: CommandSavedQuery,
+    results: Vec<CommandSearchResult>,
+    warnings: Vec<String>, // Why the results may not be what the query was saved for
+}
+
+// What in a saved filter no longer points anywhere: pages that were deleted or trashed since,
+// and tags no page has any more
+async fn search_filter_warnings(
+    pool: &sqlx::PgPool,
+    filter: &page_handler::SearchFilter,
+) -> Result<Vec<String>, CommandError> {
+    let mut warnings = Vec::new();
+    for (field, page_id) in [("links_to", filter.links_to), ("linked_from", filter.linked_from)] {
+        let Some(page_id) = page_id else { continue };
+        match page_handler::get_page(pool, page_id).await {
+            Ok(page) if page.deleted_at.is_some() => {
+                warnings.push(format!("{}: page '{}' is in the trash", field, page.title));
+            }
+            Ok(_) => {}
+            Err(dal_error::DalError::NotFound) => {
+                warnings.push(format!("{}: page {} no longer exists", field, page_id));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    if let Some(tag) = &filter.has_tag {
+        if !tag_handler::tag_exists(pool, tag).await? {
+            warnings.push(format!("has_tag: no page is tagged '{}' any more", tag));
+        }
+    }
+    Ok(warnings)
+}
+
+// Command to run a saved query, e.g. for a query block: a block that stores a saved query's ID
+// and shows its results live. A filter pointing at pages or tags that are gone still runs,
+// with a warning for each; a filter this version can't read gives no results and a warning.
+#[tauri::command]
+async fn run_saved_query(
+    state: State<'_, AppState>,
+    id: String,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<CommandSavedQueryResults, CommandError> {
+    let query_uuid = parse_uuid(&id, "id", "saved query ID")?;
+    let (limit, offset) = resolve_pagination(limit, offset)?;
+    let pool = state.pool()?;
+    let saved = db::with_retry(|| saved_query_handler::get_saved_query(&pool, query_uuid))
+        .await
+        .map_err(not_found_as(format!("Saved query with ID {} not found", id)))?;
+
+    let (results, warnings) = match parse_search_filter(saved.filter.clone(), "filter") {
+        Ok(filter) => (
+            run_search(&pool, &saved.query, &filter, limit, offset).await?,
+            search_filter_warnings(&pool, &filter).await?,
+        ),
+        Err(e) => (Vec::new(), vec![format!("The saved filter can't be used: {}", e)]),
+    };
+    Ok(CommandSavedQueryResults {
+        saved_query: CommandSavedQuery::from(saved),
+        results,
+        warnings,
+    })
+}
+
+// Command to delete a saved query. Query blocks showing it get a not-found error when run.
+#[tauri::command]
+async fn delete_saved_query(state: State<'_, AppState>, id: String) -> Result<bool, CommandError> {
+    let query_uuid = parse_uuid(&id, "id", "saved query ID")?;
+    Ok(saved_query_handler::delete_saved_query(&state.pool()?, query_uuid).await?)
+}
+
+// Default and maximum number of suggestions returned by autocompletion commands
+const DEFAULT_SUGGESTION_LIMIT: i64 = 10;
+const MAX_SUGGESTION_LIMIT: i64 = 50;
+
+#[derive(serde::Serialize, Debug)]
+struct CommandPageTitleSuggestion {
+    id: String,
+    title: String,
+    updated_at: String,
+    matched_alias: Option<String>, // The alias that matched, when the title didn't
+}
+
+#[derive(serde::Serialize, Debug)]
+struct CommandPageTitleSuggestions {
+    pages: Vec<CommandPageTitleSuggestion>,
+    create_title: Option<String>, // Set when include_create is on and no page has this title yet
+}
+
+// Command to suggest pages while typing a [[link]]; matching ignores case and accents
+#[tauri::command]
+async fn suggest_page_titles(
+    state: State<'_, AppState>,
+    prefix: String,
+    limit: Option<i64>,
+    include_create: Option<bool>,
+) -> Result<CommandPageTitleSuggestions, CommandError> {
+    let limit = limit.unwrap_or(DEFAULT_SUGGESTION_LIMIT);
+    if !(1..=MAX_SUGGESTION_LIMIT).contains(&limit) {
+        return Err(CommandError::invalid_input(
+            "limit",
+            format!("limit must be between 1 and {}", MAX_SUGGESTION_LIMIT),
+        ));
+    }
+    let prefix = prefix.trim();
+
+    let pool = state.pool()?;
+    let (pages, exact_match_exists) = db::with_retry(|| page_handler::suggest_page_titles(&pool, prefix, limit)).await?;
+    let create_title = (include_create.unwrap_or(false) && !prefix.is_empty() && !exact_match_exists)
+        .then(|| prefix.to_string());
+    Ok(CommandPageTitleSuggestions {
+        pages: pages
+            .into_iter()
+            .map(|page| CommandPageTitleSuggestion {
+                id: page.id.to_string(),
+                title: page.title,
+                updated_at: page.updated_at.to_rfc3339(),
+                matched_alias: page.matched_alias,
+            })
+            .collect(),
+        create_title,
+    })
+}
+
+#[derive(serde::Serialize, Debug)]
+struct CommandBlockSuggestion {
+    block_id: String,
+    page_id: String,
+    page_title: String,
+    snippet: String,
+}
+
+// Command to suggest blocks while typing a (((block reference))). exclude_page_id is the page
+// being edited; its blocks are left out unless allow_self_references is set.
+#[tauri::command]
+async fn suggest_blocks(
+    state: State<'_, AppState>,
+    query: String,
+    limit: Option<i64>,
+    exclude_page_id: Option<String>,
+    allow_self_references: Option<bool>,
+) -> Result<Vec<CommandBlockSuggestion>, CommandError> {
+    let limit = limit.unwrap_or(DEFAULT_SUGGESTION_LIMIT);
+    if !(1..=MAX_SUGGESTION_LIMIT).contains(&limit) {
+        return Err(CommandError::invalid_input(
+            "limit",
+            format!("limit must be between 1 and {}", MAX_SUGGESTION_LIMIT),
+        ));
+    }
+    let exclude_page_uuid = match exclude_page_id {
+        Some(id) if !allow_self_references.unwrap_or(false) => Some(parse_uuid(&id, "exclude_page_id", "page ID")?),
+        _ => None,
+    };
+
+    let pool = state.pool()?;
+    let suggestions = db::with_retry(|| block_handler::suggest_blocks(&pool, query.trim(), limit, exclude_page_uuid)).await?;
+    Ok(suggestions
+        .into_iter()
+        .map(|suggestion| CommandBlockSuggestion {
+            block_id: suggestion.block_id.to_string(),
+            page_id: suggestion.page_id.to_string(),
+            page_title: suggestion.page_title,
+            snippet: suggestion.snippet,
+        })
+        .collect())
+}
+
+// Command to search block text, e.g. for (((block ref))) autocomplete
+#[tauri::command]
+async fn search_blocks(
+    state: State<'_, AppState>,
+    query: String,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<Vec<CommandBlockSearchResult>, CommandError> {
+    let (limit, offset) = resolve_pagination(limit, offset)?;
+    let pool = state.pool()?;
+    let results = db::with_retry(|| block_handler::search_blocks(&pool, &query, limit, offset)).await?;
+    Ok(results.into_iter().map(CommandBlockSearchResult::from).collect())
+}
+
+// New get_page_details function (replaces read_markdown_file)
+#[tauri::command]
+async fn get_page_details(state: State<'_, AppState>, id: String) -> Result<CommandPage, CommandError> {
+    let page_uuid = parse_uuid(&id, "id", "page ID")?;
+    let pool = state.pool()?;
+    let page = db::with_retry(|| page_handler::get_page(&pool, page_uuid))
+        .await
+        .map_err(not_found_as(format!("Page with ID {} not found", id)))?;
+    Ok(CommandPage::from(page))
+}
+
+// Command to get a page with its backlinks and per-block incoming reference counts in one call
+#[tauri::command]
+async fn get_page_with_references(state: State<'_, AppState>, id: String) -> Result<CommandPageWithReferences, CommandError> {
+    let page_uuid = parse_uuid(&id, "id", "page ID")?;
+    let pool = state.pool()?;
+    let page = db::with_retry(|| page_handler::get_page(&pool, page_uuid))
+        .await
+        .map_err(not_found_as(format!("Page with ID {} not found", id)))?;
+
+    let backlinks = db::with_retry(|| link_handler::find_backlink_sources_for_page(&pool, page_uuid, false)).await?;
+    let reference_counts = db::with_retry(|| link_handler::get_reference_counts_for_page(&pool, page_uuid)).await?;
+
+    Ok(CommandPageWithReferences {
+        page: CommandPage::from(page),
+        backlink_count: backlinks.len(),
+        backlinks: backlinks.into_iter().map(CommandBacklinkSource::from).collect(),
+        block_reference_counts: reference_counts
+            .into_iter()
+            .map(|(block_id, count)| (block_id.to_string(), count))
+            .collect(),
+    })
+}
+
+// Command to count the links and block references into a page from pages outside the trash,
+// for a "n linked references" badge
+#[tauri::command]
+async fn get_reference_summary(
+    state: State<'_, AppState>,
+    page_id: String,
+) -> Result<link_handler::ReferenceSummary, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    let pool = state.pool()?;
+    Ok(db::with_retry(|| link_handler::get_reference_summary(&pool, page_uuid)).await?)
+}
+
+// Command to get_reference_summary for many pages at once (e.g. every page in the sidebar),
+// keyed by page ID
+#[tauri::command]
+async fn get_reference_summaries(
+    state: State<'_, AppState>,
+    page_ids: Vec<String>,
+) -> Result<HashMap<String, link_handler::ReferenceSummary>, CommandError> {
+    let page_uuids = page_ids
+        .iter()
+        .map(|id| parse_uuid(id, "page_ids", "page ID"))
+        .collect::<Result<Vec<_>, _>>()?;
+    let pool = state.pool()?;
+    let summaries = db::with_retry(|| link_handler::get_reference_summaries(&pool, &page_uuids)).await?;
+    Ok(summaries.into_iter().map(|(id, summary)| (id.to_string(), summary)).collect())
+}
+
+// Command to get word, block and link counts for a page
+#[tauri::command]
+async fn get_page_stats(state: State<'_, AppState>, page_id: String) -> Result<stats_handler::PageStats, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    let pool = state.pool()?;
+    db::with_retry(|| stats_handler::get_page_stats(&pool, page_uuid))
+        .await
+        .map_err(not_found_as(format!("Page with ID {} not found", page_id)))
+}
+
+// Command to get totals for the whole vault, including pages created per month
+#[tauri::command]
+async fn get_vault_stats(state: State<'_, AppState>) -> Result<stats_handler::VaultStats, CommandError> {
+    let pool = state.pool()?;
+    db::with_retry(|| stats_handler::get_vault_stats(&pool))
+        .await
+        .map_err(CommandError::from)
+}
+
+// Command to find orphan pages, broken block references, unresolved [[links]] and recordings
+// whose file is gone
+#[tauri::command]
+async fn get_vault_health(state: State<'_, AppState>) -> Result<health_handler::VaultHealth, CommandError> {
+    let audio_dir = state.audio_dir.read().await.clone();
+    health_handler::get_vault_health(&state.pool()?, &audio_dir)
+        .await
+        .map_err(CommandError::from)
+}
+
+// Command to run the database maintenance now, whatever its schedule: prunes old revisions,
+// removes references, links and audio timestamps left pointing at nothing, and refreshes
+// table statistics. Returns the rows cleaned per step. Fails with a conflict while a run is
+// going, here or in another app instance sharing the database.
+#[tauri::command]
+async fn run_maintenance_now(state: State<'_, AppState>) -> Result<maintenance::MaintenanceReport, CommandError> {
+    let options = maintenance_options(&state, false)?;
+    maintenance::run_maintenance(&state.pool()?, &options)
+        .await?
+        .ok_or_else(|| CommandError::conflict("Database maintenance is already running"))
+}
+
+// Command to get the report of the newest maintenance run, scheduled or not, or null if none
+// has run yet
+#[tauri::command]
+async fn get_last_maintenance_report(
+    state: State<'_, AppState>,
+) -> Result<Option<maintenance::MaintenanceReport>, CommandError> {
+    Ok(maintenance::get_last_report(&state.pool()?).await?)
+}
+
+// Result of a save. unchanged is true when everything sent matched what was stored, so nothing
+// was written and updated_at is what it was.
+#[derive(serde::Serialize, Debug)]
+struct CommandPageUpdate {
+    updated_at: String,
+    unchanged: bool,
+}
+
+impl From<page_handler::PageUpdate> for CommandPageUpdate {
+    fn from(update: page_handler::PageUpdate) -> Self {
+        CommandPageUpdate {
+            updated_at: update.updated_at.to_rfc3339(),
+            unchanged: !update.changed,
+        }
+    }
+}
+
+// New update_page_content function (replaces write_markdown_file)
+// Returns the page's updated_at after the save, or null if there's no such page. Pass the
+// updated_at the editor last saw as expected_updated_at to refuse the write when another window
+// saved since; the conflict error then carries the page as it is now. create_stub_pages (default: the
+// links.create_stub_pages setting) creates empty pages for `[[links]]` that match no page.
+// A change to the content keeps the old content as a revision, described by change_summary.
+// A save that changes something emits page://updated, and links://changed when the page now
+// links to other pages more or fewer times.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)] // Each argument is a separate optional field of the IPC call
+async fn update_page_content(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    id: String,
+    title: Option<String>,
+    raw_markdown: Option<String>,
+    content_json: Option<Value>, // Allow updating content_json too
+    expected_updated_at: Option<String>,
+    create_stub_pages: Option<bool>,
+    change_summary: Option<String>,
+) -> Result<Option<CommandPageUpdate>, CommandError> {
+    let page_uuid = parse_uuid(&id, "id", "page ID")?;
+    let create_stub_pages = create_stub_pages_or_setting(&state, create_stub_pages)?;
+    let expected_updated_at = expected_updated_at
+        .map(|value| {
+            chrono::DateTime::parse_from_rfc3339(&value)
+                .map(|dt| dt.with_timezone(&chrono::Utc))
+                .map_err(|e| CommandError::invalid_input("expected_updated_at", format!("Invalid timestamp: {}", e)))
+        })
+        .transpose()?;
+
+    // Prepare Option<&str> for title and raw_markdown
+    let title_ref = title.as_deref();
+    // let raw_markdown_ref = raw_markdown.as_deref();
+
+    let pool = state.pool()?;
+    let result = page_handler::update_page(
+        &pool,
+        page_uuid,
+        title_ref,
+        content_json, // Pass content_json directly
+        raw_markdown.as_deref().map(Some), // If raw_markdown is Some(String), pass Some(Some(string_slice)). If None, pass None.
+        expected_updated_at,
+        create_stub_pages,
+        change_summary.as_deref().map(str::trim).filter(|summary| !summary.is_empty()),
+    )
+    .await;
+
+    match result {
+        Ok(Some(update)) => {
+            emit_page_update_events(&app_handle, page_uuid, &update);
+            Ok(Some(CommandPageUpdate::from(update)))
+        }
+        Ok(None) => Ok(None),
+        Err(dal_error::DalError::Conflict(message)) => {
+            let current = page_handler::get_page(&pool, page_uuid).await?;
+            let current = serde_json::to_value(CommandPage::from(current)).map_err(|e| e.to_string())?;
+            Err(CommandError::stale_write(message, current))
+        }
+        Err(e) => Err(CommandError::from(e)),
+    }
+}
+
+// The create_stub_pages argument of a save, or the links.create_stub_pages setting without one
+fn create_stub_pages_or_setting(state: &AppState, create_stub_pages: Option<bool>) -> Result<bool, CommandError> {
+    match create_stub_pages {
+        Some(create) => Ok(create),
+        None => {
+            let app_settings = state.settings.lock().map_err(|_| "Failed to acquire settings lock".to_string())?;
+            Ok(app_settings.links.create_stub_pages)
+        }
+    }
+}
+
+// page://updated when a save changed the page, and links://changed when it now links to other
+// pages more or fewer times
+fn emit_page_update_events(app_handle: &AppHandle, page_id: Uuid, update: &page_handler::PageUpdate) {
+    if update.changed {
+        page_events::emit_page_changed(app_handle, page_id, Some(update.updated_at), PageChangeKind::Edited);
+    }
+    if !update.link_targets_changed.is_empty() {
+        let mut page_ids = vec![page_id];
+        page_ids.extend(&update.link_targets_changed);
+        page_events::emit_links_changed(app_handle, &page_ids);
+    }
+}
+
+// A block node sent for a block edit: a single block, with no blocks inside it, whose uniqueID
+// is block_id. A node without a uniqueID gets that one.
+fn block_node_input(mut node: Value, field: &str, block_id: Uuid) -> Result<Value, CommandError> {
+    json_utils::check_single_block_node(&node).map_err(|e| CommandError::invalid_input(field, e))?;
+    if json_utils::unique_id(&node).is_some_and(|id| id != block_id) {
+        return Err(CommandError::invalid_input(field, format!("The node's uniqueID must be {}", block_id)));
+    }
+    node["uniqueID"] = Value::String(block_id.to_string());
+    Ok(node)
+}
+
+// Command to save a single block: new_node_json takes the place of the block's node in the
+// page's content, and only that block's row, links and references are rewritten, rather than
+// sending the whole page. Edits that move blocks around go through update_page_content. No
+// revision is kept. Returns the page's updated_at after the save, or null if there's no such
+// page. Emits the same events as update_page_content.
+#[tauri::command]
+async fn update_block_content(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    page_id: String,
+    block_id: String,
+    new_node_json: Value,
+    create_stub_pages: Option<bool>,
+) -> Result<Option<CommandPageUpdate>, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    let block_uuid = parse_uuid(&block_id, "block_id", "block ID")?;
+    let node = block_node_input(new_node_json, "new_node_json", block_uuid)?;
+    let create_stub_pages = create_stub_pages_or_setting(&state, create_stub_pages)?;
+
+    let pool = state.pool()?;
+    let update = page_handler::update_block_node(&pool, page_uuid, block_uuid, node, create_stub_pages)
+        .await
+        .map_err(not_found_as(format!("Block {} is not on page {}", block_id, page_id)))?;
+    if let Some(update) = &update {
+        emit_page_update_events(&app_handle, page_uuid, update);
+    }
+    Ok(update.map(CommandPageUpdate::from))
+}
+
+#[derive(serde::Serialize, Debug)]
+struct CommandBlockInsert {
+    block_id: String, // The inserted block's uniqueID
+    updated_at: String,
+}
+
+// Command to insert a single block after the anchor block, among its siblings; after a list
+// item, past the item's nested list. The node gets a new uniqueID unless it has one. A list
+// item can only follow a list item, and another block only a block that isn't one. Returns
+// null if there's no such page. Emits the same events as update_page_content.
+#[tauri::command]
+async fn insert_block_after(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    page_id: String,
+    anchor_block_id: String,
+    node_json: Value,
+    create_stub_pages: Option<bool>,
+) -> Result<Option<CommandBlockInsert>, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    let anchor_uuid = parse_uuid(&anchor_block_id, "anchor_block_id", "block ID")?;
+    let block_uuid = json_utils::unique_id(&node_json).unwrap_or_else(Uuid::new_v4);
+    let node = block_node_input(node_json, "node_json", block_uuid)?;
+    let create_stub_pages = create_stub_pages_or_setting(&state, create_stub_pages)?;
+
+    let pool = state.pool()?;
+    let update = page_handler::insert_block_node(&pool, page_uuid, anchor_uuid, node, create_stub_pages)
+        .await
+        .map_err(not_found_as(format!("Block {} is not on page {}", anchor_block_id, page_id)))?;
+    Ok(update.map(|update| {
+        emit_page_update_events(&app_handle, page_uuid, &update);
+        CommandBlockInsert {
+            block_id: block_uuid.to_string(),
+            updated_at: update.updated_at.to_rfc3339(),
+        }
+    }))
+}
+
+// Command to delete a single block from a page: its node, its nested list if it's a list item,
+// and the rows of the blocks in them. Returns the page's updated_at after the save, or null if
+// there's no such page. Emits the same events as update_page_content.
+#[tauri::command]
+async fn delete_block_from_page(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    page_id: String,
+    block_id: String,
+) -> Result<Option<CommandPageUpdate>, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    let block_uuid = parse_uuid(&block_id, "block_id", "block ID")?;
+
+    let pool = state.pool()?;
+    let update = page_handler::delete_block_node(&pool, page_uuid, block_uuid)
+        .await
+        .map_err(not_found_as(format!("Block {} is not on page {}", block_id, page_id)))?;
+    if let Some(update) = &update {
+        emit_page_update_events(&app_handle, page_uuid, update);
+    }
+    Ok(update.map(CommandPageUpdate::from))
+}
+
+#[derive(serde::Serialize, Debug)]
+struct CommandPageRevisionMetadata {
+    id: String,
+    page_id: String,
+    change_summary: Option<String>,
+    page_updated_at: String, // When this content was saved
+    created_at: String,      // When it was replaced
+}
+
+impl From<revision_handler::PageRevisionMetadata> for CommandPageRevisionMetadata {
+    fn from(revision: revision_handler::PageRevisionMetadata) -> Self {
+        CommandPageRevisionMetadata {
+            id: revision.id.to_string(),
+            page_id: revision.page_id.to_string(),
+            change_summary: revision.change_summary,
+            page_updated_at: revision.page_updated_at.to_rfc3339(),
+            created_at: revision.created_at.to_rfc3339(),
+        }
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+struct CommandPageRevision {
+    #[serde(flatten)]
+    metadata: CommandPageRevisionMetadata,
+    content_json: Value,
+    raw_markdown: Option<String>,
+}
+
+impl From<revision_handler::PageRevision> for CommandPageRevision {
+    fn from(revision: revision_handler::PageRevision) -> Self {
+        CommandPageRevision {
+            metadata: CommandPageRevisionMetadata {
+                id: revision.id.to_string(),
+                page_id: revision.page_id.to_string(),
+                change_summary: revision.change_summary,
+                page_updated_at: revision.page_updated_at.to_rfc3339(),
+                created_at: revision.created_at.to_rfc3339(),
+            },
+            content_json: revision.content_json,
+            raw_markdown: revision.raw_markdown,
+        }
+    }
+}
+
+// Command to list a page's earlier versions, newest first, without their content
+#[tauri::command]
+async fn list_page_revisions(state: State<'_, AppState>, page_id: String) -> Result<Vec<CommandPageRevisionMetadata>, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    let pool = state.pool()?;
+    let revisions = db::with_retry(|| revision_handler::list_revisions(&pool, page_uuid)).await?;
+    Ok(revisions.into_iter().map(CommandPageRevisionMetadata::from).collect())
+}
+
+// Command to get one earlier version of a page with its content
+#[tauri::command]
+async fn get_page_revision(state: State<'_, AppState>, revision_id: String) -> Result<CommandPageRevision, CommandError> {
+    let revision_uuid = parse_uuid(&revision_id, "revision_id", "revision ID")?;
+    let pool = state.pool()?;
+    let revision = db::with_retry(|| revision_handler::get_revision(&pool, revision_uuid))
+        .await
+        .map_err(not_found_as(format!("Revision with ID {} not found", revision_id)))?;
+    Ok(CommandPageRevision::from(revision))
+}
+
+// Command to put an earlier version's content back on its page. The content it replaces is
+// kept as a revision, so a restore can be undone the same way. Returns the updated page.
+#[tauri::command]
+async fn restore_page_revision(state: State<'_, AppState>, revision_id: String) -> Result<CommandPage, CommandError> {
+    let revision_uuid = parse_uuid(&revision_id, "revision_id", "revision ID")?;
+    let pool = state.pool()?;
+    let page_id = page_handler::restore_page_revision(&pool, revision_uuid)
+        .await
+        .map_err(not_found_as(format!("Revision with ID {} not found", revision_id)))?;
+    Ok(CommandPage::from(page_handler::get_page(&pool, page_id).await?))
+}
+
+// Command to compare the Markdown of two versions of a page line by line, from a to b
+#[tauri::command]
+async fn diff_page_revisions(
+    state: State<'_, AppState>,
+    a: String,
+    b: String,
+) -> Result<Vec<revision_handler::DiffLine>, CommandError> {
+    let a_uuid = parse_uuid(&a, "a", "revision ID")?;
+    let b_uuid = parse_uuid(&b, "b", "revision ID")?;
+    let pool = state.pool()?;
+    let old = revision_handler::get_revision(&pool, a_uuid)
+        .await
+        .map_err(not_found_as(format!("Revision with ID {} not found", a)))?;
+    let new = revision_handler::get_revision(&pool, b_uuid)
+        .await
+        .map_err(not_found_as(format!("Revision with ID {} not found", b)))?;
+    if old.page_id != new.page_id {
+        return Err(CommandError::invalid_input("b", "Both revisions must belong to the same page"));
+    }
+    Ok(revision_handler::diff_lines(&old.markdown(), &new.markdown()))
+}
+
+// Command to rename a page; rewrites [[Old Title]] links in pages that link to it. Emits
+// page://updated for the renamed page and each page whose links were rewritten.
+#[tauri::command]
+async fn rename_page(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    id: String,
+    new_title: String,
+) -> Result<Vec<String>, CommandError> {
+    let page_uuid = parse_uuid(&id, "id", "page ID")?;
+    let new_title = new_title.trim();
+    if new_title.is_empty() {
+        return Err(CommandError::invalid_input("new_title", "Page title cannot be empty"));
+    }
+
+    let pool = state.pool()?;
+    let touched_ids = page_handler::rename_page(&pool, page_uuid, new_title)
+        .await
+        .map_err(not_found_as(format!("Page with ID {} not found", id)))?;
+
+    for (touched_id, updated_at) in page_handler::get_updated_at(&pool, &touched_ids).await? {
+        let kind = if touched_id == page_uuid { PageChangeKind::Renamed } else { PageChangeKind::Edited };
+        page_events::emit_page_changed(&app_handle, touched_id, Some(updated_at), kind);
+    }
+    Ok(touched_ids.into_iter().map(|uuid| uuid.to_string()).collect())
+}
+
+// Command to give a page's blocks that lack a uniqueID (or share one with an earlier block)
+// fresh IDs, so they get block rows. Returns how many IDs were added and replaced.
+#[tauri::command]
+async fn normalize_page(state: State<'_, AppState>, page_id: String) -> Result<json_utils::UniqueIdRepair, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    page_handler::normalize_page(&state.pool()?, page_uuid)
+        .await
+        .map_err(not_found_as(format!("Page with ID {} not found", page_id)))
+}
+
+// Command to run normalize_page on every page, trashed ones included. Emits
+// normalize://progress events while it runs.
+#[tauri::command]
+async fn normalize_all_pages(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<page_normalize::NormalizeSummary, CommandError> {
+    Ok(page_normalize::normalize_all_pages(&state.pool()?, &app_handle, None).await?)
+}
+
+// Command to compare a page's block, link and block reference rows with its content_json.
+// With fix, a page whose rows don't match gets its content synced again.
+#[tauri::command]
+async fn verify_page_integrity(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    page_id: String,
+    fix: Option<bool>,
+) -> Result<page_handler::PageIntegrity, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    page_integrity::verify_page(&state.pool()?, &app_handle, page_uuid, fix.unwrap_or(false))
+        .await
+        .map_err(not_found_as(format!("Page with ID {} not found", page_id)))
+}
+
+// Command to run verify_page_integrity on every page, trashed ones included. Emits
+// integrity://progress events while it runs and returns the pages whose rows don't match.
+#[tauri::command]
+async fn verify_all_pages(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    fix: bool,
+) -> Result<page_integrity::IntegritySummary, CommandError> {
+    Ok(page_integrity::verify_all_pages(&state.pool()?, &app_handle, fix).await?)
+}
+
+// Command to copy a page (content, blocks and outgoing links) under a new title
+#[tauri::command]
+async fn duplicate_page(state: State<'_, AppState>, id: String, new_title: String) -> Result<CommandPage, CommandError> {
+    let page_uuid = parse_uuid(&id, "id", "page ID")?;
+    let new_title = new_title.trim();
+    if new_title.is_empty() {
+        return Err(CommandError::invalid_input("new_title", "Page title cannot be empty"));
+    }
+
+    let pool = state.pool()?;
+    let new_page_id = page_handler::duplicate_page(&pool, page_uuid, new_title)
+        .await
+        .map_err(not_found_as(format!("Page with ID {} not found", id)))?;
+
+    let new_page = page_handler::get_page(&pool, new_page_id)
+        .await?;
+    Ok(CommandPage::from(new_page))
+}
+
+#[derive(serde::Serialize, Debug)]
+struct CommandExtractedBlock {
+    new_page: CommandPage,
+    source_page: CommandPage, // With a [[link]] to the new page where the block was
+}
+
+// Command to move a block and its children to a new page, leaving a link to it behind. Block
+// IDs are kept, so references to the moved blocks keep working.
+#[tauri::command]
+async fn extract_block_to_page(
+    state: State<'_, AppState>,
+    block_id: String,
+    title: String,
+) -> Result<CommandExtractedBlock, CommandError> {
+    let block_uuid = parse_uuid(&block_id, "block_id", "block ID")?;
+    let title = title.trim();
+    if title.is_empty() {
+        return Err(CommandError::invalid_input("title", "Page title cannot be empty"));
+    }
+    // The title goes into a [[link]], where these would end it early or be read as an alias
+    // or heading
+    if title.contains(['[', ']', '|', '#']) {
+        return Err(CommandError::invalid_input("title", "Page title cannot contain [, ], | or #"));
+    }
+
+    let pool = state.pool()?;
+    let (new_page_id, source_page_id) = page_handler::extract_block_to_page(&pool, block_uuid, title)
+        .await
+        .map_err(not_found_as(format!("Block with ID {} not found", block_id)))?;
+
+    let new_page = page_handler::get_page(&pool, new_page_id).await?;
+    let source_page = page_handler::get_page(&pool, source_page_id).await?;
+    Ok(CommandExtractedBlock {
+        new_page: CommandPage::from(new_page),
+        source_page: CommandPage::from(source_page),
+    })
+}
+
+#[derive(serde::Serialize, Debug)]
+struct CommandMergedPages {
+    target_page: CommandPage, // With the source's blocks appended
+    relinked_page_ids: Vec<String>, // Pages whose [[links]] to the source were rewritten
+}
+
+// Command to merge one page into another, e.g. to resolve two pages with the same title: the
+// source's blocks are appended to the target, links, references and recordings move over, and
+// the source goes to the trash. Emits page events for every page changed and links://changed.
+#[tauri::command]
+async fn merge_pages(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    source_id: String,
+    target_id: String,
+) -> Result<CommandMergedPages, CommandError> {
+    let source_uuid = parse_uuid(&source_id, "source_id", "page ID")?;
+    let target_uuid = parse_uuid(&target_id, "target_id", "page ID")?;
+    if source_uuid == target_uuid {
+        return Err(CommandError::invalid_input("target_id", "A page can't be merged into itself"));
+    }
+
+    let pool = state.pool()?;
+    let merge = page_handler::merge_pages(&pool, source_uuid, target_uuid)
+        .await
+        .map_err(not_found_as("Source or target page not found"))?;
+
+    page_events::emit_page_changed(&app_handle, source_uuid, None, PageChangeKind::Trashed);
+    for (page_id, updated_at) in page_handler::get_updated_at(&pool, &merge.relinked_pages).await? {
+        page_events::emit_page_changed(&app_handle, page_id, Some(updated_at), PageChangeKind::Edited);
+    }
+    let target_page = page_handler::get_page(&pool, target_uuid).await?;
+    page_events::emit_page_changed(&app_handle, target_uuid, Some(target_page.updated_at), PageChangeKind::Edited);
+    let mut page_ids = vec![source_uuid, target_uuid];
+    page_ids.extend(&merge.relinked_pages);
+    page_ids.extend(&merge.link_targets_changed);
+    page_ids.sort();
+    page_ids.dedup();
+    page_events::emit_links_changed(&app_handle, &page_ids);
+
+    Ok(CommandMergedPages {
+        target_page: CommandPage::from(target_page),
+        relinked_page_ids: merge.relinked_pages.iter().map(Uuid::to_string).collect(),
+    })
+}
+
+// Command to list the pages given a suffixed title because another page had the same one, so
+// they can be merged or renamed
+#[tauri::command]
+async fn list_renamed_duplicate_pages(
+    state: State<'_, AppState>,
+) -> Result<Vec<page_handler::RenamedDuplicatePage>, CommandError> {
+    let pool = state.pool()?;
+    Ok(db::with_retry(|| page_handler::list_renamed_duplicate_pages(&pool)).await?)
+}
+
+// Built-in template placeholders ({{date}}, {{title}}) plus the caller's own variables,
+// which take precedence
+fn template_variables(title: &str, mut variables: HashMap<String, String>) -> HashMap<String, String> {
+    variables
+        .entry("date".to_string())
+        .or_insert_with(|| chrono::Local::now().format("%Y-%m-%d").to_string());
+    variables.entry("title".to_string()).or_insert_with(|| title.to_string());
+    variables
+}
+
+// Command to list the pages flagged as templates
+#[tauri::command]
+async fn list_templates(state: State<'_, AppState>) -> Result<Vec<CommandPageMetadata>, CommandError> {
+    let pool = state.pool()?;
+    let templates = db::with_retry(|| page_handler::list_templates(&pool)).await?;
+    Ok(templates.into_iter().map(CommandPageMetadata::from).collect())
+}
+
+// Command to flag or unflag a page as a template
+#[tauri::command]
+async fn set_page_is_template(state: State<'_, AppState>, id: String, is_template: bool) -> Result<bool, CommandError> {
+    let page_uuid = parse_uuid(&id, "id", "page ID")?;
+    page_handler::set_page_is_template(&state.pool()?, page_uuid, is_template)
+        .await
+        .map_err(CommandError::from)
+}
+
+// Command to create a page from a template, filling in {{placeholders}}
+#[tauri::command]
+async fn create_page_from_template(
+    state: State<'_, AppState>,
+    template_id: String,
+    title: String,
+    variables: Option<HashMap<String, String>>,
+) -> Result<CommandPage, CommandError> {
+    let template_uuid = parse_uuid(&template_id, "template_id", "template ID")?;
+    let title = title.trim();
+    if title.is_empty() {
+        return Err(CommandError::invalid_input("title", "Page title cannot be empty"));
+    }
+
+    let pool = state.pool()?;
+    let variables = template_variables(title, variables.unwrap_or_default());
+    let new_page_id = page_handler::create_page_from_template(&pool, template_uuid, title, &variables)
+        .await
+        .map_err(not_found_as(format!("Template with ID {} not found", template_id)))?;
+
+    let new_page = page_handler::get_page(&pool, new_page_id)
+        .await?;
+    Ok(CommandPage::from(new_page))
+}
+
+// Command to set (or clear) the template used for new daily notes
+#[tauri::command]
+fn set_daily_note_template(state: State<AppState>, template_id: Option<String>) -> Result<(), CommandError> {
+    if let Some(id) = &template_id {
+        parse_uuid(id, "template_id", "template ID")?;
+    }
+    let mut app_settings = state.settings.lock().map_err(|_| "Failed to acquire settings lock".to_string())?;
+    app_settings.templates.daily_note_template_id = template_id;
+    Ok(settings::save(&state.app_data_dir, &app_settings)?)
+}
+
+// Command to turn on (or off) creating stub pages for `[[links]]` that match no page when a
+// page is saved
+#[tauri::command]
+fn set_create_stub_pages(state: State<AppState>, enabled: bool) -> Result<(), CommandError> {
+    let mut app_settings = state.settings.lock().map_err(|_| "Failed to acquire settings lock".to_string())?;
+    app_settings.links.create_stub_pages = enabled;
+    Ok(settings::save(&state.app_data_dir, &app_settings)?)
+}
+
+// Command to give a page another name [[links]] can use. Fails with a conflict if a page is
+// already titled or aliased that way, ignoring case.
+#[tauri::command]
+async fn add_page_alias(state: State<'_, AppState>, page_id: String, alias: String) -> Result<String, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    let alias = alias.trim();
+    if alias.is_empty() {
+        return Err(CommandError::invalid_input("alias", "Alias cannot be empty"));
+    }
+    let created = alias_handler::add_alias(&state.pool()?, page_uuid, alias)
+        .await
+        .map_err(not_found_as(format!("Page with ID {} not found", page_id)))?;
+    Ok(created.alias)
+}
+
+// Command to remove an alias from a page; returns false if the page didn't have it
+#[tauri::command]
+async fn remove_page_alias(state: State<'_, AppState>, page_id: String, alias: String) -> Result<bool, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    alias_handler::remove_alias(&state.pool()?, page_uuid, alias.trim())
+        .await
+        .map_err(CommandError::from)
+}
+
+// Command to list a page's aliases
+#[tauri::command]
+async fn list_page_aliases(state: State<'_, AppState>, page_id: String) -> Result<Vec<String>, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    let pool = state.pool()?;
+    let aliases = db::with_retry(|| alias_handler::list_aliases(&pool, page_uuid)).await?;
+    Ok(aliases.into_iter().map(|alias| alias.alias).collect())
+}
+
+// Command to list favorite pages in their sidebar order
+#[tauri::command]
+async fn list_favorites(state: State<'_, AppState>) -> Result<Vec<CommandPageMetadata>, CommandError> {
+    let pool = state.pool()?;
+    let pages = db::with_retry(|| page_handler::list_favorites(&pool)).await?;
+    Ok(pages.into_iter().map(CommandPageMetadata::from).collect())
+}
+
+// Command to add a page to (or remove it from) the favorites
+#[tauri::command]
+async fn set_favorite(state: State<'_, AppState>, page_id: String, favorite: bool) -> Result<bool, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    page_handler::set_favorite(&state.pool()?, page_uuid, favorite)
+        .await
+        .map_err(CommandError::from)
+}
+
+// Command to store a new favorites order; page_ids must list every favorite exactly once
+#[tauri::command]
+async fn reorder_favorites(state: State<'_, AppState>, page_ids: Vec<String>) -> Result<Vec<CommandPageMetadata>, CommandError> {
+    let page_uuids = page_ids
+        .iter()
+        .map(|id| parse_uuid(id, "page_ids", "page ID"))
+        .collect::<Result<Vec<Uuid>, CommandError>>()?;
+
+    let pool = state.pool()?;
+    page_handler::reorder_favorites(&pool, &page_uuids)
+        .await?;
+    let pages = page_handler::list_favorites(&pool)
+        .await?;
+    Ok(pages.into_iter().map(CommandPageMetadata::from).collect())
+}
+
+// Trims a tag name and drops a leading '#', rejecting names that end up empty
+fn normalize_tag_name<'a>(name: &'a str, field: &str) -> Result<&'a str, CommandError> {
+    let name = name.trim().trim_start_matches('#').trim();
+    if name.is_empty() {
+        return Err(CommandError::invalid_input(field, "Tag name cannot be empty"));
+    }
+    Ok(name)
+}
+
+// Command to tag a page
+#[tauri::command]
+async fn add_tag_to_page(state: State<'_, AppState>, page_id: String, tag: String) -> Result<String, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    let created = tag_handler::add_tag_to_page(&state.pool()?, page_uuid, normalize_tag_name(&tag, "tag")?)
+        .await?;
+    Ok(created.name) // The stored casing, which may differ from the one passed in
+}
+
+// Command to remove a tag from a page
+#[tauri::command]
+async fn remove_tag_from_page(state: State<'_, AppState>, page_id: String, tag: String) -> Result<bool, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    tag_handler::remove_tag_from_page(&state.pool()?, page_uuid, normalize_tag_name(&tag, "tag")?)
+        .await
+        .map_err(CommandError::from)
+}
+
+// Command to list all tags with how many pages use each
+#[tauri::command]
+async fn list_tags(state: State<'_, AppState>) -> Result<Vec<CommandTag>, CommandError> {
+    let pool = state.pool()?;
+    let tags = db::with_retry(|| tag_handler::list_tags(&pool)).await?;
+    Ok(tags.into_iter().map(CommandTag::from).collect())
+}
+
+// Command to list the pages with a tag (matched ignoring case)
+#[tauri::command]
+async fn list_pages_with_tag(state: State<'_, AppState>, tag: String) -> Result<Vec<CommandPageMetadata>, CommandError> {
+    let pool = state.pool()?;
+    let tag = normalize_tag_name(&tag, "tag")?;
+    let pages = db::with_retry(|| tag_handler::list_pages_with_tag(&pool, tag)).await?;
+    Ok(pages.into_iter().map(CommandPageMetadata::from).collect())
+}
+
+// Command to rename a tag (merging it into an existing tag of that name); rewrites #hashtags
+// in pages and returns the IDs of the pages that changed
+#[tauri::command]
+async fn rename_tag(state: State<'_, AppState>, old_name: String, new_name: String) -> Result<Vec<String>, CommandError> {
+    let touched_ids = tag_handler::rename_tag(&state.pool()?, normalize_tag_name(&old_name, "old_name")?, normalize_tag_name(&new_name, "new_name")?)
+        .await
+        .map_err(not_found_as(format!("Tag '{}' not found", old_name)))?;
+    Ok(touched_ids.into_iter().map(|uuid| uuid.to_string()).collect())
+}
+
+// Command to create a new note. Emits page://created.
+#[tauri::command]
+async fn create_note(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    title: String, // Changed from &str to String
+    content: String, // Changed from &str to String, assumed to be raw_markdown
+) -> Result<CommandPage, CommandError> {
+    // The Markdown is converted into blocks, and passed along so it is kept as written rather
+    // than rendered from the blocks. A page whose blocks or links can't be stored is removed.
+    let content_json = markdown_import::markdown_to_content(&content);
+    let create_stub_pages = create_stub_pages_or_setting(&state, None)?;
+    let pool = state.pool()?;
+    let (new_page_id, update) =
+        page_handler::create_page_with_content(&pool, &title, content_json, Some(&content), create_stub_pages).await?;
+
+    // Fetch the created page to return its full details
+    let new_page_details = page_handler::get_page(&pool, new_page_id)
+        .await?;
+    let updated_at = Some(new_page_details.updated_at);
+    page_events::emit_page_changed(&app_handle, new_page_id, updated_at, PageChangeKind::Created);
+    if !update.link_targets_changed.is_empty() {
+        let mut page_ids = vec![new_page_id];
+        page_ids.extend(&update.link_targets_changed);
+        page_events::emit_links_changed(&app_handle, &page_ids);
+    }
+
+    Ok(CommandPage::from(new_page_details))
+}
+
+// Command to import a folder of Markdown notes (e.g. an Obsidian vault). Emits
+// import://progress events while it runs; safe to re-run after an interruption. With a
+// channel_id, a summary too big to return (one with many failures) is sent as transfer chunks.
+#[tauri::command]
+async fn import_vault(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    path: String,
+    options: Option<vault_import::ImportOptions>,
+    channel_id: Option<String>,
+) -> Result<transfer::Transferred<vault_import::ImportSummary>, CommandError> {
+    let options = options.unwrap_or_default();
+    let summary = vault_import::import_vault(&state.pool()?, &app_handle, Path::new(&path), &options, None).await?;
+    Ok(transfer::send_or_return(&app_handle, channel_id.as_deref(), summary).await?)
+}
+
+// Command to import one Markdown file as a page, as import_vault imports each file: titled
+// after the file name, with its body converted into blocks. A page with the same title is
+// skipped or merged into as options.on_duplicate says.
+#[tauri::command]
+async fn import_markdown_file(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    path: String,
+    options: Option<vault_import::ImportOptions>,
+) -> Result<vault_import::FileImport, CommandError> {
+    let path = Path::new(&path);
+    if !path.is_file() {
+        return Err(CommandError::invalid_input("path", format!("{} is not a file", path.display())));
+    }
+    let options = options.unwrap_or_default();
+    let import = vault_import::import_markdown_file(&state.pool()?, path, &options).await?;
+    if let Some(page_id) = import.page_id {
+        let kind = if import.summary.created > 0 { PageChangeKind::Created } else { PageChangeKind::Edited };
+        page_events::emit_page_changed(&app_handle, page_id, None, kind);
+    }
+    Ok(import)
+}
+
+// Command to import a Roam Research graph exported as JSON. Each Roam page becomes a page
+// of list items, one per block; re-running it updates the pages it imported before. Emits
+// import://progress events (with a total of 0) while it runs. channel_id works as for
+// import_vault.
+#[tauri::command]
+async fn import_roam_json(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    path: String,
+    channel_id: Option<String>,
+) -> Result<transfer::Transferred<roam_import::RoamImportSummary>, CommandError> {
+    let summary = roam_import::import_roam_json(&state.pool()?, &app_handle, Path::new(&path)).await?;
+    Ok(transfer::send_or_return(&app_handle, channel_id.as_deref(), summary).await?)
+}
+
+// params of an import_vault job: the same arguments as the import_vault command takes
+#[derive(serde::Deserialize, Debug)]
+struct ImportVaultJobParams {
+    path: String,
+    #[serde(default)]
+    options: vault_import::ImportOptions,
+}
+
+// Command to start a long-running operation in the background: "import_vault" (params as for
+// the import_vault command) or "normalize_all_pages" (no params). Returns the job ID at once;
+// the job emits job://progress events and its summary ends up in get_job_status.
+#[tauri::command]
+async fn start_job(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    kind: jobs::JobKind,
+    params: Option<Value>,
+) -> Result<String, CommandError> {
+    // Held until the job is registered, so a vault switch either sees it running or happens first
+    let _switching = state.vault_switch.lock().await;
+    let pool = state.pool()?;
+    let params = params.unwrap_or(Value::Null);
+    let job_id = match kind {
+        jobs::JobKind::ImportVault => {
+            let params: ImportVaultJobParams = serde_json::from_value(params)
+                .map_err(|e| CommandError::invalid_input("params", format!("Invalid import_vault params: {}", e)))?;
+            let handle = app_handle.clone();
+            state.jobs.start(&app_handle, kind, move |job| async move {
+                let root = Path::new(&params.path);
+                vault_import::import_vault(&pool, &handle, root, &params.options, Some(&job)).await
+            })
+        }
+        jobs::JobKind::NormalizeAllPages => {
+            let handle = app_handle.clone();
+            state.jobs.start(&app_handle, kind, move |job| async move {
+                page_normalize::normalize_all_pages(&pool, &handle, Some(&job))
+                    .await
+                    .map_err(|e| e.to_string())
+            })
+        }
+    };
+    Ok(job_id.to_string())
+}
+
+// Command to ask a running job to stop once its current batch is done. Returns the job's
+// status as it was; a job that has already stopped is left as it is.
+#[tauri::command]
+fn cancel_job(state: State<AppState>, job_id: String) -> Result<jobs::JobStatus, CommandError> {
+    let job_uuid = parse_uuid(&job_id, "job_id", "job ID")?;
+    state
+        .jobs
+        .cancel(job_uuid)
+        .ok_or_else(|| CommandError::not_found(format!("Job with ID {} not found", job_id)))
+}
+
+// Command to get a job's progress, and its result or error once it has stopped
+#[tauri::command]
+fn get_job_status(state: State<AppState>, job_id: String) -> Result<jobs::JobStatus, CommandError> {
+    let job_uuid = parse_uuid(&job_id, "job_id", "job ID")?;
+    state
+        .jobs
+        .status(job_uuid)
+        .ok_or_else(|| CommandError::not_found(format!("Job with ID {} not found", job_id)))
+}
+
+// Command to list the jobs started since the app launched, newest first. Only the most recent
+// finished jobs are kept.
+#[tauri::command]
+fn list_jobs(state: State<AppState>) -> Vec<jobs::JobStatus> {
+    state.jobs.list()
+}
+
+// Command to back up every note, block, link, tag and recording (with its timestamps and
+// transcript) to one file. With include_audio the backup is a zip that also holds the
+// recordings' audio files; otherwise it is a JSON Lines file. With a channel_id the written
+// file is also sent, base64-encoded, as transfer chunks (e.g. to offer it as a download).
+#[tauri::command]
+async fn create_backup(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    dest_path: String,
+    include_audio: Option<bool>,
+    channel_id: Option<String>,
+) -> Result<CommandBackupSummary, CommandError> {
+    let dest = PathBuf::from(&dest_path);
+    if dest.file_name().is_none() || dest.is_dir() {
+        return Err(CommandError::invalid_input("dest_path", "dest_path must be a file path"));
+    }
+    let app_version = app_handle.package_info().version.to_string();
+    let backup = vault_backup::create_backup(&state.pool()?, &dest, include_audio.unwrap_or(false), app_version).await?;
+    let transfer = match channel_id {
+        Some(channel_id) => Some(transfer::send_file(&app_handle, &channel_id, &dest).await?),
+        None => None,
+    };
+    Ok(CommandBackupSummary { backup, transfer })
+}
+
+#[derive(serde::Serialize, Debug)]
+struct CommandBackupSummary {
+    #[serde(flatten)]
+    backup: vault_backup::BackupSummary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transfer: Option<transfer::TransferSummary>, // Only when the file was sent through a channel
+}
+
+// Command to restore a backup made by create_backup. mode is "replace" (delete everything
+// first) or "merge-by-id" (keep existing rows and overwrite those with the same IDs). IDs are
+// kept; backups from a newer schema than this app's are refused.
+#[tauri::command]
+async fn restore_backup(
+    state: State<'_, AppState>,
+    path: String,
+    mode: vault_backup::RestoreMode,
+) -> Result<vault_backup::RestoreSummary, CommandError> {
+    let path = PathBuf::from(path);
+    if !path.is_file() {
+        return Err(CommandError::invalid_input("path", "Backup file does not exist"));
+    }
+    // Restoring rewrites recordings, including one that is still being written
+    if audio::has_active_recordings() {
+        return Err(CommandError::conflict("Stop all recordings before restoring a backup"));
+    }
+    let audio_dir = state.audio_dir.read().await.clone();
+    Ok(vault_backup::restore_backup(&state.pool()?, &path, mode, &audio_dir).await?)
+}
+
+// Command to create a daily note, or return today's if it already exists. Uses the configured
+// daily note template unless use_template is false or no template is set. Safe to call from
+// several windows at once: a unique index on daily note titles prevents duplicates.
+#[tauri::command]
+async fn create_daily_note(state: State<'_, AppState>, use_template: Option<bool>) -> Result<CommandPage, CommandError> {
+    let today = chrono::Local::now().date_naive();
+    get_or_create_daily_page(&state, today, use_template.unwrap_or(true)).await
+}
+
+// Command to open the daily note for any date (YYYY-MM-DD), creating it like create_daily_note
+#[tauri::command]
+async fn get_or_create_daily_note(
+    state: State<'_, AppState>,
+    date: String,
+    use_template: Option<bool>,
+) -> Result<CommandPage, CommandError> {
+    let date = parse_date(&date, "date")?;
+    get_or_create_daily_page(&state, date, use_template.unwrap_or(true)).await
+}
+
+async fn get_or_create_daily_page(
+    state: &AppState,
+    date: chrono::NaiveDate,
+    use_template: bool,
+) -> Result<CommandPage, CommandError> {
+    let date_str = date.format("%Y-%m-%d").to_string();
+    let pool = state.pool()?;
+
+    let template_id = if use_template { daily_note_template_id(state)? } else { None };
+    let template_uuid = template_id
+        .as_deref()
+        .map(|id| parse_uuid(id, "template_id", "daily note template ID"))
+        .transpose()?;
+    let variables = template_variables(&date_str, HashMap::from([("date".to_string(), date_str.clone())]));
+    let daily_page = page_handler::get_or_create_daily_page(&pool, &date_str, template_uuid.map(|id| (id, &variables)))
+        .await
+        .map_err(|e| match (e, &template_id) {
+            (dal_error::DalError::NotFound, Some(template_id)) => {
+                CommandError::not_found(format!("Daily note template {} not found", template_id))
+            }
+            (e, _) => e.into(),
+        })?;
+
+    Ok(CommandPage::from(daily_page))
+}
+
+fn daily_note_template_id(state: &AppState) -> Result<Option<String>, CommandError> {
+    Ok(state
+        .settings
+        .lock()
+        .map_err(|_| "Failed to acquire settings lock".to_string())?
+        .templates
+        .daily_note_template_id
+        .clone())
+}
+
+// Command to add text to the end of today's daily note (creating the note if needed) without
+// opening it, for quick capture. [[Links]] in the text are registered like in an editor save.
+// Returns the new block's ID.
+#[tauri::command]
+async fn append_to_daily_note(
+    state: State<'_, AppState>,
+    text: String,
+    as_block_type: Option<page_handler::AppendBlockType>,
+) -> Result<String, CommandError> {
+    if text.trim().is_empty() {
+        return Err(CommandError::invalid_input("text", "text cannot be empty"));
+    }
+    let today = chrono::Local::now().date_naive();
+    let page = get_or_create_daily_page(&state, today, true).await?;
+    let page_uuid = parse_uuid(&page.id, "page_id", "page ID")?;
+    let block_id = page_handler::append_block_to_page(&state.pool()?, page_uuid, text.trim_end(), as_block_type.unwrap_or_default())
+        .await
+        .map_err(not_found_as("Today's daily note was deleted"))?;
+    Ok(block_id.to_string())
+}
+
+// Command to find the closest existing daily note before or after a date, skipping days
+// without one. Returns None when there is none in that direction.
+#[tauri::command]
+async fn get_adjacent_daily_note(
+    state: State<'_, AppState>,
+    date: String,
+    direction: page_handler::DailyNoteDirection,
+) -> Result<Option<CommandPageMetadata>, CommandError> {
+    let date = parse_date(&date, "date")?;
+    let page = page_handler::get_adjacent_daily_page(&state.pool()?, date, direction).await?;
+    Ok(page.map(CommandPageMetadata::from))
+}
+
+// Longest range list_daily_notes accepts, enough for a year view
+const MAX_DAILY_NOTE_RANGE_DAYS: i64 = 366;
+
+// Command to list the daily notes between two dates (inclusive) for the calendar, with whether
+// each has anything in it beyond its template
+#[tauri::command]
+async fn list_daily_notes(
+    state: State<'_, AppState>,
+    from: String,
+    to: String,
+) -> Result<Vec<page_handler::DailyNoteSummary>, CommandError> {
+    let from = parse_date(&from, "from")?;
+    let to = parse_date(&to, "to")?;
+    if to < from {
+        return Err(CommandError::invalid_input("to", "to cannot be before from"));
+    }
+    if (to - from).num_days() >= MAX_DAILY_NOTE_RANGE_DAYS {
+        return Err(CommandError::invalid_input(
+            "to",
+            format!("The range cannot be longer than {} days", MAX_DAILY_NOTE_RANGE_DAYS),
+        ));
+    }
+    let template_id = match daily_note_template_id(&state)? {
+        Some(template_id) => Some(parse_uuid(&template_id, "template_id", "daily note template ID")?),
+        None => None,
+    };
+    Ok(page_handler::list_daily_pages(&state.pool()?, from, to, template_id).await?)
+}
+
+// Command to delete a note (moves it to the trash; use purge_page for a permanent delete)
+#[tauri::command]
+async fn delete_note(app_handle: AppHandle, state: State<'_, AppState>, note_id: String) -> Result<bool, CommandError> {
+    let page_uuid = parse_uuid(&note_id, "note_id", "page ID")?;
+    trash_page_and_notify(&app_handle, &state, page_uuid).await
+}
+
+// Command to move a page to the trash
+#[tauri::command]
+async fn trash_page(app_handle: AppHandle, state: State<'_, AppState>, id: String) -> Result<bool, CommandError> {
+    let page_uuid = parse_uuid(&id, "id", "page ID")?;
+    trash_page_and_notify(&app_handle, &state, page_uuid).await
+}
+
+// Trashes the page, then emits page://deleted and links://changed for the pages it linked with,
+// whose backlinks and outgoing links no longer show it
+async fn trash_page_and_notify(app_handle: &AppHandle, state: &AppState, page_id: Uuid) -> Result<bool, CommandError> {
+    let pool = state.pool()?;
+    let trashed = page_handler::trash_page(&pool, page_id).await?;
+    if trashed {
+        page_events::emit_page_changed(app_handle, page_id, None, PageChangeKind::Trashed);
+        page_events::emit_links_changed(app_handle, &link_handler::linked_page_ids(&pool, &[page_id]).await?);
+    }
+    Ok(trashed)
+}
+
+// Command to list pages currently in the trash
+#[tauri::command]
+async fn list_trashed_pages(state: State<'_, AppState>) -> Result<Vec<CommandPageMetadata>, CommandError> {
+    let pool = state.pool()?;
+    let pages = db::with_retry(|| page_handler::list_trashed_pages(&pool)).await?;
+    Ok(pages.into_iter().map(CommandPageMetadata::from).collect())
+}
+
+// Command to restore a page from the trash. new_title is required when the original title
+// has since been taken by another page. Emits page://created and links://changed.
+#[tauri::command]
+async fn restore_page(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    id: String,
+    new_title: Option<String>,
+) -> Result<CommandPage, CommandError> {
+    let page_uuid = parse_uuid(&id, "id", "page ID")?;
+    let new_title = new_title.as_deref().map(str::trim);
+    if new_title == Some("") {
+        return Err(CommandError::invalid_input("new_title", "Page title cannot be empty"));
+    }
+
+    page_handler::restore_page(&state.pool()?, page_uuid, new_title)
+        .await
+        .map_err(not_found_as(format!("Page with ID {} is not in the trash", id)))?;
+
+    let page = page_handler::get_page(&state.pool()?, page_uuid)
+        .await
+        .map_err(not_found_as(format!("Page with ID {} not found", id)))?;
+    page_events::emit_page_changed(&app_handle, page_uuid, Some(page.updated_at), PageChangeKind::Restored);
+    page_events::emit_links_changed(&app_handle, &link_handler::linked_page_ids(&state.pool()?, &[page_uuid]).await?);
+    Ok(CommandPage::from(page))
+}
+
+// Command to permanently delete a page. Emits page://deleted and links://changed.
+#[tauri::command]
+async fn purge_page(app_handle: AppHandle, state: State<'_, AppState>, id: String) -> Result<bool, CommandError> {
+    let page_uuid = parse_uuid(&id, "id", "page ID")?;
+    let pool = state.pool()?;
+    // Read before the purge, which deletes the page's links with it
+    let linked_ids = link_handler::linked_page_ids(&pool, &[page_uuid]).await?;
+    let purged = page_handler::purge_page(&pool, page_uuid).await?;
+    if purged {
+        page_events::emit_page_changed(&app_handle, page_uuid, None, PageChangeKind::Purged);
+        page_events::emit_links_changed(&app_handle, &linked_ids);
+    }
+    Ok(purged)
+}
+
+// Command to permanently delete trashed pages older than the given number of days
+#[tauri::command]
+async fn empty_trash(state: State<'_, AppState>, older_than_days: Option<i32>) -> Result<u64, CommandError> {
+    let older_than_days = older_than_days.unwrap_or(0);
+    if older_than_days < 0 {
+        return Err(CommandError::invalid_input("older_than_days", "older_than_days cannot be negative"));
+    }
+    page_handler::empty_trash(&state.pool()?, older_than_days)
+        .await
+        .map_err(CommandError::from)
+}
+
+// Outcome for one ID passed to a bulk command. Results come back in the order the IDs were
+// given, so a bad ID fails on its own instead of failing the whole batch.
+#[derive(serde::Serialize, Debug)]
+struct CommandBulkResult {
+    id: String,
+    success: bool,
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_path: Option<String>, // Set by bulk_export_pages
+}
+
+impl CommandBulkResult {
+    fn succeeded(id: &str) -> Self {
+        CommandBulkResult {
+            id: id.to_string(),
+            success: true,
+            error: None,
+            file_path: None,
+        }
+    }
+
+    fn failed(id: &str, error: impl Into<String>) -> Self {
+        CommandBulkResult {
+            id: id.to_string(),
+            success: false,
+            error: Some(error.into()),
+            file_path: None,
+        }
+    }
+}
+
+// The IDs of a bulk command that parse as UUIDs
+fn parse_bulk_ids(ids: &[String]) -> Vec<Uuid> {
+    ids.iter().filter_map(|id| Uuid::parse_str(id).ok()).collect()
+}
+
+// One result per ID: success for those in done, missing_error for other valid IDs
+fn bulk_results(ids: &[String], done: &std::collections::HashSet<Uuid>, missing_error: &str) -> Vec<CommandBulkResult> {
+    ids.iter()
+        .map(|id| match Uuid::parse_str(id) {
+            Ok(uuid) if done.contains(&uuid) => CommandBulkResult::succeeded(id),
+            Ok(_) => CommandBulkResult::failed(id, missing_error),
+            Err(e) => CommandBulkResult::failed(id, format!("Invalid page ID format: {}", e)),
+        })
+        .collect()
+}
+
+// Command to move several pages to the trash in one transaction. Emits page://deleted for each
+// and links://changed once for all of them.
+#[tauri::command]
+async fn bulk_delete_pages(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    ids: Vec<String>,
+) -> Result<Vec<CommandBulkResult>, CommandError> {
+    let pool = state.pool()?;
+    let trashed = page_handler::trash_pages(&pool, &parse_bulk_ids(&ids)).await?;
+    for page_id in &trashed {
+        page_events::emit_page_changed(&app_handle, *page_id, None, PageChangeKind::Trashed);
+    }
+    if !trashed.is_empty() {
+        page_events::emit_links_changed(&app_handle, &link_handler::linked_page_ids(&pool, &trashed).await?);
+    }
+    Ok(bulk_results(&ids, &trashed.into_iter().collect(), "Page not found or already in the trash"))
+}
+
+// Command to apply the same change (tags, favorite, template) to several pages in one transaction
+#[tauri::command]
+async fn bulk_update_pages(
+    state: State<'_, AppState>,
+    ids: Vec<String>,
+    patch: page_handler::PagePatch,
+) -> Result<Vec<CommandBulkResult>, CommandError> {
+    if patch.is_empty() {
+        return Err(CommandError::invalid_input("patch", "The patch doesn't change anything"));
+    }
+    let patch = page_handler::PagePatch {
+        add_tags: patch
+            .add_tags
+            .iter()
+            .map(|name| normalize_tag_name(name, "patch.add_tags").map(str::to_string))
+            .collect::<Result<_, _>>()?,
+        remove_tags: patch
+            .remove_tags
+            .iter()
+            .map(|name| normalize_tag_name(name, "patch.remove_tags").map(str::to_string))
+            .collect::<Result<_, _>>()?,
+        ..patch
+    };
+
+    let updated = page_handler::bulk_update_pages(&state.pool()?, &parse_bulk_ids(&ids), &patch).await?;
+    Ok(bulk_results(&ids, &updated.into_iter().collect(), "Page not found or in the trash"))
+}
+
+// Command to write several pages as Markdown files (with front matter) into a directory.
+// Unlike notes directory sync, nothing is recorded, so the files can go anywhere.
+#[tauri::command]
+async fn bulk_export_pages(
+    state: State<'_, AppState>,
+    ids: Vec<String>,
+    dir: String,
+) -> Result<Vec<CommandBulkResult>, CommandError> {
+    let dir = PathBuf::from(dir);
+    if !dir.is_dir() {
+        return Err(CommandError::invalid_input("dir", "Directory does not exist"));
+    }
+
+    let pages = page_handler::get_pages(&state.pool()?, &parse_bulk_ids(&ids)).await?;
+    let pages_by_id: HashMap<Uuid, DalPage> = pages.into_iter().map(|page| (page.id, page)).collect();
+    let mut used_paths = std::collections::HashSet::new();
+    let results = ids
+        .iter()
+        .map(|id| {
+            let uuid = match Uuid::parse_str(id) {
+                Ok(uuid) => uuid,
+                Err(e) => return CommandBulkResult::failed(id, format!("Invalid page ID format: {}", e)),
+            };
+            let Some(page) = pages_by_id.get(&uuid) else {
+                return CommandBulkResult::failed(id, "Page not found or in the trash");
+            };
+            match note_sync::write_page_file(&dir, page, &mut used_paths) {
+                Ok(path) => CommandBulkResult {
+                    file_path: Some(path.to_string_lossy().to_string()),
+                    ..CommandBulkResult::succeeded(id)
+                },
+                Err(e) => CommandBulkResult::failed(id, e),
+            }
+        })
+        .collect();
+    Ok(results)
+}
+
+// Command to export a page as a standalone HTML file. options.include_linked_pages also exports
+// the pages it links to into the same directory, with the links between them working;
+// options.bundle_audio copies the recordings its blocks have timestamps in, for click-to-seek.
+#[tauri::command]
+async fn export_page_html(
+    state: State<'_, AppState>,
+    page_id: String,
+    dest_path: String,
+    options: Option<html_export::HtmlExportOptions>,
+) -> Result<html_export::HtmlExportSummary, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    let dest = PathBuf::from(&dest_path);
+    if dest.file_name().is_none() || dest.is_dir() {
+        return Err(CommandError::invalid_input("dest_path", "dest_path must be a file path"));
+    }
+    if !dest.parent().is_some_and(|dir| dir.as_os_str().is_empty() || dir.is_dir()) {
+        return Err(CommandError::invalid_input("dest_path", "The destination directory does not exist"));
+    }
+
+    let pool = state.pool()?;
+    let page = page_handler::get_page(&pool, page_uuid)
+        .await
+        .map_err(not_found_as(format!("Page with ID {} not found", page_id)))?;
+    if page.deleted_at.is_some() {
+        return Err(CommandError::conflict("The page is in the trash"));
+    }
+    let vault_key = state.vault_key()?;
+    Ok(html_export::export_page_html(&pool, page, &dest, options.unwrap_or_default(), vault_key.as_ref()).await?)
+}
+
+// Command to find backlinks for a note, one per linking block
+#[tauri::command]
+async fn find_backlinks(state: State<'_, AppState>, note_id: String) -> Result<Vec<CommandBacklink>, CommandError> {
+    let page_uuid = parse_uuid(&note_id, "note_id", "page ID")?;
+
+    let pool = state.pool()?;
+    let backlinks = db::with_retry(|| link_handler::find_backlink_sources_for_page(&pool, page_uuid, true)).await?;
+    Ok(backlinks.into_iter().map(CommandBacklink::from).collect())
+}
+
+// Command to find the pages a note links to, plus the `[[titles]]` in it that match no page
+#[tauri::command]
+async fn find_outgoing_links(state: State<'_, AppState>, page_id: String) -> Result<CommandOutgoingLinks, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+
+    let pool = state.pool()?;
+    let links = db::with_retry(|| link_handler::find_outgoing_link_pages(&pool, page_uuid)).await?;
+    let unresolved = db::with_retry(|| link_handler::find_unresolved_links_for_page(&pool, page_uuid)).await?;
+    Ok(CommandOutgoingLinks {
+        links: links.into_iter().map(CommandOutgoingLink::from).collect(),
+        unresolved: unresolved.into_iter().map(CommandUnresolvedLink::from).collect(),
+    })
+}
+
+// Command to start recording. audio_format is "wav" (default), "flac" or "opus"; track_mode
+// is "mixed" (default), "split" (separate mic and system audio files) or "mono_mixed"
+#[tauri::command]
+#[allow(clippy::too_many_arguments)] // Each argument is a separate optional field of the IPC call
 async fn start_recording(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    page_id: Option<String>,
+    recording_id: String,
+    mic_device_name: Option<String>,
+    loopback_device_name: Option<String>,
+    audio_format: Option<String>,
+    track_mode: Option<audio::TrackMode>,
+    mic_gain: Option<f32>,
+    loopback_gain: Option<f32>,
+    silence: Option<audio::SilenceSettings>,
+    max_duration_minutes: Option<u64>, // 0 for no limit
+    min_free_space_mb: Option<u64>,
+    ring_buffer_capacity: Option<usize>, // Samples per stream
+    title: Option<String>, // Defaults to the page's title and today's date
+) -> Result<audio::StartedRecording, CommandError> {
+    let (recording_settings, vault_configured) = {
+        let app_settings = state.settings.lock().map_err(|_| "Failed to acquire settings lock".to_string())?;
+        (app_settings.recording.clone(), app_settings.vault.is_configured())
+    };
+    // Once the vault has a passphrase every recording is encrypted, so none can start while locked
+    let vault_key = state.vault_key()?;
+    if vault_configured && vault_key.is_none() {
+        return Err(CommandError::vault_locked(
+            "Recordings are encrypted and the vault is locked. Unlock it to start recording.",
+        ));
+    }
+    let max_duration_minutes = max_duration_minutes.unwrap_or(recording_settings.max_duration_minutes);
+    let limits = audio::RecordingLimits {
+        max_duration: (max_duration_minutes > 0).then(|| std::time::Duration::from_secs(max_duration_minutes * 60)),
+        min_free_space_bytes: min_free_space_mb.unwrap_or(recording_settings.min_free_space_mb) * 1_000_000,
+    };
+    let silence = silence.unwrap_or_default();
+    if !(0.0..=1.0).contains(&silence.threshold) {
+        return Err(CommandError::invalid_input("silence", "Silence threshold must be between 0.0 and 1.0"));
+    }
+    let ring_buffer_capacity = ring_buffer_capacity.unwrap_or(audio::DEFAULT_RING_BUFFER_CAPACITY);
+    if !(audio::MIN_RING_BUFFER_CAPACITY..=audio::MAX_RING_BUFFER_CAPACITY).contains(&ring_buffer_capacity) {
+        return Err(CommandError::invalid_input(
+            "ring_buffer_capacity",
+            format!(
+                "Ring buffer capacity must be between {} and {} samples",
+                audio::MIN_RING_BUFFER_CAPACITY,
+                audio::MAX_RING_BUFFER_CAPACITY
+            ),
+        ));
+    }
+    let format = match audio_format.as_deref() {
+        Some(value) => audio_encoder::AudioFormat::parse(value).map_err(|e| CommandError::invalid_input("audio_format", e))?,
+        None => audio_encoder::AudioFormat::default(),
+    };
+    let title = match title.as_deref().map(str::trim).filter(|title| !title.is_empty()) {
+        Some(title) => Some(title.to_string()),
+        None => default_recording_title(&state, page_id.as_deref()).await,
+    };
+    let audio_dir = state.audio_dir.read().await.clone();
+    let audio_dir_str = audio_dir.to_str().ok_or_else(|| "Audio directory path is not valid UTF-8".to_string())?;
+
+    audio::start_recording(
+        app_handle,
+        page_id.as_deref(),
+        &recording_id,
+        audio_dir_str,
+        audio::RecordingOptions {
+            mic_device_name: mic_device_name.as_deref(),
+            loopback_device_name: loopback_device_name.as_deref(),
+            format,
+            track_mode: track_mode.unwrap_or_default(),
+            mic_gain: mic_gain.unwrap_or(audio::DEFAULT_GAIN),
+            loopback_gain: loopback_gain.unwrap_or(audio::DEFAULT_GAIN),
+            silence,
+            limits,
+            ring_buffer_capacity,
+            encryption_key: vault_key.as_ref(),
+            title: title.as_deref(),
+        },
+    )
+    .map_err(|e| match e {
+        audio::StartRecordingError::InvalidId(message) => CommandError::invalid_input("recording_id", message),
+        audio::StartRecordingError::AlreadyRecording(message) => CommandError::already_recording(message),
+        audio::StartRecordingError::Failed(message) => CommandError::audio_device(message),
+    })
+}
+
+// "<page title> <date>" for a recording of a page, or None without a page or database. A daily
+// note already named after today isn't given the date twice.
+async fn default_recording_title(state: &AppState, page_id: Option<&str>) -> Option<String> {
+    let page_uuid = Uuid::parse_str(page_id?).ok()?;
+    let page = page_handler::get_page(&state.pool().ok()?, page_uuid).await.ok()?;
+    let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+    if page.title == today {
+        Some(page.title)
+    } else {
+        Some(format!("{} {}", page.title, today))
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+struct CommandVaultStatus {
+    configured: bool, // A passphrase has been set, so new recordings are encrypted
+    unlocked: bool,
+}
+
+fn vault_status(state: &AppState) -> Result<CommandVaultStatus, CommandError> {
+    let configured = state
+        .settings
+        .lock()
+        .map_err(|_| "Failed to acquire settings lock".to_string())?
+        .vault
+        .is_configured();
+    Ok(CommandVaultStatus {
+        configured,
+        unlocked: state.vault_key()?.is_some(),
+    })
+}
+
+// Command to report whether recordings are encrypted and whether the vault is unlocked
+#[tauri::command]
+fn get_vault_lock_status(state: State<AppState>) -> Result<CommandVaultStatus, CommandError> {
+    vault_status(&state)
+}
+
+// Command to set the passphrase that protects recordings, which turns on encryption for new
+// ones. Changing it needs current_passphrase; the vault key itself stays the same, so files
+// encrypted before remain readable. The vault is left unlocked.
+#[tauri::command]
+async fn set_vault_passphrase(
+    state: State<'_, AppState>,
+    passphrase: String,
+    current_passphrase: Option<String>,
+) -> Result<CommandVaultStatus, CommandError> {
+    if passphrase.chars().count() < vault_crypto::MIN_PASSPHRASE_LEN {
+        return Err(CommandError::invalid_input(
+            "passphrase",
+            format!("The passphrase must be at least {} characters", vault_crypto::MIN_PASSPHRASE_LEN),
+        ));
+    }
+    let vault = state
+        .settings
+        .lock()
+        .map_err(|_| "Failed to acquire settings lock".to_string())?
+        .vault
+        .clone();
+    let current_passphrase = match vault.is_configured() {
+        true => Some(current_passphrase.ok_or_else(|| {
+            CommandError::invalid_input("current_passphrase", "The current passphrase is needed to change it")
+        })?),
+        false => None,
+    };
+
+    // Argon2 is deliberately slow, so keep it off the async runtime
+    let (vault, key) = tokio::task::spawn_blocking(move || {
+        let mut vault = vault;
+        let key = match current_passphrase {
+            Some(current) => vault_crypto::unwrap_key(&current, &vault)?.ok_or_else(|| {
+                CommandError::invalid_input("current_passphrase", "The current passphrase is incorrect")
+            })?,
+            None => vault_crypto::VaultKey::generate(),
+        };
+        vault_crypto::wrap_key(&key, &passphrase, &mut vault)?;
+        Ok::<_, CommandError>((vault, key))
+    })
+    .await
+    .map_err(|e| format!("Vault key task failed: {}", e))??;
+
+    {
+        let mut app_settings = state.settings.lock().map_err(|_| "Failed to acquire settings lock".to_string())?;
+        app_settings.vault = vault;
+        settings::save(&state.app_data_dir, &app_settings)?;
+    }
+    *state.vault_key.write().map_err(|_| "Failed to acquire vault key lock".to_string())? = Some(key);
+    vault_status(&state)
+}
+
+// Command to unlock the vault, keeping its key in memory until lock_vault or the app exits
+#[tauri::command]
+async fn unlock_vault(state: State<'_, AppState>, passphrase: String) -> Result<CommandVaultStatus, CommandError> {
+    let vault = state
+        .settings
+        .lock()
+        .map_err(|_| "Failed to acquire settings lock".to_string())?
+        .vault
+        .clone();
+    if !vault.is_configured() {
+        return Err(CommandError::conflict("No vault passphrase has been set"));
+    }
+
+    let key = tokio::task::spawn_blocking(move || vault_crypto::unwrap_key(&passphrase, &vault))
+        .await
+        .map_err(|e| format!("Vault key task failed: {}", e))??
+        .ok_or_else(|| CommandError::invalid_input("passphrase", "Incorrect passphrase"))?;
+    *state.vault_key.write().map_err(|_| "Failed to acquire vault key lock".to_string())? = Some(key);
+    vault_status(&state)
+}
+
+// Command to forget the vault key. Recordings already running keep writing encrypted audio;
+// new ones are refused until the vault is unlocked again.
+#[tauri::command]
+fn lock_vault(state: State<AppState>) -> Result<CommandVaultStatus, CommandError> {
+    *state.vault_key.write().map_err(|_| "Failed to acquire vault key lock".to_string())? = None;
+    vault_status(&state)
+}
+
+// Command to encrypt the recordings made before the vault had a passphrase. Progress is sent as
+// vault://encrypt-progress events; recordings still running are skipped.
+#[tauri::command]
+async fn encrypt_existing_audio(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<audio_encryption::EncryptSummary, CommandError> {
+    if !vault_status(&state)?.configured {
+        return Err(CommandError::conflict("Set a vault passphrase before encrypting recordings"));
+    }
+    let key = state
+        .vault_key()?
+        .ok_or_else(|| CommandError::vault_locked(vault_crypto::LOCKED_MESSAGE))?;
+    Ok(audio_encryption::encrypt_existing_audio(&state.pool()?, &app_handle, &key).await?)
+}
+
+// Command to list available audio input devices
+#[tauri::command]
+fn list_audio_devices() -> Result<Vec<audio::AudioDeviceInfo>, CommandError> {
+    audio::list_input_devices().map_err(CommandError::audio_device)
+}
+
+// Command to get details (devices, elapsed time) of an active recording
+#[tauri::command]
+fn get_recording_info(recording_id: String) -> Result<audio::RecordingInfo, CommandError> {
+    audio::get_recording_info(&recording_id).map_err(CommandError::not_found)
+}
+
+// Command to get an active recording's sample counters (pushed, dropped, stream errors, underruns)
+#[tauri::command]
+fn get_recording_diagnostics(recording_id: String) -> Result<audio::RecordingDiagnostics, CommandError> {
+    audio::get_recording_diagnostics(&recording_id).map_err(CommandError::not_found)
+}
+
+// Command to list every running recording, longest running first
+#[tauri::command]
+fn list_active_recordings() -> Vec<audio::RecordingInfo> {
+    audio::list_active_recordings()
+}
+
+// Command to change an active recording's mic and loopback gains (0.0 to 4.0, 1.0 = unchanged)
+#[tauri::command]
+fn set_recording_gain(
+    recording_id: String,
+    mic_gain: Option<f32>,
+    loopback_gain: Option<f32>,
+) -> Result<audio::RecordingInfo, CommandError> {
+    audio::set_recording_gain(&recording_id, mic_gain, loopback_gain).map_err(CommandError::not_found)
+}
+
+// Command to get how far into an active recording we are, in milliseconds
+#[tauri::command]
+fn get_recording_elapsed_ms(recording_id: String) -> Result<u64, CommandError> {
+    audio::get_recording_elapsed_ms(&recording_id).map_err(CommandError::not_found)
+}
+
+// Command to check whether a recording is currently running
+#[tauri::command]
+fn is_recording_active(recording_id: String) -> bool {
+    audio::is_recording_active(&recording_id)
+}
+
+// Command to stop recording
+#[tauri::command]
+async fn stop_recording(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    recording_id: String,
+) -> Result<CommandStoppedRecording, CommandError> {
+    let rec_uuid = parse_uuid(&recording_id, "recording_id", "recording ID")?;
+
+    let stopped = audio::stop_recording(rec_uuid.to_string(), &state.pool()?, &app_handle)
+        .await
+        .map_err(CommandError::audio_device)?;
+
+    Ok(CommandStoppedRecording::from(stopped))
+}
+
+// Command to stop every running recording. One that fails to stop is reported without keeping
+// the others from being stopped.
+#[tauri::command]
+async fn stop_all_recordings(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<CommandStopAllRecordings, CommandError> {
+    let pool = state.pool()?;
+    let mut result = CommandStopAllRecordings { stopped: Vec::new(), failures: Vec::new() };
+    for info in audio::list_active_recordings() {
+        match audio::stop_recording(info.recording_id.clone(), &pool, &app_handle).await {
+            Ok(stopped) => result.stopped.push(CommandStoppedRecording::from(stopped)),
+            Err(error) => result.failures.push(CommandRecordingFailure { recording_id: info.recording_id, error }),
+        }
+    }
+    Ok(result)
+}
+
+// Command to salvage recordings left unfinished by a crash
+#[tauri::command]
+async fn recover_recordings(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+) -> Result<recording_recovery::RecoveryReport, CommandError> {
+    let audio_dir = state.audio_dir.read().await.clone();
+    Ok(recover_and_report_recordings(&app_handle, &state.pool()?, &audio_dir).await?)
+}
+
+// Command to get audio recordings for a note
+#[tauri::command]
+async fn get_audio_recordings(state: State<'_, AppState>, page_id: String) -> Result<Vec<CommandAudioRecording>, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    let pool = state.pool()?;
+    let recordings = db::with_retry(|| audio_handler::get_audio_recordings_for_page(&pool, page_uuid)).await?;
+    let result: Vec<CommandAudioRecording> = recordings.into_iter().map(CommandAudioRecording::from).collect();
+    Ok(result)
+}
+
+// Command to list recordings that aren't attached to a page, so they can be claimed
+#[tauri::command]
+async fn list_unassigned_recordings(state: State<'_, AppState>) -> Result<Vec<CommandAudioRecording>, CommandError> {
+    let recordings = audio_handler::get_unassigned_audio_recordings(&state.pool()?).await?;
+    Ok(recordings.into_iter().map(CommandAudioRecording::from).collect())
+}
+
+// Command to attach a recording to a page, or to detach it when page_id is null
+#[tauri::command]
+async fn set_recording_page(
+    state: State<'_, AppState>,
+    recording_id: String,
+    page_id: Option<String>,
+) -> Result<CommandAudioRecording, CommandError> {
+    let recording_uuid = parse_uuid(&recording_id, "recording_id", "recording ID")?;
+    let page_uuid = page_id
+        .as_deref()
+        .map(|id| parse_uuid(id, "page_id", "page ID"))
+        .transpose()?;
+
+    let pool = state.pool()?;
+    if let Some(page_uuid) = page_uuid {
+        let page = page_handler::get_page(&pool, page_uuid)
+            .await
+            .map_err(not_found_as(format!("Page with ID {} not found", page_uuid)))?;
+        if page.deleted_at.is_some() {
+            return Err(CommandError::invalid_input("page_id", "Cannot attach a recording to a page in the trash"));
+        }
+    }
+
+    let recording = audio_handler::update_audio_recording_page(&pool, recording_uuid, page_uuid)
+        .await?
+        .ok_or_else(|| CommandError::not_found(format!("Audio recording with ID {} not found", recording_id)))?;
+    Ok(CommandAudioRecording::from(recording))
+}
+
+// Command to name a recording and describe it. A field left out (null) is kept; an empty
+// string clears it.
+#[tauri::command]
+async fn update_recording_metadata(
+    state: State<'_, AppState>,
+    recording_id: String,
+    title: Option<String>,
+    description: Option<String>,
+) -> Result<CommandAudioRecording, CommandError> {
+    let recording_uuid = parse_uuid(&recording_id, "recording_id", "recording ID")?;
+    let title = title.as_deref().map(|title| Some(title.trim()).filter(|title| !title.is_empty()));
+    let description = description.as_deref().map(|description| Some(description).filter(|d| !d.trim().is_empty()));
+
+    let recording = audio_handler::update_audio_recording_metadata(&state.pool()?, recording_uuid, title, description)
+        .await?
+        .ok_or_else(|| CommandError::not_found(format!("Audio recording with ID {} not found", recording_id)))?;
+    Ok(CommandAudioRecording::from(recording))
+}
+
+// A recording in the vault-wide list, with the page it belongs to
+#[derive(serde::Serialize, Debug)]
+struct CommandRecordingListing {
+    #[serde(flatten)]
+    recording: CommandAudioRecording,
+    page_title: Option<String>, // None for recordings attached to no page
+    has_transcript: bool,
+    timestamp_count: i64,
+}
+
+// Command to list the recordings of every page, newest first, optionally narrowed by a filter
+// (created_from/created_to, has_transcript, has_timestamps)
+#[tauri::command]
+async fn list_all_recordings(
+    state: State<'_, AppState>,
+    filter: Option<audio_handler::RecordingFilter>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<Vec<CommandRecordingListing>, CommandError> {
+    let (limit, offset) = resolve_pagination(limit, offset)?;
+    let filter = filter.unwrap_or_default();
+    if let (Some(from), Some(to)) = (filter.created_from, filter.created_to) {
+        if from > to {
+            return Err(CommandError::invalid_input("filter.created_from", "created_from must not be after created_to"));
+        }
+    }
+
+    let pool = state.pool()?;
+    let recordings = db::with_retry(|| audio_handler::list_all_recordings(&pool, &filter, limit, offset)).await?;
+    Ok(recordings
+        .into_iter()
+        .map(|listing| CommandRecordingListing {
+            recording: CommandAudioRecording::from(listing.recording),
+            page_title: listing.page_title,
+            has_transcript: listing.has_transcript,
+            timestamp_count: listing.timestamp_count,
+        })
+        .collect())
+}
+
+// Command to get a block's audio timestamps, with the recording each one points into
+#[tauri::command]
+async fn get_block_audio_timestamps(
+    state: State<'_, AppState>,
+    block_id: String,
+) -> Result<Vec<CommandBlockAudioTimestamp>, CommandError> {
+    let block_uuid = parse_uuid(&block_id, "block_id", "block ID")?;
+    let timestamps = audio_handler::get_audio_timestamps_for_block(&state.pool()?, block_uuid).await?;
+    Ok(timestamps.into_iter().map(CommandBlockAudioTimestamp::from).collect())
+}
+
+// Command to get the audio timestamps of every block on a page in one call, keyed by block_id.
+// Blocks without timestamps are left out.
+#[tauri::command]
+async fn get_audio_timestamps_for_page(
+    state: State<'_, AppState>,
+    page_id: String,
+) -> Result<HashMap<String, Vec<CommandBlockAudioTimestamp>>, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    let timestamps = audio_handler::get_audio_timestamps_for_page(&state.pool()?, page_uuid).await?;
+
+    let mut by_block: HashMap<String, Vec<CommandBlockAudioTimestamp>> = HashMap::new();
+    for timestamp in timestamps {
+        by_block
+            .entry(timestamp.block_id.to_string())
+            .or_default()
+            .push(CommandBlockAudioTimestamp::from(timestamp));
+    }
+    Ok(by_block)
+}
+
+// Command to store a transcript made outside the app (e.g. by Whisper) for a recording,
+// replacing any it already has. format is "whisper_json", "srt" or "vtt".
+#[tauri::command]
+async fn import_transcript(
+    state: State<'_, AppState>,
+    recording_id: String,
+    format: transcript_import::TranscriptFormat,
+    data: String,
+) -> Result<transcript_handler::Transcript, CommandError> {
+    let recording_uuid = parse_uuid(&recording_id, "recording_id", "recording ID")?;
+    let parsed = transcript_import::parse_transcript(format, &data).map_err(|e| CommandError::invalid_input("data", e))?;
+
+    let pool = state.pool()?;
+    audio_handler::get_audio_recording(&pool, recording_uuid)
+        .await
+        .map_err(not_found_as(format!("Audio recording with ID {} not found", recording_id)))?;
+    transcript_handler::save_transcript(&pool, recording_uuid, &parsed.full_text, &parsed.segments)
+        .await
+        .map_err(CommandError::from)
+}
+
+// Command to get a recording's transcript
+#[tauri::command]
+async fn get_transcript(state: State<'_, AppState>, recording_id: String) -> Result<transcript_handler::Transcript, CommandError> {
+    let recording_uuid = parse_uuid(&recording_id, "recording_id", "recording ID")?;
+    transcript_handler::get_transcript(&state.pool()?, recording_uuid)
+        .await
+        .map_err(not_found_as(format!("Recording {} has no transcript", recording_id)))
+}
+
+// Command to search transcript segments, across the vault or within one page's recordings
+#[tauri::command]
+async fn search_transcripts(
     state: State<'_, AppState>,
+    query: String,
     page_id: Option<String>,
+    limit: Option<i64>,
+) -> Result<Vec<transcript_handler::TranscriptMatch>, CommandError> {
+    let query = query.trim();
+    if query.is_empty() {
+        return Err(CommandError::invalid_input("query", "Search query cannot be empty"));
+    }
+    let page_uuid = page_id
+        .as_deref()
+        .map(|id| parse_uuid(id, "page_id", "page ID"))
+        .transpose()?;
+    let (limit, _) = resolve_pagination(limit, None)?;
+
+    transcript_handler::search_transcripts(&state.pool()?, query, page_uuid, limit)
+        .await
+        .map_err(CommandError::from)
+}
+
+// Command to read part of a recording for playback, e.g. from a block's audio timestamp.
+// The range is clamped to the file, so it works for recordings without a stored duration.
+// track picks the file of a split recording (see recording_track_path).
+#[tauri::command]
+async fn get_audio_data(
+    state: State<'_, AppState>,
+    recording_id: String,
+    start_ms: Option<u64>,
+    end_ms: Option<u64>,
+    track: Option<String>,
+) -> Result<audio_playback::AudioClip, CommandError> {
+    let recording_uuid = parse_uuid(&recording_id, "recording_id", "recording ID")?;
+    if let (Some(start_ms), Some(end_ms)) = (start_ms, end_ms) {
+        if end_ms < start_ms {
+            return Err(CommandError::invalid_input("end_ms", "end_ms cannot be before start_ms"));
+        }
+    }
+
+    let recording = audio_handler::get_audio_recording(&state.pool()?, recording_uuid)
+        .await
+        .map_err(not_found_as(format!("Audio recording with ID {} not found", recording_id)))?;
+    let track_path = recording_track_path(&recording, track.as_deref())?;
+    let file_path = PathBuf::from(&track_path);
+    if !file_path.is_file() {
+        return Err(CommandError::not_found(format!("Audio file {} not found", track_path)));
+    }
+    let vault_key = state.vault_key_for_file(&file_path)?;
+
+    let clip = tokio::task::spawn_blocking(move || {
+        audio_playback::read_wav_range(&file_path, start_ms, end_ms, vault_key.as_ref())
+    })
+    .await
+    .map_err(|e| format!("Audio read task failed: {}", e))??;
+    Ok(clip)
+}
+
+// Command to get a URL the webview can stream a whole recording (or one track of a split
+// recording) from
+#[tauri::command]
+async fn get_audio_file_url(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
     recording_id: String,
-) -> Result<String, String> {
-    let audio_dir_pathbuf = state.audio_dir.lock().map_err(|_| "Failed to acquire audio directory lock".to_string())?;
-    let audio_dir_str = audio_dir_pathbuf.to_str().ok_or_else(|| "Audio directory path is not valid UTF-8".to_string())?;
+    track: Option<String>,
+) -> Result<audio_playback::AudioFileUrl, CommandError> {
+    let recording_uuid = parse_uuid(&recording_id, "recording_id", "recording ID")?;
+    let recording = audio_handler::get_audio_recording(&state.pool()?, recording_uuid)
+        .await
+        .map_err(not_found_as(format!("Audio recording with ID {} not found", recording_id)))?;
+    let track_path = recording_track_path(&recording, track.as_deref())?;
+    let file_path = PathBuf::from(&track_path);
+    if !file_path.is_file() {
+        return Err(CommandError::not_found(format!("Audio file {} not found", track_path)));
+    }
+    // The webview would be handed the encrypted bytes
+    if vault_crypto::is_encrypted(&file_path)? {
+        return Err(CommandError::conflict(
+            "Encrypted recordings can't be streamed; play them in ranges with get_audio_data",
+        ));
+    }
 
-    audio::start_recording(
-        page_id.as_deref(),
-        &recording_id,
-        audio_dir_str,
-    )
+    // The configured scope only covers $APP, and the audio directory can be moved in settings
+    app_handle
+        .asset_protocol_scope()
+        .allow_file(&file_path)
+        .map_err(|e| format!("Failed to allow audio file for playback: {}", e))?;
+
+    Ok(audio_playback::AudioFileUrl {
+        url: audio_playback::asset_url(&file_path),
+        file_path: track_path,
+        mime_type: recording.mime_type,
+    })
 }
 
-// Command to stop recording
+// The file to play for track: the recording's only file when track is None, or "mic" or
+// "system" for the two files of a split recording
+fn recording_track_path(recording: &DalAudioRecording, track: Option<&str>) -> Result<String, CommandError> {
+    let is_split = recording.track_mode == audio::TrackMode::Split.as_str();
+    match track {
+        None => Ok(recording.file_path.clone()),
+        Some("mic") if is_split => Ok(recording.file_path.clone()),
+        Some("system") if is_split => recording.secondary_file_path.clone().ok_or_else(|| {
+            CommandError::not_found(format!("Recording {} has no system audio track", recording.id))
+        }),
+        Some(_) if !is_split => Err(CommandError::invalid_input("track", "Only split recordings have separate tracks")),
+        Some(_) => Err(CommandError::invalid_input("track", "track must be \"mic\" or \"system\"")),
+    }
+}
+
+// Default context around a timestamp for export_clip_around_timestamp
+const DEFAULT_CLIP_BEFORE_MS: u64 = 5_000;
+const DEFAULT_CLIP_AFTER_MS: u64 = 120_000;
+
+// Command to save part of a recording as a standalone audio file, e.g. to share one answer
+// from a lecture. format is wav, flac or opus; without it the destination's extension decides,
+// and a destination without an extension gets the format's. The range is clamped to the file.
 #[tauri::command]
-async fn stop_recording(state: State<'_, AppState>, recording_id: String) -> Result<CommandAudioRecording, String> {
-    let rec_uuid = Uuid::parse_str(&recording_id).map_err(|e| format!("Invalid recording ID: {}", e))?;
+async fn export_audio_clip(
+    state: State<'_, AppState>,
+    recording_id: String,
+    start_ms: u64,
+    end_ms: u64,
+    dest_path: String,
+    format: Option<String>,
+) -> Result<audio_playback::ExportedClip, CommandError> {
+    let recording_uuid = parse_uuid(&recording_id, "recording_id", "recording ID")?;
+    if end_ms <= start_ms {
+        return Err(CommandError::invalid_input("end_ms", "end_ms must be after start_ms"));
+    }
+    let recording = audio_handler::get_audio_recording(&state.pool()?, recording_uuid)
+        .await
+        .map_err(not_found_as(format!("Audio recording with ID {} not found", recording_id)))?;
+    write_audio_clip(&state, recording.file_path, start_ms, end_ms, dest_path, format).await
+}
 
-    let dal_audio_recording = audio::stop_recording(rec_uuid.to_string(), &state.pool)
+// Command to save the audio around a block's timestamp as a standalone file: before_ms before
+// it and after_ms after it, clamped to the recording
+#[tauri::command]
+async fn export_clip_around_timestamp(
+    state: State<'_, AppState>,
+    timestamp_id: String,
+    before_ms: Option<u64>,
+    after_ms: Option<u64>,
+    dest_path: String,
+    format: Option<String>,
+) -> Result<audio_playback::ExportedClip, CommandError> {
+    let timestamp_uuid = parse_uuid(&timestamp_id, "timestamp_id", "timestamp ID")?;
+    let pool = state.pool()?;
+    let timestamp = audio_handler::get_audio_timestamp(&pool, timestamp_uuid)
+        .await?
+        .ok_or_else(|| CommandError::not_found(format!("Audio timestamp with ID {} not found", timestamp_id)))?;
+    let recording = audio_handler::get_audio_recording(&pool, timestamp.audio_recording_id)
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(not_found_as(format!(
+            "Audio recording with ID {} not found",
+            timestamp.audio_recording_id
+        )))?;
 
-    Ok(CommandAudioRecording::from(dal_audio_recording))
+    let at_ms = timestamp.timestamp_ms.max(0) as u64;
+    let start_ms = at_ms.saturating_sub(before_ms.unwrap_or(DEFAULT_CLIP_BEFORE_MS));
+    let end_ms = at_ms.saturating_add(after_ms.unwrap_or(DEFAULT_CLIP_AFTER_MS));
+    if end_ms <= start_ms {
+        return Err(CommandError::invalid_input("after_ms", "The clip would be empty"));
+    }
+    write_audio_clip(&state, recording.file_path, start_ms, end_ms, dest_path, format).await
 }
 
-// Command to get audio recordings for a note
+async fn write_audio_clip(
+    state: &AppState,
+    source_path: String,
+    start_ms: u64,
+    end_ms: u64,
+    dest_path: String,
+    format: Option<String>,
+) -> Result<audio_playback::ExportedClip, CommandError> {
+    let source = PathBuf::from(&source_path);
+    if !source.is_file() {
+        return Err(CommandError::not_found(format!("Audio file {} not found", source_path)));
+    }
+
+    let mut dest = PathBuf::from(dest_path.trim());
+    if dest.as_os_str().is_empty() {
+        return Err(CommandError::invalid_input("dest_path", "Destination path cannot be empty"));
+    }
+    let format = match (format, dest.extension().and_then(|ext| ext.to_str())) {
+        (Some(format), _) => audio_encoder::AudioFormat::parse(&format)
+            .map_err(|e| CommandError::invalid_input("format", e))?,
+        (None, Some(ext)) => audio_encoder::AudioFormat::parse(ext)
+            .map_err(|e| CommandError::invalid_input("dest_path", e))?,
+        (None, None) => audio_encoder::AudioFormat::Wav,
+    };
+    if dest.extension().is_none() {
+        dest.set_extension(format.extension());
+    }
+    if dest == source {
+        return Err(CommandError::invalid_input(
+            "dest_path",
+            "The clip cannot overwrite the recording it comes from",
+        ));
+    }
+    let vault_key = state.vault_key_for_file(&source)?;
+
+    let clip = tokio::task::spawn_blocking(move || {
+        audio_playback::export_wav_clip(&source, start_ms, end_ms, &dest, format, vault_key.as_ref())
+    })
+    .await
+    .map_err(|e| format!("Clip export task failed: {}", e))??;
+    Ok(clip)
+}
+
+// Returned by delete_audio_recording: the removed recording and whether its file went with it
+#[derive(serde::Serialize, Debug)]
+struct CommandDeletedRecording {
+    #[serde(flatten)]
+    recording: CommandAudioRecording,
+    file_deleted: bool,
+}
+
+// Command to delete a recording and its timestamps, and optionally its file (both files of a
+// split recording)
 #[tauri::command]
-async fn get_audio_recordings(state: State<'_, AppState>, page_id: String) -> Result<Vec<CommandAudioRecording>, String> {
-    let page_uuid = Uuid::parse_str(&page_id).map_err(|e| format!("Invalid page ID format: {}", e))?;
-    let recordings = audio_handler::get_audio_recordings_for_page(&state.pool, page_uuid)
+async fn delete_audio_recording(
+    state: State<'_, AppState>,
+    recording_id: String,
+    delete_file: bool,
+) -> Result<CommandDeletedRecording, CommandError> {
+    let recording_uuid = parse_uuid(&recording_id, "recording_id", "recording ID")?;
+    if audio::is_recording_active(&recording_uuid.to_string()) {
+        return Err(CommandError::conflict("Stop the recording before deleting it"));
+    }
+
+    // The row goes first: a file left behind by a failed delete is harmless, a row without one isn't
+    let recording = audio_handler::delete_audio_recording(&state.pool()?, recording_uuid)
+        .await?
+        .ok_or_else(|| CommandError::not_found(format!("Audio recording with ID {} not found", recording_id)))?;
+
+    let mut file_deleted = false;
+    if delete_file {
+        match std::fs::remove_file(&recording.file_path) {
+            Ok(()) => file_deleted = true,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("Failed to delete audio file {}: {}", recording.file_path, e),
+        }
+        if let Some(secondary_file_path) = &recording.secondary_file_path {
+            match std::fs::remove_file(secondary_file_path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => eprintln!("Failed to delete audio file {}: {}", secondary_file_path, e),
+            }
+        }
+    }
+
+    Ok(CommandDeletedRecording {
+        recording: CommandAudioRecording::from(recording),
+        file_deleted,
+    })
+}
+
+// Command to rename a recording's file. The file stays in its directory and keeps its extension;
+// the system track of a split recording is renamed to match, as <new_name>_system.
+#[tauri::command]
+async fn rename_audio_recording(
+    state: State<'_, AppState>,
+    recording_id: String,
+    new_name: String,
+) -> Result<CommandAudioRecording, CommandError> {
+    let recording_uuid = parse_uuid(&recording_id, "recording_id", "recording ID")?;
+    if audio::is_recording_active(&recording_uuid.to_string()) {
+        return Err(CommandError::conflict("Stop the recording before renaming it"));
+    }
+
+    let pool = state.pool()?;
+    let recording = audio_handler::get_audio_recording(&pool, recording_uuid)
         .await
-        .map_err(|e| e.to_string())?;
-    let result: Vec<CommandAudioRecording> = recordings.into_iter().map(CommandAudioRecording::from).collect();
-    Ok(result)
+        .map_err(not_found_as(format!("Audio recording with ID {} not found", recording_id)))?;
+    let old_path = PathBuf::from(&recording.file_path);
+    let extension = old_path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    let new_path = old_path.with_file_name(recording_file_name(&new_name, extension)?);
+    if new_path == old_path {
+        return Ok(CommandAudioRecording::from(recording));
+    }
+
+    let mut renames = vec![(old_path, new_path.clone())];
+    if let Some(secondary_file_path) = &recording.secondary_file_path {
+        let old_secondary = PathBuf::from(secondary_file_path);
+        let stem = new_path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match old_secondary.extension() {
+            Some(ext) => format!("{}_system.{}", stem, ext.to_string_lossy()),
+            None => format!("{}_system", stem),
+        };
+        renames.push((old_secondary.clone(), old_secondary.with_file_name(name)));
+    }
+    if let Some((_, existing)) = renames.iter().find(|(_, new_path)| new_path.exists()) {
+        return Err(CommandError::conflict(format!("A file named {} already exists", existing.display())));
+    }
+
+    for (index, (from, to)) in renames.iter().enumerate() {
+        if let Err(e) = std::fs::rename(from, to) {
+            undo_renames(&renames[..index]);
+            return Err(CommandError::from(format!("Failed to rename audio file: {}", e)));
+        }
+    }
+    let new_path_str = new_path.to_string_lossy().to_string();
+    let new_secondary_str = renames.get(1).map(|(_, to)| to.to_string_lossy().to_string());
+    let result = audio_handler::update_audio_recording_file_paths(
+        &pool,
+        recording_uuid,
+        Some(&new_path_str),
+        new_secondary_str.as_deref(),
+    )
+    .await;
+
+    match result {
+        Ok(Some(updated)) => Ok(CommandAudioRecording::from(updated)),
+        result => {
+            // Put the files back so the row still points at them
+            undo_renames(&renames);
+            match result {
+                Ok(_) => Err(CommandError::not_found(format!("Audio recording with ID {} not found", recording_id))),
+                Err(e) => Err(CommandError::from(e)),
+            }
+        }
+    }
+}
+
+fn undo_renames(renames: &[(PathBuf, PathBuf)]) {
+    for (from, to) in renames {
+        if let Err(e) = std::fs::rename(to, from) {
+            eprintln!("Failed to move audio file {} back to {}: {}", to.display(), from.display(), e);
+        }
+    }
+}
+
+// File name for a renamed recording: new_name plus the recording's extension, unless new_name
+// already ends with it
+fn recording_file_name(new_name: &str, extension: &str) -> Result<String, CommandError> {
+    let name = new_name.trim();
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(CommandError::invalid_input("new_name", "Recording name cannot be empty"));
+    }
+    if name.chars().any(|c| matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control()) {
+        return Err(CommandError::invalid_input(
+            "new_name",
+            "Recording name cannot contain / \\ : * ? \" < > | or control characters",
+        ));
+    }
+
+    let has_extension = Path::new(name)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension));
+    if extension.is_empty() || has_extension {
+        Ok(name.to_string())
+    } else {
+        Ok(format!("{}.{}", name, extension))
+    }
 }
 
 // New get_audio_timestamps_for_recording function (replaces get_audio_block_references)
 #[tauri::command]
-async fn get_audio_timestamps_for_recording(state: State<'_, AppState>, recording_id: String) -> Result<Vec<CommandAudioTimestamp>, String> {
-    let recording_uuid = Uuid::parse_str(&recording_id).map_err(|e| format!("Invalid recording ID format: {}", e))?;
-    let timestamps = audio_handler::get_audio_timestamps_for_recording(&state.pool, recording_uuid)
-        .await
-        .map_err(|e| e.to_string())?;
+async fn get_audio_timestamps_for_recording(state: State<'_, AppState>, recording_id: String) -> Result<Vec<CommandAudioTimestamp>, CommandError> {
+    let recording_uuid = parse_uuid(&recording_id, "recording_id", "recording ID")?;
+    let timestamps = audio_handler::get_audio_timestamps_for_recording(&state.pool()?, recording_uuid)
+        .await?;
     let result: Vec<CommandAudioTimestamp> = timestamps.into_iter().map(CommandAudioTimestamp::from).collect();
     Ok(result)
 }
@@ -457,49 +3861,387 @@ async fn add_audio_timestamp(
     audio_recording_id: String,
     block_id: String,
     timestamp_ms: i32,
-) -> Result<CommandAudioTimestamp, String> {
-    let recording_uuid = Uuid::parse_str(&audio_recording_id).map_err(|e| format!("Invalid recording ID format: {}", e))?;
-    let block_uuid = Uuid::parse_str(&block_id).map_err(|e| format!("Invalid block ID format: {}", e))?;
+) -> Result<CommandAudioTimestamp, CommandError> {
+    let recording_uuid = parse_uuid(&audio_recording_id, "audio_recording_id", "recording ID")?;
+    let block_uuid = parse_uuid(&block_id, "block_id", "block ID")?;
+
+    if timestamp_ms < 0 {
+        return Err(CommandError::invalid_input("timestamp_ms", "timestamp_ms cannot be negative"));
+    }
+    // While recording with silence skipped, the recording's timeline runs ahead of the file
+    let timestamp_ms = match audio::map_recording_position(&recording_uuid.to_string(), timestamp_ms as u64) {
+        Some(position_ms) => position_ms as i32, // Never later than timestamp_ms
+        None => timestamp_ms,
+    };
 
-    let new_timestamp_id = audio_handler::add_audio_timestamp_to_block(
-        &state.pool,
+    let created_timestamp = audio_handler::add_audio_timestamp_to_block(
+        &state.pool()?,
         recording_uuid,
         block_uuid,
         timestamp_ms,
     )
-    .await
-    .map_err(|e| e.to_string())?;
+    .await?;
 
-    // To return the full CommandAudioTimestamp, we need to fetch it.
-    // Assuming add_audio_timestamp_to_block returns the ID of the new timestamp.
-    // A more direct way would be if add_audio_timestamp_to_block returned the created object.
-    // For now, let's try to find it among all timestamps for that recording.
-    // This is not ideal if there are many timestamps.
-    // A dedicated get_audio_timestamp(id) would be better.
-    // For the sake of this refactor, we'll fetch all for the recording and find by ID.
-    let timestamps_for_recording = audio_handler::get_audio_timestamps_for_recording(&state.pool, recording_uuid)
+    Ok(CommandAudioTimestamp::from(created_timestamp))
+}
+
+// Command to move an audio timestamp to a new position in its recording
+#[tauri::command]
+async fn update_audio_timestamp(
+    state: State<'_, AppState>,
+    id: String,
+    timestamp_ms: i32,
+) -> Result<CommandAudioTimestamp, CommandError> {
+    let timestamp_uuid = parse_uuid(&id, "id", "audio timestamp ID")?;
+    if timestamp_ms < 0 {
+        return Err(CommandError::invalid_input("timestamp_ms", "timestamp_ms cannot be negative"));
+    }
+
+    let updated_timestamp = audio_handler::update_audio_timestamp(&state.pool()?, timestamp_uuid, timestamp_ms)
+        .await?
+        .ok_or_else(|| CommandError::not_found(format!("Audio timestamp with ID {} not found", id)))?;
+
+    Ok(CommandAudioTimestamp::from(updated_timestamp))
+}
+
+// Command to delete an audio timestamp
+#[tauri::command]
+async fn delete_audio_timestamp(state: State<'_, AppState>, id: String) -> Result<bool, CommandError> {
+    let timestamp_uuid = parse_uuid(&id, "id", "audio timestamp ID")?;
+    audio_handler::delete_audio_timestamp(&state.pool()?, timestamp_uuid)
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(CommandError::from)
+}
 
-    let created_timestamp = timestamps_for_recording.into_iter().find(|ts| ts.id == new_timestamp_id)
-        .ok_or_else(|| format!("Failed to retrieve newly created audio timestamp with id {}", new_timestamp_id))?;
+// One timestamp for add_audio_timestamps_bulk
+#[derive(serde::Deserialize, Debug)]
+struct CommandTimestampEntry {
+    block_id: String,
+    timestamp_ms: i32,
+}
 
-    Ok(CommandAudioTimestamp::from(created_timestamp))
+// What add_audio_timestamps_bulk did with an entry. status is "added", with the new timestamp,
+// "duplicate", or "rejected", with the reason as error.
+#[derive(serde::Serialize, Debug)]
+struct CommandTimestampEntryResult {
+    block_id: String,
+    timestamp_ms: i32,
+    status: &'static str,
+    timestamp: Option<CommandAudioTimestamp>,
+    error: Option<String>,
 }
 
-// Command to get references to a specific block
+// Command to attach many timestamps to a recording at once, e.g. from an imported transcript.
+// Each entry is checked on its own and gets a result, in the order given.
 #[tauri::command]
-async fn get_references_for_block(state: State<'_, AppState>, block_id: String) -> Result<Vec<CommandBlockReference>, String> {
-    let block_uuid = Uuid::parse_str(&block_id).map_err(|e| format!("Invalid block ID format: {}", e))?;
+async fn add_audio_timestamps_bulk(
+    state: State<'_, AppState>,
+    recording_id: String,
+    entries: Vec<CommandTimestampEntry>,
+) -> Result<Vec<CommandTimestampEntryResult>, CommandError> {
+    let recording_uuid = parse_uuid(&recording_id, "recording_id", "recording ID")?;
 
-    let references = link_handler::get_block_references_to_block(&state.pool, block_uuid)
+    // Entries whose block ID doesn't parse are rejected here, the others checked by the insert
+    let parsed: Vec<Result<audio_handler::TimestampEntry, String>> = entries
+        .iter()
+        .map(|entry| {
+            Uuid::parse_str(&entry.block_id)
+                .map(|block_id| audio_handler::TimestampEntry {
+                    block_id,
+                    timestamp_ms: entry.timestamp_ms,
+                })
+                .map_err(|e| format!("Invalid block ID: {}", e))
+        })
+        .collect();
+    let valid: Vec<audio_handler::TimestampEntry> =
+        parsed.iter().filter_map(|entry| entry.as_ref().ok().copied()).collect();
+    let outcomes = audio_handler::add_audio_timestamps_bulk(&state.pool()?, recording_uuid, &valid)
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(not_found_as(format!("Audio recording with ID {} not found", recording_id)))?;
+    let outcomes = audio_handler::merge_timestamp_outcomes(parsed, outcomes)?;
+
+    let mut results = Vec::with_capacity(entries.len());
+    for (entry, outcome) in entries.into_iter().zip(outcomes) {
+        let (status, timestamp, error) = match outcome {
+            audio_handler::TimestampEntryOutcome::Added(timestamp) => {
+                ("added", Some(CommandAudioTimestamp::from(timestamp)), None)
+            }
+            audio_handler::TimestampEntryOutcome::Duplicate => ("duplicate", None, None),
+            audio_handler::TimestampEntryOutcome::Rejected(reason) => ("rejected", None, Some(reason)),
+        };
+        results.push(CommandTimestampEntryResult {
+            block_id: entry.block_id,
+            timestamp_ms: entry.timestamp_ms,
+            status,
+            timestamp,
+            error,
+        });
+    }
+    Ok(results)
+}
+
+// Command to move every timestamp of a recording at or after after_ms by delta_ms, e.g. after
+// trimming silence from its start. Positions that would fall before the start are set to 0 with
+// clamp_negative, and otherwise refuse the shift. Returns the moved timestamps.
+#[tauri::command]
+async fn shift_audio_timestamps(
+    state: State<'_, AppState>,
+    recording_id: String,
+    delta_ms: i32,
+    after_ms: i32,
+    clamp_negative: bool,
+) -> Result<Vec<CommandAudioTimestamp>, CommandError> {
+    let recording_uuid = parse_uuid(&recording_id, "recording_id", "recording ID")?;
+    if after_ms < 0 {
+        return Err(CommandError::invalid_input("after_ms", "after_ms cannot be negative"));
+    }
+
+    let timestamps =
+        audio_handler::shift_audio_timestamps(&state.pool()?, recording_uuid, delta_ms, after_ms, clamp_negative)
+            .await
+            .map_err(not_found_as(format!("Audio recording with ID {} not found", recording_id)))?;
+    Ok(timestamps.into_iter().map(CommandAudioTimestamp::from).collect())
+}
+
+// Command to get references to a specific block
+#[tauri::command]
+async fn get_references_for_block(state: State<'_, AppState>, block_id: String) -> Result<Vec<CommandBlockReference>, CommandError> {
+    let block_uuid = parse_uuid(&block_id, "block_id", "block ID")?;
+
+    let pool = state.pool()?;
+    let references = db::with_retry(|| link_handler::get_block_references_to_block(&pool, block_uuid)).await?;
 
     let command_references = references.into_iter().map(CommandBlockReference::from).collect();
     Ok(command_references)
 }
 
+// Command to resolve a (((block reference))) for rendering in place, following references
+// inside it up to `depth` levels (default 3, at most 10)
+#[tauri::command]
+async fn resolve_block_reference(
+    state: State<'_, AppState>,
+    block_id: String,
+    depth: Option<u32>,
+) -> Result<page_handler::ResolvedBlock, CommandError> {
+    let block_uuid = parse_uuid(&block_id, "block_id", "block ID")?;
+    let depth = depth.unwrap_or(page_handler::DEFAULT_REFERENCE_DEPTH);
+    if depth > page_handler::MAX_REFERENCE_DEPTH {
+        return Err(CommandError::invalid_input(
+            "depth",
+            format!("depth cannot be more than {}", page_handler::MAX_REFERENCE_DEPTH),
+        ));
+    }
+    match page_handler::resolve_block_reference(&state.pool()?, block_uuid, depth).await? {
+        page_handler::ResolvedReference::Block(block) => Ok(block),
+        _ => Err(CommandError::not_found(format!("Block with ID {} not found", block_id))),
+    }
+}
+
+// Command to point every (((reference))) to old_block_id at new_block_id instead, so a block
+// whose content moved elsewhere can be deleted without breaking them. Pages that fail to
+// update are reported without stopping the rest.
+#[tauri::command]
+async fn redirect_block_references(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    old_block_id: String,
+    new_block_id: String,
+) -> Result<CommandBlockReferenceRedirect, CommandError> {
+    let old_uuid = parse_uuid(&old_block_id, "old_block_id", "block ID")?;
+    let new_uuid = parse_uuid(&new_block_id, "new_block_id", "block ID")?;
+    if old_uuid == new_uuid {
+        return Err(CommandError::invalid_input("new_block_id", "new_block_id must differ from old_block_id"));
+    }
+
+    let redirect = page_handler::redirect_block_references(&state.pool()?, old_uuid, new_uuid)
+        .await
+        .map_err(not_found_as(format!("Block with ID {} not found", new_block_id)))?;
+    for page in &redirect.pages {
+        page_events::emit_page_changed(&app_handle, page.page_id, Some(page.update.updated_at), PageChangeKind::Edited);
+        if !page.update.link_targets_changed.is_empty() {
+            let mut page_ids = vec![page.page_id];
+            page_ids.extend(&page.update.link_targets_changed);
+            page_events::emit_links_changed(&app_handle, &page_ids);
+        }
+    }
+    Ok(CommandBlockReferenceRedirect {
+        pages_modified: redirect.pages.iter().map(|page| page.page_id.to_string()).collect(),
+        failures: redirect.failures,
+    })
+}
+
+// Command to list the block references made from a page, grouped by the block containing them
+#[tauri::command]
+async fn get_outgoing_references_for_page(
+    state: State<'_, AppState>,
+    page_id: String,
+) -> Result<Vec<CommandReferencingBlock>, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    let pool = state.pool()?;
+    let blocks = db::with_retry(|| link_handler::get_outgoing_references_for_page(&pool, page_uuid)).await?;
+    Ok(blocks.into_iter().map(CommandReferencingBlock::from).collect())
+}
+
+// Command to move a block under a new parent (or to the top level) at a given sibling position.
+// The node moves in the page's content too. Emits the same events as update_page_content.
+#[tauri::command]
+async fn move_block(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    block_id: String,
+    new_parent_id: Option<String>,
+    new_index: i32,
+) -> Result<CommandBlock, CommandError> {
+    let block_uuid = parse_uuid(&block_id, "block_id", "block ID")?;
+    let parent_uuid = new_parent_id
+        .map(|id| parse_uuid(&id, "new_parent_id", "parent block ID"))
+        .transpose()?;
+
+    let (block, update) = page_handler::move_block_node(&state.pool()?, block_uuid, parent_uuid, new_index)
+        .await
+        .map_err(not_found_as(format!("Block {} or its new parent is not in the page content", block_id)))?
+        .ok_or_else(|| CommandError::not_found(format!("Block with ID {} not found", block_id)))?;
+    emit_page_update_events(&app_handle, block.page_id, &update);
+    Ok(CommandBlock::from(block))
+}
+
+// Command to delete a block without resubmitting its page, by its ID alone. With recursive, a
+// list item's nested list is deleted with it; without, a block that has nested blocks is
+// refused rather than taking them along. The block's node leaves the page content, so a later
+// save of the page doesn't bring it back. Emits the same events as update_page_content.
+#[tauri::command]
+async fn delete_block(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    block_id: String,
+    recursive: Option<bool>,
+) -> Result<block_handler::DeletedBlocks, CommandError> {
+    let block_uuid = parse_uuid(&block_id, "block_id", "block ID")?;
+
+    let (deleted, update) = page_handler::delete_block_tree(&state.pool()?, block_uuid, recursive.unwrap_or(false))
+        .await
+        .map_err(not_found_as(format!("Block {} is not in its page's content", block_id)))?
+        .ok_or_else(|| CommandError::not_found(format!("Block with ID {} not found", block_id)))?;
+    emit_page_update_events(&app_handle, deleted.page_id, &update);
+    Ok(deleted)
+}
+
+// Command to list the blocks edited since a moment (by default the start of today, local time),
+// grouped by page, for a "Today's edits" view
+#[tauri::command]
+async fn get_recently_edited_blocks(
+    state: State<'_, AppState>,
+    since: Option<String>,
+    limit: Option<i64>,
+) -> Result<Vec<block_handler::PageEdits>, CommandError> {
+    let since = match since {
+        Some(value) => chrono::DateTime::parse_from_rfc3339(&value)
+            .map(|dt| dt.with_timezone(&chrono::Utc))
+            .map_err(|e| CommandError::invalid_input("since", format!("Invalid timestamp: {}", e)))?,
+        None => chrono::Local::now()
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .and_then(|midnight| midnight.and_local_timezone(chrono::Local).earliest())
+            .map(|midnight| midnight.with_timezone(&chrono::Utc))
+            .ok_or_else(|| "Failed to work out the start of today".to_string())?,
+    };
+    let (limit, _) = resolve_pagination(limit, None)?;
+    let pool = state.pool()?;
+    Ok(db::with_retry(|| block_handler::get_recently_edited_blocks(&pool, since, limit)).await?)
+}
+
+// Command to list checklist items and todo blocks across the vault, e.g. the open ones or
+// those completed this week
+#[tauri::command]
+async fn list_tasks(
+    state: State<'_, AppState>,
+    filter: Option<block_handler::TaskFilter>,
+) -> Result<Vec<block_handler::Task>, CommandError> {
+    let filter = filter.unwrap_or_default();
+    if filter.open_only && filter.completed_this_week {
+        return Err(CommandError::invalid_input(
+            "filter",
+            "open_only and completed_this_week can't be combined",
+        ));
+    }
+    if filter.created_within_days.is_some_and(|days| days < 0) {
+        return Err(CommandError::invalid_input("filter", "created_within_days cannot be negative"));
+    }
+    if filter.limit.is_some_and(|limit| limit <= 0) {
+        return Err(CommandError::invalid_input("filter", "limit must be positive"));
+    }
+    let pool = state.pool()?;
+    Ok(db::with_retry(|| block_handler::list_tasks(&pool, &filter)).await?)
+}
+
+// Command to check or uncheck a task. The page's content and Markdown are updated too, so the
+// editor shows the change; the page's new updated_at is returned for the next save.
+#[tauri::command]
+async fn set_task_state(state: State<'_, AppState>, block_id: String, done: bool) -> Result<String, CommandError> {
+    let block_uuid = parse_uuid(&block_id, "block_id", "block ID")?;
+    let updated_at = page_handler::set_task_state(&state.pool()?, block_uuid, done)
+        .await
+        .map_err(not_found_as(format!("Block with ID {} not found", block_id)))?;
+    Ok(updated_at.to_rfc3339())
+}
+
+// Command to get the pages and links for the graph view. With a channel_id, a graph too big to
+// return in one message is sent as transfer chunks instead.
+#[tauri::command]
+async fn get_graph_data(
+    app_handle: AppHandle,
+    state: State<'_, AppState>,
+    options: Option<link_handler::GraphOptions>,
+    channel_id: Option<String>,
+) -> Result<transfer::Transferred<CommandGraphData>, CommandError> {
+    let options = options.unwrap_or_default();
+    let pool = state.pool()?;
+    let graph = db::with_retry(|| link_handler::get_graph_data(&pool, &options)).await?;
+    Ok(transfer::send_or_return(&app_handle, channel_id.as_deref(), CommandGraphData::from(graph)).await?)
+}
+
+// Command to create a block and, if the page is being recorded, stamp it with the current
+// recording position. The position is read here rather than by the frontend to avoid drift.
+#[tauri::command]
+async fn create_block_with_timestamp(
+    state: State<'_, AppState>,
+    page_id: String,
+    parent_block_id: Option<String>,
+    block_type: Option<String>,
+) -> Result<CommandBlockWithTimestamp, CommandError> {
+    let page_uuid = parse_uuid(&page_id, "page_id", "page ID")?;
+    let parent_uuid = parent_block_id
+        .map(|id| parse_uuid(&id, "parent_block_id", "parent block ID"))
+        .transpose()?;
+
+    let active_recording = audio::find_active_recording_for_page(page_uuid);
+    let recording_position = match &active_recording {
+        Some(active) => Some(audio_handler::RecordingPosition {
+            recording_id: active.recording_id,
+            page_id: active.page_id,
+            file_path: &active.file_path,
+            secondary_file_path: active.secondary_file_path.as_deref(),
+            track_mode: active.track_mode.as_str(),
+            mime_type: active.mime_type,
+            timestamp_ms: i32::try_from(active.elapsed_ms)
+                .map_err(|_| CommandError::audio_device("Recording is too long to timestamp"))?,
+        }),
+        None => None,
+    };
+
+    let (block, timestamp) = audio_handler::create_block_with_timestamp(
+        &state.pool()?,
+        page_uuid,
+        parent_uuid,
+        block_type.as_deref(),
+        recording_position,
+    )
+    .await?;
+    Ok(CommandBlockWithTimestamp {
+        block: CommandBlock::from(block),
+        timestamp: timestamp.map(CommandAudioTimestamp::from),
+    })
+}
 
 #[tokio::main]
 async fn main() {
@@ -517,29 +4259,270 @@ async fn main() {
                 }
             }
         });
+        tauri::async_runtime::spawn(watch_active_recordings(app.app_handle().clone()));
+        tauri::async_runtime::spawn(schedule_maintenance(app.app_handle().clone()));
         Ok(())
         })
         .invoke_handler(tauri::generate_handler![
+            get_db_status,
+            set_database_url,
+            list_vaults,
+            get_active_vault,
+            add_vault,
+            switch_vault,
+            get_schema_version,
+            get_app_status,
             get_notes_directory,
             set_notes_directory,
+            sync_notes_directory,
+            start_notes_watcher,
+            stop_notes_watcher,
+            is_notes_watcher_running,
             get_audio_directory,
             set_audio_directory,
             get_all_notes,
+            count_notes,
             search_notes,
+            save_query,
+            update_saved_query,
+            list_saved_queries,
+            run_saved_query,
+            delete_saved_query,
             get_page_details,
             update_page_content,
+            update_block_content,
+            insert_block_after,
+            delete_block_from_page,
+            rename_page,
             create_note,
+            duplicate_page,
+            extract_block_to_page,
+            merge_pages,
+            list_renamed_duplicate_pages,
+            list_favorites,
+            set_favorite,
+            reorder_favorites,
+            add_tag_to_page,
+            remove_tag_from_page,
+            add_page_alias,
+            remove_page_alias,
+            list_page_aliases,
+            list_tags,
+            list_pages_with_tag,
+            rename_tag,
             create_daily_note,
+            get_or_create_daily_note,
+            get_adjacent_daily_note,
+            list_daily_notes,
+            append_to_daily_note,
+            list_templates,
+            set_page_is_template,
+            create_page_from_template,
+            set_daily_note_template,
+            set_create_stub_pages,
+            import_vault,
+            import_markdown_file,
+            import_roam_json,
+            start_job,
+            cancel_job,
+            get_job_status,
+            list_jobs,
+            normalize_page,
+            normalize_all_pages,
+            verify_page_integrity,
+            verify_all_pages,
+            create_backup,
+            restore_backup,
             delete_note,
+            trash_page,
+            list_trashed_pages,
+            restore_page,
+            purge_page,
+            empty_trash,
+            bulk_delete_pages,
+            bulk_update_pages,
+            bulk_export_pages,
+            export_page_html,
             find_backlinks,
+            find_outgoing_links,
+            list_page_revisions,
+            get_page_revision,
+            restore_page_revision,
+            diff_page_revisions,
             start_recording,
+            get_vault_lock_status,
+            set_vault_passphrase,
+            unlock_vault,
+            lock_vault,
+            encrypt_existing_audio,
             stop_recording,
+            stop_all_recordings,
+            recover_recordings,
+            list_audio_devices,
+            get_recording_info,
+            list_active_recordings,
+            get_recording_diagnostics,
+            set_recording_gain,
+            get_recording_elapsed_ms,
+            is_recording_active,
             get_audio_recordings,
+            get_audio_data,
+            get_audio_file_url,
+            export_audio_clip,
+            export_clip_around_timestamp,
+            import_transcript,
+            get_transcript,
+            search_transcripts,
+            get_block_audio_timestamps,
+            get_audio_timestamps_for_page,
+            list_unassigned_recordings,
+            list_all_recordings,
+            update_recording_metadata,
+            set_recording_page,
+            delete_audio_recording,
+            rename_audio_recording,
             get_audio_timestamps_for_recording, // Renamed
             add_audio_timestamp, // Renamed
-            get_references_for_block
+            update_audio_timestamp,
+            delete_audio_timestamp,
+            add_audio_timestamps_bulk,
+            shift_audio_timestamps,
+            get_references_for_block,
+            get_outgoing_references_for_page,
+            resolve_block_reference,
+            redirect_block_references,
+            move_block,
+            delete_block,
+            get_recently_edited_blocks,
+            list_tasks,
+            set_task_state,
+            create_block_with_timestamp,
+            get_graph_data,
+            search_blocks,
+            suggest_page_titles,
+            suggest_blocks,
+            get_page_with_references,
+            get_reference_summary,
+            get_reference_summaries,
+            get_page_stats,
+            get_vault_stats,
+            get_vault_health,
+            run_maintenance_now,
+            get_last_maintenance_report
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app_handle, event| match event {
+            // Keep the window open while recordings are saved, so the frontend can show it
+            tauri::RunEvent::WindowEvent { event: tauri::WindowEvent::CloseRequested { api, .. }, .. }
+                if finish_recordings_before_exit(app_handle) =>
+            {
+                api.prevent_close();
+            }
+            tauri::RunEvent::ExitRequested { api, .. } if finish_recordings_before_exit(app_handle) => {
+                api.prevent_exit();
+            }
+            // Stop the notes watcher thread before the process exits
+            tauri::RunEvent::Exit => {
+                if let Some(state) = app_handle.try_state::<AppState>() {
+                    if let Ok(mut watcher) = state.notes_watcher.lock() {
+                        if let Some(watcher) = watcher.take() {
+                            watcher.stop();
+                        }
+                    }
+                }
+            }
+            _ => {}
+        });
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn test_state(notes_dir: PathBuf, audio_dir: PathBuf) -> AppState {
+        AppState {
+            pool: RwLock::new(None),
+            db_error: Mutex::new(None),
+            settings: Mutex::new(settings::Settings::default()),
+            app_data_dir: std::env::temp_dir(),
+            notes_dir: tokio::sync::RwLock::new(notes_dir),
+            audio_dir: tokio::sync::RwLock::new(audio_dir),
+            notes_watcher: Mutex::new(None),
+            vault_key: RwLock::new(None),
+            vault_switch: tokio::sync::Mutex::new(()),
+            jobs: jobs::JobRegistry::default(),
+        }
+    }
+
+    fn temp_dirs(count: usize) -> Vec<PathBuf> {
+        (0..count)
+            .map(|_| {
+                let dir = std::env::temp_dir().join(format!("gita-dir-test-{}", Uuid::new_v4()));
+                std::fs::create_dir(&dir).unwrap();
+                dir
+            })
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_reads_see_whole_directories_while_they_change() {
+        let dirs = temp_dirs(4);
+        let names: Vec<String> = dirs.iter().map(|dir| dir.to_str().unwrap().to_string()).collect();
+        let state = Arc::new(test_state(dirs[0].clone(), dirs[0].clone()));
+
+        let mut tasks = Vec::new();
+        for writer in 0..4 {
+            let (state, names) = (state.clone(), names.clone());
+            tasks.push(tokio::spawn(async move {
+                for round in 0..50 {
+                    let name = names[(writer + round) % names.len()].clone();
+                    let path = check_directory(name).unwrap();
+                    // Set as set_notes_directory does: the lock stays held while the watcher moves
+                    let mut notes_dir = state.notes_dir.write().await;
+                    *notes_dir = path.clone();
+                    tokio::task::yield_now().await;
+                    drop(notes_dir);
+                    *state.audio_dir.write().await = path;
+                }
+            }));
+        }
+        for _ in 0..4 {
+            let (state, names) = (state.clone(), names.clone());
+            tasks.push(tokio::spawn(async move {
+                for _ in 0..200 {
+                    assert!(names.contains(&state.notes_directory().await.unwrap()));
+                    assert!(names.contains(&state.audio_directory().await.unwrap()));
+                    tokio::task::yield_now().await;
+                }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        // Once the writers are done, a read sees the last write
+        *state.notes_dir.write().await = check_directory(names[1].clone()).unwrap();
+        *state.audio_dir.write().await = check_directory(names[2].clone()).unwrap();
+        assert_eq!(state.notes_directory().await.unwrap(), names[1]);
+        assert_eq!(state.audio_directory().await.unwrap(), names[2]);
+        for dir in dirs {
+            std::fs::remove_dir(dir).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn a_missing_directory_is_rejected_and_leaves_the_directory_unchanged() {
+        let dirs = temp_dirs(1);
+        let state = test_state(dirs[0].clone(), dirs[0].clone());
+        let missing = dirs[0].join("missing").to_str().unwrap().to_string();
+
+        let error = check_directory(missing).unwrap_err();
+        assert!(matches!(&error, CommandError::InvalidInput { field, .. } if field == "path"), "{:?}", error);
+        assert_eq!(state.notes_directory().await.unwrap(), dirs[0].to_str().unwrap());
+        assert_eq!(state.audio_directory().await.unwrap(), dirs[0].to_str().unwrap());
+        std::fs::remove_dir(&dirs[0]).unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+// Database maintenance: prunes old page revisions, removes rows left pointing at blocks, pages
+// or recordings that no longer exist, and refreshes the planner statistics of the tables every
+// save writes. Runs on a schedule (settings.maintenance) and on demand. A Postgres advisory lock
+// keeps two app instances sharing a database from running it at the same time.
+
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgConnection, PgPool};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::dal_error::DalError;
+use crate::revision_handler;
+
+// Key of the session-level advisory lock held for the whole run
+const MAINTENANCE_LOCK_KEY: i64 = 0x6769_7461_6d61_696e; // "gitamain"
+
+// How often the scheduler checks whether a run is due
+pub const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+// Runs kept in maintenance_runs, newest first
+const MAX_STORED_RUNS: i64 = 30;
+
+// Tables written on every save, whose statistics go stale first
+const ANALYZED_TABLES: &[&str] = &[
+    "pages",
+    "blocks",
+    "page_links",
+    "page_link_blocks",
+    "block_references",
+    "audio_timestamps",
+    "page_revisions",
+];
+
+// Rows cleaned by one run, per step
+#[derive(Debug, Clone, sqlx::FromRow, serde::Serialize)]
+pub struct MaintenanceReport {
+    pub id: Uuid,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    pub scheduled: bool, // False when run by hand
+    pub revisions_pruned: i64,
+    pub block_references_removed: i64,
+    pub page_links_removed: i64,
+    pub audio_timestamps_removed: i64,
+    pub tables_analyzed: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MaintenanceOptions {
+    pub revision_retention_days: u32, // Revisions older than this are pruned; 0 keeps them
+    pub active_recording_ids: Vec<Uuid>, // Recordings still running, whose rows are left alone
+    pub scheduled: bool,
+}
+
+// Runs every step and stores the report. Returns None, having done nothing, when another
+// connection (another app instance, or a run already going here) holds the maintenance lock.
+pub async fn run_maintenance(
+    pool: &PgPool,
+    options: &MaintenanceOptions,
+) -> Result<Option<MaintenanceReport>, DalError> {
+    // A connection of its own, without the statement timeout, as for migrations. It is closed
+    // afterwards rather than returned to the pool, which also releases the lock however the run
+    // ended.
+    let mut conn = pool.acquire().await?;
+    conn.execute("SET statement_timeout = 0").await?;
+    let locked = sqlx::query_scalar!(r#"SELECT pg_try_advisory_lock($1) AS "locked!""#, MAINTENANCE_LOCK_KEY)
+        .fetch_one(&mut *conn)
+        .await?;
+    let result = if locked {
+        run_steps(&mut conn, options).await.map(Some)
+    } else {
+        Ok(None)
+    };
+    drop(conn.detach());
+    result
+}
+
+async fn run_steps(conn: &mut PgConnection, options: &MaintenanceOptions) -> Result<MaintenanceReport, DalError> {
+    let started_at = Utc::now();
+
+    // Revisions beyond the retention period, and beyond the per-page limit in case it was
+    // lowered since they were saved
+    let revisions_pruned = sqlx::query!(
+        r#"
+        DELETE FROM page_revisions
+        WHERE ($1::int4 > 0 AND created_at < now() - make_interval(days => $1::int4))
+           OR id IN (
+               SELECT id
+               FROM (
+                   SELECT id, row_number() OVER (PARTITION BY page_id ORDER BY created_at DESC, id) AS rank
+                   FROM page_revisions
+               ) ranked
+               WHERE rank > $2
+           )
+        "#,
+        options.revision_retention_days.min(i32::MAX as u32) as i32,
+        revision_handler::MAX_REVISIONS_PER_PAGE
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    // References from or to blocks that were deleted outside a page save. A page save drops
+    // those from its own blocks; references to another page's deleted blocks stay until then.
+    let block_references_removed = sqlx::query!(
+        r#"
+        DELETE FROM block_references br
+        WHERE NOT EXISTS (SELECT 1 FROM blocks b WHERE b.id = br.referencing_block_id)
+           OR NOT EXISTS (SELECT 1 FROM blocks b WHERE b.id = br.referenced_block_id)
+        "#
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    // Links whose source or target page is gone. Purging a page cascades to its links, so this
+    // only finds rows written while the foreign keys weren't enforced. Links to pages in the
+    // trash are kept, so restoring a page brings its backlinks back.
+    let page_links_removed = sqlx::query!(
+        r#"
+        DELETE FROM page_links l
+        WHERE NOT EXISTS (SELECT 1 FROM pages p WHERE p.id = l.source_page_id)
+           OR NOT EXISTS (SELECT 1 FROM pages p WHERE p.id = l.target_page_id)
+        "#
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    // Timestamps whose recording has no row, which like the links above the foreign key
+    // normally prevents. The rows of recordings still running are never touched.
+    let audio_timestamps_removed = sqlx::query!(
+        r#"
+        DELETE FROM audio_timestamps t
+        WHERE NOT (t.audio_recording_id = ANY($1))
+          AND NOT EXISTS (SELECT 1 FROM audio_recordings r WHERE r.id = t.audio_recording_id)
+        "#,
+        &options.active_recording_ids
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    let mut tables_analyzed = Vec::with_capacity(ANALYZED_TABLES.len());
+    for table in ANALYZED_TABLES {
+        conn.execute(format!("ANALYZE {}", table).as_str()).await?;
+        tables_analyzed.push(table.to_string());
+    }
+
+    let report = sqlx::query_as!(
+        MaintenanceReport,
+        r#"
+        INSERT INTO maintenance_runs
+            (id, started_at, scheduled, revisions_pruned, block_references_removed, page_links_removed,
+             audio_timestamps_removed, tables_analyzed)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING id, started_at, finished_at, scheduled, revisions_pruned, block_references_removed,
+                  page_links_removed, audio_timestamps_removed, tables_analyzed
+        "#,
+        Uuid::new_v4(),
+        started_at,
+        options.scheduled,
+        revisions_pruned as i64,
+        block_references_removed as i64,
+        page_links_removed as i64,
+        audio_timestamps_removed as i64,
+        &tables_analyzed
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM maintenance_runs
+        WHERE id NOT IN (
+            SELECT id FROM maintenance_runs
+            ORDER BY finished_at DESC, id
+            LIMIT $1
+        )
+        "#,
+        MAX_STORED_RUNS
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    println!(
+        "[Maintenance] Pruned {} revisions; removed {} block references, {} page links and {} audio timestamps",
+        report.revisions_pruned,
+        report.block_references_removed,
+        report.page_links_removed,
+        report.audio_timestamps_removed
+    );
+    Ok(report)
+}
+
+// The newest run by any app instance sharing the database, or None if none has run yet
+pub async fn get_last_report(pool: &PgPool) -> Result<Option<MaintenanceReport>, DalError> {
+    let report = sqlx::query_as!(
+        MaintenanceReport,
+        r#"
+        SELECT id, started_at, finished_at, scheduled, revisions_pruned, block_references_removed,
+               page_links_removed, audio_timestamps_removed, tables_analyzed
+        FROM maintenance_runs
+        ORDER BY finished_at DESC, id
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(report)
+}
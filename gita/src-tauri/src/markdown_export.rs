@@ -0,0 +1,347 @@
+// Renders content_json as Markdown, so raw_markdown keeps following the content when a save
+// doesn't send it, and for pages created here. Close to what the editor writes: top-level blocks
+// separated by blank lines, nested list items indented to their parent's text (two spaces under
+// a bullet or task) and tasks as `- [ ]` / `- [x]`. `[[links]]` and `(((block references)))`
+// are text in the content and are written as they are; block reference nodes are written the
+// same way. markdown_import reads the result back into the same blocks.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::json_utils;
+
+lazy_static! {
+    // The start of a line that Markdown would read as a block marker rather than text
+    static ref BLOCK_MARKER_REGEX: Regex =
+        Regex::new(r"^(?:#{1,6}(?:\s|$)|[-*+](?:\s|$)|\d+[.)](?:\s|$)|>|```|~~~|(?:[-*_]\s*){3,}$)").unwrap();
+    // Spans written as they are, since escaping inside them would change a title or ID
+    static ref PROTECTED_SPAN_REGEX: Regex = Regex::new(r"\[\[.*?\]\]|\(\(\(.*?\)\)\)").unwrap();
+}
+
+// The editor's text format bits that Markdown can express, outermost first
+const TEXT_MARKS: [(u64, &str); 3] = [(1, "**"), (2, "*"), (4, "~~")];
+const CODE_FORMAT: u64 = 16;
+
+// Markdown for a content_json tree, without a trailing newline
+pub fn render_markdown(content: &Value) -> String {
+    children(json_utils::content_root(content))
+        .iter()
+        .map(|node| render_block_lines(node).join("\n"))
+        .filter(|block| !block.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+// Lines of one top-level block. A list item on its own renders as a one-item list.
+pub fn render_block_lines(node: &Value) -> Vec<String> {
+    block_lines(node, 0)
+}
+
+fn block_lines(node: &Value, indent: usize) -> Vec<String> {
+    match json_utils::node_type(node).unwrap_or_default() {
+        "heading" => {
+            let level = node
+                .get("tag")
+                .and_then(|v| v.as_str())
+                .and_then(|tag| tag.strip_prefix('h'))
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(1);
+            // A heading is one line
+            let text = render_inline(children(node)).replace('\n', " ");
+            vec![format!("{} {}", "#".repeat(level), text.trim())]
+        }
+        "quote" => inline_lines(children(node))
+            .into_iter()
+            .map(|line| if line.is_empty() { ">".to_string() } else { format!("> {}", line) })
+            .collect(),
+        "code" => {
+            let language = node.get("language").and_then(|v| v.as_str()).unwrap_or_default();
+            let code = code_text(children(node));
+            // A fence longer than any run of backticks in the code
+            let longest_run = code.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+            let fence = "`".repeat(longest_run.max(2) + 1);
+            let mut lines = vec![format!("{}{}", fence, language)];
+            lines.extend(code.split('\n').map(String::from));
+            lines.push(fence);
+            lines
+        }
+        "horizontalrule" => vec!["***".to_string()],
+        "list" => list_lines(node, indent),
+        "listitem" => {
+            let list_type = if node.get("checked").is_some() { "check" } else { "bullet" };
+            list_lines(&serde_json::json!({ "type": "list", "listType": list_type, "children": [node] }), indent)
+        }
+        "table" => table_lines(node),
+        _ => inline_lines(children(node)),
+    }
+}
+
+fn list_lines(list: &Value, indent: usize) -> Vec<String> {
+    let list_type = list.get("listType").and_then(|v| v.as_str()).unwrap_or("bullet");
+    let mut number = list.get("start").and_then(|v| v.as_u64()).unwrap_or(1);
+    let mut lines = Vec::new();
+    // Nested items are indented to the text of the item they belong to
+    let mut text_indent = indent + 2;
+    for item in children(list) {
+        let (nested, inline): (Vec<&Value>, Vec<&Value>) =
+            children(item).iter().partition(|child| json_utils::node_type(child) == Some("list"));
+        if !inline.is_empty() || !json_utils::is_nested_list_holder(item) {
+            let marker = match list_type {
+                "number" => format!("{}. ", number),
+                "check" if item.get("checked").and_then(|v| v.as_bool()) == Some(true) => "- [x] ".to_string(),
+                "check" => "- [ ] ".to_string(),
+                _ => "- ".to_string(),
+            };
+            number += 1;
+            // A task's text starts after the checkbox, but its nested items only need to be
+            // indented past the `- `
+            let marker_width = if list_type == "number" { marker.len() } else { 2 };
+            text_indent = indent + marker_width;
+
+            let inline: Vec<Value> = inline.into_iter().cloned().collect();
+            for (index, line) in inline_lines(&inline).into_iter().enumerate() {
+                if index == 0 {
+                    lines.push(format!("{}{}{}", " ".repeat(indent), marker, line).trim_end().to_string());
+                } else {
+                    lines.push(format!("{}{}", " ".repeat(text_indent), line).trim_end().to_string());
+                }
+            }
+        }
+        for nested_list in nested {
+            lines.extend(list_lines(nested_list, text_indent));
+        }
+    }
+    lines
+}
+
+// A GitHub-style table, the first row as its header
+fn table_lines(table: &Value) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (index, row) in children(table).iter().enumerate() {
+        let cells: Vec<String> = children(row)
+            .iter()
+            .map(|cell| {
+                let text: Vec<String> = children(cell).iter().map(|block| render_inline(children(block))).collect();
+                text.join(" ").replace('\n', " ").replace('|', "\\|")
+            })
+            .collect();
+        lines.push(format!("| {} |", cells.join(" | ")));
+        if index == 0 {
+            lines.push(format!("|{}", " --- |".repeat(cells.len())));
+        }
+    }
+    lines
+}
+
+// Lines of a block's inline nodes, each escaped where it would read as a block marker
+fn inline_lines(nodes: &[Value]) -> Vec<String> {
+    render_inline(nodes).split('\n').map(escape_line_start).collect()
+}
+
+// Text of inline nodes with Markdown for bold, italic, strikethrough, inline code and links.
+// Line breaks come out as newlines.
+fn render_inline(nodes: &[Value]) -> String {
+    let mut text = String::new();
+    for node in nodes {
+        match json_utils::node_type(node).unwrap_or_default() {
+            "text" => {
+                let content = node.get("text").and_then(|v| v.as_str()).unwrap_or_default();
+                let format = node.get("format").and_then(|v| v.as_u64()).unwrap_or(0);
+                text.push_str(&marked_text(content, format));
+            }
+            "code-highlight" => text.push_str(node.get("text").and_then(|v| v.as_str()).unwrap_or_default()),
+            "linebreak" => text.push('\n'),
+            "tab" => text.push('\t'),
+            "link" | "autolink" => {
+                let url = node.get("url").and_then(|v| v.as_str()).unwrap_or_default();
+                text.push_str(&format!("[{}]({})", render_inline(children(node)), url));
+            }
+            "block-reference" => {
+                let block_id = node.get("blockId").and_then(|v| v.as_str()).unwrap_or_default();
+                text.push_str(&format!("((({})))", block_id));
+            }
+            "list" => {} // Rendered as its own lines
+            _ => text.push_str(&render_inline(children(node))),
+        }
+    }
+    text
+}
+
+// Text in its marks. Whitespace at either end goes outside them, where Markdown expects it,
+// and inline code goes innermost, since nothing inside backticks is formatted.
+fn marked_text(content: &str, format: u64) -> String {
+    let trimmed = content.trim();
+    if trimmed.is_empty() {
+        return content.to_string();
+    }
+    let leading = &content[..content.len() - content.trim_start().len()];
+    let trailing = &content[content.trim_end().len()..];
+
+    let mut inner = if format & CODE_FORMAT != 0 {
+        let ticks = if trimmed.contains('`') { "``" } else { "`" };
+        let pad = if trimmed.starts_with('`') || trimmed.ends_with('`') { " " } else { "" };
+        format!("{}{}{}{}{}", ticks, pad, trimmed, pad, ticks)
+    } else {
+        escape_text(trimmed)
+    };
+    for (bit, mark) in TEXT_MARKS.iter().rev() {
+        if format & bit != 0 {
+            inner = format!("{}{}{}", mark, inner, mark);
+        }
+    }
+    format!("{}{}{}", leading, inner, trailing)
+}
+
+// Escapes the characters that would otherwise start formatting, outside links and references
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut last = 0;
+    for span in PROTECTED_SPAN_REGEX.find_iter(text) {
+        escaped.push_str(&escape_plain(&text[last..span.start()]));
+        escaped.push_str(span.as_str());
+        last = span.end();
+    }
+    escaped.push_str(&escape_plain(&text[last..]));
+    escaped
+}
+
+fn escape_plain(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut escaped = String::with_capacity(text.len());
+    for (index, &c) in chars.iter().enumerate() {
+        let escape = match c {
+            '\\' | '*' | '`' => true,
+            '~' => chars.get(index + 1) == Some(&'~') || index > 0 && chars[index - 1] == '~',
+            // An underscore only starts or ends emphasis next to a non-word character
+            '_' => {
+                let word = |c: Option<&char>| c.is_some_and(|c| c.is_alphanumeric());
+                !(word(index.checked_sub(1).and_then(|i| chars.get(i))) && word(chars.get(index + 1)))
+            }
+            _ => false,
+        };
+        if escape {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn escape_line_start(line: &str) -> String {
+    let trimmed = line.trim_start();
+    if !BLOCK_MARKER_REGEX.is_match(trimmed) {
+        return line.to_string();
+    }
+    let indent = &line[..line.len() - trimmed.len()];
+    // An ordered list marker is escaped at its `.` or `)`, anything else at its first character
+    match trimmed.find(|c: char| !c.is_ascii_digit()) {
+        Some(end) if end > 0 => format!("{}{}\\{}", indent, &trimmed[..end], &trimmed[end..]),
+        _ => format!("{}\\{}", indent, trimmed),
+    }
+}
+
+// The code block's text, without formatting
+fn code_text(nodes: &[Value]) -> String {
+    let mut text = String::new();
+    for node in nodes {
+        match json_utils::node_type(node).unwrap_or_default() {
+            "linebreak" => text.push('\n'),
+            "tab" => text.push('\t'),
+            _ => match node.get("text").and_then(|v| v.as_str()) {
+                Some(content) => text.push_str(content),
+                None => text.push_str(&code_text(children(node))),
+            },
+        }
+    }
+    text
+}
+
+fn children(node: &Value) -> &[Value] {
+    node.get("children").and_then(|v| v.as_array()).map(Vec::as_slice).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown_import::markdown_to_content;
+
+    fn without_unique_ids(mut node: Value) -> Value {
+        if let Some(object) = node.as_object_mut() {
+            object.remove("uniqueID");
+            for value in object.values_mut() {
+                *value = without_unique_ids(value.take());
+            }
+        } else if let Some(array) = node.as_array_mut() {
+            for value in array.iter_mut() {
+                *value = without_unique_ids(value.take());
+            }
+        }
+        node
+    }
+
+    // Exports the content, imports the Markdown again and checks the same blocks come back
+    fn assert_round_trips(content: &Value) {
+        let markdown = render_markdown(content);
+        let imported = markdown_to_content(&markdown);
+        assert_eq!(without_unique_ids(imported), without_unique_ids(content.clone()), "through:\n{}", markdown);
+    }
+
+    #[test]
+    fn imported_markdown_round_trips() {
+        let markdown = "\
+# Heading with *italic*
+
+Text with **bold**, *italic*, ~~struck~~, `code` and ***both***.
+A second line with a [link](https://example.com) and [[Wiki Page]].
+
+> A quote
+> over two lines
+
+````rust
+let fence = \"```\";
+````
+
+- Item
+  - Nested **bold** item
+    1. Numbered
+    2. Numbered again
+       - [ ] Open task
+       - [x] Done task
+- Last item";
+        let content = markdown_to_content(markdown);
+        assert_eq!(render_markdown(&content), markdown);
+        assert_round_trips(&content);
+    }
+
+    #[test]
+    fn text_that_looks_like_markdown_round_trips() {
+        let lines = [
+            "# not a heading",
+            "- not a list",
+            "12. not numbered",
+            "> not a quote",
+            "*stars* and _underscores_ but snake_case",
+            "~~tildes~~ and a \\ backslash",
+            "[[Links * stay]] as they are",
+            "---",
+        ];
+        let mut content = markdown_to_content(&lines.map(|_| "x").join("\n\n"));
+        for (index, line) in lines.iter().enumerate() {
+            content["root"]["children"][index]["children"][0]["text"] = Value::from(*line);
+        }
+        assert_round_trips(&content);
+    }
+
+    #[test]
+    fn formats_round_trip_with_surrounding_spaces() {
+        let mut content = markdown_to_content("x");
+        content["root"]["children"][0]["children"] = serde_json::json!([
+            crate::page_handler::text_node("plain ", 0),
+            crate::page_handler::text_node("bold and italic", 3),
+            crate::page_handler::text_node(" then ", 0),
+            crate::page_handler::text_node("struck code", 4 | CODE_FORMAT),
+        ]);
+        assert_round_trips(&content);
+    }
+}
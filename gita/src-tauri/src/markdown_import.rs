@@ -0,0 +1,498 @@
+// Converts Markdown into the editor's content_json, so pages created from Markdown (new notes
+// and imported files) open with their blocks rather than empty. Headings, paragraphs, quotes,
+// fenced code and (task) lists become blocks with uniqueIDs; bold, italic, strikethrough and
+// inline code become text formats and links become link nodes. `[[wiki links]]` stay text,
+// which is how the editor stores them and where the link extractor finds them.
+//
+// The editor can't hold blocks inside list items or quotes, so what is nested there (a second
+// paragraph, a code block, a heading) is kept as lines of the item or quote instead.
+
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, LinkType, Options, Parser, Tag, TagEnd};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::json_utils::{self, list_node};
+use crate::page_handler::{self, text_node};
+
+// The editor's text format bits
+const BOLD_FORMAT: u64 = 1;
+const ITALIC_FORMAT: u64 = 2;
+const STRIKETHROUGH_FORMAT: u64 = 4;
+const CODE_FORMAT: u64 = 16;
+
+enum BlockKind {
+    Paragraph,
+    Heading(HeadingLevel),
+    Quote,
+}
+
+enum LinkKind {
+    Url(String),
+    Wiki { target: String, has_label: bool },
+    Image(String),
+}
+
+// An element still being read, innermost last
+enum Frame {
+    Block { kind: BlockKind, inline: Vec<Value> },
+    Code { language: Option<String>, text: String },
+    List { start: Option<u64>, items: Vec<Value> },
+    Item { inline: Vec<Value>, lists: Vec<Value>, checked: Option<bool> },
+    Link { kind: LinkKind, inline: Vec<Value> },
+    Skipped, // Front matter, which the page keeps in raw_markdown only
+}
+
+#[derive(Default)]
+struct Converter {
+    blocks: Vec<Value>, // Top-level blocks so far
+    stack: Vec<Frame>,
+    formats: Vec<u64>, // Text format inside each open emphasis, strong or strikethrough
+}
+
+// content_json for a Markdown document, with a new uniqueID for every block. Front matter is
+// left out.
+pub fn markdown_to_content(markdown: &str) -> Value {
+    let options = Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_WIKILINKS
+        | Options::ENABLE_YAML_STYLE_METADATA_BLOCKS;
+    let mut converter = Converter::default();
+    for event in Parser::new_ext(markdown, options) {
+        converter.event(event);
+    }
+    page_handler::new_page_content(&Value::Null, converter.blocks, &[])
+}
+
+// Titles of the `[[...]]` links in the content's text, as extract_page_link_titles finds them.
+// Code blocks aren't text, so `[[` in code is never a link.
+pub fn link_titles(content: &Value) -> Vec<String> {
+    fn collect(node: &Value, text: &mut String) {
+        if node.get("type").and_then(|v| v.as_str()) == Some("text") {
+            text.push_str(node.get("text").and_then(|v| v.as_str()).unwrap_or_default());
+            text.push('\n');
+        }
+        for child in node.get("children").and_then(|v| v.as_array()).into_iter().flatten() {
+            collect(child, text);
+        }
+    }
+
+    let mut text = String::new();
+    collect(content.get("root").unwrap_or(content), &mut text);
+    page_handler::extract_page_link_titles(&text)
+}
+
+impl Converter {
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) | Event::InlineHtml(text) => self.push_text(&text, 0),
+            // A line of an HTML block, kept as text
+            Event::Html(line) => {
+                if !self.inline_mut().is_empty() {
+                    self.push_inline(linebreak_node());
+                }
+                self.push_text(&line, 0);
+            }
+            Event::Code(code) => self.push_text(&code, CODE_FORMAT),
+            Event::InlineMath(math) | Event::DisplayMath(math) => self.push_text(&math, 0),
+            Event::FootnoteReference(label) => self.push_text(&format!("[^{}]", label), 0),
+            Event::SoftBreak | Event::HardBreak => self.push_inline(linebreak_node()),
+            Event::Rule => self.finish_block(paragraph_node(vec![text_node("***", 0)])),
+            // In a loose list the marker comes inside the item's first paragraph
+            Event::TaskListMarker(checked) => {
+                let item = self.stack.iter_mut().rev().find_map(|frame| match frame {
+                    Frame::Item { checked, .. } => Some(checked),
+                    _ => None,
+                });
+                if let Some(item_checked) = item {
+                    *item_checked = Some(checked);
+                }
+            }
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        let frame = match tag {
+            Tag::Paragraph | Tag::HtmlBlock => Frame::Block { kind: BlockKind::Paragraph, inline: Vec::new() },
+            Tag::Heading { level, .. } => Frame::Block { kind: BlockKind::Heading(level), inline: Vec::new() },
+            Tag::BlockQuote(_) => Frame::Block { kind: BlockKind::Quote, inline: Vec::new() },
+            Tag::CodeBlock(kind) => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => info.split_whitespace().next().map(String::from),
+                    CodeBlockKind::Indented => None,
+                };
+                Frame::Code { language, text: String::new() }
+            }
+            Tag::List(start) => Frame::List { start, items: Vec::new() },
+            Tag::Item => Frame::Item { inline: Vec::new(), lists: Vec::new(), checked: None },
+            Tag::Emphasis | Tag::Strong | Tag::Strikethrough => {
+                let bit = match tag {
+                    Tag::Emphasis => ITALIC_FORMAT,
+                    Tag::Strong => BOLD_FORMAT,
+                    _ => STRIKETHROUGH_FORMAT,
+                };
+                self.formats.push(self.format() | bit);
+                return;
+            }
+            Tag::Link { link_type: LinkType::WikiLink { has_pothole }, dest_url, .. } => Frame::Link {
+                kind: LinkKind::Wiki { target: dest_url.to_string(), has_label: has_pothole },
+                inline: Vec::new(),
+            },
+            Tag::Link { dest_url, .. } => Frame::Link {
+                kind: LinkKind::Url(dest_url.to_string()),
+                inline: Vec::new(),
+            },
+            Tag::Image { dest_url, .. } => Frame::Link {
+                kind: LinkKind::Image(dest_url.to_string()),
+                inline: Vec::new(),
+            },
+            Tag::MetadataBlock(_) => Frame::Skipped,
+            // Not enabled, so never parsed
+            _ => return,
+        };
+        self.stack.push(frame);
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough => {
+                self.formats.pop();
+                return;
+            }
+            TagEnd::Paragraph
+            | TagEnd::HtmlBlock
+            | TagEnd::Heading(_)
+            | TagEnd::BlockQuote(_)
+            | TagEnd::CodeBlock
+            | TagEnd::List(_)
+            | TagEnd::Item
+            | TagEnd::Link
+            | TagEnd::Image
+            | TagEnd::MetadataBlock(_) => {}
+            _ => return,
+        }
+        let Some(frame) = self.stack.pop() else {
+            return;
+        };
+
+        match frame {
+            Frame::Block { kind, inline } => match kind {
+                BlockKind::Paragraph => self.finish_block(paragraph_node(inline)),
+                BlockKind::Heading(level) => self.finish_block(heading_node(level, inline)),
+                BlockKind::Quote => self.finish_block(element_node("quote", inline)),
+            },
+            Frame::Code { language, text } => self.finish_block(code_node(language.as_deref(), &text)),
+            Frame::List { start, items } => {
+                for list in list_nodes(start, items) {
+                    match self.stack.last_mut() {
+                        Some(Frame::Item { lists, .. }) => lists.push(list),
+                        _ => self.finish_block(list),
+                    }
+                }
+            }
+            Frame::Item { inline, lists, checked } => {
+                if let Some(Frame::List { items, .. }) = self.stack.last_mut() {
+                    let mut item = element_node("listitem", inline);
+                    if let Some(checked) = checked {
+                        item["checked"] = Value::Bool(checked);
+                    }
+                    items.push(item);
+                    // A nested list goes in a list item of its own after its parent
+                    if !lists.is_empty() {
+                        items.push(element_node("listitem", lists));
+                    }
+                }
+            }
+            Frame::Link { kind, inline } => match kind {
+                LinkKind::Url(url) => self.push_inline(serde_json::json!({
+                    "children": inline,
+                    "direction": "ltr",
+                    "format": "",
+                    "indent": 0,
+                    "rel": "noreferrer",
+                    "target": null,
+                    "title": null,
+                    "type": "link",
+                    "url": url,
+                    "version": 1
+                })),
+                LinkKind::Wiki { target, has_label } => {
+                    let text = if has_label {
+                        format!("[[{}|{}]]", target, plain_text(&inline))
+                    } else {
+                        format!("[[{}]]", target)
+                    };
+                    self.push_text(&text, 0);
+                }
+                LinkKind::Image(url) => self.push_text(&format!("![{}]({})", plain_text(&inline), url), 0),
+            },
+            Frame::Skipped => {}
+        }
+    }
+
+    fn format(&self) -> u64 {
+        self.formats.last().copied().unwrap_or(0)
+    }
+
+    // Text in the current format, as text nodes separated by line breaks
+    fn push_text(&mut self, text: &str, format: u64) {
+        match self.stack.last_mut() {
+            Some(Frame::Code { text: code, .. }) => {
+                code.push_str(text);
+                return;
+            }
+            Some(Frame::Skipped) => return,
+            _ => {}
+        }
+        let format = self.format() | format;
+        for (index, line) in text.strip_suffix('\n').unwrap_or(text).split('\n').enumerate() {
+            if index > 0 {
+                self.push_inline(linebreak_node());
+            }
+            if line.is_empty() {
+                continue;
+            }
+            // Text split over several events (e.g. at a `[` that turned out not to be a link)
+            // goes back into one node
+            let inline = self.inline_mut();
+            match inline.last_mut() {
+                Some(last) if is_text_with_format(last, format) => {
+                    let joined = format!("{}{}", last["text"].as_str().unwrap_or_default(), line);
+                    last["text"] = Value::String(joined);
+                }
+                _ => inline.push(text_node(line, format)),
+            }
+        }
+    }
+
+    fn push_inline(&mut self, node: Value) {
+        self.inline_mut().push(node);
+    }
+
+    // The inline children being read. Inline content outside any block (which the parser
+    // doesn't produce) starts a paragraph.
+    fn inline_mut(&mut self) -> &mut Vec<Value> {
+        if !matches!(
+            self.stack.last(),
+            Some(Frame::Block { .. } | Frame::Item { .. } | Frame::Link { .. })
+        ) {
+            self.stack.push(Frame::Block { kind: BlockKind::Paragraph, inline: Vec::new() });
+        }
+        match self.stack.last_mut() {
+            Some(Frame::Block { inline, .. } | Frame::Item { inline, .. } | Frame::Link { inline, .. }) => inline,
+            _ => unreachable!("an inline frame was just pushed"),
+        }
+    }
+
+    // Adds a finished block at the top level, or as lines of the item or quote it is in
+    fn finish_block(&mut self, block: Value) {
+        let Some(Frame::Block { inline, .. } | Frame::Item { inline, .. }) = self.stack.last_mut() else {
+            let mut block = block;
+            assign_unique_ids(&mut block);
+            self.blocks.push(block);
+            return;
+        };
+        let mut lines = Vec::new();
+        block_lines(&block, &mut lines);
+        for line in lines {
+            if !inline.is_empty() {
+                inline.push(linebreak_node());
+            }
+            inline.extend(line);
+        }
+    }
+}
+
+// The inline content of a block that can't be nested, a line per paragraph, list item or line
+// of code. Headings are kept as bold text.
+fn block_lines(block: &Value, lines: &mut Vec<Vec<Value>>) {
+    let children = block.get("children").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+    match block.get("type").and_then(|v| v.as_str()).unwrap_or_default() {
+        "list" => {
+            for item in &children {
+                block_lines(item, lines);
+            }
+        }
+        "listitem" if json_utils::is_nested_list_holder(block) => {
+            for list in &children {
+                block_lines(list, lines);
+            }
+        }
+        "code" => {
+            let mut line = Vec::new();
+            for node in children {
+                if node.get("type").and_then(|v| v.as_str()) == Some("linebreak") {
+                    lines.push(std::mem::take(&mut line));
+                } else {
+                    let text = node.get("text").and_then(|v| v.as_str()).unwrap_or_default();
+                    line.push(text_node(text, CODE_FORMAT));
+                }
+            }
+            lines.push(line);
+        }
+        "heading" => lines.push(
+            children
+                .into_iter()
+                .map(|mut node| {
+                    if let Some(format) = node.get("format").and_then(|v| v.as_u64()) {
+                        node["format"] = Value::from(format | BOLD_FORMAT);
+                    }
+                    node
+                })
+                .collect(),
+        ),
+        _ => lines.push(children),
+    }
+}
+
+// Gives every block in a top-level block a uniqueID. Nested-list holders aren't blocks.
+fn assign_unique_ids(node: &mut Value) {
+    let kind = json_utils::node_type(node).unwrap_or_default().to_string();
+    if json_utils::BLOCK_NODE_TYPES.contains(&kind.as_str()) && !json_utils::is_nested_list_holder(node) {
+        node["uniqueID"] = Value::String(Uuid::new_v4().to_string());
+    }
+    if kind == "code" {
+        return;
+    }
+    if let Some(Value::Array(children)) = node.get_mut("children") {
+        children.iter_mut().for_each(assign_unique_ids);
+    }
+}
+
+fn is_text_with_format(node: &Value, format: u64) -> bool {
+    json_utils::node_type(node) == Some("text") && node.get("format").and_then(|v| v.as_u64()) == Some(format)
+}
+
+fn plain_text(nodes: &[Value]) -> String {
+    nodes
+        .iter()
+        .map(|node| match node.get("text").and_then(|v| v.as_str()) {
+            Some(text) => text.to_string(),
+            None => plain_text(node.get("children").and_then(|v| v.as_array()).map(Vec::as_slice).unwrap_or_default()),
+        })
+        .collect()
+}
+
+fn linebreak_node() -> Value {
+    serde_json::json!({ "type": "linebreak", "version": 1 })
+}
+
+fn element_node(kind: &str, children: Vec<Value>) -> Value {
+    serde_json::json!({
+        "children": children,
+        "direction": "ltr",
+        "format": "",
+        "indent": 0,
+        "type": kind,
+        "version": 1
+    })
+}
+
+fn paragraph_node(children: Vec<Value>) -> Value {
+    element_node("paragraph", children)
+}
+
+fn heading_node(level: HeadingLevel, children: Vec<Value>) -> Value {
+    let mut heading = element_node("heading", children);
+    heading["tag"] = Value::String(level.to_string());
+    heading
+}
+
+// Lines of code as the editor's code-highlight nodes, which the link extractor doesn't read
+fn code_node(language: Option<&str>, text: &str) -> Value {
+    let mut children = Vec::new();
+    for (index, line) in text.strip_suffix('\n').unwrap_or(text).split('\n').enumerate() {
+        if index > 0 {
+            children.push(linebreak_node());
+        }
+        if !line.is_empty() {
+            let mut node = text_node(line, 0);
+            node["type"] = Value::from("code-highlight");
+            node["highlightType"] = Value::Null;
+            children.push(node);
+        }
+    }
+    let mut code = element_node("code", children);
+    if let Some(language) = language {
+        code["language"] = Value::from(language);
+    }
+    code
+}
+
+// A bullet list as one list per run of task or non-task items, since the editor's check lists
+// hold only tasks, and a numbered list as it is
+fn list_nodes(start: Option<u64>, items: Vec<Value>) -> Vec<Value> {
+    if let Some(start) = start {
+        return vec![list_node("number", start, items)];
+    }
+    let mut lists = Vec::new();
+    let mut run: Vec<Value> = Vec::new();
+    let mut run_is_check = false;
+    for item in items {
+        let is_check = item.get("checked").is_some();
+        // A nested-list holder stays with the item before it
+        if !json_utils::is_nested_list_holder(&item) {
+            if is_check != run_is_check && !run.is_empty() {
+                lists.push(list_node(if run_is_check { "check" } else { "bullet" }, 1, std::mem::take(&mut run)));
+            }
+            run_is_check = is_check;
+        }
+        run.push(item);
+    }
+    if !run.is_empty() {
+        lists.push(list_node(if run_is_check { "check" } else { "bullet" }, 1, run));
+    }
+    lists
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    // Every uniqueID as "<id>", so the golden files show which nodes get one
+    fn with_placeholder_ids(mut node: Value) -> Value {
+        if let Some(id) = node.get_mut("uniqueID") {
+            assert!(id.as_str().is_some_and(|id| Uuid::parse_str(id).is_ok()), "bad uniqueID {}", id);
+            *id = Value::from("<id>");
+        }
+        if let Some(Value::Array(children)) = node.get_mut("children") {
+            for child in children.iter_mut() {
+                *child = with_placeholder_ids(child.take());
+            }
+        }
+        if let Some(root) = node.get_mut("root") {
+            *root = with_placeholder_ids(root.take());
+        }
+        node
+    }
+
+    // Converts testdata/markdown_import/<name>.md and compares it with <name>.json. Set
+    // UPDATE_GOLDEN=1 to rewrite the .json files instead.
+    fn assert_golden(name: &str) -> Value {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/markdown_import");
+        let markdown = std::fs::read_to_string(dir.join(format!("{}.md", name))).unwrap();
+        let content = markdown_to_content(&markdown);
+        let actual = with_placeholder_ids(content.clone());
+        let golden = dir.join(format!("{}.json", name));
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&golden, serde_json::to_string_pretty(&actual).unwrap() + "\n").unwrap();
+        }
+        let expected: Value = serde_json::from_str(&std::fs::read_to_string(&golden).unwrap()).unwrap();
+        assert_eq!(actual, expected, "{} differs from {}.json", name, name);
+        content
+    }
+
+    #[test]
+    fn nested_lists_four_levels_deep() {
+        assert_golden("nested_lists");
+    }
+
+    #[test]
+    fn fenced_code_keeps_wiki_links_as_code() {
+        let content = assert_golden("fenced_code_links");
+        // Code in a list item becomes inline code of the item, which is text to the editor
+        assert_eq!(link_titles(&content), vec!["Real Page".to_string(), "Nested Code".to_string()]);
+    }
+}
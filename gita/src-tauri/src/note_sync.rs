@@ -0,0 +1,389 @@
+// Two-way sync between the database and the Markdown files in the notes directory.
+//
+// Each page is written as `<title>.md` with an `id` in its front matter. note_sync_state
+// remembers, per page, which file it was last synced with, the file's hash at that point and
+// when. On each run:
+// - only the page changed (updated_at > last_synced_at): the file is rewritten
+// - only the file changed (hash differs): the page is updated from the file
+// - both changed: reported as a conflict and neither side is touched
+// - a file that moved but kept its front matter id is treated as a rename, not a new page
+// - files without a known id become new pages; files deleted on disk trash their page
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::dal_error::DalError;
+use crate::file_system::{self, NoteFrontMatter};
+use crate::markdown_import;
+use crate::page_handler::{self, Page};
+use crate::sync_handler::{self, SyncState};
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SyncConflict {
+    pub page_id: Uuid,
+    pub title: String,
+    pub file_path: String,
+    pub reason: String,
+    pub db_updated_at: DateTime<Utc>,
+    pub file_modified_at: Option<DateTime<Utc>>, // None when the file was deleted
+    pub last_synced_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SyncFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct SyncReport {
+    pub exported: usize,      // Files written from the database
+    pub imported: usize,      // Pages updated from changed files
+    pub created: usize,       // Pages created from new files
+    pub renamed: usize,       // Files moved/renamed on disk, matched by front matter id
+    pub trashed: usize,       // Pages trashed because their file was deleted
+    pub removed_files: usize, // Files deleted because their page was trashed or purged
+    pub conflicts: Vec<SyncConflict>,
+    pub failures: Vec<SyncFailure>,
+}
+
+// A Markdown file found in the notes directory
+struct DiskNote {
+    relative_path: String,
+    content: String,
+    hash: String,
+    page_id: Option<Uuid>,
+    title: Option<String>,
+    created_at: Option<DateTime<Utc>>,
+    modified_at: Option<DateTime<Utc>>,
+}
+
+impl DiskNote {
+    fn read(notes_dir: &Path, path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("Failed to read file: {}", e))?;
+        let (front_matter, _) = file_system::extract_front_matter(&content);
+        let front_matter = front_matter.unwrap_or_default();
+        let relative_path = path
+            .strip_prefix(notes_dir)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string();
+        let fallback_title = path.file_stem().map(|stem| stem.to_string_lossy().trim().to_string());
+        Ok(DiskNote {
+            hash: content_hash(&content),
+            page_id: front_matter.id.as_deref().and_then(|id| Uuid::parse_str(id.trim()).ok()),
+            title: front_matter
+                .title
+                .filter(|title| !title.trim().is_empty())
+                .or(fallback_title.filter(|title| !title.is_empty())),
+            created_at: front_matter.created_at.as_deref().and_then(file_system::parse_front_matter_date),
+            modified_at: std::fs::metadata(path).and_then(|m| m.modified()).ok().map(DateTime::<Utc>::from),
+            relative_path,
+            content,
+        })
+    }
+
+    fn body(&self) -> &str {
+        file_system::extract_front_matter(&self.content).1
+    }
+}
+
+pub async fn sync_notes_directory(pool: &PgPool, notes_dir: &Path) -> Result<SyncReport, String> {
+    std::fs::create_dir_all(notes_dir).map_err(|e| format!("Failed to create notes directory: {}", e))?;
+
+    let mut report = SyncReport::default();
+    let pages = page_handler::get_all_pages(pool).await.map_err(|e| e.to_string())?;
+    let states: HashMap<Uuid, SyncState> = sync_handler::get_all_sync_states(pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|state| (state.page_id, state))
+        .collect();
+
+    let mut used_paths: HashSet<String> = HashSet::new();
+    let mut files_by_page: HashMap<Uuid, DiskNote> = HashMap::new();
+    let mut unmatched_files: Vec<DiskNote> = Vec::new();
+    for path in file_system::scan_directory(notes_dir)? {
+        match DiskNote::read(notes_dir, &path) {
+            Ok(note) => {
+                used_paths.insert(note.relative_path.clone());
+                match note.page_id {
+                    // A copied file repeats the id; only the first one keeps it
+                    Some(id) if !files_by_page.contains_key(&id) => {
+                        files_by_page.insert(id, note);
+                    }
+                    _ => unmatched_files.push(note),
+                }
+            }
+            Err(e) => report.failures.push(SyncFailure {
+                path: path.display().to_string(),
+                error: e,
+            }),
+        }
+    }
+
+    let live_ids: HashSet<Uuid> = pages.iter().map(|page| page.id).collect();
+    for page in &pages {
+        let file = files_by_page.remove(&page.id);
+        let path_hint = file.as_ref().map(|f| f.relative_path.clone()).unwrap_or_else(|| page.title.clone());
+        if let Err(e) = sync_page(pool, notes_dir, page, states.get(&page.id), file, &mut used_paths, &mut report).await {
+            report.failures.push(SyncFailure { path: path_hint, error: e });
+        }
+    }
+
+    // Pages that were synced before but have since been trashed or purged in the app
+    for state in states.values().filter(|state| !live_ids.contains(&state.page_id)) {
+        let file = files_by_page.remove(&state.page_id);
+        if let Err(e) = remove_file_of_deleted_page(pool, notes_dir, state, file, &mut report).await {
+            report.failures.push(SyncFailure {
+                path: state.file_path.clone(),
+                error: e,
+            });
+        }
+    }
+
+    // Files with no id, or an id that doesn't belong to any live page, are new notes
+    unmatched_files.extend(files_by_page.into_values());
+    for note in unmatched_files {
+        let path = note.relative_path.clone();
+        if let Err(e) = create_page_from_file(pool, notes_dir, note, &mut report).await {
+            report.failures.push(SyncFailure { path, error: e });
+        }
+    }
+
+    Ok(report)
+}
+
+async fn sync_page(
+    pool: &PgPool,
+    notes_dir: &Path,
+    page: &Page,
+    state: Option<&SyncState>,
+    file: Option<DiskNote>,
+    used_paths: &mut HashSet<String>,
+    report: &mut SyncReport,
+) -> Result<(), String> {
+    let db_changed = state.is_none_or(|s| page.updated_at > s.last_synced_at);
+
+    let file = match (file, state) {
+        (Some(file), _) => file,
+        (None, None) => {
+            let relative_path = allocate_file_path(notes_dir, &page.title, used_paths);
+            export_page(pool, notes_dir, page, &relative_path).await?;
+            report.exported += 1;
+            return Ok(());
+        }
+        (None, Some(state)) => {
+            // The file was deleted on disk
+            if db_changed {
+                report.conflicts.push(SyncConflict {
+                    page_id: page.id,
+                    title: page.title.clone(),
+                    file_path: state.file_path.clone(),
+                    reason: "The file was deleted but the page was changed in the app".to_string(),
+                    db_updated_at: page.updated_at,
+                    file_modified_at: None,
+                    last_synced_at: Some(state.last_synced_at),
+                });
+            } else {
+                page_handler::trash_page(pool, page.id).await.map_err(|e| e.to_string())?;
+                sync_handler::delete_sync_state(pool, page.id).await.map_err(|e| e.to_string())?;
+                report.trashed += 1;
+            }
+            return Ok(());
+        }
+    };
+
+    let file_changed = state.is_none_or(|s| s.content_hash != file.hash);
+    let renamed = state.is_some_and(|s| s.file_path != file.relative_path);
+
+    if file_changed && db_changed {
+        // Never synced (e.g. the state table was reset) but already identical: just record it
+        if state.is_none() && render_page(page)? == file.content {
+            record_state(pool, page.id, &file.relative_path, &file.hash).await?;
+            return Ok(());
+        }
+        report.conflicts.push(SyncConflict {
+            page_id: page.id,
+            title: page.title.clone(),
+            file_path: file.relative_path.clone(),
+            reason: "Both the file and the page changed since the last sync".to_string(),
+            db_updated_at: page.updated_at,
+            file_modified_at: file.modified_at,
+            last_synced_at: state.map(|s| s.last_synced_at),
+        });
+        return Ok(());
+    }
+
+    if file_changed {
+        import_file_into_page(pool, page, &file).await?;
+        report.imported += 1;
+    } else if db_changed {
+        export_page(pool, notes_dir, page, &file.relative_path).await?;
+        report.exported += 1;
+    } else if renamed {
+        record_state(pool, page.id, &file.relative_path, &file.hash).await?;
+    }
+    if renamed {
+        report.renamed += 1;
+    }
+    Ok(())
+}
+
+async fn remove_file_of_deleted_page(
+    pool: &PgPool,
+    notes_dir: &Path,
+    state: &SyncState,
+    file: Option<DiskNote>,
+    report: &mut SyncReport,
+) -> Result<(), String> {
+    if let Some(file) = file {
+        if file.hash != state.content_hash {
+            // Edited on disk after the page was deleted: keep the file, just stop tracking it
+            // so the next sync imports it as a new page
+            let deleted_at = match page_handler::get_page(pool, state.page_id).await {
+                Ok(page) => page.deleted_at,
+                Err(DalError::NotFound) => None,
+                Err(e) => return Err(e.to_string()),
+            };
+            report.conflicts.push(SyncConflict {
+                page_id: state.page_id,
+                title: file.title.clone().unwrap_or_default(),
+                file_path: file.relative_path.clone(),
+                reason: "The page was deleted in the app but the file was changed".to_string(),
+                db_updated_at: deleted_at.unwrap_or(state.last_synced_at),
+                file_modified_at: file.modified_at,
+                last_synced_at: Some(state.last_synced_at),
+            });
+        } else {
+            std::fs::remove_file(notes_dir.join(&file.relative_path))
+                .map_err(|e| format!("Failed to remove file: {}", e))?;
+            report.removed_files += 1;
+        }
+    }
+    sync_handler::delete_sync_state(pool, state.page_id).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+async fn create_page_from_file(
+    pool: &PgPool,
+    notes_dir: &Path,
+    note: DiskNote,
+    report: &mut SyncReport,
+) -> Result<(), String> {
+    let title = note.title.clone().ok_or_else(|| "File name cannot be used as a page title".to_string())?;
+    if let Some(existing) = page_handler::get_page_by_title(pool, &title).await.map_err(|e| e.to_string())? {
+        report.conflicts.push(SyncConflict {
+            page_id: existing.id,
+            title,
+            file_path: note.relative_path.clone(),
+            reason: "A different page with this title already exists".to_string(),
+            db_updated_at: existing.updated_at,
+            file_modified_at: note.modified_at,
+            last_synced_at: None,
+        });
+        return Ok(());
+    }
+
+    // Converted into blocks as an import would be, with its links and tags stored
+    let content_json = markdown_import::markdown_to_content(note.body());
+    let (page_id, _) = page_handler::create_page_with_content(pool, &title, content_json, Some(note.body()), false)
+        .await
+        .map_err(|e| e.to_string())?;
+    if let Some(created_at) = note.created_at {
+        page_handler::set_page_created_at(pool, page_id, created_at).await.map_err(|e| e.to_string())?;
+    }
+
+    // Rewrite the file so its front matter carries the new page's id
+    let page = page_handler::get_page(pool, page_id)
+        .await
+        .map_err(|e| e.to_string())?;
+    export_page(pool, notes_dir, &page, &note.relative_path).await?;
+    report.created += 1;
+    Ok(())
+}
+
+async fn import_file_into_page(pool: &PgPool, page: &Page, file: &DiskNote) -> Result<(), String> {
+    if let Some(title) = file.title.as_deref() {
+        if title != page.title {
+            page_handler::rename_page(pool, page.id, title).await.map_err(|e| e.to_string())?;
+        }
+    }
+    // The blocks are rebuilt from the file, so the page's links, tags and hash follow it
+    let content_json = markdown_import::markdown_to_content(file.body());
+    let raw_markdown = Some(Some(file.body()));
+    let summary = Some("Changed on disk");
+    page_handler::update_page(pool, page.id, None, Some(content_json), raw_markdown, None, false, summary)
+        .await
+        .map_err(|e| e.to_string())?;
+    record_state(pool, page.id, &file.relative_path, &file.hash).await
+}
+
+async fn export_page(pool: &PgPool, notes_dir: &Path, page: &Page, relative_path: &str) -> Result<(), String> {
+    let content = render_page(page)?;
+    let path = notes_dir.join(relative_path);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
+    }
+    std::fs::write(&path, &content).map_err(|e| format!("Failed to write file: {}", e))?;
+    record_state(pool, page.id, relative_path, &content_hash(&content)).await
+}
+
+// Writes a page to `<dir>/<title>.md` outside of sync: no sync state is recorded, so the
+// notes directory is unaffected. used_paths keeps pages with the same title apart.
+pub fn write_page_file(dir: &Path, page: &Page, used_paths: &mut HashSet<String>) -> Result<PathBuf, String> {
+    let content = render_page(page)?;
+    let path = dir.join(allocate_file_path(dir, &page.title, used_paths));
+    std::fs::write(&path, &content).map_err(|e| format!("Failed to write file: {}", e))?;
+    Ok(path)
+}
+
+async fn record_state(pool: &PgPool, page_id: Uuid, relative_path: &str, hash: &str) -> Result<(), String> {
+    sync_handler::upsert_sync_state(pool, page_id, relative_path, hash)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// The file content for a page. Front matter already present in raw_markdown (e.g. from a
+// vault import) is replaced; its tags are kept if the page has none of its own.
+fn render_page(page: &Page) -> Result<String, String> {
+    let markdown = page.raw_markdown.as_deref().unwrap_or_default();
+    let (existing_front_matter, body) = file_system::extract_front_matter(markdown);
+    let front_matter = NoteFrontMatter {
+        id: Some(page.id.to_string()),
+        title: Some(page.title.clone()),
+        created_at: Some(page.created_at.to_rfc3339()),
+        updated_at: Some(page.updated_at.to_rfc3339()),
+        tags: if page.tags.is_empty() {
+            existing_front_matter.and_then(|fm| fm.tags)
+        } else {
+            Some(page.tags.clone())
+        },
+    };
+    file_system::render_note(&front_matter, body)
+}
+
+// Picks `<title>.md`, or `<title> (n).md` if that name is taken
+fn allocate_file_path(notes_dir: &Path, title: &str, used_paths: &mut HashSet<String>) -> String {
+    let base = file_system::file_name_for_title(title);
+    let mut candidate = format!("{}.md", base);
+    let mut n = 2;
+    while used_paths.contains(&candidate) || notes_dir.join(&candidate).exists() {
+        candidate = format!("{} ({}).md", base, n);
+        n += 1;
+    }
+    used_paths.insert(candidate.clone());
+    candidate
+}
+
+fn content_hash(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
@@ -0,0 +1,143 @@
+// Watches the notes directory for Markdown files being created, modified, deleted or renamed
+// and forwards them to the frontend as vault://file-changed events.
+
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+pub const EVENT_VAULT_FILE_CHANGED: &str = "vault://file-changed";
+
+// notify coalesces events for the same path within this window, so editors that write through
+// temp files or save in several steps produce a single event
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(500);
+// How often the event thread checks whether it has been asked to stop
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileChangeKind {
+    Created,
+    Modified,
+    Deleted,
+    Renamed,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FileChangedEvent {
+    pub path: String,
+    pub kind: FileChangeKind,
+    pub old_path: Option<String>, // Only for renames
+}
+
+pub struct NotesWatcher {
+    directory: PathBuf,
+    watcher: Option<RecommendedWatcher>,
+    event_thread: Option<JoinHandle<()>>,
+    stop_signal: Arc<AtomicBool>,
+}
+
+impl NotesWatcher {
+    pub fn start(app_handle: AppHandle, directory: &Path) -> Result<Self, String> {
+        let (tx, rx) = channel();
+        let mut watcher = notify::watcher(tx, DEBOUNCE_DELAY).map_err(|e| format!("Failed to create file watcher: {}", e))?;
+        watcher
+            .watch(directory, RecursiveMode::Recursive)
+            .map_err(|e| format!("Failed to watch {}: {}", directory.display(), e))?;
+
+        let stop_signal = Arc::new(AtomicBool::new(false));
+        let thread_stop_signal = stop_signal.clone();
+        let event_thread = thread::spawn(move || {
+            while !thread_stop_signal.load(Ordering::Relaxed) {
+                let event = match rx.recv_timeout(STOP_POLL_INTERVAL) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                if let Some(change) = to_file_changed_event(event) {
+                    if let Err(e) = app_handle.emit(EVENT_VAULT_FILE_CHANGED, change) {
+                        eprintln!("[NotesWatcher] Failed to emit file change event: {}", e);
+                    }
+                }
+            }
+        });
+
+        println!("[NotesWatcher] Watching {}", directory.display());
+        Ok(NotesWatcher {
+            directory: directory.to_path_buf(),
+            watcher: Some(watcher),
+            event_thread: Some(event_thread),
+            stop_signal,
+        })
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop_signal.store(true, Ordering::Relaxed);
+        self.watcher.take(); // Dropping the watcher stops the OS-level watch
+        if let Some(handle) = self.event_thread.take() {
+            if handle.join().is_err() {
+                eprintln!("[NotesWatcher] Event thread panicked");
+            }
+            println!("[NotesWatcher] Stopped watching {}", self.directory.display());
+        }
+    }
+}
+
+impl Drop for NotesWatcher {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn is_markdown(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    let markdown = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("md"));
+    markdown && !hidden
+}
+
+fn change(path: PathBuf, kind: FileChangeKind) -> FileChangedEvent {
+    FileChangedEvent {
+        path: path.to_string_lossy().to_string(),
+        kind,
+        old_path: None,
+    }
+}
+
+fn to_file_changed_event(event: DebouncedEvent) -> Option<FileChangedEvent> {
+    match event {
+        DebouncedEvent::Create(path) if is_markdown(&path) => Some(change(path, FileChangeKind::Created)),
+        DebouncedEvent::Write(path) if is_markdown(&path) => Some(change(path, FileChangeKind::Modified)),
+        DebouncedEvent::Remove(path) if is_markdown(&path) => Some(change(path, FileChangeKind::Deleted)),
+        DebouncedEvent::Rename(from, to) => match (is_markdown(&from), is_markdown(&to)) {
+            (true, true) => Some(FileChangedEvent {
+                path: to.to_string_lossy().to_string(),
+                kind: FileChangeKind::Renamed,
+                old_path: Some(from.to_string_lossy().to_string()),
+            }),
+            // Saved via a temp file that was then renamed over the note
+            (false, true) => Some(change(to, FileChangeKind::Modified)),
+            (true, false) => Some(change(from, FileChangeKind::Deleted)),
+            (false, false) => None,
+        },
+        DebouncedEvent::Error(e, path) => {
+            eprintln!("[NotesWatcher] Watch error{}: {}", path.map(|p| format!(" on {}", p.display())).unwrap_or_default(), e);
+            None
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,70 @@
+// Events telling every window that pages or the links between them changed, so views can
+// re-fetch what they show. They carry IDs only, never content, and are emitted by the
+// commands once their transaction has committed.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+use uuid::Uuid;
+
+pub const EVENT_PAGE_CREATED: &str = "page://created";
+pub const EVENT_PAGE_UPDATED: &str = "page://updated";
+pub const EVENT_PAGE_DELETED: &str = "page://deleted";
+pub const EVENT_LINKS_CHANGED: &str = "links://changed";
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PageChangeKind {
+    Created,  // page://created
+    Restored, // page://created, back from the trash
+    Edited,   // page://updated: content, title or Markdown saved
+    Renamed,  // page://updated
+    Trashed,  // page://deleted
+    Purged,   // page://deleted for good
+}
+
+impl PageChangeKind {
+    fn event(self) -> &'static str {
+        match self {
+            PageChangeKind::Created | PageChangeKind::Restored => EVENT_PAGE_CREATED,
+            PageChangeKind::Edited | PageChangeKind::Renamed => EVENT_PAGE_UPDATED,
+            PageChangeKind::Trashed | PageChangeKind::Purged => EVENT_PAGE_DELETED,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PageChangedEvent {
+    pub page_id: String,
+    pub updated_at: Option<String>, // None for deletions
+    pub kind: PageChangeKind,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LinksChangedEvent {
+    pub page_ids: Vec<String>, // Pages whose backlinks or outgoing links changed
+}
+
+pub fn emit_page_changed(app_handle: &AppHandle, page_id: Uuid, updated_at: Option<DateTime<Utc>>, kind: PageChangeKind) {
+    let event = PageChangedEvent {
+        page_id: page_id.to_string(),
+        updated_at: updated_at.map(|dt| dt.to_rfc3339()),
+        kind,
+    };
+    if let Err(e) = app_handle.emit(kind.event(), event) {
+        eprintln!("[Page events] Failed to emit {}: {}", kind.event(), e);
+    }
+}
+
+// Does nothing when no pages are given
+pub fn emit_links_changed(app_handle: &AppHandle, page_ids: &[Uuid]) {
+    if page_ids.is_empty() {
+        return;
+    }
+    let event = LinksChangedEvent {
+        page_ids: page_ids.iter().map(Uuid::to_string).collect(),
+    };
+    if let Err(e) = app_handle.emit(EVENT_LINKS_CHANGED, event) {
+        eprintln!("[Page events] Failed to emit {}: {}", EVENT_LINKS_CHANGED, e);
+    }
+}
@@ -1,6 +1,6 @@
 use chrono::{DateTime, Utc};
 use serde_json::Value;
-use sqlx::PgPool;
+use sqlx::{PgConnection, PgExecutor, PgPool};
 use uuid::Uuid;
 use regex::Regex; // Added for parsing
 use lazy_static::lazy_static; // Added for static Regex
@@ -10,6 +10,10 @@ use crate::dal_error::DalError;
 // Import handlers (will be needed later)
 use crate::link_handler;
 use crate::block_handler;
+use crate::tag_handler;
+use crate::json_utils::{self, find_block_path, is_list_item, is_nested_list_holder, json_pointer};
+use crate::markdown_export::{render_block_lines, render_markdown};
+use crate::revision_handler;
 
 
 // Helper structs for parsing
@@ -18,15 +22,24 @@ struct ExtractedBlockInfo {
     id: Uuid,
     block_type: Option<String>,
     parent_block_id: Option<Uuid>, // ID of the direct parent block from content_json
-    // Add other fields like order if needed
+    order_index: i32, // Position among blocks sharing the same parent, in document order
+    content_text: Option<String>, // Normalized text of the block's own inline children
+    task_state: Option<&'static str>, // "todo"/"done" for checklist items and todo blocks
+}
+
+// Per-block bookkeeping accumulated while walking content_json
+#[derive(Debug, Default)]
+struct BlockTraversalState {
+    sibling_counts: std::collections::HashMap<Option<Uuid>, i32>, // Next order_index per parent
+    block_texts: std::collections::HashMap<Uuid, String>, // Raw text of each block's own inline children
 }
 
 #[derive(Debug, Clone)]
 struct ParsedPageLink {
     target_title: Option<String>,
     target_id: Option<Uuid>,
-    // If we need to identify which block a page link is in (e.g. for rich text editing later)
-    // referencing_block_id: Option<Uuid>
+    // The block the [[link]] was written in, when it sits inside one
+    referencing_block_id: Option<Uuid>,
 }
 
 #[derive(Debug, Clone)]
@@ -39,6 +52,8 @@ struct ParsedBlockReference {
 lazy_static! {
     static ref PAGE_LINK_REGEX: Regex = Regex::new(r"\[\[(.*?)\]\]").unwrap();
     static ref BLOCK_REF_REGEX: Regex = Regex::new(r"\(\(\((.*?)\)\)\)").unwrap();
+    static ref TEMPLATE_PLACEHOLDER_REGEX: Regex = Regex::new(r"\{\{\s*([A-Za-z0-9_.-]+)\s*\}\}").unwrap();
+    static ref MARKDOWN_PREFIX_REGEX: Regex = Regex::new(r"^\s*(?:[-*+] \[[ xX]\] |[-*+] |\d+[.)] |#{1,6} |> |```\S*)?").unwrap();
 }
 
 #[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
@@ -46,11 +61,33 @@ pub struct Page {
     pub id: Uuid,
     pub title: String,
     pub created_at: DateTime<Utc>,
-    pub updated_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>, // Moves with every write to the row
+    pub content_updated_at: DateTime<Utc>, // Only moves when content_json or raw_markdown changes
     pub content_json: Value,
     pub raw_markdown: Option<String>,
+    pub deleted_at: Option<DateTime<Utc>>, // Set when the page is in the trash
+    pub is_template: bool, // Offered by create_page_from_template
+    pub is_favorite: bool,
+    pub favorite_order: Option<i32>, // Position in the favorites list; None unless is_favorite
+    pub tags: Vec<String>,
+    pub aliases: Vec<String>, // Other names [[links]] can use for this page
+}
+
+// Lightweight page row used for listings; excludes content_json and raw_markdown.
+#[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct PageMetadata {
+    pub id: Uuid,
+    pub title: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub content_updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub is_favorite: bool,
+    pub favorite_order: Option<i32>,
+    pub tags: Vec<String>,
 }
 
+// Creates a page. DalError::TitleTaken if a live page already has the title, ignoring case.
 pub async fn create_page(
     pool: &PgPool,
     title: &str,
@@ -58,7 +95,7 @@ pub async fn create_page(
     raw_markdown: Option<&str>,
 ) -> Result<Uuid, DalError> {
     let new_id = Uuid::new_v4();
-    let query_result = sqlx::query!(
+    let inserted = sqlx::query!(
         r#"
         INSERT INTO pages (id, title, content_json, raw_markdown, created_at, updated_at)
         VALUES ($1, $2, $3, $4, now(), now())
@@ -70,16 +107,117 @@ pub async fn create_page(
         raw_markdown
     )
     .fetch_one(pool)
+    .await
+    .map_err(DalError::from);
+
+    match inserted {
+        Ok(row) => Ok(row.id),
+        Err(e) if e.is_unique_violation() => {
+            ensure_title_free(pool, title, None).await?;
+            Err(e)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// DalError::TitleTaken if a live page other than `except` has this title, ignoring case
+pub(crate) async fn ensure_title_free<'e>(
+    executor: impl PgExecutor<'e>,
+    title: &str,
+    except: Option<Uuid>,
+) -> Result<(), DalError> {
+    let existing = sqlx::query_scalar!(
+        r#"
+        SELECT id
+        FROM pages
+        WHERE lower(title) = lower($1) AND deleted_at IS NULL AND ($2::uuid IS NULL OR id <> $2)
+        "#,
+        title,
+        except
+    )
+    .fetch_optional(executor)
+    .await?;
+
+    match existing {
+        Some(existing_id) => Err(DalError::TitleTaken {
+            title: title.to_string(),
+            existing_id,
+        }),
+        None => Ok(()),
+    }
+}
+
+// Creates a daily note unless a live page with the same date title exists, in which case
+// None is returned. Relies on the unique index over live YYYY-MM-DD titles, so concurrent
+// callers can't both create one.
+pub async fn insert_daily_page(
+    pool: &PgPool,
+    title: &str,
+    content_json: Value,
+    raw_markdown: Option<&str>,
+) -> Result<Option<Uuid>, DalError> {
+    let new_id = Uuid::new_v4();
+    let inserted = sqlx::query_scalar!(
+        r#"
+        INSERT INTO pages (id, title, content_json, raw_markdown, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, now(), now())
+        ON CONFLICT (title) WHERE deleted_at IS NULL AND title ~ '^[0-9]{4}-[0-9]{2}-[0-9]{2}$'
+        DO NOTHING
+        RETURNING id
+        "#,
+        new_id,
+        title,
+        content_json,
+        raw_markdown
+    )
+    .fetch_optional(pool)
     .await?;
 
-    Ok(query_result.id)
+    Ok(inserted)
+}
+
+// The live daily note titled with the date (YYYY-MM-DD), created if there is none yet: from the
+// template, filled in with the variables, or else with the default daily content. Safe to call
+// concurrently; a caller that loses the race to create the note gets the one that won.
+// DalError::NotFound if the template doesn't exist.
+pub async fn get_or_create_daily_page(
+    pool: &PgPool,
+    date: &str,
+    template: Option<(Uuid, &std::collections::HashMap<String, String>)>,
+) -> Result<Page, DalError> {
+    if let Some(page) = get_page_by_title(pool, date).await? {
+        return Ok(page);
+    }
+
+    let new_page_id = match template {
+        Some((template_id, variables)) => match create_page_from_template(pool, template_id, date, variables).await {
+            Ok(id) => Some(id),
+            // Another caller created this date's note in the meantime
+            Err(e) if e.is_unique_violation() => None,
+            Err(DalError::Conflict(_) | DalError::TitleTaken { .. }) => None,
+            Err(e) => return Err(e),
+        },
+        None => {
+            let (content_json, raw_markdown) = default_daily_content(date);
+            insert_daily_page(pool, date, content_json, Some(&raw_markdown)).await?
+        }
+    };
+
+    match new_page_id {
+        Some(id) => get_page(pool, id).await,
+        None => get_page_by_title(pool, date)
+            .await?
+            .ok_or_else(|| DalError::Conflict(format!("The daily note for {} was deleted while being created", date))),
+    }
 }
 
-pub async fn get_page(pool: &PgPool, id: Uuid) -> Result<Option<Page>, DalError> {
+// Returns DalError::NotFound if there is no page with this ID (trashed pages are included)
+pub async fn get_page(pool: &PgPool, id: Uuid) -> Result<Page, DalError> {
     let page = sqlx::query_as!(
         Page,
         r#"
-        SELECT id, title, content_json, raw_markdown, created_at, updated_at
+        SELECT id, title, content_json, raw_markdown, created_at, updated_at, deleted_at, is_template, is_favorite, favorite_order,
+               content_updated_at, page_tag_names(id) AS "tags!", page_alias_names(id) AS "aliases!"
         FROM pages
         WHERE id = $1
         "#,
@@ -88,17 +226,37 @@ pub async fn get_page(pool: &PgPool, id: Uuid) -> Result<Option<Page>, DalError>
     .fetch_optional(pool)
     .await?;
 
-    Ok(page)
+    page.ok_or(DalError::NotFound)
 }
 
-pub async fn list_pages(pool: &PgPool) -> Result<Vec<Page>, DalError> {
+// Orders list_pages can return pages in
+#[derive(Debug, serde::Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PageSort {
+    #[default]
+    RecentlyEdited, // Newest content_updated_at first
+    RecentlyCreated,
+    Title, // A-Z, ignoring case
+}
+
+// Lists page metadata in the given order. Ties fall back to the most recently edited, then to
+// `id`, so offset pagination stays stable when several pages share the same timestamps.
+pub async fn list_pages(pool: &PgPool, limit: i64, offset: i64, sort: PageSort) -> Result<Vec<PageMetadata>, DalError> {
     let pages = sqlx::query_as!(
-        Page,
+        PageMetadata,
         r#"
-        SELECT id, title, content_json, raw_markdown, created_at, updated_at
+        SELECT id, title, created_at, updated_at, deleted_at, is_favorite, favorite_order,
+               content_updated_at, page_tag_names(id) AS "tags!"
         FROM pages
-        ORDER BY updated_at DESC
-        "#
+        WHERE deleted_at IS NULL
+        ORDER BY CASE WHEN $3 THEN created_at END DESC, CASE WHEN $4 THEN lower(title) END ASC,
+                 content_updated_at DESC, id DESC
+        LIMIT $1 OFFSET $2
+        "#,
+        limit,
+        offset,
+        sort == PageSort::RecentlyCreated,
+        sort == PageSort::Title
     )
     .fetch_all(pool)
     .await?;
@@ -106,337 +264,3838 @@ pub async fn list_pages(pool: &PgPool) -> Result<Vec<Page>, DalError> {
     Ok(pages)
 }
 
+pub async fn count_pages(pool: &PgPool) -> Result<i64, DalError> {
+    let result = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!"
+        FROM pages
+        WHERE deleted_at IS NULL
+        "#
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(result.count)
+}
+
 // Still to implement:
 // update_page
 // delete_page
 // search_pages
 
+// What update_page did to a page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageUpdate {
+    pub updated_at: DateTime<Utc>, // The page's updated_at afterwards
+    pub changed: bool,             // False when every given field already had the given value
+    pub link_targets_changed: Vec<Uuid>, // Pages this one now links to more or fewer times
+}
+
+// Returns what was done, or None if there is no page with this ID. Fields that already have
+// the given value are left alone: content_json is compared by its content_hash, and the
+// block and link sync only runs when it differs. When nothing differs nothing is written and
+// updated_at stays as it was.
+// With expected_updated_at, the write is refused with DalError::Conflict unless the page is
+// still at that version, so a stale editor can't overwrite newer changes. With
+// create_stub_pages, `[[Titles]]` matching no page get an empty stub page to link to.
+// When the content changes, what it was is kept as a revision, with change_summary.
+#[allow(clippy::too_many_arguments)]
 pub async fn update_page(
     pool: &PgPool,
     id: Uuid,
     title: Option<&str>,
     content_json: Option<Value>,
     raw_markdown: Option<Option<&str>>, // Option<Option<T>> to distinguish between no-update and set-to-NULL
-) -> Result<bool, DalError> {
+    expected_updated_at: Option<DateTime<Utc>>,
+    create_stub_pages: bool,
+    change_summary: Option<&str>,
+) -> Result<Option<PageUpdate>, DalError> {
+    // All block, link and page writes share one transaction so a failure part-way through
+    // leaves the page exactly as it was.
+    let mut tx = pool.begin().await?;
+    let update = update_page_in(
+        &mut tx,
+        id,
+        title,
+        content_json,
+        raw_markdown,
+        expected_updated_at,
+        create_stub_pages,
+        change_summary,
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(update)
+}
+
+// update_page inside a caller's transaction, for changes that span several pages
+#[allow(clippy::too_many_arguments)]
+async fn update_page_in(
+    tx: &mut PgConnection,
+    id: Uuid,
+    title: Option<&str>,
+    content_json: Option<Value>,
+    raw_markdown: Option<Option<&str>>,
+    expected_updated_at: Option<DateTime<Utc>>,
+    create_stub_pages: bool,
+    change_summary: Option<&str>,
+) -> Result<Option<PageUpdate>, DalError> {
+    // Locking the row makes a concurrent writer wait here, then see the version this one wrote
+    let current = sqlx::query!(
+        r#"
+        SELECT title, updated_at, content_json, raw_markdown, content_hash
+        FROM pages
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(current) = current else {
+        return Ok(None);
+    };
+    let current_updated_at = current.updated_at;
+    if expected_updated_at.is_some_and(|expected| expected != current_updated_at) {
+        return Err(DalError::Conflict(format!(
+            "Page {} was changed at {} by another window",
+            id,
+            current_updated_at.to_rfc3339()
+        )));
+    }
+
+    // Drop the fields that wouldn't change. Pages saved before content hashes were stored, or
+    // whose content_json was last written without a sync, have none and are always synced.
+    let new_content_hash = content_json.as_ref().map(json_utils::content_json_hash);
+    let content_unchanged = new_content_hash.is_some() && new_content_hash == current.content_hash;
+    let content_json = content_json.filter(|_| !content_unchanged);
+    // raw_markdown follows changed content when the save doesn't send it
+    let rendered_markdown;
+    let raw_markdown = match (raw_markdown, &content_json) {
+        (None, Some(content)) => {
+            rendered_markdown = render_markdown(content);
+            Some(Some(rendered_markdown.as_str()))
+        }
+        (raw_markdown, _) => raw_markdown,
+    };
+    let title = title.filter(|title| *title != current.title);
+    if let Some(title) = title {
+        ensure_title_free(&mut *tx, title, Some(id)).await?;
+    }
+    let raw_markdown = raw_markdown.filter(|markdown| *markdown != current.raw_markdown.as_deref());
+    if title.is_none() && content_json.is_none() && raw_markdown.is_none() {
+        // Nothing to update
+        return Ok(Some(PageUpdate {
+            updated_at: current_updated_at,
+            changed: false,
+            link_targets_changed: Vec::new(),
+        }));
+    }
+
+    // Keep the content being replaced, unless the update leaves it as it is
+    let mut content_changed = false;
+    if content_json.is_some() || raw_markdown.is_some() {
+        let current_hash = revision_handler::content_hash(&current.content_json, current.raw_markdown.as_deref());
+        let new_hash = revision_handler::content_hash(
+            content_json.as_ref().unwrap_or(&current.content_json),
+            raw_markdown.unwrap_or(current.raw_markdown.as_deref()),
+        );
+        content_changed = new_hash != current_hash;
+        if content_changed {
+            revision_handler::add_revision(
+                &mut *tx,
+                id,
+                &current.content_json,
+                current.raw_markdown.as_deref(),
+                &current_hash,
+                change_summary,
+                current_updated_at,
+            )
+            .await?;
+        }
+    }
+
     // Block synchronization, link and reference handling if content_json is updated
+    let mut link_targets_changed = Vec::new();
     if let Some(new_content_json) = &content_json {
         // 1. Extract blocks, links, and references from the new content
         let (parsed_links, parsed_block_refs, extracted_blocks) =
             extract_links_references_and_blocks(new_content_json, id);
 
         // --- Block Synchronization ---
-        // Get existing blocks for this page from the DB
-        let existing_db_blocks = block_handler::get_blocks_for_page(pool, id).await?;
-        let existing_db_block_ids: std::collections::HashSet<Uuid> =
-            existing_db_blocks.iter().map(|b| b.id).collect();
+        // Get existing blocks for this page from the DB, keyed by ID so each extracted block can
+        // be compared with its stored row
+        let existing_db_blocks: std::collections::HashMap<Uuid, block_handler::Block> =
+            block_handler::get_blocks_for_page(&mut *tx, id)
+                .await?
+                .into_iter()
+                .map(|b| (b.id, b))
+                .collect();
         let extracted_block_ids: std::collections::HashSet<Uuid> =
             extracted_blocks.iter().map(|eb| eb.id).collect();
+        let mut blocks_updated = 0;
 
-        // Blocks to Delete: in existing_db_block_ids but not in extracted_block_ids
-        for block_id_to_delete in existing_db_block_ids.difference(&extracted_block_ids) {
-            // Before deleting a block, ensure related entities like block_references are handled.
-            // Current link_handler::remove_all_block_references_from_referencing_page below
-            // will clear references *originating* from this page. If this block is referenced BY
-            // other pages, those references will remain (which might be desired, or might need cleanup).
-            // Also, if blocks are nested, deleting a parent might orphan children if not handled.
-            // For now, we proceed with direct deletion.
-            if let Err(e) = block_handler::delete_block(pool, *block_id_to_delete).await {
-                 eprintln!("Failed to delete block {}: {}", block_id_to_delete, e);
-                 // Decide if to continue or return error. For now, log and continue.
-            }
-        }
-
-        // Blocks to Add: in extracted_block_ids but not in existing_db_block_ids
-        for eb_to_add in extracted_blocks.iter().filter(|eb| !existing_db_block_ids.contains(&eb.id)) {
-            // The block_handler::create_block needs to accept the ID.
-            // This will be addressed in Step 3 of the subtask.
-            if let Err(e) = block_handler::create_block(
-                pool,
-                eb_to_add.id, // This is the ID from content_json
-                id,           // page_id
-                eb_to_add.parent_block_id,
-                eb_to_add.block_type.as_deref(),
-            )
-            .await {
-                eprintln!("Failed to create block {}: {}", eb_to_add.id, e);
-                // Decide if to continue or return error.
+        // Blocks to Delete: stored but no longer in the content. Not a recursive delete of
+        // the rows: the content decides. Children that were deleted with their parent are gone from it
+        // and deleted here too; children that survive (the parent was unindented away) get
+        // their new parent below. References made from this page are rebuilt after the block
+        // sync; references from other pages and audio timestamps on the blocks are kept, since
+        // the editor can undo the deletion, and show up in the vault health report until then.
+        let block_ids_to_delete: Vec<Uuid> = existing_db_blocks
+            .keys()
+            .filter(|block_id| !extracted_block_ids.contains(block_id))
+            .copied()
+            .collect();
+        let blocks_deleted = block_handler::delete_blocks(&mut *tx, &block_ids_to_delete).await?;
+
+        // Blocks to Add: in the content but not stored yet, inserted together after the loop
+        let mut new_blocks = Vec::new();
+        for eb in extracted_blocks.iter() {
+            match existing_db_blocks.get(&eb.id) {
+                None => new_blocks.push(block_handler::NewBlock {
+                    id: eb.id, // This is the ID from content_json
+                    page_id: id,
+                    parent_block_id: eb.parent_block_id,
+                    block_type: eb.block_type.clone(),
+                    order_index: eb.order_index,
+                    content_text: eb.content_text.clone(),
+                    task_state: eb.task_state.map(str::to_string),
+                }),
+                // Blocks to Update: present in both, but moved (e.g. indented under another
+                // bullet), reordered, retyped, edited or checked off. Unchanged blocks aren't written.
+                Some(existing) => {
+                    let parent_changed = existing.parent_block_id != eb.parent_block_id;
+                    let type_changed = existing.block_type != eb.block_type;
+                    let order_changed = existing.order_index != eb.order_index;
+                    let text_changed = existing.content_text != eb.content_text;
+                    let task_changed = existing.task_state.as_deref() != eb.task_state;
+                    if task_changed {
+                        block_handler::set_block_task_state(&mut *tx, eb.id, eb.task_state).await?;
+                    }
+                    if parent_changed || type_changed || order_changed || text_changed {
+                        block_handler::update_block(
+                            &mut *tx,
+                            eb.id,
+                            parent_changed.then_some(eb.parent_block_id),
+                            type_changed.then(|| eb.block_type.clone()),
+                            order_changed.then_some(eb.order_index),
+                            text_changed.then(|| eb.content_text.clone()),
+                        )
+                        .await?;
+                    }
+                    if parent_changed || type_changed || order_changed || text_changed || task_changed {
+                        blocks_updated += 1;
+                    }
+                }
             }
         }
-        // TODO: Handle Blocks to Update (if type or parent_id changes). For now, focusing on add/delete.
+        let blocks_added = block_handler::create_blocks(&mut *tx, &new_blocks).await?;
 
+        println!(
+            "Page {}: {} blocks added, {} updated, {} deleted",
+            id, blocks_added, blocks_updated, blocks_deleted
+        );
 
         // --- Link and Reference Processing (after block sync) ---
         // 2. Clear existing links/references for this page
-        link_handler::remove_all_page_links_from_source(pool, id).await?;
-        link_handler::remove_all_block_references_from_referencing_page(pool, id).await?;
-
-        // 3. Add new page links
-        for plink in parsed_links {
-            if let Some(target_id) = plink.target_id {
-                link_handler::add_page_link(pool, id, target_id).await?;
-            } else if let Some(target_title) = plink.target_title {
-                if let Some(target_page) = get_page_by_title(pool, &target_title).await? {
-                    link_handler::add_page_link(pool, id, target_page.id).await?;
-                } else {
-                    eprintln!("Broken link: Page with title '{}' not found.", target_title);
-                }
-            }
-        }
+        let old_link_counts = link_handler::remove_all_page_links_from_source(&mut *tx, id).await?;
+        link_handler::remove_all_unresolved_links_from_source(&mut *tx, id).await?;
+        link_handler::remove_all_block_references_from_referencing_page(&mut *tx, id).await?;
 
-        // 4. Add new block references
-        for bref in parsed_block_refs {
-            match block_handler::get_page_id_for_block(pool, bref.referenced_block_id).await? {
-                Some(referenced_page_id) => {
-                    link_handler::add_block_reference(
-                        pool,
-                        id, // referencing_page_id (current page)
-                        bref.referencing_block_id,
-                        referenced_page_id,
-                        bref.referenced_block_id,
-                    )
-                    .await?;
-                }
-                None => {
-                    // Log details about the broken reference
-                    eprintln!(
-                        "Skipping block reference from page {} block {} to non-existent block ID: {}",
-                        id, // source_page_id is the current page being updated
-                        bref.referencing_block_id,
-                        bref.referenced_block_id
-                    );
-                }
-            }
+        // 3. Add new page links, counting each occurrence, and the blocks each one was written
+        // in. Titles matching no page get a stub page or are kept as unresolved links.
+        let (links, unresolved_titles) = resolve_link_targets(&mut *tx, &parsed_links, create_stub_pages).await?;
+        let link_counts = link_handler::set_page_links(&mut *tx, id, &links).await?;
+        link_handler::add_unresolved_links(&mut *tx, id, &unresolved_titles).await?;
+
+        println!("Page {}: {} page links stored ({} occurrences)", id, link_counts.len(), links.len());
+        link_targets_changed = old_link_counts
+            .keys()
+            .chain(link_counts.keys())
+            .filter(|target_id| old_link_counts.get(*target_id) != link_counts.get(*target_id))
+            .copied()
+            .collect::<std::collections::HashSet<_>>()
+            .into_iter()
+            .collect();
+
+        // 4. Add new block references, skipping those to blocks that don't exist
+        let references: Vec<(Uuid, Uuid)> = parsed_block_refs
+            .iter()
+            .map(|bref| (bref.referencing_block_id, bref.referenced_block_id))
+            .collect();
+        let stored: std::collections::HashSet<Uuid> =
+            link_handler::add_block_references(&mut *tx, id, &references).await?.into_iter().collect();
+        for bref in parsed_block_refs.iter().filter(|bref| !stored.contains(&bref.referenced_block_id)) {
+            // Log details about the broken reference
+            eprintln!(
+                "Skipping block reference from page {} block {} to non-existent block ID: {}",
+                id, // source_page_id is the current page being updated
+                bref.referencing_block_id,
+                bref.referenced_block_id
+            );
         }
+
+        // 5. Sync tags typed as #hashtags; tags added by hand are kept
+        sync_hashtag_tags(&mut *tx, id, new_content_json).await?;
     }
 
-    // Build the query dynamically based on which fields are provided for the page itself update
-    let mut set_clauses = Vec::new();
-    let mut params_count = 1; // Start with $1 for id
+    let content = content_json.zip(new_content_hash);
+    let mut query = page_update_query(id, title, content, raw_markdown, content_changed, expected_updated_at);
+    let updated_at = query
+        .build_query_scalar::<DateTime<Utc>>()
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| DalError::Conflict(format!("Page {} was changed by another window", id)))?;
+    Ok(Some(PageUpdate {
+        updated_at,
+        changed: true,
+        link_targets_changed,
+    }))
+}
 
-    if title.is_some() {
-        params_count += 1;
-        set_clauses.push(format!("title = ${}", params_count));
+// The UPDATE that update_page_in runs for the fields that changed, returning the new
+// updated_at. content is the new content_json with its hash. Each field is pushed together with
+// its bound value, so placeholders and binds can't get out of step whichever combination is
+// given.
+fn page_update_query<'a>(
+    id: Uuid,
+    title: Option<&'a str>,
+    content: Option<(Value, String)>,
+    raw_markdown: Option<Option<&'a str>>,
+    content_changed: bool,
+    expected_updated_at: Option<DateTime<Utc>>,
+) -> sqlx::QueryBuilder<'a, sqlx::Postgres> {
+    let mut query = sqlx::QueryBuilder::<sqlx::Postgres>::new("UPDATE pages SET updated_at = now()");
+    if let Some(title) = title {
+        query.push(", title = ").push_bind(title);
     }
-    // Use the potentially modified content_json if it was part of the input
-    if content_json.is_some() {
-        params_count += 1;
-        set_clauses.push(format!("content_json = ${}", params_count));
+    if content_changed {
+        query.push(", content_updated_at = now()");
     }
-    if raw_markdown.is_some() {
-        params_count += 1;
-        set_clauses.push(format!("raw_markdown = ${}", params_count));
+    if let Some((content_json, content_hash)) = content {
+        query.push(", content_json = ").push_bind(content_json);
+        query.push(", content_hash = ").push_bind(content_hash);
     }
+    if let Some(raw_markdown) = raw_markdown {
+        query.push(", raw_markdown = ").push_bind(raw_markdown); // None clears the column
+    }
+    query.push(" WHERE id = ").push_bind(id);
+    if let Some(expected_updated_at) = expected_updated_at {
+        query.push(" AND updated_at = ").push_bind(expected_updated_at);
+    }
+    query.push(" RETURNING updated_at");
+    query
+}
 
-    if set_clauses.is_empty() && content_json.is_none() { // if only content_json was updated, set_clauses might be empty
-        // No actual page table fields to update, but links might have been.
-        // If content_json was also none, then truly nothing to do.
-        if content_json.is_none() { return Ok(false); }
-        // If content_json was Some, link updates happened, but page table itself might not need an update
-        // unless we want to bump updated_at. Let's assume for now link updates don't bump page updated_at
-        // unless content_json field itself changes.
-        // However, the current logic below will add updated_at = now() if any other field changes.
-        // To ensure updated_at is bumped if content_json is updated (even if other fields are not):
-        if content_json.is_some() && set_clauses.is_empty() {
-             // This case means only content_json was provided, and it was processed for links.
-             // We still need to update the actual content_json in the DB and updated_at.
-             // The existing logic for adding content_json to set_clauses handles this.
-             // So, if set_clauses is empty here, it means title, raw_markdown were None,
-             // and content_json was also None (already checked by outer if).
-             // This part of the logic seems a bit convoluted now. Let's simplify.
+// Resolves the targets of parsed links. Each title is resolved once. Titles matching no page get
+// a stub page when asked for (one per title, ignoring case), and are otherwise returned as
+// unresolved. Links holding a UUID are ID links (target_id) and never get a stub. Returns each
+// resolved occurrence as (target, block it was written in), and the unresolved titles.
+async fn resolve_link_targets(
+    tx: &mut PgConnection,
+    parsed_links: &[ParsedPageLink],
+    create_stub_pages: bool,
+) -> Result<(Vec<(Uuid, Option<Uuid>)>, Vec<String>), DalError> {
+    let titles: Vec<String> = parsed_links
+        .iter()
+        .filter_map(|plink| plink.target_title.clone())
+        .filter(|title| !title.is_empty())
+        .collect::<std::collections::HashSet<_>>()
+        .into_iter()
+        .collect();
+    let mut resolved = resolve_page_titles(&mut *tx, &titles).await?;
+    let mut unresolved_titles = Vec::new();
+    let mut stub_ids: std::collections::HashMap<String, Uuid> = std::collections::HashMap::new();
+    let missing_titles: Vec<String> = titles.into_iter().filter(|title| !resolved.contains_key(title)).collect();
+    for title in missing_titles {
+        if create_stub_pages {
+            let stub_id = match stub_ids.get(&title.to_lowercase()) {
+                Some(stub_id) => *stub_id,
+                None => create_stub_page(&mut *tx, &title).await?,
+            };
+            stub_ids.insert(title.to_lowercase(), stub_id);
+            resolved.insert(title, stub_id);
+        } else {
+            unresolved_titles.push(title);
         }
     }
 
-    // If no fields to update for the page table itself, and content_json was not updated (already handled above)
-    // then we can return.
-    // However, if content_json was updated, link processing happened.
-    // The page table update must proceed if content_json (the field) is being set.
-    if set_clauses.is_empty() {
-         // This means title, content_json (as a field to set), and raw_markdown were all None.
-         // Link processing for a new content_json would have been handled by the `if let Some(new_content_json) = &content_json` block.
-         // If content_json was Some, then set_clauses would not be empty.
-         // Therefore, if set_clauses is empty here, it means no page fields need updating.
-        return Ok(true); // Assuming link updates were successful if they happened. Or return based on link update results.
-                         // For now, let's say if link updates happened, they succeeded or logged errors.
-                         // The function should ideally return based on whether the page update SQL runs.
+    let links: Vec<(Uuid, Option<Uuid>)> = parsed_links
+        .iter()
+        .filter_map(|plink| {
+            let target_id = plink
+                .target_id
+                .or_else(|| plink.target_title.as_ref().and_then(|title| resolved.get(title).copied()))?;
+            Some((target_id, plink.referencing_block_id))
+        })
+        .collect();
+    Ok((links, unresolved_titles))
+}
+
+// Sets the page's tags typed as #hashtags in content_json; tags added by hand are kept
+async fn sync_hashtag_tags(tx: &mut PgConnection, page_id: Uuid, content_json: &Value) -> Result<(), DalError> {
+    let hashtags = tag_handler::extract_hashtags(content_json);
+    tag_handler::remove_content_tags_except(&mut *tx, page_id, &hashtags).await?;
+    for name in &hashtags {
+        let tag = tag_handler::get_or_create_tag(&mut *tx, name).await?;
+        tag_handler::add_content_tag(&mut *tx, page_id, tag.id).await?;
     }
+    tag_handler::delete_unused_tags(&mut *tx).await?;
+    Ok(())
+}
 
+// --- Block Edits ---
+// Saves of a single block, so an edit inside one block doesn't send and resync the whole page.
+// Each splices the block's node into the stored content_json, writes that block's row and
+// redoes the links and references written in it; those from the page's other blocks are left
+// as they are. Tags are resynced from the whole content, and raw_markdown, when the page has
+// one, is rendered again from it. No revision is kept: the next update_page keeps one. Pages
+// whose blocks have no rows yet (saved before blocks were stored) get update_page's full sync.
 
-    // Always update the updated_at timestamp if any page field is changing.
-    set_clauses.push(format!("updated_at = now()"));
+struct LockedContent {
+    content_json: Value,
+    raw_markdown: Option<String>,
+    updated_at: DateTime<Utc>,
+}
 
-    let query_str = format!(
-        "UPDATE pages SET {} WHERE id = $1 RETURNING id",
-        set_clauses.join(", ")
-    );
+// The page's content, with its row locked for the edit. None if there is no such page.
+async fn lock_page_content(tx: &mut PgConnection, page_id: Uuid) -> Result<Option<LockedContent>, DalError> {
+    let page = sqlx::query!(
+        r#"
+        SELECT content_json, raw_markdown, updated_at
+        FROM pages
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        page_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
 
-    let mut query = sqlx::query(&query_str);
-    query = query.bind(id);
+    Ok(page.map(|page| LockedContent {
+        content_json: page.content_json,
+        raw_markdown: page.raw_markdown,
+        updated_at: page.updated_at,
+    }))
+}
 
-    if let Some(t) = title {
-        query = query.bind(t);
+// A list item can only take the place of, or follow, a list item, and any other block only
+// another block that isn't one
+fn check_same_container(existing: &Value, node: &Value) -> Result<(), DalError> {
+    match (is_list_item(existing), is_list_item(node)) {
+        (true, false) => Err(DalError::Conflict("Only a list item can go in a list".to_string())),
+        (false, true) => Err(DalError::Conflict("A list item can only go in a list".to_string())),
+        _ => Ok(()),
     }
-    // Bind the original content_json Option here
-    if let Some(c) = &content_json { // content_json here is the Option passed to the function
-        query = query.bind(c);
+}
+
+// Redoes the links and references written in block_ids from the page's new content, resyncs
+// its tags and stores the content. The blocks' rows must be written (or still exist) by now.
+async fn finish_block_edit(
+    tx: &mut PgConnection,
+    page_id: Uuid,
+    page: LockedContent,
+    block_ids: &[Uuid],
+    create_stub_pages: bool,
+) -> Result<PageUpdate, DalError> {
+    let LockedContent { content_json, raw_markdown, .. } = page;
+    let (parsed_links, parsed_block_refs, _) = extract_links_references_and_blocks(&content_json, page_id);
+
+    let block_links: Vec<ParsedPageLink> = parsed_links
+        .iter()
+        .filter(|plink| plink.referencing_block_id.is_some_and(|block_id| block_ids.contains(&block_id)))
+        .cloned()
+        .collect();
+    let (links, unresolved_titles) = resolve_link_targets(&mut *tx, &block_links, create_stub_pages).await?;
+    let links: Vec<(Uuid, Uuid)> = links
+        .into_iter()
+        .filter_map(|(target_id, block_id)| Some((target_id, block_id?)))
+        .collect();
+    let link_counts = link_handler::set_block_links(&mut *tx, page_id, block_ids, &links).await?;
+
+    // Titles no longer written anywhere on the page stop being unresolved links
+    let page_titles: Vec<String> = parsed_links.iter().filter_map(|plink| plink.target_title.clone()).collect();
+    link_handler::remove_unresolved_links_except(&mut *tx, page_id, &page_titles).await?;
+    link_handler::add_unresolved_links(&mut *tx, page_id, &unresolved_titles).await?;
+
+    link_handler::remove_block_references_from_blocks(&mut *tx, block_ids).await?;
+    let references: Vec<(Uuid, Uuid)> = parsed_block_refs
+        .iter()
+        .filter(|bref| block_ids.contains(&bref.referencing_block_id))
+        .map(|bref| (bref.referencing_block_id, bref.referenced_block_id))
+        .collect();
+    link_handler::add_block_references(&mut *tx, page_id, &references).await?;
+
+    sync_hashtag_tags(&mut *tx, page_id, &content_json).await?;
+
+    let raw_markdown = raw_markdown.map(|_| render_markdown(&content_json));
+    let content_hash = json_utils::content_json_hash(&content_json);
+    let updated_at = sqlx::query_scalar!(
+        r#"
+        UPDATE pages
+        SET content_json = $2, content_hash = $3, raw_markdown = $4, updated_at = now(), content_updated_at = now()
+        WHERE id = $1
+        RETURNING updated_at
+        "#,
+        page_id,
+        content_json,
+        content_hash,
+        raw_markdown
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    Ok(PageUpdate {
+        updated_at,
+        changed: true,
+        link_targets_changed: link_counts.into_keys().collect(),
+    })
+}
+
+// Puts node (a single block whose uniqueID is block_id) in place of the block's node on the
+// page. Returns None if there is no such page, and DalError::NotFound if the block isn't in its
+// content. A node the same as the stored one writes nothing.
+pub async fn update_block_node(
+    pool: &PgPool,
+    page_id: Uuid,
+    block_id: Uuid,
+    node: Value,
+    create_stub_pages: bool,
+) -> Result<Option<PageUpdate>, DalError> {
+    let mut tx = pool.begin().await?;
+    let Some(mut page) = lock_page_content(&mut tx, page_id).await? else {
+        return Ok(None);
+    };
+
+    let existing = json_utils::find_block_node(&page.content_json, block_id).ok_or(DalError::NotFound)?;
+    check_same_container(existing, &node)?;
+    if *existing == node {
+        return Ok(Some(PageUpdate {
+            updated_at: page.updated_at,
+            changed: false,
+            link_targets_changed: Vec::new(),
+        }));
     }
-    if let Some(rm) = raw_markdown {
-        match rm {
-            Some(val) => query = query.bind(val),
-            None => query = query.bind(Option::<&str>::None),
+    json_utils::replace_block_node(&mut page.content_json, block_id, node);
+
+    let Some(row) = block_handler::get_block(&mut *tx, block_id).await?.filter(|row| row.page_id == page_id) else {
+        let update =
+            update_page_in(&mut tx, page_id, None, Some(page.content_json), None, None, create_stub_pages, None).await?;
+        tx.commit().await?;
+        return Ok(update);
+    };
+    let (_, _, extracted_blocks) = extract_links_references_and_blocks(&page.content_json, page_id);
+    if let Some(eb) = extracted_blocks.iter().find(|eb| eb.id == block_id) {
+        if row.task_state.as_deref() != eb.task_state {
+            block_handler::set_block_task_state(&mut *tx, block_id, eb.task_state).await?;
+        }
+        let type_changed = row.block_type != eb.block_type;
+        let text_changed = row.content_text != eb.content_text;
+        if type_changed || text_changed {
+            block_handler::update_block(
+                &mut *tx,
+                block_id,
+                None,
+                type_changed.then(|| eb.block_type.clone()),
+                None,
+                text_changed.then(|| eb.content_text.clone()),
+            )
+            .await?;
         }
     }
 
-    let result = query.execute(pool).await?;
-    Ok(result.rows_affected() > 0)
+    let update = finish_block_edit(&mut tx, page_id, page, &[block_id], create_stub_pages).await?;
+    tx.commit().await?;
+    Ok(Some(update))
 }
 
+// Inserts node (a single block with a uniqueID of its own) after the anchor block, among its
+// siblings. Returns None if there is no such page, and DalError::NotFound if the anchor isn't
+// in its content.
+pub async fn insert_block_node(
+    pool: &PgPool,
+    page_id: Uuid,
+    anchor_block_id: Uuid,
+    node: Value,
+    create_stub_pages: bool,
+) -> Result<Option<PageUpdate>, DalError> {
+    let block_id = json_utils::unique_id(&node)
+        .ok_or_else(|| DalError::Internal("The block to insert has no uniqueID".to_string()))?;
+    let mut tx = pool.begin().await?;
+    let Some(mut page) = lock_page_content(&mut tx, page_id).await? else {
+        return Ok(None);
+    };
 
-// Placeholder for get_page_by_title - to be implemented as per Step 4
-pub async fn get_page_by_title(pool: &PgPool, title: &str) -> Result<Option<Page>, DalError> {
-    let page = sqlx::query_as!(
-        Page,
-        r#"
-        SELECT id, title, content_json, raw_markdown, created_at, updated_at
-        FROM pages
-        WHERE title = $1
-        "#,
-        title
-    )
-    .fetch_optional(pool)
-    .await
-    .map_err(DalError::from)?; // Convert sqlx::Error to DalError
+    let anchor = json_utils::find_block_node(&page.content_json, anchor_block_id).ok_or(DalError::NotFound)?;
+    check_same_container(anchor, &node)?;
+    if json_utils::find_block_node(&page.content_json, block_id).is_some()
+        || block_handler::get_block(&mut *tx, block_id).await?.is_some()
+    {
+        return Err(DalError::Conflict(format!("Block {} already exists", block_id)));
+    }
+    json_utils::insert_block_after(&mut page.content_json, anchor_block_id, node);
 
-    Ok(page)
+    let anchor_row = block_handler::get_block(&mut *tx, anchor_block_id).await?;
+    if anchor_row.is_none_or(|row| row.page_id != page_id) {
+        let update =
+            update_page_in(&mut tx, page_id, None, Some(page.content_json), None, None, create_stub_pages, None).await?;
+        tx.commit().await?;
+        return Ok(update);
+    }
+    let (_, _, extracted_blocks) = extract_links_references_and_blocks(&page.content_json, page_id);
+    if let Some(eb) = extracted_blocks.into_iter().find(|eb| eb.id == block_id) {
+        block_handler::shift_sibling_order(&mut *tx, page_id, eb.parent_block_id, eb.order_index, 1).await?;
+        let new_block = block_handler::NewBlock {
+            id: eb.id,
+            page_id,
+            parent_block_id: eb.parent_block_id,
+            block_type: eb.block_type,
+            order_index: eb.order_index,
+            content_text: eb.content_text,
+            task_state: eb.task_state.map(str::to_string),
+        };
+        block_handler::create_blocks(&mut *tx, &[new_block]).await?;
+    }
+
+    let update = finish_block_edit(&mut tx, page_id, page, &[block_id], create_stub_pages).await?;
+    tx.commit().await?;
+    Ok(Some(update))
 }
 
+// Removes the block's node from the page, with its nested list if it's a list item, and the
+// rows of every block in them. Returns None if there is no such page, and DalError::NotFound if
+// the block isn't in its content. As in update_page, references to the removed blocks from
+// other pages and their audio timestamps are kept.
+pub async fn delete_block_node(pool: &PgPool, page_id: Uuid, block_id: Uuid) -> Result<Option<PageUpdate>, DalError> {
+    let mut tx = pool.begin().await?;
+    let Some(mut page) = lock_page_content(&mut tx, page_id).await? else {
+        return Ok(None);
+    };
 
-// New private function to extract links and references
-fn extract_links_references_and_blocks(
-    content_json: &Value,
-    current_page_id: Uuid,
-) -> (Vec<ParsedPageLink>, Vec<ParsedBlockReference>, Vec<ExtractedBlockInfo>) {
-    let mut page_links = Vec::new();
-    let mut block_references = Vec::new();
-    let mut extracted_blocks = std::collections::HashSet::new(); // Use HashSet to store unique blocks
+    let removed = json_utils::remove_block_node(&mut page.content_json, block_id).ok_or(DalError::NotFound)?;
+    let removed_ids: Vec<Uuid> = removed.iter().flat_map(json_utils::collect_unique_ids).collect();
 
-    // Helper recursive function to traverse the JSON
-    fn traverse_json(
-        node: &Value,
-        current_parent_block_id: Option<Uuid>, // ID of the immediate parent Lexical node if it's a block
-        page_links: &mut Vec<ParsedPageLink>,
-        block_references: &mut Vec<ParsedBlockReference>,
-        extracted_blocks: &mut std::collections::HashSet<ExtractedBlockInfo>,
-        current_page_id: Uuid,
-    ) {
-        if let Some(obj) = node.as_object() {
-            let mut current_block_unique_id: Option<Uuid> = None;
-            let mut _current_block_type: Option<String> = None;
+    let Some(row) = block_handler::get_block(&mut *tx, block_id).await?.filter(|row| row.page_id == page_id) else {
+        let update = update_page_in(&mut tx, page_id, None, Some(page.content_json), None, None, false, None).await?;
+        tx.commit().await?;
+        return Ok(update);
+    };
 
-            if let Some(id_str) = obj.get("uniqueID").and_then(|v| v.as_str()) {
-                if let Ok(id) = Uuid::parse_str(id_str) {
-                    current_block_unique_id = Some(id);
-                    _current_block_type = obj.get("type").and_then(|v| v.as_str()).map(String::from);
+    // The links written in the removed blocks are counted off before their rows (and so their
+    // page_link_blocks rows) go
+    let update = finish_block_edit(&mut tx, page_id, page, &removed_ids, false).await?;
+    block_handler::delete_blocks(&mut *tx, &removed_ids).await?;
+    block_handler::shift_sibling_order(&mut *tx, page_id, row.parent_block_id, row.order_index + 1, -1).await?;
+    tx.commit().await?;
+    Ok(Some(update))
+}
 
-                    extracted_blocks.insert(ExtractedBlockInfo {
-                        id,
-                        block_type: _current_block_type.clone(),
-                        parent_block_id: current_parent_block_id,
-                    });
-                }
-            }
+// Deletes a block found by its ID alone: its node leaves the page content, with its nested list
+// if it's a list item, and the page is resynced from it, so their rows go too. Unlike
+// delete_block_node, the references made from inside the removed blocks and their audio
+// timestamps go with them. Without with_nested, a list item with a nested list is refused with
+// DalError::Conflict rather than taking the list along. Returns None if there is no such block;
+// DalError::NotFound if it isn't in its page's content.
+pub async fn delete_block_tree(
+    pool: &PgPool,
+    block_id: Uuid,
+    with_nested: bool,
+) -> Result<Option<(block_handler::DeletedBlocks, PageUpdate)>, DalError> {
+    let mut tx = pool.begin().await?;
+    let Some(page_id) = block_handler::get_page_id_for_block(&mut *tx, block_id).await? else {
+        return Ok(None);
+    };
+    let Some(mut page) = lock_page_content(&mut tx, page_id).await? else {
+        return Ok(None);
+    };
 
-            // Determine the parent_id for children of this node.
-            // If this node is a block (has uniqueID), it's the parent for its direct children.
-            // Otherwise, children inherit the parent_id from this node's level.
-            let parent_id_for_children = current_block_unique_id.or(current_parent_block_id);
+    let removed = json_utils::remove_block_node(&mut page.content_json, block_id).ok_or(DalError::NotFound)?;
+    if !with_nested && removed.len() > 1 {
+        return Err(DalError::Conflict(format!(
+            "Block {} has nested blocks; delete it with them or move them first",
+            block_id
+        )));
+    }
+    let removed_ids: Vec<Uuid> = removed.iter().flat_map(json_utils::collect_unique_ids).collect();
 
-            if let Some(node_type_str) = obj.get("type").and_then(|v| v.as_str()) {
-                if node_type_str == "text" {
-                    if let Some(text_content) = obj.get("text").and_then(|v| v.as_str()) {
-                        // Page links
-                        for cap in PAGE_LINK_REGEX.captures_iter(text_content) {
-                            let content = cap[1].trim().to_string();
-                            if let Ok(target_uuid) = Uuid::parse_str(&content) {
-                                page_links.push(ParsedPageLink { target_id: Some(target_uuid), target_title: None });
-                            } else {
-                                page_links.push(ParsedPageLink { target_id: None, target_title: Some(content) });
-                            }
-                        }
+    let deleted = block_handler::clear_deleted_block_attachments(&mut tx, page_id, removed_ids).await?;
+    let update = update_page_in(&mut tx, page_id, None, Some(page.content_json), None, None, false, None)
+        .await?
+        .ok_or(DalError::NotFound)?;
+    tx.commit().await?;
+    Ok(Some((deleted, update)))
+}
 
-                        // Block references
-                        // The referencing_block_id is the parent block that contains this text node.
-                        if let Some(referencing_id) = parent_id_for_children { // Must be text within a block with uniqueID
-                            for cap in BLOCK_REF_REGEX.captures_iter(text_content) {
-                                if let Ok(referenced_b_id) = Uuid::parse_str(cap[1].trim()) {
-                                    block_references.push(ParsedBlockReference {
-                                        referencing_block_id: referencing_id,
-                                        referenced_block_id: referenced_b_id,
-                                    });
-                                }
-                            }
-                        }
-                    }
-                }
-                // Other node types (like "link" or custom elements) could also contain text or be blocks themselves.
-                // If a "link" node type also has a uniqueID, it would have been captured as a block above.
-                // Its children would then be processed.
-            }
+// Moves a block, with its nested list if it's a list item, under new_parent_id (None for the
+// top level of the page) at new_index among its new siblings, clamped to the end of them. The
+// node moves in content_json and the page is resynced from it, so its rows follow. Only a list
+// item can hold nested blocks, and only list items can go under one. At the top level a list
+// item moved next to a block that isn't one gets a list of its own, while any other block can't
+// go between list items. Returns the moved block's row and the page update, or None if there is
+// no such block; DalError::NotFound if the block or new parent isn't in its page's content.
+pub async fn move_block_node(
+    pool: &PgPool,
+    block_id: Uuid,
+    new_parent_id: Option<Uuid>,
+    new_index: i32,
+) -> Result<Option<(block_handler::Block, PageUpdate)>, DalError> {
+    let mut tx = pool.begin().await?;
+    let Some(page_id) = block_handler::get_page_id_for_block(&mut *tx, block_id).await? else {
+        return Ok(None);
+    };
+    let Some(mut page) = lock_page_content(&mut tx, page_id).await? else {
+        return Ok(None);
+    };
 
-            // Recursively traverse children, passing the determined parent_id_for_children
-            if let Some(children) = obj.get("children").and_then(|v| v.as_array()) {
-                for child in children {
-                    traverse_json(child, parent_id_for_children, page_links, block_references, extracted_blocks, current_page_id);
+    let moved = json_utils::remove_block_node(&mut page.content_json, block_id).ok_or(DalError::NotFound)?;
+    let moved_is_item = is_list_item(&moved[0]);
+    if let Some(parent_id) = new_parent_id {
+        // The new parent must be on the same page and must not be the block itself or one of
+        // its descendants, which would detach the subtree into a cycle
+        if moved.iter().flat_map(json_utils::collect_unique_ids).any(|id| id == parent_id) {
+            return Err(DalError::Conflict("Cannot move a block under itself or one of its children".to_string()));
+        }
+        let Some(parent) = json_utils::find_block_node(&page.content_json, parent_id) else {
+            return match block_handler::get_page_id_for_block(&mut *tx, parent_id).await? {
+                Some(_) => Err(DalError::Conflict("Cannot move a block under a block on another page".to_string())),
+                None => Err(DalError::NotFound),
+            };
+        };
+        if !is_list_item(parent) {
+            return Err(DalError::Conflict("Blocks can only be nested under a list item".to_string()));
+        }
+        if !moved_is_item {
+            return Err(DalError::Conflict("Only a list item can go in a list".to_string()));
+        }
+    }
+
+    // The new siblings in order, without the moved block
+    let (_, _, extracted_blocks) = extract_links_references_and_blocks(&page.content_json, page_id);
+    let mut siblings: Vec<_> = extracted_blocks.iter().filter(|eb| eb.parent_block_id == new_parent_id).collect();
+    siblings.sort_by_key(|eb| eb.order_index);
+    let target_index = (new_index.max(0) as usize).min(siblings.len());
+    let anchor = siblings.get(target_index).map(|eb| (eb.id, false)).or(siblings.last().map(|eb| (eb.id, true)));
+
+    let inserted = match (anchor, new_parent_id) {
+        (Some((anchor_id, after)), _) => {
+            let anchor_node = json_utils::find_block_node(&page.content_json, anchor_id).ok_or(DalError::NotFound)?;
+            match (is_list_item(anchor_node), moved_is_item) {
+                (true, false) => return Err(DalError::Conflict("Only a list item can go in a list".to_string())),
+                (false, true) => {
+                    let list = json_utils::list_node("bullet", 1, moved);
+                    json_utils::insert_blocks_next_to(&mut page.content_json, anchor_id, vec![list], after)
                 }
+                _ => json_utils::insert_blocks_next_to(&mut page.content_json, anchor_id, moved, after),
             }
-        } else if let Some(arr) = node.as_array() {
-            for item in arr {
-                traverse_json(item, current_parent_block_id, page_links, block_references, extracted_blocks, current_page_id);
+        }
+        (None, Some(parent_id)) => json_utils::append_nested_items(&mut page.content_json, parent_id, moved),
+        (None, None) => {
+            let nodes = if moved_is_item { vec![json_utils::list_node("bullet", 1, moved)] } else { moved };
+            let root = match page.content_json.get("root") {
+                Some(_) => &mut page.content_json["root"],
+                None => &mut page.content_json,
+            };
+            match root.get_mut("children").and_then(|children| children.as_array_mut()) {
+                Some(children) => {
+                    children.extend(nodes);
+                    true
+                }
+                None => false,
             }
         }
+    };
+    if !inserted {
+        return Err(DalError::Internal(format!("Failed to put block {} in its new place", block_id)));
     }
 
-    if let Some(root) = content_json.get("root") {
-        traverse_json(root, None, &mut page_links, &mut block_references, &mut extracted_blocks, current_page_id);
-    } else {
-        traverse_json(content_json, None, &mut page_links, &mut block_references, &mut extracted_blocks, current_page_id);
-    }
-
-    (page_links, block_references, extracted_blocks.into_iter().collect())
+    let update = update_page_in(&mut tx, page_id, None, Some(page.content_json), None, None, false, None)
+        .await?
+        .ok_or(DalError::NotFound)?;
+    let block = block_handler::get_block(&mut *tx, block_id).await?.ok_or(DalError::NotFound)?;
+    tx.commit().await?;
+    Ok(Some((block, update)))
 }
 
-
-pub async fn delete_page(pool: &PgPool, id: Uuid) -> Result<bool, DalError> {
-    let result = sqlx::query!(
+// The live page with this title, ignoring case; titles are unique among live pages
+pub async fn get_page_by_title<'e>(executor: impl PgExecutor<'e>, title: &str) -> Result<Option<Page>, DalError> {
+    let page = sqlx::query_as!(
+        Page,
         r#"
-        DELETE FROM pages
-        WHERE id = $1
+        SELECT id, title, content_json, raw_markdown, created_at, updated_at, deleted_at, is_template, is_favorite, favorite_order,
+               content_updated_at, page_tag_names(id) AS "tags!", page_alias_names(id) AS "aliases!"
+        FROM pages
+        WHERE lower(title) = lower($1) AND deleted_at IS NULL
         "#,
-        id
+        title
     )
-    .execute(pool)
-    .await?;
+    .fetch_optional(executor)
+    .await
+    .map_err(DalError::from)?; // Convert sqlx::Error to DalError
 
-    Ok(result.rows_affected() > 0)
+    Ok(page)
 }
 
-pub async fn search_pages(pool: &PgPool, query_term: &str) -> Result<Vec<Page>, DalError> {
-    let search_pattern = format!("%{}%", query_term);
+// The live pages [[links]] to these titles go to, keyed by title. A title goes to the page
+// titled exactly that, else one whose title matches ignoring case (the oldest when several
+// do), else the page with that alias. Titles that go nowhere are left out.
+pub async fn resolve_page_titles<'e>(
+    executor: impl PgExecutor<'e>,
+    titles: &[String],
+) -> Result<std::collections::HashMap<String, Uuid>, DalError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT t.title AS "title!", m.id
+        FROM unnest($1::text[]) AS t(title)
+        CROSS JOIN LATERAL (
+            SELECT c.id
+            FROM (
+                SELECT p.id, CASE WHEN p.title = t.title THEN 0 ELSE 1 END AS rank, p.created_at
+                FROM pages p
+                WHERE lower(p.title) = lower(t.title) AND p.deleted_at IS NULL
+                UNION ALL
+                SELECT p.id, 2 AS rank, p.created_at
+                FROM page_aliases a
+                JOIN pages p ON p.id = a.page_id
+                WHERE lower(a.alias) = lower(t.title) AND p.deleted_at IS NULL
+            ) c
+            ORDER BY c.rank, c.created_at, c.id
+            LIMIT 1
+        ) m
+        "#,
+        titles
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(rows.into_iter().filter_map(|row| Some((row.title, row.id?))).collect())
+}
+
+// A new stub page (no content) for a [[link]] to a title no page resolves to
+async fn create_stub_page<'e>(executor: impl PgExecutor<'e>, title: &str) -> Result<Uuid, DalError> {
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO pages (id, title, content_json, raw_markdown, created_at, updated_at)
+        VALUES ($1, $2, '{}'::jsonb, NULL, now(), now())
+        RETURNING id
+        "#,
+        Uuid::new_v4(),
+        title
+    )
+    .fetch_one(executor)
+    .await?;
+    println!("Created stub page '{}' ({}) for a link", title, id);
+    Ok(id)
+}
+
+// Full rows of every live page, for bulk operations such as syncing to the notes directory
+pub async fn get_all_pages(pool: &PgPool) -> Result<Vec<Page>, DalError> {
+    let pages = sqlx::query_as!(
+        Page,
+        r#"
+        SELECT id, title, content_json, raw_markdown, created_at, updated_at, deleted_at, is_template, is_favorite, favorite_order,
+               content_updated_at, page_tag_names(id) AS "tags!", page_alias_names(id) AS "aliases!"
+        FROM pages
+        WHERE deleted_at IS NULL
+        ORDER BY created_at ASC, id ASC
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(pages)
+}
+
+// Overrides a page's created_at, e.g. with the date from an imported note's front matter
+pub async fn set_page_created_at(pool: &PgPool, id: Uuid, created_at: DateTime<Utc>) -> Result<bool, DalError> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE pages
+        SET created_at = $2
+        WHERE id = $1
+        "#,
+        id,
+        created_at
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Titles of the `[[wiki links]]` in plain Markdown text, in order of first appearance.
+// Obsidian's `[[Title|alias]]` and `[[Title#Heading]]` forms resolve to "Title".
+pub fn extract_page_link_titles(text: &str) -> Vec<String> {
+    let mut titles: Vec<String> = Vec::new();
+    for cap in PAGE_LINK_REGEX.captures_iter(text) {
+        let target = cap[1].split(['|', '#']).next().unwrap_or_default().trim();
+        if !target.is_empty() && !titles.iter().any(|t| t == target) {
+            titles.push(target.to_string());
+        }
+    }
+    titles
+}
+
+// The `[[...]]` targets in editor text as update_page resolves them: whole, so a target may be
+// a title holding `|` or `#`, or a page ID
+pub(crate) fn page_link_targets(text: &str) -> Vec<String> {
+    PAGE_LINK_REGEX.captures_iter(text).map(|cap| cap[1].trim().to_string()).collect()
+}
+
+// Renames a page and rewrites `[[Old Title]]` occurrences in every page linking to it.
+// Returns the IDs of all pages that were modified (including the renamed page itself).
+pub async fn rename_page(pool: &PgPool, id: Uuid, new_title: &str) -> Result<Vec<Uuid>, DalError> {
+    let mut tx = pool.begin().await?;
+
+    let old_title = sqlx::query!(
+        r#"
+        SELECT title
+        FROM pages
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .map(|row| row.title)
+    .ok_or(DalError::NotFound)?;
+
+    if old_title == new_title {
+        return Ok(vec![id]);
+    }
+    ensure_title_free(&mut *tx, new_title, Some(id)).await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE pages
+        SET title = $2, updated_at = now()
+        WHERE id = $1
+        "#,
+        id,
+        new_title
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // Pages linking to this one (including self-links) may contain the old title in their text.
+    let linking_pages = sqlx::query!(
+        r#"
+        SELECT p.id, p.content_json, p.raw_markdown
+        FROM pages p
+        JOIN page_links l ON l.source_page_id = p.id
+        WHERE l.target_page_id = $1
+        FOR UPDATE OF p
+        "#,
+        id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut touched_page_ids = vec![id];
+    for page in linking_pages {
+        let mut content_json = page.content_json;
+        let json_changed = rewrite_page_link_titles_in_json(&mut content_json, &old_title, new_title);
+        let new_markdown = page
+            .raw_markdown
+            .as_deref()
+            .and_then(|md| rewrite_page_link_titles(md, &old_title, new_title));
+
+        if !json_changed && new_markdown.is_none() {
+            continue;
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE pages
+            SET content_json = $2, raw_markdown = COALESCE($3, raw_markdown), content_hash = NULL,
+                updated_at = now(), content_updated_at = now()
+            WHERE id = $1
+            "#,
+            page.id,
+            content_json,
+            new_markdown
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if !touched_page_ids.contains(&page.id) {
+            touched_page_ids.push(page.id);
+        }
+    }
+
+    tx.commit().await?;
+    Ok(touched_page_ids)
+}
+
+// Copies a page under a new title and returns the copy's ID. Every block gets a fresh ID, since
+// block IDs are global and syncing the copy's blocks would otherwise move them off the original.
+// Block references between blocks of the copied page follow their new IDs; references to blocks
+// on other pages are kept. Backlinks and audio recordings stay with the original.
+pub async fn duplicate_page(pool: &PgPool, id: Uuid, new_title: &str) -> Result<Uuid, DalError> {
+    let original = get_page(pool, id).await?;
+    ensure_title_free(pool, new_title, None).await?;
+
+    let mut id_map = std::collections::HashMap::new();
+    collect_block_ids(&original.content_json, &mut id_map);
+    let mut content_json = original.content_json;
+    remap_block_ids_in_json(&mut content_json, &id_map);
+    let raw_markdown = original
+        .raw_markdown
+        .map(|md| remap_block_refs(&md, &id_map).unwrap_or(md));
+
+    let (new_id, _) = create_page_with_content(pool, new_title, content_json, raw_markdown.as_deref(), false).await?;
+    Ok(new_id)
+}
+
+// Creates a page from existing content_json, including its blocks and links. The row is
+// inserted with its content but no content_hash, so the update_page that follows does the block
+// and link sync without keeping a revision; if that fails the page is removed.
+pub(crate) async fn create_page_with_content(
+    pool: &PgPool,
+    title: &str,
+    content_json: Value,
+    raw_markdown: Option<&str>,
+    create_stub_pages: bool,
+) -> Result<(Uuid, PageUpdate), DalError> {
+    let new_id = create_page(pool, title, content_json.clone(), raw_markdown).await?;
+    let update = match update_page(
+        pool,
+        new_id,
+        None,
+        Some(content_json),
+        raw_markdown.map(Some),
+        None,
+        create_stub_pages,
+        None,
+    )
+    .await
+    {
+        Ok(Some(update)) => update,
+        result => {
+            if let Err(cleanup_err) = purge_page(pool, new_id).await {
+                eprintln!("Failed to remove partially created page {}: {}", new_id, cleanup_err);
+            }
+            return Err(result.err().unwrap_or(DalError::NotFound));
+        }
+    };
+
+    Ok((new_id, update))
+}
+
+// --- Templates ---
+
+pub async fn list_templates(pool: &PgPool) -> Result<Vec<PageMetadata>, DalError> {
+    let templates = sqlx::query_as!(
+        PageMetadata,
+        r#"
+        SELECT id, title, created_at, updated_at, deleted_at, is_favorite, favorite_order,
+               content_updated_at, page_tag_names(id) AS "tags!"
+        FROM pages
+        WHERE is_template AND deleted_at IS NULL
+        ORDER BY title
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(templates)
+}
+
+pub async fn set_page_is_template(pool: &PgPool, id: Uuid, is_template: bool) -> Result<bool, DalError> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE pages
+        SET is_template = $2, updated_at = now()
+        WHERE id = $1
+        "#,
+        id,
+        is_template
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Creates a page from a template. The template's blocks get fresh IDs, and `{{name}}`
+// placeholders in its text nodes and raw_markdown are replaced from `variables`. Placeholders
+// without a value are left as they are. DalError::TitleTaken if a live page already has the title.
+pub async fn create_page_from_template(
+    pool: &PgPool,
+    template_id: Uuid,
+    title: &str,
+    variables: &std::collections::HashMap<String, String>,
+) -> Result<Uuid, DalError> {
+    let template = get_page(pool, template_id).await?;
+    if !template.is_template || template.deleted_at.is_some() {
+        return Err(DalError::NotFound);
+    }
+    ensure_title_free(pool, title, None).await?;
+
+    let mut id_map = std::collections::HashMap::new();
+    collect_block_ids(&template.content_json, &mut id_map);
+    let mut content_json = template.content_json;
+    remap_block_ids_in_json(&mut content_json, &id_map);
+    substitute_placeholders_in_json(&mut content_json, variables);
+    let raw_markdown = template.raw_markdown.map(|md| {
+        let md = remap_block_refs(&md, &id_map).unwrap_or(md);
+        substitute_placeholders(&md, variables).unwrap_or(md)
+    });
+
+    let (new_id, _) = create_page_with_content(pool, title, content_json, raw_markdown.as_deref(), false).await?;
+    Ok(new_id)
+}
+
+// Replaces `{{name}}` with the value of `name`. Returns None if nothing was replaced.
+fn substitute_placeholders(text: &str, variables: &std::collections::HashMap<String, String>) -> Option<String> {
+    let mut changed = false;
+    let substituted = TEMPLATE_PLACEHOLDER_REGEX.replace_all(text, |caps: &regex::Captures| {
+        match variables.get(&caps[1]) {
+            Some(value) => {
+                changed = true;
+                value.clone()
+            }
+            None => caps[0].to_string(),
+        }
+    });
+
+    if changed {
+        Some(substituted.into_owned())
+    } else {
+        None
+    }
+}
+
+// Applies substitute_placeholders to every text node in a content_json tree
+fn substitute_placeholders_in_json(node: &mut Value, variables: &std::collections::HashMap<String, String>) {
+    match node {
+        Value::Object(obj) => {
+            if obj.get("type").and_then(|v| v.as_str()) == Some("text") {
+                let substituted = obj
+                    .get("text")
+                    .and_then(|v| v.as_str())
+                    .and_then(|text| substitute_placeholders(text, variables));
+                if let Some(text) = substituted {
+                    obj.insert("text".to_string(), Value::String(text));
+                }
+            }
+            for value in obj.values_mut() {
+                if value.is_object() || value.is_array() {
+                    substitute_placeholders_in_json(value, variables);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items.iter_mut() {
+                substitute_placeholders_in_json(item, variables);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Assigns a fresh ID to every block (node with a uniqueID) in a content_json tree
+fn collect_block_ids(node: &Value, id_map: &mut std::collections::HashMap<Uuid, Uuid>) {
+    match node {
+        Value::Object(obj) => {
+            if let Some(id) = obj.get("uniqueID").and_then(|v| v.as_str()).and_then(|s| Uuid::parse_str(s).ok()) {
+                id_map.entry(id).or_insert_with(Uuid::new_v4);
+            }
+            for value in obj.values() {
+                collect_block_ids(value, id_map);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                collect_block_ids(item, id_map);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Rewrites uniqueIDs and `(((block)))` references in text nodes according to id_map
+fn remap_block_ids_in_json(node: &mut Value, id_map: &std::collections::HashMap<Uuid, Uuid>) {
+    match node {
+        Value::Object(obj) => {
+            let new_id = obj
+                .get("uniqueID")
+                .and_then(|v| v.as_str())
+                .and_then(|s| Uuid::parse_str(s).ok())
+                .and_then(|id| id_map.get(&id));
+            if let Some(new_id) = new_id {
+                obj.insert("uniqueID".to_string(), Value::String(new_id.to_string()));
+            }
+            if obj.get("type").and_then(|v| v.as_str()) == Some("text") {
+                let rewritten = obj
+                    .get("text")
+                    .and_then(|v| v.as_str())
+                    .and_then(|text| remap_block_refs(text, id_map));
+                if let Some(text) = rewritten {
+                    obj.insert("text".to_string(), Value::String(text));
+                }
+            }
+            for value in obj.values_mut() {
+                if value.is_object() || value.is_array() {
+                    remap_block_ids_in_json(value, id_map);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items.iter_mut() {
+                remap_block_ids_in_json(item, id_map);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Rewrites only the `(((block)))` references in text nodes according to id_map, leaving
+// uniqueIDs alone. Returns whether anything changed.
+fn remap_block_refs_in_json(node: &mut Value, id_map: &std::collections::HashMap<Uuid, Uuid>) -> bool {
+    match node {
+        Value::Object(obj) => {
+            let mut changed = false;
+            if obj.get("type").and_then(|v| v.as_str()) == Some("text") {
+                let rewritten = obj
+                    .get("text")
+                    .and_then(|v| v.as_str())
+                    .and_then(|text| remap_block_refs(text, id_map));
+                if let Some(text) = rewritten {
+                    obj.insert("text".to_string(), Value::String(text));
+                    changed = true;
+                }
+            }
+            for value in obj.values_mut() {
+                if value.is_object() || value.is_array() {
+                    changed |= remap_block_refs_in_json(value, id_map);
+                }
+            }
+            changed
+        }
+        Value::Array(items) => {
+            let mut changed = false;
+            for item in items.iter_mut() {
+                changed |= remap_block_refs_in_json(item, id_map);
+            }
+            changed
+        }
+        _ => false,
+    }
+}
+
+// Replaces `(((old_id)))` with `(((new_id)))` for IDs in id_map. Returns None if nothing matched.
+fn remap_block_refs(text: &str, id_map: &std::collections::HashMap<Uuid, Uuid>) -> Option<String> {
+    let mut changed = false;
+    let rewritten = BLOCK_REF_REGEX.replace_all(text, |caps: &regex::Captures| {
+        match Uuid::parse_str(caps[1].trim()).ok().and_then(|id| id_map.get(&id)) {
+            Some(new_id) => {
+                changed = true;
+                format!("((({})))", new_id)
+            }
+            None => caps[0].to_string(),
+        }
+    });
+
+    if changed {
+        Some(rewritten.into_owned())
+    } else {
+        None
+    }
+}
+
+// Replaces `[[old_title]]` with `[[new_title]]` in a piece of text. Links match ignoring case, as
+// resolve_page_titles resolves them. Returns None if nothing matched.
+fn rewrite_page_link_titles(text: &str, old_title: &str, new_title: &str) -> Option<String> {
+    let old_title = old_title.to_lowercase();
+    let mut changed = false;
+    let rewritten = PAGE_LINK_REGEX.replace_all(text, |caps: &regex::Captures| {
+        if caps[1].trim().to_lowercase() == old_title {
+            changed = true;
+            format!("[[{}]]", new_title)
+        } else {
+            caps[0].to_string()
+        }
+    });
+
+    if changed {
+        Some(rewritten.into_owned())
+    } else {
+        None
+    }
+}
+
+// Applies rewrite_page_link_titles to every text node in a Lexical content_json tree.
+fn rewrite_page_link_titles_in_json(node: &mut Value, old_title: &str, new_title: &str) -> bool {
+    match node {
+        Value::Object(obj) => {
+            let mut changed = false;
+            if obj.get("type").and_then(|v| v.as_str()) == Some("text") {
+                let rewritten = obj
+                    .get("text")
+                    .and_then(|v| v.as_str())
+                    .and_then(|text| rewrite_page_link_titles(text, old_title, new_title));
+                if let Some(text) = rewritten {
+                    obj.insert("text".to_string(), Value::String(text));
+                    changed = true;
+                }
+            }
+            for value in obj.values_mut() {
+                if value.is_object() || value.is_array() {
+                    changed |= rewrite_page_link_titles_in_json(value, old_title, new_title);
+                }
+            }
+            changed
+        }
+        Value::Array(items) => {
+            let mut changed = false;
+            for item in items.iter_mut() {
+                changed |= rewrite_page_link_titles_in_json(item, old_title, new_title);
+            }
+            changed
+        }
+        _ => false,
+    }
+}
+
+
+// --- Daily notes ---
+
+#[derive(Debug, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DailyNoteDirection {
+    Previous,
+    Next,
+}
+
+// A daily note for the calendar. has_content is false while the note still holds nothing
+// beyond what it was created with.
+#[derive(Debug, serde::Serialize)]
+pub struct DailyNoteSummary {
+    pub date: chrono::NaiveDate,
+    pub page_id: Uuid,
+    pub updated_at: DateTime<Utc>,
+    pub has_content: bool,
+}
+
+// Content for a daily note created without a template: (content_json, raw_markdown)
+pub fn default_daily_content(date: &str) -> (Value, String) {
+    let content_json = serde_json::json!({
+        "type": "doc",
+        "content": [
+            { "type": "heading", "attrs": { "level": 1 }, "content": [{ "type": "text", "text": date }] },
+            { "type": "paragraph" } // Add an empty paragraph
+        ]
+    });
+    (content_json, format!("# {}\n\n", date))
+}
+
+// The nearest live daily note strictly before or after `date`, however many days away.
+// YYYY-MM-DD titles sort the same as their dates.
+pub async fn get_adjacent_daily_page(
+    pool: &PgPool,
+    date: chrono::NaiveDate,
+    direction: DailyNoteDirection,
+) -> Result<Option<PageMetadata>, DalError> {
+    let date = date.format("%Y-%m-%d").to_string();
+    let page = sqlx::query_as!(
+        PageMetadata,
+        r#"
+        SELECT id, title, created_at, updated_at, deleted_at, is_favorite, favorite_order,
+               content_updated_at, page_tag_names(id) AS "tags!"
+        FROM pages
+        WHERE deleted_at IS NULL AND title ~ $1
+          AND CASE WHEN $3 THEN title > $2 ELSE title < $2 END
+        ORDER BY CASE WHEN $3 THEN title END ASC, title DESC
+        LIMIT 1
+        "#,
+        link_handler::DAILY_NOTE_TITLE_PATTERN,
+        date,
+        direction == DailyNoteDirection::Next
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(page)
+}
+
+// Live daily notes dated from..=to, oldest first. A note has content once it differs from a
+// fresh one: either the daily note template (when template_id is set) filled in for its date,
+// or the default daily content. Block IDs and empty paragraphs don't count as differences.
+pub async fn list_daily_pages(
+    pool: &PgPool,
+    from: chrono::NaiveDate,
+    to: chrono::NaiveDate,
+    template_id: Option<Uuid>,
+) -> Result<Vec<DailyNoteSummary>, DalError> {
+    let template = match template_id {
+        Some(template_id) => match get_page(pool, template_id).await {
+            Ok(template) if template.is_template && template.deleted_at.is_none() => Some(template.content_json),
+            Ok(_) | Err(DalError::NotFound) => None,
+            Err(e) => return Err(e),
+        },
+        None => None,
+    };
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, title, content_json, updated_at
+        FROM pages
+        WHERE deleted_at IS NULL AND title ~ $1 AND title BETWEEN $2 AND $3
+        ORDER BY title
+        "#,
+        link_handler::DAILY_NOTE_TITLE_PATTERN,
+        from.format("%Y-%m-%d").to_string(),
+        to.format("%Y-%m-%d").to_string()
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut notes = Vec::with_capacity(rows.len());
+    for row in rows {
+        // The pattern lets through impossible dates like 2024-13-40
+        let Ok(date) = chrono::NaiveDate::parse_from_str(&row.title, "%Y-%m-%d") else {
+            continue;
+        };
+        let fingerprint = content_fingerprint(&row.content_json);
+        let mut fresh = vec![content_fingerprint(&default_daily_content(&row.title).0)];
+        if let Some(template) = &template {
+            let variables = std::collections::HashMap::from([
+                ("date".to_string(), row.title.clone()),
+                ("title".to_string(), row.title.clone()),
+            ]);
+            let mut content = template.clone();
+            substitute_placeholders_in_json(&mut content, &variables);
+            fresh.push(content_fingerprint(&content));
+        }
+        notes.push(DailyNoteSummary {
+            date,
+            page_id: row.id,
+            updated_at: row.updated_at,
+            has_content: !fresh.contains(&fingerprint),
+        });
+    }
+
+    Ok(notes)
+}
+
+// What a content_json tree holds, independent of the editor's JSON layout and block IDs:
+// the non-empty text of each block (however it's split into text nodes), checkbox states, and
+// nodes other than plain containers (images, embeds...), in document order. Works for both
+// editor content and the default daily content.
+fn content_fingerprint(content: &Value) -> Vec<String> {
+    const CONTAINERS: &[&str] = &["root", "doc", "paragraph", "heading", "list", "listitem", "quote", "linebreak"];
+
+    fn flush(text: &mut String, out: &mut Vec<String>) {
+        let trimmed = text.trim();
+        if !trimmed.is_empty() {
+            out.push(format!("text:{}", trimmed));
+        }
+        text.clear();
+    }
+
+    fn walk(node: &Value, text: &mut String, out: &mut Vec<String>) {
+        match node {
+            Value::Object(obj) => {
+                match obj.get("type").and_then(|v| v.as_str()) {
+                    Some("text") => {
+                        text.push_str(obj.get("text").and_then(|v| v.as_str()).unwrap_or_default());
+                        return;
+                    }
+                    Some(kind) => {
+                        flush(text, out); // A new node starts a new run of text
+                        if !CONTAINERS.contains(&kind) {
+                            out.push(format!("node:{}", kind));
+                        }
+                    }
+                    None => {}
+                }
+                if let Some(checked) = obj.get("checked").and_then(|v| v.as_bool()) {
+                    out.push(format!("checked:{}", checked));
+                }
+                obj.values().for_each(|value| walk(value, text, out));
+            }
+            Value::Array(items) => items.iter().for_each(|item| walk(item, text, out)),
+            _ => {}
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut text = String::new();
+    walk(content, &mut text, &mut out);
+    flush(&mut text, &mut out);
+    out
+}
+
+
+// --- Quick capture ---
+
+// Times append_block_to_page re-reads the page when it changes before the write goes through,
+// waiting a little longer after each conflict
+const APPEND_ATTEMPTS: u32 = 8;
+const APPEND_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(20);
+
+#[derive(Debug, serde::Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum AppendBlockType {
+    #[default]
+    Paragraph,
+    Bullet, // Joins the page's last list when it is a bullet list
+}
+
+// Adds text as a new block at the end of a page, with its raw_markdown, and syncs blocks and
+// links like an editor save. The write only goes through if the page hasn't changed since it
+// was read; otherwise the page is read again and the block re-added, so an edit saved in the
+// meantime is kept. Returns the new block's ID.
+pub async fn append_block_to_page(
+    pool: &PgPool,
+    page_id: Uuid,
+    text: &str,
+    block_type: AppendBlockType,
+) -> Result<Uuid, DalError> {
+    let block_id = Uuid::new_v4();
+    for attempt in 0..APPEND_ATTEMPTS {
+        if attempt > 0 {
+            // The random block ID spreads out callers that keep colliding with each other
+            let jitter = std::time::Duration::from_millis(u64::from(block_id.as_bytes()[0] % 20));
+            tokio::time::sleep(APPEND_RETRY_DELAY * attempt + jitter).await;
+        }
+        let page = get_page(pool, page_id).await?;
+        if page.deleted_at.is_some() {
+            return Err(DalError::Conflict(format!("Page {} is in the trash", page_id)));
+        }
+
+        let mut content_json = page.content_json;
+        let (node, joined_list) = append_block_node(&mut content_json, block_id, text, block_type);
+        let raw_markdown = match page.raw_markdown.as_deref().map(str::trim_end) {
+            Some(markdown) if !markdown.is_empty() => format!(
+                "{}{}{}",
+                markdown,
+                if joined_list { "\n" } else { "\n\n" },
+                render_block_lines(&node).join("\n")
+            ),
+            _ => render_markdown(&content_json),
+        };
+
+        match update_page(pool, page_id, None, Some(content_json), Some(Some(&raw_markdown)), Some(page.updated_at), false, None).await {
+            Ok(Some(_)) => return Ok(block_id),
+            Ok(None) => return Err(DalError::NotFound),
+            Err(DalError::Conflict(_)) => continue, // Saved by someone else since it was read
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(DalError::Conflict(format!(
+        "Page {} kept changing; the text wasn't added after {} attempts",
+        page_id, APPEND_ATTEMPTS
+    )))
+}
+
+// A Lexical text node; format is the editor's bit set (1 bold, 2 italic, 4 strikethrough, ...)
+pub(crate) fn text_node(text: &str, format: u64) -> Value {
+    serde_json::json!({
+        "detail": 0,
+        "format": format,
+        "mode": "normal",
+        "style": "",
+        "text": text,
+        "type": "text",
+        "version": 1
+    })
+}
+
+// Appends a paragraph or bullet list item holding text to the root of a Lexical tree. Content
+// in another format, which the editor can't load, is replaced by a fresh root. Returns the new
+// block's node and whether it went into an existing list.
+fn append_block_node(content: &mut Value, block_id: Uuid, text: &str, block_type: AppendBlockType) -> (Value, bool) {
+    if !content.pointer("/root/children").is_some_and(Value::is_array) {
+        *content = new_page_content(&Value::Null, Vec::new(), &[]);
+    }
+    let Some(Value::Array(children)) = content.pointer_mut("/root/children") else {
+        unreachable!("the root was just created");
+    };
+
+    // Lines become text nodes separated by line breaks
+    let mut inline = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        if index > 0 {
+            inline.push(serde_json::json!({ "type": "linebreak", "version": 1 }));
+        }
+        if !line.is_empty() {
+            inline.push(text_node(line, 0));
+        }
+    }
+
+    match block_type {
+        AppendBlockType::Paragraph => {
+            let node = serde_json::json!({
+                "children": inline,
+                "direction": "ltr",
+                "format": "",
+                "indent": 0,
+                "type": "paragraph",
+                "version": 1,
+                "uniqueID": block_id.to_string()
+            });
+            children.push(node.clone());
+            (node, false)
+        }
+        AppendBlockType::Bullet => {
+            let mut item = serde_json::json!({
+                "children": inline,
+                "direction": "ltr",
+                "format": "",
+                "indent": 0,
+                "type": "listitem",
+                "value": 1,
+                "version": 1,
+                "uniqueID": block_id.to_string()
+            });
+            let last_list = children.last_mut().filter(|last| {
+                last.get("type").and_then(|v| v.as_str()) == Some("list")
+                    && last.get("listType").and_then(|v| v.as_str()) == Some("bullet")
+            });
+            if let Some(Value::Array(items)) = last_list.and_then(|list| list.get_mut("children")) {
+                item["value"] = Value::from(items.len() + 1);
+                items.push(item.clone());
+                return (item, true);
+            }
+            children.push(serde_json::json!({
+                "children": [item.clone()],
+                "direction": "ltr",
+                "format": "",
+                "indent": 0,
+                "listType": "bullet",
+                "start": 1,
+                "tag": "ul",
+                "type": "list",
+                "version": 1
+            }));
+            (item, false)
+        }
+    }
+}
+
+
+// --- Extracting blocks ---
+
+// Moves a block, with everything nested under it, to a new page titled `title` and leaves a
+// `[[title]]` link in its place. Block IDs are kept so `(((references)))` to the moved blocks
+// keep resolving; their block_references rows now point at the new page. Either everything
+// changes or nothing does. Returns (new page ID, source page ID).
+pub async fn extract_block_to_page(pool: &PgPool, block_id: Uuid, title: &str) -> Result<(Uuid, Uuid), DalError> {
+    let mut tx = pool.begin().await?;
+
+    let source_id = block_handler::get_page_id_for_block(&mut *tx, block_id)
+        .await?
+        .ok_or(DalError::NotFound)?;
+    let source = sqlx::query!(
+        r#"
+        SELECT content_json, raw_markdown, deleted_at
+        FROM pages
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        source_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if source.deleted_at.is_some() {
+        return Err(DalError::Conflict("The block's page is in the trash".to_string()));
+    }
+    ensure_title_free(&mut *tx, title, None).await?;
+
+    let mut source_content = source.content_json;
+    let extracted = take_block_node(&mut source_content, block_id, title).ok_or_else(|| {
+        DalError::Conflict(format!("Block {} isn't in its page's content; save the page and try again", block_id))
+    })?;
+    let mut moved_block_ids = std::collections::HashMap::new();
+    for node in &extracted.nodes {
+        collect_block_ids(node, &mut moved_block_ids);
+    }
+    let moved_block_ids: Vec<Uuid> = moved_block_ids.into_keys().collect();
+
+    let new_content = new_page_content(&source_content, extracted.nodes.clone(), &extracted.wrappers);
+    let new_markdown = render_markdown(&new_content);
+    let source_markdown = source.raw_markdown.map(|md| {
+        replace_block_in_markdown(&md, &extracted.nodes[0], title).unwrap_or_else(|| render_markdown(&source_content))
+    });
+
+    // The moved blocks are handed to the new page before either page is synced, so the source
+    // sync doesn't delete them and references to them (from the source page too) resolve to
+    // the new page. The new page must exist first, which also lets the `[[title]]` link resolve.
+    let new_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO pages (id, title, content_json, raw_markdown, created_at, updated_at)
+        VALUES ($1, $2, '{}'::jsonb, $3, now(), now())
+        "#,
+        new_id,
+        title,
+        new_markdown
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE blocks
+        SET page_id = $2, updated_at = now()
+        WHERE id = ANY($1) AND page_id = $3
+        "#,
+        &moved_block_ids,
+        new_id,
+        source_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    update_page_in(&mut tx, source_id, None, Some(source_content), source_markdown.as_deref().map(Some), None, false, None).await?;
+    update_page_in(&mut tx, new_id, None, Some(new_content), None, None, false, None).await?;
+
+    // References from other pages aren't rebuilt by either sync
+    sqlx::query!(
+        r#"
+        UPDATE block_references
+        SET referenced_page_id = $2
+        WHERE referenced_block_id = ANY($1)
+        "#,
+        &moved_block_ids,
+        new_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok((new_id, source_id))
+}
+
+// A block cut out of a content_json tree
+struct ExtractedNode {
+    nodes: Vec<Value>, // The block's node, then the list item holding its nested list if it has one
+    wrappers: Vec<Value>, // Container nodes it sat in (e.g. its list), outermost first, without children
+}
+
+// Replaces the node with uniqueID block_id by a node of the same kind holding a `[[title]]`
+// link, and returns the original. Lexical keeps a list item's nested list in the next list
+// item, so that one is taken too. Returns None if there is no such node.
+fn take_block_node(content: &mut Value, block_id: Uuid, title: &str) -> Option<ExtractedNode> {
+    let path = find_block_path(content, &block_id.to_string())?;
+
+    // Containers between the nearest enclosing block (or the root) and the block, so the moved
+    // node keeps a valid parent on its new page: a list item needs its list
+    let mut wrappers = Vec::new();
+    for depth in 0..path.len() {
+        let Some(ancestor) = content.pointer(&json_pointer(&path[..depth])).and_then(|v| v.as_object()) else {
+            continue;
+        };
+        if ancestor.contains_key("uniqueID") {
+            wrappers.clear();
+        } else if ancestor.get("type").and_then(|v| v.as_str()).is_some_and(|kind| kind != "root") {
+            let mut wrapper = ancestor.clone();
+            wrapper.insert("children".to_string(), Value::Array(Vec::new()));
+            wrappers.push(Value::Object(wrapper));
+        }
+    }
+
+    let slot = content.pointer_mut(&json_pointer(&path))?;
+    let mut placeholder = slot.as_object()?.clone();
+    placeholder.insert("uniqueID".to_string(), Value::String(Uuid::new_v4().to_string()));
+    placeholder.insert(
+        "children".to_string(),
+        serde_json::json!([{
+            "detail": 0,
+            "format": 0,
+            "mode": "normal",
+            "style": "",
+            "text": format!("[[{}]]", title),
+            "type": "text",
+            "version": 1
+        }]),
+    );
+    let node = std::mem::replace(slot, Value::Object(placeholder));
+
+    let mut nodes = vec![node];
+    if is_list_item(&nodes[0]) {
+        let (index, parent_path) = path.split_last()?;
+        let index: usize = index.parse().ok()?;
+        if let Some(Value::Array(siblings)) = content.pointer_mut(&json_pointer(parent_path)) {
+            if siblings.get(index + 1).is_some_and(is_nested_list_holder) {
+                nodes.push(siblings.remove(index + 1));
+            }
+        }
+    }
+    Some(ExtractedNode { nodes, wrappers })
+}
+
+// The node with uniqueID block_id and, for a list item, the next list item when it holds the
+// block's nested list. None if there is no such node.
+pub fn block_subtree(content: &Value, block_id: Uuid) -> Option<Vec<Value>> {
+    let path = find_block_path(content, &block_id.to_string())?;
+    let mut nodes = vec![content.pointer(&json_pointer(&path))?.clone()];
+    if is_list_item(&nodes[0]) {
+        let (index, parent_path) = path.split_last()?;
+        let index: usize = index.parse().ok()?;
+        if let Some(next) = content.pointer(&json_pointer(parent_path)).and_then(|siblings| siblings.get(index + 1)) {
+            if is_nested_list_holder(next) {
+                nodes.push(next.clone());
+            }
+        }
+    }
+    Some(nodes)
+}
+
+// Content for a page holding just the extracted nodes, in their containers, under a root like
+// the source page's
+pub(crate) fn new_page_content(source_content: &Value, nodes: Vec<Value>, wrappers: &[Value]) -> Value {
+    let mut top = nodes;
+    for wrapper in wrappers.iter().rev() {
+        let mut wrapper = wrapper.clone();
+        wrapper["children"] = Value::Array(top);
+        top = vec![wrapper];
+    }
+
+    match source_content.get("root").and_then(|root| root.as_object()) {
+        Some(root) => {
+            let mut root = root.clone();
+            root.insert("children".to_string(), Value::Array(top));
+            let mut content = source_content.clone();
+            content["root"] = Value::Object(root);
+            content
+        }
+        None => serde_json::json!({
+            "root": { "children": top, "direction": null, "format": "", "indent": 0, "type": "root", "version": 1 }
+        }),
+    }
+}
+
+// Replaces the lines of raw_markdown that hold the extracted block (found by its first
+// line of text) with a `[[title]]` line using the same indentation and list or heading marker.
+// Nested list items below it go too. Returns None if the block can't be found.
+fn replace_block_in_markdown(markdown: &str, node: &Value, title: &str) -> Option<String> {
+    let rendered = render_block_lines(node).join("\n");
+    let rendered_lines: Vec<&str> = rendered.lines().collect();
+    let first_text = split_markdown_prefix(rendered_lines.first()?).1;
+    if first_text.trim().is_empty() {
+        return None;
+    }
+
+    let lines: Vec<&str> = markdown.lines().collect();
+    let start = lines.iter().position(|line| split_markdown_prefix(line).1.trim() == first_text.trim())?;
+    let (prefix, _) = split_markdown_prefix(lines[start]);
+    let indent = lines[start].len() - lines[start].trim_start().len();
+    let marker = prefix.trim_start();
+    let is_list_item = marker.starts_with(['-', '*', '+']) || marker.starts_with(|c: char| c.is_ascii_digit());
+
+    let mut end = start + 1;
+    if is_list_item {
+        let indent_of = |line: &str| line.len() - line.trim_start().len();
+        while end < lines.len() && !lines[end].trim().is_empty() && indent_of(lines[end]) > indent {
+            end += 1;
+        }
+    } else {
+        end = (start + rendered_lines.len()).min(lines.len());
+    }
+
+    let prefix = if prefix.trim_start().starts_with("```") { "" } else { prefix };
+    let mut replaced: Vec<String> = lines[..start].iter().map(|line| line.to_string()).collect();
+    replaced.push(format!("{}[[{}]]", prefix, title));
+    replaced.extend(lines[end..].iter().map(|line| line.to_string()));
+    let mut markdown_out = replaced.join("\n");
+    if markdown.ends_with('\n') {
+        markdown_out.push('\n');
+    }
+    Some(markdown_out)
+}
+
+// Splits a Markdown line into its leading indentation plus block marker (`- `, `1. `,
+// `- [ ] `, `## `, `> `) and the text after it
+fn split_markdown_prefix(line: &str) -> (&str, &str) {
+    let prefix_len = MARKDOWN_PREFIX_REGEX.find(line).map(|m| m.end()).unwrap_or(0);
+    line.split_at(prefix_len)
+}
+
+// New private function to extract links and references
+fn extract_links_references_and_blocks(
+    content_json: &Value,
+    current_page_id: Uuid,
+) -> (Vec<ParsedPageLink>, Vec<ParsedBlockReference>, Vec<ExtractedBlockInfo>) {
+    let mut page_links = Vec::new();
+    let mut block_references = Vec::new();
+    let mut extracted_blocks = std::collections::HashSet::new(); // Use HashSet to store unique blocks
+    let mut block_state = BlockTraversalState::default();
+
+    // Helper recursive function to traverse the JSON
+    fn traverse_json(
+        node: &Value,
+        current_parent_block_id: Option<Uuid>, // ID of the immediate parent Lexical node if it's a block
+        page_links: &mut Vec<ParsedPageLink>,
+        block_references: &mut Vec<ParsedBlockReference>,
+        extracted_blocks: &mut std::collections::HashSet<ExtractedBlockInfo>,
+        block_state: &mut BlockTraversalState,
+        current_page_id: Uuid,
+    ) {
+        if let Some(obj) = node.as_object() {
+            let mut current_block_unique_id: Option<Uuid> = None;
+            let mut _current_block_type: Option<String> = None;
+
+            if let Some(id) = json_utils::unique_id(node) {
+                current_block_unique_id = Some(id);
+                _current_block_type = obj.get("type").and_then(|v| v.as_str()).map(String::from);
+
+                let next_index = block_state.sibling_counts.entry(current_parent_block_id).or_insert(0);
+                extracted_blocks.insert(ExtractedBlockInfo {
+                    id,
+                    block_type: _current_block_type.clone(),
+                    parent_block_id: current_parent_block_id,
+                    order_index: *next_index,
+                    content_text: None, // Filled in once the whole tree has been traversed
+                    task_state: task_state(obj),
+                });
+                *next_index += 1;
+            }
+
+            // Determine the parent_id for children of this node.
+            // If this node is a block (has uniqueID), it's the parent for its direct children.
+            // Otherwise, children inherit the parent_id from this node's level.
+            let parent_id_for_children = current_block_unique_id.or(current_parent_block_id);
+
+            if let Some(node_type_str) = obj.get("type").and_then(|v| v.as_str()) {
+                if node_type_str == "linebreak" {
+                    if let Some(block_id) = parent_id_for_children {
+                        block_state.block_texts.entry(block_id).or_default().push(' ');
+                    }
+                }
+                if node_type_str == "text" {
+                    if let Some(text_content) = obj.get("text").and_then(|v| v.as_str()) {
+                        if let Some(block_id) = parent_id_for_children {
+                            block_state.block_texts.entry(block_id).or_default().push_str(text_content);
+                        }
+
+                        // Page links
+                        for cap in PAGE_LINK_REGEX.captures_iter(text_content) {
+                            let content = cap[1].trim().to_string();
+                            if let Ok(target_uuid) = Uuid::parse_str(&content) {
+                                page_links.push(ParsedPageLink { target_id: Some(target_uuid), target_title: None, referencing_block_id: parent_id_for_children });
+                            } else {
+                                page_links.push(ParsedPageLink { target_id: None, target_title: Some(content), referencing_block_id: parent_id_for_children });
+                            }
+                        }
+
+                        // Block references
+                        // The referencing_block_id is the parent block that contains this text node.
+                        if let Some(referencing_id) = parent_id_for_children { // Must be text within a block with uniqueID
+                            for cap in BLOCK_REF_REGEX.captures_iter(text_content) {
+                                if let Ok(referenced_b_id) = Uuid::parse_str(cap[1].trim()) {
+                                    block_references.push(ParsedBlockReference {
+                                        referencing_block_id: referencing_id,
+                                        referenced_block_id: referenced_b_id,
+                                    });
+                                }
+                            }
+                        }
+                    }
+                }
+                // Other node types (like "link" or custom elements) could also contain text or be blocks themselves.
+                // If a "link" node type also has a uniqueID, it would have been captured as a block above.
+                // Its children would then be processed.
+            }
+
+            // Recursively traverse children, passing the determined parent_id_for_children.
+            // Lexical keeps a nested list in the listitem after the item it belongs to, so
+            // the blocks in such a holder (when it isn't a block itself) are children of the
+            // previous sibling block.
+            if let Some(children) = obj.get("children").and_then(|v| v.as_array()) {
+                let mut previous_block_id: Option<Uuid> = None;
+                for child in children {
+                    let child_block_id = json_utils::unique_id(child);
+                    let parent_for_child = if child_block_id.is_none() && is_nested_list_holder(child) {
+                        previous_block_id.or(parent_id_for_children)
+                    } else {
+                        parent_id_for_children
+                    };
+                    traverse_json(child, parent_for_child, page_links, block_references, extracted_blocks, block_state, current_page_id);
+                    previous_block_id = child_block_id;
+                }
+            }
+        } else if let Some(arr) = node.as_array() {
+            for item in arr {
+                traverse_json(item, current_parent_block_id, page_links, block_references, extracted_blocks, block_state, current_page_id);
+            }
+        }
+    }
+
+    let root = json_utils::content_root(content_json);
+    traverse_json(root, None, &mut page_links, &mut block_references, &mut extracted_blocks, &mut block_state, current_page_id);
+
+    let blocks = extracted_blocks
+        .into_iter()
+        .map(|mut eb| {
+            eb.content_text = block_state.block_texts.get(&eb.id).and_then(|text| normalize_block_text(text));
+            eb
+        })
+        .collect();
+
+    (page_links, block_references, blocks)
+}
+
+// A checklist item (a list item with a `checked` flag, which only check lists have) or a todo
+// block is a task
+fn task_state(node: &serde_json::Map<String, Value>) -> Option<&'static str> {
+    let checked = node.get("checked").and_then(|v| v.as_bool());
+    match node.get("type").and_then(|v| v.as_str()) {
+        Some("listitem") if checked.is_some() => Some(if checked == Some(true) { "done" } else { "todo" }),
+        Some("todo") => Some(if checked == Some(true) { "done" } else { "todo" }),
+        _ => None,
+    }
+}
+
+// Collapses runs of whitespace and trims; whitespace-only text becomes None so it isn't indexed
+fn normalize_block_text(text: &str) -> Option<String> {
+    let normalized = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if normalized.is_empty() {
+        None
+    } else {
+        Some(normalized)
+    }
+}
+
+
+// --- Merging pages ---
+
+// What merge_pages changed
+#[derive(Debug)]
+pub struct PageMerge {
+    pub target: PageUpdate,
+    pub relinked_pages: Vec<Uuid>, // Pages whose [[links]] to the source now name the target
+    pub link_targets_changed: Vec<Uuid>, // Pages linked more or fewer times by any of the saves
+}
+
+// Merges the source page into the target, which is how duplicate pages are resolved. The
+// source's blocks are appended to the target's content with their IDs kept, so `(((references)))`
+// to them follow, and `[[links]]` to the source in other pages are rewritten to the target.
+// Link and reference rows, recordings, aliases and hand-added tags move to the target. The
+// source is left empty, its content kept as a revision, and moved to the trash. Either
+// everything changes or nothing does.
+pub async fn merge_pages(pool: &PgPool, source_id: Uuid, target_id: Uuid) -> Result<PageMerge, DalError> {
+    if source_id == target_id {
+        return Err(DalError::Conflict("A page can't be merged into itself".to_string()));
+    }
+    let mut tx = pool.begin().await?;
+
+    // Locked in ID order, so two merges of the same pages can't deadlock
+    let pages = sqlx::query!(
+        r#"
+        SELECT id, title, content_json, raw_markdown, deleted_at
+        FROM pages
+        WHERE id = ANY($1)
+        ORDER BY id
+        FOR UPDATE
+        "#,
+        &[source_id, target_id][..]
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let source = pages.iter().find(|page| page.id == source_id).ok_or(DalError::NotFound)?;
+    let target = pages.iter().find(|page| page.id == target_id).ok_or(DalError::NotFound)?;
+    if let Some(trashed) = [source, target].into_iter().find(|page| page.deleted_at.is_some()) {
+        return Err(DalError::Conflict(format!("Page {} is in the trash", trashed.id)));
+    }
+
+    let mut content = target.content_json.clone();
+    if !content.pointer("/root/children").is_some_and(Value::is_array) {
+        content = new_page_content(&Value::Null, Vec::new(), &[]);
+    }
+    let source_children = source
+        .content_json
+        .pointer("/root/children")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    if let Some(Value::Array(children)) = content.pointer_mut("/root/children") {
+        children.extend(source_children);
+    }
+    // Both pages' Markdown is kept as written when they have it
+    let raw_markdown = match (target.raw_markdown.as_deref(), source.raw_markdown.as_deref()) {
+        (Some(target_markdown), Some(source_markdown)) => [target_markdown.trim_end(), source_markdown.trim()]
+            .into_iter()
+            .filter(|markdown| !markdown.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        _ => render_markdown(&content),
+    };
+
+    // The blocks are handed to the target before either page is synced, so the source sync
+    // doesn't delete them (as in extract_block_to_page)
+    sqlx::query!(
+        r#"
+        UPDATE blocks
+        SET page_id = $2, updated_at = now()
+        WHERE page_id = $1
+        "#,
+        source_id,
+        target_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    let summary = format!("Merged '{}' into '{}'", source.title, target.title);
+    let target_update = update_page_in(
+        &mut tx,
+        target_id,
+        None,
+        Some(content),
+        Some(Some(&raw_markdown)),
+        None,
+        false,
+        Some(&summary),
+    )
+    .await?
+    .ok_or(DalError::NotFound)?;
+    let source_update = update_page_in(
+        &mut tx,
+        source_id,
+        None,
+        Some(new_page_content(&Value::Null, Vec::new(), &[])),
+        Some(None),
+        None,
+        false,
+        Some(&summary),
+    )
+    .await?
+    .ok_or(DalError::NotFound)?;
+    let mut link_targets_changed = target_update.link_targets_changed.clone();
+    link_targets_changed.extend(source_update.link_targets_changed);
+
+    // References from other pages aren't rebuilt by either sync
+    sqlx::query!(
+        r#"
+        UPDATE block_references
+        SET referenced_page_id = $2
+        WHERE referenced_page_id = $1
+        "#,
+        source_id,
+        target_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE audio_recordings
+        SET page_id = $2
+        WHERE page_id = $1
+        "#,
+        source_id,
+        target_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    // Aliases are unique across pages, so the target can't already have one of them
+    sqlx::query!(
+        r#"
+        UPDATE page_aliases
+        SET page_id = $2
+        WHERE page_id = $1
+        "#,
+        source_id,
+        target_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    // Tags typed in the content came along with it
+    sqlx::query!(
+        r#"
+        INSERT INTO page_tags (page_id, tag_id, from_content)
+        SELECT $2, tag_id, false
+        FROM page_tags
+        WHERE page_id = $1 AND NOT from_content
+        ON CONFLICT (page_id, tag_id) DO NOTHING
+        "#,
+        source_id,
+        target_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE pages
+        SET deleted_at = now(), is_favorite = false, favorite_order = NULL
+        WHERE id = $1
+        "#,
+        source_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    compact_favorite_order(&mut *tx).await?;
+    sqlx::query!("DELETE FROM renamed_duplicate_pages WHERE page_id = $1", source_id)
+        .execute(&mut *tx)
+        .await?;
+
+    // Pages linking to the source by title or ID are saved with the target's instead
+    let linking_pages = sqlx::query!(
+        r#"
+        SELECT p.id, p.content_json, p.raw_markdown
+        FROM pages p
+        JOIN page_links l ON l.source_page_id = p.id
+        WHERE l.target_page_id = $1 AND p.id <> $1
+        FOR UPDATE OF p
+        "#,
+        source_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let (source_key, target_key) = (source_id.to_string(), target_id.to_string());
+    let renames = [
+        (source.title.as_str(), target.title.as_str()),
+        (source_key.as_str(), target_key.as_str()),
+    ];
+    let mut relinked_pages = Vec::new();
+    for page in linking_pages {
+        let mut content_json = page.content_json;
+        let mut raw_markdown = page.raw_markdown;
+        let mut changed = false;
+        for (old, new) in &renames {
+            changed |= rewrite_page_link_titles_in_json(&mut content_json, old, new);
+            if let Some(markdown) = raw_markdown.as_deref().and_then(|md| rewrite_page_link_titles(md, old, new)) {
+                raw_markdown = Some(markdown);
+                changed = true;
+            }
+        }
+        if !changed {
+            continue;
+        }
+        let update = update_page_in(
+            &mut tx,
+            page.id,
+            None,
+            Some(content_json),
+            Some(raw_markdown.as_deref()),
+            None,
+            false,
+            Some(&summary),
+        )
+        .await?
+        .ok_or(DalError::NotFound)?;
+        link_targets_changed.extend(update.link_targets_changed);
+        relinked_pages.push(page.id);
+    }
+
+    // Whatever still points at the source (e.g. a link in another case than its title) is
+    // pointed at the target, adding to the counts of links that already go there
+    sqlx::query!(
+        r#"
+        INSERT INTO page_links (source_page_id, target_page_id, created_at, link_count)
+        SELECT source_page_id, $2, created_at, link_count
+        FROM page_links
+        WHERE target_page_id = $1
+        ON CONFLICT (source_page_id, target_page_id)
+        DO UPDATE SET link_count = page_links.link_count + EXCLUDED.link_count
+        "#,
+        source_id,
+        target_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO page_link_blocks (source_page_id, target_page_id, block_id, link_count)
+        SELECT source_page_id, $2, block_id, link_count
+        FROM page_link_blocks
+        WHERE target_page_id = $1
+        ON CONFLICT (source_page_id, target_page_id, block_id)
+        DO UPDATE SET link_count = page_link_blocks.link_count + EXCLUDED.link_count
+        "#,
+        source_id,
+        target_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    // Takes the source's page_link_blocks rows with it
+    sqlx::query!("DELETE FROM page_links WHERE target_page_id = $1", source_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    link_targets_changed.sort();
+    link_targets_changed.dedup();
+    Ok(PageMerge {
+        target: target_update,
+        relinked_pages,
+        link_targets_changed,
+    })
+}
+
+// A page renamed by migration 0025 because another live page had the same title
+#[derive(Debug, serde::Serialize)]
+pub struct RenamedDuplicatePage {
+    pub page_id: Uuid,
+    pub title: String, // With the suffix it was given
+    pub original_title: String,
+    pub kept_page_id: Option<Uuid>, // The page that kept the title; None once it was purged
+    pub renamed_at: DateTime<Utc>,
+}
+
+// The live pages renamed for having a duplicate title and not yet merged, oldest title first
+pub async fn list_renamed_duplicate_pages(pool: &PgPool) -> Result<Vec<RenamedDuplicatePage>, DalError> {
+    let pages = sqlx::query_as!(
+        RenamedDuplicatePage,
+        r#"
+        SELECT r.page_id, p.title, r.original_title, r.kept_page_id, r.renamed_at
+        FROM renamed_duplicate_pages r
+        JOIN pages p ON p.id = r.page_id
+        WHERE p.deleted_at IS NULL
+        ORDER BY lower(r.original_title), p.created_at
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(pages)
+}
+
+
+// --- Revisions ---
+
+// Writes a revision's content back to its page through update_page, so blocks, links and tags
+// follow it. The content being replaced becomes a revision in turn. Returns the page's ID.
+pub async fn restore_page_revision(pool: &PgPool, revision_id: Uuid) -> Result<Uuid, DalError> {
+    let revision = revision_handler::get_revision(pool, revision_id).await?;
+    let summary = format!("Restored the version saved at {}", revision.page_updated_at.to_rfc3339());
+    update_page(
+        pool,
+        revision.page_id,
+        None,
+        Some(revision.content_json),
+        revision.raw_markdown.as_deref().map(Some),
+        None,
+        false,
+        Some(&summary),
+    )
+    .await?
+    .ok_or(DalError::NotFound)?;
+    Ok(revision.page_id)
+}
+
+// --- Repairing block IDs ---
+
+// IDs of every page, trashed ones included, oldest first
+pub async fn list_page_ids(pool: &PgPool) -> Result<Vec<Uuid>, DalError> {
+    let ids = sqlx::query_scalar!(
+        r#"
+        SELECT id
+        FROM pages
+        ORDER BY created_at, id
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(ids)
+}
+
+// Gives the page's block nodes missing or duplicate uniqueIDs fresh ones and saves the content
+// through update_page, so the repaired blocks get rows. A page that needs no repair isn't
+// written; one saved by someone else meanwhile fails with DalError::Conflict.
+pub async fn normalize_page(pool: &PgPool, id: Uuid) -> Result<json_utils::UniqueIdRepair, DalError> {
+    let page = get_page(pool, id).await?;
+    let mut content_json = page.content_json;
+    let repair = json_utils::repair_unique_ids(&mut content_json);
+    if repair.changed() {
+        update_page(pool, id, None, Some(content_json), None, Some(page.updated_at), false, Some("Repaired block IDs"))
+            .await?
+            .ok_or(DalError::NotFound)?;
+    }
+    Ok(repair)
+}
+
+
+// --- Checking block, link and reference rows ---
+
+// A row of block_references, or one the content says should be there
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize)]
+pub struct BlockReferencePair {
+    pub referencing_block_id: Uuid,
+    pub referenced_block_id: Uuid,
+}
+
+// Where a page's stored rows differ from what update_page would store for its content_json.
+// Unresolved [[links]] have no page_links row either way and aren't compared.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PageIntegrity {
+    pub page_id: Uuid,
+    pub title: String,
+    pub missing_blocks: Vec<Uuid>, // uniqueIDs in the content with no block row on this page
+    pub orphan_blocks: Vec<Uuid>,  // Block rows of the page whose ID isn't in the content
+    pub missing_links: Vec<Uuid>,  // Pages the content links to, with no page_links row
+    pub orphan_links: Vec<Uuid>,   // page_links rows to pages the content doesn't link to
+    pub missing_refs: Vec<BlockReferencePair>, // References to existing blocks, with no row
+    pub orphan_refs: Vec<BlockReferencePair>,  // block_references rows the content doesn't have
+    pub fixed: bool, // Whether the page was resynced after this was found
+}
+
+impl PageIntegrity {
+    pub fn is_consistent(&self) -> bool {
+        self.missing_blocks.is_empty()
+            && self.orphan_blocks.is_empty()
+            && self.missing_links.is_empty()
+            && self.orphan_links.is_empty()
+            && self.missing_refs.is_empty()
+            && self.orphan_refs.is_empty()
+    }
+}
+
+// Compares the block, link and block reference rows of each page with its content_json. The
+// rows of all the pages are read with one query per table. Pages that don't exist are left out.
+pub async fn check_page_integrity(pool: &PgPool, ids: &[Uuid]) -> Result<Vec<PageIntegrity>, DalError> {
+    let mut conn = pool.acquire().await?;
+    let pages = sqlx::query!(
+        r#"
+        SELECT id, title, content_json
+        FROM pages
+        WHERE id = ANY($1)
+        ORDER BY created_at, id
+        "#,
+        ids
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut stored_blocks: std::collections::HashMap<Uuid, std::collections::HashSet<Uuid>> =
+        std::collections::HashMap::new();
+    for row in sqlx::query!("SELECT id, page_id FROM blocks WHERE page_id = ANY($1)", ids)
+        .fetch_all(&mut *conn)
+        .await?
+    {
+        stored_blocks.entry(row.page_id).or_default().insert(row.id);
+    }
+    let mut stored_links: std::collections::HashMap<Uuid, std::collections::HashSet<Uuid>> =
+        std::collections::HashMap::new();
+    for row in sqlx::query!("SELECT source_page_id, target_page_id FROM page_links WHERE source_page_id = ANY($1)", ids)
+        .fetch_all(&mut *conn)
+        .await?
+    {
+        stored_links.entry(row.source_page_id).or_default().insert(row.target_page_id);
+    }
+    let mut stored_refs: std::collections::HashMap<Uuid, std::collections::HashSet<BlockReferencePair>> =
+        std::collections::HashMap::new();
+    for row in sqlx::query!(
+        r#"
+        SELECT referencing_page_id, referencing_block_id, referenced_block_id
+        FROM block_references
+        WHERE referencing_page_id = ANY($1)
+        "#,
+        ids
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    {
+        stored_refs.entry(row.referencing_page_id).or_default().insert(BlockReferencePair {
+            referencing_block_id: row.referencing_block_id,
+            referenced_block_id: row.referenced_block_id,
+        });
+    }
+
+    let mut reports = Vec::with_capacity(pages.len());
+    for page in pages {
+        let (parsed_links, parsed_block_refs, extracted_blocks) =
+            extract_links_references_and_blocks(&page.content_json, page.id);
+
+        let content_blocks: std::collections::HashSet<Uuid> = extracted_blocks.iter().map(|eb| eb.id).collect();
+        let page_blocks = stored_blocks.remove(&page.id).unwrap_or_default();
+
+        // Link targets as update_page resolves them, without stubs for titles matching no page.
+        // ID links to pages that don't exist are skipped by the sync too.
+        let (links, _) = resolve_link_targets(&mut conn, &parsed_links, false).await?;
+        let link_targets: Vec<Uuid> = links.iter().map(|(target_id, _)| *target_id).collect();
+        let content_links: std::collections::HashSet<Uuid> =
+            sqlx::query_scalar!("SELECT id FROM pages WHERE id = ANY($1)", &link_targets)
+                .fetch_all(&mut *conn)
+                .await?
+                .into_iter()
+                .collect();
+        let page_links = stored_links.remove(&page.id).unwrap_or_default();
+
+        // References are stored when the referenced block has a row, which this page's own
+        // blocks get in the same sync
+        let referenced_ids: Vec<Uuid> = parsed_block_refs.iter().map(|bref| bref.referenced_block_id).collect();
+        let referenced_blocks: std::collections::HashSet<Uuid> =
+            sqlx::query_scalar!("SELECT id FROM blocks WHERE id = ANY($1)", &referenced_ids)
+                .fetch_all(&mut *conn)
+                .await?
+                .into_iter()
+                .chain(content_blocks.iter().copied())
+                .collect();
+        let content_refs: std::collections::HashSet<BlockReferencePair> = parsed_block_refs
+            .iter()
+            .filter(|bref| referenced_blocks.contains(&bref.referenced_block_id))
+            .map(|bref| BlockReferencePair {
+                referencing_block_id: bref.referencing_block_id,
+                referenced_block_id: bref.referenced_block_id,
+            })
+            .collect();
+        let page_refs = stored_refs.remove(&page.id).unwrap_or_default();
+
+        reports.push(PageIntegrity {
+            page_id: page.id,
+            title: page.title,
+            missing_blocks: sorted_difference(&content_blocks, &page_blocks),
+            orphan_blocks: sorted_difference(&page_blocks, &content_blocks),
+            missing_links: sorted_difference(&content_links, &page_links),
+            orphan_links: sorted_difference(&page_links, &content_links),
+            missing_refs: sorted_difference(&content_refs, &page_refs),
+            orphan_refs: sorted_difference(&page_refs, &content_refs),
+            fixed: false,
+        });
+    }
+    Ok(reports)
+}
+
+fn sorted_difference<T: Copy + Ord + std::hash::Hash>(
+    a: &std::collections::HashSet<T>,
+    b: &std::collections::HashSet<T>,
+) -> Vec<T> {
+    let mut difference: Vec<T> = a.difference(b).copied().collect();
+    difference.sort();
+    difference
+}
+
+// Runs update_page's block, link and reference sync again on the page's stored content, for
+// rows that drifted from it. The content_hash is cleared first, since update_page skips the sync
+// for content it already has; raw_markdown is passed as stored, so it isn't rendered again, and
+// with the content unchanged no revision is kept. The page's updated_at moves on.
+pub async fn resync_page(pool: &PgPool, id: Uuid) -> Result<PageUpdate, DalError> {
+    let mut tx = pool.begin().await?;
+    let page = sqlx::query!(
+        r#"
+        UPDATE pages SET content_hash = NULL
+        WHERE id = $1
+        RETURNING content_json, raw_markdown
+        "#,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(DalError::NotFound)?;
+
+    let update = update_page_in(
+        &mut tx,
+        id,
+        None,
+        Some(page.content_json),
+        Some(page.raw_markdown.as_deref()),
+        None,
+        false,
+        None,
+    )
+    .await?
+    .ok_or(DalError::NotFound)?;
+    tx.commit().await?;
+    Ok(update)
+}
+
+
+// --- Redirecting block references ---
+
+// A page whose references redirect_block_references rewrote
+#[derive(Debug)]
+pub struct RedirectedPage {
+    pub page_id: Uuid,
+    pub update: PageUpdate,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct RedirectFailure {
+    pub page_id: Uuid,
+    pub error: String,
+}
+
+#[derive(Debug, Default)]
+pub struct BlockReferenceRedirect {
+    pub pages: Vec<RedirectedPage>,
+    pub failures: Vec<RedirectFailure>,
+}
+
+// Points every `(((old_block_id)))` in the vault at new_block_id, e.g. before deleting a block
+// whose content moved elsewhere. Each referencing page has the text in its content_json and
+// raw_markdown rewritten and is saved, which moves its block_references rows along. Pages are
+// found by their rows and by their text, so references whose rows went with a deleted block
+// are redirected too; trashed pages are included. Each page is saved in its own transaction,
+// and one that fails is reported without stopping the rest. NotFound if new_block_id has no
+// block row.
+pub async fn redirect_block_references(
+    pool: &PgPool,
+    old_block_id: Uuid,
+    new_block_id: Uuid,
+) -> Result<BlockReferenceRedirect, DalError> {
+    if block_handler::get_block(pool, new_block_id).await?.is_none() {
+        return Err(DalError::NotFound);
+    }
+
+    let page_ids = sqlx::query_scalar!(
+        r#"
+        SELECT id AS "id!"
+        FROM pages
+        WHERE id IN (SELECT referencing_page_id FROM block_references WHERE referenced_block_id = $1)
+            OR content_json::text LIKE $2
+            OR raw_markdown LIKE $2
+        ORDER BY id
+        "#,
+        old_block_id,
+        format!("%{}%", old_block_id)
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let id_map = std::collections::HashMap::from([(old_block_id, new_block_id)]);
+    let mut redirect = BlockReferenceRedirect::default();
+    for page_id in page_ids {
+        match redirect_page_references(pool, page_id, &id_map).await {
+            Ok(Some(update)) => redirect.pages.push(RedirectedPage { page_id, update }),
+            Ok(None) => {} // Only mentions the ID outside a reference, or was deleted meanwhile
+            Err(e) => {
+                eprintln!("[Redirect] Failed to redirect references in page {}: {}", page_id, e);
+                redirect.failures.push(RedirectFailure {
+                    page_id,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+    Ok(redirect)
+}
+
+// Rewrites the references in id_map in one page and saves it. None if nothing was rewritten.
+async fn redirect_page_references(
+    pool: &PgPool,
+    page_id: Uuid,
+    id_map: &std::collections::HashMap<Uuid, Uuid>,
+) -> Result<Option<PageUpdate>, DalError> {
+    let mut tx = pool.begin().await?;
+    let Some(page) = sqlx::query!(
+        r#"
+        SELECT content_json, raw_markdown
+        FROM pages
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        page_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let mut content_json = page.content_json;
+    let json_changed = remap_block_refs_in_json(&mut content_json, id_map);
+    let new_markdown = page.raw_markdown.as_deref().and_then(|md| remap_block_refs(md, id_map));
+    if !json_changed && new_markdown.is_none() {
+        return Ok(None);
+    }
+
+    let raw_markdown = new_markdown.as_deref().or(page.raw_markdown.as_deref());
+    let update = update_page_in(
+        &mut tx,
+        page_id,
+        None,
+        Some(content_json),
+        Some(raw_markdown),
+        None,
+        false,
+        Some("Redirected block references"),
+    )
+    .await?
+    .ok_or(DalError::NotFound)?;
+    tx.commit().await?;
+    Ok(Some(update))
+}
+
+
+// --- Resolving block references ---
+
+// How deep resolve_block_reference follows references when the caller doesn't say
+pub const DEFAULT_REFERENCE_DEPTH: u32 = 3;
+pub const MAX_REFERENCE_DEPTH: u32 = 10;
+
+// A referenced block ready to be rendered in place of its (((reference)))
+#[derive(Debug, serde::Serialize)]
+pub struct ResolvedBlock {
+    pub block_id: Uuid,
+    pub page_id: Uuid,
+    pub page_title: String,
+    pub text: Option<String>,
+    pub nodes: Vec<Value>, // From block_subtree; empty if the page's content doesn't have the block yet
+    pub references: Vec<ResolvedReference>, // References inside the block, in the order they appear
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ResolvedReference {
+    Block(ResolvedBlock),
+    Cycle { block_id: Uuid },       // Already being resolved further up; expanding it would loop
+    Missing { block_id: Uuid },     // No such block
+    Unexpanded { block_id: Uuid },  // Past the requested depth
+}
+
+// Resolves the block, then the references inside it, down to `depth` levels of nesting
+pub async fn resolve_block_reference(pool: &PgPool, block_id: Uuid, depth: u32) -> Result<ResolvedReference, DalError> {
+    let mut pages = std::collections::HashMap::new();
+    let mut ancestors = Vec::new();
+    resolve_reference(pool, block_id, depth, &mut ancestors, &mut pages).await
+}
+
+type PageCache = std::collections::HashMap<Uuid, (String, Value)>; // page_id -> (title, content_json)
+
+fn resolve_reference<'a>(
+    pool: &'a PgPool,
+    block_id: Uuid,
+    depth: u32,
+    ancestors: &'a mut Vec<Uuid>,
+    pages: &'a mut PageCache,
+) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<ResolvedReference, DalError>> + Send + 'a>> {
+    Box::pin(async move {
+        let Some(block) = sqlx::query!("SELECT page_id, content_text FROM blocks WHERE id = $1", block_id)
+            .fetch_optional(pool)
+            .await?
+        else {
+            return Ok(ResolvedReference::Missing { block_id });
+        };
+        if let std::collections::hash_map::Entry::Vacant(entry) = pages.entry(block.page_id) {
+            let page = sqlx::query!("SELECT title, content_json FROM pages WHERE id = $1", block.page_id)
+                .fetch_one(pool)
+                .await?;
+            entry.insert((page.title, page.content_json));
+        }
+        let (page_title, content) = &pages[&block.page_id];
+        let page_title = page_title.clone();
+        let nodes = block_subtree(content, block_id).unwrap_or_default();
+
+        let mut nested_ids = Vec::new();
+        for node in &nodes {
+            collect_block_reference_ids(node, &mut nested_ids);
+        }
+        let mut references = Vec::with_capacity(nested_ids.len());
+        ancestors.push(block_id);
+        for nested_id in nested_ids {
+            let reference = if ancestors.contains(&nested_id) {
+                ResolvedReference::Cycle { block_id: nested_id }
+            } else if depth == 0 {
+                ResolvedReference::Unexpanded { block_id: nested_id }
+            } else {
+                resolve_reference(pool, nested_id, depth - 1, ancestors, pages).await?
+            };
+            references.push(reference);
+        }
+        ancestors.pop();
+
+        Ok(ResolvedReference::Block(ResolvedBlock {
+            block_id,
+            page_id: block.page_id,
+            page_title,
+            text: block.content_text,
+            nodes,
+            references,
+        }))
+    })
+}
+
+// IDs of the (((block references))) in a node's text, in order and without repeats
+pub(crate) fn collect_block_reference_ids(node: &Value, ids: &mut Vec<Uuid>) {
+    match node {
+        Value::Object(obj) => {
+            if obj.get("type").and_then(|v| v.as_str()) == Some("text") {
+                if let Some(text) = obj.get("text").and_then(|v| v.as_str()) {
+                    for cap in BLOCK_REF_REGEX.captures_iter(text) {
+                        if let Ok(id) = Uuid::parse_str(cap[1].trim()) {
+                            if !ids.contains(&id) {
+                                ids.push(id);
+                            }
+                        }
+                    }
+                }
+            }
+            obj.values().for_each(|value| collect_block_reference_ids(value, ids));
+        }
+        Value::Array(items) => items.iter().for_each(|item| collect_block_reference_ids(item, ids)),
+        _ => {}
+    }
+}
+
+
+// --- Tasks ---
+
+// Checks or unchecks a task block: its checkbox in the page's content_json, the `[ ]`/`[x]` on
+// its line in raw_markdown (when it can be found) and its task state. Returns the page's new
+// updated_at, or DalError::NotFound if the block doesn't exist; a block that isn't a task is a
+// conflict.
+pub async fn set_task_state(pool: &PgPool, block_id: Uuid, done: bool) -> Result<DateTime<Utc>, DalError> {
+    let mut tx = pool.begin().await?;
+
+    let block = block_handler::get_block(&mut *tx, block_id).await?.ok_or(DalError::NotFound)?;
+    if block.task_state.is_none() {
+        return Err(DalError::Conflict(format!("Block {} isn't a task", block_id)));
+    }
+    let page = sqlx::query!(
+        r#"
+        SELECT content_json, raw_markdown
+        FROM pages
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        block.page_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let mut content_json = page.content_json;
+    let path = find_block_path(&content_json, &block_id.to_string()).ok_or_else(|| {
+        DalError::Conflict(format!("Block {} isn't in its page's content; save the page and try again", block_id))
+    })?;
+    if let Some(Value::Object(node)) = content_json.pointer_mut(&json_pointer(&path)) {
+        node.insert("checked".to_string(), Value::Bool(done));
+    }
+    let raw_markdown = page
+        .raw_markdown
+        .map(|md| block.content_text.as_deref().and_then(|text| set_markdown_checkbox(&md, text, done)).unwrap_or(md));
+
+    block_handler::set_block_task_state(&mut *tx, block_id, Some(if done { "done" } else { "todo" })).await?;
+    let updated_at = sqlx::query_scalar!(
+        r#"
+        UPDATE pages
+        SET content_json = $2, raw_markdown = $3, content_hash = NULL, updated_at = now(),
+            content_updated_at = CASE
+                WHEN content_json IS DISTINCT FROM $2 OR raw_markdown IS DISTINCT FROM $3 THEN now()
+                ELSE content_updated_at
+            END
+        WHERE id = $1
+        RETURNING updated_at
+        "#,
+        block.page_id,
+        content_json,
+        raw_markdown
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(updated_at)
+}
+
+// Sets the checkbox on the first `- [ ] text` / `- [x] text` line whose text matches. Returns
+// None if there is no such line.
+fn set_markdown_checkbox(markdown: &str, text: &str, done: bool) -> Option<String> {
+    let mut lines: Vec<String> = markdown.lines().map(str::to_string).collect();
+    let line = lines.iter_mut().find(|line| {
+        let (prefix, rest) = split_markdown_prefix(line);
+        prefix.contains("] ") && normalize_block_text(rest).as_deref() == Some(text)
+    })?;
+    let (prefix, rest) = split_markdown_prefix(line);
+    let open = prefix.find('[')?;
+    *line = format!("{}[{}] {}", &prefix[..open], if done { "x" } else { " " }, rest);
+
+    let mut updated = lines.join("\n");
+    if markdown.ends_with('\n') {
+        updated.push('\n');
+    }
+    Some(updated)
+}
+
+// --- Trash ---
+// Pages are soft-deleted by setting deleted_at. Trashed pages are hidden from listings,
+// search and title resolution until restored or purged.
+
+// The updated_at of each of these pages that exists
+pub async fn get_updated_at<'e>(
+    executor: impl PgExecutor<'e>,
+    ids: &[Uuid],
+) -> Result<Vec<(Uuid, DateTime<Utc>)>, DalError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, updated_at
+        FROM pages
+        WHERE id = ANY($1)
+        "#,
+        ids
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| (row.id, row.updated_at)).collect())
+}
+
+// Trashing a page also takes it out of the favorites
+pub async fn trash_page(pool: &PgPool, id: Uuid) -> Result<bool, DalError> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE pages
+        SET deleted_at = now(), is_favorite = false, favorite_order = NULL
+        WHERE id = $1 AND deleted_at IS NULL
+        "#,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+    compact_favorite_order(&mut *tx).await?;
+
+    tx.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
 
+pub async fn list_trashed_pages(pool: &PgPool) -> Result<Vec<Page>, DalError> {
     let pages = sqlx::query_as!(
         Page,
         r#"
-        SELECT id, title, content_json, raw_markdown, created_at, updated_at
+        SELECT id, title, content_json, raw_markdown, created_at, updated_at, deleted_at, is_template, is_favorite, favorite_order,
+               content_updated_at, page_tag_names(id) AS "tags!", page_alias_names(id) AS "aliases!"
         FROM pages
-        WHERE title ILIKE $1  -- Case-insensitive search for title
-        -- For searching in JSONB:
-        -- OR content_json::text ILIKE $1
-        -- (This is a simple text search in JSON, more advanced JSONB operators can be used)
-        ORDER BY updated_at DESC
+        WHERE deleted_at IS NOT NULL
+        ORDER BY deleted_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(pages)
+}
+
+// Restores a trashed page. If another live page has taken its title in the meantime the
+// restore is rejected with DalError::TitleTaken, unless a replacement title is supplied.
+pub async fn restore_page(pool: &PgPool, id: Uuid, new_title: Option<&str>) -> Result<bool, DalError> {
+    let mut tx = pool.begin().await?;
+
+    let trashed = sqlx::query!(
+        r#"
+        SELECT title
+        FROM pages
+        WHERE id = $1 AND deleted_at IS NOT NULL
+        FOR UPDATE
+        "#,
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(DalError::NotFound)?;
+
+    let title = new_title.unwrap_or(&trashed.title);
+    ensure_title_free(&mut *tx, title, Some(id)).await?;
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE pages
+        SET deleted_at = NULL, title = $2
+        WHERE id = $1
+        "#,
+        id,
+        title
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// --- Favorites ---
+
+// Live favorites in their stored order
+pub async fn list_favorites(pool: &PgPool) -> Result<Vec<PageMetadata>, DalError> {
+    let pages = sqlx::query_as!(
+        PageMetadata,
+        r#"
+        SELECT id, title, created_at, updated_at, deleted_at, is_favorite, favorite_order,
+               content_updated_at, page_tag_names(id) AS "tags!"
+        FROM pages
+        WHERE is_favorite AND deleted_at IS NULL
+        ORDER BY favorite_order ASC NULLS LAST, title ASC
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(pages)
+}
+
+// Adds a page to the end of the favorites, or removes it. Returns false if the page doesn't
+// exist (or is trashed).
+pub async fn set_favorite(pool: &PgPool, id: Uuid, favorite: bool) -> Result<bool, DalError> {
+    let mut tx = pool.begin().await?;
+
+    let result = if favorite {
+        sqlx::query!(
+            r#"
+            UPDATE pages
+            SET is_favorite = true,
+                favorite_order = COALESCE(
+                    favorite_order,
+                    (SELECT COALESCE(MAX(favorite_order) + 1, 0) FROM pages WHERE is_favorite)
+                )
+            WHERE id = $1 AND deleted_at IS NULL
+            "#,
+            id
+        )
+        .execute(&mut *tx)
+        .await?
+    } else {
+        sqlx::query!(
+            r#"
+            UPDATE pages
+            SET is_favorite = false, favorite_order = NULL
+            WHERE id = $1 AND deleted_at IS NULL
+            "#,
+            id
+        )
+        .execute(&mut *tx)
+        .await?
+    };
+    compact_favorite_order(&mut *tx).await?;
+
+    tx.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// Stores a new favorites order. page_ids must contain exactly the current favorites.
+pub async fn reorder_favorites(pool: &PgPool, page_ids: &[Uuid]) -> Result<(), DalError> {
+    let mut tx = pool.begin().await?;
+
+    let current: std::collections::HashSet<Uuid> = sqlx::query_scalar!(
+        r#"
+        SELECT id
+        FROM pages
+        WHERE is_favorite AND deleted_at IS NULL
+        FOR UPDATE
+        "#
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .collect();
+
+    let submitted: std::collections::HashSet<Uuid> = page_ids.iter().copied().collect();
+    if submitted.len() != page_ids.len() {
+        return Err(DalError::Conflict("The favorites order lists a page more than once".to_string()));
+    }
+    if submitted != current {
+        return Err(DalError::Conflict(
+            "The favorites order doesn't match the current favorites. Reload them and try again.".to_string(),
+        ));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE pages p
+        SET favorite_order = o.position - 1
+        FROM unnest($1::uuid[]) WITH ORDINALITY AS o(id, position)
+        WHERE p.id = o.id
+        "#,
+        page_ids
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+// Renumbers favorites 0..n in their current order, closing gaps left by removed pages
+async fn compact_favorite_order<'e>(executor: impl PgExecutor<'e>) -> Result<(), DalError> {
+    sqlx::query!(
+        r#"
+        UPDATE pages p
+        SET favorite_order = ranked.position
+        FROM (
+            SELECT id, (ROW_NUMBER() OVER (ORDER BY favorite_order NULLS LAST, title) - 1)::int AS position
+            FROM pages
+            WHERE is_favorite AND deleted_at IS NULL
+        ) ranked
+        WHERE p.id = ranked.id AND p.favorite_order IS DISTINCT FROM ranked.position
+        "#
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+// Permanently deletes a page. Blocks, links and references are removed by ON DELETE CASCADE.
+pub async fn purge_page(pool: &PgPool, id: Uuid) -> Result<bool, DalError> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM pages
+        WHERE id = $1
+        "#,
+        id
+    )
+    .execute(&mut *tx)
+    .await?;
+    compact_favorite_order(&mut *tx).await?; // In case a live favorite was purged directly
+
+    tx.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// Purges trashed pages that were deleted more than `older_than_days` days ago.
+pub async fn empty_trash(pool: &PgPool, older_than_days: i32) -> Result<u64, DalError> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM pages
+        WHERE deleted_at IS NOT NULL
+          AND deleted_at <= now() - make_interval(days => $1)
+        "#,
+        older_than_days
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// --- Bulk operations ---
+
+// Changes applied to every page by bulk_update_pages; fields left out are untouched
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct PagePatch {
+    pub add_tags: Vec<String>,
+    pub remove_tags: Vec<String>,
+    pub is_favorite: Option<bool>,
+    pub is_template: Option<bool>,
+}
+
+impl PagePatch {
+    pub fn is_empty(&self) -> bool {
+        self.add_tags.is_empty() && self.remove_tags.is_empty() && self.is_favorite.is_none() && self.is_template.is_none()
+    }
+}
+
+// Live pages among ids, with their content, in no particular order
+pub async fn get_pages(pool: &PgPool, ids: &[Uuid]) -> Result<Vec<Page>, DalError> {
+    let pages = sqlx::query_as!(
+        Page,
+        r#"
+        SELECT id, title, content_json, raw_markdown, created_at, updated_at, deleted_at, is_template, is_favorite, favorite_order,
+               content_updated_at, page_tag_names(id) AS "tags!", page_alias_names(id) AS "aliases!"
+        FROM pages
+        WHERE id = ANY($1) AND deleted_at IS NULL
+        "#,
+        ids
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(pages)
+}
+
+// Moves several pages to the trash at once. Returns the IDs that were trashed; pages that don't
+// exist or are already in the trash are left out.
+pub async fn trash_pages(pool: &PgPool, ids: &[Uuid]) -> Result<Vec<Uuid>, DalError> {
+    let mut tx = pool.begin().await?;
+
+    let trashed = sqlx::query_scalar!(
+        r#"
+        UPDATE pages
+        SET deleted_at = now(), is_favorite = false, favorite_order = NULL
+        WHERE id = ANY($1) AND deleted_at IS NULL
+        RETURNING id
+        "#,
+        ids
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    compact_favorite_order(&mut *tx).await?;
+
+    tx.commit().await?;
+    Ok(trashed)
+}
+
+// Applies a patch to every live page in ids, in one transaction. Returns the IDs of the pages
+// it was applied to; the rest don't exist or are in the trash.
+pub async fn bulk_update_pages(pool: &PgPool, ids: &[Uuid], patch: &PagePatch) -> Result<Vec<Uuid>, DalError> {
+    let mut tx = pool.begin().await?;
+
+    let page_ids = sqlx::query_scalar!(
+        r#"
+        SELECT id
+        FROM pages
+        WHERE id = ANY($1) AND deleted_at IS NULL
+        ORDER BY array_position($1, id)
+        FOR UPDATE
+        "#,
+        ids
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    if page_ids.is_empty() {
+        return Ok(page_ids);
+    }
+
+    for name in &patch.add_tags {
+        let tag = tag_handler::get_or_create_tag(&mut *tx, name).await?;
+        tag_handler::add_tag_to_pages(&mut *tx, &page_ids, tag.id).await?;
+    }
+    if !patch.remove_tags.is_empty() {
+        for name in &patch.remove_tags {
+            tag_handler::remove_tag_from_pages(&mut *tx, &page_ids, name).await?;
+        }
+        tag_handler::delete_unused_tags(&mut *tx).await?;
+    }
+
+    match patch.is_favorite {
+        // New favorites go to the end of the list, in the order they were given
+        Some(true) => {
+            sqlx::query!(
+                r#"
+                UPDATE pages p
+                SET is_favorite = true,
+                    favorite_order = (SELECT COALESCE(MAX(favorite_order) + 1, 0) FROM pages WHERE is_favorite) + o.position - 1
+                FROM unnest($1::uuid[]) WITH ORDINALITY AS o(id, position)
+                WHERE p.id = o.id AND NOT p.is_favorite
+                "#,
+                &page_ids
+            )
+            .execute(&mut *tx)
+            .await?;
+            compact_favorite_order(&mut *tx).await?;
+        }
+        Some(false) => {
+            sqlx::query!(
+                r#"
+                UPDATE pages
+                SET is_favorite = false, favorite_order = NULL
+                WHERE id = ANY($1)
+                "#,
+                &page_ids
+            )
+            .execute(&mut *tx)
+            .await?;
+            compact_favorite_order(&mut *tx).await?;
+        }
+        None => {}
+    }
+
+    if let Some(is_template) = patch.is_template {
+        sqlx::query!(
+            r#"
+            UPDATE pages
+            SET is_template = $2, updated_at = now()
+            WHERE id = ANY($1) AND is_template <> $2
+            "#,
+            &page_ids,
+            is_template
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(page_ids)
+}
+
+// --- Link autocompletion ---
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct PageTitleSuggestion {
+    pub id: Uuid,
+    pub title: String,
+    pub updated_at: DateTime<Utc>,
+    pub matched_alias: Option<String>, // Set when only one of the page's aliases matched
+}
+
+// Live, non-template pages whose title (or else an alias) contains `prefix`, ignoring case and
+// accents. Names starting with it come first, then those with a word starting with it, then the
+// rest; most recently updated first within each group. Also returns whether a page is titled
+// or aliased exactly `prefix` (again ignoring case and accents), so the caller knows whether to
+// offer creating it.
+pub async fn suggest_page_titles(
+    pool: &PgPool,
+    prefix: &str,
+    limit: i64,
+) -> Result<(Vec<PageTitleSuggestion>, bool), DalError> {
+    let like_term = prefix.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    let regex_term = regex::escape(prefix);
+
+    let suggestions = sqlx::query_as!(
+        PageTitleSuggestion,
+        r#"
+        WITH term AS (
+            SELECT lower(unaccent($1)) AS like_term, lower(unaccent($2)) AS regex_term
+        )
+        SELECT p.id, p.title, p.updated_at, m.alias AS "matched_alias?"
+        FROM pages p
+        CROSS JOIN term
+        LEFT JOIN LATERAL (
+            SELECT a.alias, lower(unaccent(a.alias)) AS alias_search
+            FROM page_aliases a
+            WHERE a.page_id = p.id AND lower(unaccent(a.alias)) LIKE '%' || term.like_term || '%'
+            ORDER BY lower(unaccent(a.alias)) LIKE term.like_term || '%' DESC, lower(a.alias)
+            LIMIT 1
+        ) m ON p.title_search NOT LIKE '%' || term.like_term || '%'
+        WHERE p.deleted_at IS NULL
+          AND NOT p.is_template
+          AND (p.title_search LIKE '%' || term.like_term || '%' OR m.alias IS NOT NULL)
+        ORDER BY
+            CASE
+                WHEN coalesce(m.alias_search, p.title_search) LIKE term.like_term || '%' THEN 0
+                WHEN coalesce(m.alias_search, p.title_search) ~ ('\m' || term.regex_term) THEN 1
+                ELSE 2
+            END,
+            p.updated_at DESC,
+            p.id
+        LIMIT $3
+        "#,
+        like_term,
+        regex_term,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let exact_match_exists = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM pages
+            WHERE deleted_at IS NULL AND title_search = lower(unaccent(trim($1)))
+        ) OR EXISTS (
+            SELECT 1 FROM page_aliases a JOIN pages p ON p.id = a.page_id
+            WHERE p.deleted_at IS NULL AND lower(unaccent(a.alias)) = lower(unaccent(trim($1)))
+        ) AS "exists!"
+        "#,
+        prefix
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok((suggestions, exact_match_exists))
+}
+
+// The SearchFilter fields this version understands. Bump it when fields are added, so a saved
+// search written by a newer version can be told apart.
+pub const SEARCH_FILTER_VERSION: u32 = 1;
+
+// Narrows search_pages beyond its text query. Filters combine: a page must match every one
+// that is set. Date ranges include both ends.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SearchFilter {
+    pub version: u32,
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+    pub updated_from: Option<DateTime<Utc>>,
+    pub updated_to: Option<DateTime<Utc>>,
+    pub has_tag: Option<String>, // Tag name, ignoring case
+    pub links_to: Option<Uuid>, // Pages that link to this page
+    pub linked_from: Option<Uuid>, // Pages this page links to
+    pub has_audio: Option<bool>, // Whether the page has recordings
+    pub is_daily_note: Option<bool>, // Whether the page is titled YYYY-MM-DD
+}
+
+impl Default for SearchFilter {
+    fn default() -> Self {
+        SearchFilter {
+            version: SEARCH_FILTER_VERSION,
+            created_from: None,
+            created_to: None,
+            updated_from: None,
+            updated_to: None,
+            has_tag: None,
+            links_to: None,
+            linked_from: None,
+            has_audio: None,
+            is_daily_note: None,
+        }
+    }
+}
+
+impl SearchFilter {
+    // The names of the filters that are set, as in the serialized filter
+    pub fn active_filters(&self) -> Vec<&'static str> {
+        let created = self.created_from.is_some() || self.created_to.is_some();
+        let updated = self.updated_from.is_some() || self.updated_to.is_some();
+        [
+            (created, "created"),
+            (updated, "updated"),
+            (self.has_tag.is_some(), "has_tag"),
+            (self.links_to.is_some(), "links_to"),
+            (self.linked_from.is_some(), "linked_from"),
+            (self.has_audio.is_some(), "has_audio"),
+            (self.is_daily_note.is_some(), "is_daily_note"),
+        ]
+        .into_iter()
+        .filter_map(|(set, name)| set.then_some(name))
+        .collect()
+    }
+}
+
+// Live pages whose title contains query_term (ignoring case) and that match the filter, in one
+// statement. An empty query_term matches every title.
+pub async fn search_pages(
+    pool: &PgPool,
+    query_term: &str,
+    filter: &SearchFilter,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<PageMetadata>, DalError> {
+    let search_pattern = format!("%{}%", query_term);
+
+    let pages = sqlx::query_as!(
+        PageMetadata,
+        r#"
+        SELECT p.id, p.title, p.created_at, p.updated_at, p.deleted_at, p.is_favorite, p.favorite_order,
+               p.content_updated_at, page_tag_names(p.id) AS "tags!"
+        FROM pages p
+        WHERE p.title ILIKE $1  -- Case-insensitive search for title
+          AND p.deleted_at IS NULL
+          AND ($2::timestamptz IS NULL OR p.created_at >= $2)
+          AND ($3::timestamptz IS NULL OR p.created_at <= $3)
+          AND ($4::timestamptz IS NULL OR p.updated_at >= $4)
+          AND ($5::timestamptz IS NULL OR p.updated_at <= $5)
+          AND ($6::text IS NULL OR EXISTS (
+              SELECT 1
+              FROM page_tags pt
+              JOIN tags t ON t.id = pt.tag_id
+              WHERE pt.page_id = p.id AND lower(t.name) = lower($6)
+          ))
+          AND ($7::uuid IS NULL OR EXISTS (
+              SELECT 1 FROM page_links l WHERE l.source_page_id = p.id AND l.target_page_id = $7
+          ))
+          AND ($8::uuid IS NULL OR EXISTS (
+              SELECT 1 FROM page_links l WHERE l.source_page_id = $8 AND l.target_page_id = p.id
+          ))
+          AND ($9::bool IS NULL OR EXISTS (SELECT 1 FROM audio_recordings a WHERE a.page_id = p.id) = $9)
+          AND ($10::bool IS NULL OR (p.title ~ '^[0-9]{4}-[0-9]{2}-[0-9]{2}$') = $10)
+        ORDER BY p.updated_at DESC, p.id DESC
+        LIMIT $11 OFFSET $12
         "#,
-        search_pattern
+        search_pattern,
+        filter.created_from,
+        filter.created_to,
+        filter.updated_from,
+        filter.updated_to,
+        filter.has_tag,
+        filter.links_to,
+        filter.linked_from,
+        filter.has_audio,
+        filter.is_daily_note,
+        limit,
+        offset
     )
     .fetch_all(pool)
     .await?;
 
     Ok(pages)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn paragraph(id: Uuid, text: &str) -> Value {
+        json!({"type": "paragraph", "uniqueID": id.to_string(), "children": [{"type": "text", "text": text}]})
+    }
+
+    fn root(children: Vec<Value>) -> Value {
+        json!({"root": {"type": "root", "children": children}})
+    }
+
+    #[test]
+    fn rewrite_page_link_titles_matches_ignoring_case() {
+        let text = "See [[linux]], [[ LINUX ]] and [[Linux Kernel]]";
+        let rewritten = rewrite_page_link_titles(text, "Linux", "GNU/Linux");
+        assert_eq!(rewritten.as_deref(), Some("See [[GNU/Linux]], [[GNU/Linux]] and [[Linux Kernel]]"));
+        assert_eq!(rewrite_page_link_titles("[[Linux Kernel]]", "Linux", "GNU/Linux"), None);
+    }
+
+    #[test]
+    fn rewrite_page_link_titles_in_json_rewrites_nested_text() {
+        let mut content = serde_json::json!({"root": {"children": [
+            {"type": "paragraph", "children": [{"type": "text", "text": "Uses [[linux]]"}]},
+            {"type": "paragraph", "children": [{"type": "text", "text": "Unrelated [[BSD]]"}]}
+        ]}});
+        assert!(rewrite_page_link_titles_in_json(&mut content, "Linux", "GNU/Linux"));
+        assert_eq!(content.pointer("/root/children/0/children/0/text"), Some(&Value::from("Uses [[GNU/Linux]]")));
+        assert_eq!(content.pointer("/root/children/1/children/0/text"), Some(&Value::from("Unrelated [[BSD]]")));
+    }
+
+    #[sqlx::test(migrator = "crate::db::MIGRATOR")]
+    async fn create_page_from_template_refuses_a_taken_title(pool: PgPool) {
+        let existing = create_page(&pool, "Weekly Review", json!({}), None).await.unwrap();
+        let template = create_page(&pool, "Review template", json!({}), None).await.unwrap();
+        set_page_is_template(&pool, template, true).await.unwrap();
+
+        let result = create_page_from_template(&pool, template, "weekly review", &Default::default()).await;
+        assert!(matches!(result, Err(DalError::TitleTaken { existing_id, .. }) if existing_id == existing));
+    }
+
+    #[sqlx::test(migrator = "crate::db::MIGRATOR")]
+    async fn restoring_a_page_whose_title_was_taken_leaves_the_live_page_alone(pool: PgPool) {
+        let old = create_page(&pool, "A", json!({}), Some("Old")).await.unwrap();
+        assert!(trash_page(&pool, old).await.unwrap());
+        let live = create_page(&pool, "A", json!({}), Some("New")).await.unwrap();
+        let before = get_page(&pool, live).await.unwrap();
+
+        let result = restore_page(&pool, old, None).await;
+        assert!(matches!(result, Err(DalError::TitleTaken { existing_id, .. }) if existing_id == live));
+        assert!(get_page(&pool, old).await.unwrap().deleted_at.is_some());
+
+        // Restored under another title instead
+        assert!(restore_page(&pool, old, Some("A (restored)")).await.unwrap());
+        let restored = get_page(&pool, old).await.unwrap();
+        assert_eq!((restored.title.as_str(), restored.deleted_at), ("A (restored)", None));
+
+        let after = get_page(&pool, live).await.unwrap();
+        assert_eq!((after.title, after.raw_markdown), (before.title, before.raw_markdown));
+        assert_eq!((after.updated_at, after.deleted_at), (before.updated_at, None));
+    }
+
+    #[sqlx::test(migrator = "crate::db::MIGRATOR")]
+    async fn create_page_with_content_stores_blocks_and_links_without_a_revision(pool: PgPool) {
+        let target = create_page(&pool, "Target", json!({}), None).await.unwrap();
+        let block_id = Uuid::new_v4();
+        let content = root(vec![paragraph(block_id, "Links to [[Target]]")]);
+
+        let (page_id, update) =
+            create_page_with_content(&pool, "Source", content, Some("Links to [[Target]]"), false).await.unwrap();
+        assert_eq!(update.link_targets_changed, vec![target]);
+        let blocks = block_handler::get_blocks_for_page(&pool, page_id).await.unwrap();
+        assert_eq!(blocks.iter().map(|block| block.id).collect::<Vec<_>>(), vec![block_id]);
+        assert!(revision_handler::list_revisions(&pool, page_id).await.unwrap().is_empty());
+        assert_eq!(get_page(&pool, page_id).await.unwrap().raw_markdown.as_deref(), Some("Links to [[Target]]"));
+    }
+
+    #[sqlx::test(migrator = "crate::db::MIGRATOR")]
+    async fn updating_some_fields_of_a_page_leaves_the_others(pool: PgPool) {
+        let old_content = root(vec![paragraph(Uuid::new_v4(), "Old")]);
+        let new_content = root(vec![paragraph(Uuid::new_v4(), "New")]);
+        let stored = |id: Uuid| {
+            let pool = pool.clone();
+            async move {
+                sqlx::query!(
+                    r#"
+                    SELECT title, content_json, content_hash, raw_markdown, content_updated_at
+                    FROM pages
+                    WHERE id = $1
+                    "#,
+                    id
+                )
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+            }
+        };
+        let id = create_page(&pool, "Page", json!({}), None).await.unwrap();
+        update_page(&pool, id, None, Some(old_content.clone()), Some(Some("Old text")), None, false, None)
+            .await
+            .unwrap();
+        let before = stored(id).await;
+
+        // Only the title
+        let mut tx = pool.begin().await.unwrap();
+        update_page_in(&mut tx, id, Some("Renamed"), None, None, None, false, None).await.unwrap().unwrap();
+        tx.commit().await.unwrap();
+        let row = stored(id).await;
+        assert_eq!(row.title, "Renamed");
+        assert_eq!((&row.content_json, &row.content_hash), (&before.content_json, &before.content_hash));
+        assert_eq!(row.raw_markdown.as_deref(), Some("Old text"));
+        assert_eq!(row.content_updated_at, before.content_updated_at);
+
+        // Only the content
+        let mut tx = pool.begin().await.unwrap();
+        update_page_in(&mut tx, id, None, Some(new_content.clone()), None, None, false, None).await.unwrap().unwrap();
+        tx.commit().await.unwrap();
+        let row = stored(id).await;
+        assert_eq!(row.title, "Renamed");
+        assert_eq!(row.content_json, new_content);
+        assert_eq!(row.content_hash, Some(json_utils::content_json_hash(&new_content)));
+        // raw_markdown isn't sent, so it follows the content
+        assert_eq!(row.raw_markdown, Some(render_markdown(&new_content)));
+        assert!(row.content_updated_at > before.content_updated_at);
+
+        // Clearing raw_markdown alone
+        let mut tx = pool.begin().await.unwrap();
+        update_page_in(&mut tx, id, None, None, Some(None), None, false, None).await.unwrap().unwrap();
+        tx.commit().await.unwrap();
+        let cleared = stored(id).await;
+        assert_eq!((cleared.title, cleared.content_json), ("Renamed".to_string(), new_content));
+        assert_eq!(cleared.raw_markdown, None);
+    }
+
+    #[sqlx::test(migrator = "crate::db::MIGRATOR")]
+    async fn page_update_query_writes_each_given_field(pool: PgPool) {
+        let old_content = root(vec![paragraph(Uuid::new_v4(), "Old")]);
+        let new_content = root(vec![paragraph(Uuid::new_v4(), "New")]);
+        let new_hash = json_utils::content_json_hash(&new_content);
+        let mut index = 0;
+        for set_title in [false, true] {
+            for set_content in [false, true] {
+                for raw_markdown in [None, Some(None), Some(Some("New text"))] {
+                    index += 1;
+                    let old_title = format!("Page {}", index);
+                    let new_title = format!("Renamed {}", index);
+                    let id = create_page(&pool, &old_title, old_content.clone(), Some("Old text")).await.unwrap();
+
+                    let content = set_content.then(|| (new_content.clone(), new_hash.clone()));
+                    let title = set_title.then_some(new_title.as_str());
+                    let mut query = page_update_query(id, title, content, raw_markdown, false, None);
+                    query.build().execute(&pool).await.unwrap();
+
+                    let page = get_page(&pool, id).await.unwrap();
+                    assert_eq!(page.title, if set_title { new_title } else { old_title });
+                    assert_eq!(&page.content_json, if set_content { &new_content } else { &old_content });
+                    // Some(None) clears raw_markdown, None leaves it
+                    assert_eq!(page.raw_markdown.as_deref(), raw_markdown.unwrap_or(Some("Old text")));
+                }
+            }
+        }
+    }
+
+    #[sqlx::test(migrator = "crate::db::MIGRATOR")]
+    async fn page_update_query_skips_a_page_changed_since(pool: PgPool) {
+        let id = create_page(&pool, "Page", json!({}), None).await.unwrap();
+        let stale = get_page(&pool, id).await.unwrap().updated_at - chrono::Duration::seconds(1);
+        let mut query = page_update_query(id, Some("Renamed"), None, None, false, Some(stale));
+        let updated_at = query.build_query_scalar::<DateTime<Utc>>().fetch_optional(&pool).await.unwrap();
+        assert_eq!(updated_at, None);
+        assert_eq!(get_page(&pool, id).await.unwrap().title, "Page");
+    }
+
+    #[sqlx::test(migrator = "crate::db::MIGRATOR")]
+    async fn indenting_a_block_stores_its_new_parent(pool: PgPool) {
+        let (parent_id, child_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let item = |id: Uuid, text: &str, nested: Vec<Value>| {
+            let mut children = vec![json!({"type": "text", "text": text})];
+            if !nested.is_empty() {
+                children.push(json!({"type": "list", "children": nested}));
+            }
+            json!({"type": "listitem", "uniqueID": id.to_string(), "children": children})
+        };
+        let list = |items: Vec<Value>| root(vec![json!({"type": "list", "children": items})]);
+        let parent_of = |blocks: &[block_handler::Block], id: Uuid| {
+            blocks.iter().find(|block| block.id == id).map(|block| block.parent_block_id)
+        };
+
+        let page_id = create_page(&pool, "Outline", json!({}), None).await.unwrap();
+        let flat = list(vec![item(parent_id, "Parent", vec![]), item(child_id, "Child", vec![])]);
+        update_page(&pool, page_id, None, Some(flat), None, None, false, None).await.unwrap();
+        let blocks = block_handler::get_blocks_for_page(&pool, page_id).await.unwrap();
+        assert_eq!(parent_of(&blocks, child_id), Some(None));
+
+        let indented = list(vec![item(parent_id, "Parent", vec![item(child_id, "Child", vec![])])]);
+        update_page(&pool, page_id, None, Some(indented), None, None, false, None).await.unwrap();
+        let blocks = block_handler::get_blocks_for_page(&pool, page_id).await.unwrap();
+        assert_eq!(parent_of(&blocks, child_id), Some(Some(parent_id)));
+        assert_eq!(parent_of(&blocks, parent_id), Some(None));
+        let child = blocks.iter().find(|block| block.id == child_id).unwrap();
+        assert_eq!(child.order_index, 0);
+    }
+
+    #[sqlx::test(migrator = "crate::db::MIGRATOR")]
+    async fn moving_a_block_moves_its_node_in_the_content(pool: PgPool) {
+        let (intro, first, second) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let item = |id: Uuid, text: &str| {
+            json!({"type": "listitem", "uniqueID": id.to_string(), "children": [{"type": "text", "text": text}]})
+        };
+        let content = root(vec![
+            paragraph(intro, "Intro"),
+            json!({"type": "list", "listType": "bullet", "children": [item(first, "First"), item(second, "Second")]}),
+        ]);
+        let page_id = create_page(&pool, "Outline", json!({}), None).await.unwrap();
+        update_page(&pool, page_id, None, Some(content), None, None, false, None).await.unwrap();
+        let position = |content: &Value, id: Uuid| {
+            json_utils::json_pointer(&find_block_path(content, &id.to_string()).unwrap())
+        };
+
+        // Indent the second item under the first
+        let (block, update) = move_block_node(&pool, second, Some(first), 0).await.unwrap().unwrap();
+        assert_eq!((block.parent_block_id, block.order_index), (Some(first), 0));
+        let page = get_page(&pool, page_id).await.unwrap();
+        assert_eq!(page.updated_at, update.updated_at);
+        assert_eq!(position(&page.content_json, second), "/root/children/1/children/1/children/0/children/0");
+        assert!(is_nested_list_holder(page.content_json.pointer("/root/children/1/children/1").unwrap()));
+        let stored_hash = sqlx::query_scalar!("SELECT content_hash FROM pages WHERE id = $1", page_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored_hash, Some(json_utils::content_json_hash(&page.content_json)));
+
+        // Move the first item, with the second nested under it, above the paragraph
+        let (block, _) = move_block_node(&pool, first, None, 0).await.unwrap().unwrap();
+        assert_eq!((block.parent_block_id, block.order_index), (None, 0));
+        let page = get_page(&pool, page_id).await.unwrap();
+        assert_eq!(position(&page.content_json, first), "/root/children/0/children/0");
+        assert_eq!(position(&page.content_json, second), "/root/children/0/children/1/children/0/children/0");
+        assert_eq!(position(&page.content_json, intro), "/root/children/1");
+        let blocks = block_handler::get_blocks_for_page(&pool, page_id).await.unwrap();
+        let intro_row = blocks.iter().find(|block| block.id == intro).unwrap();
+        assert_eq!(intro_row.order_index, 1);
+
+        // A paragraph can't go under a list item, nor a block under itself
+        let result = move_block_node(&pool, intro, Some(first), 0).await;
+        assert!(matches!(result, Err(DalError::Conflict(_))));
+        let result = move_block_node(&pool, first, Some(second), 0).await;
+        assert!(matches!(result, Err(DalError::Conflict(_))));
+        assert_eq!(get_page(&pool, page_id).await.unwrap().content_json, page.content_json);
+    }
+
+    #[sqlx::test(migrator = "crate::db::MIGRATOR")]
+    async fn deleting_a_block_tree_removes_its_nodes_from_the_content(pool: PgPool) {
+        let (intro, first, second) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let item = |id: Uuid, text: &str| {
+            json!({"type": "listitem", "uniqueID": id.to_string(), "children": [{"type": "text", "text": text}]})
+        };
+        let nested = json!({"type": "listitem", "children": [
+            {"type": "list", "listType": "bullet", "children": [item(second, "Second")]},
+        ]});
+        let content = root(vec![
+            paragraph(intro, &format!("See ((({})))", second)),
+            json!({"type": "list", "listType": "bullet", "children": [item(first, "First"), nested]}),
+        ]);
+        let page_id = create_page(&pool, "Outline", json!({}), None).await.unwrap();
+        update_page(&pool, page_id, None, Some(content), None, None, false, None).await.unwrap();
+
+        // The first item has the second nested under it
+        let result = delete_block_tree(&pool, first, false).await;
+        assert!(matches!(result, Err(DalError::Conflict(_))));
+
+        let (deleted, update) = delete_block_tree(&pool, first, true).await.unwrap().unwrap();
+        assert_eq!(deleted.block_ids, vec![first, second]);
+        assert_eq!(deleted.broken_references, 1);
+        let page = get_page(&pool, page_id).await.unwrap();
+        assert_eq!(page.updated_at, update.updated_at);
+        assert_eq!(page.content_json, root(vec![paragraph(intro, &format!("See ((({})))", second))]));
+        let blocks = block_handler::get_blocks_for_page(&pool, page_id).await.unwrap();
+        assert_eq!(blocks.iter().map(|block| block.id).collect::<Vec<_>>(), vec![intro]);
+
+        assert!(delete_block_tree(&pool, first, true).await.unwrap().is_none());
+    }
+
+    #[sqlx::test(migrator = "crate::db::MIGRATOR")]
+    async fn concurrent_updates_from_the_same_version_conflict(pool: PgPool) {
+        let id = create_page(&pool, "Page", json!({}), None).await.unwrap();
+        let version = get_page(&pool, id).await.unwrap().updated_at;
+
+        // Both writers saw the same version; the row lock lets one through and the other then
+        // finds the page changed
+        let tasks: Vec<_> = ["First", "Second"]
+            .into_iter()
+            .map(|title| {
+                let pool = pool.clone();
+                tokio::spawn(async move {
+                    let result = update_page(&pool, id, Some(title), None, None, Some(version), false, None).await;
+                    (title, result)
+                })
+            })
+            .collect();
+        let mut written = Vec::new();
+        let mut conflicts = 0;
+        for task in tasks {
+            match task.await.unwrap() {
+                (title, Ok(update)) => written.push((title, update.unwrap().updated_at)),
+                (_, Err(DalError::Conflict(_))) => conflicts += 1,
+                (_, Err(e)) => panic!("unexpected error: {}", e),
+            }
+        }
+        assert_eq!((written.len(), conflicts), (1, 1));
+        let (winner, new_version) = written[0];
+        let page = get_page(&pool, id).await.unwrap();
+        assert_eq!((page.title.as_str(), page.updated_at), (winner, new_version));
+
+        // Done in turn, the second write is rejected and the first one kept
+        let result = update_page(&pool, id, Some("Third"), None, None, Some(version), false, None).await;
+        assert!(matches!(result, Err(DalError::Conflict(_))), "{:?}", result.map(|_| ()));
+        assert_eq!(get_page(&pool, id).await.unwrap().title, winner);
+    }
+
+    #[sqlx::test(migrator = "crate::db::MIGRATOR")]
+    async fn concurrent_daily_note_requests_create_one_page(pool: PgPool) {
+        let template = create_page(&pool, "Daily template", root(vec![paragraph(Uuid::new_v4(), "{{date}}")]), None)
+            .await
+            .unwrap();
+        set_page_is_template(&pool, template, true).await.unwrap();
+        let variables = std::collections::HashMap::from([("date".to_string(), "2024-05-02".to_string())]);
+
+        for (date, use_template) in [("2024-05-01", false), ("2024-05-02", true)] {
+            let tasks: Vec<_> = (0..8)
+                .map(|_| {
+                    let (pool, variables) = (pool.clone(), variables.clone());
+                    tokio::spawn(async move {
+                        let template = use_template.then_some((template, &variables));
+                        get_or_create_daily_page(&pool, date, template).await.unwrap().id
+                    })
+                })
+                .collect();
+            let mut ids = Vec::new();
+            for task in tasks {
+                ids.push(task.await.unwrap());
+            }
+            ids.dedup();
+            assert_eq!(ids.len(), 1, "{} got several pages", date);
+
+            let count = sqlx::query_scalar!("SELECT count(*) FROM pages WHERE title = $1", date)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            assert_eq!(count, Some(1));
+        }
+    }
+
+    // Counts the statements sqlx runs on this thread while set as the default subscriber, from
+    // the sqlx::query events it logs for each one
+    #[derive(Clone, Default)]
+    struct StatementCounter(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+    impl StatementCounter {
+        fn count(&self) -> usize {
+            self.0.load(std::sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    impl tracing::Subscriber for StatementCounter {
+        fn enabled(&self, metadata: &tracing::Metadata<'_>) -> bool {
+            metadata.target() == "sqlx::query"
+        }
+        fn new_span(&self, _: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            tracing::span::Id::from_u64(1)
+        }
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+        fn event(&self, event: &tracing::Event<'_>) {
+            if event.metadata().target() == "sqlx::query" {
+                self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            }
+        }
+        fn enter(&self, _: &tracing::span::Id) {}
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    // Statements run by saving a page with `blocks` paragraphs over it, then by replacing them all
+    // with new ones. Each paragraph links to a page, references the first one and has a hashtag.
+    async fn statements_to_sync(pool: &PgPool, blocks: usize) -> (usize, usize) {
+        let page_id = create_page(pool, &format!("Page with {} blocks", blocks), json!({}), None).await.unwrap();
+        let content = || {
+            let ids: Vec<Uuid> = (0..blocks).map(|_| Uuid::new_v4()).collect();
+            let text = format!("See [[Target]], ((({}))) and #topic", ids[0]);
+            root(ids.iter().map(|id| paragraph(*id, &text)).collect())
+        };
+
+        let counter = StatementCounter::default();
+        let _guard = tracing::subscriber::set_default(counter.clone());
+        update_page(pool, page_id, None, Some(content()), None, None, false, None).await.unwrap();
+        let created = counter.count();
+        update_page(pool, page_id, None, Some(content()), None, None, false, None).await.unwrap();
+        let replaced = counter.count() - created;
+
+        assert_eq!(block_handler::get_blocks_for_page(pool, page_id).await.unwrap().len(), blocks);
+        assert_eq!(link_handler::find_outgoing_links_for_page(pool, page_id).await.unwrap().len(), 1);
+        assert_eq!(link_handler::get_outgoing_references_for_page(pool, page_id).await.unwrap().len(), blocks);
+        (created, replaced)
+    }
+
+    #[sqlx::test(migrator = "crate::db::MIGRATOR")]
+    async fn syncing_blocks_takes_the_same_statements_however_many_there_are(pool: PgPool) {
+        create_page(&pool, "Target", json!({}), None).await.unwrap();
+        let small = statements_to_sync(&pool, 10).await;
+        let large = statements_to_sync(&pool, 1000).await;
+        assert!(small.0 > 0 && small.1 > 0, "no statements were counted");
+        assert_eq!(large, small);
+    }
+}
@@ -0,0 +1,114 @@
+// Checks that the block, link and block reference rows of every page still match its
+// content_json, which they stop doing after a crash or a bug part-way through a save, and
+// optionally resyncs the pages that don't.
+
+use serde::Serialize;
+use sqlx::PgPool;
+use tauri::{AppHandle, Emitter};
+use uuid::Uuid;
+
+use crate::dal_error::DalError;
+use crate::page_events::{self, PageChangeKind};
+use crate::page_handler::{self, PageIntegrity};
+
+pub const EVENT_INTEGRITY_PROGRESS: &str = "integrity://progress";
+
+// Pages whose content is held in memory at once
+const BATCH_SIZE: usize = 100;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct IntegrityProgressEvent {
+    pub current: usize, // Pages checked so far
+    pub total: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct IntegrityFailure {
+    pub page_id: String,
+    pub error: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct IntegritySummary {
+    pub total_pages: usize,
+    pub pages_fixed: usize,
+    pub pages: Vec<PageIntegrity>, // Only the pages whose rows don't match
+    pub failures: Vec<IntegrityFailure>,
+}
+
+// Checks one page, resyncing it when asked and its rows don't match
+pub async fn verify_page(
+    pool: &PgPool,
+    app_handle: &AppHandle,
+    page_id: Uuid,
+    fix: bool,
+) -> Result<PageIntegrity, DalError> {
+    let mut report = page_handler::check_page_integrity(pool, &[page_id])
+        .await?
+        .pop()
+        .ok_or(DalError::NotFound)?;
+    if fix && !report.is_consistent() {
+        fix_page(pool, app_handle, &mut report).await?;
+    }
+    Ok(report)
+}
+
+// Checks every page, trashed ones included, a batch at a time, emitting a progress event after
+// each batch
+pub async fn verify_all_pages(pool: &PgPool, app_handle: &AppHandle, fix: bool) -> Result<IntegritySummary, DalError> {
+    let page_ids = page_handler::list_page_ids(pool).await?;
+    let mut summary = IntegritySummary {
+        total_pages: page_ids.len(),
+        ..Default::default()
+    };
+
+    let mut checked = 0;
+    for batch in page_ids.chunks(BATCH_SIZE) {
+        let reports = page_handler::check_page_integrity(pool, batch).await?;
+        checked += batch.len();
+        for mut report in reports.into_iter().filter(|report| !report.is_consistent()) {
+            // A page that fails to resync is recorded and the check moves on to the next one
+            if fix {
+                if let Err(e) = fix_page(pool, app_handle, &mut report).await {
+                    eprintln!("[Integrity] Failed to resync page {}: {}", report.page_id, e);
+                    summary.failures.push(IntegrityFailure {
+                        page_id: report.page_id.to_string(),
+                        error: e.to_string(),
+                    });
+                }
+            }
+            if report.fixed {
+                summary.pages_fixed += 1;
+            }
+            summary.pages.push(report);
+        }
+
+        let event = IntegrityProgressEvent {
+            current: checked,
+            total: page_ids.len(),
+        };
+        if let Err(e) = app_handle.emit(EVENT_INTEGRITY_PROGRESS, event) {
+            eprintln!("[Integrity] Failed to emit progress event: {}", e);
+        }
+    }
+
+    println!(
+        "[Integrity] {} of {} pages don't match their content, {} resynced",
+        summary.pages.len(),
+        summary.total_pages,
+        summary.pages_fixed
+    );
+    Ok(summary)
+}
+
+async fn fix_page(pool: &PgPool, app_handle: &AppHandle, report: &mut PageIntegrity) -> Result<(), DalError> {
+    let update = page_handler::resync_page(pool, report.page_id).await?;
+    report.fixed = true;
+    page_events::emit_page_changed(app_handle, report.page_id, Some(update.updated_at), PageChangeKind::Edited);
+    if !update.link_targets_changed.is_empty() {
+        let mut page_ids = vec![report.page_id];
+        page_ids.extend(&update.link_targets_changed);
+        page_events::emit_links_changed(app_handle, &page_ids);
+    }
+    Ok(())
+}
@@ -0,0 +1,90 @@
+// Repairs the block uniqueIDs of every page in the vault, for content imported or edited by
+// older versions whose blocks never got an ID (and so no block row).
+
+use serde::Serialize;
+use sqlx::PgPool;
+use tauri::{AppHandle, Emitter};
+
+use crate::dal_error::DalError;
+use crate::jobs::JobContext;
+use crate::page_handler;
+
+pub const EVENT_NORMALIZE_PROGRESS: &str = "normalize://progress";
+
+#[derive(Serialize, Debug, Clone)]
+pub struct NormalizeProgressEvent {
+    pub current: usize, // 1-based index of the page just processed
+    pub total: usize,
+    pub page_id: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct NormalizeFailure {
+    pub page_id: String,
+    pub error: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct NormalizeSummary {
+    pub total_pages: usize,
+    pub pages_changed: usize,
+    pub ids_added: usize,
+    pub ids_fixed: usize,
+    pub failures: Vec<NormalizeFailure>,
+}
+
+// Run as a job, it stops before the next page once cancelled; each page is saved in its own
+// transaction, so the pages before it stay repaired
+pub async fn normalize_all_pages(
+    pool: &PgPool,
+    app_handle: &AppHandle,
+    job: Option<&JobContext>,
+) -> Result<NormalizeSummary, DalError> {
+    let page_ids = page_handler::list_page_ids(pool).await?;
+    let mut summary = NormalizeSummary {
+        total_pages: page_ids.len(),
+        ..Default::default()
+    };
+
+    // A failing page is recorded and the repair moves on to the next one
+    for (index, page_id) in page_ids.iter().enumerate() {
+        if job.is_some_and(JobContext::is_cancelled) {
+            println!("[Normalize] Cancelled after {} of {} pages", index, page_ids.len());
+            break;
+        }
+        match page_handler::normalize_page(pool, *page_id).await {
+            Ok(repair) => {
+                if repair.changed() {
+                    summary.pages_changed += 1;
+                }
+                summary.ids_added += repair.ids_added;
+                summary.ids_fixed += repair.ids_fixed;
+            }
+            Err(e) => {
+                eprintln!("[Normalize] Failed to normalize page {}: {}", page_id, e);
+                summary.failures.push(NormalizeFailure {
+                    page_id: page_id.to_string(),
+                    error: e.to_string(),
+                });
+            }
+        }
+
+        let event = NormalizeProgressEvent {
+            current: index + 1,
+            total: page_ids.len(),
+            page_id: page_id.to_string(),
+        };
+        if let Err(e) = app_handle.emit(EVENT_NORMALIZE_PROGRESS, event) {
+            eprintln!("[Normalize] Failed to emit progress event: {}", e);
+        }
+        if let Some(job) = job {
+            job.progress(index + 1, page_ids.len(), page_id.to_string());
+        }
+    }
+
+    println!(
+        "[Normalize] {} of {} pages changed: {} IDs added, {} fixed",
+        summary.pages_changed, summary.total_pages, summary.ids_added, summary.ids_fixed
+    );
+    Ok(summary)
+}
@@ -0,0 +1,296 @@
+// Salvages recordings left behind by a crash. The writer thread only finalizes the WAV header
+// when a recording is stopped, so after a crash the file claims to hold no audio and there is
+// no finished audio_recordings row for it. recover_recordings finds such `<uuid>.wav` files (or
+// `<uuid>_mic.wav` and `<uuid>_system.wav` for split tracks), rewrites the RIFF and data chunk
+// sizes from the file length and saves the row. A recording that shutdown couldn't finish in
+// time leaves a `<uuid>.recovery.json` note beside it with its page and start time.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::audio::{self, TrackMode};
+use crate::audio_encoder::AudioFormat;
+use crate::audio_handler;
+use crate::vault_crypto;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RecoveredRecording {
+    pub recording_id: Uuid,
+    pub file_path: String,
+    pub secondary_file_path: Option<String>, // System track of a split recording
+    pub duration_ms: i32,
+    pub header_repaired: bool, // False when the header was already finalized
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RecoveryFailure {
+    pub file_path: String,
+    pub error: String,
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct RecoveryReport {
+    pub recovered: Vec<RecoveredRecording>,
+    pub empty_files: Vec<String>, // Files without audio frames; left on disk and not saved
+    pub failures: Vec<RecoveryFailure>,
+}
+
+impl RecoveryReport {
+    pub fn is_empty(&self) -> bool {
+        self.recovered.is_empty() && self.empty_files.is_empty() && self.failures.is_empty()
+    }
+}
+
+// Left beside a recording's file by shutdown when the recording couldn't be finished and saved
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecoveryNote {
+    pub recording_id: Uuid,
+    pub page_id: Option<Uuid>,
+    #[serde(default)] // Notes left by versions before recordings had titles
+    pub title: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub file_path: String,
+}
+
+fn recovery_note_path(audio_dir: &Path, recording_id: Uuid) -> PathBuf {
+    audio_dir.join(format!("{}.recovery.json", recording_id))
+}
+
+pub fn write_recovery_note(note: &RecoveryNote) -> Result<(), String> {
+    let audio_dir = Path::new(&note.file_path)
+        .parent()
+        .ok_or_else(|| format!("Recording file {} has no parent directory", note.file_path))?;
+    let json = serde_json::to_vec_pretty(note).map_err(|e| e.to_string())?;
+    let mut file = File::create(recovery_note_path(audio_dir, note.recording_id)).map_err(|e| e.to_string())?;
+    file.write_all(&json).and_then(|_| file.sync_all()).map_err(|e| e.to_string())
+}
+
+// The note for a recording, if shutdown left one. An unreadable note is ignored.
+fn read_recovery_note(audio_dir: &Path, recording_id: Uuid) -> Option<RecoveryNote> {
+    let json = std::fs::read(recovery_note_path(audio_dir, recording_id)).ok()?;
+    serde_json::from_slice(&json)
+        .map_err(|e| eprintln!("[AudioRecovery] Ignoring unreadable recovery note for {}: {}", recording_id, e))
+        .ok()
+}
+
+// What a WAV header repair found
+struct WavRepair {
+    frames: u64,
+    sample_rate: u32,
+    channels: u16,
+    repaired: bool,
+}
+
+pub async fn recover_recordings(pool: &PgPool, audio_dir: &Path) -> Result<RecoveryReport, String> {
+    let mut report = RecoveryReport::default();
+    if !audio_dir.exists() {
+        return Ok(report);
+    }
+
+    // Recordings still running have an unfinished header too, and must be left alone
+    let mut candidates: Vec<(Uuid, PathBuf)> = Vec::new();
+    let entries = std::fs::read_dir(audio_dir).map_err(|e| format!("Failed to read audio directory: {}", e))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_wav = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case(AudioFormat::Wav.extension()));
+        // A split recording is found by its mic track; the system track is picked up with it
+        let id = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| Uuid::parse_str(stem.strip_suffix("_mic").unwrap_or(stem)).ok());
+        if let (true, Some(id)) = (is_wav, id) {
+            if path.is_file() && !audio::is_recording_active(&id.to_string()) {
+                candidates.push((id, path));
+            }
+        }
+    }
+    if candidates.is_empty() {
+        return Ok(report);
+    }
+
+    let ids: Vec<Uuid> = candidates.iter().map(|(id, _)| *id).collect();
+    let unfinished = audio_handler::find_unfinished_recording_ids(pool, &ids)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    for (id, path) in candidates.into_iter().filter(|(id, _)| unfinished.contains(id)) {
+        let file_path = path.to_string_lossy().to_string();
+        let repair = match repair_wav_header(&path) {
+            Ok(repair) => repair,
+            Err(e) => {
+                report.failures.push(RecoveryFailure {
+                    file_path,
+                    error: format!("Failed to repair WAV header: {}", e),
+                });
+                continue;
+            }
+        };
+        if repair.frames == 0 {
+            report.empty_files.push(file_path);
+            continue;
+        }
+
+        let is_split = path.file_stem().and_then(|stem| stem.to_str()).is_some_and(|stem| stem.ends_with("_mic"));
+        let track_mode = match (is_split, repair.channels) {
+            (true, _) => TrackMode::Split,
+            (false, 1) => TrackMode::MonoMixed,
+            (false, _) => TrackMode::Mixed,
+        };
+        let mut secondary_file_path: Option<String> = None;
+        let mut secondary_repaired = false;
+        let system_path = audio_dir.join(format!("{}_system.{}", id, AudioFormat::Wav.extension()));
+        if is_split && system_path.is_file() {
+            let system_file_path = system_path.to_string_lossy().to_string();
+            match repair_wav_header(&system_path) {
+                Ok(system_repair) => {
+                    secondary_file_path = Some(system_file_path);
+                    secondary_repaired = system_repair.repaired;
+                }
+                // The mic track is still worth saving on its own
+                Err(e) => report.failures.push(RecoveryFailure {
+                    file_path: system_file_path,
+                    error: format!("Failed to repair WAV header: {}", e),
+                }),
+            }
+        }
+
+        let duration_ms = match i32::try_from(repair.frames * 1000 / repair.sample_rate as u64) {
+            Ok(duration_ms) => duration_ms,
+            Err(_) => {
+                report.failures.push(RecoveryFailure {
+                    file_path,
+                    error: "Recording is too long to store its duration".to_string(),
+                });
+                continue;
+            }
+        };
+        let note = read_recovery_note(audio_dir, id);
+        if let Err(e) = audio_handler::save_recovered_recording(
+            pool,
+            id,
+            note.as_ref().and_then(|note| note.page_id),
+            &file_path,
+            secondary_file_path.as_deref(),
+            track_mode.as_str(),
+            AudioFormat::Wav.mime_type(),
+            duration_ms,
+            note.as_ref().map(|note| note.started_at),
+            note.as_ref().and_then(|note| note.title.as_deref()),
+        )
+        .await
+        {
+            report.failures.push(RecoveryFailure {
+                file_path,
+                error: format!("Failed to save recording metadata: {}", e),
+            });
+            continue;
+        }
+        if note.is_some() {
+            if let Err(e) = std::fs::remove_file(recovery_note_path(audio_dir, id)) {
+                eprintln!("[AudioRecovery] Failed to remove recovery note for {}: {}", id, e);
+            }
+        }
+
+        println!(
+            "[AudioRecovery] Recovered recording {} ({}ms, header repaired: {})",
+            id,
+            duration_ms,
+            repair.repaired || secondary_repaired
+        );
+        report.recovered.push(RecoveredRecording {
+            recording_id: id,
+            file_path,
+            secondary_file_path,
+            duration_ms,
+            header_repaired: repair.repaired || secondary_repaired,
+        });
+    }
+
+    Ok(report)
+}
+
+// Recomputes the RIFF and data chunk sizes from the file length. A file cut off in the middle
+// of a frame is truncated to the last whole frame.
+fn repair_wav_header(path: &Path) -> io::Result<WavRepair> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    // Its header is sealed with the rest of the file, and the audio after the last whole chunk
+    // was never written
+    if vault_crypto::is_encrypted(path)? {
+        return Err(invalid("Encrypted recordings can't be repaired after a crash"));
+    }
+
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let file_len = file.metadata()?.len();
+
+    let mut riff_header = [0u8; 12];
+    file.read_exact(&mut riff_header)?;
+    if &riff_header[0..4] != b"RIFF" || &riff_header[8..12] != b"WAVE" {
+        return Err(invalid("Not a WAV file"));
+    }
+    let stored_riff_len = u32::from_le_bytes([riff_header[4], riff_header[5], riff_header[6], riff_header[7]]);
+
+    // Walk the chunks up to the data chunk, whose size is the one left unwritten
+    let mut format: Option<(u32, u16, u16)> = None; // (sample rate, channels, bytes per frame)
+    let mut position: u64 = 12;
+    let (data_offset, stored_data_len) = loop {
+        if position + 8 > file_len {
+            return Err(invalid("WAV file has no data chunk"));
+        }
+        file.seek(SeekFrom::Start(position))?;
+        let mut chunk_header = [0u8; 8];
+        file.read_exact(&mut chunk_header)?;
+        let chunk_len = u32::from_le_bytes([chunk_header[4], chunk_header[5], chunk_header[6], chunk_header[7]]);
+        match &chunk_header[0..4] {
+            b"fmt " => {
+                let mut fmt = [0u8; 16];
+                file.read_exact(&mut fmt)?;
+                let channels = u16::from_le_bytes([fmt[2], fmt[3]]);
+                let sample_rate = u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]);
+                let block_align = u16::from_le_bytes([fmt[12], fmt[13]]);
+                format = Some((sample_rate, channels, block_align));
+            }
+            b"data" => break (position + 8, chunk_len),
+            _ => {}
+        }
+        position += 8 + chunk_len as u64 + (chunk_len as u64 & 1); // Chunks are padded to even sizes
+    };
+
+    let (sample_rate, channels, block_align) = format.ok_or_else(|| invalid("WAV file has no fmt chunk"))?;
+    if sample_rate == 0 || block_align == 0 {
+        return Err(invalid("WAV fmt chunk is invalid"));
+    }
+
+    let frames = (file_len - data_offset) / block_align as u64;
+    let data_len = u32::try_from(frames * block_align as u64)
+        .map_err(|_| invalid("Recording is too large for a WAV header"))?;
+    let riff_len = u32::try_from(data_offset - 8 + data_len as u64)
+        .map_err(|_| invalid("Recording is too large for a WAV header"))?;
+
+    let repaired =
+        stored_riff_len != riff_len || stored_data_len != data_len || file_len != data_offset + data_len as u64;
+    if repaired {
+        file.set_len(data_offset + data_len as u64)?;
+        write_u32_at(&mut file, 4, riff_len)?;
+        write_u32_at(&mut file, data_offset - 4, data_len)?;
+        file.sync_all()?;
+    }
+
+    Ok(WavRepair {
+        frames,
+        sample_rate,
+        channels,
+        repaired,
+    })
+}
+
+fn write_u32_at(file: &mut File, offset: u64, value: u32) -> io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(&value.to_le_bytes())
+}
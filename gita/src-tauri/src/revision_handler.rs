@@ -0,0 +1,213 @@
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgExecutor};
+use uuid::Uuid;
+
+// Import the shared DalError
+use crate::dal_error::DalError;
+use crate::json_utils;
+use crate::markdown_export;
+
+// Older revisions of a page are pruned once it has this many
+pub const MAX_REVISIONS_PER_PAGE: i64 = 50;
+
+// A revision without its content, for listing a page's history
+#[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct PageRevisionMetadata {
+    pub id: Uuid,
+    pub page_id: Uuid,
+    pub change_summary: Option<String>,
+    pub page_updated_at: DateTime<Utc>, // When this content was saved
+    pub created_at: DateTime<Utc>,      // When it was replaced
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct PageRevision {
+    pub id: Uuid,
+    pub page_id: Uuid,
+    pub content_json: Value,
+    pub raw_markdown: Option<String>,
+    pub content_hash: String,
+    pub change_summary: Option<String>,
+    pub page_updated_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+// SHA-256 (hex) of a page's content. content_json is hashed in its canonical form, so equal
+// content always hashes the same.
+pub fn content_hash(content_json: &Value, raw_markdown: Option<&str>) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(json_utils::canonical_json(content_json).as_bytes());
+    hasher.update([0u8]);
+    if let Some(markdown) = raw_markdown {
+        hasher.update([1u8]);
+        hasher.update(markdown.as_bytes());
+    }
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Saves content a page is about to be changed from, then drops the page's revisions beyond
+// MAX_REVISIONS_PER_PAGE, oldest first
+pub async fn add_revision(
+    tx: &mut PgConnection,
+    page_id: Uuid,
+    content_json: &Value,
+    raw_markdown: Option<&str>,
+    content_hash: &str,
+    change_summary: Option<&str>,
+    page_updated_at: DateTime<Utc>,
+) -> Result<Uuid, DalError> {
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO page_revisions (id, page_id, content_json, raw_markdown, content_hash, change_summary, page_updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id
+        "#,
+        Uuid::new_v4(),
+        page_id,
+        content_json,
+        raw_markdown,
+        content_hash,
+        change_summary,
+        page_updated_at
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM page_revisions
+        WHERE page_id = $1
+          AND id NOT IN (
+              SELECT id FROM page_revisions
+              WHERE page_id = $1
+              ORDER BY created_at DESC, id
+              LIMIT $2
+          )
+        "#,
+        page_id,
+        MAX_REVISIONS_PER_PAGE
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(id)
+}
+
+// Newest first
+pub async fn list_revisions<'e>(executor: impl PgExecutor<'e>, page_id: Uuid) -> Result<Vec<PageRevisionMetadata>, DalError> {
+    let revisions = sqlx::query_as!(
+        PageRevisionMetadata,
+        r#"
+        SELECT id, page_id, change_summary, page_updated_at, created_at
+        FROM page_revisions
+        WHERE page_id = $1
+        ORDER BY created_at DESC, id
+        "#,
+        page_id
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(revisions)
+}
+
+impl PageRevision {
+    // The revision's Markdown, rendered from content_json when it has none
+    pub fn markdown(&self) -> String {
+        match &self.raw_markdown {
+            Some(markdown) => markdown.clone(),
+            None => markdown_export::render_markdown(&self.content_json),
+        }
+    }
+}
+
+// Returns DalError::NotFound if there is no revision with this ID
+pub async fn get_revision<'e>(executor: impl PgExecutor<'e>, id: Uuid) -> Result<PageRevision, DalError> {
+    let revision = sqlx::query_as!(
+        PageRevision,
+        r#"
+        SELECT id, page_id, content_json, raw_markdown, content_hash, change_summary, page_updated_at, created_at
+        FROM page_revisions
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(executor)
+    .await?;
+
+    revision.ok_or(DalError::NotFound)
+}
+
+// --- Diffs ---
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffOp {
+    Equal,
+    Delete, // Only in the old text
+    Insert, // Only in the new text
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct DiffLine {
+    pub op: DiffOp,
+    pub text: String,
+}
+
+// Above this many line pairs (after trimming the common start and end) the changed middle is
+// shown as deleted then inserted, rather than matched line by line
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+// Line-based diff of two texts, from a longest common subsequence of their lines
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let line = |op: DiffOp, text: &str| DiffLine { op, text: text.to_string() };
+
+    let prefix = old_lines.iter().zip(&new_lines).take_while(|(a, b)| a == b).count();
+    let suffix = old_lines[prefix..]
+        .iter()
+        .rev()
+        .zip(new_lines[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old_lines[prefix..old_lines.len() - suffix];
+    let new_mid = &new_lines[prefix..new_lines.len() - suffix];
+
+    let mut diff: Vec<DiffLine> = old_lines[..prefix].iter().map(|text| line(DiffOp::Equal, text)).collect();
+    if old_mid.len().saturating_mul(new_mid.len()) > MAX_DIFF_CELLS {
+        diff.extend(old_mid.iter().map(|text| line(DiffOp::Delete, text)));
+        diff.extend(new_mid.iter().map(|text| line(DiffOp::Insert, text)));
+    } else {
+        // lcs[i][j]: length of the longest common subsequence of old_mid[i..] and new_mid[j..]
+        let (n, m) = (old_mid.len(), new_mid.len());
+        let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i][j] = if old_mid[i] == new_mid[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && old_mid[i] == new_mid[j] {
+                diff.push(line(DiffOp::Equal, old_mid[i]));
+                i += 1;
+                j += 1;
+            } else if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+                diff.push(line(DiffOp::Delete, old_mid[i]));
+                i += 1;
+            } else {
+                diff.push(line(DiffOp::Insert, new_mid[j]));
+                j += 1;
+            }
+        }
+    }
+    diff.extend(old_lines[old_lines.len() - suffix..].iter().map(|text| line(DiffOp::Equal, text)));
+    diff
+}
@@ -0,0 +1,550 @@
+// Imports a Roam Research graph exported as JSON: an array of pages, each with a title and a
+// tree of blocks. A page becomes an outline of list items, one per block, in Roam's order and
+// nesting. Roam's uids are mapped to IDs here and the mapping is kept in imported_uids, so
+// `((uid))` references become `(((id)))` references to the same blocks and re-running an
+// import updates the pages it made before instead of duplicating them.
+//
+// The file is parsed one page at a time, so exports of several hundred MB don't need to fit in
+// memory. A page that can't be imported is reported and the import goes on with the next one.
+
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use serde::de::{Deserializer, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::import_handler;
+use crate::markdown_export;
+use crate::page_handler::{self, text_node};
+use crate::vault_import::{self, ImportProgressEvent, EVENT_IMPORT_PROGRESS};
+
+const SOURCE: &str = "roam";
+const PAGE_BUFFER: usize = 16; // Pages parsed ahead of the one being written
+
+lazy_static! {
+    // {{embed: ((uid))}} and {{[[embed]]: ((uid))}}, which show a block in place
+    static ref EMBED_REGEX: Regex =
+        Regex::new(r"\{\{\s*(?:\[\[)?embed(?:-path|-children)?(?:\]\])?\s*:\s*(\(\([\w-]+\)\))\s*\}\}").unwrap();
+    static ref UID_REF_REGEX: Regex = Regex::new(r"\(\(([\w-]+)\)\)").unwrap();
+    static ref TAG_LINK_REGEX: Regex = Regex::new(r"#\[\[([^\[\]]+)\]\]").unwrap();
+    static ref TASK_REGEX: Regex = Regex::new(r"^\s*\{\{\s*(?:\[\[)?(TODO|DONE)(?:\]\])?\s*\}\}\s*").unwrap();
+    static ref MARKDOWN_LINK_REGEX: Regex = Regex::new(r"\[([^\[\]]*)\]\(([^()\s]+)\)").unwrap();
+}
+
+// Roam's inline marks and the editor's format bits for them
+const MARKS: [(&str, u64); 4] = [("**", 1), ("__", 2), ("~~", 4), ("^^", 128)];
+const CODE_FORMAT: u64 = 16;
+
+#[derive(Deserialize, Debug)]
+struct RoamPage {
+    title: String,
+    #[serde(default)]
+    uid: Option<String>,
+    #[serde(default)]
+    children: Vec<RoamBlock>,
+    #[serde(rename = "create-time", default)]
+    create_time: Option<i64>, // Milliseconds since the epoch
+}
+
+#[derive(Deserialize, Debug)]
+struct RoamBlock {
+    #[serde(default)]
+    string: String,
+    #[serde(default)]
+    uid: String,
+    #[serde(default)]
+    children: Vec<RoamBlock>,
+    #[serde(default)]
+    heading: Option<u8>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RoamImportFailure {
+    pub title: Option<String>, // None when the page couldn't be read at all
+    pub error: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct RoamImportSummary {
+    pub total_pages: usize,
+    pub created: usize,
+    pub updated: usize,       // Pages from an earlier import of the same graph
+    pub filled_stubs: usize,  // Placeholder pages (created for link targets) that got their blocks
+    pub stubs_created: usize, // Empty pages created for links to pages that aren't in the export
+    pub blocks: usize,
+    pub failures: Vec<RoamImportFailure>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PageOutcome {
+    Created,
+    FilledStub,
+    Updated,
+}
+
+// References that can only be stored once the whole file has been read
+#[derive(Default)]
+struct PendingReferences {
+    pages: HashSet<Uuid>,           // Pages referencing blocks that didn't exist yet when written
+    remapped: HashMap<Uuid, Uuid>,  // Old ID to new ID of blocks that moved to another page
+}
+
+// Progress events are the vault import's, with a total of 0: the number of pages isn't known
+// until the whole file has been read
+pub async fn import_roam_json(pool: &PgPool, app_handle: &AppHandle, path: &Path) -> Result<RoamImportSummary, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let (sender, mut receiver) = mpsc::channel(PAGE_BUFFER);
+    let parser = tokio::task::spawn_blocking(move || stream_pages(file, sender));
+
+    let mut summary = RoamImportSummary::default();
+    let mut pending = PendingReferences::default();
+    while let Some(page) = receiver.recv().await {
+        summary.total_pages += 1;
+        let title = page.get("title").and_then(|v| v.as_str()).map(String::from);
+        if let Err(e) = import_page(pool, page, &mut summary, &mut pending).await {
+            let label = title.clone().unwrap_or_else(|| format!("page {}", summary.total_pages));
+            eprintln!("[Roam import] Failed to import {}: {}", label, e);
+            summary.failures.push(RoamImportFailure { title: title.clone(), error: e });
+        }
+
+        let event = ImportProgressEvent {
+            current: summary.total_pages,
+            total: 0,
+            path: title.unwrap_or_default(),
+        };
+        if let Err(e) = app_handle.emit(EVENT_IMPORT_PROGRESS, event) {
+            eprintln!("[Roam import] Failed to emit progress event: {}", e);
+        }
+    }
+
+    // A syntax error ends the stream early; the pages before it have been imported
+    match parser.await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) if summary.total_pages == 0 => return Err(format!("Not a Roam JSON export: {}", e)),
+        Ok(Err(e)) => summary.failures.push(RoamImportFailure {
+            title: None,
+            error: format!("The file couldn't be read after page {}: {}", summary.total_pages, e),
+        }),
+        Err(e) => return Err(format!("Failed to read the export: {}", e)),
+    }
+
+    store_pending_references(pool, &pending, &mut summary).await;
+    Ok(summary)
+}
+
+// Parses the top-level array one page at a time, handing each to the importer. Stops early if
+// the importer has gone away.
+fn stream_pages(file: File, sender: mpsc::Sender<Value>) -> Result<(), String> {
+    struct PageStream(mpsc::Sender<Value>);
+
+    impl<'de> Visitor<'de> for PageStream {
+        type Value = ();
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("an array of Roam pages")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+            while let Some(page) = seq.next_element::<Value>()? {
+                if self.0.blocking_send(page).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(file));
+    deserializer.deserialize_seq(PageStream(sender)).map_err(|e| e.to_string())
+}
+
+async fn import_page(
+    pool: &PgPool,
+    page: Value,
+    summary: &mut RoamImportSummary,
+    pending: &mut PendingReferences,
+) -> Result<(), String> {
+    let mut page: RoamPage = serde_json::from_value(page).map_err(|e| format!("Not a Roam page: {}", e))?;
+    let title = page.title.trim().to_string();
+    if title.is_empty() {
+        return Err("The page has no title".to_string());
+    }
+    // Pages are matched to earlier imports by uid; exports without page uids fall back to titles
+    let page_key = match page.uid.as_deref().map(str::trim) {
+        Some(uid) if !uid.is_empty() => uid.to_string(),
+        _ => format!("title:{}", title),
+    };
+    assign_missing_uids(&mut page.children, &page_key);
+
+    // Every uid the page holds or references gets its ID up front
+    let mut block_uids = Vec::new();
+    let mut referenced_uids = Vec::new();
+    collect_uids(&page.children, &mut block_uids, &mut referenced_uids);
+    let mut all_uids: Vec<String> = block_uids.iter().chain(&referenced_uids).cloned().collect();
+    all_uids.sort();
+    all_uids.dedup();
+    let mut ids = import_handler::map_import_uids(pool, SOURCE, &all_uids)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let (page_id, outcome) = match import_handler::get_imported_page(pool, SOURCE, &page_key)
+        .await
+        .map_err(|e| e.to_string())?
+    {
+        Some(page_id) => (page_id, PageOutcome::Updated),
+        None => {
+            let (page_id, outcome) = match page_handler::get_page_by_title(pool, &title)
+                .await
+                .map_err(|e| e.to_string())?
+            {
+                Some(existing) if vault_import::is_stub(&existing) => (existing.id, PageOutcome::FilledStub),
+                Some(_) => return Err(format!("A page titled \"{}\" already exists and wasn't imported from Roam", title)),
+                None => {
+                    let page_id = page_handler::create_page(pool, &title, serde_json::json!({}), None)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    (page_id, PageOutcome::Created)
+                }
+            };
+            import_handler::set_imported_page(pool, SOURCE, &page_key, page_id)
+                .await
+                .map_err(|e| e.to_string())?;
+            (page_id, outcome)
+        }
+    };
+
+    // A block moved here from another page since the last import still has its old ID there
+    // until that page is imported again, so it gets a new one
+    let block_ids: Vec<Uuid> = block_uids.iter().filter_map(|uid| ids.get(uid).copied()).collect();
+    let taken = import_handler::blocks_on_other_pages(pool, page_id, &block_ids)
+        .await
+        .map_err(|e| e.to_string())?;
+    if !taken.is_empty() {
+        let moved: Vec<String> = block_uids
+            .iter()
+            .filter(|uid| ids.get(*uid).is_some_and(|id| taken.contains(id)))
+            .cloned()
+            .collect();
+        let fresh = import_handler::remap_import_uids(pool, SOURCE, &moved)
+            .await
+            .map_err(|e| e.to_string())?;
+        for (uid, id) in fresh {
+            if let Some(old_id) = ids.insert(uid, id) {
+                pending.remapped.insert(old_id, id);
+            }
+        }
+    }
+
+    // A page without blocks stays a placeholder unless it had blocks from an earlier import
+    if page.children.is_empty() && outcome != PageOutcome::Updated {
+        count_page(summary, outcome);
+        return Ok(());
+    }
+
+    let mut link_targets = Vec::new();
+    let lists = block_lists(&page.children, &ids, &mut link_targets);
+    let content = page_handler::new_page_content(&Value::Null, lists, &[]);
+
+    // Link targets must exist before the page is written for its links to be stored
+    link_targets.sort();
+    link_targets.dedup();
+    for target in link_targets {
+        if target == title || Uuid::parse_str(&target).is_ok() {
+            continue;
+        }
+        let existing = page_handler::get_page_by_title(pool, &target)
+            .await
+            .map_err(|e| e.to_string())?;
+        if existing.is_none() {
+            page_handler::create_page(pool, &target, serde_json::json!({}), None)
+                .await
+                .map_err(|e| e.to_string())?;
+            summary.stubs_created += 1;
+        }
+    }
+
+    let markdown = markdown_export::render_markdown(&content);
+    page_handler::update_page(pool, page_id, Some(&title), Some(content), Some(Some(&markdown)), None, false, Some("Roam import"))
+        .await
+        .map_err(|e| e.to_string())?;
+    import_handler::set_imported_block_page(pool, SOURCE, &block_uids, page_id)
+        .await
+        .map_err(|e| e.to_string())?;
+    if outcome != PageOutcome::Updated {
+        if let Some(created_at) = page.create_time.and_then(chrono::DateTime::from_timestamp_millis) {
+            page_handler::set_page_created_at(pool, page_id, created_at)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+
+    // References to blocks further on in the file are stored once it has been read
+    let referenced_ids: Vec<Uuid> = referenced_uids.iter().filter_map(|uid| ids.get(uid).copied()).collect();
+    let missing = import_handler::missing_block_ids(pool, &referenced_ids)
+        .await
+        .map_err(|e| e.to_string())?;
+    if !missing.is_empty() {
+        pending.pages.insert(page_id);
+    }
+
+    summary.blocks += block_uids.len();
+    count_page(summary, outcome);
+    Ok(())
+}
+
+fn count_page(summary: &mut RoamImportSummary, outcome: PageOutcome) {
+    match outcome {
+        PageOutcome::Created => summary.created += 1,
+        PageOutcome::FilledStub => summary.filled_stubs += 1,
+        PageOutcome::Updated => summary.updated += 1,
+    }
+}
+
+// Rewrites pages whose references couldn't be stored when they were imported: references to
+// blocks that came later in the file, and references to blocks that moved and got a new ID
+async fn store_pending_references(pool: &PgPool, pending: &PendingReferences, summary: &mut RoamImportSummary) {
+    let mut page_ids: Vec<Uuid> = pending.pages.iter().copied().collect();
+    if !pending.remapped.is_empty() {
+        let old_ids: Vec<Uuid> = pending.remapped.keys().copied().collect();
+        match import_handler::pages_referencing_blocks(pool, &old_ids).await {
+            Ok(referencing) => page_ids.extend(referencing),
+            Err(e) => summary.failures.push(RoamImportFailure {
+                title: None,
+                error: format!("Failed to find references to moved blocks: {}", e),
+            }),
+        }
+    }
+    page_ids.sort();
+    page_ids.dedup();
+
+    for page_id in page_ids {
+        if let Err((title, e)) = rewrite_references(pool, page_id, &pending.remapped).await {
+            eprintln!("[Roam import] Failed to store references of page {}: {}", page_id, e);
+            summary.failures.push(RoamImportFailure { title, error: e });
+        }
+    }
+}
+
+// Saves the page again so its references are stored, pointing references to moved blocks at
+// their new IDs. The page may have been written by hand, so its Markdown is only edited in place.
+async fn rewrite_references(
+    pool: &PgPool,
+    page_id: Uuid,
+    remapped: &HashMap<Uuid, Uuid>,
+) -> Result<(), (Option<String>, String)> {
+    let page = page_handler::get_page(pool, page_id).await.map_err(|e| (None, e.to_string()))?;
+    let replace_ids = |text: &str| {
+        remapped.iter().fold(text.to_string(), |text, (old_id, new_id)| {
+            text.replace(&format!("((({})))", old_id), &format!("((({})))", new_id))
+        })
+    };
+    let content: Value = serde_json::from_str(&replace_ids(&page.content_json.to_string()))
+        .map_err(|e| (Some(page.title.clone()), e.to_string()))?;
+    let markdown = page.raw_markdown.as_deref().map(replace_ids);
+    page_handler::update_page(
+        pool,
+        page_id,
+        None,
+        Some(content),
+        Some(markdown.as_deref()),
+        Some(page.updated_at),
+        false,
+        None,
+    )
+    .await
+    .map_err(|e| (Some(page.title), e.to_string()))?;
+    Ok(())
+}
+
+// Blocks without a uid are keyed by their position, so re-imports still find them
+fn assign_missing_uids(blocks: &mut [RoamBlock], prefix: &str) {
+    for (index, block) in blocks.iter_mut().enumerate() {
+        let path = format!("{}/{}", prefix, index);
+        if block.uid.trim().is_empty() {
+            block.uid = path.clone();
+        }
+        assign_missing_uids(&mut block.children, &path);
+    }
+}
+
+fn collect_uids(blocks: &[RoamBlock], block_uids: &mut Vec<String>, referenced_uids: &mut Vec<String>) {
+    for block in blocks {
+        block_uids.push(block.uid.clone());
+        referenced_uids.extend(UID_REF_REGEX.captures_iter(&block.string).map(|cap| cap[1].to_string()));
+        collect_uids(&block.children, block_uids, referenced_uids);
+    }
+}
+
+// Blocks as list items, children in a nested list after their parent. Consecutive TODO/DONE
+// blocks form check lists and the rest bullet lists. Adds the `[[...]]` targets to link_targets.
+fn block_lists(blocks: &[RoamBlock], ids: &HashMap<String, Uuid>, link_targets: &mut Vec<String>) -> Vec<Value> {
+    let mut lists = Vec::new();
+    let mut items: Vec<Value> = Vec::new();
+    let mut list_type = "bullet";
+    for block in blocks {
+        let (checked, text) = convert_block_string(&block.string, ids);
+        let item_list_type = if checked.is_some() { "check" } else { "bullet" };
+        if item_list_type != list_type && !items.is_empty() {
+            lists.push(list_node(list_type, std::mem::take(&mut items)));
+        }
+        list_type = item_list_type;
+        link_targets.extend(page_handler::page_link_targets(&text));
+
+        // Lexical has no headings inside list items, so Roam's headings are kept as bold text
+        let base_format = if block.heading.is_some_and(|level| level > 0) { 1 } else { 0 };
+        let mut item = serde_json::json!({
+            "children": inline_nodes(&text, base_format),
+            "direction": "ltr",
+            "format": "",
+            "indent": 0,
+            "type": "listitem",
+            "value": items.len() + 1,
+            "version": 1
+        });
+        if let Some(id) = ids.get(&block.uid) {
+            item["uniqueID"] = Value::from(id.to_string());
+        }
+        if let Some(checked) = checked {
+            item["checked"] = Value::Bool(checked);
+        }
+        items.push(item);
+
+        if !block.children.is_empty() {
+            items.push(serde_json::json!({
+                "children": block_lists(&block.children, ids, link_targets),
+                "direction": "ltr",
+                "format": "",
+                "indent": 0,
+                "type": "listitem",
+                "value": items.len() + 1,
+                "version": 1
+            }));
+        }
+    }
+    if !items.is_empty() {
+        lists.push(list_node(list_type, items));
+    }
+    lists
+}
+
+fn list_node(list_type: &str, items: Vec<Value>) -> Value {
+    serde_json::json!({
+        "children": items,
+        "direction": "ltr",
+        "format": "",
+        "indent": 0,
+        "listType": list_type,
+        "start": 1,
+        "tag": "ul",
+        "type": "list",
+        "version": 1
+    })
+}
+
+// A block's text in this app's conventions, and whether it is a done (true) or open task.
+// Embeds become references and `#[[Tag]]` a page link; unknown uids are left as they are.
+fn convert_block_string(string: &str, ids: &HashMap<String, Uuid>) -> (Option<bool>, String) {
+    let (checked, rest) = match TASK_REGEX.captures(string) {
+        Some(cap) => (Some(&cap[1] == "DONE"), &string[cap[0].len()..]),
+        None => (None, string),
+    };
+    let text = EMBED_REGEX.replace_all(rest, "$1");
+    let text = TAG_LINK_REGEX.replace_all(&text, "[[$1]]");
+    let text = UID_REF_REGEX.replace_all(&text, |cap: &Captures| match ids.get(&cap[1]) {
+        Some(id) => format!("((({})))", id),
+        None => cap[0].to_string(),
+    });
+    (checked, text.into_owned())
+}
+
+// Text, Markdown links and line breaks as Lexical inline nodes
+fn inline_nodes(text: &str, base_format: u64) -> Vec<Value> {
+    let mut nodes = Vec::new();
+    for (index, line) in text.split('\n').enumerate() {
+        if index > 0 {
+            nodes.push(serde_json::json!({ "type": "linebreak", "version": 1 }));
+        }
+        let mut last = 0;
+        for cap in MARKDOWN_LINK_REGEX.captures_iter(line) {
+            let whole = cap.get(0).map(|m| m.range()).unwrap_or_default();
+            push_marked_text(&mut nodes, &line[last..whole.start], base_format);
+            let (label, url) = (cap[1].trim(), &cap[2]);
+            if url.starts_with("[[") {
+                // Roam's [alias]([[Page]]) is a page link
+                push_marked_text(&mut nodes, url, base_format);
+            } else {
+                nodes.push(serde_json::json!({
+                    "children": [text_node(if label.is_empty() { url } else { label }, base_format)],
+                    "direction": "ltr",
+                    "format": "",
+                    "indent": 0,
+                    "rel": "noreferrer",
+                    "target": null,
+                    "title": null,
+                    "type": "link",
+                    "url": url,
+                    "version": 1
+                }));
+            }
+            last = whole.end;
+        }
+        push_marked_text(&mut nodes, &line[last..], base_format);
+    }
+    nodes
+}
+
+// Splits text at Roam's **bold**, __italic__, ~~strikethrough~~, ^^highlight^^ and `code`
+// marks into text nodes. Marks without a closing one are kept as text, and page links and
+// references are never split, so they still resolve.
+fn push_marked_text(nodes: &mut Vec<Value>, text: &str, base_format: u64) {
+    let mut format = 0;
+    let mut current = String::new();
+    let mut rest = text;
+    let flush = |nodes: &mut Vec<Value>, current: &mut String, format: u64| {
+        if !current.is_empty() {
+            nodes.push(text_node(current, base_format | format));
+            current.clear();
+        }
+    };
+
+    while let Some(c) = rest.chars().next() {
+        let protected = [("[[", "]]"), ("(((", ")))")]
+            .iter()
+            .find(|(open, _)| rest.starts_with(open))
+            .and_then(|(open, close)| rest[open.len()..].find(close).map(|end| open.len() + end + close.len()));
+        if let Some(len) = protected {
+            current.push_str(&rest[..len]);
+            rest = &rest[len..];
+            continue;
+        }
+        if c == '`' {
+            if let Some(end) = rest[1..].find('`') {
+                flush(nodes, &mut current, format);
+                if end > 0 {
+                    nodes.push(text_node(&rest[1..1 + end], base_format | format | CODE_FORMAT));
+                }
+                rest = &rest[end + 2..];
+                continue;
+            }
+        }
+        if let Some((mark, bit)) = MARKS.iter().find(|(mark, _)| rest.starts_with(mark)) {
+            if format & bit != 0 || rest[mark.len()..].contains(mark) {
+                flush(nodes, &mut current, format);
+                format ^= bit;
+                rest = &rest[mark.len()..];
+                continue;
+            }
+        }
+        current.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    flush(nodes, &mut current, format);
+}
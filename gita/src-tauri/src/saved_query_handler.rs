@@ -0,0 +1,134 @@
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+// Import the shared DalError
+use crate::dal_error::DalError;
+
+// A search saved under a name. filter is a serialized page_handler::SearchFilter, which may
+// have been written by another version of the app.
+#[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct SavedQuery {
+    pub id: Uuid,
+    pub name: String,
+    pub query: String,
+    pub filter: Value,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// Refused with DalError::Conflict when a saved query already has this name, ignoring case
+pub async fn create_saved_query<'e>(
+    executor: impl PgExecutor<'e>,
+    name: &str,
+    query: &str,
+    filter: &Value,
+) -> Result<SavedQuery, DalError> {
+    let saved = sqlx::query_as!(
+        SavedQuery,
+        r#"
+        INSERT INTO saved_queries (id, name, query, filter, created_at, updated_at)
+        VALUES ($1, $2, $3, $4, now(), now())
+        RETURNING id, name, query, filter, created_at, updated_at
+        "#,
+        Uuid::new_v4(),
+        name,
+        query,
+        filter
+    )
+    .fetch_one(executor)
+    .await
+    .map_err(DalError::from);
+
+    match saved {
+        Err(e) if e.is_unique_violation() => Err(DalError::Conflict(format!("A saved query is already named '{}'", name))),
+        result => result,
+    }
+}
+
+// Renames a saved query and/or replaces its search; fields left None are kept. Returns
+// DalError::NotFound if there is no such query, and DalError::Conflict if the new name is taken.
+pub async fn update_saved_query<'e>(
+    executor: impl PgExecutor<'e>,
+    id: Uuid,
+    name: Option<&str>,
+    query: Option<&str>,
+    filter: Option<&Value>,
+) -> Result<SavedQuery, DalError> {
+    let saved = sqlx::query_as!(
+        SavedQuery,
+        r#"
+        UPDATE saved_queries
+        SET name = COALESCE($2, name),
+            query = COALESCE($3, query),
+            filter = COALESCE($4, filter),
+            updated_at = now()
+        WHERE id = $1
+        RETURNING id, name, query, filter, created_at, updated_at
+        "#,
+        id,
+        name,
+        query,
+        filter
+    )
+    .fetch_optional(executor)
+    .await
+    .map_err(DalError::from);
+
+    match saved {
+        Ok(saved) => saved.ok_or(DalError::NotFound),
+        Err(e) if e.is_unique_violation() => Err(DalError::Conflict(format!(
+            "A saved query is already named '{}'",
+            name.unwrap_or_default()
+        ))),
+        Err(e) => Err(e),
+    }
+}
+
+// Returns DalError::NotFound if there is no saved query with this ID
+pub async fn get_saved_query<'e>(executor: impl PgExecutor<'e>, id: Uuid) -> Result<SavedQuery, DalError> {
+    let saved = sqlx::query_as!(
+        SavedQuery,
+        r#"
+        SELECT id, name, query, filter, created_at, updated_at
+        FROM saved_queries
+        WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(executor)
+    .await?;
+
+    saved.ok_or(DalError::NotFound)
+}
+
+pub async fn list_saved_queries(pool: &PgPool) -> Result<Vec<SavedQuery>, DalError> {
+    let saved = sqlx::query_as!(
+        SavedQuery,
+        r#"
+        SELECT id, name, query, filter, created_at, updated_at
+        FROM saved_queries
+        ORDER BY lower(name), id
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(saved)
+}
+
+// Returns false if there was no saved query with this ID
+pub async fn delete_saved_query<'e>(executor: impl PgExecutor<'e>, id: Uuid) -> Result<bool, DalError> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM saved_queries
+        WHERE id = $1
+        "#,
+        id
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
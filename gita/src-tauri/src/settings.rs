@@ -0,0 +1,233 @@
+// App settings persisted as config.toml in the app data directory. The file is created with
+// defaults on first run; DATABASE_URL from the environment (or .env) is used when it has no URL.
+
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::db;
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DatabaseSettings {
+    pub url: Option<String>,
+    pub max_connections: u32,
+    pub connect_timeout_secs: u64,   // Also how long a command waits for a free connection
+    pub idle_timeout_secs: u64,      // Idle connections are closed after this long; 0 keeps them
+    pub statement_timeout_secs: u64, // Statements running longer are cancelled; 0 for no limit
+}
+
+impl Default for DatabaseSettings {
+    fn default() -> Self {
+        DatabaseSettings {
+            url: None,
+            max_connections: 5,
+            connect_timeout_secs: 5,
+            idle_timeout_secs: 600,
+            statement_timeout_secs: 30,
+        }
+    }
+}
+
+impl DatabaseSettings {
+    // The configured URL, falling back to the DATABASE_URL environment variable
+    pub fn resolved_url(&self) -> Option<String> {
+        self.url
+            .clone()
+            .filter(|url| !url.trim().is_empty())
+            .or_else(|| env::var("DATABASE_URL").ok())
+    }
+
+    pub fn pool_config(&self) -> db::PoolConfig {
+        let optional_secs = |secs: u64| (secs > 0).then(|| Duration::from_secs(secs));
+        db::PoolConfig {
+            max_connections: self.max_connections.max(1),
+            acquire_timeout: Duration::from_secs(self.connect_timeout_secs),
+            idle_timeout: optional_secs(self.idle_timeout_secs),
+            statement_timeout: optional_secs(self.statement_timeout_secs),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct TemplateSettings {
+    pub daily_note_template_id: Option<String>, // Template page used by create_daily_note
+}
+
+// Defaults for start_recording's limits
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RecordingSettings {
+    pub min_free_space_mb: u64,    // Recordings won't start, and stop, below this much free disk space
+    pub max_duration_minutes: u64, // 0 for no limit
+}
+
+impl Default for RecordingSettings {
+    fn default() -> Self {
+        RecordingSettings {
+            min_free_space_mb: 500,
+            max_duration_minutes: 480,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct LinkSettings {
+    pub create_stub_pages: bool, // Saving a page creates empty pages for `[[links]]` with no page
+}
+
+// The wrapped key that encrypts recordings (see vault_crypto). Recordings are only encrypted
+// once set_vault_passphrase has stored a key here.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct VaultSettings {
+    pub kdf_salt: Option<String>,    // Base64 Argon2id salt for the passphrase
+    pub wrapped_key: Option<String>, // Base64 nonce and vault key encrypted under the passphrase
+    pub kdf_memory_kib: u32,         // Argon2id cost, used the next time the passphrase is set
+    pub kdf_iterations: u32,
+    pub kdf_parallelism: u32,
+}
+
+impl Default for VaultSettings {
+    fn default() -> Self {
+        VaultSettings {
+            kdf_salt: None,
+            wrapped_key: None,
+            kdf_memory_kib: 64 * 1024,
+            kdf_iterations: 3,
+            kdf_parallelism: 1,
+        }
+    }
+}
+
+impl VaultSettings {
+    pub fn is_configured(&self) -> bool {
+        self.wrapped_key.is_some()
+    }
+}
+
+// The scheduled database maintenance (see maintenance.rs)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MaintenanceSettings {
+    pub enabled: bool,                // Run on a schedule; run_maintenance_now works either way
+    pub interval_hours: u64,          // Time between runs, counted from the last run by any app instance
+    pub revision_retention_days: u32, // Page revisions older than this are pruned; 0 keeps them
+}
+
+impl Default for MaintenanceSettings {
+    fn default() -> Self {
+        MaintenanceSettings {
+            enabled: true,
+            interval_hours: 24,
+            revision_retention_days: 90,
+        }
+    }
+}
+
+// A named vault: a database of its own, with its own notes and audio directories. Commands work
+// on the active one; with none active, the database settings' URL and the notes and audio
+// directories in the app data directory are used. Pool options always come from the database
+// settings.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VaultConfig {
+    pub name: String,
+    pub database_url: String,
+    pub notes_dir: String,
+    pub audio_dir: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Settings {
+    pub database: DatabaseSettings,
+    pub templates: TemplateSettings,
+    pub recording: RecordingSettings,
+    pub links: LinkSettings,
+    pub vault: VaultSettings,
+    pub maintenance: MaintenanceSettings,
+    pub vaults: Vec<VaultConfig>,
+    pub active_vault: Option<String>, // Name of the vault in use; None for the database settings' URL
+}
+
+impl Settings {
+    pub fn find_vault(&self, name: &str) -> Option<&VaultConfig> {
+        self.vaults.iter().find(|vault| vault.name == name)
+    }
+
+    // The vault in use, or None for the database settings' URL (also when the active vault was
+    // taken out of the list by hand)
+    pub fn active_vault_config(&self) -> Option<&VaultConfig> {
+        self.find_vault(self.active_vault.as_deref()?)
+    }
+
+    // The database settings, with the URL of the given vault
+    pub fn database_for(&self, vault: Option<&VaultConfig>) -> DatabaseSettings {
+        let mut database = self.database.clone();
+        if let Some(vault) = vault {
+            database.url = Some(vault.database_url.clone());
+        }
+        database
+    }
+
+    // The notes and audio directories of the given vault, or those in the app data directory
+    pub fn directories_for(vault: Option<&VaultConfig>, app_data_dir: &Path) -> (PathBuf, PathBuf) {
+        match vault {
+            Some(vault) => (PathBuf::from(&vault.notes_dir), PathBuf::from(&vault.audio_dir)),
+            None => (app_data_dir.join("notes"), app_data_dir.join("audio")),
+        }
+    }
+}
+
+pub fn config_path(app_data_dir: &Path) -> PathBuf {
+    app_data_dir.join(CONFIG_FILE_NAME)
+}
+
+// Reads config.toml, writing a default one first if it doesn't exist yet
+pub fn load_or_create(app_data_dir: &Path) -> Result<Settings, String> {
+    let path = config_path(app_data_dir);
+    if !path.exists() {
+        let settings = Settings::default();
+        save(app_data_dir, &settings)?;
+        return Ok(settings);
+    }
+
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    toml::from_str(&contents).map_err(|e| format!("Invalid settings in {}: {}", path.display(), e))
+}
+
+pub fn save(app_data_dir: &Path, settings: &Settings) -> Result<(), String> {
+    let path = config_path(app_data_dir);
+    let contents = toml::to_string_pretty(settings).map_err(|e| format!("Failed to serialize settings: {}", e))?;
+    std::fs::write(&path, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+// Hides the password in a connection string so it can be shown in the UI or logs
+pub fn redact_database_url(url: &str) -> String {
+    let Some(scheme_end) = url.find("://") else {
+        return url.to_string();
+    };
+    let authority_start = scheme_end + 3;
+    let authority_end = url[authority_start..]
+        .find('/')
+        .map_or(url.len(), |i| authority_start + i);
+    let Some(at) = url[authority_start..authority_end].rfind('@') else {
+        return url.to_string();
+    };
+    let userinfo = &url[authority_start..authority_start + at];
+    match userinfo.find(':') {
+        Some(colon) => format!(
+            "{}{}:****{}",
+            &url[..authority_start],
+            &userinfo[..colon],
+            &url[authority_start + at..]
+        ),
+        None => url.to_string(),
+    }
+}
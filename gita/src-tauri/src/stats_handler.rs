@@ -0,0 +1,167 @@
+use serde_json::Value;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// Import the shared DalError
+use crate::dal_error::DalError;
+
+#[derive(Debug, serde::Serialize)]
+pub struct PageStats {
+    pub page_id: Uuid,
+    pub word_count: usize,
+    pub character_count: usize, // Text only; formatting and structure aren't counted
+    pub block_count: usize,
+    pub outgoing_link_count: i64,
+    pub backlink_count: i64, // From live pages only
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct MonthlyPageCount {
+    pub month: String, // YYYY-MM
+    pub page_count: i64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct VaultStats {
+    pub page_count: i64, // Live pages; trashed ones are counted separately
+    pub trashed_page_count: i64,
+    pub block_count: i64,
+    pub page_link_count: i64,
+    pub block_reference_count: i64,
+    pub audio_recording_count: i64,
+    pub total_audio_duration_ms: i64,
+    pub database_size_bytes: i64,
+    pub pages_created_per_month: Vec<MonthlyPageCount>, // Oldest month first; months without pages are left out
+}
+
+// Text and block totals for a page, computed from its content_json so they're never stale
+pub async fn get_page_stats(pool: &PgPool, page_id: Uuid) -> Result<PageStats, DalError> {
+    let content_json = sqlx::query_scalar!(
+        r#"
+        SELECT content_json
+        FROM pages
+        WHERE id = $1
+        "#,
+        page_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(DalError::NotFound)?;
+
+    let link_counts = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM page_links WHERE source_page_id = $1) AS "outgoing!",
+            (SELECT COUNT(*)
+             FROM page_links l
+             JOIN pages src ON src.id = l.source_page_id
+             WHERE l.target_page_id = $1 AND src.deleted_at IS NULL) AS "backlinks!"
+        "#,
+        page_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let content = content_stats(&content_json);
+    Ok(PageStats {
+        page_id,
+        word_count: content.text.split_whitespace().count(),
+        character_count: content.character_count,
+        block_count: content.block_count,
+        outgoing_link_count: link_counts.outgoing,
+        backlink_count: link_counts.backlinks,
+    })
+}
+
+pub async fn get_vault_stats(pool: &PgPool) -> Result<VaultStats, DalError> {
+    let totals = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM pages WHERE deleted_at IS NULL) AS "page_count!",
+            (SELECT COUNT(*) FROM pages WHERE deleted_at IS NOT NULL) AS "trashed_page_count!",
+            (SELECT COUNT(*) FROM blocks) AS "block_count!",
+            (SELECT COUNT(*) FROM page_links) AS "page_link_count!",
+            (SELECT COUNT(*) FROM block_references) AS "block_reference_count!",
+            (SELECT COUNT(*) FROM audio_recordings) AS "audio_recording_count!",
+            (SELECT COALESCE(SUM(duration_ms), 0)::bigint FROM audio_recordings) AS "total_audio_duration_ms!",
+            pg_database_size(current_database()) AS "database_size_bytes!"
+        "#
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let pages_created_per_month = sqlx::query_as!(
+        MonthlyPageCount,
+        r#"
+        SELECT to_char(date_trunc('month', created_at), 'YYYY-MM') AS "month!", COUNT(*) AS "page_count!"
+        FROM pages
+        WHERE deleted_at IS NULL
+        GROUP BY date_trunc('month', created_at)
+        ORDER BY date_trunc('month', created_at)
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(VaultStats {
+        page_count: totals.page_count,
+        trashed_page_count: totals.trashed_page_count,
+        block_count: totals.block_count,
+        page_link_count: totals.page_link_count,
+        block_reference_count: totals.block_reference_count,
+        audio_recording_count: totals.audio_recording_count,
+        total_audio_duration_ms: totals.total_audio_duration_ms,
+        database_size_bytes: totals.database_size_bytes,
+        pages_created_per_month,
+    })
+}
+
+#[derive(Debug, Default)]
+struct ContentStats {
+    text: String, // All text, with a space wherever one element ends and another begins
+    character_count: usize,
+    block_count: usize,
+}
+
+fn content_stats(content_json: &Value) -> ContentStats {
+    fn walk(node: &Value, stats: &mut ContentStats) {
+        match node {
+            Value::Object(obj) => {
+                if obj.get("uniqueID").and_then(|v| v.as_str()).is_some() {
+                    stats.block_count += 1;
+                }
+                match obj.get("type").and_then(|v| v.as_str()) {
+                    Some("text") => {
+                        if let Some(text) = obj.get("text").and_then(|v| v.as_str()) {
+                            stats.text.push_str(text);
+                            stats.character_count += text.chars().count();
+                        }
+                    }
+                    Some("linebreak") => stats.text.push(' '),
+                    _ => {}
+                }
+                // Words in adjacent paragraphs or list items mustn't run together
+                if let Some(children) = obj.get("children").or_else(|| obj.get("content")).and_then(|v| v.as_array()) {
+                    stats.text.push(' ');
+                    for child in children {
+                        walk(child, stats);
+                    }
+                    stats.text.push(' ');
+                }
+                if let Some(root) = obj.get("root") {
+                    walk(root, stats);
+                }
+            }
+            Value::Array(items) => {
+                for item in items {
+                    walk(item, stats);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut stats = ContentStats::default();
+    walk(content_json, &mut stats);
+    stats
+}
@@ -0,0 +1,84 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// Import the shared DalError
+use crate::dal_error::DalError;
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct SyncState {
+    pub page_id: Uuid,
+    pub file_path: String, // Relative to the notes directory
+    pub content_hash: String,
+    pub last_synced_at: DateTime<Utc>,
+}
+
+pub async fn get_all_sync_states(pool: &PgPool) -> Result<Vec<SyncState>, DalError> {
+    let states = sqlx::query_as!(
+        SyncState,
+        r#"
+        SELECT page_id, file_path, content_hash, last_synced_at
+        FROM note_sync_state
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(states)
+}
+
+// Records that page_id and file_path are in sync as of now
+pub async fn upsert_sync_state(
+    pool: &PgPool,
+    page_id: Uuid,
+    file_path: &str,
+    content_hash: &str,
+) -> Result<(), DalError> {
+    let mut tx = pool.begin().await?;
+
+    // file_path is unique; release it from any other page first (e.g. a file that was
+    // deleted and recreated for a different page)
+    sqlx::query!(
+        r#"
+        DELETE FROM note_sync_state
+        WHERE file_path = $1 AND page_id <> $2
+        "#,
+        file_path,
+        page_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO note_sync_state (page_id, file_path, content_hash, last_synced_at)
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT (page_id) DO UPDATE
+        SET file_path = EXCLUDED.file_path,
+            content_hash = EXCLUDED.content_hash,
+            last_synced_at = EXCLUDED.last_synced_at
+        "#,
+        page_id,
+        file_path,
+        content_hash
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn delete_sync_state(pool: &PgPool, page_id: Uuid) -> Result<bool, DalError> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM note_sync_state
+        WHERE page_id = $1
+        "#,
+        page_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,437 @@
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::Value;
+use sqlx::{PgExecutor, PgPool};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+// Import the shared DalError
+use crate::dal_error::DalError;
+use crate::page_handler::PageMetadata;
+
+lazy_static! {
+    // A # that starts a word, followed by a name containing at least one non-digit. The first
+    // group keeps the preceding character so rewrites can put it back.
+    static ref HASHTAG_REGEX: Regex = Regex::new(r"(^|[^\w#&/])#([\w/-]*[^\W\d][\w/-]*)").unwrap();
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct Tag {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, sqlx::FromRow, serde::Serialize, serde::Deserialize)]
+pub struct TagWithCount {
+    pub id: Uuid,
+    pub name: String,
+    pub page_count: i64, // Live pages only
+}
+
+// Returns the tag matching `name` ignoring case, creating it with this casing if it's new
+pub async fn get_or_create_tag<'e>(executor: impl PgExecutor<'e>, name: &str) -> Result<Tag, DalError> {
+    let tag = sqlx::query_as!(
+        Tag,
+        r#"
+        INSERT INTO tags (id, name, created_at)
+        VALUES ($1, $2, now())
+        ON CONFLICT ((lower(name))) DO UPDATE SET name = tags.name -- No-op so the existing row is returned
+        RETURNING id, name, created_at
+        "#,
+        Uuid::new_v4(),
+        name
+    )
+    .fetch_one(executor)
+    .await?;
+
+    Ok(tag)
+}
+
+// Tags a page by hand. A tag that was typed in the page becomes a manual one, so it stays
+// when the hashtag is removed from the text.
+pub async fn add_tag_to_page(pool: &PgPool, page_id: Uuid, name: &str) -> Result<Tag, DalError> {
+    let mut tx = pool.begin().await?;
+
+    let tag = get_or_create_tag(&mut *tx, name).await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO page_tags (page_id, tag_id, from_content, created_at)
+        VALUES ($1, $2, false, now())
+        ON CONFLICT (page_id, tag_id) DO UPDATE SET from_content = false
+        "#,
+        page_id,
+        tag.id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(tag)
+}
+
+// Tags several pages by hand at once, like add_tag_to_page
+pub async fn add_tag_to_pages<'e>(executor: impl PgExecutor<'e>, page_ids: &[Uuid], tag_id: Uuid) -> Result<(), DalError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO page_tags (page_id, tag_id, from_content, created_at)
+        SELECT page_id, $2, false, now()
+        FROM unnest($1::uuid[]) AS page_id
+        ON CONFLICT (page_id, tag_id) DO UPDATE SET from_content = false
+        "#,
+        page_ids,
+        tag_id
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+// Removes a tag from several pages at once. The caller deletes the tag if it's now unused.
+pub async fn remove_tag_from_pages<'e>(executor: impl PgExecutor<'e>, page_ids: &[Uuid], name: &str) -> Result<u64, DalError> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM page_tags pt
+        USING tags t
+        WHERE pt.tag_id = t.id AND pt.page_id = ANY($1) AND lower(t.name) = lower($2)
+        "#,
+        page_ids,
+        name
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// Removes a tag from a page. Returns false if the page didn't have it. A #tag still present in
+// the page's text comes back the next time the page is saved.
+pub async fn remove_tag_from_page(pool: &PgPool, page_id: Uuid, name: &str) -> Result<bool, DalError> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM page_tags pt
+        USING tags t
+        WHERE pt.tag_id = t.id AND pt.page_id = $1 AND lower(t.name) = lower($2)
+        "#,
+        page_id,
+        name
+    )
+    .execute(&mut *tx)
+    .await?;
+    delete_unused_tags(&mut *tx).await?;
+
+    tx.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// Every tag with the number of live pages using it, alphabetically
+pub async fn list_tags(pool: &PgPool) -> Result<Vec<TagWithCount>, DalError> {
+    let tags = sqlx::query_as!(
+        TagWithCount,
+        r#"
+        SELECT t.id, t.name, COUNT(p.id) AS "page_count!"
+        FROM tags t
+        LEFT JOIN page_tags pt ON pt.tag_id = t.id
+        LEFT JOIN pages p ON p.id = pt.page_id AND p.deleted_at IS NULL
+        GROUP BY t.id
+        ORDER BY lower(t.name)
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(tags)
+}
+
+// Whether a tag with this name (ignoring case) exists
+pub async fn tag_exists<'e>(executor: impl PgExecutor<'e>, name: &str) -> Result<bool, DalError> {
+    let exists = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM tags WHERE lower(name) = lower($1)) AS "exists!"
+        "#,
+        name
+    )
+    .fetch_one(executor)
+    .await?;
+
+    Ok(exists)
+}
+
+// Live pages with the tag (matched ignoring case), most recently updated first
+pub async fn list_pages_with_tag(pool: &PgPool, name: &str) -> Result<Vec<PageMetadata>, DalError> {
+    let pages = sqlx::query_as!(
+        PageMetadata,
+        r#"
+        SELECT p.id, p.title, p.created_at, p.updated_at, p.deleted_at, p.is_favorite, p.favorite_order,
+               p.content_updated_at, page_tag_names(p.id) AS "tags!"
+        FROM pages p
+        JOIN page_tags pt ON pt.page_id = p.id
+        JOIN tags t ON t.id = pt.tag_id
+        WHERE lower(t.name) = lower($1) AND p.deleted_at IS NULL
+        ORDER BY p.updated_at DESC, p.id DESC
+        "#,
+        name
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(pages)
+}
+
+// Renames a tag, or merges it into another tag when the new name is already taken (ignoring
+// case). Hashtags typed in pages are rewritten too, so the next save doesn't bring the old name
+// back. Returns the IDs of the pages whose content changed.
+pub async fn rename_tag(pool: &PgPool, old_name: &str, new_name: &str) -> Result<Vec<Uuid>, DalError> {
+    let mut tx = pool.begin().await?;
+
+    let old_tag = sqlx::query_as!(
+        Tag,
+        r#"
+        SELECT id, name, created_at
+        FROM tags
+        WHERE lower(name) = lower($1)
+        FOR UPDATE
+        "#,
+        old_name
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(DalError::NotFound)?;
+
+    let target = sqlx::query_as!(
+        Tag,
+        r#"
+        SELECT id, name, created_at
+        FROM tags
+        WHERE lower(name) = lower($1) AND id <> $2
+        FOR UPDATE
+        "#,
+        new_name,
+        old_tag.id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    // Pages that typed the old tag, read before the associations move
+    let tagged_pages = sqlx::query!(
+        r#"
+        SELECT p.id, p.content_json, p.raw_markdown
+        FROM pages p
+        JOIN page_tags pt ON pt.page_id = p.id
+        WHERE pt.tag_id = $1 AND pt.from_content
+        FOR UPDATE OF p
+        "#,
+        old_tag.id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    match target {
+        Some(target) => {
+            // Pages already tagged with the target keep that association; a manual tag wins
+            sqlx::query!(
+                r#"
+                INSERT INTO page_tags (page_id, tag_id, from_content, created_at)
+                SELECT page_id, $2, from_content, created_at
+                FROM page_tags
+                WHERE tag_id = $1
+                ON CONFLICT (page_id, tag_id)
+                DO UPDATE SET from_content = page_tags.from_content AND EXCLUDED.from_content
+                "#,
+                old_tag.id,
+                target.id
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!("DELETE FROM tags WHERE id = $1", old_tag.id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        None => {
+            sqlx::query!("UPDATE tags SET name = $2 WHERE id = $1", old_tag.id, new_name)
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+
+    let mut touched_page_ids = Vec::new();
+    for page in tagged_pages {
+        let mut content_json = page.content_json;
+        let json_changed = rewrite_hashtags_in_json(&mut content_json, &old_tag.name, new_name);
+        let new_markdown = page
+            .raw_markdown
+            .as_deref()
+            .and_then(|md| rewrite_hashtags(md, &old_tag.name, new_name));
+
+        if !json_changed && new_markdown.is_none() {
+            continue;
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE pages
+            SET content_json = $2, raw_markdown = COALESCE($3, raw_markdown), content_hash = NULL,
+                updated_at = now(), content_updated_at = now()
+            WHERE id = $1
+            "#,
+            page.id,
+            content_json,
+            new_markdown
+        )
+        .execute(&mut *tx)
+        .await?;
+        touched_page_ids.push(page.id);
+    }
+
+    tx.commit().await?;
+    Ok(touched_page_ids)
+}
+
+// Drops the page's typed-tag associations for tags no longer in `names` (compared ignoring
+// case). Manual tags are left alone.
+pub async fn remove_content_tags_except<'e>(
+    executor: impl PgExecutor<'e>,
+    page_id: Uuid,
+    names: &[String],
+) -> Result<(), DalError> {
+    let lowered: Vec<String> = names.iter().map(|name| name.to_lowercase()).collect();
+    sqlx::query!(
+        r#"
+        DELETE FROM page_tags pt
+        USING tags t
+        WHERE pt.tag_id = t.id
+          AND pt.page_id = $1
+          AND pt.from_content
+          AND NOT (lower(t.name) = ANY($2))
+        "#,
+        page_id,
+        &lowered
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+// Records that a page's text contains the tag; an existing manual association is kept as is
+pub async fn add_content_tag<'e>(executor: impl PgExecutor<'e>, page_id: Uuid, tag_id: Uuid) -> Result<(), DalError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO page_tags (page_id, tag_id, from_content, created_at)
+        VALUES ($1, $2, true, now())
+        ON CONFLICT (page_id, tag_id) DO NOTHING
+        "#,
+        page_id,
+        tag_id
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+// Tags no page uses any more
+pub async fn delete_unused_tags<'e>(executor: impl PgExecutor<'e>) -> Result<u64, DalError> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM tags t
+        WHERE NOT EXISTS (SELECT 1 FROM page_tags pt WHERE pt.tag_id = t.id)
+        "#
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// #hashtags in a content_json tree's text nodes, once each ignoring case (first casing wins)
+pub fn extract_hashtags(content_json: &Value) -> Vec<String> {
+    fn collect(node: &Value, tags: &mut Vec<String>, seen: &mut HashSet<String>) {
+        match node {
+            Value::Object(obj) => {
+                if obj.get("type").and_then(|v| v.as_str()) == Some("text") {
+                    if let Some(text) = obj.get("text").and_then(|v| v.as_str()) {
+                        for cap in HASHTAG_REGEX.captures_iter(text) {
+                            let tag = cap[2].trim_end_matches(['-', '/']);
+                            if seen.insert(tag.to_lowercase()) {
+                                tags.push(tag.to_string());
+                            }
+                        }
+                    }
+                }
+                for value in obj.values() {
+                    if value.is_object() || value.is_array() {
+                        collect(value, tags, seen);
+                    }
+                }
+            }
+            Value::Array(items) => {
+                for item in items {
+                    collect(item, tags, seen);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut tags = Vec::new();
+    collect(content_json, &mut tags, &mut HashSet::new());
+    tags
+}
+
+// Replaces `#old_name` (ignoring case) with `#new_name`. Returns None if nothing matched.
+fn rewrite_hashtags(text: &str, old_name: &str, new_name: &str) -> Option<String> {
+    let mut changed = false;
+    let rewritten = HASHTAG_REGEX.replace_all(text, |caps: &regex::Captures| {
+        let tag = caps[2].trim_end_matches(['-', '/']);
+        if tag.to_lowercase() == old_name.to_lowercase() {
+            changed = true;
+            format!("{}#{}{}", &caps[1], new_name, &caps[2][tag.len()..])
+        } else {
+            caps[0].to_string()
+        }
+    });
+
+    if changed {
+        Some(rewritten.into_owned())
+    } else {
+        None
+    }
+}
+
+// Applies rewrite_hashtags to every text node in a content_json tree
+fn rewrite_hashtags_in_json(node: &mut Value, old_name: &str, new_name: &str) -> bool {
+    match node {
+        Value::Object(obj) => {
+            let mut changed = false;
+            if obj.get("type").and_then(|v| v.as_str()) == Some("text") {
+                let rewritten = obj
+                    .get("text")
+                    .and_then(|v| v.as_str())
+                    .and_then(|text| rewrite_hashtags(text, old_name, new_name));
+                if let Some(text) = rewritten {
+                    obj.insert("text".to_string(), Value::String(text));
+                    changed = true;
+                }
+            }
+            for value in obj.values_mut() {
+                if value.is_object() || value.is_array() {
+                    changed |= rewrite_hashtags_in_json(value, old_name, new_name);
+                }
+            }
+            changed
+        }
+        Value::Array(items) => {
+            let mut changed = false;
+            for item in items.iter_mut() {
+                changed |= rewrite_hashtags_in_json(item, old_name, new_name);
+            }
+            changed
+        }
+        _ => false,
+    }
+}
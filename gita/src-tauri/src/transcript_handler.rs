@@ -0,0 +1,128 @@
+use chrono::{DateTime, Utc};
+use sqlx::types::Json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// Import the shared DalError
+use crate::dal_error::DalError;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TranscriptSegment {
+    pub start_ms: i64, // From the start of the recording
+    pub end_ms: i64,
+    pub text: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Transcript {
+    pub recording_id: Uuid,
+    pub full_text: String,
+    pub segments: Vec<TranscriptSegment>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>, // When it was last replaced
+}
+
+// A transcript segment that matched a search, with what's needed to jump into the audio
+#[derive(Debug, sqlx::FromRow, serde::Serialize)]
+pub struct TranscriptMatch {
+    pub recording_id: Uuid,
+    pub page_id: Option<Uuid>,
+    pub file_path: String,
+    pub segment_index: i64, // 0-based position in the transcript's segments
+    pub start_ms: i64,
+    pub end_ms: i64,
+    pub text: String,
+}
+
+// Stores the transcript for a recording, replacing any existing one in the same statement
+pub async fn save_transcript(
+    pool: &PgPool,
+    recording_id: Uuid,
+    full_text: &str,
+    segments: &[TranscriptSegment],
+) -> Result<Transcript, DalError> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO transcripts (recording_id, full_text, segments, created_at, updated_at)
+        VALUES ($1, $2, $3, now(), now())
+        ON CONFLICT (recording_id) DO UPDATE
+        SET full_text = EXCLUDED.full_text,
+            segments = EXCLUDED.segments,
+            updated_at = now()
+        RETURNING recording_id, full_text, segments AS "segments: Json<Vec<TranscriptSegment>>", created_at, updated_at
+        "#,
+        recording_id,
+        full_text,
+        Json(segments) as _
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(Transcript {
+        recording_id: row.recording_id,
+        full_text: row.full_text,
+        segments: row.segments.0,
+        created_at: row.created_at,
+        updated_at: row.updated_at,
+    })
+}
+
+// Returns DalError::NotFound if the recording has no transcript
+pub async fn get_transcript(pool: &PgPool, recording_id: Uuid) -> Result<Transcript, DalError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT recording_id, full_text, segments AS "segments: Json<Vec<TranscriptSegment>>", created_at, updated_at
+        FROM transcripts
+        WHERE recording_id = $1
+        "#,
+        recording_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or(DalError::NotFound)?;
+
+    Ok(Transcript {
+        recording_id: row.recording_id,
+        full_text: row.full_text,
+        segments: row.segments.0,
+        created_at: row.created_at,
+        updated_at: row.updated_at,
+    })
+}
+
+// Segments whose text contains query (ignoring case), newest recordings first and in order
+// within each. With page_id, only recordings attached to that page are searched.
+pub async fn search_transcripts(
+    pool: &PgPool,
+    query: &str,
+    page_id: Option<Uuid>,
+    limit: i64,
+) -> Result<Vec<TranscriptMatch>, DalError> {
+    let like_term = query.to_lowercase().replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+
+    let matches = sqlx::query_as!(
+        TranscriptMatch,
+        r#"
+        SELECT r.id AS recording_id, r.page_id, r.file_path,
+               s.position - 1 AS "segment_index!",
+               (s.segment->>'start_ms')::bigint AS "start_ms!",
+               (s.segment->>'end_ms')::bigint AS "end_ms!",
+               s.segment->>'text' AS "text!"
+        FROM transcripts t
+        JOIN audio_recordings r ON r.id = t.recording_id
+        CROSS JOIN LATERAL jsonb_array_elements(t.segments) WITH ORDINALITY AS s(segment, position)
+        WHERE lower(t.full_text) LIKE '%' || $1 || '%'
+          AND lower(s.segment->>'text') LIKE '%' || $1 || '%'
+          AND ($2::uuid IS NULL OR r.page_id = $2)
+        ORDER BY r.created_at DESC, r.id, s.position
+        LIMIT $3
+        "#,
+        like_term,
+        page_id,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(matches)
+}
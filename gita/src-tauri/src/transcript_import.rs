@@ -0,0 +1,194 @@
+// Parses transcripts produced outside the app into segments with millisecond timestamps.
+// Supported: Whisper JSON (openai-whisper's `segments` with times in seconds, or whisper.cpp's
+// `transcription` with millisecond offsets), SubRip (.srt) and WebVTT (.vtt).
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::transcript_handler::TranscriptSegment;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptFormat {
+    WhisperJson,
+    Srt,
+    Vtt,
+}
+
+#[derive(Debug)]
+pub struct ParsedTranscript {
+    pub full_text: String,
+    pub segments: Vec<TranscriptSegment>,
+}
+
+pub fn parse_transcript(format: TranscriptFormat, data: &str) -> Result<ParsedTranscript, String> {
+    let (full_text, mut segments) = match format {
+        TranscriptFormat::WhisperJson => parse_whisper_json(data)?,
+        TranscriptFormat::Srt | TranscriptFormat::Vtt => (None, parse_cues(data, format)?),
+    };
+
+    segments.retain(|segment| !segment.text.is_empty());
+    if segments.is_empty() {
+        return Err("The transcript has no segments".to_string());
+    }
+    segments.sort_by_key(|segment| segment.start_ms);
+
+    let full_text = full_text
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty())
+        .unwrap_or_else(|| {
+            segments
+                .iter()
+                .map(|segment| segment.text.as_str())
+                .collect::<Vec<_>>()
+                .join(" ")
+        });
+    Ok(ParsedTranscript { full_text, segments })
+}
+
+fn parse_whisper_json(data: &str) -> Result<(Option<String>, Vec<TranscriptSegment>), String> {
+    let json: Value = serde_json::from_str(data).map_err(|e| format!("Invalid Whisper JSON: {}", e))?;
+    let full_text = json.get("text").and_then(|v| v.as_str()).map(str::to_string);
+
+    // openai-whisper: {"text": "...", "segments": [{"start": 1.5, "end": 3.0, "text": "..."}]}
+    if let Some(items) = json.get("segments").and_then(|v| v.as_array()) {
+        let segments = items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                let seconds = |key: &str| {
+                    item.get(key)
+                        .and_then(|v| v.as_f64())
+                        .filter(|s| s.is_finite() && *s >= 0.0)
+                        .map(|s| (s * 1000.0).round() as i64)
+                        .ok_or_else(|| format!("Segment {} has no valid \"{}\" time", index + 1, key))
+                };
+                segment(seconds("start")?, seconds("end")?, item.get("text").and_then(|v| v.as_str()).unwrap_or_default())
+                    .map_err(|e| format!("Segment {}: {}", index + 1, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        return Ok((full_text, segments));
+    }
+
+    // whisper.cpp: {"transcription": [{"offsets": {"from": 0, "to": 1500}, "text": "..."}]}
+    if let Some(items) = json.get("transcription").and_then(|v| v.as_array()) {
+        let segments = items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                let offset = |key: &str| {
+                    item.get("offsets")
+                        .and_then(|offsets| offsets.get(key))
+                        .and_then(|v| v.as_i64())
+                        .filter(|ms| *ms >= 0)
+                        .ok_or_else(|| format!("Segment {} has no valid \"offsets.{}\"", index + 1, key))
+                };
+                segment(offset("from")?, offset("to")?, item.get("text").and_then(|v| v.as_str()).unwrap_or_default())
+                    .map_err(|e| format!("Segment {}: {}", index + 1, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        return Ok((full_text, segments));
+    }
+
+    Err("Whisper JSON has neither \"segments\" nor \"transcription\"".to_string())
+}
+
+// SRT and VTT are both blank-line separated cues with a `start --> end` timing line
+fn parse_cues(data: &str, format: TranscriptFormat) -> Result<Vec<TranscriptSegment>, String> {
+    let data = data.trim_start_matches('\u{feff}').replace("\r\n", "\n").replace('\r', "\n");
+    let mut blocks = data.split("\n\n").map(str::trim).filter(|block| !block.is_empty()).peekable();
+
+    if format == TranscriptFormat::Vtt {
+        match blocks.next() {
+            Some(header) if header.starts_with("WEBVTT") => {}
+            _ => return Err("A WebVTT file must start with WEBVTT".to_string()),
+        }
+    }
+
+    let mut segments = Vec::new();
+    for block in blocks {
+        // VTT comments, styles and regions aren't cues
+        if format == TranscriptFormat::Vtt
+            && (block.starts_with("NOTE") || block.starts_with("STYLE") || block.starts_with("REGION"))
+        {
+            continue;
+        }
+
+        let mut lines = block.lines();
+        // An SRT counter or a VTT cue identifier may come before the timing line
+        let timing = match lines.next() {
+            Some(line) if line.contains("-->") => line,
+            Some(_) => lines.next().filter(|line| line.contains("-->")).ok_or_else(|| {
+                format!("Cue without a timing line: {}", block.lines().next().unwrap_or_default())
+            })?,
+            None => continue,
+        };
+
+        let (start, rest) = timing.split_once("-->").unwrap_or_default();
+        // VTT cue settings (e.g. `align:start`) follow the end time
+        let end = rest.split_whitespace().next().unwrap_or_default();
+        let start_ms = parse_timestamp(start.trim()).ok_or_else(|| format!("Invalid start time in \"{}\"", timing))?;
+        let end_ms = parse_timestamp(end).ok_or_else(|| format!("Invalid end time in \"{}\"", timing))?;
+
+        let text = lines.map(strip_cue_tags).collect::<Vec<_>>().join(" ");
+        segments.push(segment(start_ms, end_ms, &text).map_err(|e| format!("Cue at {}: {}", start.trim(), e))?);
+    }
+    Ok(segments)
+}
+
+fn segment(start_ms: i64, end_ms: i64, text: &str) -> Result<TranscriptSegment, String> {
+    if end_ms < start_ms {
+        return Err("ends before it starts".to_string());
+    }
+    Ok(TranscriptSegment {
+        start_ms,
+        end_ms,
+        text: text.split_whitespace().collect::<Vec<_>>().join(" "),
+    })
+}
+
+// `hh:mm:ss,mmm` (SRT) or `[hh:]mm:ss.mmm` (VTT) in milliseconds. A fraction with fewer than
+// three digits is scaled, so `00:01.5` is 1500 ms.
+fn parse_timestamp(value: &str) -> Option<i64> {
+    let (clock, fraction) = match value.rsplit_once([',', '.']) {
+        Some((clock, fraction)) => (clock, fraction),
+        None => (value, ""),
+    };
+    let millis = if fraction.is_empty() {
+        0
+    } else {
+        if fraction.len() > 3 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        fraction.parse::<i64>().ok()? * 10_i64.pow(3 - fraction.len() as u32)
+    };
+
+    let parts: Vec<i64> = clock
+        .split(':')
+        .map(|part| part.parse::<i64>().ok().filter(|n| *n >= 0))
+        .collect::<Option<_>>()?;
+    let (hours, minutes, seconds) = match parts.as_slice() {
+        [hours, minutes, seconds] => (*hours, *minutes, *seconds),
+        [minutes, seconds] => (0, *minutes, *seconds),
+        _ => return None,
+    };
+    if minutes >= 60 || seconds >= 60 {
+        return None;
+    }
+    Some(((hours * 60 + minutes) * 60 + seconds) * 1000 + millis)
+}
+
+// Drops markup such as <i>, <b> and VTT voice spans (<v Speaker>)
+fn strip_cue_tags(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut in_tag = false;
+    for c in line.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
@@ -0,0 +1,230 @@
+// Sends payloads too big for one IPC message to the webview in pieces. The frontend picks a
+// channel_id, passes it to the command and listens for transfer://chunk events carrying it,
+// answering each with a transfer://ack event; the next chunk isn't sent until that ack
+// arrives, so a slow webview is never more than one chunk behind. transfer://complete ends
+// the transfer with a SHA-256 of the whole payload to check the pieces against.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, EventId, Listener};
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+
+pub const EVENT_CHUNK: &str = "transfer://chunk";
+pub const EVENT_COMPLETE: &str = "transfer://complete";
+pub const EVENT_ACK: &str = "transfer://ack";
+
+// Longest `data` string in a chunk event
+pub const MAX_CHUNK_BYTES: usize = 256 * 1024;
+
+// Serialized results up to this size are returned from the command as usual, channel or not
+pub const DIRECT_MAX_BYTES: usize = 1024 * 1024;
+
+// How long to wait for the webview to acknowledge a chunk before giving up on the transfer
+const ACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferEncoding {
+    Text,   // `data` is a piece of UTF-8 text (JSON), never split inside a character
+    Base64, // `data` is a piece of binary content, base64-encoded on its own
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ChunkEvent {
+    pub channel_id: String,
+    pub sequence: u64, // 0-based; the webview acks each one with the same number
+    pub encoding: TransferEncoding,
+    pub data: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TransferSummary {
+    pub channel_id: String,
+    pub chunks: u64,
+    pub total_bytes: u64, // Of the payload itself, before any base64
+    pub checksum: String, // SHA-256 of the payload, lowercase hex
+}
+
+#[derive(Deserialize, Debug)]
+struct AckEvent {
+    channel_id: String,
+    sequence: u64,
+}
+
+// A command result that is either the value itself or, when it was too big and the caller gave
+// a channel_id, the summary of the transfer that carried it. Untagged, so callers that don't
+// pass a channel_id see the same JSON as before.
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum Transferred<T> {
+    Direct(T),
+    Chunked(TransferSummary),
+}
+
+// One payload on its way to the webview. Listens for acks from the time it is opened until it
+// is dropped.
+pub struct Transfer {
+    app_handle: AppHandle,
+    channel_id: String,
+    encoding: TransferEncoding,
+    listener: EventId,
+    acks: mpsc::UnboundedReceiver<u64>,
+    sequence: u64,
+    total_bytes: u64,
+    hasher: Sha256,
+}
+
+impl Transfer {
+    pub fn open(app_handle: &AppHandle, channel_id: &str, encoding: TransferEncoding) -> Result<Self, String> {
+        if channel_id.is_empty() {
+            return Err("channel_id must not be empty".to_string());
+        }
+        let (sender, acks) = mpsc::unbounded_channel();
+        let expected_channel = channel_id.to_string();
+        let listener = app_handle.listen_any(EVENT_ACK, move |event| {
+            match serde_json::from_str::<AckEvent>(event.payload()) {
+                Ok(ack) if ack.channel_id == expected_channel => {
+                    let _ = sender.send(ack.sequence);
+                }
+                Ok(_) => {} // An ack for another transfer
+                Err(e) => eprintln!("[Transfer] Ignoring malformed ack: {}", e),
+            }
+        });
+        Ok(Transfer {
+            app_handle: app_handle.clone(),
+            channel_id: channel_id.to_string(),
+            encoding,
+            listener,
+            acks,
+            sequence: 0,
+            total_bytes: 0,
+            hasher: Sha256::new(),
+        })
+    }
+
+    // Sends the next part of the payload as one or more chunks. With the text encoding, `bytes`
+    // must be valid UTF-8 on its own, so send text in one call rather than in arbitrary slices.
+    pub async fn send(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.hasher.update(bytes);
+        self.total_bytes += bytes.len() as u64;
+        match self.encoding {
+            TransferEncoding::Text => {
+                let mut text = std::str::from_utf8(bytes).map_err(|e| format!("Payload is not UTF-8: {}", e))?;
+                while !text.is_empty() {
+                    let mut end = text.len().min(MAX_CHUNK_BYTES);
+                    while !text.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    let (piece, rest) = text.split_at(end);
+                    self.send_chunk(piece.to_string()).await?;
+                    text = rest;
+                }
+            }
+            TransferEncoding::Base64 => {
+                use base64::Engine;
+                // Every 3 bytes become 4 characters
+                for piece in bytes.chunks(MAX_CHUNK_BYTES / 4 * 3) {
+                    let data = base64::engine::general_purpose::STANDARD.encode(piece);
+                    self.send_chunk(data).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Emits transfer://complete once the last chunk has been acknowledged
+    pub fn finish(mut self) -> Result<TransferSummary, String> {
+        let hasher = std::mem::take(&mut self.hasher);
+        let summary = TransferSummary {
+            channel_id: self.channel_id.clone(),
+            chunks: self.sequence,
+            total_bytes: self.total_bytes,
+            checksum: hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect(),
+        };
+        self.app_handle
+            .emit(EVENT_COMPLETE, summary.clone())
+            .map_err(|e| format!("Failed to emit transfer completion: {}", e))?;
+        Ok(summary)
+    }
+
+    async fn send_chunk(&mut self, data: String) -> Result<(), String> {
+        let sequence = self.sequence;
+        let event = ChunkEvent {
+            channel_id: self.channel_id.clone(),
+            sequence,
+            encoding: self.encoding,
+            data,
+        };
+        self.app_handle
+            .emit(EVENT_CHUNK, event)
+            .map_err(|e| format!("Failed to emit chunk {}: {}", sequence, e))?;
+        self.sequence += 1;
+
+        // Acks for earlier chunks that arrive twice are skipped
+        loop {
+            match tokio::time::timeout(ACK_TIMEOUT, self.acks.recv()).await {
+                Ok(Some(acked)) if acked == sequence => return Ok(()),
+                Ok(Some(_)) => continue,
+                Ok(None) => return Err("Transfer listener closed".to_string()),
+                Err(_) => {
+                    return Err(format!(
+                        "Chunk {} of transfer {} was not acknowledged within {} seconds",
+                        sequence,
+                        self.channel_id,
+                        ACK_TIMEOUT.as_secs()
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        self.app_handle.unlisten(self.listener);
+    }
+}
+
+// Returns `value` as is when there's no channel_id or it serializes small enough; otherwise
+// sends its JSON through a transfer on that channel and returns the summary in its place
+pub async fn send_or_return<T: Serialize>(
+    app_handle: &AppHandle,
+    channel_id: Option<&str>,
+    value: T,
+) -> Result<Transferred<T>, String> {
+    let Some(channel_id) = channel_id else {
+        return Ok(Transferred::Direct(value));
+    };
+    let json = serde_json::to_vec(&value).map_err(|e| format!("Failed to serialize result: {}", e))?;
+    if json.len() <= DIRECT_MAX_BYTES {
+        return Ok(Transferred::Direct(value));
+    }
+    drop(value);
+
+    let mut transfer = Transfer::open(app_handle, channel_id, TransferEncoding::Text)?;
+    transfer.send(&json).await?;
+    transfer.finish().map(Transferred::Chunked)
+}
+
+// Sends the contents of a file, base64-encoded, reading no more than a chunk at a time
+pub async fn send_file(app_handle: &AppHandle, channel_id: &str, path: &Path) -> Result<TransferSummary, String> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut transfer = Transfer::open(app_handle, channel_id, TransferEncoding::Base64)?;
+    let mut buffer = vec![0; MAX_CHUNK_BYTES / 4 * 3];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if read == 0 {
+            break;
+        }
+        transfer.send(&buffer[..read]).await?;
+    }
+    transfer.finish()
+}
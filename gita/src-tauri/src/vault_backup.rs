@@ -0,0 +1,570 @@
+// Backs up the whole vault to one file and restores it. A backup is JSON Lines: a header with
+// the schema version, one line per table row and an end line with the row counts, so neither
+// side ever holds more than a batch of rows in memory and a truncated file is detected before
+// anything is restored. With audio included, the lines go into backup.jsonl inside a zip next
+// to the recordings' files (audio/<recording id>/<file name>).
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::backup_handler::{self, BackupTable, BACKUP_TABLES};
+use crate::dal_error::DalError;
+use crate::db;
+
+const BACKUP_FORMAT: &str = "gita-backup";
+const BACKUP_FORMAT_VERSION: u32 = 1;
+const BACKUP_ENTRY_NAME: &str = "backup.jsonl";
+const AUDIO_ENTRY_PREFIX: &str = "audio/";
+const BATCH_SIZE: usize = 500;
+const MAX_REPORTED_ERRORS: usize = 50;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupHeader {
+    pub format: String,
+    pub format_version: u32,
+    pub schema_version: i64, // Newest migration applied to the database the backup came from
+    pub app_version: String,
+    pub created_at: String,
+    pub includes_audio: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum BackupLine {
+    Header(BackupHeader),
+    Row { table: String, row: Value },
+    End { row_counts: BTreeMap<String, u64> },
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestoreMode {
+    Replace,   // Delete everything first, then load the backup
+    MergeById, // Keep existing rows; rows with the same ID are overwritten by the backup's
+}
+
+#[derive(Serialize, Debug)]
+pub struct BackupSummary {
+    pub file_path: String,
+    pub schema_version: i64,
+    pub row_counts: BTreeMap<String, u64>,
+    pub audio_files: usize,
+    pub missing_audio_files: Vec<String>, // Recorded in the database but not found on disk
+    pub size_bytes: u64,
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct TableRestoreCounts {
+    pub table: String,
+    pub inserted: u64,
+    pub updated: u64,
+    pub skipped: u64, // Unchanged, or refused by the database (see errors)
+}
+
+#[derive(Serialize, Debug)]
+pub struct RestoreSummary {
+    pub schema_version: i64, // Of the backup
+    pub created_at: String,
+    pub tables: Vec<TableRestoreCounts>,
+    pub audio_files_restored: usize,
+    pub errors: Vec<String>, // The first MAX_REPORTED_ERRORS rows that couldn't be restored
+}
+
+// --- Creating backups ---
+
+pub async fn create_backup(
+    pool: &PgPool,
+    dest: &Path,
+    include_audio: bool,
+    app_version: String,
+) -> Result<BackupSummary, String> {
+    let schema_version = db::get_schema_version(pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .current_version
+        .unwrap_or(0);
+    let header = BackupHeader {
+        format: BACKUP_FORMAT.to_string(),
+        format_version: BACKUP_FORMAT_VERSION,
+        schema_version,
+        app_version,
+        created_at: chrono::Utc::now().to_rfc3339(),
+        includes_audio: include_audio,
+    };
+
+    // Written next to the destination and renamed into place, so a failed backup never
+    // replaces a good one
+    let partial_path = partial_path(dest);
+    let lines_path = if include_audio { temp_path("jsonl") } else { partial_path.clone() };
+    let result = write_backup_lines(pool, &lines_path, header).await;
+    let (row_counts, audio_files) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            let _ = std::fs::remove_file(&lines_path);
+            return Err(e);
+        }
+    };
+
+    let mut missing_audio_files = Vec::new();
+    let mut audio_file_count = 0;
+    if include_audio {
+        let (zip_path, lines) = (partial_path.clone(), lines_path.clone());
+        let result = tokio::task::spawn_blocking(move || write_zip(&zip_path, &lines, audio_files))
+            .await
+            .map_err(|e| format!("Backup task failed: {}", e))
+            .and_then(|result| result);
+        let _ = std::fs::remove_file(&lines_path);
+        match result {
+            Ok((added, missing)) => {
+                audio_file_count = added;
+                missing_audio_files = missing;
+            }
+            Err(e) => {
+                let _ = std::fs::remove_file(&partial_path);
+                return Err(e);
+            }
+        }
+    }
+
+    std::fs::rename(&partial_path, dest).map_err(|e| {
+        let _ = std::fs::remove_file(&partial_path);
+        format!("Failed to move backup into place: {}", e)
+    })?;
+    let size_bytes = std::fs::metadata(dest).map(|metadata| metadata.len()).unwrap_or(0);
+    Ok(BackupSummary {
+        file_path: dest.to_string_lossy().to_string(),
+        schema_version,
+        row_counts,
+        audio_files: audio_file_count,
+        missing_audio_files,
+        size_bytes,
+    })
+}
+
+// A recording file to put into the zip: (recording ID, path on disk)
+type AudioFile = (String, String);
+
+async fn write_backup_lines(
+    pool: &PgPool,
+    path: &Path,
+    header: BackupHeader,
+) -> Result<(BTreeMap<String, u64>, Vec<AudioFile>), String> {
+    let file = File::create(path).map_err(|e| format!("Failed to create backup file: {}", e))?;
+    let mut writer = BufWriter::new(file);
+    write_line(&mut writer, &BackupLine::Header(header))?;
+
+    let mut row_counts = BTreeMap::new();
+    let mut audio_files = Vec::new();
+    let mut tx = backup_handler::begin_snapshot(pool).await.map_err(|e| e.to_string())?;
+    for table in BACKUP_TABLES {
+        backup_handler::open_table_cursor(&mut tx, table).await.map_err(|e| e.to_string())?;
+        let mut count: u64 = 0;
+        loop {
+            let rows = backup_handler::fetch_cursor_rows(&mut tx, BATCH_SIZE).await.map_err(|e| e.to_string())?;
+            if rows.is_empty() {
+                break;
+            }
+            for row in rows {
+                if table.name == "audio_recordings" {
+                    audio_files.extend(recording_files(&row));
+                }
+                write_line(&mut writer, &BackupLine::Row { table: table.name.to_string(), row })?;
+                count += 1;
+            }
+        }
+        backup_handler::close_cursor(&mut tx).await.map_err(|e| e.to_string())?;
+        row_counts.insert(table.name.to_string(), count);
+    }
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    write_line(&mut writer, &BackupLine::End { row_counts: row_counts.clone() })?;
+    let file = writer.into_inner().map_err(|e| format!("Failed to write backup file: {}", e))?;
+    file.sync_all().map_err(|e| format!("Failed to write backup file: {}", e))?;
+    Ok((row_counts, audio_files))
+}
+
+fn write_line(writer: &mut impl Write, line: &BackupLine) -> Result<(), String> {
+    serde_json::to_writer(&mut *writer, line)
+        .map_err(io::Error::from)
+        .and_then(|()| writer.write_all(b"\n"))
+        .map_err(|e| format!("Failed to write backup file: {}", e))
+}
+
+// Both files of a split recording
+fn recording_files(row: &Value) -> Vec<AudioFile> {
+    let id = row.get("id").and_then(|v| v.as_str()).unwrap_or_default();
+    ["file_path", "secondary_file_path"]
+        .iter()
+        .filter_map(|column| row.get(*column).and_then(|v| v.as_str()))
+        .map(|path| (id.to_string(), path.to_string()))
+        .collect()
+}
+
+// Returns the number of audio files added and the paths of those that were missing
+fn write_zip(zip_path: &Path, lines_path: &Path, audio_files: Vec<AudioFile>) -> Result<(usize, Vec<String>), String> {
+    use zip::write::SimpleFileOptions;
+    use zip::CompressionMethod;
+
+    let zip_error = |e: &dyn std::fmt::Display| format!("Failed to write backup archive: {}", e);
+    let file = File::create(zip_path).map_err(|e| zip_error(&e))?;
+    let mut zip = zip::ZipWriter::new(BufWriter::new(file));
+    let large_file = |len: u64| len >= u32::MAX as u64;
+
+    let lines_len = std::fs::metadata(lines_path).map_err(|e| zip_error(&e))?.len();
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .large_file(large_file(lines_len));
+    zip.start_file(BACKUP_ENTRY_NAME, options).map_err(|e| zip_error(&e))?;
+    io::copy(&mut File::open(lines_path).map_err(|e| zip_error(&e))?, &mut zip).map_err(|e| zip_error(&e))?;
+
+    let mut added = 0;
+    let mut missing = Vec::new();
+    for (recording_id, path) in audio_files {
+        let path = PathBuf::from(path);
+        let (Ok(mut source), Some(file_name)) = (File::open(&path), path.file_name()) else {
+            missing.push(path.to_string_lossy().to_string());
+            continue;
+        };
+        let len = source.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        // Recordings are mostly compressed already or don't shrink much, so they're stored as is
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(large_file(len));
+        let entry_name = format!("{}{}/{}", AUDIO_ENTRY_PREFIX, recording_id, file_name.to_string_lossy());
+        zip.start_file(entry_name, options).map_err(|e| zip_error(&e))?;
+        io::copy(&mut source, &mut zip).map_err(|e| zip_error(&e))?;
+        added += 1;
+    }
+
+    let writer = zip.finish().map_err(|e| zip_error(&e))?;
+    let file = writer.into_inner().map_err(|e| zip_error(&e))?;
+    file.sync_all().map_err(|e| zip_error(&e))?;
+    Ok((added, missing))
+}
+
+// --- Restoring backups ---
+
+pub async fn restore_backup(
+    pool: &PgPool,
+    path: &Path,
+    mode: RestoreMode,
+    audio_dir: &Path,
+) -> Result<RestoreSummary, String> {
+    let (source_path, source) = (path.to_path_buf(), audio_dir.to_path_buf());
+    let opened = tokio::task::spawn_blocking(move || open_backup(&source_path, &source))
+        .await
+        .map_err(|e| format!("Restore task failed: {}", e))??;
+    let result = restore_lines(pool, &opened, mode).await;
+    if opened.temporary {
+        let _ = std::fs::remove_file(&opened.lines_path);
+    }
+    result
+}
+
+// A backup ready to be read: the lines file (extracted from the zip if there was one) and the
+// audio files restored from it, by (recording ID, file name)
+struct OpenedBackup {
+    lines_path: PathBuf,
+    temporary: bool,
+    header: BackupHeader,
+    audio_files: HashMap<(String, String), PathBuf>,
+}
+
+fn open_backup(path: &Path, audio_dir: &Path) -> Result<OpenedBackup, String> {
+    let mut magic = [0u8; 4];
+    let is_zip = File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .map(|()| &magic == b"PK\x03\x04")
+        .map_err(|e| format!("Failed to read backup {}: {}", path.display(), e))?;
+    if !is_zip {
+        let header = check_backup_lines(path)?;
+        return Ok(OpenedBackup {
+            lines_path: path.to_path_buf(),
+            temporary: false,
+            header,
+            audio_files: HashMap::new(),
+        });
+    }
+
+    let zip_error = |e: &dyn std::fmt::Display| format!("Failed to read backup archive: {}", e);
+    let file = File::open(path).map_err(|e| zip_error(&e))?;
+    let mut archive = zip::ZipArchive::new(BufReader::new(file)).map_err(|e| zip_error(&e))?;
+    let lines_path = temp_path("jsonl");
+    {
+        let mut entry = archive
+            .by_name(BACKUP_ENTRY_NAME)
+            .map_err(|_| format!("{} is not a backup: it has no {}", path.display(), BACKUP_ENTRY_NAME))?;
+        let mut target = File::create(&lines_path).map_err(|e| zip_error(&e))?;
+        io::copy(&mut entry, &mut target).map_err(|e| zip_error(&e))?;
+    }
+    let header = match check_backup_lines(&lines_path) {
+        Ok(header) => header,
+        Err(e) => {
+            let _ = std::fs::remove_file(&lines_path);
+            return Err(e);
+        }
+    };
+
+    // Audio goes into the audio directory; a file already there under the same name is kept
+    // and the recording points at it
+    std::fs::create_dir_all(audio_dir).map_err(|e| format!("Failed to create audio directory: {}", e))?;
+    let mut audio_files = HashMap::new();
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(|e| zip_error(&e))?;
+        let Some((recording_id, file_name)) = entry
+            .name()
+            .strip_prefix(AUDIO_ENTRY_PREFIX)
+            .and_then(|rest| rest.split_once('/'))
+            .filter(|(_, file_name)| !file_name.is_empty() && !file_name.contains(['/', '\\']) && *file_name != "..")
+            .map(|(id, file_name)| (id.to_string(), file_name.to_string()))
+        else {
+            continue;
+        };
+        let target = audio_dir.join(&file_name);
+        if !target.exists() {
+            let result = File::create(&target).and_then(|mut file| io::copy(&mut entry, &mut file));
+            if let Err(e) = result {
+                let _ = std::fs::remove_file(&target);
+                let _ = std::fs::remove_file(&lines_path);
+                return Err(format!("Failed to restore audio file {}: {}", file_name, e));
+            }
+        }
+        audio_files.insert((recording_id, file_name), target);
+    }
+
+    Ok(OpenedBackup {
+        lines_path,
+        temporary: true,
+        header,
+        audio_files,
+    })
+}
+
+// Reads the whole file once before anything is written: the header must be one this app can
+// restore, every line must parse and the end line's counts must match, so a damaged or cut
+// off backup is refused up front
+fn check_backup_lines(path: &Path) -> Result<BackupHeader, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to read backup: {}", e))?;
+    let mut lines = BufReader::new(file).lines();
+
+    let header = match lines.next().transpose().map_err(|e| format!("Failed to read backup: {}", e))? {
+        Some(line) => match serde_json::from_str::<BackupLine>(&line) {
+            Ok(BackupLine::Header(header)) if header.format == BACKUP_FORMAT => header,
+            _ => return Err("Not a backup file".to_string()),
+        },
+        None => return Err("The backup file is empty".to_string()),
+    };
+    if header.format_version > BACKUP_FORMAT_VERSION {
+        return Err(format!(
+            "The backup uses format version {}, but this app only reads up to version {}. Please update the app.",
+            header.format_version, BACKUP_FORMAT_VERSION
+        ));
+    }
+    let latest_schema_version = db::MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0);
+    if header.schema_version > latest_schema_version {
+        return Err(format!(
+            "The backup was made with database schema version {}, which is newer than this app's ({}). Please update the app.",
+            header.schema_version, latest_schema_version
+        ));
+    }
+
+    let mut row_counts: BTreeMap<String, u64> = BTreeMap::new();
+    for (index, line) in lines.enumerate() {
+        let line = line.map_err(|e| format!("Failed to read backup: {}", e))?;
+        let line_number = index + 2;
+        match serde_json::from_str::<BackupLine>(&line) {
+            Ok(BackupLine::Row { table, row }) => {
+                if backup_handler::backup_table(&table).is_none() {
+                    return Err(format!("Line {} of the backup is for unknown table '{}'", line_number, table));
+                }
+                if !row.is_object() {
+                    return Err(format!("Line {} of the backup is not a row", line_number));
+                }
+                *row_counts.entry(table).or_default() += 1;
+            }
+            Ok(BackupLine::End { row_counts: expected }) => {
+                let counted = |table: &String| row_counts.get(table).copied().unwrap_or(0);
+                if let Some((table, count)) = expected.iter().find(|(table, count)| counted(table) != **count) {
+                    return Err(format!(
+                        "The backup is damaged: it should have {} {} rows but has {}",
+                        count,
+                        table,
+                        counted(table)
+                    ));
+                }
+                return Ok(header);
+            }
+            Ok(BackupLine::Header(_)) => return Err(format!("Line {} of the backup is a second header", line_number)),
+            Err(e) => return Err(format!("Line {} of the backup is invalid: {}", line_number, e)),
+        }
+    }
+    Err("The backup is incomplete: it ends before its last line".to_string())
+}
+
+async fn restore_lines(pool: &PgPool, opened: &OpenedBackup, mode: RestoreMode) -> Result<RestoreSummary, String> {
+    if mode == RestoreMode::Replace {
+        let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+        backup_handler::delete_backup_tables(&mut tx).await.map_err(|e| e.to_string())?;
+        tx.commit().await.map_err(|e| e.to_string())?;
+    }
+
+    let mut restorer = Restorer {
+        pool,
+        columns: HashMap::new(),
+        counts: BACKUP_TABLES
+            .iter()
+            .map(|table| TableRestoreCounts { table: table.name.to_string(), ..Default::default() })
+            .collect(),
+        errors: Vec::new(),
+    };
+
+    let file = File::open(&opened.lines_path).map_err(|e| format!("Failed to read backup: {}", e))?;
+    let mut batch: Vec<Value> = Vec::with_capacity(BATCH_SIZE);
+    let mut batch_table: Option<&'static BackupTable> = None;
+    // The header was checked by check_backup_lines
+    for line in BufReader::new(file).lines().skip(1) {
+        let line = line.map_err(|e| format!("Failed to read backup: {}", e))?;
+        let (table, mut row) = match serde_json::from_str::<BackupLine>(&line) {
+            Ok(BackupLine::Row { table, row }) => (table, row),
+            _ => break,
+        };
+        let table = backup_handler::backup_table(&table).ok_or_else(|| format!("Unknown table '{}'", table))?;
+        if table.name == "audio_recordings" {
+            point_at_restored_audio(&mut row, &opened.audio_files);
+        }
+
+        if batch_table.is_some_and(|current| current.name != table.name) || batch.len() >= BATCH_SIZE {
+            restorer.restore_batch(batch_table.unwrap(), &batch).await?;
+            batch.clear();
+        }
+        batch_table = Some(table);
+        batch.push(row);
+    }
+    if let Some(table) = batch_table {
+        restorer.restore_batch(table, &batch).await?;
+    }
+
+    Ok(RestoreSummary {
+        schema_version: opened.header.schema_version,
+        created_at: opened.header.created_at.clone(),
+        tables: restorer.counts,
+        audio_files_restored: opened.audio_files.len(),
+        errors: restorer.errors,
+    })
+}
+
+// Recordings restored from a zip live in this machine's audio directory now
+fn point_at_restored_audio(row: &mut Value, audio_files: &HashMap<(String, String), PathBuf>) {
+    let id = row.get("id").and_then(|v| v.as_str()).unwrap_or_default().to_string();
+    for column in ["file_path", "secondary_file_path"] {
+        let restored = row
+            .get(column)
+            .and_then(|v| v.as_str())
+            .and_then(|path| Path::new(path).file_name())
+            .and_then(|file_name| audio_files.get(&(id.clone(), file_name.to_string_lossy().to_string())))
+            .map(|target| target.to_string_lossy().to_string());
+        if let Some(restored) = restored {
+            row[column] = Value::String(restored);
+        }
+    }
+}
+
+struct Restorer<'a> {
+    pool: &'a PgPool,
+    columns: HashMap<&'static str, Vec<String>>, // Current columns of each table
+    counts: Vec<TableRestoreCounts>,
+    errors: Vec<String>,
+}
+
+impl Restorer<'_> {
+    // One transaction per batch. If the batch fails as a whole (say a tag name already used by
+    // another tag, or a block whose page was refused), its rows are retried one by one so only
+    // the offending ones are skipped.
+    async fn restore_batch(&mut self, table: &'static BackupTable, rows: &[Value]) -> Result<(), String> {
+        let columns = self.batch_columns(table, rows).await?;
+        let batch_result = async {
+            let mut tx = self.pool.begin().await?;
+            let counts = backup_handler::upsert_rows(&mut tx, table, &columns, rows).await?;
+            tx.commit().await?;
+            Ok::<_, DalError>(counts)
+        }
+        .await;
+
+        let index = BACKUP_TABLES.iter().position(|t| t.name == table.name).unwrap_or_default();
+        match batch_result {
+            Ok(counts) => self.add_counts(index, counts),
+            Err(_) => {
+                for row in rows {
+                    let result = async {
+                        let mut conn = self.pool.acquire().await?;
+                        backup_handler::upsert_rows(&mut conn, table, &columns, std::slice::from_ref(row)).await
+                    }
+                    .await;
+                    match result {
+                        Ok(counts) => self.add_counts(index, counts),
+                        Err(e) => {
+                            self.counts[index].skipped += 1;
+                            if self.errors.len() < MAX_REPORTED_ERRORS {
+                                self.errors.push(format!("{} {}: {}", table.name, row_key(table, row), e));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add_counts(&mut self, index: usize, counts: backup_handler::UpsertCounts) {
+        let table_counts = &mut self.counts[index];
+        table_counts.inserted += counts.inserted;
+        table_counts.updated += counts.updated;
+        table_counts.skipped += counts.unchanged;
+    }
+
+    // The columns named in the batch's rows, each checked to exist in the table
+    async fn batch_columns(&mut self, table: &'static BackupTable, rows: &[Value]) -> Result<Vec<String>, String> {
+        if !self.columns.contains_key(table.name) {
+            let columns = backup_handler::table_columns(self.pool, table).await.map_err(|e| e.to_string())?;
+            self.columns.insert(table.name, columns);
+        }
+        let known = &self.columns[table.name];
+
+        let mut columns: Vec<String> = Vec::new();
+        for key in rows.iter().filter_map(|row| row.as_object()).flat_map(|row| row.keys()) {
+            if table.derived_columns.contains(&key.as_str()) || columns.contains(key) {
+                continue;
+            }
+            if !known.contains(key) {
+                return Err(format!("The backup has a column '{}' that table {} doesn't have", key, table.name));
+            }
+            columns.push(key.clone());
+        }
+        Ok(columns)
+    }
+}
+
+fn row_key(table: &BackupTable, row: &Value) -> String {
+    table
+        .key_columns
+        .iter()
+        .map(|column| row.get(*column).and_then(|v| v.as_str()).unwrap_or("?"))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn partial_path(dest: &Path) -> PathBuf {
+    let mut file_name = dest.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".partial");
+    dest.with_file_name(file_name)
+}
+
+fn temp_path(extension: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("gita-backup-{}.{}", Uuid::new_v4(), extension))
+}
@@ -0,0 +1,425 @@
+// Encryption at rest for recordings. A random 256-bit vault key encrypts audio files; config.toml
+// only holds it wrapped (AES-256-GCM) under a key derived from the user's passphrase with
+// Argon2id, and it is only kept unwrapped in memory while the vault is unlocked.
+//
+// An encrypted file is MAGIC and a random file ID, then its contents in CHUNK_SIZE chunks, each
+// sealed on its own as nonce + ciphertext + tag. That lets hound and the FLAC writer seek back
+// to patch their headers: a rewritten chunk is sealed again with a fresh nonce. Each chunk is
+// bound to its file, its index and whether it is the last one, and the last chunk is always
+// partial (possibly empty), so reordered, swapped or cut-off chunks fail to decrypt.
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::Engine;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroize;
+
+use crate::settings::VaultSettings;
+
+const MAGIC: &[u8; 8] = b"GITAENC1";
+const FILE_ID_LEN: usize = 16;
+const HEADER_LEN: u64 = (MAGIC.len() + FILE_ID_LEN) as u64;
+const CHUNK_SIZE: usize = 64 * 1024; // Plaintext bytes per chunk
+const NONCE_LEN: usize = 12;
+const CHUNK_OVERHEAD: usize = NONCE_LEN + 16; // Nonce and GCM tag
+const SEALED_CHUNK_LEN: u64 = (CHUNK_SIZE + CHUNK_OVERHEAD) as u64;
+
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const WRAP_AAD: &[u8] = b"gita vault key";
+
+pub const MIN_PASSPHRASE_LEN: usize = 8;
+pub const LOCKED_MESSAGE: &str = "The vault is locked. Unlock it with your passphrase first.";
+
+// The key recordings are encrypted with. Wiped from memory when dropped.
+#[derive(Clone)]
+pub struct VaultKey([u8; KEY_LEN]);
+
+impl VaultKey {
+    pub fn generate() -> Self {
+        let mut key = [0u8; KEY_LEN];
+        OsRng.fill_bytes(&mut key);
+        VaultKey(key)
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new_from_slice(&self.0).expect("vault keys are 32 bytes")
+    }
+}
+
+impl Drop for VaultKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+// --- Passphrase wrapping ---
+
+// The key that wraps the vault key, derived from the passphrase with the configured Argon2id
+// cost
+fn wrapping_cipher(passphrase: &str, salt: &[u8], vault: &VaultSettings) -> Result<Aes256Gcm, String> {
+    let params = Params::new(vault.kdf_memory_kib, vault.kdf_iterations, vault.kdf_parallelism, Some(KEY_LEN))
+        .map_err(|e| format!("Invalid vault key derivation settings: {}", e))?;
+    let mut derived = [0u8; KEY_LEN];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut derived)
+        .map_err(|e| format!("Failed to derive the vault key: {}", e))?;
+    let cipher = Aes256Gcm::new_from_slice(&derived).expect("derived keys are 32 bytes");
+    derived.zeroize();
+    Ok(cipher)
+}
+
+// Wraps key under passphrase with a fresh salt, storing the salt and wrapped key in vault
+pub fn wrap_key(key: &VaultKey, passphrase: &str, vault: &mut VaultSettings) -> Result<(), String> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let cipher = wrapping_cipher(passphrase, &salt, vault)?;
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let sealed = cipher
+        .encrypt(&nonce, Payload { msg: &key.0, aad: WRAP_AAD })
+        .map_err(|_| "Failed to wrap the vault key".to_string())?;
+
+    let engine = base64::engine::general_purpose::STANDARD;
+    let mut wrapped = nonce.to_vec();
+    wrapped.extend_from_slice(&sealed);
+    vault.kdf_salt = Some(engine.encode(salt));
+    vault.wrapped_key = Some(engine.encode(wrapped));
+    Ok(())
+}
+
+// The vault key, or None if the passphrase is wrong. Fails if no passphrase has been set.
+pub fn unwrap_key(passphrase: &str, vault: &VaultSettings) -> Result<Option<VaultKey>, String> {
+    let (Some(salt), Some(wrapped)) = (&vault.kdf_salt, &vault.wrapped_key) else {
+        return Err("No vault passphrase has been set".to_string());
+    };
+    let engine = base64::engine::general_purpose::STANDARD;
+    let salt = engine.decode(salt).map_err(|e| format!("Invalid vault salt in settings: {}", e))?;
+    let wrapped = engine.decode(wrapped).map_err(|e| format!("Invalid wrapped vault key in settings: {}", e))?;
+    if wrapped.len() != NONCE_LEN + KEY_LEN + 16 {
+        return Err("Invalid wrapped vault key in settings".to_string());
+    }
+
+    let cipher = wrapping_cipher(passphrase, &salt, vault)?;
+    let (nonce, sealed) = wrapped.split_at(NONCE_LEN);
+    let Ok(mut unwrapped) = cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad: WRAP_AAD }) else {
+        return Ok(None);
+    };
+    let mut key = [0u8; KEY_LEN];
+    key.copy_from_slice(&unwrapped);
+    unwrapped.zeroize();
+    Ok(Some(VaultKey(key)))
+}
+
+// --- Encrypted files ---
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Whether the file at path is encrypted, going by its header
+pub fn is_encrypted(path: &Path) -> io::Result<bool> {
+    let mut magic = [0u8; MAGIC.len()];
+    let mut file = File::open(path)?;
+    match file.read_exact(&mut magic) {
+        Ok(()) => Ok(&magic == MAGIC),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// An encrypted file read and written like a plain one. One chunk is held decrypted at a time;
+// changes to it are sealed when another chunk is needed, on flush and on drop.
+pub struct EncryptedFile {
+    file: File,
+    cipher: Aes256Gcm,
+    file_id: [u8; FILE_ID_LEN],
+    len: u64,      // Plaintext length
+    position: u64, // Plaintext position
+    chunk: Vec<u8>,
+    chunk_index: Option<u64>, // Which chunk `chunk` holds, once one is loaded
+    dirty: bool,
+}
+
+impl EncryptedFile {
+    // Creates (or truncates) path as an empty encrypted file
+    pub fn create(path: &Path, key: &VaultKey) -> io::Result<Self> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        let mut file_id = [0u8; FILE_ID_LEN];
+        OsRng.fill_bytes(&mut file_id);
+        file.write_all(MAGIC)?;
+        file.write_all(&file_id)?;
+
+        let mut encrypted = EncryptedFile {
+            file,
+            cipher: key.cipher(),
+            file_id,
+            len: 0,
+            position: 0,
+            chunk: Vec::with_capacity(CHUNK_SIZE),
+            chunk_index: Some(0),
+            dirty: true, // Seal the empty last chunk so the file is valid from the start
+        };
+        encrypted.flush_chunk()?;
+        Ok(encrypted)
+    }
+
+    // Opens an encrypted file for reading
+    pub fn open(path: &Path, key: &VaultKey) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut header = [0u8; HEADER_LEN as usize];
+        file.read_exact(&mut header).map_err(|_| invalid_data("Not an encrypted file"))?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(invalid_data("Not an encrypted file"));
+        }
+        let mut file_id = [0u8; FILE_ID_LEN];
+        file_id.copy_from_slice(&header[MAGIC.len()..]);
+
+        let body_len = file.metadata()?.len() - HEADER_LEN;
+        let partial_len = body_len % SEALED_CHUNK_LEN;
+        if partial_len < CHUNK_OVERHEAD as u64 {
+            return Err(invalid_data("Encrypted file is cut short"));
+        }
+        let len = body_len / SEALED_CHUNK_LEN * CHUNK_SIZE as u64 + partial_len - CHUNK_OVERHEAD as u64;
+
+        Ok(EncryptedFile {
+            file,
+            cipher: key.cipher(),
+            file_id,
+            len,
+            position: 0,
+            chunk: Vec::with_capacity(CHUNK_SIZE),
+            chunk_index: None,
+            dirty: false,
+        })
+    }
+
+    // Seals any pending changes and syncs the file to disk
+    pub fn sync_all(&mut self) -> io::Result<()> {
+        self.flush_chunk()?;
+        self.file.sync_all()
+    }
+
+    fn last_chunk_index(&self) -> u64 {
+        self.len / CHUNK_SIZE as u64
+    }
+
+    fn aad(&self, index: u64, is_last: bool) -> Vec<u8> {
+        let mut aad = self.file_id.to_vec();
+        aad.extend_from_slice(&index.to_le_bytes());
+        aad.push(is_last as u8);
+        aad
+    }
+
+    fn write_sealed(&mut self, index: u64, plaintext: &[u8]) -> io::Result<()> {
+        let aad = self.aad(index, index == self.last_chunk_index());
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let sealed = self
+            .cipher
+            .encrypt(&nonce, Payload { msg: plaintext, aad: &aad })
+            .map_err(|_| io::Error::other("Failed to encrypt audio"))?;
+        self.file.seek(SeekFrom::Start(HEADER_LEN + index * SEALED_CHUNK_LEN))?;
+        self.file.write_all(&nonce)?;
+        self.file.write_all(&sealed)
+    }
+
+    fn flush_chunk(&mut self) -> io::Result<()> {
+        let Some(index) = self.chunk_index.filter(|_| self.dirty) else {
+            return Ok(());
+        };
+        let chunk = std::mem::take(&mut self.chunk);
+        let result = self.write_sealed(index, &chunk);
+        self.chunk = chunk;
+        result?;
+        self.dirty = false;
+
+        // A chunk that just filled up is followed by an empty last chunk
+        let last = self.last_chunk_index();
+        if index + 1 == last && self.len.is_multiple_of(CHUNK_SIZE as u64) {
+            self.write_sealed(last, &[])?;
+        }
+        Ok(())
+    }
+
+    fn load_chunk(&mut self, index: u64) -> io::Result<()> {
+        if self.chunk_index == Some(index) {
+            return Ok(());
+        }
+        self.flush_chunk()?;
+        self.chunk_index = None;
+
+        let last = self.last_chunk_index();
+        let plaintext_len = if index == last { (self.len % CHUNK_SIZE as u64) as usize } else { CHUNK_SIZE };
+        let mut sealed = vec![0u8; plaintext_len + CHUNK_OVERHEAD];
+        self.file.seek(SeekFrom::Start(HEADER_LEN + index * SEALED_CHUNK_LEN))?;
+        self.file.read_exact(&mut sealed)?;
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let aad = self.aad(index, index == last);
+        self.chunk = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: &aad })
+            .map_err(|_| invalid_data("Encrypted file is damaged or was encrypted with another key"))?;
+        self.chunk_index = Some(index);
+        Ok(())
+    }
+}
+
+impl Read for EncryptedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.len {
+            return Ok(0);
+        }
+        self.load_chunk(self.position / CHUNK_SIZE as u64)?;
+        let offset = (self.position % CHUNK_SIZE as u64) as usize;
+        let count = buf.len().min(self.chunk.len() - offset);
+        buf[..count].copy_from_slice(&self.chunk[offset..offset + count]);
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl Write for EncryptedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.position > self.len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Can't write past the end of an encrypted file",
+            ));
+        }
+        self.load_chunk(self.position / CHUNK_SIZE as u64)?;
+        let offset = (self.position % CHUNK_SIZE as u64) as usize;
+        let count = buf.len().min(CHUNK_SIZE - offset);
+        if self.chunk.len() < offset + count {
+            self.chunk.resize(offset + count, 0);
+        }
+        self.chunk[offset..offset + count].copy_from_slice(&buf[..count]);
+        self.dirty = true;
+        self.position += count as u64;
+        self.len = self.len.max(self.position);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_chunk()?;
+        self.file.flush()
+    }
+}
+
+impl Seek for EncryptedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = target.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid seek position"))?;
+        Ok(self.position)
+    }
+}
+
+impl Drop for EncryptedFile {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush_chunk() {
+            eprintln!("[Vault] Failed to write the end of an encrypted file: {}", e);
+        }
+    }
+}
+
+// A recording file, encrypted or not
+pub enum VaultFile {
+    Plain(File),
+    Encrypted(Box<EncryptedFile>),
+}
+
+impl VaultFile {
+    // Creates an encrypted file when a key is given and a plain one otherwise
+    pub fn create(path: &Path, key: Option<&VaultKey>) -> io::Result<Self> {
+        match key {
+            Some(key) => Ok(VaultFile::Encrypted(Box::new(EncryptedFile::create(path, key)?))),
+            None => Ok(VaultFile::Plain(File::create(path)?)),
+        }
+    }
+
+    // Opens a file for reading, decrypting it if it is encrypted. An encrypted file can't be
+    // opened without the key, i.e. while the vault is locked.
+    pub fn open(path: &Path, key: Option<&VaultKey>) -> io::Result<Self> {
+        if !is_encrypted(path)? {
+            return Ok(VaultFile::Plain(File::open(path)?));
+        }
+        let key = key.ok_or_else(|| io::Error::new(io::ErrorKind::PermissionDenied, LOCKED_MESSAGE))?;
+        Ok(VaultFile::Encrypted(Box::new(EncryptedFile::open(path, key)?)))
+    }
+}
+
+impl Read for VaultFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            VaultFile::Plain(file) => file.read(buf),
+            VaultFile::Encrypted(file) => file.read(buf),
+        }
+    }
+}
+
+impl Write for VaultFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            VaultFile::Plain(file) => file.write(buf),
+            VaultFile::Encrypted(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            VaultFile::Plain(file) => file.flush(),
+            VaultFile::Encrypted(file) => file.flush(),
+        }
+    }
+}
+
+impl Seek for VaultFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            VaultFile::Plain(file) => file.seek(pos),
+            VaultFile::Encrypted(file) => file.seek(pos),
+        }
+    }
+}
+
+// Replaces a plain file with an encrypted copy of it. The copy is written beside the file and
+// renamed over it once complete, so a failure leaves the original untouched. Returns false if
+// the file was already encrypted.
+pub fn encrypt_in_place(path: &Path, key: &VaultKey) -> io::Result<bool> {
+    if is_encrypted(path)? {
+        return Ok(false);
+    }
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".encrypting");
+    let temp_path: PathBuf = path.with_file_name(temp_name);
+
+    let result = (|| {
+        let mut source = File::open(path)?;
+        let mut dest = BufWriter::new(EncryptedFile::create(&temp_path, key)?);
+        io::copy(&mut source, &mut dest)?;
+        dest.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&temp_path, path)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result.map(|_| true)
+}
+
+// Copies a recording to dest as a plain file, decrypting it if it is encrypted
+pub fn copy_decrypted(source: &Path, dest: &Path, key: Option<&VaultKey>) -> io::Result<u64> {
+    let mut reader = io::BufReader::new(VaultFile::open(source, key)?);
+    let mut writer = BufWriter::new(File::create(dest)?);
+    let copied = io::copy(&mut reader, &mut writer)?;
+    writer.flush()?;
+    Ok(copied)
+}
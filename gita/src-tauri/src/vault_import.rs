@@ -0,0 +1,258 @@
+// Imports a folder of Markdown notes (e.g. an Obsidian vault) into the database. Each file
+// becomes a page titled after its file name, with the file content as raw_markdown and its
+// body converted into blocks.
+// Re-running an import is safe: pages are matched by title, so nothing is created twice.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use std::path::Path;
+use tauri::{AppHandle, Emitter};
+use uuid::Uuid;
+
+use crate::file_system;
+use crate::jobs::JobContext;
+use crate::markdown_import;
+use crate::page_handler::{self, Page};
+use crate::tag_handler;
+
+pub const EVENT_IMPORT_PROGRESS: &str = "import://progress";
+
+// What to do when a file's title matches a page that already has content
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateStrategy {
+    #[default]
+    Skip, // Leave the existing page untouched
+    Merge, // Append the file's body to the existing page, unless it's already there
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ImportOptions {
+    pub on_duplicate: DuplicateStrategy,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ImportProgressEvent {
+    pub current: usize, // 1-based index of the file just processed
+    pub total: usize,
+    pub path: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ImportFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ImportSummary {
+    pub total_files: usize,
+    pub created: usize,
+    pub filled_stubs: usize,  // Placeholder pages (created for link targets) that got their file
+    pub merged: usize,
+    pub skipped: usize,
+    pub stubs_created: usize, // Empty pages created for links to notes that don't exist
+    pub failures: Vec<ImportFailure>,
+}
+
+// Run as a job, it stops before the next file once cancelled. Files already imported stay, and
+// running the import again picks up where it stopped.
+pub async fn import_vault(
+    pool: &PgPool,
+    app_handle: &AppHandle,
+    root: &Path,
+    options: &ImportOptions,
+    job: Option<&JobContext>,
+) -> Result<ImportSummary, String> {
+    let files = file_system::scan_directory(root)?;
+    let mut summary = ImportSummary {
+        total_files: files.len(),
+        ..Default::default()
+    };
+
+    // A failing file is recorded and the import moves on to the next one
+    for (index, path) in files.iter().enumerate() {
+        if job.is_some_and(JobContext::is_cancelled) {
+            println!("[Import] Cancelled after {} of {} files", index, files.len());
+            break;
+        }
+        if let Err(e) = import_file(pool, path, options, &mut summary).await {
+            eprintln!("[Import] Failed to import {}: {}", path.display(), e);
+            summary.failures.push(ImportFailure {
+                path: path.display().to_string(),
+                error: e,
+            });
+        }
+
+        let event = ImportProgressEvent {
+            current: index + 1,
+            total: files.len(),
+            path: path.display().to_string(),
+        };
+        if let Err(e) = app_handle.emit(EVENT_IMPORT_PROGRESS, event) {
+            eprintln!("[Import] Failed to emit progress event: {}", e);
+        }
+        if let Some(job) = job {
+            job.progress(index + 1, files.len(), path.display().to_string());
+        }
+    }
+
+    Ok(summary)
+}
+
+// A single file imported as import_vault imports each of its files
+#[derive(Serialize, Debug)]
+pub struct FileImport {
+    pub page_id: Option<Uuid>, // None when the file was skipped as a duplicate
+    pub summary: ImportSummary,
+}
+
+pub async fn import_markdown_file(pool: &PgPool, path: &Path, options: &ImportOptions) -> Result<FileImport, String> {
+    let mut summary = ImportSummary {
+        total_files: 1,
+        ..Default::default()
+    };
+    let page_id = import_file(pool, path, options, &mut summary).await?;
+    Ok(FileImport { page_id, summary })
+}
+
+// Returns the page the file was imported into, or None if it was skipped
+async fn import_file(
+    pool: &PgPool,
+    path: &Path,
+    options: &ImportOptions,
+    summary: &mut ImportSummary,
+) -> Result<Option<Uuid>, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| format!("Failed to read file: {}", e))?;
+    let title = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().trim().to_string())
+        .filter(|stem| !stem.is_empty())
+        .ok_or_else(|| "File name cannot be used as a page title".to_string())?;
+    // The front matter stays in raw_markdown so nothing is lost; its dates and tags are
+    // applied to the page.
+    let (front_matter, body) = file_system::extract_front_matter(&content);
+    let created_at = front_matter
+        .as_ref()
+        .and_then(|fm| fm.created_at.as_deref())
+        .and_then(file_system::parse_front_matter_date);
+
+    let existing = page_handler::get_page_by_title(pool, &title)
+        .await
+        .map_err(|e| e.to_string())?;
+    // The page's content is written (and its blocks and links stored) last, once its link
+    // targets exist
+    let (page_id, content_json, raw_markdown, change_summary) = match existing {
+        None => {
+            // Created with its content but without blocks or links, which the write below adds
+            // without keeping a revision, as the content doesn't change
+            let content_json = markdown_import::markdown_to_content(body);
+            let id = page_handler::create_page(pool, &title, content_json.clone(), Some(&content))
+                .await
+                .map_err(|e| e.to_string())?;
+            summary.created += 1;
+            if let Some(created_at) = created_at {
+                page_handler::set_page_created_at(pool, id, created_at).await.map_err(|e| e.to_string())?;
+            }
+            (id, content_json, content.clone(), None)
+        }
+        Some(page) if is_stub(&page) => {
+            summary.filled_stubs += 1;
+            if let Some(created_at) = created_at {
+                page_handler::set_page_created_at(pool, page.id, created_at).await.map_err(|e| e.to_string())?;
+            }
+            (page.id, markdown_import::markdown_to_content(body), content.clone(), None)
+        }
+        Some(page) => match options.on_duplicate {
+            DuplicateStrategy::Skip => {
+                summary.skipped += 1;
+                // A page holding exactly this file came from an earlier (possibly interrupted)
+                // run, so its content and links are still (re)written below. Any other page
+                // isn't ours.
+                if page.raw_markdown.as_deref() != Some(content.as_str()) {
+                    return Ok(None);
+                }
+                (page.id, markdown_import::markdown_to_content(body), content.clone(), None)
+            }
+            DuplicateStrategy::Merge => {
+                summary.merged += 1;
+                let existing_markdown = page.raw_markdown.clone().unwrap_or_default();
+                if existing_markdown.contains(body.trim()) {
+                    let content_json = merged_content(&page.content_json, &existing_markdown, None);
+                    (page.id, content_json, existing_markdown, None)
+                } else {
+                    let merged = format!("{}\n\n{}", existing_markdown.trim_end(), body.trim_start());
+                    let content_json = merged_content(&page.content_json, &merged, Some(body));
+                    (page.id, content_json, merged, Some("Merged from import"))
+                }
+            }
+        },
+    };
+
+    for tag in front_matter.iter().flat_map(|fm| fm.tags.iter().flatten()) {
+        let tag = tag.trim().trim_start_matches('#');
+        if !tag.is_empty() {
+            tag_handler::add_tag_to_page(pool, page_id, tag).await.map_err(|e| e.to_string())?;
+        }
+    }
+
+    for target_title in markdown_import::link_titles(&content_json) {
+        if target_title != title {
+            resolve_or_create_stub(pool, &target_title, summary).await?;
+        }
+    }
+    page_handler::update_page(
+        pool,
+        page_id,
+        None,
+        Some(content_json),
+        Some(Some(&raw_markdown)),
+        None,
+        false,
+        change_summary,
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(Some(page_id))
+}
+
+// Content for a page with a file merged into it: the page's blocks followed by the added
+// body's, or the whole merged Markdown converted for a page that has no blocks yet
+fn merged_content(existing: &Value, merged_markdown: &str, added_body: Option<&str>) -> Value {
+    let Some(children) = existing.pointer("/root/children").and_then(|v| v.as_array()) else {
+        let (_, merged_body) = file_system::extract_front_matter(merged_markdown);
+        return markdown_import::markdown_to_content(merged_body);
+    };
+    let mut content = existing.clone();
+    if let Some(body) = added_body {
+        let added = markdown_import::markdown_to_content(body);
+        let mut children = children.clone();
+        children.extend(added.pointer("/root/children").and_then(|v| v.as_array()).cloned().unwrap_or_default());
+        content["root"]["children"] = Value::Array(children);
+    }
+    content
+}
+
+// A page with no content yet, such as one created for a link target before its file was seen
+pub(crate) fn is_stub(page: &Page) -> bool {
+    let markdown_empty = page.raw_markdown.as_deref().is_none_or(|md| md.trim().is_empty());
+    let content_empty = page.content_json.as_object().is_some_and(|obj| obj.is_empty());
+    markdown_empty && content_empty
+}
+
+async fn resolve_or_create_stub(pool: &PgPool, title: &str, summary: &mut ImportSummary) -> Result<Uuid, String> {
+    if let Some(page) = page_handler::get_page_by_title(pool, title)
+        .await
+        .map_err(|e| e.to_string())?
+    {
+        return Ok(page.id);
+    }
+    let id = page_handler::create_page(pool, title, serde_json::json!({}), None)
+        .await
+        .map_err(|e| e.to_string())?;
+    summary.stubs_created += 1;
+    Ok(id)
+}